@@ -0,0 +1,121 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per message overhead of the in-process connector, which hands message enums straight to the
+//! server, compared to sending the same messages through JSON serializers the way remote
+//! connectors do. Run with `cargo bench --features client,server,serialize-json`.
+
+use buttplug::{
+  core::{
+    connector::{ButtplugConnector, ButtplugInProcessClientConnectorBuilder},
+    message::{
+      serializer::{
+        ButtplugClientJSONSerializer,
+        ButtplugMessageSerializer,
+        ButtplugServerJSONSerializer,
+      },
+      ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage,
+      ButtplugMessage,
+      ButtplugMessageSpecVersion,
+      RequestDeviceList,
+      RequestServerInfo,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  server::{ButtplugServer, ButtplugServerBuilder},
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use tokio::{runtime::Builder, sync::mpsc};
+
+fn request_device_list(id: u32) -> ButtplugCurrentSpecClientMessage {
+  let mut msg = RequestDeviceList::default();
+  msg.set_id(id);
+  msg.into()
+}
+
+fn round_trip(c: &mut Criterion) {
+  let runtime = Builder::new_current_thread()
+    .enable_all()
+    .build()
+    .expect("Benchmark runtime setup should succeed");
+  let _guard = runtime.enter();
+  let mut group = c.benchmark_group("client_server_round_trip");
+
+  // In-process connector, no serialization.
+  let mut connector = ButtplugInProcessClientConnectorBuilder::default().finish();
+  let (sender, mut receiver) = mpsc::channel(256);
+  runtime.block_on(async {
+    connector
+      .connect(sender)
+      .await
+      .expect("Benchmark connector setup should succeed");
+    let mut rsi = RequestServerInfo::new("Benchmark", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    rsi.set_id(1);
+    connector
+      .send(rsi.into())
+      .await
+      .expect("Benchmark connector setup should succeed");
+    receiver.recv().await;
+  });
+  group.bench_function("in_process", |b| {
+    b.iter(|| {
+      runtime.block_on(async {
+        connector
+          .send(request_device_list(2))
+          .await
+          .expect("Benchmark messages should send");
+        let reply = receiver.recv().await;
+        assert!(matches!(
+          reply,
+          Some(ButtplugCurrentSpecServerMessage::DeviceList(_))
+        ));
+      })
+    })
+  });
+
+  // The same exchange, serialized to JSON on both sides like a remote connector would.
+  let server: ButtplugServer = ButtplugServerBuilder::default()
+    .finish()
+    .expect("Benchmark server setup should succeed");
+  let client_serializer = ButtplugClientJSONSerializer::default();
+  let server_serializer = ButtplugServerJSONSerializer::default();
+  server_serializer.force_message_version(&ButtplugMessageSpecVersion::Version3);
+  runtime.block_on(async {
+    server
+      .parse_message(
+        RequestServerInfo::new("Benchmark", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+      )
+      .await
+      .expect("Benchmark server setup should succeed");
+  });
+  group.bench_function("json", |b| {
+    b.iter(|| {
+      runtime.block_on(async {
+        let outgoing = client_serializer.serialize(&[request_device_list(2)]);
+        let incoming = server_serializer
+          .deserialize(&outgoing)
+          .expect("Benchmark messages are valid");
+        let reply = server
+          .parse_message(incoming[0].clone())
+          .await
+          .expect("Benchmark messages should succeed");
+        let reply = client_serializer
+          .deserialize(&server_serializer.serialize(&[reply]))
+          .expect("Benchmark messages are valid");
+        assert!(matches!(
+          reply[0],
+          ButtplugCurrentSpecServerMessage::DeviceList(_)
+        ));
+      })
+    })
+  });
+  group.finish();
+}
+
+criterion_group!(benches, round_trip);
+criterion_main!(benches);
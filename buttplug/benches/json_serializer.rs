@@ -0,0 +1,73 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side JSON deserialization, for comparing validation modes. Run with `cargo bench`.
+
+use buttplug::core::message::{
+  serializer::{
+    ButtplugMessageSerializer,
+    ButtplugSerializedMessage,
+    ButtplugServerJSONSerializer,
+    JsonValidationMode,
+  },
+  ButtplugMessageSpecVersion,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const SCALAR_CMD: &str = r#"[{"ScalarCmd": {"Id": 1, "DeviceIndex": 0, "Scalars": [
+  {"Index": 0, "Scalar": 0.5, "ActuatorType": "Vibrate"},
+  {"Index": 1, "Scalar": 0.25, "ActuatorType": "Vibrate"}
+]}}]"#;
+
+const LINEAR_CMD: &str = r#"[{"LinearCmd": {"Id": 2, "DeviceIndex": 0, "Vectors": [
+  {"Index": 0, "Duration": 500, "Position": 0.3}
+]}}]"#;
+
+const MODES: [JsonValidationMode; 3] = [
+  JsonValidationMode::Always,
+  JsonValidationMode::FirstOfType,
+  JsonValidationMode::Never,
+];
+
+fn server_serializer(mode: JsonValidationMode) -> ButtplugServerJSONSerializer {
+  let serializer = ButtplugServerJSONSerializer::new(mode);
+  serializer.force_message_version(&ButtplugMessageSpecVersion::Version3);
+  serializer
+}
+
+fn deserialize_commands(c: &mut Criterion) {
+  let mut group = c.benchmark_group("deserialize_commands");
+  for (name, json) in [("ScalarCmd", SCALAR_CMD), ("LinearCmd", LINEAR_CMD)] {
+    let msg = ButtplugSerializedMessage::Text(json.to_owned());
+    for mode in MODES {
+      let serializer = server_serializer(mode);
+      group.bench_with_input(
+        BenchmarkId::new(name, format!("{:?}", mode)),
+        &msg,
+        |b, msg| {
+          b.iter(|| {
+            serializer
+              .deserialize(black_box(msg))
+              .expect("Benchmark messages are valid")
+          })
+        },
+      );
+    }
+  }
+  group.finish();
+}
+
+fn create_serializer(c: &mut Criterion) {
+  // The schema is compiled once per process, so this should stay cheap no matter how many
+  // connections come in.
+  c.bench_function("create_serializer", |b| {
+    b.iter(|| server_serializer(JsonValidationMode::Always))
+  });
+}
+
+criterion_group!(benches, deserialize_commands, create_serializer);
+criterion_main!(benches);
@@ -0,0 +1,169 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server message routing under load, from [ButtplugServer::parse_message] down to the simulated
+//! hardware. Simulates one second of 10 devices being driven at 50Hz, and reports heap allocations
+//! per message along with criterion's timings. Run with `cargo bench --features simulated-manager`.
+
+use buttplug::{
+  core::message::{
+    ActuatorType,
+    ButtplugClientMessage,
+    ButtplugMessage,
+    ButtplugServerMessage,
+    RequestServerInfo,
+    ScalarCmd,
+    ScalarSubcommand,
+    StartScanning,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  },
+  server::{
+    device::hardware::communication::simulated::{
+      SimulatedDeviceCommunicationManagerBuilder,
+      SimulatedDeviceConfig,
+      SimulatedDeviceHandle,
+    },
+    ButtplugServer,
+    ButtplugServerBuilder,
+  },
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::{pin_mut, StreamExt};
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::runtime::Builder;
+
+const DEVICE_COUNT: u32 = 10;
+const UPDATE_RATE_HZ: u32 = 50;
+
+/// Counts heap allocations, so the benchmark can report how many each routed message costs.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Build a server with simulated devices and wait for all of them to connect. The device handles
+/// have to be kept alive for as long as the devices are in use.
+async fn setup_server() -> (ButtplugServer, Vec<SimulatedDeviceHandle>) {
+  let mut comm_builder = SimulatedDeviceCommunicationManagerBuilder::default();
+  let handles = (0..DEVICE_COUNT)
+    .map(|i| {
+      comm_builder.add_device(&SimulatedDeviceConfig::new(
+        "Massage Demo",
+        Some(&format!("bench-device-{}", i)),
+      ))
+    })
+    .collect();
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(comm_builder)
+    .finish()
+    .expect("Benchmark server setup should succeed");
+  let events = server.event_stream();
+  pin_mut!(events);
+  server
+    .parse_message(
+      RequestServerInfo::new("Benchmark", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Benchmark server setup should succeed");
+  server
+    .parse_message(StartScanning::default().into())
+    .await
+    .expect("Benchmark server setup should succeed");
+  let mut added = 0;
+  while added < DEVICE_COUNT {
+    if let Some(ButtplugServerMessage::DeviceAdded(_)) = events.next().await {
+      added += 1;
+    }
+  }
+  (server, handles)
+}
+
+/// One tick of updates for every device. Values change every tick, so every command makes it
+/// through to the hardware.
+fn tick_messages(tick: u32) -> Vec<ButtplugClientMessage> {
+  let speed = (tick % 20) as f64 / 20.0;
+  (0..DEVICE_COUNT)
+    .map(|device_index| {
+      let mut msg = ScalarCmd::new(
+        device_index,
+        vec![
+          ScalarSubcommand::new(0, speed, ActuatorType::Vibrate),
+          ScalarSubcommand::new(1, 1.0 - speed, ActuatorType::Vibrate),
+        ],
+      );
+      msg.set_id(tick + 1);
+      msg.into()
+    })
+    .collect()
+}
+
+/// Send one second worth of updates.
+async fn run_one_second(server: &ButtplugServer, ticks: Vec<Vec<ButtplugClientMessage>>) {
+  for tick in ticks {
+    for msg in tick {
+      server
+        .parse_message(msg)
+        .await
+        .expect("Benchmark commands should succeed");
+    }
+  }
+}
+
+fn one_second_of_messages() -> Vec<Vec<ButtplugClientMessage>> {
+  (0..UPDATE_RATE_HZ).map(tick_messages).collect()
+}
+
+fn route_messages(c: &mut Criterion) {
+  // Single threaded, so allocation counts don't pick up unrelated work from other threads.
+  let runtime = Builder::new_current_thread()
+    .enable_all()
+    .build()
+    .expect("Benchmark runtime setup should succeed");
+  // The server spawns tasks on shutdown, so it needs to be dropped inside the runtime too.
+  let _guard = runtime.enter();
+  let (server, _handles) = runtime.block_on(setup_server());
+
+  // Messages are built before counting starts, so only routing and device handling are measured.
+  let ticks = one_second_of_messages();
+  let before = ALLOCATIONS.load(Ordering::Relaxed);
+  runtime.block_on(run_one_second(&server, ticks));
+  let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+  println!(
+    "{} devices at {}Hz: {} allocations per second, {:.1} per message",
+    DEVICE_COUNT,
+    UPDATE_RATE_HZ,
+    allocations,
+    allocations as f64 / (DEVICE_COUNT * UPDATE_RATE_HZ) as f64
+  );
+
+  c.bench_function("route_one_second_10_devices_50hz", |b| {
+    b.iter_batched(
+      one_second_of_messages,
+      |ticks| runtime.block_on(run_one_second(&server, ticks)),
+      criterion::BatchSize::SmallInput,
+    )
+  });
+}
+
+criterion_group!(benches, route_messages);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+#![no_main]
+
+use buttplug::server::device::protocol::{parsing, xinput};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let _ = parsing::battery_level("fuzz", data);
+  let _ = xinput::parse_battery(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use buttplug::server::device::protocol::kgoal_boost::parse_pressure;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let _ = parse_pressure(data);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use buttplug::server::device::protocol::{kiiroo_v21, kiiroo_v2_vibrator};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let _ = kiiroo_v21::parse_battery(data);
+  let _ = kiiroo_v21::parse_sensors(data);
+  let _ = kiiroo_v2_vibrator::parse_touch(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use buttplug::server::device::protocol::lovense::{lovense_model_resolver, parse_battery};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let _ = parse_battery(data);
+  if let Ok(type_response) = std::str::from_utf8(data) {
+    let _ = lovense_model_resolver(type_response);
+  }
+});
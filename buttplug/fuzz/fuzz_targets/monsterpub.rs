@@ -0,0 +1,9 @@
+#![no_main]
+
+use buttplug::server::device::protocol::monsterpub::{auth_response, parse_model_name};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let _ = parse_model_name(data);
+  let _ = auth_response(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use buttplug::server::device::protocol::vibcrafter::decrypt;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let _ = decrypt(data);
+});
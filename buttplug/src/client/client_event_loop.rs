@@ -10,13 +10,16 @@
 use super::{
   client_message_sorter::ClientMessageSorter,
   device::{ButtplugClientDevice, ButtplugClientDeviceEvent},
+  ButtplugClientError,
   ButtplugClientEvent,
   ButtplugClientMessageFuturePair,
   ButtplugClientMessageSender,
+  ButtplugServerMessageFuture,
+  ButtplugServerMessageResult,
 };
 use crate::core::{
-  connector::{ButtplugConnector, ButtplugConnectorStateShared},
-  errors::{ButtplugDeviceError, ButtplugError},
+  connector::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorStateShared},
+  errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
   message::{
     ButtplugCurrentSpecClientMessage,
     ButtplugCurrentSpecServerMessage,
@@ -24,13 +27,18 @@ use crate::core::{
     ButtplugMessageValidator,
     DeviceList,
     DeviceMessageInfo,
+    RequestDeviceList,
+    RequestServerInfo,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
   },
 };
 use dashmap::DashMap;
-use futures::FutureExt;
+use futures::{pin_mut, FutureExt};
 use std::sync::{
   atomic::{AtomicBool, Ordering},
   Arc,
+  Mutex,
+  Weak,
 };
 use tokio::sync::{broadcast, mpsc};
 
@@ -64,6 +72,10 @@ pub enum ButtplugClientRequest {
 ///   routing them to their proper receivers until either server/client
 ///   disconnects.
 ///
+/// - If the connector loses its connection and supports reconnecting, it will
+///   wait for the connector to come back up, replay the handshake, and rebind
+///   existing devices to the indexes the server now reports for them.
+///
 /// - On disconnect, it will tear down, and cannot be used again. All clients
 ///   and devices associated with the loop will be invalidated, and connect must
 ///   be called on the client again (or a new client should be created).
@@ -82,6 +94,8 @@ where
   ConnectorType:
     ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage> + 'static,
 {
+  /// Client name, needed to replay the handshake when reconnecting.
+  client_name: String,
   /// Connected status from client, managed by the event loop in case of disconnect.
   connected_status: Arc<AtomicBool>,
   /// Connector the event loop will use to communicate with the [ButtplugServer]
@@ -90,6 +104,9 @@ where
   from_connector_receiver: mpsc::Receiver<ButtplugCurrentSpecServerMessage>,
   /// Map of devices shared between the client and the event loop
   device_map: Arc<DashMap<u32, Arc<ButtplugClientDevice>>>,
+  /// Devices that have been removed, kept so the handles can be revived if the
+  /// same device is added again. Shared with the client so it outlives the loop.
+  retired_devices: Arc<Mutex<Vec<Weak<ButtplugClientDevice>>>>,
   /// Sends events to the [ButtplugClient] instance.
   to_client_sender: broadcast::Sender<ButtplugClientEvent>,
   /// Sends events to the client receiver. Stored here so it can be handed to
@@ -110,18 +127,23 @@ where
   /// Given the [ButtplugClientConnector] object, as well as the channels used
   /// for communicating with the client, creates an event loop structure and
   /// returns it.
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
+    client_name: &str,
     connected_status: Arc<AtomicBool>,
     connector: ConnectorType,
     from_connector_receiver: mpsc::Receiver<ButtplugCurrentSpecServerMessage>,
     to_client_sender: broadcast::Sender<ButtplugClientEvent>,
     from_client_sender: Arc<ButtplugClientMessageSender>,
     device_map: Arc<DashMap<u32, Arc<ButtplugClientDevice>>>,
+    retired_devices: Arc<Mutex<Vec<Weak<ButtplugClientDevice>>>>,
   ) -> Self {
     trace!("Creating ButtplugClientEventLoop instance.");
     Self {
+      client_name: client_name.to_owned(),
       connected_status,
       device_map,
+      retired_devices,
       from_client_receiver: from_client_sender.subscribe(),
       from_client_sender,
       to_client_sender,
@@ -141,22 +163,82 @@ where
       "Trying to create a client device from DeviceMessageInfo: {:?}",
       info
     );
-    match self.device_map.get(&info.device_index()) {
-      // If the device already exists in our map, clone it.
-      Some(dev) => {
-        debug!("Device already exists, creating clone.");
-        dev.clone()
-      }
-      // If it doesn't, insert it.
+    // If the device already exists in our map, clone it.
+    if let Some(dev) = self.device_map.get(&info.device_index()) {
+      debug!("Device already exists, creating clone.");
+      return dev.clone();
+    }
+    // If it's a device we've seen before, bring its old handle back, otherwise
+    // insert a new one.
+    let device = match self.revive_device(info) {
+      Some(device) => device,
       None => {
         debug!("Device does not exist, creating new entry.");
-        let device = Arc::new(ButtplugClientDevice::new_from_device_info(
+        Arc::new(ButtplugClientDevice::new_from_device_info(
           info,
           &self.from_client_sender,
-        ));
-        self.device_map.insert(info.device_index(), device.clone());
-        device
+        ))
       }
+    };
+    self.device_map.insert(info.device_index(), device.clone());
+    device
+  }
+
+  /// Keeps a removed device around, so its handle can be revived if the server
+  /// adds the same device again. Devices without connection info can't be
+  /// matched later, so they aren't kept.
+  fn retire_device(&self, device: &Arc<ButtplugClientDevice>) {
+    if device.connection_info().is_none() {
+      return;
+    }
+    let mut retired_devices = self
+      .retired_devices
+      .lock()
+      .expect("Retired device lock should never be poisoned.");
+    retired_devices.retain(|retired| retired.strong_count() > 0);
+    retired_devices.push(Arc::downgrade(device));
+  }
+
+  /// Finds a removed device handle that the user still holds for the device
+  /// described by `info`, and points it at the device's new index.
+  ///
+  /// Devices match if they were at the same address with the same name and
+  /// messages, so a handle never ends up driving a device it wasn't made for.
+  fn revive_device(&mut self, info: &DeviceMessageInfo) -> Option<Arc<ButtplugClientDevice>> {
+    let device = {
+      let mut retired_devices = self
+        .retired_devices
+        .lock()
+        .expect("Retired device lock should never be poisoned.");
+      retired_devices.retain(|retired| retired.strong_count() > 0);
+      let pos = retired_devices.iter().position(|retired| {
+        retired.upgrade().is_some_and(|device| {
+          device.matches_device_info(info) && device.message_attributes() == info.device_messages()
+        })
+      })?;
+      retired_devices.swap_remove(pos).upgrade()?
+    };
+    debug!(
+      "Reviving device {} from index {} at {}",
+      device.name(),
+      device.index(),
+      info.device_index()
+    );
+    self.move_device_index(&device, info.device_index());
+    device.set_device_connected(true);
+    device.set_client_connected(true);
+    Some(device)
+  }
+
+  /// Points a device at a new index, telling the client if it changed.
+  fn move_device_index(&mut self, device: &Arc<ButtplugClientDevice>, index: u32) {
+    let old_index = device.index();
+    device.set_index(index);
+    if old_index != index {
+      self.send_client_event(ButtplugClientEvent::DeviceIndexChanged(
+        device.clone(),
+        old_index,
+      ));
     }
   }
 
@@ -191,6 +273,7 @@ where
     device.queue_event(ButtplugClientDeviceEvent::DeviceRemoved);
     // Then remove it from our storage map
     self.device_map.remove(&device_index);
+    self.retire_device(&device);
     self.send_client_event(ButtplugClientEvent::DeviceRemoved(device));
   }
 
@@ -231,8 +314,13 @@ where
         self.send_client_event(ButtplugClientEvent::DeviceAdded(device));
       }
       ButtplugCurrentSpecServerMessage::DeviceRemoved(dev) => {
-        if self.device_map.contains_key(&dev.device_index()) {
+        let device = self
+          .device_map
+          .get(&dev.device_index())
+          .map(|device| device.value().clone());
+        if let Some(device) = device {
           trace!("Device removed, updating map and sending to client");
+          device.set_removed(dev.reason(), dev.will_reconnect());
           self.disconnect_device(dev.device_index());
         } else {
           error!("Received DeviceRemoved for non-existent device index");
@@ -266,6 +354,9 @@ where
       ButtplugCurrentSpecServerMessage::Error(e) => {
         self.send_client_event(ButtplugClientEvent::Error(e.into()));
       }
+      ButtplugCurrentSpecServerMessage::Log(log) => {
+        self.send_client_event(ButtplugClientEvent::Log(log));
+      }
       _ => error!("Cannot process message, dropping: {:?}", msg),
     }
   }
@@ -287,6 +378,159 @@ where
     }
   }
 
+  /// Sends a message to the server and waits for its response, while still
+  /// handling anything else the server sends in the meantime.
+  ///
+  /// Only used while reconnecting, when the event loop itself needs a reply
+  /// before it can go back to routing messages for the client.
+  async fn send_message_and_wait(
+    &mut self,
+    msg: ButtplugCurrentSpecClientMessage,
+  ) -> ButtplugServerMessageResult {
+    let reply_fut = ButtplugServerMessageFuture::default();
+    self
+      .send_message(ButtplugClientMessageFuturePair::new(
+        msg,
+        reply_fut.get_state_clone(),
+      ))
+      .await;
+    let reply_fut = reply_fut.fuse();
+    pin_mut!(reply_fut);
+    loop {
+      select! {
+        reply = reply_fut => return reply,
+        event = self.from_connector_receiver.recv().fuse() => match event {
+          None => return Err(ButtplugConnectorError::ConnectorChannelClosed.into()),
+          Some(msg) => self.parse_connector_message(msg).await,
+        },
+      };
+    }
+  }
+
+  /// Matches the device list from a reconnected server against the devices
+  /// we already handed out.
+  ///
+  /// Devices still at the same address keep their [ButtplugClientDevice]
+  /// instance, pointed at their new index, with a
+  /// [ButtplugClientEvent::DeviceIndexChanged] event if it moved. Devices that
+  /// are gone are removed, and devices we haven't seen before are added, with
+  /// events for both.
+  fn rebind_devices(&mut self, device_list: DeviceList) {
+    let mut old_devices: Vec<Arc<ButtplugClientDevice>> = self
+      .device_map
+      .iter()
+      .map(|pair| pair.value().clone())
+      .collect();
+    self.device_map.clear();
+    let mut new_devices = vec![];
+    for info in device_list.devices() {
+      if let Some(pos) = old_devices
+        .iter()
+        .position(|device| device.matches_device_info(info))
+      {
+        let device = old_devices.swap_remove(pos);
+        debug!(
+          "Rebinding device {} from index {} to {}",
+          device.name(),
+          device.index(),
+          info.device_index()
+        );
+        self.move_device_index(&device, info.device_index());
+        self.device_map.insert(info.device_index(), device);
+      } else {
+        new_devices.push(info);
+      }
+    }
+    for device in old_devices {
+      device.set_device_connected(false);
+      device.queue_event(ButtplugClientDeviceEvent::DeviceRemoved);
+      self.retire_device(&device);
+      self.send_client_event(ButtplugClientEvent::DeviceRemoved(device));
+    }
+    for info in new_devices {
+      let device = self.create_client_device(info);
+      self.send_client_event(ButtplugClientEvent::DeviceAdded(device));
+    }
+  }
+
+  /// Replays the handshake over a reconnected connector, then rebinds devices.
+  async fn resume_session(&mut self) -> Result<(), ButtplugClientError> {
+    let msg = self
+      .send_message_and_wait(
+        RequestServerInfo::new(&self.client_name, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+      )
+      .await?;
+    if !matches!(msg, ButtplugCurrentSpecServerMessage::ServerInfo(_)) {
+      return Err(
+        ButtplugError::from(ButtplugHandshakeError::UnexpectedHandshakeMessageReceived(
+          format!("{:?}", msg),
+        ))
+        .into(),
+      );
+    }
+    if let ButtplugCurrentSpecServerMessage::DeviceList(device_list) = self
+      .send_message_and_wait(RequestDeviceList::default().into())
+      .await?
+    {
+      self.rebind_devices(device_list);
+    }
+    Ok(())
+  }
+
+  /// Tries to bring the connection back up after the connector loses it.
+  ///
+  /// Returns false if the connector can't reconnect, gives up, or the client
+  /// asks to disconnect in the meantime, in which case the loop should exit.
+  async fn reconnect(&mut self) -> bool {
+    let (connector_sender, connector_receiver) = mpsc::channel(256);
+    let Some(reconnect_fut) = self.connector.reconnect(connector_sender) else {
+      return false;
+    };
+    info!("Connector disconnected, trying to reconnect.");
+    self.connected_status.store(false, Ordering::SeqCst);
+    self.sorter.cancel_pending_futures();
+    self.send_client_event(ButtplugClientEvent::ServerReconnecting);
+    let reconnect_fut = reconnect_fut.fuse();
+    pin_mut!(reconnect_fut);
+    loop {
+      select! {
+        result = reconnect_fut => match result {
+          Ok(()) => break,
+          Err(e) => {
+            error!("Could not reconnect to server: {:?}", e);
+            return false;
+          }
+        },
+        client = self.from_client_receiver.recv().fuse() => match client {
+          Err(_) => {
+            info!("Client disconnected while reconnecting, exiting loop.");
+            return false;
+          }
+          Ok(ButtplugClientRequest::Disconnect(state)) => {
+            trace!("Client requested disconnect while reconnecting");
+            state.set_reply(Ok(()));
+            return false;
+          }
+          Ok(ButtplugClientRequest::Message(msg_fut)) => {
+            msg_fut
+              .waker
+              .set_reply(Err(ButtplugConnectorError::ConnectorNotConnected.into()));
+          }
+          Ok(ButtplugClientRequest::HandleDeviceList(_)) => {}
+        },
+      };
+    }
+    self.from_connector_receiver = connector_receiver;
+    if let Err(e) = self.resume_session().await {
+      error!("Could not resume session with server: {:?}", e);
+      return false;
+    }
+    info!("Reconnected to server.");
+    self.connected_status.store(true, Ordering::SeqCst);
+    self.send_client_event(ButtplugClientEvent::ServerConnect);
+    true
+  }
+
   /// Parses message types from the client, returning false when disconnect
   /// happens.
   ///
@@ -328,8 +572,10 @@ where
       select! {
         event = self.from_connector_receiver.recv().fuse() => match event {
           None => {
-            info!("Connector disconnected, exiting loop.");
-            break;
+            if !self.reconnect().await {
+              info!("Connector disconnected, exiting loop.");
+              break;
+            }
           }
           Some(msg) => {
             self.parse_connector_message(msg).await;
@@ -13,7 +13,10 @@ use crate::{
     ButtplugClientMessageFuturePair,
     ButtplugServerMessageStateShared,
   },
-  core::message::{ButtplugCurrentSpecServerMessage, ButtplugMessage, ButtplugMessageValidator},
+  core::{
+    connector::ButtplugConnectorError,
+    message::{ButtplugCurrentSpecServerMessage, ButtplugMessage, ButtplugMessageValidator},
+  },
 };
 use dashmap::DashMap;
 use std::sync::{
@@ -107,6 +110,16 @@ impl ClientMessageSorter {
       }
     }
   }
+
+  /// Fails every future still waiting on a response, for when the connection the messages were
+  /// sent over is gone and no response will ever arrive.
+  pub fn cancel_pending_futures(&self) {
+    self.future_map.retain(|id, state| {
+      trace!("Cancelling future for id {}.", id);
+      state.set_reply(Err(ButtplugConnectorError::ConnectorChannelClosed.into()));
+      false
+    });
+  }
 }
 
 impl Default for ClientMessageSorter {
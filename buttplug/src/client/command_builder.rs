@@ -0,0 +1,303 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Builds device commands that are checked against the device's message attributes before being
+//! sent.
+//!
+//! The command methods on [ButtplugClientDevice] pass values through to the server, so a command
+//! the device can't take only fails once the server replies. A [DeviceCommandBuilder] checks each
+//! command as it's added (that the feature exists, is the right actuator or sensor type, and that
+//! the value fits its range) and reports the first problem locally, without a round trip.
+//!
+//! ```ignore
+//! device
+//!   .command()
+//!   .scalar(0, 0.5, ActuatorType::Vibrate)
+//!   .scalar_step(1, 3, ActuatorType::Rotate)
+//!   .linear(0, 500, 1.0)
+//!   .send()
+//!   .await?;
+//! ```
+
+use super::{
+  create_boxed_future_client_error,
+  device::ButtplugClientDevice,
+  ButtplugClientResultFuture,
+};
+use crate::core::{
+  errors::ButtplugDeviceError,
+  message::{
+    ActuatorType,
+    ButtplugCurrentSpecClientMessage,
+    ButtplugDeviceMessageType,
+    ClientGenericDeviceMessageAttributes,
+    LinearCmd,
+    RotateCmd,
+    RotationSubcommand,
+    ScalarCmd,
+    ScalarSubcommand,
+    SensorSubscribeCmd,
+    SensorType,
+    SensorUnsubscribeCmd,
+    VectorSubcommand,
+  },
+};
+use futures::FutureExt;
+use std::collections::BTreeMap;
+
+/// Collects commands for a single device, checking each against the device's message attributes.
+///
+/// Commands for the same feature replace each other, so only the last value set for a feature is
+/// sent. Scalar, linear and rotate commands are each sent as a single message covering all of
+/// their features. Created by [ButtplugClientDevice::command].
+pub struct DeviceCommandBuilder<'a> {
+  device: &'a ButtplugClientDevice,
+  scalars: BTreeMap<u32, (f64, ActuatorType)>,
+  linears: BTreeMap<u32, (u32, f64)>,
+  rotations: BTreeMap<u32, (f64, bool)>,
+  sensor_subscriptions: Vec<(u32, SensorType, bool)>,
+  /// First problem found with a command, returned when building.
+  error: Option<ButtplugDeviceError>,
+}
+
+fn check_unit_range(index: u32, value: f64) -> Result<(), ButtplugDeviceError> {
+  if (0.0..=1.0).contains(&value) {
+    Ok(())
+  } else {
+    Err(ButtplugDeviceError::DeviceFeatureValueOutOfRange(
+      index,
+      value.to_string(),
+    ))
+  }
+}
+
+fn feature_attributes(
+  attrs: &Option<Vec<ClientGenericDeviceMessageAttributes>>,
+  message_type: ButtplugDeviceMessageType,
+  index: u32,
+) -> Result<&ClientGenericDeviceMessageAttributes, ButtplugDeviceError> {
+  let attrs = attrs
+    .as_ref()
+    .ok_or(ButtplugDeviceError::MessageNotSupported(message_type))?;
+  attrs
+    .get(index as usize)
+    .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+      attrs.len() as u32,
+      index,
+    ))
+}
+
+impl<'a> DeviceCommandBuilder<'a> {
+  pub(super) fn new(device: &'a ButtplugClientDevice) -> Self {
+    Self {
+      device,
+      scalars: BTreeMap::new(),
+      linears: BTreeMap::new(),
+      rotations: BTreeMap::new(),
+      sensor_subscriptions: vec![],
+      error: None,
+    }
+  }
+
+  /// Runs a check, keeping the first error found.
+  fn check<T>(&mut self, result: Result<T, ButtplugDeviceError>) -> Option<T> {
+    match result {
+      Ok(value) => Some(value),
+      Err(err) => {
+        self.error.get_or_insert(err);
+        None
+      }
+    }
+  }
+
+  fn scalar_attributes(
+    &self,
+    index: u32,
+    actuator: ActuatorType,
+  ) -> Result<&'a ClientGenericDeviceMessageAttributes, ButtplugDeviceError> {
+    let attrs = feature_attributes(
+      self.device.message_attributes().scalar_cmd(),
+      ButtplugDeviceMessageType::ScalarCmd,
+      index,
+    )?;
+    if *attrs.actuator_type() != actuator {
+      return Err(ButtplugDeviceError::DeviceActuatorTypeMismatch(
+        index.to_string(),
+        actuator,
+        *attrs.actuator_type(),
+      ));
+    }
+    Ok(attrs)
+  }
+
+  /// Set the scalar actuator at `index` to `value`, from 0.0 to 1.0.
+  pub fn scalar(&mut self, index: u32, value: f64, actuator: ActuatorType) -> &mut Self {
+    let result = self
+      .scalar_attributes(index, actuator)
+      .and_then(|_| check_unit_range(index, value));
+    if self.check(result).is_some() {
+      self.scalars.insert(index, (value, actuator));
+    }
+    self
+  }
+
+  /// Set the scalar actuator at `index` to one of its steps, from 0 to its step count.
+  pub fn scalar_step(&mut self, index: u32, step: u32, actuator: ActuatorType) -> &mut Self {
+    let result = self.scalar_attributes(index, actuator).and_then(|attrs| {
+      let step_count = *attrs.step_count();
+      if step > step_count {
+        Err(ButtplugDeviceError::DeviceFeatureStepOutOfRange(
+          index, step_count, step,
+        ))
+      } else {
+        Ok(step as f64 / step_count.max(1) as f64)
+      }
+    });
+    if let Some(value) = self.check(result) {
+      self.scalars.insert(index, (value, actuator));
+    }
+    self
+  }
+
+  /// Move the linear actuator at `index` to `position`, from 0.0 to 1.0, over `duration` ms.
+  pub fn linear(&mut self, index: u32, duration: u32, position: f64) -> &mut Self {
+    let result = feature_attributes(
+      self.device.message_attributes().linear_cmd(),
+      ButtplugDeviceMessageType::LinearCmd,
+      index,
+    )
+    .and_then(|_| check_unit_range(index, position));
+    if self.check(result).is_some() {
+      self.linears.insert(index, (duration, position));
+    }
+    self
+  }
+
+  /// Spin the rotator at `index` at `speed`, from 0.0 to 1.0.
+  pub fn rotate(&mut self, index: u32, speed: f64, clockwise: bool) -> &mut Self {
+    let result = feature_attributes(
+      self.device.message_attributes().rotate_cmd(),
+      ButtplugDeviceMessageType::RotateCmd,
+      index,
+    )
+    .and_then(|_| check_unit_range(index, speed));
+    if self.check(result).is_some() {
+      self.rotations.insert(index, (speed, clockwise));
+    }
+    self
+  }
+
+  fn sensor_subscription(&mut self, index: u32, sensor_type: SensorType, subscribe: bool) {
+    let result = self
+      .device
+      .message_attributes()
+      .sensor_subscribe_cmd()
+      .as_ref()
+      .ok_or(ButtplugDeviceError::MessageNotSupported(
+        ButtplugDeviceMessageType::SensorSubscribeCmd,
+      ))
+      .and_then(|attrs| {
+        let attr = attrs
+          .get(index as usize)
+          .ok_or(ButtplugDeviceError::DeviceSensorIndexError(
+            attrs.len() as u32,
+            index,
+          ))?;
+        if *attr.sensor_type() != sensor_type {
+          return Err(ButtplugDeviceError::DeviceSensorTypeMismatch(
+            index,
+            sensor_type,
+            *attr.sensor_type(),
+          ));
+        }
+        Ok(())
+      });
+    if self.check(result).is_some() {
+      self
+        .sensor_subscriptions
+        .push((index, sensor_type, subscribe));
+    }
+  }
+
+  /// Subscribe to the sensor at `index`.
+  pub fn subscribe_sensor(&mut self, index: u32, sensor_type: SensorType) -> &mut Self {
+    self.sensor_subscription(index, sensor_type, true);
+    self
+  }
+
+  /// Unsubscribe from the sensor at `index`.
+  pub fn unsubscribe_sensor(&mut self, index: u32, sensor_type: SensorType) -> &mut Self {
+    self.sensor_subscription(index, sensor_type, false);
+    self
+  }
+
+  /// Check the commands, and turn them into the messages that would be sent to the server.
+  pub fn build(&self) -> Result<Vec<ButtplugCurrentSpecClientMessage>, ButtplugDeviceError> {
+    if let Some(err) = &self.error {
+      return Err(err.clone());
+    }
+    let device_index = self.device.index();
+    let mut messages = vec![];
+    if !self.scalars.is_empty() {
+      let scalars = self
+        .scalars
+        .iter()
+        .map(|(index, (value, actuator))| ScalarSubcommand::new(*index, *value, *actuator))
+        .collect();
+      messages.push(ScalarCmd::new(device_index, scalars).into());
+    }
+    if !self.linears.is_empty() {
+      let vectors = self
+        .linears
+        .iter()
+        .map(|(index, (duration, position))| VectorSubcommand::new(*index, *duration, *position))
+        .collect();
+      messages.push(LinearCmd::new(device_index, vectors).into());
+    }
+    if !self.rotations.is_empty() {
+      let rotations = self
+        .rotations
+        .iter()
+        .map(|(index, (speed, clockwise))| RotationSubcommand::new(*index, *speed, *clockwise))
+        .collect();
+      messages.push(RotateCmd::new(device_index, rotations).into());
+    }
+    for (index, sensor_type, subscribe) in &self.sensor_subscriptions {
+      messages.push(if *subscribe {
+        SensorSubscribeCmd::new(device_index, *index, *sensor_type).into()
+      } else {
+        SensorUnsubscribeCmd::new(device_index, *index, *sensor_type).into()
+      });
+    }
+    if messages.is_empty() {
+      return Err(ButtplugDeviceError::ProtocolRequirementError(
+        "No commands were added to the builder".to_owned(),
+      ));
+    }
+    Ok(messages)
+  }
+
+  /// Check the commands and send them to the device, in the order scalar, linear, rotate, then
+  /// sensor subscriptions. Nothing is sent if any command is invalid.
+  pub fn send(&self) -> ButtplugClientResultFuture {
+    let messages = match self.build() {
+      Ok(messages) => messages,
+      Err(err) => return create_boxed_future_client_error(err.into()),
+    };
+    let send_futs: Vec<_> = messages
+      .into_iter()
+      .map(|msg| self.device.send_message(msg))
+      .collect();
+    async move {
+      for fut in send_futs {
+        fut.await?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+}
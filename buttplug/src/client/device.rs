@@ -8,7 +8,9 @@
 //! Representation and management of devices connected to the server.
 
 use super::{
+  command_builder::DeviceCommandBuilder,
   create_boxed_future_client_error,
+  sensor_stream::{ButtplugClientSensorStream, SensorSubscription, SensorUpdate},
   ButtplugClientMessageSender,
   ButtplugClientResultFuture,
 };
@@ -22,13 +24,19 @@ use crate::{
       ButtplugDeviceMessageType,
       ClientDeviceMessageAttributes,
       ClientGenericDeviceMessageAttributes,
+      DeviceConnectionInfo,
+      DeviceLatency,
       DeviceMessageInfo,
+      DeviceRemovedReason,
       Endpoint,
       LinearCmd,
+      PatternKeyframe,
+      PlayPatternCmd,
       RawReadCmd,
       RawSubscribeCmd,
       RawUnsubscribeCmd,
       RawWriteCmd,
+      RequestDeviceLatency,
       RotateCmd,
       RotationSubcommand,
       ScalarCmd,
@@ -38,19 +46,21 @@ use crate::{
       SensorType,
       SensorUnsubscribeCmd,
       StopDeviceCmd,
+      StopPatternCmd,
       VectorSubcommand,
     },
   },
   util::stream::convert_broadcast_receiver_to_stream,
 };
-use futures::{FutureExt, Stream};
-use getset::{CopyGetters, Getters};
+use futures::{future, FutureExt, Stream, StreamExt};
+use getset::Getters;
 use std::{
   collections::HashMap,
   fmt,
   sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU32, Ordering},
     Arc,
+    Mutex,
   },
 };
 use tokio::sync::broadcast;
@@ -140,7 +150,7 @@ pub enum LinearCommand {
   LinearMap(HashMap<u32, (u32, f64)>),
 }
 
-#[derive(Getters, CopyGetters)]
+#[derive(Getters)]
 /// Client-usable representation of device connected to the corresponding
 /// [ButtplugServer][crate::server::ButtplugServer]
 ///
@@ -156,13 +166,16 @@ pub struct ButtplugClientDevice {
   display_name: Option<String>,
   /// Index of the device, matching the index in the
   /// [ButtplugServer][crate::server::ButtplugServer]'s
-  /// [DeviceManager][crate::server::device_manager::DeviceManager].
-  #[getset(get_copy = "pub")]
-  index: u32,
+  /// [DeviceManager][crate::server::device_manager::DeviceManager]. Can change if the client
+  /// reconnects and the server hands out a new index for the same device.
+  index: Arc<AtomicU32>,
   /// Map of messages the device can take, along with the attributes of those
   /// messages.
   #[getset(get = "pub")]
   message_attributes: ClientDeviceMessageAttributes,
+  /// How the server is connected to the device, if the server reported it.
+  #[getset(get = "pub")]
+  connection_info: Option<DeviceConnectionInfo>,
   /// Sends commands from the [ButtplugClientDevice] instance to the
   /// [ButtplugClient][super::ButtplugClient]'s event loop, which will then send
   /// the message on to the [ButtplugServer][crate::server::ButtplugServer]
@@ -176,6 +189,13 @@ pub struct ButtplugClientDevice {
   /// [ButtplugClientDevice] instance is still connected to the
   /// [ButtplugServer][crate::server::ButtplugServer].
   client_connected: Arc<AtomicBool>,
+  /// Number of [ButtplugClientSensorStream] instances using each subscribed
+  /// sensor, so we only unsubscribe once the last one is dropped.
+  sensor_subscription_counts: Arc<Mutex<HashMap<u32, usize>>>,
+  /// Why the server removed the device, once it has and if the server said.
+  removed_reason: Arc<Mutex<Option<DeviceRemovedReason>>>,
+  /// True if the server said it will try to connect the device again after removing it.
+  will_reconnect: Arc<AtomicBool>,
 }
 
 impl ButtplugClientDevice {
@@ -197,6 +217,7 @@ impl ButtplugClientDevice {
     display_name: &Option<String>,
     index: u32,
     message_attributes: &ClientDeviceMessageAttributes,
+    connection_info: &Option<DeviceConnectionInfo>,
     message_sender: &Arc<ButtplugClientMessageSender>,
   ) -> Self {
     info!(
@@ -210,12 +231,16 @@ impl ButtplugClientDevice {
     Self {
       name: name.to_owned(),
       display_name: display_name.clone(),
-      index,
+      index: Arc::new(AtomicU32::new(index)),
       message_attributes: message_attributes.clone(),
+      connection_info: connection_info.clone(),
       event_loop_sender: message_sender.clone(),
       internal_event_sender: event_sender,
       device_connected,
       client_connected,
+      sensor_subscription_counts: Arc::new(Mutex::new(HashMap::new())),
+      removed_reason: Arc::new(Mutex::new(None)),
+      will_reconnect: Arc::new(AtomicBool::new(false)),
     }
   }
 
@@ -228,14 +253,34 @@ impl ButtplugClientDevice {
       info.device_display_name(),
       info.device_index(),
       info.device_messages(),
+      info.device_connection_info(),
       sender,
     )
   }
 
+  pub fn index(&self) -> u32 {
+    self.index.load(Ordering::SeqCst)
+  }
+
   pub fn connected(&self) -> bool {
     self.device_connected.load(Ordering::SeqCst)
   }
 
+  /// Why the server removed the device. None while the device is connected, or if the server
+  /// didn't give a reason (e.g. servers using older message specs).
+  pub fn removed_reason(&self) -> Option<DeviceRemovedReason> {
+    *self
+      .removed_reason
+      .lock()
+      .expect("Lock is never held across a panic")
+  }
+
+  /// True if the server will try to connect the device again by itself after removing it. The
+  /// device then comes back as a new DeviceAdded event.
+  pub fn will_reconnect(&self) -> bool {
+    self.will_reconnect.load(Ordering::SeqCst)
+  }
+
   pub fn event_stream(&self) -> Box<dyn Stream<Item = ButtplugClientDeviceEvent> + Send + Unpin> {
     Box::new(Box::pin(convert_broadcast_receiver_to_stream(
       self.internal_event_sender.subscribe(),
@@ -334,7 +379,7 @@ impl ButtplugClientDevice {
         }
       }
     }
-    let msg = ScalarCmd::new(self.index, scalar_vec).into();
+    let msg = ScalarCmd::new(self.index(), scalar_vec).into();
     info!("{:?}", msg);
     self.event_loop_sender.send_message_expect_ok(msg)
   }
@@ -365,7 +410,58 @@ impl ButtplugClientDevice {
     )
   }
 
+  pub fn frequency_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
+    self.scalar_value_attributes(&ActuatorType::Frequency)
+  }
+
+  /// Commands a device driven by a waveform to vibrate with the given amplitude and frequency,
+  /// assuming it has Vibrate and Frequency actuators. Both are 0.0-1.0, with frequency spread over
+  /// the frequency range of the device.
+  pub fn vibrate_waveform(&self, amplitude: f64, frequency: f64) -> ButtplugClientResultFuture {
+    let frequency_attrs = self.frequency_attributes();
+    if frequency_attrs.is_empty() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::UnhandledCommand(format!(
+          "ScalarCmd with {} is not handled by this device",
+          ActuatorType::Frequency
+        ))
+        .into(),
+      );
+    }
+    let scalars = self
+      .vibrate_attributes()
+      .iter()
+      .map(|attr| (*attr.index(), (amplitude, ActuatorType::Vibrate)))
+      .chain(
+        frequency_attrs
+          .iter()
+          .map(|attr| (*attr.index(), (frequency, ActuatorType::Frequency))),
+      )
+      .collect();
+    self.scalar(&ScalarCommand::ScalarMap(scalars))
+  }
+
   pub fn scalar(&self, scalar_cmd: &ScalarCommand) -> ButtplugClientResultFuture {
+    self.send_scalar(scalar_cmd, None)
+  }
+
+  /// Sends a scalar command scheduled for a time on the server clock, as returned by
+  /// [ButtplugClient::server_time](super::ButtplugClient::server_time). The server sends it to the
+  /// device early enough to make up for the device's latency, so commands scheduled for the same
+  /// time on several devices take effect together.
+  pub fn scalar_at(
+    &self,
+    scalar_cmd: &ScalarCommand,
+    server_time: u64,
+  ) -> ButtplugClientResultFuture {
+    self.send_scalar(scalar_cmd, Some(server_time))
+  }
+
+  fn send_scalar(
+    &self,
+    scalar_cmd: &ScalarCommand,
+    scheduled_time: Option<u64>,
+  ) -> ButtplugClientResultFuture {
     if self.message_attributes.scalar_cmd().is_none() {
       return create_boxed_future_client_error(
         ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::VibrateCmd).into(),
@@ -415,8 +511,9 @@ impl ButtplugClientDevice {
         }
       }
     }
-    let msg = ScalarCmd::new(self.index, scalar_vec).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    let mut msg = ScalarCmd::new(self.index(), scalar_vec);
+    msg.set_scheduled_time(scheduled_time);
+    self.event_loop_sender.send_message_expect_ok(msg.into())
   }
 
   pub fn linear_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
@@ -429,6 +526,24 @@ impl ButtplugClientDevice {
 
   /// Commands device to move linearly, assuming it has the features to do so.
   pub fn linear(&self, linear_cmd: &LinearCommand) -> ButtplugClientResultFuture {
+    self.send_linear(linear_cmd, None)
+  }
+
+  /// Sends a linear command scheduled for a time on the server clock. See
+  /// [ButtplugClientDevice::scalar_at].
+  pub fn linear_at(
+    &self,
+    linear_cmd: &LinearCommand,
+    server_time: u64,
+  ) -> ButtplugClientResultFuture {
+    self.send_linear(linear_cmd, Some(server_time))
+  }
+
+  fn send_linear(
+    &self,
+    linear_cmd: &LinearCommand,
+    scheduled_time: Option<u64>,
+  ) -> ButtplugClientResultFuture {
     if self.message_attributes.linear_cmd().is_none() {
       return create_boxed_future_client_error(
         ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::LinearCmd).into(),
@@ -473,8 +588,9 @@ impl ButtplugClientDevice {
         }
       }
     }
-    let msg = LinearCmd::new(self.index, linear_vec).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    let mut msg = LinearCmd::new(self.index(), linear_vec);
+    msg.set_scheduled_time(scheduled_time);
+    self.event_loop_sender.send_message_expect_ok(msg.into())
   }
 
   pub fn rotate_attributes(&self) -> Vec<ClientGenericDeviceMessageAttributes> {
@@ -487,6 +603,24 @@ impl ButtplugClientDevice {
 
   /// Commands device to rotate, assuming it has the features to do so.
   pub fn rotate(&self, rotate_cmd: &RotateCommand) -> ButtplugClientResultFuture {
+    self.send_rotate(rotate_cmd, None)
+  }
+
+  /// Sends a rotate command scheduled for a time on the server clock. See
+  /// [ButtplugClientDevice::scalar_at].
+  pub fn rotate_at(
+    &self,
+    rotate_cmd: &RotateCommand,
+    server_time: u64,
+  ) -> ButtplugClientResultFuture {
+    self.send_rotate(rotate_cmd, Some(server_time))
+  }
+
+  fn send_rotate(
+    &self,
+    rotate_cmd: &RotateCommand,
+    scheduled_time: Option<u64>,
+  ) -> ButtplugClientResultFuture {
     if self.message_attributes.rotate_cmd().is_none() {
       return create_boxed_future_client_error(
         ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::RotateCmd).into(),
@@ -531,8 +665,9 @@ impl ButtplugClientDevice {
         }
       }
     }
-    let msg = RotateCmd::new(self.index, rotate_vec).into();
-    self.event_loop_sender.send_message_expect_ok(msg)
+    let mut msg = RotateCmd::new(self.index(), rotate_vec);
+    msg.set_scheduled_time(scheduled_time);
+    self.event_loop_sender.send_message_expect_ok(msg.into())
   }
 
   pub fn subscribe_sensor(
@@ -546,7 +681,7 @@ impl ButtplugClientDevice {
           .into(),
       );
     }
-    let msg = SensorSubscribeCmd::new(self.index, sensor_index, sensor_type).into();
+    let msg = SensorSubscribeCmd::new(self.index(), sensor_index, sensor_type).into();
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
@@ -561,10 +696,84 @@ impl ButtplugClientDevice {
           .into(),
       );
     }
-    let msg = SensorUnsubscribeCmd::new(self.index, sensor_index, sensor_type).into();
+    let msg = SensorUnsubscribeCmd::new(self.index(), sensor_index, sensor_type).into();
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  /// Subscribes to all sensors of `sensor_type` on the device, returning a
+  /// stream of their readings.
+  ///
+  /// Subscribing happens before the returned future resolves, and the sensors
+  /// are unsubscribed once the last stream using them is dropped. The stream
+  /// ends when the device or client disconnects.
+  pub fn sensor_stream(
+    &self,
+    sensor_type: SensorType,
+  ) -> ButtplugClientResultFuture<ButtplugClientSensorStream> {
+    // The stream isn't Sync, so we can't use create_boxed_future_client_error here.
+    let Some(sensor_attrs) = self.message_attributes.sensor_subscribe_cmd() else {
+      return future::ready(Err(
+        ButtplugError::from(ButtplugDeviceError::MessageNotSupported(
+          ButtplugDeviceMessageType::SensorSubscribeCmd,
+        ))
+        .into(),
+      ))
+      .boxed();
+    };
+    let sensor_indexes: Vec<u32> = sensor_attrs
+      .iter()
+      .enumerate()
+      .filter(|x| *x.1.sensor_type() == sensor_type)
+      .map(|x| x.0 as u32)
+      .collect();
+    if sensor_indexes.is_empty() {
+      return future::ready(Err(
+        ButtplugError::from(ButtplugDeviceError::ProtocolSensorNotSupported(sensor_type)).into(),
+      ))
+      .boxed();
+    }
+    // Start listening before subscribing, so we can't miss the first readings.
+    let stream_indexes = sensor_indexes.clone();
+    let updates = self
+      .event_stream()
+      .take_while(|event| future::ready(matches!(event, ButtplugClientDeviceEvent::Message(_))))
+      .filter_map(move |event| {
+        let update = match event {
+          ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(
+            reading,
+          )) if reading.sensor_type() == sensor_type
+            && stream_indexes.contains(&reading.sensor_index()) =>
+          {
+            SensorUpdate::from_reading(&reading)
+          }
+          _ => None,
+        };
+        future::ready(update)
+      });
+    let (subscription, new_indexes) = SensorSubscription::new(
+      sensor_type,
+      sensor_indexes,
+      &self.index,
+      &self.device_connected,
+      &self.sensor_subscription_counts,
+      &self.event_loop_sender,
+    );
+    let subscribe_futs: Vec<_> = new_indexes
+      .into_iter()
+      .map(|sensor_index| self.subscribe_sensor(sensor_index, sensor_type))
+      .collect();
+    async move {
+      // Subscribe one at a time, as protocols may set up their hardware
+      // subscription on the first sensor. If this fails, dropping the
+      // subscription cleans up whatever did get subscribed.
+      for fut in subscribe_futs {
+        fut.await?;
+      }
+      Ok(ButtplugClientSensorStream::new(updates, subscription))
+    }
+    .boxed()
+  }
+
   fn read_single_sensor(&self, sensor_type: &SensorType) -> ButtplugClientResultFuture<Vec<i32>> {
     if self.message_attributes.sensor_read_cmd().is_none() {
       return create_boxed_future_client_error(
@@ -586,7 +795,7 @@ impl ButtplugClientDevice {
         ButtplugDeviceError::ProtocolSensorNotSupported(*sensor_type).into(),
       );
     }
-    let msg = SensorReadCmd::new(self.index, sensor_indexes[0], *sensor_type).into();
+    let msg = SensorReadCmd::new(self.index(), sensor_indexes[0], *sensor_type).into();
     let reply = self.event_loop_sender.send_message(msg);
     async move {
       if let ButtplugCurrentSpecServerMessage::SensorReading(data) = reply.await? {
@@ -648,7 +857,7 @@ impl ButtplugClientDevice {
       );
     }
     let msg = ButtplugCurrentSpecClientMessage::RawWriteCmd(RawWriteCmd::new(
-      self.index,
+      self.index(),
       endpoint,
       data,
       write_with_response,
@@ -668,7 +877,7 @@ impl ButtplugClientDevice {
       );
     }
     let msg = ButtplugCurrentSpecClientMessage::RawReadCmd(RawReadCmd::new(
-      self.index,
+      self.index(),
       endpoint,
       expected_length,
       timeout,
@@ -696,8 +905,10 @@ impl ButtplugClientDevice {
         ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::RawSubscribeCmd).into(),
       );
     }
-    let msg =
-      ButtplugCurrentSpecClientMessage::RawSubscribeCmd(RawSubscribeCmd::new(self.index, endpoint));
+    let msg = ButtplugCurrentSpecClientMessage::RawSubscribeCmd(RawSubscribeCmd::new(
+      self.index(),
+      endpoint,
+    ));
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
@@ -708,27 +919,125 @@ impl ButtplugClientDevice {
       );
     }
     let msg = ButtplugCurrentSpecClientMessage::RawUnsubscribeCmd(RawUnsubscribeCmd::new(
-      self.index, endpoint,
+      self.index(),
+      endpoint,
     ));
     self.event_loop_sender.send_message_expect_ok(msg)
   }
 
+  /// Start building a set of commands for the device, which are checked against the device's
+  /// message attributes before anything is sent. See [DeviceCommandBuilder].
+  pub fn command(&self) -> DeviceCommandBuilder<'_> {
+    DeviceCommandBuilder::new(self)
+  }
+
+  /// Plays a timed intensity curve on all of the device's scalar actuators, with the server
+  /// handling timing. If `looping` is true, the pattern repeats until [Self::stop_pattern] is called.
+  pub fn play_pattern(
+    &self,
+    keyframes: &[PatternKeyframe],
+    looping: bool,
+  ) -> ButtplugClientResultFuture {
+    if self.message_attributes.scalar_cmd().is_none() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::ScalarCmd).into(),
+      );
+    }
+    let msg = ButtplugCurrentSpecClientMessage::PlayPatternCmd(PlayPatternCmd::new(
+      self.index(),
+      keyframes,
+      looping,
+    ));
+    self.event_loop_sender.send_message_expect_ok(msg)
+  }
+
+  /// Stops any pattern playing on the device, and stops the device.
+  pub fn stop_pattern(&self) -> ButtplugClientResultFuture {
+    if self.message_attributes.scalar_cmd().is_none() {
+      return create_boxed_future_client_error(
+        ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::ScalarCmd).into(),
+      );
+    }
+    self
+      .event_loop_sender
+      .send_message_expect_ok(StopPatternCmd::new(self.index()).into())
+  }
+
   /// Commands device to stop all movement.
   pub fn stop(&self) -> ButtplugClientResultFuture {
     // All devices accept StopDeviceCmd
     self
       .event_loop_sender
-      .send_message_expect_ok(StopDeviceCmd::new(self.index).into())
+      .send_message_expect_ok(StopDeviceCmd::new(self.index()).into())
+  }
+
+  /// Latency stats the server has recorded for output commands sent to the device. Useful for
+  /// working out whether a laggy device is slow on the server side or in transport.
+  pub fn latency(&self) -> ButtplugClientResultFuture<DeviceLatency> {
+    let send_fut = self
+      .event_loop_sender
+      .send_message(RequestDeviceLatency::new(self.index()).into());
+    async move {
+      match send_fut.await? {
+        ButtplugCurrentSpecServerMessage::DeviceLatency(latency) => Ok(latency),
+        ButtplugCurrentSpecServerMessage::Error(err) => Err(ButtplugError::from(err).into()),
+        msg => Err(
+          ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+            "{:?}",
+            msg
+          )))
+          .into(),
+        ),
+      }
+    }
+    .boxed()
+  }
+
+  /// Sends a message to the server as-is, returning whatever the server replies with. Used by the
+  /// remote server communication manager, which forwards device messages it gets from its own
+  /// server.
+  pub(crate) fn send_message(
+    &self,
+    msg: ButtplugCurrentSpecClientMessage,
+  ) -> ButtplugClientResultFuture<ButtplugCurrentSpecServerMessage> {
+    self.event_loop_sender.send_message(msg)
   }
 
   pub(super) fn set_device_connected(&self, connected: bool) {
     self.device_connected.store(connected, Ordering::SeqCst);
   }
 
+  pub(super) fn set_removed(&self, reason: Option<DeviceRemovedReason>, will_reconnect: bool) {
+    *self
+      .removed_reason
+      .lock()
+      .expect("Lock is never held across a panic") = reason;
+    self.will_reconnect.store(will_reconnect, Ordering::SeqCst);
+  }
+
   pub(super) fn set_client_connected(&self, connected: bool) {
     self.client_connected.store(connected, Ordering::SeqCst);
   }
 
+  /// Points this instance at the index the server gave the same device after a reconnect.
+  pub(super) fn set_index(&self, index: u32) {
+    self.index.store(index, Ordering::SeqCst);
+  }
+
+  /// True if `info` describes the same physical device as this instance, meaning it has the same
+  /// name and was reported at the same transport address. Devices without connection info never
+  /// match, as there's no way to tell them apart from other devices with the same name.
+  pub(super) fn matches_device_info(&self, info: &DeviceMessageInfo) -> bool {
+    match (&self.connection_info, info.device_connection_info()) {
+      (Some(ours), Some(theirs)) => {
+        self.name == *info.device_name()
+          && ours.transport() == theirs.transport()
+          && ours.address() == theirs.address()
+      }
+      _ => false,
+    }
+  }
+
   pub(super) fn queue_event(&self, event: ButtplugClientDeviceEvent) {
     if self.internal_event_sender.receiver_count() == 0 {
       // We can drop devices before we've hooked up listeners or after the device manager drops,
@@ -748,7 +1057,7 @@ impl Eq for ButtplugClientDevice {
 
 impl PartialEq for ButtplugClientDevice {
   fn eq(&self, other: &Self) -> bool {
-    self.index == other.index
+    self.index() == other.index()
   }
 }
 
@@ -756,7 +1065,7 @@ impl fmt::Debug for ButtplugClientDevice {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct("ButtplugClientDevice")
       .field("name", &self.name)
-      .field("index", &self.index)
+      .field("index", &self.index())
       .finish()
   }
 }
@@ -0,0 +1,161 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Named groups of client devices, for sending one command to many devices.
+
+use super::{
+  ButtplugClientDevice,
+  ButtplugClientError,
+  ButtplugClientMessageSender,
+  ButtplugClientResultFuture,
+  ScalarCommand,
+};
+use crate::core::{
+  errors::{ButtplugError, ButtplugMessageError},
+  message::{
+    AddDeviceToGroup,
+    ButtplugCurrentSpecClientMessage,
+    ButtplugCurrentSpecServerMessage,
+    GroupCommandResult,
+    GroupScalarCmd,
+    RemoveDeviceFromGroup,
+    ScalarSubcommand,
+  },
+};
+use futures::future::{self, FutureExt};
+use getset::Getters;
+use std::sync::Arc;
+
+/// Named set of devices that commands can be broadcast to, kept by the server.
+///
+/// Group membership lives on the server, so a single message reaches every device in the group.
+/// The server filters commands per device before sending, so a device only gets values for
+/// actuators it actually has, with the actuator type the command asked for. Devices with nothing
+/// to do are skipped, and errors from single devices are reported in the [GroupCommandResult]
+/// instead of failing the whole command.
+///
+/// Groups are created with [ButtplugClient::device_group](super::ButtplugClient::device_group).
+#[derive(Getters)]
+pub struct ButtplugClientDeviceGroup {
+  #[getset(get = "pub")]
+  name: String,
+  #[getset(get = "pub")]
+  devices: Vec<Arc<ButtplugClientDevice>>,
+  message_sender: Arc<ButtplugClientMessageSender>,
+}
+
+impl ButtplugClientDeviceGroup {
+  pub(super) fn new(name: &str, message_sender: &Arc<ButtplugClientMessageSender>) -> Self {
+    Self {
+      name: name.to_owned(),
+      devices: vec![],
+      message_sender: message_sender.clone(),
+    }
+  }
+
+  /// Add a device to the group on the server. Adding a device that's already in the group does
+  /// nothing.
+  pub fn add_device(&mut self, device: &Arc<ButtplugClientDevice>) -> ButtplugClientResultFuture {
+    if !self.devices.contains(device) {
+      self.devices.push(device.clone());
+    }
+    self
+      .message_sender
+      .send_message_expect_ok(AddDeviceToGroup::new(&self.name, device.index()).into())
+  }
+
+  /// Remove a device from the group on the server.
+  pub fn remove_device(&mut self, device: &ButtplugClientDevice) -> ButtplugClientResultFuture {
+    self.devices.retain(|member| **member != *device);
+    self
+      .message_sender
+      .send_message_expect_ok(RemoveDeviceFromGroup::new(&self.name, device.index()).into())
+  }
+
+  /// Send a scalar command to all devices in the group.
+  ///
+  /// Indexes in [ScalarCommand::ScalarVec] and [ScalarCommand::ScalarMap] refer to each device's
+  /// own scalar actuators, and are dropped for devices where that actuator doesn't exist or has a
+  /// different actuator type. [ScalarCommand::Scalar] sets every actuator of the given type.
+  pub fn scalar(
+    &self,
+    scalar_cmd: &ScalarCommand,
+  ) -> ButtplugClientResultFuture<GroupCommandResult> {
+    let subcommands: Vec<ScalarSubcommand> = match scalar_cmd {
+      ScalarCommand::Scalar((scalar, actuator_type)) => {
+        // The server drops indexes a device doesn't have, so cover the largest device in the group.
+        let actuator_count = self
+          .devices
+          .iter()
+          .map(|device| device.scalar_attributes().len() as u32)
+          .max()
+          .unwrap_or(0);
+        (0..actuator_count)
+          .map(|index| ScalarSubcommand::new(index, *scalar, *actuator_type))
+          .collect()
+      }
+      ScalarCommand::ScalarVec(vec) => vec
+        .iter()
+        .enumerate()
+        .map(|(index, (scalar, actuator_type))| {
+          ScalarSubcommand::new(index as u32, *scalar, *actuator_type)
+        })
+        .collect(),
+      ScalarCommand::ScalarMap(map) => map
+        .iter()
+        .map(|(index, (scalar, actuator_type))| {
+          ScalarSubcommand::new(*index, *scalar, *actuator_type)
+        })
+        .collect(),
+    };
+    let msg = ButtplugCurrentSpecClientMessage::GroupScalarCmd(GroupScalarCmd::new(
+      &self.name,
+      subcommands,
+    ));
+    let send_fut = self.message_sender.send_message(msg);
+    async move {
+      match send_fut.await? {
+        ButtplugCurrentSpecServerMessage::GroupCommandResult(result) => Ok(result),
+        ButtplugCurrentSpecServerMessage::Error(err) => Err(ButtplugError::from(err).into()),
+        msg => Err(
+          ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+            "{:?}",
+            msg
+          )))
+          .into(),
+        ),
+      }
+    }
+    .boxed()
+  }
+
+  /// Stop all devices in the group.
+  pub fn stop(&self) -> ButtplugClientResultFuture<GroupCommandResult> {
+    let futs: Vec<_> = self
+      .devices
+      .iter()
+      .map(|device| {
+        let device_index = device.index();
+        let fut = device.stop();
+        async move { (device_index, fut.await) }
+      })
+      .collect();
+    async move {
+      let mut result = GroupCommandResult::default();
+      for (device_index, device_result) in future::join_all(futs).await {
+        match device_result {
+          Ok(_) => result.add_success(device_index),
+          Err(ButtplugClientError::ButtplugError(err)) => result.add_failure(device_index, err),
+          // Connector errors aren't specific to the device, so fail the whole command.
+          Err(err) => return Err(err),
+        }
+      }
+      Ok(result)
+    }
+    .boxed()
+  }
+}
@@ -8,18 +8,27 @@
 //! Communications API for accessing Buttplug Servers
 pub mod client_event_loop;
 pub mod client_message_sorter;
+pub mod command_builder;
 pub mod device;
+pub mod device_group;
+pub mod sensor_stream;
 
 use crate::{
   core::{
     connector::{ButtplugConnector, ButtplugConnectorError, ButtplugConnectorFuture},
-    errors::{ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     message::{
       ButtplugCurrentSpecClientMessage,
       ButtplugCurrentSpecServerMessage,
+      Log,
+      LogLevel,
+      ManagerScanningStatus,
       Ping,
       RequestDeviceList,
+      RequestLog,
+      RequestScanningStatus,
       RequestServerInfo,
+      ScanFilter,
       StartScanning,
       StopAllDevices,
       StopScanning,
@@ -42,13 +51,21 @@ pub use device::{
   ScalarCommand,
   ScalarValueCommand,
 };
+pub use command_builder::DeviceCommandBuilder;
+pub use device_group::ButtplugClientDeviceGroup;
+pub use sensor_stream::{ButtplugClientSensorStream, SensorUpdate, SensorValue};
 use futures::{
   future::{self, BoxFuture, FutureExt},
   Stream,
 };
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
+use instant::Instant;
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+    Weak,
+  },
+  time::Duration,
 };
 use thiserror::Error;
 use tokio::sync::{broadcast, mpsc, Mutex};
@@ -139,9 +156,27 @@ pub enum ButtplugClientEvent {
   ServerConnect,
   /// Emitted when a client connector detects that the server has disconnected.
   ServerDisconnect,
+  /// Emitted when the connection to the server was lost, and the connector is
+  /// trying to reestablish it. Devices stay valid while this happens, and will
+  /// be followed by either [ButtplugClientEvent::ServerConnect] if reconnecting
+  /// works, or [ButtplugClientEvent::ServerDisconnect] if it doesn't.
+  ServerReconnecting,
+  /// Emitted when a device the client already handed out comes back at a
+  /// different index, either because the connection was reestablished, or
+  /// because the server added the same device again after removing it (for
+  /// instance, after a server restart). Includes the [ButtplugClientDevice],
+  /// which now uses the new index, and the index it had before.
+  ///
+  /// Revived devices are then announced with
+  /// [ButtplugClientEvent::DeviceAdded] as usual, so apps that keep their own
+  /// index based maps can use this to move entries over.
+  DeviceIndexChanged(Arc<ButtplugClientDevice>, u32),
   /// Emitted when an error that cannot be matched to a request is received from
   /// the server.
   Error(ButtplugError),
+  /// Emitted for each server log message, once logs were requested with
+  /// [ButtplugClient::request_log].
+  Log(Log),
 }
 
 impl Unpin for ButtplugClientEvent {
@@ -265,11 +300,15 @@ pub struct ButtplugClient {
   client_name: String,
   /// The server name that we're current connected to.
   server_name: Arc<Mutex<Option<String>>>,
+  /// Server clock time from the handshake, and when it was estimated to be that time locally.
+  server_clock: Arc<std::sync::Mutex<Option<(u64, Instant)>>>,
   event_stream: broadcast::Sender<ButtplugClientEvent>,
   // Sender to relay messages to the internal client loop
   message_sender: Arc<ButtplugClientMessageSender>,
   connected: Arc<AtomicBool>,
   device_map: Arc<DashMap<u32, Arc<ButtplugClientDevice>>>,
+  /// Removed devices whose handles can be revived, kept across connections.
+  retired_devices: Arc<std::sync::Mutex<Vec<Weak<ButtplugClientDevice>>>>,
 }
 
 impl ButtplugClient {
@@ -280,6 +319,7 @@ impl ButtplugClient {
     Self {
       client_name: name.to_owned(),
       server_name: Arc::new(Mutex::new(None)),
+      server_clock: Arc::new(std::sync::Mutex::new(None)),
       event_stream,
       message_sender: Arc::new(ButtplugClientMessageSender::new(
         &message_sender,
@@ -287,6 +327,7 @@ impl ButtplugClient {
       )),
       connected,
       device_map: Arc::new(DashMap::new()),
+      retired_devices: Arc::new(std::sync::Mutex::new(vec![])),
     }
   }
 
@@ -315,12 +356,14 @@ impl ButtplugClient {
     })?;
     info!("Connection to server succeeded.");
     let mut client_event_loop = ButtplugClientEventLoop::new(
+      &self.client_name,
       self.connected.clone(),
       connector,
       connector_receiver,
       self.event_stream.clone(),
       self.message_sender.clone(),
       self.device_map.clone(),
+      self.retired_devices.clone(),
     );
 
     // Start the event loop before we run the handshake.
@@ -342,6 +385,7 @@ impl ButtplugClient {
   async fn run_handshake(&self) -> ButtplugClientResult {
     // Run our handshake
     info!("Running handshake with server.");
+    let sent = Instant::now();
     let msg = self
       .message_sender
       .send_message_ignore_connect_status(
@@ -353,6 +397,16 @@ impl ButtplugClient {
     if let ButtplugCurrentSpecServerMessage::ServerInfo(server_info) = msg {
       info!("Connected to {}", server_info.server_name());
       *self.server_name.lock().await = Some(server_info.server_name().clone());
+      // The server read its clock somewhere between us sending the request and getting the reply,
+      // so assume it was halfway through.
+      *self
+        .server_clock
+        .lock()
+        .expect("Lock is never held across a panic") = server_info.server_time().map(|time| {
+        let now = Instant::now();
+        let half_round_trip = (now - sent) / 2;
+        (time + half_round_trip.as_millis() as u64, now)
+      });
       // Don't set ourselves as connected until after ServerInfo has been
       // received. This means we avoid possible races with the RequestServerInfo
       // handshake.
@@ -418,6 +472,17 @@ impl ButtplugClient {
       .send_message_expect_ok(StartScanning::default().into())
   }
 
+  /// Tells server to only scan for devices matching the filter, e.g. a device the app has connected
+  /// to before. Transports that can't find any matching devices aren't scanned at all.
+  ///
+  /// Returns Err([ButtplugClientError]) if request fails due to issues with
+  /// DeviceManagers on the server, disconnection, etc.
+  pub fn start_scanning_with_filter(&self, filter: ScanFilter) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(StartScanning::new(Some(filter)).into())
+  }
+
   /// Tells server to stop scanning for devices.
   ///
   /// Returns Err([ButtplugClientError]) if request fails due to issues with
@@ -428,6 +493,37 @@ impl ButtplugClient {
       .send_message_expect_ok(StopScanning::default().into())
   }
 
+  /// Asks the server to send its logs at `level` or more severe, which arrive as
+  /// [ButtplugClientEvent::Log]. [LogLevel::Off] stops the logs. Fails if the server doesn't forward
+  /// logs.
+  pub fn request_log(&self, level: LogLevel) -> ButtplugClientResultFuture {
+    self
+      .message_sender
+      .send_message_expect_ok(RequestLog::new(level).into())
+  }
+
+  /// Asks the server for the scanning state of each of its hardware communication managers, e.g.
+  /// to tell whether Bluetooth scanning failed while other managers are still scanning.
+  pub fn scanning_status(&self) -> ButtplugClientResultFuture<Vec<ManagerScanningStatus>> {
+    let send_fut = self
+      .message_sender
+      .send_message(RequestScanningStatus::default().into());
+    async move {
+      match send_fut.await? {
+        ButtplugCurrentSpecServerMessage::ScanningStatus(status) => Ok(status.managers().clone()),
+        ButtplugCurrentSpecServerMessage::Error(err) => Err(ButtplugError::from(err).into()),
+        msg => Err(
+          ButtplugError::from(ButtplugMessageError::UnexpectedMessageType(format!(
+            "{:?}",
+            msg
+          )))
+          .into(),
+        ),
+      }
+    }
+    .boxed()
+  }
+
   /// Tells server to stop all devices.
   ///
   /// Returns Err([ButtplugClientError]) if request fails due to issues with
@@ -448,6 +544,12 @@ impl ButtplugClient {
     Box::pin(stream)
   }
 
+  /// Handle for a named device group on the server. The group itself is created once a device is
+  /// added to it.
+  pub fn device_group(&self, name: &str) -> ButtplugClientDeviceGroup {
+    ButtplugClientDeviceGroup::new(name, &self.message_sender)
+  }
+
   /// Retreives a list of currently connected devices.
   pub fn devices(&self) -> Vec<Arc<ButtplugClientDevice>> {
     self
@@ -477,4 +579,23 @@ impl ButtplugClient {
       None
     }
   }
+
+  /// Estimate of the current time on the server's clock, in milliseconds, for scheduling commands
+  /// with methods like [ButtplugClientDevice::scalar_at]. Returns None if the server didn't share
+  /// its clock during the handshake.
+  pub fn server_time(&self) -> Option<u64> {
+    let (time, at) = (*self
+      .server_clock
+      .lock()
+      .expect("Lock is never held across a panic"))?;
+    Some(time + at.elapsed().as_millis() as u64)
+  }
+
+  /// Server clock time a duration from now, for scheduling commands. Returns None if the server
+  /// didn't share its clock during the handshake.
+  pub fn server_time_after(&self, delay: Duration) -> Option<u64> {
+    self
+      .server_time()
+      .map(|time| time + delay.as_millis() as u64)
+  }
 }
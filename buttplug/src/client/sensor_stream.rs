@@ -0,0 +1,219 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Typed streams of sensor readings from client devices.
+
+use super::ButtplugClientMessageSender;
+use crate::{
+  core::message::{SensorReading, SensorType, SensorUnsubscribeCmd},
+  util::async_manager,
+};
+use futures::Stream;
+use getset::{CopyGetters, Getters};
+use std::{
+  collections::HashMap,
+  pin::Pin,
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+    Mutex,
+  },
+  task::{Context, Poll},
+};
+
+/// Typed value of a sensor reading.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SensorValue {
+  /// Battery level, from 0.0 to 1.0.
+  Battery(f64),
+  /// Signal strength, in dBm.
+  Rssi(i32),
+  /// True if the button is pressed.
+  Button(bool),
+  /// Pressure, within the range given in the sensor's attributes.
+  Pressure(i32),
+  /// Position, within the range given in the sensor's attributes.
+  Position(i32),
+  /// Raw data from sensors without a typed representation.
+  Unknown(Vec<i32>),
+}
+
+impl SensorValue {
+  /// Parses reading data for a sensor type. Returns None if the data is empty for a type that
+  /// needs a value.
+  pub fn parse(sensor_type: SensorType, data: &[i32]) -> Option<Self> {
+    Some(match sensor_type {
+      SensorType::Battery => Self::Battery(*data.first()? as f64 / 100.0),
+      SensorType::RSSI => Self::Rssi(*data.first()?),
+      SensorType::Button => Self::Button(*data.first()? != 0),
+      SensorType::Pressure => Self::Pressure(*data.first()?),
+      SensorType::Position => Self::Position(*data.first()?),
+      SensorType::Unknown => Self::Unknown(data.to_vec()),
+    })
+  }
+}
+
+/// Reading from one of the sensors a [ButtplugClientSensorStream] is subscribed to.
+#[derive(Clone, Debug, PartialEq, Getters, CopyGetters)]
+pub struct SensorUpdate {
+  /// Index of the sensor in the device's SensorSubscribeCmd attributes.
+  #[getset(get_copy = "pub")]
+  sensor_index: u32,
+  #[getset(get = "pub")]
+  value: SensorValue,
+}
+
+impl SensorUpdate {
+  pub(super) fn from_reading(reading: &SensorReading) -> Option<Self> {
+    Some(Self {
+      sensor_index: reading.sensor_index(),
+      value: SensorValue::parse(reading.sensor_type(), reading.data())?,
+    })
+  }
+}
+
+/// Holds a subscription on a set of sensors, unsubscribing from any sensors no other stream is
+/// using when dropped.
+pub(super) struct SensorSubscription {
+  sensor_type: SensorType,
+  sensor_indexes: Vec<u32>,
+  device_index: Arc<AtomicU32>,
+  device_connected: Arc<AtomicBool>,
+  /// Number of live subscriptions per sensor index, shared by all streams on a device.
+  subscription_counts: Arc<Mutex<HashMap<u32, usize>>>,
+  event_loop_sender: Arc<ButtplugClientMessageSender>,
+}
+
+impl SensorSubscription {
+  /// Registers the subscription, returning it along with the sensor indexes that weren't
+  /// subscribed to yet, which the caller needs to send subscribe commands for.
+  pub(super) fn new(
+    sensor_type: SensorType,
+    sensor_indexes: Vec<u32>,
+    device_index: &Arc<AtomicU32>,
+    device_connected: &Arc<AtomicBool>,
+    subscription_counts: &Arc<Mutex<HashMap<u32, usize>>>,
+    event_loop_sender: &Arc<ButtplugClientMessageSender>,
+  ) -> (Self, Vec<u32>) {
+    let mut new_indexes = vec![];
+    {
+      let mut counts = subscription_counts
+        .lock()
+        .expect("Lock is never held across a panic.");
+      for sensor_index in &sensor_indexes {
+        let count = counts.entry(*sensor_index).or_default();
+        if *count == 0 {
+          new_indexes.push(*sensor_index);
+        }
+        *count += 1;
+      }
+    }
+    (
+      Self {
+        sensor_type,
+        sensor_indexes,
+        device_index: device_index.clone(),
+        device_connected: device_connected.clone(),
+        subscription_counts: subscription_counts.clone(),
+        event_loop_sender: event_loop_sender.clone(),
+      },
+      new_indexes,
+    )
+  }
+}
+
+impl Drop for SensorSubscription {
+  fn drop(&mut self) {
+    let mut counts = self
+      .subscription_counts
+      .lock()
+      .expect("Lock is never held across a panic.");
+    for sensor_index in &self.sensor_indexes {
+      let Some(count) = counts.get_mut(sensor_index) else {
+        continue;
+      };
+      *count -= 1;
+      if *count > 0 {
+        continue;
+      }
+      counts.remove(sensor_index);
+      // There's nothing to unsubscribe from if the device is already gone.
+      if !self.device_connected.load(Ordering::SeqCst) {
+        continue;
+      }
+      let fut = self.event_loop_sender.send_message_expect_ok(
+        SensorUnsubscribeCmd::new(
+          self.device_index.load(Ordering::SeqCst),
+          *sensor_index,
+          self.sensor_type,
+        )
+        .into(),
+      );
+      let sensor_index = *sensor_index;
+      async_manager::spawn(async move {
+        if let Err(e) = fut.await {
+          debug!(
+            "Could not unsubscribe from sensor {}: {:?}",
+            sensor_index, e
+          );
+        }
+      });
+    }
+  }
+}
+
+/// Stream of typed readings from subscribed sensors, as returned by
+/// [ButtplugClientDevice::sensor_stream](super::ButtplugClientDevice::sensor_stream).
+///
+/// Ends when the device or client disconnects. Dropping the stream unsubscribes from its sensors,
+/// unless another stream is still using them.
+pub struct ButtplugClientSensorStream {
+  updates: Pin<Box<dyn Stream<Item = SensorUpdate> + Send>>,
+  _subscription: SensorSubscription,
+}
+
+impl ButtplugClientSensorStream {
+  pub(super) fn new(
+    updates: impl Stream<Item = SensorUpdate> + Send + 'static,
+    subscription: SensorSubscription,
+  ) -> Self {
+    Self {
+      updates: Box::pin(updates),
+      _subscription: subscription,
+    }
+  }
+}
+
+impl Stream for ButtplugClientSensorStream {
+  type Item = SensorUpdate;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.updates.as_mut().poll_next(cx)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_sensor_value_parse() {
+    assert_eq!(
+      SensorValue::parse(SensorType::Battery, &[42]),
+      Some(SensorValue::Battery(0.42))
+    );
+    assert_eq!(
+      SensorValue::parse(SensorType::Button, &[1]),
+      Some(SensorValue::Button(true))
+    );
+    assert_eq!(SensorValue::parse(SensorType::Pressure, &[]), None);
+    assert_eq!(
+      SensorValue::parse(SensorType::Unknown, &[1, 2]),
+      Some(SensorValue::Unknown(vec![1, 2]))
+    );
+  }
+}
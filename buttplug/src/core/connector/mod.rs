@@ -63,7 +63,7 @@
 //! There are slightly more useful situations like device forwarders where this work comes in also,
 //! but that Windows 7/Android example is where the idea originally came from.
 
-#[cfg(all(feature = "server", feature = "client", not(feature = "wasm")))]
+#[cfg(all(feature = "server", feature = "client"))]
 mod in_process_connector;
 pub mod remote_connector;
 pub mod transport;
@@ -74,12 +74,14 @@ use crate::{
 };
 use displaydoc::Display;
 use futures::future::{self, BoxFuture, FutureExt};
-#[cfg(all(feature = "server", feature = "client", not(feature = "wasm")))]
+#[cfg(all(feature = "server", feature = "client"))]
 pub use in_process_connector::{
   ButtplugInProcessClientConnector,
   ButtplugInProcessClientConnectorBuilder,
+  ButtplugMultiplexedClientConnector,
 };
 pub use remote_connector::{
+  ButtplugReconnectPolicy,
   ButtplugRemoteClientConnector,
   ButtplugRemoteConnector,
   ButtplugRemoteServerConnector,
@@ -89,6 +91,9 @@ use tokio::sync::mpsc::Sender;
 #[cfg(feature = "websockets")]
 pub use transport::ButtplugWebsocketClientTransport;
 
+#[cfg(feature = "mqtt")]
+pub use transport::{ButtplugMqttTransport, ButtplugMqttTransportBuilder};
+
 #[cfg(feature = "websockets")]
 pub use transport::{ButtplugWebsocketServerTransport, ButtplugWebsocketServerTransportBuilder};
 
@@ -181,6 +186,18 @@ where
   /// If the connector is not currently connected, or an error happens during
   /// the send operation, this will return a [ButtplugConnectorError]
   fn send(&self, msg: OutboundMessageType) -> ButtplugConnectorResultFuture;
+  /// Reestablishes a connection that was lost without [ButtplugConnector::disconnect] being called,
+  /// sending incoming messages to a new receiver.
+  ///
+  /// Returns None if the connector does not support reconnecting, meaning a lost connection is
+  /// final. Otherwise, the returned future resolves once the connection is back up, or with an
+  /// error if the connector has given up trying.
+  fn reconnect(
+    &mut self,
+    _message_receiver: Sender<InboundMessageType>,
+  ) -> Option<BoxFuture<'static, Result<(), ButtplugConnectorError>>> {
+    None
+  }
 }
 
 #[cfg(all(feature = "websockets", feature = "serialize-json"))]
@@ -202,3 +219,19 @@ pub fn new_json_ws_client_connector(
     address,
   ))
 }
+
+/// Same as [new_json_ws_client_connector], but using the MessagePack serializer. The server has
+/// to use a MessagePack or negotiated serializer.
+#[cfg(all(feature = "websockets", feature = "serialize-msgpack"))]
+pub fn new_msgpack_ws_client_connector(
+  address: &str,
+) -> impl ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage> {
+  use crate::core::message::serializer::ButtplugClientMessagePackSerializer;
+
+  ButtplugRemoteClientConnector::<
+      ButtplugWebsocketClientTransport,
+      ButtplugClientMessagePackSerializer,
+    >::new(ButtplugWebsocketClientTransport::new_insecure_connector(
+    address,
+  ))
+}
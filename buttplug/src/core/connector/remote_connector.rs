@@ -26,12 +26,55 @@ use crate::{
     ButtplugMessage,
     ButtplugServerMessage,
   },
-  util::async_manager,
+  util::{async_manager, sleep},
 };
 use futures::{future::BoxFuture, select, FutureExt};
-use std::marker::PhantomData;
+use getset::CopyGetters;
+use std::{
+  marker::PhantomData,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
+/// Controls how a [ButtplugRemoteConnector] tries to bring a connection back up after the transport
+/// loses it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct ButtplugReconnectPolicy {
+  /// Number of connection attempts to make before giving up.
+  max_attempts: u32,
+  /// Time to wait before each connection attempt.
+  retry_delay: Duration,
+}
+
+impl ButtplugReconnectPolicy {
+  pub fn new(max_attempts: u32, retry_delay: Duration) -> Self {
+    Self {
+      max_attempts,
+      retry_delay,
+    }
+  }
+}
+
+impl Default for ButtplugReconnectPolicy {
+  fn default() -> Self {
+    Self::new(5, Duration::from_secs(1))
+  }
+}
+
+/// Holds the transport between connections, if the connector is allowed to reconnect.
+type TransportSlot<TransportType> = Arc<Mutex<Option<TransportType>>>;
+
+fn return_transport<TransportType>(
+  transport_slot: Option<TransportSlot<TransportType>>,
+  transport: TransportType,
+) {
+  if let Some(slot) = transport_slot {
+    *slot.lock().expect("Lock is never held across a panic.") = Some(transport);
+  }
+}
+
 enum ButtplugRemoteConnectorMessage<T>
 where
   T: ButtplugMessage + 'static,
@@ -64,6 +107,8 @@ async fn remote_connector_event_loop<
   transport_outgoing_sender: Sender<ButtplugSerializedMessage>,
   // Takes data coming in from the transport.
   mut transport_incoming_recv: Receiver<ButtplugTransportIncomingMessage>,
+  // If set, the transport is handed back here when the connection is lost, so it can be reconnected.
+  transport_slot: Option<TransportSlot<TransportType>>,
 ) where
   TransportType: ButtplugConnectorTransport + 'static,
   SerializerType: ButtplugMessageSerializer<Inbound = InboundMessageType, Outbound = OutboundMessageType>
@@ -84,7 +129,9 @@ async fn remote_connector_event_loop<
       transport = transport_incoming_recv.recv().fuse() =>
       match transport {
         Some(msg) => StreamValue::Incoming(msg),
-        None => StreamValue::NoValue,
+        None => StreamValue::Incoming(ButtplugTransportIncomingMessage::Close(
+          "Transport channel closed".to_owned(),
+        )),
       },
       connector = connector_outgoing_recv.recv().fuse() =>
       match connector {
@@ -128,7 +175,8 @@ async fn remote_connector_event_loop<
           }
           ButtplugTransportIncomingMessage::Close(s) => {
             info!("Connector closing connection {}", s);
-            break;
+            return_transport(transport_slot, transport);
+            return;
           }
           // TODO We should probably make connecting an event?
           ButtplugTransportIncomingMessage::Connected => {}
@@ -150,6 +198,7 @@ async fn remote_connector_event_loop<
               .is_err()
             {
               error!("Transport has disconnected, exiting remote connector loop.");
+              return_transport(transport_slot, transport);
               return;
             }
           }
@@ -203,7 +252,12 @@ pub struct ButtplugRemoteConnector<
   /// transport to connect to). It also limits the lifetime of the connector to
   /// the lifetime of the event loop, meaning if for any reason we exit, we make
   /// sure the transport is dropped.
-  transport: Option<TransportType>,
+  ///
+  /// If a reconnect policy is set, the event loop puts the transport back when the connection is
+  /// lost, so [ButtplugConnector::reconnect] can use it again.
+  transport: TransportSlot<TransportType>,
+  /// Whether and how to reconnect if the transport loses its connection.
+  reconnect_policy: Option<ButtplugReconnectPolicy>,
   /// Sender for forwarding outgoing messages to the connector event loop.
   event_loop_sender: Option<Sender<ButtplugRemoteConnectorMessage<OutboundMessageType>>>,
   dummy_serializer: PhantomData<SerializerType>,
@@ -220,11 +274,22 @@ where
 {
   pub fn new(transport: TransportType) -> Self {
     Self {
-      transport: Some(transport),
+      transport: Arc::new(Mutex::new(Some(transport))),
+      reconnect_policy: None,
       event_loop_sender: None,
       dummy_serializer: PhantomData::default(),
     }
   }
+
+  /// Allow the connector to reconnect the transport if it loses its connection. Needs to be set
+  /// before connecting, and only works with transports that can be connected more than once.
+  pub fn set_reconnect_policy(&mut self, reconnect_policy: ButtplugReconnectPolicy) {
+    self.reconnect_policy = Some(reconnect_policy);
+  }
+
+  fn reconnect_transport_slot(&self) -> Option<TransportSlot<TransportType>> {
+    self.reconnect_policy.map(|_| self.transport.clone())
+  }
 }
 
 impl<TransportType, SerializerType, OutboundMessageType, InboundMessageType>
@@ -246,11 +311,13 @@ where
     &mut self,
     connector_incoming_sender: Sender<InboundMessageType>,
   ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
-    if self.transport.is_some() {
-      let transport = self
-        .transport
-        .take()
-        .expect("Already checked that this would be a valid take().");
+    let transport = self
+      .transport
+      .lock()
+      .expect("Lock is never held across a panic.")
+      .take();
+    if let Some(transport) = transport {
+      let transport_slot = self.reconnect_transport_slot();
       let (connector_outgoing_sender, connector_outgoing_receiver) = channel(256);
       self.event_loop_sender = Some(connector_outgoing_sender);
       async move {
@@ -275,6 +342,7 @@ where
                 transport,
                 transport_outgoing_sender,
                 transport_incoming_receiver,
+                transport_slot,
               )
               .await
             });
@@ -289,6 +357,68 @@ where
     }
   }
 
+  fn reconnect(
+    &mut self,
+    connector_incoming_sender: Sender<InboundMessageType>,
+  ) -> Option<BoxFuture<'static, Result<(), ButtplugConnectorError>>> {
+    let reconnect_policy = self.reconnect_policy?;
+    let transport_slot = self.transport.clone();
+    let (connector_outgoing_sender, connector_outgoing_receiver) = channel(256);
+    self.event_loop_sender = Some(connector_outgoing_sender);
+    Some(
+      async move {
+        for attempt in 1..=reconnect_policy.max_attempts() {
+          sleep(reconnect_policy.retry_delay()).await;
+          // If the slot is empty, we've been disconnected while waiting.
+          let Some(transport) = transport_slot
+            .lock()
+            .expect("Lock is never held across a panic.")
+            .take()
+          else {
+            return Err(ButtplugConnectorError::ConnectorNotConnected);
+          };
+          info!(
+            "Reconnecting transport, attempt {} of {}",
+            attempt,
+            reconnect_policy.max_attempts()
+          );
+          let (transport_outgoing_sender, transport_outgoing_receiver) = channel(256);
+          let (transport_incoming_sender, transport_incoming_receiver) = channel(256);
+          match transport
+            .connect(transport_outgoing_receiver, transport_incoming_sender)
+            .await
+          {
+            Ok(()) => {
+              async_manager::spawn(remote_connector_event_loop::<
+                TransportType,
+                SerializerType,
+                OutboundMessageType,
+                InboundMessageType,
+              >(
+                connector_outgoing_receiver,
+                connector_incoming_sender,
+                transport,
+                transport_outgoing_sender,
+                transport_incoming_receiver,
+                Some(transport_slot),
+              ));
+              return Ok(());
+            }
+            Err(e) => {
+              warn!("Reconnect attempt {} failed: {:?}", attempt, e);
+              return_transport(Some(transport_slot.clone()), transport);
+            }
+          }
+        }
+        Err(ButtplugConnectorError::ConnectorGenericError(format!(
+          "Could not reconnect after {} attempts",
+          reconnect_policy.max_attempts()
+        )))
+      }
+      .boxed(),
+    )
+  }
+
   fn disconnect(&self) -> ButtplugConnectorResultFuture {
     if let Some(ref sender) = self.event_loop_sender {
       let sender_clone = sender.clone();
@@ -7,6 +7,8 @@
 
 //! Transports for remote (IPC/network/etc) communication between clients and servers
 
+#[cfg(feature = "mqtt")]
+mod mqtt;
 #[cfg(feature = "websockets")]
 mod websocket;
 use crate::core::connector::{
@@ -15,6 +17,8 @@ use crate::core::connector::{
   ButtplugSerializedMessage,
 };
 use futures::future::BoxFuture;
+#[cfg(feature = "mqtt")]
+pub use mqtt::{ButtplugMqttTransport, ButtplugMqttTransportBuilder};
 use thiserror::Error;
 use tokio::sync::mpsc::{Receiver, Sender};
 #[cfg(feature = "websockets")]
@@ -55,4 +59,10 @@ pub enum ButtplugConnectorTransportSpecificError {
   TungsteniteError(#[from] TungsteniteError),
   #[error("Network error: {0}")]
   GenericNetworkError(String),
+  #[cfg(feature = "websockets")]
+  #[error("TLS error: {0}")]
+  TlsError(String),
+  #[cfg(feature = "mqtt")]
+  #[error("MQTT error: {0}")]
+  MqttError(String),
 }
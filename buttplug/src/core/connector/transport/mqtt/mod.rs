@@ -0,0 +1,11 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod mqtt_packet;
+pub mod mqtt_transport;
+
+pub use mqtt_transport::{ButtplugMqttTransport, ButtplugMqttTransportBuilder};
@@ -0,0 +1,247 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Just enough of MQTT 3.1.1 to publish and subscribe on a single topic.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const PACKET_TYPE_CONNECT: u8 = 1;
+const PACKET_TYPE_CONNACK: u8 = 2;
+const PACKET_TYPE_PUBLISH: u8 = 3;
+const PACKET_TYPE_PUBACK: u8 = 4;
+const PACKET_TYPE_SUBSCRIBE: u8 = 8;
+const PACKET_TYPE_SUBACK: u8 = 9;
+const PACKET_TYPE_PINGREQ: u8 = 12;
+const PACKET_TYPE_PINGRESP: u8 = 13;
+const PACKET_TYPE_DISCONNECT: u8 = 14;
+
+/// Largest packet we'll accept from the broker. Buttplug messages are small, so this is mostly here
+/// so a broken broker can't make us allocate the 256MB the protocol allows for.
+const MQTT_MAX_PACKET_SIZE: usize = 16 * 1024 * 1024;
+
+pub(super) const PINGREQ: [u8; 2] = [PACKET_TYPE_PINGREQ << 4, 0];
+pub(super) const DISCONNECT: [u8; 2] = [PACKET_TYPE_DISCONNECT << 4, 0];
+
+/// Packets we can receive from a broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum MqttPacket {
+  ConnAck {
+    return_code: u8,
+  },
+  Publish {
+    topic: String,
+    packet_id: Option<u16>,
+    payload: Vec<u8>,
+  },
+  SubAck {
+    packet_id: u16,
+    return_codes: Vec<u8>,
+  },
+  PingResp,
+  /// Anything else a broker could send us, which we can ignore.
+  Other(u8),
+}
+
+/// Strings are prefixed with a u16 length, so they can't be any longer than that.
+fn push_string(buf: &mut Vec<u8>, string: &str) -> Result<(), String> {
+  let len = u16::try_from(string.len()).map_err(|_| {
+    format!(
+      "MQTT strings can be at most {} bytes, got {} bytes",
+      u16::MAX,
+      string.len()
+    )
+  })?;
+  buf.extend(len.to_be_bytes());
+  buf.extend(string.as_bytes());
+  Ok(())
+}
+
+fn packet(header: u8, body: Vec<u8>) -> Vec<u8> {
+  let mut packet = vec![header];
+  // Remaining length is a variable length int, 7 bits per byte, least significant first.
+  let mut len = body.len();
+  loop {
+    let mut byte = (len % 128) as u8;
+    len /= 128;
+    if len > 0 {
+      byte |= 0x80;
+    }
+    packet.push(byte);
+    if len == 0 {
+      break;
+    }
+  }
+  packet.extend(body);
+  packet
+}
+
+pub(super) fn connect(
+  client_id: &str,
+  keep_alive_secs: u16,
+  credentials: Option<(&str, &str)>,
+) -> Result<Vec<u8>, String> {
+  let mut body = vec![];
+  push_string(&mut body, "MQTT")?;
+  // Protocol level 4 is MQTT 3.1.1
+  body.push(4);
+  // Always use a clean session, as we don't want the broker holding on to messages for us.
+  let mut flags = 0x02;
+  if credentials.is_some() {
+    flags |= 0x80 | 0x40;
+  }
+  body.push(flags);
+  body.extend(keep_alive_secs.to_be_bytes());
+  push_string(&mut body, client_id)?;
+  if let Some((username, password)) = credentials {
+    push_string(&mut body, username)?;
+    push_string(&mut body, password)?;
+  }
+  Ok(packet(PACKET_TYPE_CONNECT << 4, body))
+}
+
+pub(super) fn subscribe(packet_id: u16, topic: &str) -> Result<Vec<u8>, String> {
+  let mut body = packet_id.to_be_bytes().to_vec();
+  push_string(&mut body, topic)?;
+  // QoS 0
+  body.push(0);
+  // Subscribe has reserved flags that must be 0b0010.
+  Ok(packet((PACKET_TYPE_SUBSCRIBE << 4) | 0x02, body))
+}
+
+/// QoS 0 publish.
+pub(super) fn publish(topic: &str, payload: &[u8]) -> Result<Vec<u8>, String> {
+  let mut body = vec![];
+  push_string(&mut body, topic)?;
+  body.extend(payload);
+  Ok(packet(PACKET_TYPE_PUBLISH << 4, body))
+}
+
+pub(super) fn puback(packet_id: u16) -> Vec<u8> {
+  packet(PACKET_TYPE_PUBACK << 4, packet_id.to_be_bytes().to_vec())
+}
+
+fn read_u16(body: &[u8], offset: usize) -> Result<u16, String> {
+  body
+    .get(offset..offset + 2)
+    .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    .ok_or_else(|| "MQTT packet too short".to_owned())
+}
+
+pub(super) fn decode(header: u8, body: &[u8]) -> Result<MqttPacket, String> {
+  match header >> 4 {
+    PACKET_TYPE_CONNACK => Ok(MqttPacket::ConnAck {
+      return_code: *body.get(1).ok_or("MQTT CONNACK too short")?,
+    }),
+    PACKET_TYPE_PUBLISH => {
+      let qos = (header >> 1) & 0x03;
+      let topic_len = read_u16(body, 0)? as usize;
+      let topic = body
+        .get(2..2 + topic_len)
+        .ok_or("MQTT PUBLISH topic too short")?;
+      let topic = String::from_utf8(topic.to_vec()).map_err(|_| "MQTT topic is not UTF-8")?;
+      let mut offset = 2 + topic_len;
+      let packet_id = if qos > 0 {
+        offset += 2;
+        Some(read_u16(body, offset - 2)?)
+      } else {
+        None
+      };
+      Ok(MqttPacket::Publish {
+        topic,
+        packet_id,
+        payload: body[offset..].to_vec(),
+      })
+    }
+    PACKET_TYPE_SUBACK => Ok(MqttPacket::SubAck {
+      packet_id: read_u16(body, 0)?,
+      return_codes: body[2..].to_vec(),
+    }),
+    PACKET_TYPE_PINGRESP => Ok(MqttPacket::PingResp),
+    packet_type => Ok(MqttPacket::Other(packet_type)),
+  }
+}
+
+/// Read a single packet. Not cancel safe, as a partial read loses data.
+pub(super) async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> Result<MqttPacket, String> {
+  let header = reader.read_u8().await.map_err(|e| e.to_string())?;
+  let mut len = 0usize;
+  for shift in (0..4).map(|i| i * 7) {
+    let byte = reader.read_u8().await.map_err(|e| e.to_string())?;
+    len |= ((byte & 0x7F) as usize) << shift;
+    if byte & 0x80 == 0 {
+      break;
+    }
+  }
+  if len > MQTT_MAX_PACKET_SIZE {
+    return Err(format!("MQTT packet of {} bytes is too large", len));
+  }
+  let mut body = vec![0u8; len];
+  reader
+    .read_exact(&mut body)
+    .await
+    .map_err(|e| e.to_string())?;
+  decode(header, &body)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_publish_round_trip() {
+    // Long enough to need a 2 byte remaining length.
+    let payload = vec![b'a'; 300];
+    let encoded = publish("buttplug/server", &payload).expect("Test, assuming infallible.");
+    assert_eq!(&encoded[..3], &[0x30, 0xBD, 0x02]);
+    let mut reader = &encoded[..];
+    assert_eq!(
+      read(&mut reader).await,
+      Ok(MqttPacket::Publish {
+        topic: "buttplug/server".to_owned(),
+        packet_id: None,
+        payload
+      })
+    );
+  }
+
+  #[test]
+  fn test_decode_qos1_publish() {
+    let body = [0x00, 0x01, b't', 0x12, 0x34, b'h', b'i'];
+    assert_eq!(
+      decode(0x32, &body),
+      Ok(MqttPacket::Publish {
+        topic: "t".to_owned(),
+        packet_id: Some(0x1234),
+        payload: b"hi".to_vec()
+      })
+    );
+  }
+
+  #[test]
+  fn test_connect() {
+    assert_eq!(
+      connect("bp", 30, Some(("u", "p"))),
+      Ok(vec![
+        0x10, 0x14, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0xC2, 0x00, 0x1E, 0x00, 0x02, b'b',
+        b'p', 0x00, 0x01, b'u', 0x00, 0x01, b'p'
+      ])
+    );
+  }
+
+  #[test]
+  fn test_string_too_long() {
+    let max_len = "a".repeat(u16::MAX as usize);
+    let encoded = subscribe(1, &max_len).expect("Test, assuming infallible.");
+    // Header, 3 byte remaining length, and packet ID, then the string length.
+    assert_eq!(&encoded[6..8], &[0xFF, 0xFF]);
+    // One byte more would wrap around to a 0 length, and corrupt the packet.
+    let too_long = "a".repeat(u16::MAX as usize + 1);
+    assert!(subscribe(1, &too_long).is_err());
+    assert!(publish(&too_long, b"hi").is_err());
+    assert!(connect("bp", 30, Some(("u", &too_long))).is_err());
+  }
+}
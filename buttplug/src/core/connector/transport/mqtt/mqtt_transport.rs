@@ -0,0 +1,380 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Buttplug messages exchanged through an MQTT broker
+
+use super::mqtt_packet::{self, MqttPacket};
+use crate::{
+  core::{
+    connector::{
+      transport::{
+        ButtplugConnectorTransport,
+        ButtplugConnectorTransportSpecificError,
+        ButtplugTransportIncomingMessage,
+      },
+      ButtplugConnectorError,
+      ButtplugConnectorResultFuture,
+    },
+    message::serializer::ButtplugSerializedMessage,
+  },
+  util::async_manager,
+};
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+};
+use rand::Rng;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+  io::AsyncWriteExt,
+  net::{tcp::OwnedReadHalf, TcpStream},
+  sync::{
+    mpsc::{self, Receiver, Sender},
+    Notify,
+  },
+  time::Interval,
+};
+use tracing::Instrument;
+
+const SUBSCRIBE_PACKET_ID: u16 = 1;
+
+fn mqtt_error(msg: String) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(
+    ButtplugConnectorTransportSpecificError::MqttError(msg),
+  )
+}
+
+#[derive(Clone, Debug)]
+pub struct ButtplugMqttTransportBuilder {
+  /// Broker address, as host:port.
+  broker_address: String,
+  /// Prefix for the topics messages are exchanged on.
+  topic_prefix: String,
+  /// MQTT client ID. Randomly generated if not set.
+  client_id: Option<String>,
+  /// Username and password for the broker, if it requires them.
+  credentials: Option<(String, String)>,
+  /// Keep alive interval to request from the broker, in seconds. 0 disables keep alive.
+  keep_alive_secs: u16,
+}
+
+impl ButtplugMqttTransportBuilder {
+  /// Create a builder for a transport connecting to the broker at `broker_address` (i.e.
+  /// "127.0.0.1:1883").
+  pub fn new(broker_address: &str) -> Self {
+    Self {
+      broker_address: broker_address.to_owned(),
+      topic_prefix: "buttplug".to_owned(),
+      client_id: None,
+      credentials: None,
+      keep_alive_secs: 30,
+    }
+  }
+
+  /// Set the topic prefix. Clients publish to `<prefix>/client` and listen on `<prefix>/server`,
+  /// and servers do the opposite, so a client and server need to use the same prefix to talk to
+  /// each other. Defaults to "buttplug".
+  pub fn topic_prefix(&mut self, topic_prefix: &str) -> &mut Self {
+    self.topic_prefix = topic_prefix.to_owned();
+    self
+  }
+
+  pub fn client_id(&mut self, client_id: &str) -> &mut Self {
+    self.client_id = Some(client_id.to_owned());
+    self
+  }
+
+  pub fn credentials(&mut self, username: &str, password: &str) -> &mut Self {
+    self.credentials = Some((username.to_owned(), password.to_owned()));
+    self
+  }
+
+  /// Set the keep alive interval, in seconds. The broker drops the connection if it hears nothing
+  /// for 1.5 times this long, so we ping it at half the interval. 0 turns keep alive off, as it
+  /// does in MQTT. Defaults to 30 seconds.
+  pub fn keep_alive_secs(&mut self, keep_alive_secs: u16) -> &mut Self {
+    self.keep_alive_secs = keep_alive_secs;
+    self
+  }
+
+  fn finish(&self, publish_suffix: &str, subscribe_suffix: &str) -> ButtplugMqttTransport {
+    let client_id = self.client_id.clone().unwrap_or_else(|| {
+      // MQTT 3.1.1 brokers are only required to accept IDs up to 23 characters.
+      format!("buttplug-{:08x}", rand::thread_rng().gen::<u32>())
+    });
+    ButtplugMqttTransport {
+      broker_address: self.broker_address.clone(),
+      publish_topic: format!("{}/{}", self.topic_prefix, publish_suffix),
+      subscribe_topic: format!("{}/{}", self.topic_prefix, subscribe_suffix),
+      client_id,
+      credentials: self.credentials.clone(),
+      keep_alive_secs: self.keep_alive_secs,
+      disconnect_notifier: Arc::new(Notify::new()),
+    }
+  }
+
+  /// Build a transport for use with a
+  /// [ButtplugRemoteClientConnector](crate::core::connector::ButtplugRemoteClientConnector).
+  pub fn finish_client(&self) -> ButtplugMqttTransport {
+    self.finish("client", "server")
+  }
+
+  /// Build a transport for use with a
+  /// [ButtplugRemoteServerConnector](crate::core::connector::ButtplugRemoteServerConnector).
+  pub fn finish_server(&self) -> ButtplugMqttTransport {
+    self.finish("server", "client")
+  }
+}
+
+/// Transport that exchanges serialized messages over an MQTT broker, for setups where the client
+/// and server can't reach each other directly.
+///
+/// Messages are published at QoS 0 with no retention, so the server needs to be connected before
+/// the client. Only one client should use a topic prefix at a time, as there's no way to tell
+/// clients apart on a shared topic.
+pub struct ButtplugMqttTransport {
+  broker_address: String,
+  publish_topic: String,
+  subscribe_topic: String,
+  client_id: String,
+  credentials: Option<(String, String)>,
+  keep_alive_secs: u16,
+  /// Internally held sender, used for when disconnect is called.
+  disconnect_notifier: Arc<Notify>,
+}
+
+/// Reads packets off the broker connection. Reads aren't cancel safe, so this gets its own task
+/// instead of living in the select loop.
+async fn run_reader(mut reader: OwnedReadHalf, packet_sender: Sender<Result<MqttPacket, String>>) {
+  loop {
+    let packet = mqtt_packet::read(&mut reader).await;
+    let is_err = packet.is_err();
+    if packet_sender.send(packet).await.is_err() || is_err {
+      return;
+    }
+  }
+}
+
+/// How often to ping the broker, or None if keep alive is off. Pings go out at half the keep alive
+/// time, so we're never late.
+fn ping_period(keep_alive_secs: u16) -> Option<Duration> {
+  if keep_alive_secs == 0 {
+    return None;
+  }
+  Some(Duration::from_millis(keep_alive_secs as u64 * 500))
+}
+
+/// Wait for the next ping, or forever if keep alive is off.
+async fn next_ping(ping_interval: &mut Option<Interval>) {
+  match ping_interval {
+    Some(interval) => {
+      interval.tick().await;
+    }
+    None => future::pending().await,
+  }
+}
+
+async fn next_packet(
+  packet_receiver: &mut Receiver<Result<MqttPacket, String>>,
+) -> Result<MqttPacket, ButtplugConnectorError> {
+  packet_receiver
+    .recv()
+    .await
+    .unwrap_or_else(|| Err("Broker closed connection".to_owned()))
+    .map_err(mqtt_error)
+}
+
+impl ButtplugConnectorTransport for ButtplugMqttTransport {
+  fn connect(
+    &self,
+    mut outgoing_receiver: Receiver<ButtplugSerializedMessage>,
+    incoming_sender: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let disconnect_notifier = self.disconnect_notifier.clone();
+    let broker_address = self.broker_address.clone();
+    let publish_topic = self.publish_topic.clone();
+    let subscribe_topic = self.subscribe_topic.clone();
+    let connect_packet = mqtt_packet::connect(
+      &self.client_id,
+      self.keep_alive_secs,
+      self
+        .credentials
+        .as_ref()
+        .map(|(username, password)| (username.as_str(), password.as_str())),
+    );
+    let keep_alive_secs = self.keep_alive_secs;
+    async move {
+      let connect_packet = connect_packet.map_err(mqtt_error)?;
+      let subscribe_packet =
+        mqtt_packet::subscribe(SUBSCRIBE_PACKET_ID, &subscribe_topic).map_err(mqtt_error)?;
+      let stream = TcpStream::connect(&broker_address).await.map_err(|err| {
+        ButtplugConnectorError::TransportSpecificError(
+          ButtplugConnectorTransportSpecificError::GenericNetworkError(err.to_string()),
+        )
+      })?;
+      let (reader, mut writer) = stream.into_split();
+      let (packet_sender, mut packet_receiver) = mpsc::channel(256);
+      async_manager::spawn(run_reader(reader, packet_sender));
+
+      writer
+        .write_all(&connect_packet)
+        .await
+        .map_err(|err| mqtt_error(err.to_string()))?;
+      match next_packet(&mut packet_receiver).await? {
+        MqttPacket::ConnAck { return_code: 0 } => {}
+        MqttPacket::ConnAck { return_code } => {
+          return Err(mqtt_error(format!(
+            "Broker refused connection, return code {}",
+            return_code
+          )))
+        }
+        packet => {
+          return Err(mqtt_error(format!(
+            "Expected CONNACK from broker, got {:?}",
+            packet
+          )))
+        }
+      }
+
+      writer
+        .write_all(&subscribe_packet)
+        .await
+        .map_err(|err| mqtt_error(err.to_string()))?;
+      // Brokers can start sending publishes on the topic before the SUBACK, but no one should be
+      // sending to us yet, so we can ignore anything until we get it.
+      loop {
+        if let MqttPacket::SubAck {
+          packet_id: SUBSCRIBE_PACKET_ID,
+          return_codes,
+        } = next_packet(&mut packet_receiver).await?
+        {
+          if return_codes.first().is_none_or(|code| *code == 0x80) {
+            return Err(mqtt_error(format!(
+              "Broker refused subscription to {}",
+              subscribe_topic
+            )));
+          }
+          break;
+        }
+      }
+      info!(
+        "Connected to MQTT broker {}, listening on {}",
+        broker_address, subscribe_topic
+      );
+
+      async_manager::spawn(
+        async move {
+          let mut ping_interval = ping_period(keep_alive_secs).map(tokio::time::interval);
+          loop {
+            select! {
+              msg = outgoing_receiver.recv().fuse() => {
+                let Some(msg) = msg else {
+                  info!("Connector holding MQTT transport dropped, returning");
+                  let _ = writer.write_all(&mqtt_packet::DISCONNECT).await;
+                  return;
+                };
+                let payload = match msg {
+                  ButtplugSerializedMessage::Text(text) => text.into_bytes(),
+                  ButtplugSerializedMessage::Binary(bin) => bin,
+                };
+                let packet = match mqtt_packet::publish(&publish_topic, &payload) {
+                  Ok(packet) => packet,
+                  Err(err) => {
+                    error!("Cannot encode MQTT publish, closing transport: {}", err);
+                    break;
+                  }
+                };
+                if let Err(err) = writer.write_all(&packet).await {
+                  error!("Cannot publish to MQTT broker, assuming disconnect: {}", err);
+                  break;
+                }
+              },
+              packet = packet_receiver.recv().fuse() => {
+                match packet {
+                  Some(Ok(MqttPacket::Publish { topic, packet_id, payload })) => {
+                    // We only ever subscribe at QoS 0, but acknowledge anyways in case the broker
+                    // upgrades us.
+                    if let Some(packet_id) = packet_id {
+                      let _ = writer.write_all(&mqtt_packet::puback(packet_id)).await;
+                    }
+                    if topic != subscribe_topic {
+                      continue;
+                    }
+                    let msg = match String::from_utf8(payload) {
+                      Ok(text) => ButtplugSerializedMessage::Text(text),
+                      Err(err) => ButtplugSerializedMessage::Binary(err.into_bytes()),
+                    };
+                    if incoming_sender
+                      .send(ButtplugTransportIncomingMessage::Message(msg))
+                      .await
+                      .is_err()
+                    {
+                      warn!("MQTT transport holder has closed, exiting MQTT loop.");
+                      let _ = writer.write_all(&mqtt_packet::DISCONNECT).await;
+                      return;
+                    }
+                  }
+                  Some(Ok(packet)) => trace!("Ignoring MQTT packet {:?}", packet),
+                  Some(Err(err)) => {
+                    error!("Error in MQTT transport loop (assuming disconnect): {}", err);
+                    break;
+                  }
+                  None => break,
+                }
+              },
+              _ = next_ping(&mut ping_interval).fuse() => {
+                if let Err(err) = writer.write_all(&mqtt_packet::PINGREQ).await {
+                  error!("Cannot ping MQTT broker, assuming disconnect: {}", err);
+                  break;
+                }
+              },
+              _ = disconnect_notifier.notified().fuse() => {
+                info!("MQTT transport requested to disconnect.");
+                let _ = writer.write_all(&mqtt_packet::DISCONNECT).await;
+                break;
+              }
+            }
+          }
+          if incoming_sender
+            .send(ButtplugTransportIncomingMessage::Close(
+              "MQTT broker connection closed".to_owned(),
+            ))
+            .await
+            .is_err()
+          {
+            warn!("MQTT transport holder has closed, exiting MQTT loop.");
+          }
+        }
+        .instrument(tracing::info_span!("MQTT Transport I/O Task")),
+      );
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn disconnect(self) -> ButtplugConnectorResultFuture {
+    let disconnect_notifier = self.disconnect_notifier;
+    async move {
+      disconnect_notifier.notify_waiters();
+      Ok(())
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_ping_period() {
+    assert_eq!(ping_period(30), Some(Duration::from_secs(15)));
+    assert_eq!(ping_period(1), Some(Duration::from_millis(500)));
+    // 0 turns keep alive off, instead of pinging as fast as possible.
+    assert_eq!(ping_period(0), None);
+  }
+}
@@ -7,6 +7,7 @@
 
 //! Websocket connector for client/server communication
 
+mod self_signed;
 pub mod websocket_client;
 pub mod websocket_server;
 
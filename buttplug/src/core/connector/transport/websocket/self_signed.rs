@@ -0,0 +1,213 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Self signed certificates for the websocket server
+//!
+//! Generates an ECDSA P-256 key and a certificate for `localhost` and `127.0.0.1`, signed by that
+//! same key. That's all a local server needs, so rather than pulling in a full X.509 library, the
+//! certificate is DER encoded by hand. Browsers will still ask the user to trust the certificate
+//! (usually by visiting `https://127.0.0.1:<port>` once), as nothing vouches for it.
+
+use ring::{
+  rand::{SecureRandom, SystemRandom},
+  signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_UTF8_STRING: u8 = 0x0C;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+// Context specific tags, for the certificate version and extensions.
+const TAG_VERSION: u8 = 0xA0;
+const TAG_EXTENSIONS: u8 = 0xA3;
+// Subject alternative name entry types.
+const TAG_DNS_NAME: u8 = 0x82;
+const TAG_IP_ADDRESS: u8 = 0x87;
+
+// Object identifiers, already DER encoded.
+/// ecdsa-with-SHA256 (1.2.840.10045.4.3.2)
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+/// id-ecPublicKey (1.2.840.10045.2.1)
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+/// prime256v1 (1.2.840.10045.3.1.7)
+const OID_PRIME256V1: &[u8] = &[0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+/// commonName (2.5.4.3)
+const OID_COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+/// subjectAltName (2.5.29.17)
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1D, 0x11];
+
+const HOST_NAME: &str = "localhost";
+const HOST_IP: [u8; 4] = [127, 0, 0, 1];
+// RFC 5280 says certificates that never expire should use 99991231235959Z.
+const NOT_BEFORE: &str = "200101000000Z";
+const NOT_AFTER: &str = "99991231235959Z";
+
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+  let mut out = vec![tag];
+  let len = contents.len();
+  if len < 0x80 {
+    out.push(len as u8);
+  } else {
+    let len_bytes: Vec<u8> = len
+      .to_be_bytes()
+      .into_iter()
+      .skip_while(|byte| *byte == 0)
+      .collect();
+    out.push(0x80 | len_bytes.len() as u8);
+    out.extend(len_bytes);
+  }
+  out.extend(contents);
+  out
+}
+
+fn der_sequence(items: &[&[u8]]) -> Vec<u8> {
+  der(TAG_SEQUENCE, &items.concat())
+}
+
+/// Bit strings start with the number of unused bits in the last byte, which is always 0 for us.
+fn der_bit_string(data: &[u8]) -> Vec<u8> {
+  der(TAG_BIT_STRING, &[&[0u8][..], data].concat())
+}
+
+/// A certificate and its private key, for use with
+/// [ButtplugWebsocketServerTransportBuilder](super::ButtplugWebsocketServerTransportBuilder).
+pub(super) fn generate_self_signed_cert(
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>), String> {
+  let rng = SystemRandom::new();
+  let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+    .map_err(|_| "Cannot generate TLS key".to_owned())?;
+  let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+    .map_err(|_| "Cannot load generated TLS key".to_owned())?;
+
+  // Serial numbers are positive and at most 20 bytes. Clearing the top bit keeps it positive, and
+  // setting the next one means the encoding never needs a leading 0.
+  let mut serial = [0u8; 16];
+  rng
+    .fill(&mut serial)
+    .map_err(|_| "Cannot generate certificate serial number".to_owned())?;
+  serial[0] = (serial[0] & 0x7F) | 0x40;
+
+  let signature_algorithm = der_sequence(&[OID_ECDSA_WITH_SHA256]);
+  // Self signed, so the issuer and subject are the same.
+  let name = der_sequence(&[&der(
+    TAG_SET,
+    &der_sequence(&[OID_COMMON_NAME, &der(TAG_UTF8_STRING, HOST_NAME.as_bytes())]),
+  )]);
+  let validity = der_sequence(&[
+    &der(TAG_UTC_TIME, NOT_BEFORE.as_bytes()),
+    &der(TAG_GENERALIZED_TIME, NOT_AFTER.as_bytes()),
+  ]);
+  let public_key_info = der_sequence(&[
+    &der_sequence(&[OID_EC_PUBLIC_KEY, OID_PRIME256V1]),
+    &der_bit_string(key_pair.public_key().as_ref()),
+  ]);
+  // Browsers only look at the alternative names, not the common name.
+  let alt_names = der_sequence(&[
+    &der(TAG_DNS_NAME, HOST_NAME.as_bytes()),
+    &der(TAG_IP_ADDRESS, &HOST_IP),
+  ]);
+  let extensions = der(
+    TAG_EXTENSIONS,
+    &der_sequence(&[&der_sequence(&[
+      OID_SUBJECT_ALT_NAME,
+      &der(TAG_OCTET_STRING, &alt_names),
+    ])]),
+  );
+  let tbs_certificate = der_sequence(&[
+    // Version 3, which is needed for extensions.
+    &der(TAG_VERSION, &der(TAG_INTEGER, &[0x02])),
+    &der(TAG_INTEGER, &serial),
+    &signature_algorithm,
+    &name,
+    &validity,
+    &name,
+    &public_key_info,
+    &extensions,
+  ]);
+
+  let signature = key_pair
+    .sign(&rng, &tbs_certificate)
+    .map_err(|_| "Cannot sign TLS certificate".to_owned())?;
+  let certificate = der_sequence(&[
+    &tbs_certificate,
+    &signature_algorithm,
+    &der_bit_string(signature.as_ref()),
+  ]);
+  Ok((
+    CertificateDer::from(certificate),
+    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(pkcs8.as_ref().to_vec())),
+  ))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use rustls::{pki_types::ServerName, ClientConfig, RootCertStore, ServerConfig};
+  use std::sync::Arc;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+  #[tokio::test]
+  async fn test_self_signed_cert_is_trusted_for_localhost() {
+    let (cert, key) = generate_self_signed_cert().expect("Test, assuming infallible.");
+    let server_config = ServerConfig::builder()
+      .with_no_client_auth()
+      .with_single_cert(vec![cert.clone()], key)
+      .expect("Test, assuming infallible.");
+    let acceptor = TlsAcceptor::from(Arc::new(server_config));
+    // A client that trusts the certificate has to accept it for both names, which checks the
+    // encoding, the signature and the alternative names.
+    let mut roots = RootCertStore::empty();
+    roots.add(cert).expect("Test, assuming infallible.");
+    let connector = TlsConnector::from(Arc::new(
+      ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth(),
+    ));
+    for name in ["localhost", "127.0.0.1"] {
+      let (client_stream, server_stream) = tokio::io::duplex(16 * 1024);
+      let acceptor = acceptor.clone();
+      let server = tokio::spawn(async move {
+        let mut stream = acceptor
+          .accept(server_stream)
+          .await
+          .expect("Test, assuming infallible.");
+        stream
+          .write_all(b"hi")
+          .await
+          .expect("Test, assuming infallible.");
+        stream.flush().await.expect("Test, assuming infallible.");
+      });
+      let server_name = ServerName::try_from(name).expect("Test, assuming infallible.");
+      let mut stream = connector
+        .connect(server_name, client_stream)
+        .await
+        .expect("Test, assuming infallible.");
+      let mut buf = [0u8; 2];
+      stream
+        .read_exact(&mut buf)
+        .await
+        .expect("Test, assuming infallible.");
+      assert_eq!(&buf, b"hi");
+      server.await.expect("Test, assuming infallible.");
+    }
+  }
+
+  #[test]
+  fn test_der_length() {
+    assert_eq!(der(TAG_OCTET_STRING, &[1, 2]), vec![0x04, 0x02, 1, 2]);
+    // Lengths of 128 and up are written as a byte count, then the length.
+    let long = der(TAG_OCTET_STRING, &[0; 300]);
+    assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2C]);
+    assert_eq!(long.len(), 304);
+  }
+}
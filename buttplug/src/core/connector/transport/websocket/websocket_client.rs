@@ -36,7 +36,11 @@ use tokio::sync::{
 use tokio_tungstenite::{
   connect_async,
   connect_async_tls_with_config,
-  tungstenite::protocol::Message,
+  tungstenite::{
+    client::IntoClientRequest,
+    http::{header::AUTHORIZATION, HeaderValue},
+    protocol::Message,
+  },
   Connector,
 };
 use tracing::Instrument;
@@ -116,6 +120,8 @@ pub struct ButtplugWebsocketClientTransport {
   /// If true, bypass certificate verification. Should be true for self-signed
   /// certs.
   bypass_cert_verify: bool,
+  /// Token sent as a bearer Authorization header, for servers that require one.
+  auth_token: Option<String>,
   /// Internally held sender, used for when disconnect is called.
   disconnect_notifier: Arc<Notify>,
 }
@@ -126,6 +132,7 @@ impl ButtplugWebsocketClientTransport {
       should_use_tls,
       address: address.to_owned(),
       bypass_cert_verify,
+      auth_token: None,
       disconnect_notifier: Arc::new(Notify::new()),
     }
   }
@@ -149,6 +156,13 @@ impl ButtplugWebsocketClientTransport {
   pub fn new_secure_connector(address: &str, bypass_cert_verify: bool) -> Self {
     ButtplugWebsocketClientTransport::create(address, true, bypass_cert_verify)
   }
+
+  /// Present this token to the server when connecting, for servers set up with
+  /// [auth_token](super::ButtplugWebsocketServerTransportBuilder::auth_token).
+  pub fn with_auth_token(mut self, token: &str) -> Self {
+    self.auth_token = Some(token.to_owned());
+    self
+  }
 }
 
 impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
@@ -162,8 +176,22 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
     let address = self.address.clone();
     let should_use_tls = self.should_use_tls;
     let bypass_cert_verify = self.bypass_cert_verify;
+    let auth_token = self.auth_token.clone();
     async move {
       let url = Url::parse(&address).expect("Should be checked before here");
+      let mut request = url.into_client_request().map_err(|err| {
+        ButtplugConnectorError::TransportSpecificError(
+          ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+        )
+      })?;
+      if let Some(token) = auth_token {
+        let header = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| {
+          ButtplugConnectorError::ConnectorGenericError(
+            "Auth token is not a valid header value".to_owned(),
+          )
+        })?;
+        request.headers_mut().insert(AUTHORIZATION, header);
+      }
       let stream_result = if should_use_tls {
         // If we're supposed to be a secure connection, generate a TLS connector
         // based on our certificate verfication needs. Otherwise, just pass None in
@@ -173,9 +201,9 @@ impl ButtplugConnectorTransport for ButtplugWebsocketClientTransport {
         } else {
           None
         };
-        connect_async_tls_with_config(&url, None, false, connector).await
+        connect_async_tls_with_config(request, None, false, connector).await
       } else {
-        connect_async(&url).await
+        connect_async(request).await
       };
 
       match stream_result {
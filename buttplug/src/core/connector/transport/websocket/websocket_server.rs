@@ -5,6 +5,7 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+use super::self_signed::generate_self_signed_cert;
 use crate::{
   core::{
     connector::{
@@ -20,23 +21,47 @@ use crate::{
   },
   util::async_manager,
 };
-use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
-use std::{sync::Arc, time::Duration};
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  SinkExt,
+  StreamExt,
+};
+use rustls::{
+  pki_types::{CertificateDer, PrivateKeyDer},
+  ServerConfig,
+};
+use std::{fs::File, io::BufReader, sync::Arc, time::Duration};
 use tokio::{
-  net::{TcpListener, TcpStream},
+  io::{AsyncRead, AsyncWrite},
+  net::TcpListener,
   sync::{
     mpsc::{Receiver, Sender},
     Notify,
   },
   time::sleep,
 };
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::{
+  handshake::server::{ErrorResponse, Request, Response},
+  http::{header::AUTHORIZATION, StatusCode},
+};
 
 #[derive(Clone, Debug)]
 pub struct ButtplugWebsocketServerTransportBuilder {
   /// If true, listens all on available interfaces. Otherwise, only listens on 127.0.0.1.
   listen_on_all_interfaces: bool,
-  /// Insecure port for listening for websocket connections.
+  /// Port for listening for websocket connections.
   port: u16,
+  /// Path to a PEM encoded certificate chain. If this and the private key path are set, the server
+  /// will only accept secure (wss://) connections.
+  tls_cert_path: Option<String>,
+  /// Path to the PEM encoded private key for the certificate.
+  tls_key_path: Option<String>,
+  /// If true, generate a self signed certificate instead of loading one.
+  tls_self_signed: bool,
+  /// Shared secret clients must present to connect. If None, any client can connect.
+  auth_token: Option<String>,
 }
 
 impl Default for ButtplugWebsocketServerTransportBuilder {
@@ -44,6 +69,10 @@ impl Default for ButtplugWebsocketServerTransportBuilder {
     Self {
       listen_on_all_interfaces: false,
       port: 12345,
+      tls_cert_path: None,
+      tls_key_path: None,
+      tls_self_signed: false,
+      auth_token: None,
     }
   }
 }
@@ -59,21 +88,121 @@ impl ButtplugWebsocketServerTransportBuilder {
     self
   }
 
+  pub fn tls_cert_path(&mut self, tls_cert_path: &str) -> &mut Self {
+    self.tls_cert_path = Some(tls_cert_path.to_owned());
+    self
+  }
+
+  pub fn tls_key_path(&mut self, tls_key_path: &str) -> &mut Self {
+    self.tls_key_path = Some(tls_key_path.to_owned());
+    self
+  }
+
+  /// Accept secure (wss://) connections using a self signed certificate for `localhost` and
+  /// `127.0.0.1`, generated each time the server starts listening. Browsers won't trust it until the
+  /// user accepts it, usually by visiting `https://127.0.0.1:<port>` once. Can't be combined with
+  /// certificate and key paths.
+  pub fn tls_self_signed(&mut self, tls_self_signed: bool) -> &mut Self {
+    self.tls_self_signed = tls_self_signed;
+    self
+  }
+
+  /// Require clients to present this token when connecting, either as a `token` query parameter
+  /// in the connection URL (`ws://127.0.0.1:12345/?token=<token>`) or as an `Authorization: Bearer
+  /// <token>` header. Connections without the token are rejected with a 401 during the websocket
+  /// handshake, and the server keeps waiting for a client that has it.
+  pub fn auth_token(&mut self, token: &str) -> &mut Self {
+    self.auth_token = Some(token.to_owned());
+    self
+  }
+
   pub fn finish(&self) -> ButtplugWebsocketServerTransport {
     ButtplugWebsocketServerTransport {
       port: self.port,
       listen_on_all_interfaces: self.listen_on_all_interfaces,
+      tls_cert_path: self.tls_cert_path.clone(),
+      tls_key_path: self.tls_key_path.clone(),
+      tls_self_signed: self.tls_self_signed,
+      auth_token: self.auth_token.clone(),
       disconnect_notifier: Arc::new(Notify::new()),
     }
   }
 }
 
-async fn run_connection_loop(
-  ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
+fn tls_error(msg: String) -> ButtplugConnectorError {
+  ButtplugConnectorError::TransportSpecificError(ButtplugConnectorTransportSpecificError::TlsError(
+    msg,
+  ))
+}
+
+/// Build a rustls server config from PEM encoded certificate chain and private key files.
+fn load_tls_config(cert_path: &str, key_path: &str) -> Result<ServerConfig, String> {
+  let cert_file = File::open(cert_path)
+    .map_err(|e| format!("Cannot open certificate file {}: {}", cert_path, e))?;
+  let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+    .map_err(|e| format!("Cannot read certificate file {}: {}", cert_path, e))?
+    .into_iter()
+    .map(CertificateDer::from)
+    .collect();
+  if certs.is_empty() {
+    return Err(format!("No certificates found in {}", cert_path));
+  }
+
+  let key_file = File::open(key_path)
+    .map_err(|e| format!("Cannot open private key file {}: {}", key_path, e))?;
+  let key: PrivateKeyDer<'static> = rustls_pemfile::read_all(&mut BufReader::new(key_file))
+    .map_err(|e| format!("Cannot read private key file {}: {}", key_path, e))?
+    .into_iter()
+    .find_map(|item| match item {
+      rustls_pemfile::Item::PKCS8Key(key) => Some(PrivateKeyDer::Pkcs8(key.into())),
+      rustls_pemfile::Item::RSAKey(key) => Some(PrivateKeyDer::Pkcs1(key.into())),
+      rustls_pemfile::Item::ECKey(key) => Some(PrivateKeyDer::Sec1(key.into())),
+      _ => None,
+    })
+    .ok_or_else(|| format!("No private key found in {}", key_path))?;
+
+  tls_server_config(certs, key)
+}
+
+fn tls_server_config(
+  certs: Vec<CertificateDer<'static>>,
+  key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig, String> {
+  ServerConfig::builder()
+    .with_no_client_auth()
+    .with_single_cert(certs, key)
+    .map_err(|e| format!("Invalid certificate/key pair: {}", e))
+}
+
+/// Where the websocket server gets its TLS certificate from.
+enum TlsSource {
+  Files { cert_path: String, key_path: String },
+  SelfSigned,
+}
+
+impl TlsSource {
+  fn load(self) -> Result<ServerConfig, String> {
+    match self {
+      TlsSource::Files {
+        cert_path,
+        key_path,
+      } => load_tls_config(&cert_path, &key_path),
+      TlsSource::SelfSigned => {
+        let (cert, key) = generate_self_signed_cert()?;
+        tls_server_config(vec![cert], key)
+      }
+    }
+  }
+}
+
+async fn run_connection_loop<S>(
+  ws_stream: tokio_tungstenite::WebSocketStream<S>,
   mut request_receiver: Receiver<ButtplugSerializedMessage>,
   response_sender: Sender<ButtplugTransportIncomingMessage>,
   disconnect_notifier: Arc<Notify>,
-) {
+) where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
   info!("Starting websocket server connection event loop.");
 
   let (mut websocket_server_sender, mut websocket_server_receiver) = ws_stream.split();
@@ -172,8 +301,12 @@ async fn run_connection_loop(
                   pong_count += 1;
                   continue;
                 }
-                tokio_tungstenite::tungstenite::Message::Binary(_) => {
-                  error!("Don't know how to handle binary message types!");
+                tokio_tungstenite::tungstenite::Message::Binary(binary_msg) => {
+                  trace!("Got binary: {:?}", binary_msg);
+                  if response_sender.send(ButtplugTransportIncomingMessage::Message(ButtplugSerializedMessage::Binary(binary_msg))).await.is_err() {
+                    warn!("Connector that owns transport no longer available, exiting.");
+                    break;
+                  }
                 }
               }
             },
@@ -193,10 +326,84 @@ async fn run_connection_loop(
   }
 }
 
+/// Compare tokens without bailing out at the first differing byte, so response timing doesn't
+/// give away how much of a guessed token was right.
+fn tokens_match(expected: &str, presented: &str) -> bool {
+  expected.len() == presented.len()
+    && expected
+      .bytes()
+      .zip(presented.bytes())
+      .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+      == 0
+}
+
+/// Token from the `token` query parameter, or failing that, a bearer Authorization header.
+fn presented_token(request: &Request) -> Option<String> {
+  request
+    .uri()
+    .query()
+    .and_then(|query| {
+      url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+    })
+    .or_else(|| {
+      request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_owned())
+    })
+}
+
+/// Run the websocket handshake on a new connection. Returns None if the server requires a token
+/// and the client didn't present it.
+async fn accept_websocket<S>(
+  stream: S,
+  auth_token: Option<&str>,
+) -> Result<Option<tokio_tungstenite::WebSocketStream<S>>, ButtplugConnectorError>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+{
+  let mut authorized = true;
+  // The error type is tungstenite's, we don't get to choose its size.
+  #[allow(clippy::result_large_err)]
+  let check_token = |request: &Request, response: Response| {
+    let Some(auth_token) = auth_token else {
+      return Ok(response);
+    };
+    if presented_token(request).is_some_and(|token| tokens_match(auth_token, &token)) {
+      return Ok(response);
+    }
+    authorized = false;
+    let mut error_response = ErrorResponse::new(Some("Invalid or missing auth token".to_owned()));
+    *error_response.status_mut() = StatusCode::UNAUTHORIZED;
+    Err(error_response)
+  };
+  match tokio_tungstenite::accept_hdr_async(stream, check_token).await {
+    Ok(ws_stream) => Ok(Some(ws_stream)),
+    Err(_) if !authorized => {
+      warn!("Websocket server rejected connection without a valid auth token.");
+      Ok(None)
+    }
+    Err(err) => {
+      error!("Websocket server accept error: {:?}", err);
+      Err(ButtplugConnectorError::TransportSpecificError(
+        ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+      ))
+    }
+  }
+}
+
 /// Websocket connector for ButtplugClients, using [tokio_tungstenite]
 pub struct ButtplugWebsocketServerTransport {
   port: u16,
   listen_on_all_interfaces: bool,
+  tls_cert_path: Option<String>,
+  tls_key_path: Option<String>,
+  tls_self_signed: bool,
+  auth_token: Option<String>,
   disconnect_notifier: Arc<Notify>,
 }
 
@@ -216,9 +423,37 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
 
     let addr = format!("{}:{}", base_addr, self.port);
     debug!("Websocket: Trying to listen on {}", addr);
+    let tls_source = match (&self.tls_cert_path, &self.tls_key_path) {
+      (Some(_), Some(_)) if self.tls_self_signed => {
+        return future::ready(Err(tls_error(
+          "Certificate paths can't be used with a self signed certificate".to_owned(),
+        )))
+        .boxed()
+      }
+      (Some(cert_path), Some(key_path)) => Some(TlsSource::Files {
+        cert_path: cert_path.clone(),
+        key_path: key_path.clone(),
+      }),
+      (None, None) if self.tls_self_signed => Some(TlsSource::SelfSigned),
+      (None, None) => None,
+      _ => {
+        return future::ready(Err(tls_error(
+          "Both a certificate and private key path are required for TLS".to_owned(),
+        )))
+        .boxed()
+      }
+    };
+    let auth_token = self.auth_token.clone();
     let response_sender_clone = incoming_sender;
     let disconnect_notifier_clone = disconnect_notifier;
     let fut = async move {
+      // Load TLS configuration before binding, so bad paths are reported without tying up the port.
+      let tls_acceptor = if let Some(tls_source) = tls_source {
+        let config = tls_source.load().map_err(tls_error)?;
+        Some(TlsAcceptor::from(Arc::new(config)))
+      } else {
+        None
+      };
       // Create the event loop and TCP listener we'll accept connections on.
       let try_socket = TcpListener::bind(&addr).await;
       debug!("Websocket: Socket bound.");
@@ -228,30 +463,47 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
         )
       })?;
       debug!("Websocket: Listening on: {}", addr);
-      if let Ok((stream, _)) = listener.accept().await {
+      // Connections that fail the TLS handshake, or are rejected for a missing or wrong auth token,
+      // don't end the connect, we just go back to waiting for the next one.
+      loop {
+        let Ok((stream, _)) = listener.accept().await else {
+          return Err(ButtplugConnectorError::ConnectorGenericError(
+            "Could not run accept for port".to_owned(),
+          ));
+        };
         info!("Websocket: Got connection");
-        let ws_stream = tokio_tungstenite::accept_async(stream)
-          .await
-          .map_err(|err| {
-            error!("Websocket server accept error: {:?}", err);
-            ButtplugConnectorError::TransportSpecificError(
-              ButtplugConnectorTransportSpecificError::TungsteniteError(err),
+        if let Some(tls_acceptor) = &tls_acceptor {
+          let tls_stream = match tls_acceptor.accept(stream).await {
+            Ok(tls_stream) => tls_stream,
+            Err(err) => {
+              warn!("Websocket server TLS handshake failed: {:?}", err);
+              continue;
+            }
+          };
+          if let Some(ws_stream) = accept_websocket(tls_stream, auth_token.as_deref()).await? {
+            async_manager::spawn(async move {
+              run_connection_loop(
+                ws_stream,
+                outgoing_receiver,
+                response_sender_clone,
+                disconnect_notifier_clone,
+              )
+              .await;
+            });
+            return Ok(());
+          }
+        } else if let Some(ws_stream) = accept_websocket(stream, auth_token.as_deref()).await? {
+          async_manager::spawn(async move {
+            run_connection_loop(
+              ws_stream,
+              outgoing_receiver,
+              response_sender_clone,
+              disconnect_notifier_clone,
             )
-          })?;
-        async_manager::spawn(async move {
-          run_connection_loop(
-            ws_stream,
-            outgoing_receiver,
-            response_sender_clone,
-            disconnect_notifier_clone,
-          )
-          .await;
-        });
-        Ok(())
-      } else {
-        Err(ButtplugConnectorError::ConnectorGenericError(
-          "Could not run accept for port".to_owned(),
-        ))
+            .await;
+          });
+          return Ok(());
+        }
       }
     };
 
@@ -267,3 +519,69 @@ impl ButtplugConnectorTransport for ButtplugWebsocketServerTransport {
     .boxed()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::connector::transport::websocket::websocket_client::get_rustls_config_dangerous;
+  use tokio::{io::AsyncWriteExt, net::TcpStream, sync::mpsc};
+  use tokio_tungstenite::Connector;
+
+  #[tokio::test]
+  async fn test_failed_tls_handshake_keeps_listening() {
+    let port = 12362;
+    let transport = ButtplugWebsocketServerTransportBuilder::default()
+      .port(port)
+      .tls_self_signed(true)
+      .finish();
+    let (_outgoing_sender, outgoing_receiver) = mpsc::channel(256);
+    let (incoming_sender, _incoming_receiver) = mpsc::channel(256);
+    let connect = tokio::spawn(transport.connect(outgoing_receiver, incoming_sender));
+
+    // A plain websocket client can't finish the TLS handshake.
+    let mut stream = loop {
+      if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+        break stream;
+      }
+      sleep(Duration::from_millis(10)).await;
+    };
+    stream
+      .write_all(b"GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n")
+      .await
+      .expect("Test, assuming infallible.");
+    drop(stream);
+
+    // The server is still waiting for a client, and accepts one that speaks TLS.
+    let (_ws_stream, _) = tokio_tungstenite::connect_async_tls_with_config(
+      format!("wss://127.0.0.1:{}", port),
+      None,
+      false,
+      Some(Connector::Rustls(Arc::new(get_rustls_config_dangerous()))),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    connect
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+  }
+
+  #[test]
+  fn test_self_signed_with_cert_paths() {
+    let transport = ButtplugWebsocketServerTransportBuilder::default()
+      .tls_cert_path("cert.pem")
+      .tls_key_path("key.pem")
+      .tls_self_signed(true)
+      .finish();
+    let (_outgoing_sender, outgoing_receiver) = mpsc::channel(1);
+    let (incoming_sender, _incoming_receiver) = mpsc::channel(1);
+    assert!(matches!(
+      transport
+        .connect(outgoing_receiver, incoming_sender)
+        .now_or_never(),
+      Some(Err(ButtplugConnectorError::TransportSpecificError(
+        ButtplugConnectorTransportSpecificError::TlsError(_)
+      )))
+    ));
+  }
+}
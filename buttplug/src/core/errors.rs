@@ -136,6 +136,10 @@ pub enum ButtplugDeviceError {
   DeviceFeatureIndexError(u32, u32),
   /// Device only has {0} sensors, but was given an index of {1}
   DeviceSensorIndexError(u32, u32),
+  /// Feature {0} was given a value of {1}, but only takes values from 0.0 to 1.0
+  DeviceFeatureValueOutOfRange(u32, String),
+  /// Feature {0} only has {1} steps, but was given step {2}
+  DeviceFeatureStepOutOfRange(u32, u32, u32),
   /// Device connection error: {0}
   DeviceConnectionError(String),
   /// Device communication error: {0}
@@ -179,6 +183,125 @@ pub enum ButtplugDeviceError {
   DeviceSensorTypeMismatch(u32, SensorType, SensorType),
   /// Protocol does not have an implementation available for Sensor Type {0}
   ProtocolSensorNotSupported(SensorType),
+  /// No device group named {0}
+  DeviceGroupNotFound(String),
+  /// No funscript loaded on device {0}
+  FunscriptNotLoaded(u32),
+  /// Device {address} ({protocol}) timed out during protocol handshake
+  HandshakeTimeout { address: String, protocol: String },
+  /// Device {address} ({protocol}) failed protocol handshake: {reason}
+  HandshakeFailed {
+    address: String,
+    protocol: String,
+    reason: String,
+  },
+  /// Device {address} ({protocol}) does not have endpoint {endpoint}
+  EndpointMissing {
+    address: String,
+    protocol: String,
+    endpoint: Endpoint,
+  },
+  /// Device {address} ({protocol}) failed writing to endpoint {endpoint}: {reason}
+  WriteFailed {
+    address: String,
+    protocol: String,
+    endpoint: Endpoint,
+    reason: String,
+  },
+  /// Device {address} ({protocol}) failed reading from endpoint {endpoint}: {reason}
+  ReadFailed {
+    address: String,
+    protocol: String,
+    endpoint: Endpoint,
+    reason: String,
+  },
+  /// Device {address} ({protocol}) failed subscribing to endpoint {endpoint}: {reason}
+  SubscribeFailed {
+    address: String,
+    protocol: String,
+    endpoint: Endpoint,
+    reason: String,
+  },
+  /// Device {address} ({protocol}) disconnected
+  DeviceDisconnected { address: String, protocol: String },
+  /// Device {address} ({protocol}) protocol handler panicked, device removed: {reason}
+  ProtocolPanicked {
+    address: String,
+    protocol: String,
+    reason: String,
+  },
+  /// Device {0} did not acknowledge stop in time
+  DeviceStopTimeout(u32),
+  /// Devices {0:?} did not stop, and were disconnected
+  DevicesForceDisconnected(Vec<u32>),
+  /// Device is in safety cooldown for another {0} ms
+  DeviceSafetyCooldown(u64),
+  /// Device {0} is claimed by another client
+  DeviceClaimedByOtherClient(u32),
+  /// Scheduled command for device {0} was dropped because the device was stopped
+  ScheduledCommandCancelled(u32),
+  /// Device {0} battery is low ({1}%)
+  DeviceBatteryLow(u32, u8),
+}
+
+/// What an application can do to recover from a [ButtplugDeviceError].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceErrorRecovery {
+  /// The failure may be temporary, sending the same command again may work.
+  Retry,
+  /// The device is gone or in a bad state. It needs to be reconnected (power cycled, brought back
+  /// in range, etc.) and found again via scanning.
+  Reconnect,
+  /// The device configuration doesn't match the hardware, or the system isn't set up to allow
+  /// access to the device. Retrying won't help until the configuration is fixed.
+  CheckConfiguration,
+  /// The command doesn't fit the device, and needs to be changed before being sent again.
+  FixCommand,
+  /// Nothing specific is known about recovering from the error.
+  Unknown,
+}
+
+impl ButtplugDeviceError {
+  /// Suggests how an application could recover from the error.
+  pub fn recovery_hint(&self) -> DeviceErrorRecovery {
+    match self {
+      Self::HandshakeTimeout { .. }
+      | Self::ReadFailed { .. }
+      | Self::WriteFailed { .. }
+      | Self::SubscribeFailed { .. }
+      | Self::DeviceCommunicationError(_)
+      | Self::DeviceSafetyCooldown(_)
+      | Self::DeviceClaimedByOtherClient(_) => DeviceErrorRecovery::Retry,
+      Self::HandshakeFailed { .. }
+      | Self::DeviceDisconnected { .. }
+      | Self::ProtocolPanicked { .. }
+      | Self::DeviceNotConnected(_)
+      | Self::DeviceConnectionError(_)
+      | Self::DeviceNotAvailable(_)
+      | Self::DeviceStopTimeout(_)
+      | Self::DevicesForceDisconnected(_) => DeviceErrorRecovery::Reconnect,
+      Self::EndpointMissing { .. }
+      | Self::InvalidEndpoint(_)
+      | Self::DevicePermissionError(_)
+      | Self::ProtocolAttributesNotFound(_)
+      | Self::ProtocolNotImplemented(_)
+      | Self::DeviceConfigurationError(_) => DeviceErrorRecovery::CheckConfiguration,
+      Self::MessageNotSupported(_)
+      | Self::DeviceFeatureCountMismatch(..)
+      | Self::DeviceFeatureIndexError(..)
+      | Self::DeviceSensorIndexError(..)
+      | Self::DeviceFeatureValueOutOfRange(..)
+      | Self::DeviceFeatureStepOutOfRange(..)
+      | Self::UnhandledCommand(_)
+      | Self::ProtocolRequirementError(_)
+      | Self::DeviceActuatorTypeMismatch(..)
+      | Self::DeviceSensorTypeMismatch(..)
+      | Self::ProtocolSensorNotSupported(_)
+      | Self::DeviceGroupNotFound(_)
+      | Self::FunscriptNotLoaded(_) => DeviceErrorRecovery::FixCommand,
+      _ => DeviceErrorRecovery::Unknown,
+    }
+  }
 }
 
 /// Unknown errors occur in exceptional circumstances where no other error type
@@ -7,7 +7,7 @@
 
 use crate::core::{
   errors::ButtplugDeviceError,
-  message::{ButtplugDeviceMessageType, Endpoint},
+  message::{ButtplugDeviceMessageType, ButtplugMessageSpecVersion, Endpoint},
 };
 use getset::{Getters, MutGetters, Setters};
 use serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer};
@@ -25,20 +25,68 @@ pub enum ActuatorType {
   // For instances where we specify a position to move to ASAP. Usually servos, probably for the
   // OSR-2/SR-6.
   Position,
+  // Heating elements, where the scalar sets the temperature.
+  Heater,
+  // Frequency of a vibration, for hardware that is driven by a waveform. Paired with a Vibrate
+  // actuator that sets the amplitude. The step range is the frequency range in Hz.
+  Frequency,
+  // Electrical stimulation output power. Kept apart from Vibrate so clients can't drive e-stim
+  // hardware without knowing that's what it is.
+  Estim,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+impl ActuatorType {
+  /// First message spec version that has this actuator type.
+  pub fn message_spec_version(&self) -> ButtplugMessageSpecVersion {
+    match self {
+      ActuatorType::Heater | ActuatorType::Frequency | ActuatorType::Estim => {
+        ButtplugMessageSpecVersion::Version4
+      }
+      _ => ButtplugMessageSpecVersion::Version3,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Display)]
 pub enum SensorType {
   Unknown,
   Battery,
   RSSI,
   Button,
   Pressure,
+  // Current position of a linear actuator, like a stroker reporting where it is in its stroke.
+  Position,
   // Temperature,
   // Accelerometer,
   // Gyro,
 }
 
+impl SensorType {
+  /// First message spec version that has this sensor type.
+  pub fn message_spec_version(&self) -> ButtplugMessageSpecVersion {
+    match self {
+      SensorType::Position => ButtplugMessageSpecVersion::Version4,
+      _ => ButtplugMessageSpecVersion::Version3,
+    }
+  }
+}
+
+/// Keep the attributes before the first one spec v3 doesn't have a type for. Clients address
+/// features by their position in the list, so the ones after it can't be kept either without
+/// changing their index.
+fn spec_v3_prefix<T: Clone>(
+  attrs: &Option<Vec<T>>,
+  spec_version: impl Fn(&T) -> ButtplugMessageSpecVersion,
+) -> Option<Vec<T>> {
+  let prefix: Vec<T> = attrs
+    .as_ref()?
+    .iter()
+    .take_while(|attr| spec_version(attr) <= ButtplugMessageSpecVersion::Version3)
+    .cloned()
+    .collect();
+  (!prefix.is_empty()).then_some(prefix)
+}
+
 // This will look almost exactly like ServerDeviceMessageAttributes. However, it will only contain
 // information we want the client to know, i.e. step counts versus specific step ranges. This is
 // what will be sent to the client as part of DeviceAdded/DeviceList messages. It should not be used
@@ -159,6 +207,24 @@ impl ClientDeviceMessageAttributes {
     }
   }
 
+  /// The same attributes, without any actuators or sensors whose types were added after spec v3.
+  pub(super) fn to_spec_v3(&self) -> Self {
+    let actuator_version =
+      |attr: &ClientGenericDeviceMessageAttributes| attr.actuator_type().message_spec_version();
+    let sensor_version =
+      |attr: &SensorDeviceMessageAttributes| attr.sensor_type().message_spec_version();
+    let mut attrs = Self {
+      scalar_cmd: spec_v3_prefix(&self.scalar_cmd, actuator_version),
+      rotate_cmd: spec_v3_prefix(&self.rotate_cmd, actuator_version),
+      linear_cmd: spec_v3_prefix(&self.linear_cmd, actuator_version),
+      sensor_read_cmd: spec_v3_prefix(&self.sensor_read_cmd, sensor_version),
+      sensor_subscribe_cmd: spec_v3_prefix(&self.sensor_subscribe_cmd, sensor_version),
+      ..self.clone()
+    };
+    attrs.finalize();
+    attrs
+  }
+
   pub fn finalize(&mut self) {
     if let Some(scalar_attrs) = &mut self.scalar_cmd {
       for (i, attr) in scalar_attrs.into_iter().enumerate() {
@@ -42,6 +42,16 @@ pub struct DeviceAdded {
   )]
   #[getset(get = "pub")]
   device_message_timing_gap: Option<u32>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DeviceConnectionInfo",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub")]
+  device_connection_info: Option<DeviceConnectionInfo>,
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceMessages"))]
   #[getset(get = "pub")]
   device_messages: ClientDeviceMessageAttributes,
@@ -53,6 +63,7 @@ impl DeviceAdded {
     device_name: &str,
     device_display_name: &Option<String>,
     device_message_timing_gap: &Option<u32>,
+    device_connection_info: &Option<DeviceConnectionInfo>,
     device_messages: &ClientDeviceMessageAttributes,
   ) -> Self {
     let mut obj = Self {
@@ -61,11 +72,27 @@ impl DeviceAdded {
       device_name: device_name.to_string(),
       device_display_name: device_display_name.clone(),
       device_message_timing_gap: *device_message_timing_gap,
+      device_connection_info: device_connection_info.clone(),
       device_messages: device_messages.clone(),
     };
     obj.finalize();
     obj
   }
+
+  /// The same notification with everything spec v4 added to it stripped, see
+  /// [ClientDeviceMessageAttributes::to_spec_v3].
+  pub(crate) fn to_spec_v3(&self) -> Self {
+    let mut msg = Self::new(
+      self.device_index,
+      &self.device_name,
+      &self.device_display_name,
+      &self.device_message_timing_gap,
+      &None,
+      &self.device_messages.to_spec_v3(),
+    );
+    msg.set_id(self.id);
+    msg
+  }
 }
 
 impl ButtplugMessageValidator for DeviceAdded {
@@ -0,0 +1,201 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Messages for managing named device groups and broadcasting commands to them.
+
+use super::*;
+use crate::core::errors::ButtplugError;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+fn check_group_name(group_name: &str) -> Result<(), ButtplugMessageError> {
+  if group_name.is_empty() {
+    Err(ButtplugMessageError::InvalidMessageContents(
+      "Device group name cannot be empty".to_owned(),
+    ))
+  } else {
+    Ok(())
+  }
+}
+
+/// Adds a device to a named group, creating the group if it doesn't exist yet.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct AddDeviceToGroup {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "GroupName"))]
+  #[getset(get = "pub")]
+  group_name: String,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+}
+
+impl AddDeviceToGroup {
+  pub fn new(group_name: &str, device_index: u32) -> Self {
+    Self {
+      id: 1,
+      group_name: group_name.to_owned(),
+      device_index,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for AddDeviceToGroup {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    check_group_name(&self.group_name)
+  }
+}
+
+/// Removes a device from a named group. Groups are removed once their last device is.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct RemoveDeviceFromGroup {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "GroupName"))]
+  #[getset(get = "pub")]
+  group_name: String,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+}
+
+impl RemoveDeviceFromGroup {
+  pub fn new(group_name: &str, device_index: u32) -> Self {
+    Self {
+      id: 1,
+      group_name: group_name.to_owned(),
+      device_index,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for RemoveDeviceFromGroup {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    check_group_name(&self.group_name)
+  }
+}
+
+/// Sends scalar subcommands to every device in a group. Each device only gets the subcommands
+/// that match one of its scalar actuators by index and actuator type. The server replies with a
+/// [GroupCommandResult].
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct GroupScalarCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "GroupName"))]
+  #[getset(get = "pub")]
+  group_name: String,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Scalars"))]
+  #[getset(get = "pub")]
+  scalars: Vec<ScalarSubcommand>,
+}
+
+impl GroupScalarCmd {
+  pub fn new(group_name: &str, scalars: Vec<ScalarSubcommand>) -> Self {
+    Self {
+      id: 1,
+      group_name: group_name.to_owned(),
+      scalars,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for GroupScalarCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    check_group_name(&self.group_name)?;
+    for level in &self.scalars {
+      self.is_in_command_range(
+        level.scalar(),
+        format!(
+          "Level {} for GroupScalarCmd index {} is invalid. Level should be a value between 0.0 and 1.0",
+          level.scalar(),
+          level.index()
+        ),
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// A group member that failed to carry out a group command.
+#[derive(Debug, PartialEq, Eq, Clone, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct GroupCommandFailure {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  #[getset(get_copy = "pub")]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "ErrorCode"))]
+  #[getset(get_copy = "pub")]
+  error_code: ErrorCode,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "ErrorMessage"))]
+  #[getset(get = "pub")]
+  error_message: String,
+}
+
+impl GroupCommandFailure {
+  /// The error the device returned.
+  pub fn error(&self) -> ButtplugError {
+    Error::new(self.error_code, &self.error_message, None).original_error()
+  }
+}
+
+/// Per-device outcome of a command sent to a device group.
+#[derive(
+  Debug, Default, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get = "pub")]
+pub struct GroupCommandResult {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  #[getset(skip)]
+  id: u32,
+  /// Devices that accepted the command.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Succeeded"))]
+  succeeded: Vec<u32>,
+  /// Devices that have no actuators matching any of the command's subcommands.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Skipped"))]
+  skipped: Vec<u32>,
+  /// Devices that were sent the command but returned an error, or are not connected.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Failed"))]
+  failed: Vec<GroupCommandFailure>,
+}
+
+impl GroupCommandResult {
+  pub(crate) fn add_success(&mut self, device_index: u32) {
+    self.succeeded.push(device_index);
+  }
+
+  pub(crate) fn add_skipped(&mut self, device_index: u32) {
+    self.skipped.push(device_index);
+  }
+
+  pub(crate) fn add_failure(&mut self, device_index: u32, err: ButtplugError) {
+    let error = Error::from(err);
+    self.failed.push(GroupCommandFailure {
+      device_index,
+      error_code: error.error_code(),
+      error_message: error.error_message().clone(),
+    });
+  }
+
+  /// Returns true if no device in the group failed. Skipped devices don't count as failures.
+  pub fn is_success(&self) -> bool {
+    self.failed.is_empty()
+  }
+}
+
+impl ButtplugMessageValidator for GroupCommandResult {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
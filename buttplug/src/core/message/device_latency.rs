@@ -0,0 +1,123 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Messages for querying the command latency stats the server keeps for a device.
+
+use super::*;
+use getset::CopyGetters;
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Asks the server for latency stats of output commands sent to a device. The server replies with
+/// [DeviceLatency].
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct RequestDeviceLatency {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+}
+
+impl RequestDeviceLatency {
+  pub fn new(device_index: u32) -> Self {
+    Self {
+      id: 1,
+      device_index,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for RequestDeviceLatency {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
+
+/// Rolling stats for one stage of handling a command, in microseconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get_copy = "pub")]
+pub struct LatencyStageStats {
+  /// Number of samples the stats were computed from. All other values are 0 if this is.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "SampleCount"))]
+  sample_count: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "MinUs"))]
+  min_us: u64,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "MaxUs"))]
+  max_us: u64,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "MeanUs"))]
+  mean_us: u64,
+  /// 95th percentile
+  #[cfg_attr(feature = "serialize-json", serde(rename = "P95Us"))]
+  p95_us: u64,
+}
+
+impl LatencyStageStats {
+  pub fn new(sample_count: u32, min_us: u64, max_us: u64, mean_us: u64, p95_us: u64) -> Self {
+    Self {
+      sample_count,
+      min_us,
+      max_us,
+      mean_us,
+      p95_us,
+    }
+  }
+}
+
+/// Reply to [RequestDeviceLatency]. Stages are:
+///
+/// - Protocol: Turning the message into hardware commands.
+/// - Queue: Waiting for earlier commands or the device's rate limit before writing.
+/// - Hardware: Writing to the hardware.
+/// - Total: Receipt of the message by the device to the server replying.
+#[derive(
+  Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, CopyGetters,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get_copy = "pub")]
+pub struct DeviceLatency {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  #[getset(skip)]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  #[getset(skip)]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Protocol"))]
+  protocol: LatencyStageStats,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Queue"))]
+  queue: LatencyStageStats,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Hardware"))]
+  hardware: LatencyStageStats,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Total"))]
+  total: LatencyStageStats,
+}
+
+impl DeviceLatency {
+  pub fn new(
+    device_index: u32,
+    protocol: LatencyStageStats,
+    queue: LatencyStageStats,
+    hardware: LatencyStageStats,
+    total: LatencyStageStats,
+  ) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      protocol,
+      queue,
+      hardware,
+      total,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for DeviceLatency {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
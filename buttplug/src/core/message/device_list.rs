@@ -26,6 +26,18 @@ impl DeviceList {
   pub fn new(devices: Vec<DeviceMessageInfo>) -> Self {
     Self { id: 1, devices }
   }
+
+  /// The same list with everything spec v4 added to it stripped.
+  pub(crate) fn to_spec_v3(&self) -> Self {
+    Self {
+      id: self.id,
+      devices: self
+        .devices
+        .iter()
+        .map(DeviceMessageInfo::to_spec_v3)
+        .collect(),
+    }
+  }
 }
 
 impl ButtplugMessageValidator for DeviceList {
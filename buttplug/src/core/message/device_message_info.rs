@@ -10,6 +10,39 @@ use getset::{CopyGetters, Getters, MutGetters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
+/// How the server is connected to a device (transport, address, signal strength). Informational
+/// only, clients can't use this to address devices.
+#[derive(Clone, Debug, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct DeviceConnectionInfo {
+  /// Communication bus the device is connected over, using the same names as the device
+  /// configuration file (btle, serial, hid, etc...)
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Transport"))]
+  #[getset(get = "pub")]
+  transport: String,
+  /// Hardware address of the device. Format depends on the transport.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Address"))]
+  #[getset(get = "pub")]
+  address: String,
+  /// Signal strength when the device was connected, for transports that have one.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "RSSI", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get_copy = "pub")]
+  rssi: Option<i32>,
+}
+
+impl DeviceConnectionInfo {
+  pub fn new(transport: &str, address: &str, rssi: Option<i32>) -> Self {
+    Self {
+      transport: transport.to_owned(),
+      address: address.to_owned(),
+      rssi,
+    }
+  }
+}
+
 /// Substructure of device messages, used for attribute information (name, messages supported, etc...)
 #[derive(Clone, Debug, PartialEq, Eq, MutGetters, Getters, CopyGetters)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
@@ -35,6 +68,16 @@ pub struct DeviceMessageInfo {
   )]
   #[getset(get = "pub")]
   device_message_timing_gap: Option<u32>,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "DeviceConnectionInfo",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get = "pub")]
+  device_connection_info: Option<DeviceConnectionInfo>,
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceMessages"))]
   #[getset(get = "pub", get_mut = "pub(super)")]
   device_messages: ClientDeviceMessageAttributes,
@@ -46,6 +89,7 @@ impl DeviceMessageInfo {
     device_name: &str,
     device_display_name: &Option<String>,
     device_message_timing_gap: &Option<u32>,
+    device_connection_info: &Option<DeviceConnectionInfo>,
     device_messages: ClientDeviceMessageAttributes,
   ) -> Self {
     Self {
@@ -53,9 +97,19 @@ impl DeviceMessageInfo {
       device_name: device_name.to_owned(),
       device_display_name: device_display_name.clone(),
       device_message_timing_gap: *device_message_timing_gap,
+      device_connection_info: device_connection_info.clone(),
       device_messages,
     }
   }
+
+  /// The same info with everything spec v4 added to it stripped.
+  pub(super) fn to_spec_v3(&self) -> Self {
+    Self {
+      device_connection_info: None,
+      device_messages: self.device_messages.to_spec_v3(),
+      ..self.clone()
+    }
+  }
 }
 
 impl From<DeviceAdded> for DeviceMessageInfo {
@@ -65,6 +119,7 @@ impl From<DeviceAdded> for DeviceMessageInfo {
       device_name: device_added.device_name().clone(),
       device_display_name: device_added.device_display_name().clone(),
       device_message_timing_gap: *device_added.device_message_timing_gap(),
+      device_connection_info: device_added.device_connection_info().clone(),
       device_messages: device_added.device_messages().clone(),
     }
   }
@@ -12,6 +12,23 @@ use getset::CopyGetters;
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
+/// Why a device was removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum DeviceRemovedReason {
+  /// Disconnected on request, through the server or a client.
+  UserDisconnect,
+  /// The connection to the device was lost, e.g. it went out of bluetooth range or was unplugged.
+  LinkLost,
+  /// The device reported it was turning off.
+  PoweredOff,
+  /// The communication manager the device was connected through stopped, taking its devices with
+  /// it.
+  CommManagerShutdown,
+  /// The device stopped responding, or its protocol failed, so the server dropped it.
+  DeviceError,
+}
+
 #[derive(Debug, Default, ButtplugMessage, Clone, PartialEq, Eq, CopyGetters)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct DeviceRemoved {
@@ -20,6 +37,24 @@ pub struct DeviceRemoved {
   #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
   #[getset(get_copy = "pub")]
   device_index: u32,
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Reason", default, skip_serializing_if = "Option::is_none")
+  )]
+  #[getset(get_copy = "pub")]
+  reason: Option<DeviceRemovedReason>,
+  /// True if the server will try to connect the device again by itself, in which case it comes
+  /// back with a new DeviceAdded.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "WillReconnect",
+      default,
+      skip_serializing_if = "std::ops::Not::not"
+    )
+  )]
+  #[getset(get_copy = "pub")]
+  will_reconnect: bool,
 }
 
 impl DeviceRemoved {
@@ -27,6 +62,31 @@ impl DeviceRemoved {
     Self {
       id: 0,
       device_index,
+      reason: None,
+      will_reconnect: false,
+    }
+  }
+
+  pub fn new_with_reason(
+    device_index: u32,
+    reason: DeviceRemovedReason,
+    will_reconnect: bool,
+  ) -> Self {
+    Self {
+      id: 0,
+      device_index,
+      reason: Some(reason),
+      will_reconnect,
+    }
+  }
+
+  /// The same removal with the spec v4 fields dropped, for older spec versions.
+  pub(crate) fn without_reason(&self) -> Self {
+    Self {
+      id: self.id,
+      device_index: self.device_index,
+      reason: None,
+      will_reconnect: false,
     }
   }
 }
@@ -27,11 +27,17 @@ use core::hash::Hash;
 /// context. These names are used in [Device Configuration](crate::server::device::configuration)
 /// and the [Device Configuration File](crate::util::device_configuration), and are expected to
 /// de/serialize to lowercase versions of their names.
+///
+/// Bluetooth LE device configurations can also use custom endpoint names, which are mapped onto
+/// unused generic endpoints and looked up by protocols through
+/// [Hardware::named_endpoint](crate::server::device::hardware::Hardware::named_endpoint).
 #[derive(EnumString, Clone, Debug, PartialEq, Eq, Hash, Display, Copy)]
 #[strum(serialize_all = "lowercase")]
 pub enum Endpoint {
   /// Expect to take commands, when multiple receive endpoints may be available
   Command,
+  /// HID feature reports. Writes send a feature report, the first byte of which is the report ID.
+  Feature,
   /// Firmware updates (Buttplug does not update firmware, but some firmware endpoints are used for
   /// mode setting)
   Firmware,
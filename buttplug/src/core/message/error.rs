@@ -168,7 +168,10 @@ impl From<Error> for ErrorV0 {
 #[cfg(feature = "serialize-json")]
 #[cfg(test)]
 mod test {
-  use crate::core::message::{ButtplugCurrentSpecServerMessage, Error, ErrorCode};
+  use crate::core::{
+    errors::{ButtplugDeviceError, ButtplugError, DeviceErrorRecovery},
+    message::{ButtplugCurrentSpecServerMessage, Endpoint, Error, ErrorCode},
+  };
 
   const ERROR_STR: &str = "{\"Error\":{\"Id\":0,\"ErrorCode\":1,\"ErrorMessage\":\"Test Error\"}}";
 
@@ -196,4 +199,29 @@ mod test {
       union
     );
   }
+
+  #[test]
+  fn test_structured_device_error_roundtrip() {
+    let device_error = ButtplugDeviceError::WriteFailed {
+      address: "00:11:22:33:44:55".to_owned(),
+      protocol: "lovense".to_owned(),
+      endpoint: Endpoint::Tx,
+      reason: "Not connected".to_owned(),
+    };
+    assert_eq!(device_error.recovery_hint(), DeviceErrorRecovery::Retry);
+    let js = serde_json::to_string(&ButtplugCurrentSpecServerMessage::Error(Error::from(
+      ButtplugError::from(device_error.clone()),
+    )))
+    .expect("Infallible serialization.");
+    let union: ButtplugCurrentSpecServerMessage =
+      serde_json::from_str(&js).expect("Infallible deserialization");
+    let ButtplugCurrentSpecServerMessage::Error(error) = union else {
+      panic!("Should have deserialized an Error message");
+    };
+    assert_eq!(error.error_code(), ErrorCode::ErrorDevice);
+    assert_eq!(
+      error.original_error(),
+      ButtplugError::ButtplugDeviceError(device_error)
+    );
+  }
 }
@@ -6,7 +6,7 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
@@ -33,7 +33,16 @@ impl VectorSubcommand {
   }
 }
 
-#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
+#[derive(
+  Debug,
+  ButtplugDeviceMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Clone,
+  Getters,
+  CopyGetters,
+  Setters,
+)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct LinearCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
@@ -43,6 +52,18 @@ pub struct LinearCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Vectors"))]
   #[getset(get = "pub")]
   vectors: Vec<VectorSubcommand>,
+  /// Server clock time, in milliseconds, at which the command should take effect on the device.
+  /// If unset, the command is carried out as soon as it's received.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ScheduledTime",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub", set = "pub")]
+  scheduled_time: Option<u64>,
 }
 
 impl LinearCmd {
@@ -51,6 +72,7 @@ impl LinearCmd {
       id: 1,
       device_index,
       vectors,
+      scheduled_time: None,
     }
   }
 }
@@ -17,6 +17,8 @@ mod battery_level_cmd;
 mod battery_level_reading;
 mod client_device_message_attributes;
 mod device_added;
+mod device_group_cmd;
+mod device_latency;
 mod device_list;
 mod device_message_info;
 mod device_removed;
@@ -30,6 +32,7 @@ mod log_level;
 mod lovense_cmd;
 mod ok;
 mod ping;
+mod play_pattern_cmd;
 mod raw_read_cmd;
 mod raw_reading;
 mod raw_subscribe_cmd;
@@ -43,6 +46,7 @@ mod rssi_level_cmd;
 mod rssi_level_reading;
 mod scalar_cmd;
 mod scanning_finished;
+mod scanning_status;
 mod sensor_read_cmd;
 mod sensor_reading;
 mod sensor_subscribe_cmd;
@@ -50,6 +54,7 @@ mod sensor_unsubscribe_cmd;
 pub mod serializer;
 mod server_info;
 mod single_motor_vibrate_cmd;
+mod spec_conversion;
 mod start_scanning;
 mod stop_all_devices;
 mod stop_device_cmd;
@@ -74,14 +79,23 @@ pub use client_device_message_attributes::{
   SensorType,
 };
 pub use device_added::{DeviceAdded, DeviceAddedV0, DeviceAddedV1, DeviceAddedV2};
+pub use device_group_cmd::{
+  AddDeviceToGroup,
+  GroupCommandFailure,
+  GroupCommandResult,
+  GroupScalarCmd,
+  RemoveDeviceFromGroup,
+};
+pub use device_latency::{DeviceLatency, LatencyStageStats, RequestDeviceLatency};
 pub use device_list::{DeviceList, DeviceListV0, DeviceListV1, DeviceListV2};
 pub use device_message_info::{
+  DeviceConnectionInfo,
   DeviceMessageInfo,
   DeviceMessageInfoV0,
   DeviceMessageInfoV1,
   DeviceMessageInfoV2,
 };
-pub use device_removed::DeviceRemoved;
+pub use device_removed::{DeviceRemoved, DeviceRemovedReason};
 pub use endpoint::Endpoint;
 pub use error::{Error, ErrorCode, ErrorV0};
 pub use fleshlight_launch_fw12_cmd::FleshlightLaunchFW12Cmd;
@@ -91,6 +105,8 @@ pub use log_level::LogLevel;
 pub use lovense_cmd::LovenseCmd;
 pub use ok::Ok;
 pub use ping::Ping;
+pub(crate) use play_pattern_cmd::check_pattern_keyframes;
+pub use play_pattern_cmd::{PatternKeyframe, PlayPatternCmd, StopPatternCmd};
 pub use raw_read_cmd::RawReadCmd;
 pub use raw_reading::RawReading;
 pub use raw_subscribe_cmd::RawSubscribeCmd;
@@ -104,13 +120,20 @@ pub use rssi_level_cmd::RSSILevelCmd;
 pub use rssi_level_reading::RSSILevelReading;
 pub use scalar_cmd::{ScalarCmd, ScalarSubcommand};
 pub use scanning_finished::ScanningFinished;
+pub use scanning_status::{
+  ManagerScanningState,
+  ManagerScanningStatus,
+  RequestScanningStatus,
+  ScanningStatus,
+};
 pub use sensor_read_cmd::SensorReadCmd;
 pub use sensor_reading::SensorReading;
 pub use sensor_subscribe_cmd::SensorSubscribeCmd;
 pub use sensor_unsubscribe_cmd::SensorUnsubscribeCmd;
 pub use server_info::{ServerInfo, ServerInfoV0};
 pub use single_motor_vibrate_cmd::SingleMotorVibrateCmd;
-pub use start_scanning::StartScanning;
+pub use spec_conversion::{convert_server_messages, ButtplugSpecServerMessage};
+pub use start_scanning::{ScanFilter, StartScanning};
 pub use stop_all_devices::StopAllDevices;
 pub use stop_device_cmd::StopDeviceCmd;
 pub use stop_scanning::StopScanning;
@@ -135,6 +158,7 @@ pub enum ButtplugMessageSpecVersion {
   Version1 = 1,
   Version2 = 2,
   Version3 = 3,
+  Version4 = 4,
 }
 
 /// Message Id for events sent from the server, which are not in response to a
@@ -143,7 +167,7 @@ pub const BUTTPLUG_SERVER_EVENT_ID: u32 = 0;
 
 /// The current latest version of the spec implemented by the library.
 pub const BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION: ButtplugMessageSpecVersion =
-  ButtplugMessageSpecVersion::Version3;
+  ButtplugMessageSpecVersion::Version4;
 
 pub trait ButtplugMessageFinalizer {
   fn finalize(&mut self) {
@@ -268,6 +292,7 @@ impl Ord for ButtplugDeviceMessageType {
   ButtplugMessageValidator,
   ButtplugClientMessageType,
   FromSpecificButtplugMessage,
+  IntoStaticStr,
 )]
 pub enum ButtplugClientMessage {
   Ping(Ping),
@@ -278,6 +303,8 @@ pub enum ButtplugClientMessage {
   StartScanning(StartScanning),
   StopScanning(StopScanning),
   RequestDeviceList(RequestDeviceList),
+  RequestScanningStatus(RequestScanningStatus),
+  RequestDeviceLatency(RequestDeviceLatency),
   // Generic commands
   StopAllDevices(StopAllDevices),
   VibrateCmd(VibrateCmd),
@@ -295,6 +322,13 @@ pub enum ButtplugClientMessage {
   SensorReadCmd(SensorReadCmd),
   SensorSubscribeCmd(SensorSubscribeCmd),
   SensorUnsubscribeCmd(SensorUnsubscribeCmd),
+  // Pattern playback commands
+  PlayPatternCmd(PlayPatternCmd),
+  StopPatternCmd(StopPatternCmd),
+  // Device group commands
+  AddDeviceToGroup(AddDeviceToGroup),
+  RemoveDeviceFromGroup(RemoveDeviceFromGroup),
+  GroupScalarCmd(GroupScalarCmd),
   // Deprecated generic commands
   SingleMotorVibrateCmd(SingleMotorVibrateCmd),
   // Deprecated device specific commands
@@ -305,6 +339,47 @@ pub enum ButtplugClientMessage {
   // To Add:
 }
 
+impl ButtplugClientMessage {
+  /// Index of the device a device command is addressed to, or None for any other message.
+  pub fn device_index(&self) -> Option<u32> {
+    match self {
+      ButtplugClientMessage::VibrateCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::LinearCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::RotateCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::RawWriteCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::RawReadCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::StopDeviceCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::RawSubscribeCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::RawUnsubscribeCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::ScalarCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::BatteryLevelCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::RSSILevelCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::SensorReadCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::SensorSubscribeCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::SensorUnsubscribeCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::PlayPatternCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::StopPatternCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::AddDeviceToGroup(m) => Some(m.device_index()),
+      ButtplugClientMessage::RemoveDeviceFromGroup(m) => Some(m.device_index()),
+      ButtplugClientMessage::RequestDeviceLatency(m) => Some(m.device_index()),
+      ButtplugClientMessage::SingleMotorVibrateCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::FleshlightLaunchFW12Cmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::LovenseCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::KiirooCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::VorzeA10CycloneCmd(m) => Some(m.device_index()),
+      ButtplugClientMessage::Ping(_)
+      | ButtplugClientMessage::RequestLog(_)
+      | ButtplugClientMessage::RequestServerInfo(_)
+      | ButtplugClientMessage::StartScanning(_)
+      | ButtplugClientMessage::StopScanning(_)
+      | ButtplugClientMessage::RequestDeviceList(_)
+      | ButtplugClientMessage::RequestScanningStatus(_)
+      | ButtplugClientMessage::StopAllDevices(_)
+      | ButtplugClientMessage::GroupScalarCmd(_) => None,
+    }
+  }
+}
+
 /// Represents all possible messages a
 /// [ButtplugServer][crate::server::ButtplugServer] can send to a
 /// [ButtplugClient][crate::client::ButtplugClient].
@@ -331,10 +406,14 @@ pub enum ButtplugServerMessage {
   DeviceAdded(DeviceAdded),
   DeviceRemoved(DeviceRemoved),
   ScanningFinished(ScanningFinished),
+  ScanningStatus(ScanningStatus),
+  DeviceLatency(DeviceLatency),
   // Generic commands
   RawReading(RawReading),
   // Sensor Reading Messages
   SensorReading(SensorReading),
+  // Device group messages
+  GroupCommandResult(GroupCommandResult),
   // Deprecated Server Messages
   BatteryLevelReading(BatteryLevelReading),
   RSSILevelReading(RSSILevelReading),
@@ -372,9 +451,101 @@ impl From<ButtplugServerDeviceMessage> for ButtplugServerMessage {
 }
 
 /// Type alias for the latest version of client-to-server messages.
-pub type ButtplugCurrentSpecClientMessage = ButtplugSpecV3ClientMessage;
+pub type ButtplugCurrentSpecClientMessage = ButtplugSpecV4ClientMessage;
 /// Type alias for the latest version of server-to-client messages.
-pub type ButtplugCurrentSpecServerMessage = ButtplugSpecV3ServerMessage;
+pub type ButtplugCurrentSpecServerMessage = ButtplugSpecV4ServerMessage;
+
+/// Represents all client-to-server messages in v4 of the Buttplug Spec
+#[derive(
+  Debug,
+  Clone,
+  PartialEq,
+  ButtplugMessage,
+  ButtplugMessageValidator,
+  ButtplugClientMessageType,
+  ButtplugMessageFinalizer,
+  FromSpecificButtplugMessage,
+  TryFromButtplugClientMessage,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum ButtplugSpecV4ClientMessage {
+  // Handshake messages
+  RequestServerInfo(RequestServerInfo),
+  Ping(Ping),
+  RequestLog(RequestLog),
+  // Device enumeration messages
+  StartScanning(StartScanning),
+  StopScanning(StopScanning),
+  RequestDeviceList(RequestDeviceList),
+  RequestScanningStatus(RequestScanningStatus),
+  RequestDeviceLatency(RequestDeviceLatency),
+  // Generic commands
+  StopAllDevices(StopAllDevices),
+  VibrateCmd(VibrateCmd),
+  LinearCmd(LinearCmd),
+  RotateCmd(RotateCmd),
+  RawWriteCmd(RawWriteCmd),
+  RawReadCmd(RawReadCmd),
+  StopDeviceCmd(StopDeviceCmd),
+  RawSubscribeCmd(RawSubscribeCmd),
+  RawUnsubscribeCmd(RawUnsubscribeCmd),
+  ScalarCmd(ScalarCmd),
+  // Sensor commands
+  SensorReadCmd(SensorReadCmd),
+  SensorSubscribeCmd(SensorSubscribeCmd),
+  SensorUnsubscribeCmd(SensorUnsubscribeCmd),
+  // Pattern playback commands
+  PlayPatternCmd(PlayPatternCmd),
+  StopPatternCmd(StopPatternCmd),
+  // Device group commands
+  AddDeviceToGroup(AddDeviceToGroup),
+  RemoveDeviceFromGroup(RemoveDeviceFromGroup),
+  GroupScalarCmd(GroupScalarCmd),
+}
+
+/// Represents all server-to-client messages in v4 of the Buttplug Spec
+#[derive(
+  Debug,
+  Clone,
+  PartialEq,
+  ButtplugMessage,
+  ButtplugMessageValidator,
+  ButtplugServerMessageType,
+  FromSpecificButtplugMessage,
+  TryFromButtplugServerMessage,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum ButtplugSpecV4ServerMessage {
+  // Status messages
+  Ok(Ok),
+  Error(Error),
+  Log(Log),
+  // Handshake messages
+  ServerInfo(ServerInfo),
+  // Device enumeration messages
+  DeviceList(DeviceList),
+  DeviceAdded(DeviceAdded),
+  DeviceRemoved(DeviceRemoved),
+  ScanningFinished(ScanningFinished),
+  ScanningStatus(ScanningStatus),
+  DeviceLatency(DeviceLatency),
+  // Generic commands
+  RawReading(RawReading),
+  // Sensor commands
+  SensorReading(SensorReading),
+  // Device group messages
+  GroupCommandResult(GroupCommandResult),
+}
+
+impl ButtplugMessageFinalizer for ButtplugSpecV4ServerMessage {
+  fn finalize(&mut self) {
+    match self {
+      ButtplugSpecV4ServerMessage::DeviceAdded(da) => da.finalize(),
+      ButtplugSpecV4ServerMessage::DeviceList(dl) => dl.finalize(),
+      _ => {}
+    }
+  }
+}
 
 /// Represents all client-to-server messages in v3 of the Buttplug Spec
 #[derive(
@@ -423,7 +594,6 @@ pub enum ButtplugSpecV3ClientMessage {
   ButtplugMessageValidator,
   ButtplugServerMessageType,
   FromSpecificButtplugMessage,
-  TryFromButtplugServerMessage,
 )]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub enum ButtplugSpecV3ServerMessage {
@@ -453,6 +623,44 @@ impl ButtplugMessageFinalizer for ButtplugSpecV3ServerMessage {
   }
 }
 
+// Spec v3 shares its message structs with v4, so anything v4 added to them (fields, actuator and
+// sensor types) is stripped here. v3 clients have strict enums and schemas, and would fail to parse
+// the whole message otherwise.
+impl TryFrom<ButtplugServerMessage> for ButtplugSpecV3ServerMessage {
+  type Error = ButtplugMessageError;
+  fn try_from(msg: ButtplugServerMessage) -> Result<Self, ButtplugMessageError> {
+    match msg {
+      ButtplugServerMessage::Ok(msg) => Ok(ButtplugSpecV3ServerMessage::Ok(msg)),
+      ButtplugServerMessage::Error(msg) => Ok(ButtplugSpecV3ServerMessage::Error(msg)),
+      ButtplugServerMessage::ServerInfo(mut msg) => {
+        msg.set_server_time(None);
+        Ok(ButtplugSpecV3ServerMessage::ServerInfo(msg))
+      }
+      ButtplugServerMessage::DeviceList(msg) => {
+        Ok(ButtplugSpecV3ServerMessage::DeviceList(msg.to_spec_v3()))
+      }
+      ButtplugServerMessage::DeviceAdded(msg) => {
+        Ok(ButtplugSpecV3ServerMessage::DeviceAdded(msg.to_spec_v3()))
+      }
+      ButtplugServerMessage::DeviceRemoved(msg) => Ok(ButtplugSpecV3ServerMessage::DeviceRemoved(
+        msg.without_reason(),
+      )),
+      ButtplugServerMessage::ScanningFinished(msg) => {
+        Ok(ButtplugSpecV3ServerMessage::ScanningFinished(msg))
+      }
+      ButtplugServerMessage::RawReading(msg) => Ok(ButtplugSpecV3ServerMessage::RawReading(msg)),
+      ButtplugServerMessage::SensorReading(msg) => {
+        Ok(ButtplugSpecV3ServerMessage::SensorReading(msg))
+      }
+      _ => Err(ButtplugMessageError::VersionError(
+        "ButtplugServerMessage".to_owned(),
+        format!("{:?}", msg),
+        "ButtplugSpecV3ServerMessage".to_owned(),
+      )),
+    }
+  }
+}
+
 /// Represents all client-to-server messages in v2 of the Buttplug Spec
 #[derive(
   Debug,
@@ -527,19 +735,30 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV2ServerMessage {
     match msg {
       ButtplugServerMessage::Ok(msg) => Ok(ButtplugSpecV2ServerMessage::Ok(msg)),
       ButtplugServerMessage::Error(msg) => Ok(ButtplugSpecV2ServerMessage::Error(msg)),
-      ButtplugServerMessage::ServerInfo(msg) => Ok(ButtplugSpecV2ServerMessage::ServerInfo(msg)),
+      ButtplugServerMessage::ServerInfo(mut msg) => {
+        msg.set_server_time(None);
+        Ok(ButtplugSpecV2ServerMessage::ServerInfo(msg))
+      }
       ButtplugServerMessage::DeviceList(msg) => {
         Ok(ButtplugSpecV2ServerMessage::DeviceList(msg.into()))
       }
       ButtplugServerMessage::DeviceAdded(msg) => {
         Ok(ButtplugSpecV2ServerMessage::DeviceAdded(msg.into()))
       }
-      ButtplugServerMessage::DeviceRemoved(msg) => {
-        Ok(ButtplugSpecV2ServerMessage::DeviceRemoved(msg))
-      }
+      // Removal reasons were added in spec v4, older clients only get the device index.
+      ButtplugServerMessage::DeviceRemoved(msg) => Ok(ButtplugSpecV2ServerMessage::DeviceRemoved(
+        msg.without_reason(),
+      )),
       ButtplugServerMessage::ScanningFinished(msg) => {
         Ok(ButtplugSpecV2ServerMessage::ScanningFinished(msg))
       }
+      ButtplugServerMessage::RawReading(msg) => Ok(ButtplugSpecV2ServerMessage::RawReading(msg)),
+      ButtplugServerMessage::BatteryLevelReading(msg) => {
+        Ok(ButtplugSpecV2ServerMessage::BatteryLevelReading(msg))
+      }
+      ButtplugServerMessage::RSSILevelReading(msg) => {
+        Ok(ButtplugSpecV2ServerMessage::RSSILevelReading(msg))
+      }
       _ => Err(ButtplugMessageError::VersionError(
         "ButtplugServerMessage".to_owned(),
         format!("{:?}", msg),
@@ -629,9 +848,9 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV1ServerMessage {
       ButtplugServerMessage::DeviceAdded(msg) => {
         Ok(ButtplugSpecV1ServerMessage::DeviceAdded(msg.into()))
       }
-      ButtplugServerMessage::DeviceRemoved(msg) => {
-        Ok(ButtplugSpecV1ServerMessage::DeviceRemoved(msg))
-      }
+      ButtplugServerMessage::DeviceRemoved(msg) => Ok(ButtplugSpecV1ServerMessage::DeviceRemoved(
+        msg.without_reason(),
+      )),
       ButtplugServerMessage::ScanningFinished(msg) => {
         Ok(ButtplugSpecV1ServerMessage::ScanningFinished(msg))
       }
@@ -722,9 +941,9 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV0ServerMessage {
       ButtplugServerMessage::DeviceAdded(msg) => {
         Ok(ButtplugSpecV0ServerMessage::DeviceAdded(msg.into()))
       }
-      ButtplugServerMessage::DeviceRemoved(msg) => {
-        Ok(ButtplugSpecV0ServerMessage::DeviceRemoved(msg))
-      }
+      ButtplugServerMessage::DeviceRemoved(msg) => Ok(ButtplugSpecV0ServerMessage::DeviceRemoved(
+        msg.without_reason(),
+      )),
       ButtplugServerMessage::ScanningFinished(msg) => {
         Ok(ButtplugSpecV0ServerMessage::ScanningFinished(msg))
       }
@@ -743,7 +962,6 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV0ServerMessage {
   Debug,
   Clone,
   PartialEq,
-  Eq,
   ButtplugMessage,
   ButtplugMessageValidator,
   ButtplugClientMessageType,
@@ -753,9 +971,18 @@ impl TryFrom<ButtplugServerMessage> for ButtplugSpecV0ServerMessage {
 )]
 pub enum ButtplugDeviceManagerMessageUnion {
   RequestDeviceList(RequestDeviceList),
+  RequestScanningStatus(RequestScanningStatus),
+  RequestDeviceLatency(RequestDeviceLatency),
   StopAllDevices(StopAllDevices),
   StartScanning(StartScanning),
   StopScanning(StopScanning),
+  // Patterns play on devices, but the device manager runs playback.
+  PlayPatternCmd(PlayPatternCmd),
+  StopPatternCmd(StopPatternCmd),
+  // Device groups span several devices, so are kept by the device manager.
+  AddDeviceToGroup(AddDeviceToGroup),
+  RemoveDeviceFromGroup(RemoveDeviceFromGroup),
+  GroupScalarCmd(GroupScalarCmd),
 }
 
 /// Represents all possible device command message types.
@@ -792,3 +1019,68 @@ pub enum ButtplugDeviceCommandMessageUnion {
   SensorSubscribeCmd(SensorSubscribeCmd),
   SensorUnsubscribeCmd(SensorUnsubscribeCmd),
 }
+
+impl ButtplugDeviceCommandMessageUnion {
+  /// Server clock time the command is scheduled for, if it's a command that can be scheduled.
+  pub fn scheduled_time(&self) -> Option<u64> {
+    match self {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => msg.scheduled_time(),
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => msg.scheduled_time(),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => msg.scheduled_time(),
+      _ => None,
+    }
+  }
+}
+
+/// Where a [ButtplugClientMessage] is handled in a [ButtplugServer](crate::server::ButtplugServer).
+///
+/// Converting a message moves it into the union type for its destination, so routing messages
+/// never requires cloning them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ButtplugClientMessageDestination {
+  /// Messages the server handles itself (handshake, ping, logging), or can't handle at all.
+  Server(ButtplugClientMessage),
+  DeviceManager(ButtplugDeviceManagerMessageUnion),
+  Device(ButtplugDeviceCommandMessageUnion),
+}
+
+impl From<ButtplugClientMessage> for ButtplugClientMessageDestination {
+  fn from(msg: ButtplugClientMessage) -> Self {
+    use ButtplugClientMessageDestination::{Device, DeviceManager};
+    match msg {
+      ButtplugClientMessage::StartScanning(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::StopScanning(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::RequestDeviceList(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::RequestScanningStatus(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::RequestDeviceLatency(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::StopAllDevices(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::PlayPatternCmd(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::StopPatternCmd(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::AddDeviceToGroup(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::RemoveDeviceFromGroup(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::GroupScalarCmd(m) => DeviceManager(m.into()),
+      ButtplugClientMessage::VibrateCmd(m) => Device(m.into()),
+      ButtplugClientMessage::LinearCmd(m) => Device(m.into()),
+      ButtplugClientMessage::RotateCmd(m) => Device(m.into()),
+      ButtplugClientMessage::RawWriteCmd(m) => Device(m.into()),
+      ButtplugClientMessage::RawReadCmd(m) => Device(m.into()),
+      ButtplugClientMessage::StopDeviceCmd(m) => Device(m.into()),
+      ButtplugClientMessage::RawSubscribeCmd(m) => Device(m.into()),
+      ButtplugClientMessage::RawUnsubscribeCmd(m) => Device(m.into()),
+      ButtplugClientMessage::ScalarCmd(m) => Device(m.into()),
+      ButtplugClientMessage::BatteryLevelCmd(m) => Device(m.into()),
+      ButtplugClientMessage::RSSILevelCmd(m) => Device(m.into()),
+      ButtplugClientMessage::SensorReadCmd(m) => Device(m.into()),
+      ButtplugClientMessage::SensorSubscribeCmd(m) => Device(m.into()),
+      ButtplugClientMessage::SensorUnsubscribeCmd(m) => Device(m.into()),
+      ButtplugClientMessage::SingleMotorVibrateCmd(m) => Device(m.into()),
+      ButtplugClientMessage::FleshlightLaunchFW12Cmd(m) => Device(m.into()),
+      ButtplugClientMessage::KiirooCmd(m) => Device(m.into()),
+      ButtplugClientMessage::VorzeA10CycloneCmd(m) => Device(m.into()),
+      msg @ (ButtplugClientMessage::Ping(_)
+      | ButtplugClientMessage::RequestLog(_)
+      | ButtplugClientMessage::RequestServerInfo(_)
+      | ButtplugClientMessage::LovenseCmd(_)) => ButtplugClientMessageDestination::Server(msg),
+    }
+  }
+}
@@ -0,0 +1,122 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::*;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// A single point on a pattern's intensity curve.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get_copy = "pub")]
+pub struct PatternKeyframe {
+  /// Time from the start of the pattern, in milliseconds
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Time"))]
+  time_ms: u32,
+  /// Intensity at this point, 0.0-1.0
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Intensity"))]
+  intensity: f64,
+}
+
+impl PatternKeyframe {
+  pub fn new(time_ms: u32, intensity: f64) -> Self {
+    Self { time_ms, intensity }
+  }
+}
+
+/// Checks that keyframes are non-empty, in time order, and have intensities in the range 0.0-1.0.
+pub(crate) fn check_pattern_keyframes(
+  keyframes: &[PatternKeyframe],
+) -> Result<(), ButtplugMessageError> {
+  if keyframes.is_empty() {
+    return Err(ButtplugMessageError::InvalidMessageContents(
+      "Pattern must have at least one keyframe".to_owned(),
+    ));
+  }
+  if keyframes
+    .windows(2)
+    .any(|w| w[0].time_ms() > w[1].time_ms())
+  {
+    return Err(ButtplugMessageError::InvalidMessageContents(
+      "Pattern keyframes must be in time order".to_owned(),
+    ));
+  }
+  if let Some(keyframe) = keyframes
+    .iter()
+    .find(|k| !(0.0..=1.0).contains(&k.intensity()))
+  {
+    return Err(ButtplugMessageError::InvalidMessageContents(format!(
+      "Pattern keyframe intensity {} is outside of range 0.0-1.0",
+      keyframe.intensity()
+    )));
+  }
+  Ok(())
+}
+
+/// Plays a timed intensity curve on all of a device's scalar actuators, with the server handling
+/// timing, until the pattern finishes or is stopped.
+#[derive(
+  Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters, CopyGetters,
+)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct PlayPatternCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Keyframes"))]
+  #[getset(get = "pub")]
+  keyframes: Vec<PatternKeyframe>,
+  /// If true, the pattern restarts from the beginning once it finishes, until stopped.
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Loop", default))]
+  #[getset(get_copy = "pub")]
+  looping: bool,
+}
+
+impl PlayPatternCmd {
+  pub fn new(device_index: u32, keyframes: &[PatternKeyframe], looping: bool) -> Self {
+    Self {
+      id: 1,
+      device_index,
+      keyframes: keyframes.to_vec(),
+      looping,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for PlayPatternCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)?;
+    check_pattern_keyframes(&self.keyframes)
+  }
+}
+
+/// Stops pattern playback on a device, and stops the device.
+#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct StopPatternCmd {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "DeviceIndex"))]
+  device_index: u32,
+}
+
+impl StopPatternCmd {
+  pub fn new(device_index: u32) -> Self {
+    Self {
+      id: 1,
+      device_index,
+    }
+  }
+}
+
+impl ButtplugMessageValidator for StopPatternCmd {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
@@ -6,7 +6,7 @@
 // for full license information.
 
 use super::*;
-pub use getset::{CopyGetters, Getters};
+pub use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
@@ -32,7 +32,16 @@ impl RotationSubcommand {
   }
 }
 
-#[derive(Debug, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters)]
+#[derive(
+  Debug,
+  ButtplugDeviceMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Clone,
+  Getters,
+  CopyGetters,
+  Setters,
+)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct RotateCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
@@ -43,6 +52,18 @@ pub struct RotateCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Rotations"))]
   #[getset(get = "pub")]
   rotations: Vec<RotationSubcommand>,
+  /// Server clock time, in milliseconds, at which the command should take effect on the device.
+  /// If unset, the command is carried out as soon as it's received.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ScheduledTime",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub", set = "pub")]
+  scheduled_time: Option<u64>,
 }
 
 impl RotateCmd {
@@ -51,6 +72,7 @@ impl RotateCmd {
       id: 1,
       device_index,
       rotations,
+      scheduled_time: None,
     }
   }
 }
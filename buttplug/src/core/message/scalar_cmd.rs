@@ -6,7 +6,7 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
@@ -34,7 +34,15 @@ impl ScalarSubcommand {
 }
 
 #[derive(
-  Debug, Default, ButtplugDeviceMessage, ButtplugMessageFinalizer, PartialEq, Clone, Getters,
+  Debug,
+  Default,
+  ButtplugDeviceMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Clone,
+  Getters,
+  CopyGetters,
+  Setters,
 )]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct ScalarCmd {
@@ -45,6 +53,18 @@ pub struct ScalarCmd {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Scalars"))]
   #[getset(get = "pub")]
   scalars: Vec<ScalarSubcommand>,
+  /// Server clock time, in milliseconds, at which the command should take effect on the device.
+  /// If unset, the command is carried out as soon as it's received.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ScheduledTime",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub", set = "pub")]
+  scheduled_time: Option<u64>,
 }
 
 impl ScalarCmd {
@@ -53,6 +73,7 @@ impl ScalarCmd {
       id: 1,
       device_index,
       scalars,
+      scheduled_time: None,
     }
   }
 }
@@ -84,6 +105,7 @@ impl From<VibrateCmd> for ScalarCmd {
       id: vibrate_cmd.id(),
       device_index: vibrate_cmd.device_index(),
       scalars: subcommands,
+      scheduled_time: None,
     }
   }
 }
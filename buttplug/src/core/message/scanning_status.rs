@@ -0,0 +1,104 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Messages for querying the scanning state of each hardware communication manager.
+
+use super::*;
+use getset::{CopyGetters, Getters};
+#[cfg(feature = "serialize-json")]
+use serde::{Deserialize, Serialize};
+
+/// Asks the server for the scanning state of each of its hardware communication managers. The
+/// server replies with [ScanningStatus].
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct RequestScanningStatus {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+}
+
+impl Default for RequestScanningStatus {
+  fn default() -> Self {
+    Self { id: 1 }
+  }
+}
+
+impl ButtplugMessageValidator for RequestScanningStatus {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
+
+/// Scanning state of a single hardware communication manager.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub enum ManagerScanningState {
+  /// Not scanning, either because no scan was requested, or the last scan finished or was stopped.
+  Idle,
+  Scanning,
+  /// The manager can't scan, e.g. because there's no Bluetooth adapter.
+  Unavailable,
+  /// The manager returned an error when starting the scan.
+  Failed,
+}
+
+/// Scanning state of a hardware communication manager, identified by its name.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct ManagerScanningStatus {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "ManagerName"))]
+  #[getset(get = "pub")]
+  manager_name: String,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "State"))]
+  #[getset(get_copy = "pub")]
+  state: ManagerScanningState,
+  /// Error the manager returned, only set if the state is [ManagerScanningState::Failed].
+  #[cfg_attr(feature = "serialize-json", serde(rename = "ErrorMessage"))]
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(skip_serializing_if = "Option::is_none", default)
+  )]
+  #[getset(get = "pub")]
+  error_message: Option<String>,
+}
+
+impl ManagerScanningStatus {
+  pub fn new(
+    manager_name: &str,
+    state: ManagerScanningState,
+    error_message: Option<String>,
+  ) -> Self {
+    Self {
+      manager_name: manager_name.to_owned(),
+      state,
+      error_message,
+    }
+  }
+}
+
+/// Reply to [RequestScanningStatus].
+#[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+pub struct ScanningStatus {
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
+  id: u32,
+  #[cfg_attr(feature = "serialize-json", serde(rename = "Managers"))]
+  #[getset(get = "pub")]
+  managers: Vec<ManagerScanningStatus>,
+}
+
+impl ScanningStatus {
+  pub fn new(managers: Vec<ManagerScanningStatus>) -> Self {
+    Self { id: 1, managers }
+  }
+}
+
+impl ButtplugMessageValidator for ScanningStatus {
+  fn is_valid(&self) -> Result<(), ButtplugMessageError> {
+    self.is_not_system_id(self.id)
+  }
+}
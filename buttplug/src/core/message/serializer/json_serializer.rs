@@ -10,12 +10,14 @@ use crate::core::{
   errors::{ButtplugError, ButtplugHandshakeError},
   message::{
     self,
+    convert_server_messages,
     ButtplugClientMessage,
     ButtplugCurrentSpecClientMessage,
     ButtplugCurrentSpecServerMessage,
     ButtplugMessage,
     ButtplugMessageFinalizer,
     ButtplugMessageSpecVersion,
+    ButtplugMessageValidator,
     ButtplugServerMessage,
     ButtplugSpecV0ClientMessage,
     ButtplugSpecV0ServerMessage,
@@ -25,38 +27,165 @@ use crate::core::{
     ButtplugSpecV2ServerMessage,
     ButtplugSpecV3ClientMessage,
     ButtplugSpecV3ServerMessage,
+    ButtplugSpecV4ClientMessage,
+    ButtplugSpecV4ServerMessage,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
   },
 };
 use jsonschema::JSONSchema;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
 use serde_json::{Deserializer, Value};
-use std::{convert::TryFrom, fmt::Debug};
+use std::{
+  collections::HashSet,
+  fmt::Debug,
+  sync::{Mutex, RwLock},
+};
 
 static MESSAGE_JSON_SCHEMA: &str =
   include_str!("../../../../buttplug-schema/schema/buttplug-schema.json");
 
+/// Compiled once, on first use, and shared by all serializers.
+static MESSAGE_VALIDATOR: Lazy<JSONSchema> = Lazy::new(create_message_validator);
+
+static DEFAULT_VALIDATION_MODE: RwLock<JsonValidationMode> =
+  RwLock::new(JsonValidationMode::Always);
+
 /// Creates a [jsonschema::JSONSchema] validator using the built in buttplug message schema.
 pub fn create_message_validator() -> JSONSchema {
   let schema: serde_json::Value =
     serde_json::from_str(MESSAGE_JSON_SCHEMA).expect("Built in schema better be valid");
   JSONSchema::compile(&schema).expect("Built in schema better be valid")
 }
+
+/// How incoming JSON messages are checked against the Buttplug message schema.
+///
+/// The schema catches messages that serde would still accept, like out of range values or missing
+/// subcommands, but checking it is the most expensive part of deserialization at high message
+/// rates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsonValidationMode {
+  /// Validate every message.
+  #[default]
+  Always,
+  /// Validate the first message of each type a serializer receives, and skip validation for later
+  /// messages of that type. Meant for command streams from clients that are known to be well
+  /// behaved.
+  FirstOfType,
+  /// Never validate, only check that messages deserialize.
+  Never,
+}
+
+/// Set the validation mode for JSON serializers created with [Default], which is how connectors
+/// create them for each connection. Serializers that already exist keep their mode.
+pub fn set_default_json_validation_mode(mode: JsonValidationMode) {
+  *DEFAULT_VALIDATION_MODE
+    .write()
+    .expect("Lock is never held across a panic") = mode;
+}
+
+fn default_json_validation_mode() -> JsonValidationMode {
+  *DEFAULT_VALIDATION_MODE
+    .read()
+    .expect("Lock is never held across a panic")
+}
+
+/// Schema validation state for a single serializer.
+pub(super) struct MessageValidator {
+  mode: JsonValidationMode,
+  /// Message types that have passed validation, only used with
+  /// [JsonValidationMode::FirstOfType].
+  validated_types: Mutex<HashSet<String>>,
+}
+
+impl MessageValidator {
+  fn new(mode: JsonValidationMode) -> Self {
+    Self {
+      mode,
+      validated_types: Mutex::new(HashSet::new()),
+    }
+  }
+
+  fn validate(&self, json_msg: &Value) -> Result<(), ButtplugSerializerError> {
+    let message_types = if self.mode == JsonValidationMode::FirstOfType {
+      message_types(json_msg)
+    } else {
+      None
+    };
+    if let Some(message_types) = &message_types {
+      let validated_types = self
+        .validated_types
+        .lock()
+        .expect("Lock is never held across a panic");
+      if message_types
+        .iter()
+        .all(|message_type| validated_types.contains(message_type))
+      {
+        return Ok(());
+      }
+    }
+    if !MESSAGE_VALIDATOR.is_valid(json_msg) {
+      // If is_valid fails, re-run validation to get our error message.
+      let e = MESSAGE_VALIDATOR
+        .validate(json_msg)
+        .expect_err("We can't get here without validity checks failing.");
+      let err_vec: Vec<jsonschema::ValidationError> = e.collect();
+      return Err(ButtplugSerializerError::JsonSerializerError(format!(
+        "Error during JSON Schema Validation - Message: {} - Error: {:?}",
+        json_msg, err_vec
+      )));
+    }
+    if let Some(message_types) = message_types {
+      self
+        .validated_types
+        .lock()
+        .expect("Lock is never held across a panic")
+        .extend(message_types);
+    }
+    Ok(())
+  }
+}
+
+impl Default for MessageValidator {
+  fn default() -> Self {
+    Self::new(default_json_validation_mode())
+  }
+}
+
+/// Names of the messages in a message array, or None if it isn't shaped like one.
+fn message_types(json_msg: &Value) -> Option<Vec<String>> {
+  json_msg
+    .as_array()?
+    .iter()
+    .map(|msg| {
+      let msg = msg.as_object()?;
+      (msg.len() == 1).then(|| msg.keys().next().cloned())?
+    })
+    .collect()
+}
+
 pub struct ButtplugServerJSONSerializer {
   pub(super) message_version: OnceCell<message::ButtplugMessageSpecVersion>,
-  validator: JSONSchema,
+  validator: MessageValidator,
 }
 
 impl Default for ButtplugServerJSONSerializer {
   fn default() -> Self {
     Self {
       message_version: OnceCell::new(),
-      validator: create_message_validator(),
+      validator: MessageValidator::default(),
     }
   }
 }
 
 impl ButtplugServerJSONSerializer {
+  pub fn new(validation_mode: JsonValidationMode) -> Self {
+    Self {
+      message_version: OnceCell::new(),
+      validator: MessageValidator::new(validation_mode),
+    }
+  }
+
   pub fn force_message_version(&self, version: &ButtplugMessageSpecVersion) {
     self
       .message_version
@@ -81,57 +210,44 @@ where
 }
 
 pub fn deserialize_to_message<T>(
-  validator: &JSONSchema,
+  validator: &MessageValidator,
   msg_str: &str,
 ) -> Result<Vec<T>, ButtplugSerializerError>
 where
-  T: serde::de::DeserializeOwned + ButtplugMessageFinalizer + Clone + Debug,
+  T: serde::de::DeserializeOwned
+    + ButtplugMessageFinalizer
+    + ButtplugMessageValidator
+    + Clone
+    + Debug,
 {
   // TODO This assumes that we've gotten a full JSON string to deserialize, which may not be the
   // case.
-  let stream = Deserializer::from_str(msg_str).into_iter::<Value>();
+  let deserialize_error = |e: serde_json::Error| {
+    ButtplugSerializerError::JsonSerializerError(format!("Message: {} - Error: {:?}", msg_str, e))
+  };
 
   let mut result = vec![];
 
-  for msg in stream {
-    match msg {
-      Ok(json_msg) => {
-        if validator.is_valid(&json_msg) {
-          match serde_json::from_value::<Vec<T>>(json_msg) {
-            Ok(mut msg_vec) => {
-              for msg in msg_vec.iter_mut() {
-                msg.finalize();
-              }
-              result.append(&mut msg_vec);
-              //Ok(msg_vec)
-            }
-            Err(e) => {
-              return Err(ButtplugSerializerError::JsonSerializerError(format!(
-                "Message: {} - Error: {:?}",
-                msg_str, e
-              )))
-            }
-          }
-        } else {
-          // If is_valid fails, re-run validation to get our error message.
-          let e = validator
-            .validate(&json_msg)
-            .expect_err("We can't get here without validity checks failing.");
-          let err_vec: Vec<jsonschema::ValidationError> = e.collect();
-          return Err(ButtplugSerializerError::JsonSerializerError(format!(
-            "Error during JSON Schema Validation - Message: {} - Error: {:?}",
-            json_msg, err_vec
-          )));
-        }
-      }
-      Err(e) => {
-        return Err(ButtplugSerializerError::JsonSerializerError(format!(
-          "Message: {} - Error: {:?}",
-          msg_str, e
-        )))
-      }
+  if validator.mode == JsonValidationMode::Never {
+    // Nothing to validate, so skip the intermediate Value and deserialize directly.
+    for msg in Deserializer::from_str(msg_str).into_iter::<Vec<T>>() {
+      result.append(&mut msg.map_err(deserialize_error)?);
+    }
+  } else {
+    for msg in Deserializer::from_str(msg_str).into_iter::<Value>() {
+      let json_msg = msg.map_err(deserialize_error)?;
+      validator.validate(&json_msg)?;
+      result.append(&mut serde_json::from_value::<Vec<T>>(json_msg).map_err(deserialize_error)?);
     }
   }
+  for msg in result.iter_mut() {
+    msg.finalize();
+    // The schema may not have been checked, and even when it was, message level checks still need
+    // to run before anything downstream does math with the values.
+    msg.is_valid().map_err(|e| {
+      ButtplugSerializerError::JsonSerializerError(format!("Message: {} - Error: {}", msg_str, e))
+    })?;
+  }
   Ok(result)
 }
 
@@ -141,51 +257,23 @@ fn serialize_to_version(
 ) -> ButtplugSerializedMessage {
   ButtplugSerializedMessage::Text(match version {
     ButtplugMessageSpecVersion::Version0 => {
-      let msg_vec: Vec<ButtplugSpecV0ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV0ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV0ServerMessage::Error(
-            message::Error::from(ButtplugError::from(err)).into(),
-          ),
-        })
-        .collect();
+      let msg_vec: Vec<ButtplugSpecV0ServerMessage> = convert_server_messages(msgs);
       vec_to_protocol_json(&msg_vec)
     }
     ButtplugMessageSpecVersion::Version1 => {
-      let msg_vec: Vec<ButtplugSpecV1ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV1ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV1ServerMessage::Error(
-            message::Error::from(ButtplugError::from(err)).into(),
-          ),
-        })
-        .collect();
+      let msg_vec: Vec<ButtplugSpecV1ServerMessage> = convert_server_messages(msgs);
       vec_to_protocol_json(&msg_vec)
     }
     ButtplugMessageSpecVersion::Version2 => {
-      let msg_vec: Vec<ButtplugSpecV2ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV2ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV2ServerMessage::Error(ButtplugError::from(err).into()),
-        })
-        .collect();
+      let msg_vec: Vec<ButtplugSpecV2ServerMessage> = convert_server_messages(msgs);
       vec_to_protocol_json(&msg_vec)
     }
     ButtplugMessageSpecVersion::Version3 => {
-      let msg_vec: Vec<ButtplugSpecV3ServerMessage> = msgs
-        .iter()
-        .cloned()
-        .map(|msg| match ButtplugSpecV3ServerMessage::try_from(msg) {
-          Ok(msgv0) => msgv0,
-          Err(err) => ButtplugSpecV3ServerMessage::Error(ButtplugError::from(err).into()),
-        })
-        .collect();
+      let msg_vec: Vec<ButtplugSpecV3ServerMessage> = convert_server_messages(msgs);
+      vec_to_protocol_json(&msg_vec)
+    }
+    ButtplugMessageSpecVersion::Version4 => {
+      let msg_vec: Vec<ButtplugSpecV4ServerMessage> = convert_server_messages(msgs);
       vec_to_protocol_json(&msg_vec)
     }
   })
@@ -238,16 +326,24 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
             .map(|m| m.into())
             .collect()
         }
+        ButtplugMessageSpecVersion::Version4 => {
+          deserialize_to_message::<ButtplugSpecV4ClientMessage>(&self.validator, msg)?
+            .iter()
+            .cloned()
+            .map(|m| m.into())
+            .collect()
+        }
       });
     }
     // instead of using if/else here, return in the if, which drops the borrow.
     // so we can possibly mutate it now.
-    let msg_union = deserialize_to_message::<ButtplugSpecV3ClientMessage>(&self.validator, msg)?;
+    let msg_union =
+      deserialize_to_message::<ButtplugCurrentSpecClientMessage>(&self.validator, msg)?;
     // If the message is malformed, just return an spec version not received error.
     if msg_union.is_empty() {
       return Err(ButtplugSerializerError::MessageSpecVersionNotReceived);
     }
-    if let ButtplugSpecV3ClientMessage::RequestServerInfo(rsi) = &msg_union[0] {
+    if let ButtplugCurrentSpecClientMessage::RequestServerInfo(rsi) = &msg_union[0] {
       info!(
         "Setting JSON Wrapper message version to {}",
         rsi.message_version()
@@ -270,7 +366,7 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
       // RequestServerInfo message (so we can't set up our known spec
       // version), just encode to the latest and return.
       if let ButtplugServerMessage::Error(_) = &msgs[0] {
-        serialize_to_version(ButtplugMessageSpecVersion::Version3, msgs)
+        serialize_to_version(BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION, msgs)
       } else {
         // If we don't even have enough info to know which message
         // version to convert to, consider this a handshake error.
@@ -284,25 +380,28 @@ impl ButtplugMessageSerializer for ButtplugServerJSONSerializer {
   }
 }
 
+#[derive(Default)]
 pub struct ButtplugClientJSONSerializerImpl {
-  validator: JSONSchema,
+  validator: MessageValidator,
 }
 
-impl Default for ButtplugClientJSONSerializerImpl {
-  fn default() -> Self {
+impl ButtplugClientJSONSerializerImpl {
+  pub fn new(validation_mode: JsonValidationMode) -> Self {
     Self {
-      validator: create_message_validator(),
+      validator: MessageValidator::new(validation_mode),
     }
   }
-}
 
-impl ButtplugClientJSONSerializerImpl {
   pub fn deserialize<T>(
     &self,
     msg: &ButtplugSerializedMessage,
   ) -> Result<Vec<T>, ButtplugSerializerError>
   where
-    T: serde::de::DeserializeOwned + ButtplugMessageFinalizer + Clone + Debug,
+    T: serde::de::DeserializeOwned
+      + ButtplugMessageFinalizer
+      + ButtplugMessageValidator
+      + Clone
+      + Debug,
   {
     if let ButtplugSerializedMessage::Text(text_msg) = msg {
       deserialize_to_message::<T>(&self.validator, text_msg)
@@ -324,6 +423,14 @@ pub struct ButtplugClientJSONSerializer {
   serializer_impl: ButtplugClientJSONSerializerImpl,
 }
 
+impl ButtplugClientJSONSerializer {
+  pub fn new(validation_mode: JsonValidationMode) -> Self {
+    Self {
+      serializer_impl: ButtplugClientJSONSerializerImpl::new(validation_mode),
+    }
+  }
+}
+
 impl ButtplugMessageSerializer for ButtplugClientJSONSerializer {
   type Inbound = ButtplugCurrentSpecServerMessage;
   type Outbound = ButtplugCurrentSpecClientMessage;
@@ -343,7 +450,16 @@ impl ButtplugMessageSerializer for ButtplugClientJSONSerializer {
 #[cfg(test)]
 mod test {
   use super::*;
-  use crate::core::message::{RequestServerInfo, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION};
+  use crate::core::message::{
+    ButtplugClientMessage,
+    ButtplugCurrentSpecServerMessage,
+    DeviceRemoved,
+    DeviceRemovedReason,
+    RequestServerInfo,
+    ScanFilter,
+    StartScanning,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  };
 
   #[test]
   fn test_correct_message_version() {
@@ -473,6 +589,104 @@ mod test {
     ));
   }
 
+  fn scalar_cmd_json(scalars: &str) -> ButtplugSerializedMessage {
+    ButtplugSerializedMessage::Text(format!(
+      r#"[{{"ScalarCmd": {{"Id": 1, "DeviceIndex": 0, "Scalars": [{}]}}}}]"#,
+      scalars
+    ))
+  }
+
+  #[test]
+  fn test_validation_modes() {
+    let valid = scalar_cmd_json(r#"{"Index": 0, "Scalar": 0.5, "ActuatorType": "Vibrate"}"#);
+    // Deserializes fine, but the schema requires at least one scalar.
+    let schema_invalid = scalar_cmd_json("");
+    let serializer = |mode| {
+      let serializer = ButtplugServerJSONSerializer::new(mode);
+      serializer.force_message_version(&ButtplugMessageSpecVersion::Version3);
+      serializer
+    };
+
+    let always = serializer(JsonValidationMode::Always);
+    assert!(always.deserialize(&valid).is_ok());
+    assert!(always.deserialize(&schema_invalid).is_err());
+
+    let first_of_type = serializer(JsonValidationMode::FirstOfType);
+    // Types that haven't passed validation yet are still checked.
+    assert!(first_of_type.deserialize(&schema_invalid).is_err());
+    assert!(first_of_type.deserialize(&valid).is_ok());
+    assert!(first_of_type.deserialize(&schema_invalid).is_ok());
+    // Messages that don't deserialize are still rejected.
+    assert!(first_of_type
+      .deserialize(&scalar_cmd_json(r#"{"Index": 0}"#))
+      .is_err());
+
+    // Out of range values are caught without the schema.
+    let out_of_range =
+      scalar_cmd_json(r#"{"Index": 0, "Scalar": 1e300, "ActuatorType": "Vibrate"}"#);
+    assert!(first_of_type.deserialize(&out_of_range).is_err());
+
+    let never = serializer(JsonValidationMode::Never);
+    assert!(never.deserialize(&schema_invalid).is_ok());
+    assert!(never.deserialize(&out_of_range).is_err());
+    assert!(never
+      .deserialize(&ButtplugSerializedMessage::Text("not json".to_owned()))
+      .is_err());
+  }
+
+  #[test]
+  fn test_start_scanning_filter() {
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer.force_message_version(&BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    let json = r#"[{"StartScanning": {"Id": 1, "Filter": {"Transports": ["btle"],
+      "Addresses": ["AA:BB:CC:DD:EE:FF"]}}}]"#;
+    let messages = serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json.to_owned()))
+      .expect("Infallible deserialization");
+    assert_eq!(
+      messages,
+      vec![ButtplugClientMessage::StartScanning(StartScanning::new(
+        Some(ScanFilter::new(&[], &["btle"], &["AA:BB:CC:DD:EE:FF"]))
+      ))]
+    );
+    let json = r#"[{"StartScanning": {"Id": 1, "Filter": {"Names": ["Max"]}}}]"#;
+    assert!(serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json.to_owned()))
+      .is_err());
+  }
+
+  #[test]
+  fn test_device_removed_reason() {
+    let removed = DeviceRemoved::new_with_reason(1, DeviceRemovedReason::PoweredOff, false);
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer.force_message_version(&BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+    let ButtplugSerializedMessage::Text(json) = serializer.serialize(&[removed.clone().into()])
+    else {
+      panic!("JSON serializer should produce text");
+    };
+    assert_eq!(
+      json,
+      r#"[{"DeviceRemoved":{"Id":0,"DeviceIndex":1,"Reason":"PoweredOff"}}]"#
+    );
+    let client_serializer = ButtplugClientJSONSerializer::default();
+    let messages = client_serializer
+      .deserialize(&ButtplugSerializedMessage::Text(json))
+      .expect("Infallible deserialization");
+    assert_eq!(
+      messages,
+      vec![ButtplugCurrentSpecServerMessage::DeviceRemoved(
+        removed.clone()
+      )]
+    );
+    // Older clients only get the device index.
+    let serializer = ButtplugServerJSONSerializer::default();
+    serializer.force_message_version(&ButtplugMessageSpecVersion::Version2);
+    let ButtplugSerializedMessage::Text(json) = serializer.serialize(&[removed.into()]) else {
+      panic!("JSON serializer should produce text");
+    };
+    assert_eq!(json, r#"[{"DeviceRemoved":{"Id":0,"DeviceIndex":1}}]"#);
+  }
+
   #[test]
   fn test_client_incorrect_messages() {
     let incorrect_incoming_messages = vec![
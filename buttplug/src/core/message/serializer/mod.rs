@@ -11,10 +11,22 @@
 mod json_serializer;
 #[cfg(feature = "serialize-json")]
 pub use json_serializer::{
+  set_default_json_validation_mode,
   vec_to_protocol_json,
   ButtplugClientJSONSerializer,
   ButtplugClientJSONSerializerImpl,
   ButtplugServerJSONSerializer,
+  JsonValidationMode,
+};
+#[cfg(feature = "serialize-msgpack")]
+mod msgpack_serializer;
+#[cfg(feature = "serialize-msgpack")]
+pub use msgpack_serializer::{
+  vec_to_protocol_msgpack,
+  ButtplugClientMessagePackSerializer,
+  ButtplugSerializationFormat,
+  ButtplugServerMessagePackSerializer,
+  ButtplugServerNegotiatedSerializer,
 };
 
 use serde::{Deserialize, Serialize};
@@ -30,6 +42,8 @@ pub enum ButtplugSerializerError {
   /// Serialization error.
   #[error("Cannot serialize to JSON: {0}")]
   JsonSerializerError(String),
+  #[error("Cannot de/serialize MessagePack: {0}")]
+  MessagePackSerializerError(String),
   #[error("Cannot deserialize binary in a text handler")]
   BinaryDeserializationError,
   #[error("Cannot deserialize text in a binary handler.")]
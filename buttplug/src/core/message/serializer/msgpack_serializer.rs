@@ -0,0 +1,279 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! MessagePack serialization
+//!
+//! Messages are encoded with the same structure as the JSON protocol: each serialized message is
+//! an array of single key maps, keyed by message name, with struct fields encoded as maps using
+//! the same field names. Only the encoding differs, which cuts message size and parsing time for
+//! high rate command streams. There's no schema for binary messages, so messages are only checked
+//! by deserialization, like [JsonValidationMode::Never](super::JsonValidationMode::Never).
+//!
+//! Serialized messages are always [ButtplugSerializedMessage::Binary].
+
+use super::{
+  ButtplugMessageSerializer,
+  ButtplugSerializedMessage,
+  ButtplugSerializerError,
+  ButtplugServerJSONSerializer,
+};
+use crate::core::{
+  errors::{ButtplugError, ButtplugHandshakeError},
+  message::{
+    convert_server_messages,
+    ButtplugClientMessage,
+    ButtplugCurrentSpecClientMessage,
+    ButtplugCurrentSpecServerMessage,
+    ButtplugMessageFinalizer,
+    ButtplugMessageSpecVersion,
+    ButtplugMessageValidator,
+    ButtplugServerMessage,
+    ButtplugSpecV0ClientMessage,
+    ButtplugSpecV0ServerMessage,
+    ButtplugSpecV1ClientMessage,
+    ButtplugSpecV1ServerMessage,
+    ButtplugSpecV2ClientMessage,
+    ButtplugSpecV2ServerMessage,
+    ButtplugSpecV3ClientMessage,
+    ButtplugSpecV3ServerMessage,
+    ButtplugSpecV4ClientMessage,
+    ButtplugSpecV4ServerMessage,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  },
+};
+use once_cell::sync::OnceCell;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt::Debug, io::Cursor};
+
+fn serialize_error<E: Debug>(err: E) -> ButtplugSerializerError {
+  ButtplugSerializerError::MessagePackSerializerError(format!("{:?}", err))
+}
+
+/// Encode a message array.
+pub fn vec_to_protocol_msgpack<T>(msgs: &[T]) -> ButtplugSerializedMessage
+where
+  T: Serialize,
+{
+  // Maps with named fields, instead of the default of field arrays, so optional fields can be
+  // skipped the same way as in JSON.
+  ButtplugSerializedMessage::Binary(
+    rmp_serde::to_vec_named(msgs).expect("Infallible serialization"),
+  )
+}
+
+/// Decode one or more message arrays. Like the JSON serializer, multiple arrays can be sent back
+/// to back in one serialized message.
+pub fn deserialize_msgpack_to_message<T>(
+  msg: &ButtplugSerializedMessage,
+) -> Result<Vec<T>, ButtplugSerializerError>
+where
+  T: DeserializeOwned + ButtplugMessageFinalizer + ButtplugMessageValidator,
+{
+  let ButtplugSerializedMessage::Binary(bytes) = msg else {
+    return Err(ButtplugSerializerError::TextDeserializationError);
+  };
+  let mut cursor = Cursor::new(bytes.as_slice());
+  let mut result = vec![];
+  while (cursor.position() as usize) < bytes.len() {
+    let mut deserializer = rmp_serde::Deserializer::new(&mut cursor);
+    result.append(&mut Vec::<T>::deserialize(&mut deserializer).map_err(serialize_error)?);
+  }
+  for msg in result.iter_mut() {
+    msg.finalize();
+    // There's no schema for MessagePack, so this is the only check values get before use.
+    msg.is_valid().map_err(serialize_error)?;
+  }
+  Ok(result)
+}
+
+fn serialize_to_version(
+  version: ButtplugMessageSpecVersion,
+  msgs: &[ButtplugServerMessage],
+) -> ButtplugSerializedMessage {
+  match version {
+    ButtplugMessageSpecVersion::Version0 => {
+      let msg_vec: Vec<ButtplugSpecV0ServerMessage> = convert_server_messages(msgs);
+      vec_to_protocol_msgpack(&msg_vec)
+    }
+    ButtplugMessageSpecVersion::Version1 => {
+      let msg_vec: Vec<ButtplugSpecV1ServerMessage> = convert_server_messages(msgs);
+      vec_to_protocol_msgpack(&msg_vec)
+    }
+    ButtplugMessageSpecVersion::Version2 => {
+      let msg_vec: Vec<ButtplugSpecV2ServerMessage> = convert_server_messages(msgs);
+      vec_to_protocol_msgpack(&msg_vec)
+    }
+    ButtplugMessageSpecVersion::Version3 => {
+      let msg_vec: Vec<ButtplugSpecV3ServerMessage> = convert_server_messages(msgs);
+      vec_to_protocol_msgpack(&msg_vec)
+    }
+    ButtplugMessageSpecVersion::Version4 => {
+      let msg_vec: Vec<ButtplugSpecV4ServerMessage> = convert_server_messages(msgs);
+      vec_to_protocol_msgpack(&msg_vec)
+    }
+  }
+}
+
+/// Server side MessagePack serializer. Handles spec versions the same way as
+/// [ButtplugServerJSONSerializer].
+#[derive(Default)]
+pub struct ButtplugServerMessagePackSerializer {
+  message_version: OnceCell<ButtplugMessageSpecVersion>,
+}
+
+impl ButtplugServerMessagePackSerializer {
+  pub fn force_message_version(&self, version: &ButtplugMessageSpecVersion) {
+    self
+      .message_version
+      .set(*version)
+      .expect("This should only ever be called once.");
+  }
+}
+
+impl ButtplugMessageSerializer for ButtplugServerMessagePackSerializer {
+  type Inbound = ButtplugClientMessage;
+  type Outbound = ButtplugServerMessage;
+
+  fn deserialize(
+    &self,
+    msg: &ButtplugSerializedMessage,
+  ) -> Result<Vec<ButtplugClientMessage>, ButtplugSerializerError> {
+    if let Some(version) = self.message_version.get() {
+      return Ok(match version {
+        ButtplugMessageSpecVersion::Version0 => {
+          deserialize_msgpack_to_message::<ButtplugSpecV0ClientMessage>(msg)?
+            .into_iter()
+            .map(|m| m.into())
+            .collect()
+        }
+        ButtplugMessageSpecVersion::Version1 => {
+          deserialize_msgpack_to_message::<ButtplugSpecV1ClientMessage>(msg)?
+            .into_iter()
+            .map(|m| m.into())
+            .collect()
+        }
+        ButtplugMessageSpecVersion::Version2 => {
+          deserialize_msgpack_to_message::<ButtplugSpecV2ClientMessage>(msg)?
+            .into_iter()
+            .map(|m| m.into())
+            .collect()
+        }
+        ButtplugMessageSpecVersion::Version3 => {
+          deserialize_msgpack_to_message::<ButtplugSpecV3ClientMessage>(msg)?
+            .into_iter()
+            .map(|m| m.into())
+            .collect()
+        }
+        ButtplugMessageSpecVersion::Version4 => {
+          deserialize_msgpack_to_message::<ButtplugSpecV4ClientMessage>(msg)?
+            .into_iter()
+            .map(|m| m.into())
+            .collect()
+        }
+      });
+    }
+    // Same as JSON, the first message has to be RequestServerInfo, which can always be parsed as the
+    // latest spec version.
+    let msg_union = deserialize_msgpack_to_message::<ButtplugCurrentSpecClientMessage>(msg)?;
+    let Some(ButtplugCurrentSpecClientMessage::RequestServerInfo(rsi)) = msg_union.first() else {
+      return Err(ButtplugSerializerError::MessageSpecVersionNotReceived);
+    };
+    info!(
+      "Setting MessagePack Wrapper message version to {}",
+      rsi.message_version()
+    );
+    self
+      .message_version
+      .set(rsi.message_version())
+      .expect("This should only ever be called once.");
+    Ok(msg_union.into_iter().map(|m| m.into()).collect())
+  }
+
+  fn serialize(&self, msgs: &[ButtplugServerMessage]) -> ButtplugSerializedMessage {
+    if let Some(version) = self.message_version.get() {
+      serialize_to_version(*version, msgs)
+    } else if let ButtplugServerMessage::Error(_) = &msgs[0] {
+      serialize_to_version(BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION, msgs)
+    } else {
+      vec_to_protocol_msgpack(&[ButtplugCurrentSpecServerMessage::Error(
+        ButtplugError::from(ButtplugHandshakeError::RequestServerInfoExpected).into(),
+      )])
+    }
+  }
+}
+
+/// Client side MessagePack serializer, speaking the current spec version.
+#[derive(Default)]
+pub struct ButtplugClientMessagePackSerializer {}
+
+impl ButtplugMessageSerializer for ButtplugClientMessagePackSerializer {
+  type Inbound = ButtplugCurrentSpecServerMessage;
+  type Outbound = ButtplugCurrentSpecClientMessage;
+
+  fn deserialize(
+    &self,
+    msg: &ButtplugSerializedMessage,
+  ) -> Result<Vec<Self::Inbound>, ButtplugSerializerError> {
+    deserialize_msgpack_to_message(msg)
+  }
+
+  fn serialize(&self, msgs: &[Self::Outbound]) -> ButtplugSerializedMessage {
+    vec_to_protocol_msgpack(msgs)
+  }
+}
+
+/// Wire formats supported by [ButtplugServerNegotiatedSerializer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtplugSerializationFormat {
+  Json,
+  MessagePack,
+}
+
+/// Server side serializer that speaks whichever format the client connects with.
+///
+/// The format is picked from the first message the client sends, which is the RequestServerInfo
+/// handshake: text messages mean JSON, binary messages mean MessagePack. The connection sticks to
+/// that format afterwards, messages in the other format are rejected.
+#[derive(Default)]
+pub struct ButtplugServerNegotiatedSerializer {
+  format: OnceCell<ButtplugSerializationFormat>,
+  json: ButtplugServerJSONSerializer,
+  msgpack: ButtplugServerMessagePackSerializer,
+}
+
+impl ButtplugServerNegotiatedSerializer {
+  /// Format the connection is using, or None before the client has sent anything.
+  pub fn format(&self) -> Option<ButtplugSerializationFormat> {
+    self.format.get().copied()
+  }
+}
+
+impl ButtplugMessageSerializer for ButtplugServerNegotiatedSerializer {
+  type Inbound = ButtplugClientMessage;
+  type Outbound = ButtplugServerMessage;
+
+  fn deserialize(
+    &self,
+    msg: &ButtplugSerializedMessage,
+  ) -> Result<Vec<ButtplugClientMessage>, ButtplugSerializerError> {
+    let format = self.format.get_or_init(|| match msg {
+      ButtplugSerializedMessage::Text(_) => ButtplugSerializationFormat::Json,
+      ButtplugSerializedMessage::Binary(_) => ButtplugSerializationFormat::MessagePack,
+    });
+    match format {
+      ButtplugSerializationFormat::Json => self.json.deserialize(msg),
+      ButtplugSerializationFormat::MessagePack => self.msgpack.deserialize(msg),
+    }
+  }
+
+  fn serialize(&self, msgs: &[ButtplugServerMessage]) -> ButtplugSerializedMessage {
+    match self.format() {
+      Some(ButtplugSerializationFormat::MessagePack) => self.msgpack.serialize(msgs),
+      _ => self.json.serialize(msgs),
+    }
+  }
+}
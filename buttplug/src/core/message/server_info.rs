@@ -6,12 +6,20 @@
 // for full license information.
 
 use super::*;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
 #[derive(
-  Debug, ButtplugMessage, ButtplugMessageFinalizer, PartialEq, Eq, Clone, Getters, CopyGetters,
+  Debug,
+  ButtplugMessage,
+  ButtplugMessageFinalizer,
+  PartialEq,
+  Eq,
+  Clone,
+  Getters,
+  CopyGetters,
+  Setters,
 )]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct ServerInfo {
@@ -26,6 +34,18 @@ pub struct ServerInfo {
   #[cfg_attr(feature = "serialize-json", serde(rename = "ServerName"))]
   #[getset(get = "pub")]
   server_name: String,
+  /// Server clock time, in milliseconds, when the message was created. Clients use this to
+  /// estimate the server clock for scheduling commands.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(
+      rename = "ServerTime",
+      default,
+      skip_serializing_if = "Option::is_none"
+    )
+  )]
+  #[getset(get_copy = "pub", set = "pub")]
+  server_time: Option<u64>,
 }
 
 impl ServerInfo {
@@ -39,6 +59,7 @@ impl ServerInfo {
       message_version,
       max_ping_time,
       server_name: server_name.to_string(),
+      server_time: None,
     }
   }
 }
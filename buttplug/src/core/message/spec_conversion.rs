@@ -0,0 +1,514 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Conversion of server messages down to the message spec version a client connected with.
+//!
+//! The server only ever works with [ButtplugServerMessage], which covers every message of every
+//! spec version. Each spec version has its own server message enum, holding only the messages
+//! (and message formats) that version knows about, and serializers convert to it before sending.
+//! Anything that doesn't exist in the client's spec version is replaced by an error, so older
+//! clients never receive message types they can't parse.
+
+use super::{
+  ButtplugMessage,
+  ButtplugMessageSpecVersion,
+  ButtplugServerMessage,
+  ButtplugSpecV0ServerMessage,
+  ButtplugSpecV1ServerMessage,
+  ButtplugSpecV2ServerMessage,
+  ButtplugSpecV3ServerMessage,
+  ButtplugSpecV4ServerMessage,
+  Error,
+};
+use crate::core::errors::{ButtplugError, ButtplugMessageError};
+
+/// Server message enum for a single spec version.
+pub trait ButtplugSpecServerMessage:
+  TryFrom<ButtplugServerMessage, Error = ButtplugMessageError> + ButtplugMessage
+{
+  /// Spec version the message enum belongs to.
+  const SPEC_VERSION: ButtplugMessageSpecVersion;
+
+  /// Wraps an error in this spec version's error message.
+  fn from_error(error: Error) -> Self;
+
+  /// Converts a server message, replacing it with an error carrying the same id if it can't be
+  /// expressed in this spec version.
+  fn from_server_message(msg: ButtplugServerMessage) -> Self {
+    let id = msg.id();
+    Self::try_from(msg).unwrap_or_else(|err| {
+      debug!(
+        "Cannot send message in spec version {}, sending error instead: {}",
+        Self::SPEC_VERSION,
+        err
+      );
+      let mut error = Self::from_error(ButtplugError::from(err).into());
+      error.set_id(id);
+      error
+    })
+  }
+}
+
+impl ButtplugSpecServerMessage for ButtplugSpecV0ServerMessage {
+  const SPEC_VERSION: ButtplugMessageSpecVersion = ButtplugMessageSpecVersion::Version0;
+
+  fn from_error(error: Error) -> Self {
+    Self::Error(error.into())
+  }
+}
+
+impl ButtplugSpecServerMessage for ButtplugSpecV1ServerMessage {
+  const SPEC_VERSION: ButtplugMessageSpecVersion = ButtplugMessageSpecVersion::Version1;
+
+  fn from_error(error: Error) -> Self {
+    Self::Error(error.into())
+  }
+}
+
+impl ButtplugSpecServerMessage for ButtplugSpecV2ServerMessage {
+  const SPEC_VERSION: ButtplugMessageSpecVersion = ButtplugMessageSpecVersion::Version2;
+
+  fn from_error(error: Error) -> Self {
+    Self::Error(error)
+  }
+}
+
+impl ButtplugSpecServerMessage for ButtplugSpecV3ServerMessage {
+  const SPEC_VERSION: ButtplugMessageSpecVersion = ButtplugMessageSpecVersion::Version3;
+
+  fn from_error(error: Error) -> Self {
+    Self::Error(error)
+  }
+}
+
+impl ButtplugSpecServerMessage for ButtplugSpecV4ServerMessage {
+  const SPEC_VERSION: ButtplugMessageSpecVersion = ButtplugMessageSpecVersion::Version4;
+
+  fn from_error(error: Error) -> Self {
+    Self::Error(error)
+  }
+}
+
+/// Converts a batch of server messages to a spec version, see
+/// [ButtplugSpecServerMessage::from_server_message].
+pub fn convert_server_messages<T>(msgs: &[ButtplugServerMessage]) -> Vec<T>
+where
+  T: ButtplugSpecServerMessage,
+{
+  msgs.iter().cloned().map(T::from_server_message).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::core::message::{
+    ActuatorType,
+    AddDeviceToGroup,
+    BatteryLevelCmd,
+    BatteryLevelReading,
+    ButtplugClientMessage,
+    ButtplugSpecV0ClientMessage,
+    ButtplugSpecV1ClientMessage,
+    ButtplugSpecV2ClientMessage,
+    ButtplugSpecV3ClientMessage,
+    ButtplugSpecV4ClientMessage,
+    ClientDeviceMessageAttributes,
+    ClientDeviceMessageAttributesBuilder,
+    ClientGenericDeviceMessageAttributes,
+    DeviceAdded,
+    DeviceConnectionInfo,
+    DeviceLatency,
+    DeviceList,
+    DeviceMessageInfo,
+    DeviceRemoved,
+    DeviceRemovedReason,
+    Endpoint,
+    ErrorCode,
+    FleshlightLaunchFW12Cmd,
+    GroupCommandResult,
+    GroupScalarCmd,
+    KiirooCmd,
+    LatencyStageStats,
+    LinearCmd,
+    Log,
+    LogLevel,
+    LovenseCmd,
+    Ok,
+    PatternKeyframe,
+    Ping,
+    PlayPatternCmd,
+    RSSILevelCmd,
+    RSSILevelReading,
+    RawReadCmd,
+    RawReading,
+    RawSubscribeCmd,
+    RawUnsubscribeCmd,
+    RawWriteCmd,
+    RemoveDeviceFromGroup,
+    RequestDeviceLatency,
+    RequestDeviceList,
+    RequestLog,
+    RequestScanningStatus,
+    RequestServerInfo,
+    RotateCmd,
+    RotationSubcommand,
+    ScalarCmd,
+    ScalarSubcommand,
+    ScanningFinished,
+    ScanningStatus,
+    SensorReadCmd,
+    SensorReading,
+    SensorSubscribeCmd,
+    SensorType,
+    SensorUnsubscribeCmd,
+    ServerInfo,
+    SingleMotorVibrateCmd,
+    StartScanning,
+    StopAllDevices,
+    StopDeviceCmd,
+    StopPatternCmd,
+    StopScanning,
+    Test,
+    VectorSubcommand,
+    VibrateCmd,
+    VibrateSubcommand,
+    VorzeA10CycloneCmd,
+  };
+  use ButtplugMessageSpecVersion::{Version0, Version1, Version2, Version3, Version4};
+
+  const ALL_VERSIONS: [ButtplugMessageSpecVersion; 5] =
+    [Version0, Version1, Version2, Version3, Version4];
+
+  /// Spec versions each server message exists in. The match has no wildcard, so adding a message
+  /// type fails to compile until it's added to the matrix.
+  fn server_message_versions(msg: &ButtplugServerMessage) -> &'static [ButtplugMessageSpecVersion] {
+    match msg {
+      ButtplugServerMessage::Ok(_)
+      | ButtplugServerMessage::Error(_)
+      | ButtplugServerMessage::ServerInfo(_)
+      | ButtplugServerMessage::DeviceList(_)
+      | ButtplugServerMessage::DeviceAdded(_)
+      | ButtplugServerMessage::DeviceRemoved(_)
+      | ButtplugServerMessage::ScanningFinished(_) => &ALL_VERSIONS,
+      ButtplugServerMessage::Log(_) => &[Version0, Version1, Version4],
+      ButtplugServerMessage::Test(_) => &[],
+      ButtplugServerMessage::RawReading(_) => &[Version2, Version3, Version4],
+      ButtplugServerMessage::BatteryLevelReading(_)
+      | ButtplugServerMessage::RSSILevelReading(_) => &[Version2],
+      ButtplugServerMessage::SensorReading(_) => &[Version3, Version4],
+      ButtplugServerMessage::GroupCommandResult(_)
+      | ButtplugServerMessage::ScanningStatus(_)
+      | ButtplugServerMessage::DeviceLatency(_) => &[Version4],
+    }
+  }
+
+  /// Same as [server_message_versions], for client messages.
+  fn client_message_versions(msg: &ButtplugClientMessage) -> &'static [ButtplugMessageSpecVersion] {
+    match msg {
+      ButtplugClientMessage::Ping(_)
+      | ButtplugClientMessage::RequestServerInfo(_)
+      | ButtplugClientMessage::StartScanning(_)
+      | ButtplugClientMessage::StopScanning(_)
+      | ButtplugClientMessage::RequestDeviceList(_)
+      | ButtplugClientMessage::StopAllDevices(_)
+      | ButtplugClientMessage::StopDeviceCmd(_) => &ALL_VERSIONS,
+      ButtplugClientMessage::RequestLog(_) => &[Version0, Version4],
+      ButtplugClientMessage::SingleMotorVibrateCmd(_)
+      | ButtplugClientMessage::FleshlightLaunchFW12Cmd(_)
+      | ButtplugClientMessage::LovenseCmd(_)
+      | ButtplugClientMessage::KiirooCmd(_)
+      | ButtplugClientMessage::VorzeA10CycloneCmd(_) => &[Version0, Version1],
+      ButtplugClientMessage::VibrateCmd(_)
+      | ButtplugClientMessage::LinearCmd(_)
+      | ButtplugClientMessage::RotateCmd(_) => &[Version1, Version2, Version3, Version4],
+      ButtplugClientMessage::RawWriteCmd(_)
+      | ButtplugClientMessage::RawReadCmd(_)
+      | ButtplugClientMessage::RawSubscribeCmd(_)
+      | ButtplugClientMessage::RawUnsubscribeCmd(_) => &[Version2, Version3, Version4],
+      ButtplugClientMessage::BatteryLevelCmd(_) | ButtplugClientMessage::RSSILevelCmd(_) => {
+        &[Version2]
+      }
+      ButtplugClientMessage::ScalarCmd(_)
+      | ButtplugClientMessage::SensorReadCmd(_)
+      | ButtplugClientMessage::SensorSubscribeCmd(_)
+      | ButtplugClientMessage::SensorUnsubscribeCmd(_) => &[Version3, Version4],
+      ButtplugClientMessage::PlayPatternCmd(_)
+      | ButtplugClientMessage::StopPatternCmd(_)
+      | ButtplugClientMessage::AddDeviceToGroup(_)
+      | ButtplugClientMessage::RemoveDeviceFromGroup(_)
+      | ButtplugClientMessage::GroupScalarCmd(_)
+      | ButtplugClientMessage::RequestScanningStatus(_)
+      | ButtplugClientMessage::RequestDeviceLatency(_) => &[Version4],
+    }
+  }
+
+  fn server_messages() -> Vec<ButtplugServerMessage> {
+    let device = DeviceMessageInfo::new(
+      0,
+      "Test Device",
+      &None,
+      &None,
+      &None,
+      ClientDeviceMessageAttributes::default(),
+    );
+    vec![
+      Ok::new(1).into(),
+      Error::new(ErrorCode::ErrorDevice, "Test error", None).into(),
+      Test::new("Test").into(),
+      Log::new(LogLevel::Info, "Test log").into(),
+      ServerInfo::new("Test Server", Version3, 0).into(),
+      DeviceList::new(vec![device.clone()]).into(),
+      DeviceAdded::new(
+        0,
+        device.device_name(),
+        &None,
+        &None,
+        &None,
+        device.device_messages(),
+      )
+      .into(),
+      DeviceRemoved::new(0).into(),
+      ScanningFinished::default().into(),
+      RawReading::new(0, Endpoint::Rx, vec![1, 2, 3]).into(),
+      SensorReading::new(0, 0, SensorType::Battery, vec![50]).into(),
+      BatteryLevelReading::new(0, 0.5).into(),
+      RSSILevelReading::new(0, -40).into(),
+      GroupCommandResult::default().into(),
+      ScanningStatus::new(vec![]).into(),
+      DeviceLatency::new(
+        0,
+        LatencyStageStats::default(),
+        LatencyStageStats::new(3, 10, 30, 20, 30),
+        LatencyStageStats::default(),
+        LatencyStageStats::default(),
+      )
+      .into(),
+    ]
+  }
+
+  fn client_messages() -> Vec<ButtplugClientMessage> {
+    vec![
+      Ping::default().into(),
+      RequestServerInfo::new("Test Client", Version3).into(),
+      StartScanning::default().into(),
+      StopScanning::default().into(),
+      RequestDeviceList::default().into(),
+      RequestScanningStatus::default().into(),
+      RequestDeviceLatency::new(0).into(),
+      StopAllDevices::default().into(),
+      StopDeviceCmd::new(0).into(),
+      RequestLog::new(LogLevel::Info).into(),
+      SingleMotorVibrateCmd::new(0, 0.5).into(),
+      FleshlightLaunchFW12Cmd::new(0, 50, 50).into(),
+      LovenseCmd::new(0, "Vibrate:10;").into(),
+      KiirooCmd::new(0, "1").into(),
+      VorzeA10CycloneCmd::new(0, 50, true).into(),
+      VibrateCmd::new(0, vec![VibrateSubcommand::new(0, 0.5)]).into(),
+      LinearCmd::new(0, vec![VectorSubcommand::new(0, 500, 0.5)]).into(),
+      RotateCmd::new(0, vec![RotationSubcommand::new(0, 0.5, true)]).into(),
+      RawWriteCmd::new(0, Endpoint::Tx, &[0], false).into(),
+      RawReadCmd::new(0, Endpoint::Rx, 0, 0).into(),
+      RawSubscribeCmd::new(0, Endpoint::Rx).into(),
+      RawUnsubscribeCmd::new(0, Endpoint::Rx).into(),
+      BatteryLevelCmd::new(0).into(),
+      RSSILevelCmd::new(0).into(),
+      ScalarCmd::new(
+        0,
+        vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+      SensorReadCmd::new(0, 0, SensorType::Battery).into(),
+      SensorSubscribeCmd::new(0, 0, SensorType::Battery).into(),
+      SensorUnsubscribeCmd::new(0, 0, SensorType::Battery).into(),
+      PlayPatternCmd::new(0, &[PatternKeyframe::new(0, 0.5)], false).into(),
+      StopPatternCmd::new(0).into(),
+      AddDeviceToGroup::new("Test Group", 0).into(),
+      RemoveDeviceFromGroup::new("Test Group", 0).into(),
+      GroupScalarCmd::new(
+        "Test Group",
+        vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+    ]
+  }
+
+  /// Converts to a spec version, returning whether the message made it through as itself rather
+  /// than being replaced with an error.
+  fn check_server_conversion<T>(msg: &ButtplugServerMessage) -> bool
+  where
+    T: ButtplugSpecServerMessage + std::fmt::Debug,
+  {
+    let converted = T::from_server_message(msg.clone());
+    assert_eq!(converted.id(), msg.id());
+    let is_error = format!("{:?}", converted).starts_with("Error(");
+    matches!(msg, ButtplugServerMessage::Error(_)) || !is_error
+  }
+
+  fn server_message_converts(
+    msg: &ButtplugServerMessage,
+    version: ButtplugMessageSpecVersion,
+  ) -> bool {
+    match version {
+      Version0 => check_server_conversion::<ButtplugSpecV0ServerMessage>(msg),
+      Version1 => check_server_conversion::<ButtplugSpecV1ServerMessage>(msg),
+      Version2 => check_server_conversion::<ButtplugSpecV2ServerMessage>(msg),
+      Version3 => check_server_conversion::<ButtplugSpecV3ServerMessage>(msg),
+      Version4 => check_server_conversion::<ButtplugSpecV4ServerMessage>(msg),
+    }
+  }
+
+  /// Converts to a spec version and back, returning whether the message survived the round trip.
+  fn client_message_converts(
+    msg: &ButtplugClientMessage,
+    version: ButtplugMessageSpecVersion,
+  ) -> bool {
+    let converted = match version {
+      Version0 => {
+        ButtplugSpecV0ClientMessage::try_from(msg.clone()).map(ButtplugClientMessage::from)
+      }
+      Version1 => {
+        ButtplugSpecV1ClientMessage::try_from(msg.clone()).map(ButtplugClientMessage::from)
+      }
+      Version2 => {
+        ButtplugSpecV2ClientMessage::try_from(msg.clone()).map(ButtplugClientMessage::from)
+      }
+      Version3 => {
+        ButtplugSpecV3ClientMessage::try_from(msg.clone()).map(ButtplugClientMessage::from)
+      }
+      Version4 => {
+        ButtplugSpecV4ClientMessage::try_from(msg.clone()).map(ButtplugClientMessage::from)
+      }
+    };
+    converted.is_ok_and(|converted| converted == *msg)
+  }
+
+  #[test]
+  fn test_server_message_downgrade_matrix() {
+    for mut msg in server_messages() {
+      // Ids have to survive conversion whether it works or not, or clients can't match errors up
+      // with the requests that caused them.
+      for id in [0, 1, 7, u32::MAX] {
+        msg.set_id(id);
+        for version in ALL_VERSIONS {
+          assert_eq!(
+            server_message_converts(&msg, version),
+            server_message_versions(&msg).contains(&version),
+            "{:?} in spec version {}",
+            msg,
+            version
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_spec_v3_strips_v4_additions() {
+    let actuator = |actuator_type| ClientGenericDeviceMessageAttributes::new("", 10, actuator_type);
+    let mut builder = ClientDeviceMessageAttributesBuilder::default();
+    builder.scalar_cmd(&[
+      actuator(ActuatorType::Vibrate),
+      actuator(ActuatorType::Heater),
+      actuator(ActuatorType::Vibrate),
+    ]);
+    let attrs = builder.finish();
+    let device_added = DeviceAdded::new(
+      0,
+      "Test Device",
+      &None,
+      &None,
+      &Some(DeviceConnectionInfo::new("btle", "AA:BB", None)),
+      &attrs,
+    );
+    let ButtplugSpecV3ServerMessage::DeviceAdded(v3) =
+      ButtplugSpecV3ServerMessage::from_server_message(device_added.clone().into())
+    else {
+      panic!("DeviceAdded should exist in spec v3");
+    };
+    assert!(v3.device_connection_info().is_none());
+    // Everything from the heater on is dropped, so the vibrator after it can't be sent commands
+    // meant for the heater's index.
+    let scalars = v3
+      .device_messages()
+      .scalar_cmd()
+      .as_ref()
+      .expect("Test, assuming infallible.");
+    assert_eq!(scalars.len(), 1);
+    assert_eq!(*scalars[0].actuator_type(), ActuatorType::Vibrate);
+    assert_eq!(
+      ButtplugSpecV4ServerMessage::from_server_message(device_added.clone().into()),
+      ButtplugSpecV4ServerMessage::DeviceAdded(device_added)
+    );
+
+    let mut server_info = ServerInfo::new("Test Server", Version3, 0);
+    server_info.set_server_time(Some(100));
+    let ButtplugSpecV3ServerMessage::ServerInfo(v3) =
+      ButtplugSpecV3ServerMessage::from_server_message(server_info.into())
+    else {
+      panic!("ServerInfo should exist in spec v3");
+    };
+    assert_eq!(v3.server_time(), None);
+
+    let removed = DeviceRemoved::new_with_reason(0, DeviceRemovedReason::PoweredOff, true);
+    assert_eq!(
+      ButtplugSpecV3ServerMessage::from_server_message(removed.into()),
+      ButtplugSpecV3ServerMessage::DeviceRemoved(DeviceRemoved::new(0))
+    );
+  }
+
+  #[test]
+  fn test_client_message_upgrade_matrix() {
+    for msg in client_messages() {
+      for version in ALL_VERSIONS {
+        assert_eq!(
+          client_message_converts(&msg, version),
+          client_message_versions(&msg).contains(&version),
+          "{:?} in spec version {}",
+          msg,
+          version
+        );
+      }
+    }
+  }
+
+  #[cfg(feature = "serialize-json")]
+  #[test]
+  fn test_serializers_only_send_known_message_types() {
+    use crate::core::message::serializer::{
+      ButtplugMessageSerializer, ButtplugSerializedMessage, ButtplugServerJSONSerializer,
+    };
+
+    for version in ALL_VERSIONS {
+      let serializer = ButtplugServerJSONSerializer::default();
+      serializer.force_message_version(&version);
+      for msg in server_messages() {
+        let ButtplugSerializedMessage::Text(json) =
+          serializer.serialize(std::slice::from_ref(&msg))
+        else {
+          panic!("JSON serializer should always produce text");
+        };
+        let value: serde_json::Value =
+          serde_json::from_str(&json).expect("Test, assuming infallible.");
+        let sent_type = value[0]
+          .as_object()
+          .and_then(|obj| obj.keys().next().cloned())
+          .expect("Test, assuming infallible.");
+        let msg_type = format!("{:?}", msg);
+        let msg_type = &msg_type[..msg_type.find('(').expect("Test, assuming infallible.")];
+        if server_message_versions(&msg).contains(&version) {
+          assert_eq!(sent_type, msg_type);
+        } else {
+          assert_eq!(
+            sent_type, "Error",
+            "{} in spec version {}",
+            msg_type, version
+          );
+        }
+      }
+    }
+  }
+}
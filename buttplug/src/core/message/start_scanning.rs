@@ -6,19 +6,90 @@
 // for full license information.
 
 use super::*;
+use getset::Getters;
 #[cfg(feature = "serialize-json")]
 use serde::{Deserialize, Serialize};
 
+/// Limits a scan to devices matching all of the given lists. Empty lists match everything, so a
+/// filter with only addresses set will look for those devices, whatever protocol they use.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Getters)]
+#[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
+#[getset(get = "pub")]
+pub struct ScanFilter {
+  /// Protocol names, as used in the device configuration file (lovense, wevibe, etc...)
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Protocols", default, skip_serializing_if = "Vec::is_empty")
+  )]
+  protocols: Vec<String>,
+  /// Transport names, as used in the device configuration file (btle, serial, hid, etc...)
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Transports", default, skip_serializing_if = "Vec::is_empty")
+  )]
+  transports: Vec<String>,
+  /// Hardware addresses, as reported in DeviceConnectionInfo. Compared case insensitively.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Addresses", default, skip_serializing_if = "Vec::is_empty")
+  )]
+  addresses: Vec<String>,
+}
+
+impl ScanFilter {
+  pub fn new(protocols: &[&str], transports: &[&str], addresses: &[&str]) -> Self {
+    let to_owned = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+    Self {
+      protocols: to_owned(protocols),
+      transports: to_owned(transports),
+      addresses: to_owned(addresses),
+    }
+  }
+
+  pub fn matches_protocol(&self, protocol: &str) -> bool {
+    self.protocols.is_empty() || self.protocols.iter().any(|p| p == protocol)
+  }
+
+  pub fn matches_transport(&self, transport: &str) -> bool {
+    self.transports.is_empty() || self.transports.iter().any(|t| t == transport)
+  }
+
+  pub fn matches_address(&self, address: &str) -> bool {
+    self.addresses.is_empty()
+      || self
+        .addresses
+        .iter()
+        .any(|a| a.eq_ignore_ascii_case(address))
+  }
+}
+
 #[derive(Debug, ButtplugMessage, ButtplugMessageFinalizer, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serialize-json", derive(Serialize, Deserialize))]
 pub struct StartScanning {
   #[cfg_attr(feature = "serialize-json", serde(rename = "Id"))]
   id: u32,
+  /// Only look for matching devices. Left out of the message when unset, so unfiltered scans still
+  /// work with servers that don't know about filters.
+  #[cfg_attr(
+    feature = "serialize-json",
+    serde(rename = "Filter", default, skip_serializing_if = "Option::is_none")
+  )]
+  filter: Option<ScanFilter>,
+}
+
+impl StartScanning {
+  pub fn new(filter: Option<ScanFilter>) -> Self {
+    Self { id: 1, filter }
+  }
+
+  pub fn filter(&self) -> &Option<ScanFilter> {
+    &self.filter
+  }
 }
 
 impl Default for StartScanning {
   fn default() -> Self {
-    Self { id: 1 }
+    Self::new(None)
   }
 }
 
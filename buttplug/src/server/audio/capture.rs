@@ -0,0 +1,118 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{AudioInput, AudioReactiveError};
+use cpal::{
+  traits::{DeviceTrait, HostTrait, StreamTrait},
+  SampleFormat,
+  Stream,
+};
+use std::{sync::mpsc, thread, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// How often the capture thread checks if it should stop.
+const CAPTURE_STOP_POLL_MS: u64 = 100;
+
+fn open_stream(input: AudioInput) -> Result<Stream, AudioReactiveError> {
+  let device = cpal::default_host()
+    .default_input_device()
+    .ok_or_else(|| AudioReactiveError::CaptureError("No default audio input device".to_owned()))?;
+  let supported_config = device
+    .default_input_config()
+    .map_err(|err| AudioReactiveError::CaptureError(err.to_string()))?;
+  let sample_format = supported_config.sample_format();
+  let config = supported_config.config();
+  let sample_rate = config.sample_rate.0;
+  let channels = config.channels;
+  info!(
+    "Capturing audio from {} ({} channels at {}hz, {:?})",
+    device
+      .name()
+      .unwrap_or_else(|_| "unknown device".to_owned()),
+    channels,
+    sample_rate,
+    sample_format
+  );
+  let on_error = |err: cpal::StreamError| error!("Audio capture error: {}", err);
+  // Errors from pushing only happen once processing has stopped, at which point the capture
+  // thread is about to stop too.
+  let stream = match sample_format {
+    SampleFormat::F32 => device.build_input_stream(
+      &config,
+      move |data: &[f32], _: &cpal::InputCallbackInfo| {
+        let _ = input.push(data, sample_rate, channels);
+      },
+      on_error,
+      None,
+    ),
+    SampleFormat::I16 => device.build_input_stream(
+      &config,
+      move |data: &[i16], _: &cpal::InputCallbackInfo| {
+        let _ = input.push_i16(data, sample_rate, channels);
+      },
+      on_error,
+      None,
+    ),
+    SampleFormat::U16 => device.build_input_stream(
+      &config,
+      move |data: &[u16], _: &cpal::InputCallbackInfo| {
+        let samples: Vec<i16> = data
+          .iter()
+          .map(|sample| (*sample as i32 - 32768) as i16)
+          .collect();
+        let _ = input.push_i16(&samples, sample_rate, channels);
+      },
+      on_error,
+      None,
+    ),
+    format => {
+      return Err(AudioReactiveError::CaptureError(format!(
+        "Unsupported sample format {:?}",
+        format
+      )))
+    }
+  }
+  .map_err(|err| AudioReactiveError::CaptureError(err.to_string()))?;
+  stream
+    .play()
+    .map_err(|err| AudioReactiveError::CaptureError(err.to_string()))?;
+  Ok(stream)
+}
+
+/// Capture the default input device into input until the token is cancelled.
+///
+/// Streams can't be moved between threads on every platform, so the stream lives on its own
+/// thread. Opening is waited on, so errors are returned to the caller.
+pub(super) fn start_capture(
+  input: AudioInput,
+  token: CancellationToken,
+) -> Result<(), AudioReactiveError> {
+  let (result_sender, result_receiver) = mpsc::channel();
+  thread::Builder::new()
+    .name("buttplug-audio-capture".to_owned())
+    .spawn(move || {
+      let stream = match open_stream(input) {
+        Ok(stream) => {
+          let _ = result_sender.send(Ok(()));
+          stream
+        }
+        Err(err) => {
+          let _ = result_sender.send(Err(err));
+          return;
+        }
+      };
+      while !token.is_cancelled() {
+        thread::sleep(Duration::from_millis(CAPTURE_STOP_POLL_MS));
+      }
+      drop(stream);
+      debug!("Audio capture stopped.");
+    })
+    .map_err(|err| AudioReactiveError::CaptureError(err.to_string()))?;
+  result_receiver
+    .recv()
+    .map_err(|_| AudioReactiveError::CaptureError("Capture thread exited".to_owned()))?
+}
@@ -0,0 +1,129 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::AudioReactiveMode;
+
+/// Time constant for the running loudness average that beats are compared against, in seconds.
+const BEAT_AVERAGE_SECONDS: f64 = 1.0;
+/// How much louder than the running average a block has to be to count as a beat.
+const BEAT_THRESHOLD: f64 = 1.4;
+/// Blocks quieter than this never count as beats, so noise in near silence doesn't trigger them.
+const BEAT_MIN_LEVEL: f64 = 0.02;
+
+/// Coefficient for moving a one pole filter towards its target over a time step.
+fn smoothing_coefficient(time_constant_seconds: f64, step_seconds: f64) -> f64 {
+  if time_constant_seconds <= 0.0 {
+    1.0
+  } else {
+    1.0 - (-step_seconds / time_constant_seconds).exp()
+  }
+}
+
+/// Turns blocks of mono samples into a smoothed 0.0-1.0 level.
+pub(super) struct EnvelopeFollower {
+  mode: AudioReactiveMode,
+  gain: f64,
+  attack_seconds: f64,
+  release_seconds: f64,
+  /// Smoothed RMS loudness.
+  loudness: f64,
+  /// Long term loudness, for beat detection.
+  average: f64,
+  /// Decaying pulse started by each beat.
+  beat: f64,
+}
+
+impl EnvelopeFollower {
+  pub fn new(mode: AudioReactiveMode, gain: f64, attack_ms: u32, release_ms: u32) -> Self {
+    Self {
+      mode,
+      gain,
+      attack_seconds: attack_ms as f64 / 1000.0,
+      release_seconds: release_ms as f64 / 1000.0,
+      loudness: 0.0,
+      average: 0.0,
+      beat: 0.0,
+    }
+  }
+
+  /// Feed a block of samples, returning the level after it.
+  pub fn process(&mut self, samples: &[f32], sample_rate: u32) -> f64 {
+    if samples.is_empty() || sample_rate == 0 {
+      return self.level();
+    }
+    let step_seconds = samples.len() as f64 / sample_rate as f64;
+    let rms = (samples
+      .iter()
+      .map(|sample| (*sample as f64).powi(2))
+      .sum::<f64>()
+      / samples.len() as f64)
+      .sqrt();
+
+    let time_constant = if rms > self.loudness {
+      self.attack_seconds
+    } else {
+      self.release_seconds
+    };
+    self.loudness += (rms - self.loudness) * smoothing_coefficient(time_constant, step_seconds);
+
+    if rms > BEAT_MIN_LEVEL && rms > self.average * BEAT_THRESHOLD {
+      self.beat = 1.0;
+    } else {
+      self.beat -= self.beat * smoothing_coefficient(self.release_seconds, step_seconds);
+    }
+    self.average +=
+      (rms - self.average) * smoothing_coefficient(BEAT_AVERAGE_SECONDS, step_seconds);
+    self.level()
+  }
+
+  /// Let the level fall off as if silence was received for a time.
+  pub fn decay(&mut self, seconds: f64) -> f64 {
+    let coefficient = smoothing_coefficient(self.release_seconds, seconds);
+    self.loudness -= self.loudness * coefficient;
+    self.beat -= self.beat * coefficient;
+    self.average -= self.average * smoothing_coefficient(BEAT_AVERAGE_SECONDS, seconds);
+    self.level()
+  }
+
+  pub fn level(&self) -> f64 {
+    let value = match self.mode {
+      AudioReactiveMode::Loudness => self.loudness,
+      AudioReactiveMode::Beat => self.beat,
+    };
+    (value * self.gain).clamp(0.0, 1.0)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{super::AudioReactiveMode, EnvelopeFollower};
+
+  #[test]
+  pub fn test_loudness_envelope() {
+    let mut follower = EnvelopeFollower::new(AudioReactiveMode::Loudness, 2.0, 0, 100);
+    assert_eq!(follower.process(&[0.25; 480], 48000), 0.5);
+    // Release is smoothed, so the level falls off instead of dropping straight to 0.
+    let released = follower.process(&[0.0; 480], 48000);
+    assert!(released > 0.4 && released < 0.5);
+    assert!(follower.decay(1.0) < 0.01);
+  }
+
+  #[test]
+  pub fn test_beat_envelope() {
+    let mut follower = EnvelopeFollower::new(AudioReactiveMode::Beat, 1.0, 0, 100);
+    assert_eq!(follower.process(&[0.5; 480], 48000), 1.0);
+    // Holding the same level stops counting as a beat once the average catches up.
+    for _ in 0..200 {
+      follower.process(&[0.5; 480], 48000);
+    }
+    assert!(follower.level() < 0.01);
+    assert_eq!(follower.process(&[1.0; 480], 48000), 1.0);
+    // Quiet noise never counts.
+    let mut quiet = EnvelopeFollower::new(AudioReactiveMode::Beat, 1.0, 0, 100);
+    assert_eq!(quiet.process(&[0.01; 480], 48000), 0.0);
+  }
+}
@@ -0,0 +1,421 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Audio reactive vibration
+//!
+//! A "vibrate to music" mode, run in the server so levels don't have to make a round trip through
+//! a client. The server follows either the loudness of an audio stream, or pulses on its beats, and
+//! sets the scalar actuators of selected devices to match.
+//!
+//! Audio can come from two places:
+//!
+//! - PCM samples pushed through an [AudioInput], which is handed out by
+//!   [ButtplugServer::audio_input](crate::server::ButtplugServer::audio_input).
+//! - The default audio input device of the system, if `capture` is set. Capturing needs the
+//!   `audio-capture` feature. To follow system audio output, set the default input to a loopback
+//!   or monitor device.
+//!
+//! The subsystem is configured with an [AudioReactiveConfig], which can be built in code or loaded
+//! from JSON:
+//!
+//! ```json
+//! {
+//!   "mode": "loudness",
+//!   "gain": 2.0,
+//!   "attack-ms": 10,
+//!   "release-ms": 250,
+//!   "capture": true,
+//!   "targets": [
+//!     {
+//!       "device-name": "Lovense Hush",
+//!       "actuator-index": 0
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Gain is applied to the smoothed level before clamping it to 0.0-1.0. Attack and release set how
+//! quickly the level follows the audio when it gets louder and quieter. Each target selects devices
+//! by name and/or index, and optionally a single scalar actuator. With no targets, all scalar
+//! actuators on all devices are driven. If audio stops arriving, the level falls off as if the
+//! stream had gone silent.
+//!
+//! Stopping all devices, either through StopAllDevices or the server stopping them for a client
+//! that went away, pauses audio reactive output. It picks up again with the next output command
+//! sent to a device, or a call to
+//! [ServerDeviceManager::resume_server_output](crate::server::device::ServerDeviceManager::resume_server_output).
+
+#[cfg(feature = "audio-capture")]
+mod capture;
+mod envelope;
+
+use super::device::ServerDeviceManager;
+use crate::{
+  core::message::{ScalarCmd, ScalarSubcommand},
+  util::{async_manager, sleep},
+};
+use envelope::EnvelopeFollower;
+use futures::{select, FutureExt};
+use getset::{CopyGetters, Getters, Setters};
+use instant::Instant;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Minimum time between device updates. Audio blocks usually arrive faster than devices can take
+/// commands.
+pub const AUDIO_REACTIVE_UPDATE_INTERVAL_MS: u64 = 20;
+/// Smallest level change that is sent to devices. Levels below this are treated as 0.
+const LEVEL_EPSILON: f64 = 0.01;
+/// How long without audio before the stream is treated as silent.
+const AUDIO_TIMEOUT_MS: u64 = 100;
+/// Number of audio blocks that can be queued. If processing falls behind, new blocks are dropped
+/// instead of adding latency.
+const AUDIO_BLOCK_QUEUE_SIZE: usize = 16;
+
+fn default_gain() -> f64 {
+  1.0
+}
+
+fn default_attack_ms() -> u32 {
+  10
+}
+
+fn default_release_ms() -> u32 {
+  250
+}
+
+#[derive(Debug, Error)]
+pub enum AudioReactiveError {
+  #[error("Audio capture requires the audio-capture feature")]
+  CaptureNotAvailable,
+  #[error("Cannot capture audio: {0}")]
+  CaptureError(String),
+  #[error("Audio reactive processing has stopped")]
+  Stopped,
+}
+
+/// What part of the audio drives the devices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioReactiveMode {
+  /// Follow the smoothed loudness of the audio.
+  #[default]
+  Loudness,
+  /// Pulse to full level on each beat, falling off at the release rate.
+  Beat,
+}
+
+/// Devices and actuators driven by the audio level.
+#[derive(
+  Debug, Clone, Default, PartialEq, Serialize, Deserialize, Getters, CopyGetters, Setters,
+)]
+#[serde(rename_all = "kebab-case")]
+pub struct AudioReactiveTarget {
+  /// Only drive devices with this name.
+  #[getset(get = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  device_name: Option<String>,
+  /// Only drive the device at this index.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  device_index: Option<u32>,
+  /// Only set this scalar actuator. If not set, all scalar actuators on the device are set.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  actuator_index: Option<u32>,
+}
+
+impl AudioReactiveTarget {
+  fn selects_device(&self, index: u32, name: &str) -> bool {
+    self
+      .device_index
+      .is_none_or(|device_index| device_index == index)
+      && self
+        .device_name
+        .as_ref()
+        .is_none_or(|device_name| device_name == name)
+  }
+
+  fn selects_actuator(&self, index: u32) -> bool {
+    self
+      .actuator_index
+      .is_none_or(|actuator_index| actuator_index == index)
+  }
+}
+
+/// Audio reactive configuration. See the [module documentation](self) for the JSON format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters, Setters)]
+#[serde(rename_all = "kebab-case")]
+pub struct AudioReactiveConfig {
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default)]
+  mode: AudioReactiveMode,
+  /// Multiplier for the audio level, applied before clamping to 0.0-1.0.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default = "default_gain")]
+  gain: f64,
+  /// Time for the level to rise towards louder audio, in milliseconds.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default = "default_attack_ms")]
+  attack_ms: u32,
+  /// Time for the level to fall towards quieter audio, in milliseconds.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default = "default_release_ms")]
+  release_ms: u32,
+  /// If true, capture the default audio input device of the system.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default)]
+  capture: bool,
+  #[getset(get = "pub")]
+  #[serde(default)]
+  targets: Vec<AudioReactiveTarget>,
+}
+
+impl Default for AudioReactiveConfig {
+  fn default() -> Self {
+    Self {
+      mode: AudioReactiveMode::default(),
+      gain: default_gain(),
+      attack_ms: default_attack_ms(),
+      release_ms: default_release_ms(),
+      capture: false,
+      targets: vec![],
+    }
+  }
+}
+
+impl AudioReactiveConfig {
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    serde_json::from_str(json)
+  }
+
+  pub fn add_target(&mut self, target: AudioReactiveTarget) -> &mut Self {
+    self.targets.push(target);
+    self
+  }
+}
+
+/// Interleaved samples, along with their format.
+struct AudioBlock {
+  sample_rate: u32,
+  channels: u16,
+  samples: Vec<f32>,
+}
+
+impl AudioBlock {
+  /// Average the channels of each frame down to a single sample.
+  fn to_mono(&self) -> Vec<f32> {
+    let channels = self.channels.max(1) as usize;
+    self
+      .samples
+      .chunks(channels)
+      .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+      .collect()
+  }
+}
+
+/// Handle for pushing PCM audio into the audio reactive subsystem.
+#[derive(Clone)]
+pub struct AudioInput {
+  sender: mpsc::Sender<AudioBlock>,
+}
+
+impl AudioInput {
+  /// Push interleaved floating point samples, nominally in the range -1.0-1.0. If processing is
+  /// behind, the samples are dropped to keep latency down.
+  pub fn push(
+    &self,
+    samples: &[f32],
+    sample_rate: u32,
+    channels: u16,
+  ) -> Result<(), AudioReactiveError> {
+    let block = AudioBlock {
+      sample_rate,
+      channels,
+      samples: samples.to_vec(),
+    };
+    match self.sender.try_send(block) {
+      Ok(_) | Err(mpsc::error::TrySendError::Full(_)) => Ok(()),
+      Err(mpsc::error::TrySendError::Closed(_)) => Err(AudioReactiveError::Stopped),
+    }
+  }
+
+  /// Push interleaved 16-bit samples.
+  pub fn push_i16(
+    &self,
+    samples: &[i16],
+    sample_rate: u32,
+    channels: u16,
+  ) -> Result<(), AudioReactiveError> {
+    let samples: Vec<f32> = samples
+      .iter()
+      .map(|sample| *sample as f32 / i16::MAX as f32)
+      .collect();
+    self.push(&samples, sample_rate, channels)
+  }
+}
+
+/// Level last sent to a device, and whether that update is still being written.
+#[derive(Default)]
+struct DeviceUpdate {
+  level: f64,
+  pending: Arc<AtomicBool>,
+}
+
+fn update_devices(
+  level: f64,
+  targets: &[AudioReactiveTarget],
+  device_manager: &ServerDeviceManager,
+  updates: &mut HashMap<u32, DeviceUpdate>,
+) {
+  let all_devices = [AudioReactiveTarget::default()];
+  let targets = if targets.is_empty() {
+    &all_devices[..]
+  } else {
+    targets
+  };
+  updates.retain(|index, _| device_manager.devices().contains_key(index));
+  for device in device_manager.devices().iter() {
+    let index = *device.key();
+    let name = device.name();
+    let selecting: Vec<&AudioReactiveTarget> = targets
+      .iter()
+      .filter(|target| target.selects_device(index, &name))
+      .collect();
+    if selecting.is_empty() {
+      continue;
+    }
+    let update = updates.entry(index).or_default();
+    if (level - update.level).abs() < LEVEL_EPSILON {
+      continue;
+    }
+    // Devices that can't keep up get the latest level once their last update is written, instead
+    // of a growing backlog of writes.
+    if update.pending.load(Ordering::SeqCst) {
+      continue;
+    }
+    let Some(attrs) = device.message_attributes().scalar_cmd().clone() else {
+      continue;
+    };
+    let subcommands: Vec<ScalarSubcommand> = attrs
+      .iter()
+      .enumerate()
+      .filter(|(actuator_index, _)| {
+        selecting
+          .iter()
+          .any(|target| target.selects_actuator(*actuator_index as u32))
+      })
+      .map(|(actuator_index, attr)| {
+        ScalarSubcommand::new(actuator_index as u32, level, *attr.actuator_type())
+      })
+      .collect();
+    if subcommands.is_empty() {
+      continue;
+    }
+    update.level = level;
+    update.pending.store(true, Ordering::SeqCst);
+    let pending = update.pending.clone();
+    let fut = device.parse_message(ScalarCmd::new(index, subcommands).into());
+    async_manager::spawn(async move {
+      if let Err(err) = fut.await {
+        error!(
+          "Audio reactive could not update device {}: {:?}",
+          index, err
+        );
+      }
+      pending.store(false, Ordering::SeqCst);
+    });
+  }
+}
+
+/// Running audio reactive processing. Stops, along with any capture, when dropped.
+pub(crate) struct AudioReactive {
+  input: AudioInput,
+  cancellation_token: CancellationToken,
+}
+
+impl AudioReactive {
+  /// Start processing audio, and start capturing if the config asks for it. Capture errors are
+  /// reported here instead of from the capture thread.
+  pub(crate) fn start(
+    config: &AudioReactiveConfig,
+    device_manager: Arc<ServerDeviceManager>,
+  ) -> Result<Self, AudioReactiveError> {
+    let (sender, mut receiver) = mpsc::channel(AUDIO_BLOCK_QUEUE_SIZE);
+    let input = AudioInput { sender };
+    let cancellation_token = CancellationToken::new();
+    if config.capture() {
+      #[cfg(feature = "audio-capture")]
+      capture::start_capture(input.clone(), cancellation_token.child_token())?;
+      #[cfg(not(feature = "audio-capture"))]
+      return Err(AudioReactiveError::CaptureNotAvailable);
+    }
+
+    let child_token = cancellation_token.child_token();
+    let mut follower = EnvelopeFollower::new(
+      config.mode(),
+      config.gain(),
+      config.attack_ms(),
+      config.release_ms(),
+    );
+    let targets = config.targets().clone();
+    let update_interval = Duration::from_millis(AUDIO_REACTIVE_UPDATE_INTERVAL_MS);
+    async_manager::spawn(async move {
+      let mut updates = HashMap::new();
+      let mut last_update = Instant::now();
+      loop {
+        let level = select! {
+          _ = child_token.cancelled().fuse() => break,
+          block = receiver.recv().fuse() => match block {
+            Some(block) => follower.process(&block.to_mono(), block.sample_rate),
+            None => break,
+          },
+          _ = sleep(Duration::from_millis(AUDIO_TIMEOUT_MS)).fuse() => {
+            follower.decay(AUDIO_TIMEOUT_MS as f64 / 1000.0)
+          }
+        };
+        // The envelope only approaches 0, so snap small levels to it to let devices stop.
+        let level = if level < LEVEL_EPSILON { 0.0 } else { level };
+        if device_manager.is_server_output_paused() {
+          // Devices were stopped, so whatever level they had is gone.
+          updates.clear();
+          continue;
+        }
+        if last_update.elapsed() >= update_interval {
+          trace!("Audio reactive level {}", level);
+          update_devices(level, &targets, &device_manager, &mut updates);
+          last_update = Instant::now();
+        }
+      }
+      debug!("Audio reactive processing stopped.");
+    });
+    Ok(Self {
+      input,
+      cancellation_token,
+    })
+  }
+
+  pub(crate) fn input(&self) -> AudioInput {
+    self.input.clone()
+  }
+}
+
+impl Drop for AudioReactive {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
@@ -0,0 +1,263 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side battery polling
+//!
+//! Most devices can only have their battery level read, not subscribed to, so apps that show
+//! battery levels end up sending SensorReadCmd to every device every so often. With a
+//! [BatteryPollingPolicy] set, the server does that polling instead: battery sensors that can be read
+//! are also listed in the device's SensorSubscribeCmd attributes, and while a client is subscribed,
+//! the server reads the battery every polling interval and sends the level out as a SensorReading,
+//! like any other sensor subscription.
+//!
+//! When a polled level drops below the low battery threshold, an Error message with
+//! [DeviceBatteryLow](crate::core::errors::ButtplugDeviceError::DeviceBatteryLow) is also sent to
+//! the client. This only happens once per drop, the warning is sent again if the battery charges
+//! back above the threshold and then drops below it.
+//!
+//! Polling intervals can be set globally and overridden per device, by device address. Polling uses
+//! the same read commands a client would send, so devices that need a radio round trip per read
+//! should be given longer intervals.
+
+use crate::{
+  core::{
+    errors::ButtplugError,
+    message::{SensorReading, SensorType},
+  },
+  util,
+};
+use futures::{future::BoxFuture, select, FutureExt};
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::{broadcast, watch};
+
+/// Time between battery reads for devices without an interval of their own.
+pub const DEFAULT_BATTERY_POLLING_INTERVAL: Duration = Duration::from_secs(60);
+/// Battery level, in percent, below which the low battery warning is sent.
+pub const DEFAULT_LOW_BATTERY_THRESHOLD: u8 = 20;
+
+/// Battery polling settings for all devices connected to the server.
+#[derive(Debug, Clone)]
+pub struct BatteryPollingPolicy {
+  interval: Duration,
+  low_battery_threshold: u8,
+  devices: HashMap<String, Duration>,
+}
+
+impl Default for BatteryPollingPolicy {
+  fn default() -> Self {
+    Self {
+      interval: DEFAULT_BATTERY_POLLING_INTERVAL,
+      low_battery_threshold: DEFAULT_LOW_BATTERY_THRESHOLD,
+      devices: HashMap::new(),
+    }
+  }
+}
+
+impl BatteryPollingPolicy {
+  /// Polling interval for every device without its own interval.
+  pub fn global_interval(&mut self, interval: Duration) -> &mut Self {
+    self.interval = interval;
+    self
+  }
+
+  /// Polling interval for the device with the given address.
+  pub fn device_interval(&mut self, address: &str, interval: Duration) -> &mut Self {
+    self.devices.insert(address.to_owned(), interval);
+    self
+  }
+
+  /// Battery level, in percent, below which the low battery warning is sent. Set to 0 to never send
+  /// warnings.
+  pub fn low_battery_threshold(&mut self, percent: u8) -> &mut Self {
+    self.low_battery_threshold = percent.min(100);
+    self
+  }
+
+  /// Polling interval that applies to the device with the given address.
+  pub fn interval_for(&self, address: &str) -> Duration {
+    self.devices.get(address).copied().unwrap_or(self.interval)
+  }
+}
+
+/// Updates from a device's battery monitor.
+#[derive(Debug, Clone)]
+pub(super) enum BatteryEvent {
+  /// Polled battery level, to be sent to subscribed clients.
+  Reading(SensorReading),
+  /// Battery level, in percent, that just dropped below the low battery threshold.
+  Low(u8),
+}
+
+/// Battery polling state of a connected device.
+pub(super) struct DeviceBatteryMonitor {
+  interval: Duration,
+  low_battery_threshold: u8,
+  /// Index of the polled battery sensor in the device's SensorSubscribeCmd attributes.
+  sensor_index: u32,
+  /// True while a client is subscribed. Dropping this ends the polling task.
+  subscribed: watch::Sender<bool>,
+  event_sender: broadcast::Sender<BatteryEvent>,
+}
+
+impl DeviceBatteryMonitor {
+  pub(super) fn new(policy: &BatteryPollingPolicy, address: &str, sensor_index: u32) -> Self {
+    Self {
+      interval: policy.interval_for(address),
+      low_battery_threshold: policy.low_battery_threshold,
+      sensor_index,
+      subscribed: watch::channel(false).0,
+      event_sender: broadcast::channel(16).0,
+    }
+  }
+
+  pub(super) fn sensor_index(&self) -> u32 {
+    self.sensor_index
+  }
+
+  pub(super) fn set_subscribed(&self, subscribed: bool) {
+    self.subscribed.send_replace(subscribed);
+  }
+
+  pub(super) fn event_stream(&self) -> broadcast::Receiver<BatteryEvent> {
+    self.event_sender.subscribe()
+  }
+
+  /// Future that reads the battery, using the given function, every polling interval while a
+  /// client is subscribed. The function returns the reading along with the top of the sensor's
+  /// range, or None if the device is gone. Ends once the device is dropped.
+  pub(super) fn polling_task<F>(&self, read: F) -> BoxFuture<'static, ()>
+  where
+    F: Fn() -> Option<BoxFuture<'static, Result<(SensorReading, u32), ButtplugError>>>
+      + Send
+      + 'static,
+  {
+    let interval = self.interval;
+    let low_battery_threshold = self.low_battery_threshold;
+    let sensor_index = self.sensor_index;
+    let mut subscribed = self.subscribed.subscribe();
+    let event_sender = self.event_sender.clone();
+    async move {
+      let mut warned = false;
+      loop {
+        if subscribed.wait_for(|subscribed| *subscribed).await.is_err() {
+          return;
+        }
+        let Some(read_fut) = read() else {
+          return;
+        };
+        match read_fut.await {
+          Ok((reading, sensor_range_end)) => {
+            let level = reading.data().first().copied().unwrap_or_default();
+            let percent = battery_percent(level, sensor_range_end);
+            let _ = event_sender.send(BatteryEvent::Reading(SensorReading::new(
+              0,
+              sensor_index,
+              SensorType::Battery,
+              reading.data().clone(),
+            )));
+            if percent < low_battery_threshold {
+              if !warned {
+                warned = true;
+                let _ = event_sender.send(BatteryEvent::Low(percent));
+              }
+            } else {
+              warned = false;
+            }
+          }
+          Err(err) => warn!("Error polling battery level: {}", err),
+        }
+        select! {
+          _ = util::sleep(interval).fuse() => {}
+          result = subscribed.wait_for(|subscribed| !*subscribed).fuse() => {
+            if result.is_err() {
+              return;
+            }
+          }
+        }
+      }
+    }
+    .boxed()
+  }
+}
+
+/// Battery level in percent, from a reading in the sensor's range.
+fn battery_percent(level: i32, sensor_range_end: u32) -> u8 {
+  if sensor_range_end == 0 {
+    return 0;
+  }
+  (level.max(0) as u64 * 100 / sensor_range_end as u64).min(100) as u8
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::util::async_manager;
+  use futures::future;
+  use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  };
+
+  #[test]
+  fn test_battery_polling_policy() {
+    let mut policy = BatteryPollingPolicy::default();
+    policy
+      .global_interval(Duration::from_secs(30))
+      .device_interval("slow", Duration::from_secs(300));
+    assert_eq!(policy.interval_for("slow"), Duration::from_secs(300));
+    assert_eq!(policy.interval_for("other"), Duration::from_secs(30));
+    assert_eq!(battery_percent(50, 100), 50);
+    assert_eq!(battery_percent(3, 4), 75);
+    assert_eq!(battery_percent(-1, 100), 0);
+  }
+
+  #[tokio::test]
+  async fn test_battery_polling_task() {
+    let mut policy = BatteryPollingPolicy::default();
+    policy.global_interval(Duration::from_millis(20));
+    let monitor = DeviceBatteryMonitor::new(&policy, "test", 1);
+    let mut events = monitor.event_stream();
+    // Levels go 30, 10, 5, 50, 10, so the battery drops below the threshold twice.
+    let levels = [30, 10, 5, 50, 10];
+    let reads = Arc::new(AtomicUsize::new(0));
+    let reads_clone = reads.clone();
+    async_manager::spawn(monitor.polling_task(move || {
+      let level = levels[reads_clone.fetch_add(1, Ordering::SeqCst) % levels.len()];
+      Some(
+        future::ready(Ok((
+          SensorReading::new(0, 0, SensorType::Battery, vec![level]),
+          100,
+        )))
+        .boxed(),
+      )
+    }));
+
+    // Nothing is read until a client subscribes.
+    util::sleep(Duration::from_millis(50)).await;
+    assert_eq!(reads.load(Ordering::SeqCst), 0);
+
+    monitor.set_subscribed(true);
+    let mut readings = vec![];
+    let mut warnings = vec![];
+    while readings.len() < levels.len() {
+      match events.recv().await.expect("Test, assuming infallible.") {
+        BatteryEvent::Reading(reading) => {
+          assert_eq!(reading.sensor_index(), 1);
+          readings.push(reading.data()[0]);
+        }
+        BatteryEvent::Low(percent) => warnings.push(percent),
+      }
+    }
+    monitor.set_subscribed(false);
+    assert_eq!(readings, levels);
+    // The last warning comes after the last reading.
+    if let BatteryEvent::Low(percent) = events.recv().await.expect("Test, assuming infallible.") {
+      warnings.push(percent);
+    }
+    assert_eq!(warnings, vec![10, 10]);
+  }
+}
@@ -0,0 +1,158 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Clock synchronized commands across devices
+//!
+//! Sending the same command to several toys at once rarely looks like "at once". Each radio link
+//! has its own latency, so devices visibly drift apart. To line them up, ScalarCmd, LinearCmd and
+//! RotateCmd can carry a `ScheduledTime`, in milliseconds on the server's [ServerClock]. The server
+//! sends the current clock time in ServerInfo during the handshake, so clients can work out what
+//! time it is on the server (see `ButtplugClient::server_time`).
+//!
+//! Instead of sending a scheduled command to the device as soon as it arrives, the device manager
+//! holds it until its scheduled time, minus the mean time the device has recently taken to carry
+//! out commands (see [latency](super::latency)). Slower devices get their commands earlier, so the
+//! commands take effect on all devices at close to the same time.
+//!
+//! Commands scheduled for a time that has already passed are sent right away. Commands can't be
+//! scheduled more than [MAX_SCHEDULE_AHEAD] in the future. A StopDeviceCmd/StopAllDevices drops
+//! any scheduled commands that haven't been sent yet. The reply to a scheduled command is sent once
+//! the command has been carried out.
+
+use super::ServerDevice;
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugMessageError},
+    message::{ButtplugDeviceCommandMessageUnion, ButtplugDeviceMessage},
+  },
+  server::ButtplugServerResultFuture,
+  util::sleep,
+};
+use dashmap::DashMap;
+use futures::{future, select, FutureExt};
+use instant::Instant;
+use std::{sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+
+/// Furthest in the future a command can be scheduled.
+pub const MAX_SCHEDULE_AHEAD: Duration = Duration::from_secs(60);
+
+/// Monotonic clock that scheduled command times are measured on, in milliseconds since the device
+/// manager was built.
+#[derive(Debug, Clone, Copy)]
+pub struct ServerClock {
+  start: Instant,
+}
+
+impl Default for ServerClock {
+  fn default() -> Self {
+    Self {
+      start: Instant::now(),
+    }
+  }
+}
+
+impl ServerClock {
+  /// Current clock time, in milliseconds.
+  pub fn now(&self) -> u64 {
+    self.start.elapsed().as_millis() as u64
+  }
+
+  /// Time left until the clock reaches a time, or zero if it already has.
+  pub fn until(&self, time: u64) -> Duration {
+    let target = self.start + Duration::from_millis(time);
+    let now = Instant::now();
+    if target > now {
+      target - now
+    } else {
+      Duration::ZERO
+    }
+  }
+}
+
+/// Holds scheduled commands until it's time to send them to their devices.
+#[derive(Default)]
+pub(super) struct CommandScheduler {
+  clock: ServerClock,
+  /// Cancelled when a device is stopped, dropping its pending commands.
+  pending: DashMap<u32, CancellationToken>,
+}
+
+impl CommandScheduler {
+  pub fn clock(&self) -> ServerClock {
+    self.clock
+  }
+
+  /// Send a command to a device at its scheduled time, leading it by the device's mean command
+  /// latency.
+  pub fn schedule(
+    &self,
+    device: Arc<ServerDevice>,
+    msg: ButtplugDeviceCommandMessageUnion,
+    scheduled_time: u64,
+  ) -> ButtplugServerResultFuture {
+    let until = self.clock.until(scheduled_time);
+    if until > MAX_SCHEDULE_AHEAD {
+      return future::ready(Err(
+        ButtplugMessageError::InvalidMessageContents(format!(
+          "Commands cannot be scheduled more than {}ms ahead, got {}ms",
+          MAX_SCHEDULE_AHEAD.as_millis(),
+          until.as_millis()
+        ))
+        .into(),
+      ))
+      .boxed();
+    }
+    let delay = until.saturating_sub(device.latency_stats().total().mean());
+    if delay.is_zero() {
+      return device.parse_message(msg);
+    }
+    let device_index = msg.device_index();
+    let token = self
+      .pending
+      .entry(device_index)
+      .or_default()
+      .clone();
+    async move {
+      select! {
+        _ = token.cancelled().fuse() => {
+          Err(ButtplugDeviceError::ScheduledCommandCancelled(device_index).into())
+        }
+        _ = sleep(delay).fuse() => device.parse_message(msg).await,
+      }
+    }
+    .boxed()
+  }
+
+  /// Drop any commands scheduled for a device that haven't been sent yet.
+  pub fn cancel(&self, device_index: u32) {
+    if let Some((_, token)) = self.pending.remove(&device_index) {
+      token.cancel();
+    }
+  }
+
+  pub fn cancel_all(&self) {
+    for entry in self.pending.iter() {
+      entry.value().cancel();
+    }
+    self.pending.clear();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::ServerClock;
+  use std::time::Duration;
+
+  #[test]
+  fn test_server_clock_until() {
+    let clock = ServerClock::default();
+    assert_eq!(clock.until(0), Duration::ZERO);
+    let until = clock.until(clock.now() + 1000);
+    assert!(until > Duration::from_millis(900) && until <= Duration::from_millis(1000));
+  }
+}
@@ -0,0 +1,267 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Scalar command rate limiting
+//!
+//! Some hardware (BLE toys especially) will drop commands, or fall further and further behind, if
+//! they're sent updates faster than they can process them. Since we only ever care about the most
+//! recent value for a scalar actuator, we can coalesce updates that come in too quickly, and only
+//! send the latest value once the device's minimum update interval has passed. Everyone whose
+//! update went into a coalesced write gets the result of that write.
+
+use crate::core::{errors::ButtplugError, message::ActuatorType};
+use instant::Instant;
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// Scalar values as produced by the GenericCommandManager, one entry per actuator, with None for
+/// actuators that don't need an update.
+pub(super) type ScalarCommandSet = Vec<Option<(ActuatorType, u32)>>;
+
+/// Result of writing coalesced updates to the hardware.
+pub(super) type FlushResult = Result<(), ButtplugError>;
+
+/// What the caller should do with a scalar update after submitting it to the coalescer.
+#[derive(Debug)]
+pub(super) enum CoalescerAction {
+  /// Enough time has passed since the last write, send these commands now.
+  Send(ScalarCommandSet),
+  /// The update has been merged into the pending set, and the result of writing it will be sent on
+  /// the receiver. If a duration is returned, no flush is scheduled yet and the caller should call
+  /// [ScalarCommandCoalescer::take_pending] after waiting that long.
+  Deferred(Option<Duration>, oneshot::Receiver<FlushResult>),
+}
+
+/// Updates waiting to be written, along with whoever is waiting on the result.
+pub(super) struct PendingScalarCommands {
+  commands: ScalarCommandSet,
+  waiters: Vec<oneshot::Sender<FlushResult>>,
+}
+
+impl PendingScalarCommands {
+  pub fn commands(&self) -> &ScalarCommandSet {
+    &self.commands
+  }
+
+  /// Send the result of writing the commands to everyone whose update went into them.
+  pub fn finish(self, result: FlushResult) {
+    for waiter in self.waiters {
+      let _ = waiter.send(result.clone());
+    }
+  }
+}
+
+#[derive(Default)]
+struct CoalescerState {
+  last_send: Option<Instant>,
+  pending: Option<PendingScalarCommands>,
+  flush_scheduled: bool,
+}
+
+/// Coalesces rapid scalar updates for a single device down to a maximum update frequency.
+#[derive(Clone)]
+pub(super) struct ScalarCommandCoalescer {
+  interval: Duration,
+  state: Arc<Mutex<CoalescerState>>,
+}
+
+impl ScalarCommandCoalescer {
+  pub fn new(interval: Duration) -> Self {
+    Self {
+      interval,
+      state: Arc::new(Mutex::new(CoalescerState::default())),
+    }
+  }
+
+  pub fn submit(&self, commands: ScalarCommandSet) -> CoalescerAction {
+    self.submit_at(commands, Instant::now())
+  }
+
+  fn submit_at(&self, commands: ScalarCommandSet, now: Instant) -> CoalescerAction {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Coalescer lock should never be poisoned");
+    let next_send = state.last_send.map(|last_send| last_send + self.interval);
+    match next_send {
+      Some(next_send) if next_send > now || state.flush_scheduled => {
+        let (sender, receiver) = oneshot::channel();
+        let pending = state.pending.get_or_insert_with(|| PendingScalarCommands {
+          commands: vec![],
+          waiters: vec![],
+        });
+        pending.commands = merge_commands(std::mem::take(&mut pending.commands), commands);
+        pending.waiters.push(sender);
+        if state.flush_scheduled {
+          CoalescerAction::Deferred(None, receiver)
+        } else {
+          state.flush_scheduled = true;
+          CoalescerAction::Deferred(Some(next_send.saturating_duration_since(now)), receiver)
+        }
+      }
+      _ => {
+        state.last_send = Some(now);
+        CoalescerAction::Send(commands)
+      }
+    }
+  }
+
+  /// Take whatever updates have built up since the last write, marking the flush as done. The
+  /// result of writing them needs to be passed to [PendingScalarCommands::finish].
+  pub fn take_pending(&self) -> Option<PendingScalarCommands> {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Coalescer lock should never be poisoned");
+    state.flush_scheduled = false;
+    let pending = state.pending.take();
+    if pending.is_some() {
+      state.last_send = Some(Instant::now());
+    }
+    pending
+  }
+
+  /// Drop any pending updates, and note that a write is going out now. Used when a stop command
+  /// bypasses the rate limit, so stale values don't get written after the stop. The dropped
+  /// updates count as handled.
+  pub fn clear_pending(&self) {
+    let pending = {
+      let mut state = self
+        .state
+        .lock()
+        .expect("Coalescer lock should never be poisoned");
+      state.last_send = Some(Instant::now());
+      state.pending.take()
+    };
+    if let Some(pending) = pending {
+      pending.finish(Ok(()));
+    }
+  }
+}
+
+/// Merge a newer set of scalar updates into an older one. Later values win, but actuators that the
+/// newer set doesn't touch keep their older pending value.
+fn merge_commands(older: ScalarCommandSet, newer: ScalarCommandSet) -> ScalarCommandSet {
+  let len = older.len().max(newer.len());
+  (0..len)
+    .map(|index| {
+      newer
+        .get(index)
+        .copied()
+        .flatten()
+        .or_else(|| older.get(index).copied().flatten())
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{merge_commands, CoalescerAction, ScalarCommandCoalescer};
+  use crate::core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{ActuatorType, Endpoint},
+  };
+  use instant::Instant;
+  use std::time::Duration;
+
+  #[test]
+  pub fn test_merge_commands() {
+    let older = vec![
+      Some((ActuatorType::Vibrate, 10)),
+      Some((ActuatorType::Vibrate, 20)),
+      None,
+    ];
+    let newer = vec![None, Some((ActuatorType::Vibrate, 30)), None];
+    assert_eq!(
+      merge_commands(older, newer),
+      vec![
+        Some((ActuatorType::Vibrate, 10)),
+        Some((ActuatorType::Vibrate, 30)),
+        None
+      ]
+    );
+  }
+
+  #[test]
+  pub fn test_coalescer_defers_rapid_updates() {
+    let coalescer = ScalarCommandCoalescer::new(Duration::from_millis(100));
+    let start = Instant::now();
+    let first = vec![Some((ActuatorType::Vibrate, 10))];
+    assert!(matches!(
+      coalescer.submit_at(first.clone(), start),
+      CoalescerAction::Send(commands) if commands == first
+    ));
+    assert!(matches!(
+      coalescer.submit_at(
+        vec![Some((ActuatorType::Vibrate, 20))],
+        start + Duration::from_millis(40)
+      ),
+      CoalescerAction::Deferred(Some(delay), _) if delay == Duration::from_millis(60)
+    ));
+    // A flush is already scheduled, so this just replaces the pending value.
+    assert!(matches!(
+      coalescer.submit_at(
+        vec![Some((ActuatorType::Vibrate, 30))],
+        start + Duration::from_millis(60)
+      ),
+      CoalescerAction::Deferred(None, _)
+    ));
+    let pending = coalescer
+      .take_pending()
+      .expect("Test, assuming infallible.");
+    assert_eq!(pending.commands(), &vec![Some((ActuatorType::Vibrate, 30))]);
+    assert!(coalescer.take_pending().is_none());
+  }
+
+  #[tokio::test]
+  pub async fn test_coalescer_sends_flush_result_to_all_updates() {
+    let coalescer = ScalarCommandCoalescer::new(Duration::from_millis(100));
+    let start = Instant::now();
+    coalescer.submit_at(vec![Some((ActuatorType::Vibrate, 10))], start);
+    let mut results = vec![];
+    for (value, offset) in [(20, 10), (30, 20)] {
+      match coalescer.submit_at(
+        vec![Some((ActuatorType::Vibrate, value))],
+        start + Duration::from_millis(offset),
+      ) {
+        CoalescerAction::Deferred(_, result) => results.push(result),
+        action => panic!("Expected update to be deferred, got {:?}", action),
+      }
+    }
+    let err: ButtplugError = ButtplugDeviceError::InvalidEndpoint(Endpoint::Tx).into();
+    coalescer
+      .take_pending()
+      .expect("Test, assuming infallible.")
+      .finish(Err(err.clone()));
+    for result in results {
+      assert_eq!(
+        result.await.expect("Test, assuming infallible."),
+        Err(err.clone())
+      );
+    }
+  }
+
+  #[tokio::test]
+  pub async fn test_coalescer_clear_pending() {
+    let coalescer = ScalarCommandCoalescer::new(Duration::from_millis(100));
+    let start = Instant::now();
+    coalescer.submit_at(vec![Some((ActuatorType::Vibrate, 10))], start);
+    let CoalescerAction::Deferred(_, result) = coalescer.submit_at(
+      vec![Some((ActuatorType::Vibrate, 20))],
+      start + Duration::from_millis(10),
+    ) else {
+      panic!("Expected update to be deferred");
+    };
+    coalescer.clear_pending();
+    assert!(coalescer.take_pending().is_none());
+    // Updates dropped for a stop still get a reply.
+    assert_eq!(result.await.expect("Test, assuming infallible."), Ok(()));
+  }
+}
@@ -0,0 +1,97 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-device ordering of hardware writes
+//!
+//! Device command futures are created when a message arrives, but nothing stops them from being
+//! polled in a different order, or from two of them writing to the hardware at the same time. That
+//! means an actuator command that arrived before a StopDeviceCmd could still land on the hardware
+//! after the stop, turning the device back on.
+//!
+//! To keep that from happening, each write takes a ticket when its command is received, and waits
+//! for its turn on the device before writing. A stop cancels every ticket taken before it, so
+//! commands still waiting for their turn are dropped, and any write already in progress finishes
+//! before the stop goes out.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Place in a device's command queue, taken when a command is received.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct CommandTicket {
+  generation: u64,
+  cancellable: bool,
+}
+
+/// Marks tickets taken while it's alive as part of a stop. Stops are never cancelled, otherwise a
+/// second stop would drop the first one's writes while having nothing to write itself.
+pub(super) struct StopScope<'a> {
+  queue: &'a DeviceCommandQueue,
+}
+
+impl Drop for StopScope<'_> {
+  fn drop(&mut self) {
+    self.queue.stopping.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+#[derive(Default)]
+pub(super) struct DeviceCommandQueue {
+  write_lock: Mutex<()>,
+  generation: AtomicU64,
+  stopping: AtomicUsize,
+}
+
+impl DeviceCommandQueue {
+  pub(super) fn ticket(&self) -> CommandTicket {
+    CommandTicket {
+      generation: self.generation.load(Ordering::SeqCst),
+      cancellable: self.stopping.load(Ordering::SeqCst) == 0,
+    }
+  }
+
+  /// Cancel all pending commands. Tickets for the stop commands should be taken while the returned
+  /// scope is alive.
+  pub(super) fn begin_stop(&self) -> StopScope<'_> {
+    self.stopping.fetch_add(1, Ordering::SeqCst);
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    StopScope { queue: self }
+  }
+
+  /// Wait until no other write is in progress on the device. Returns None if the ticket was
+  /// cancelled while waiting, in which case the command should be dropped.
+  pub(super) async fn wait_for_turn(&self, ticket: CommandTicket) -> Option<MutexGuard<'_, ()>> {
+    let turn = self.write_lock.lock().await;
+    if ticket.cancellable && ticket.generation < self.generation.load(Ordering::SeqCst) {
+      None
+    } else {
+      Some(turn)
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_stop_cancels_pending_tickets() {
+    let queue = DeviceCommandQueue::default();
+    let pending = queue.ticket();
+    let stop = {
+      let _scope = queue.begin_stop();
+      queue.ticket()
+    };
+    let after_stop = queue.ticket();
+    assert!(queue.wait_for_turn(pending).await.is_none());
+    // A later stop must not cancel an earlier one.
+    drop(queue.begin_stop());
+    assert!(queue.wait_for_turn(stop).await.is_some());
+    assert!(queue.wait_for_turn(after_stop).await.is_none());
+    assert!(queue.wait_for_turn(queue.ticket()).await.is_some());
+  }
+}
@@ -137,8 +137,10 @@
 //! ### User Configurations
 //!
 
+mod scalar_adjustment;
 mod server_device_message_attributes;
 pub mod specifier;
+pub use scalar_adjustment::ScalarActuatorAdjustment;
 pub use specifier::*;
 
 pub use server_device_message_attributes::{
@@ -160,10 +162,12 @@ use derivative::Derivative;
 use getset::{Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::HashMap,
+  collections::{HashMap, HashSet},
   sync::{
     atomic::{AtomicU32, Ordering},
     Arc,
+    RwLock,
+    RwLockReadGuard,
   },
 };
 
@@ -258,6 +262,18 @@ pub struct ProtocolDeviceAttributes {
   name: Option<String>,
   /// User configured name of the device this instance represents, assuming one exists.
   display_name: Option<String>,
+  /// User configured minimum time between scalar updates sent to the hardware, in milliseconds.
+  /// Overrides the protocol default if set.
+  command_rate_limit_ms: Option<u32>,
+  /// User configured time to ramp scalar actuators between values, in milliseconds.
+  scalar_ramp_time_ms: Option<u32>,
+  /// User configured time before an idle device goes into low power mode, in milliseconds. Devices
+  /// without one never go into low power mode.
+  idle_timeout_ms: Option<u32>,
+  /// User configured scaling, inversion, and remapping of scalar actuators.
+  scalar_adjustments: Option<Vec<ScalarActuatorAdjustment>>,
+  /// User configured protocol specific settings, which are never shown to clients.
+  protocol_options: Option<HashMap<String, Vec<u32>>>,
   /// Message attributes for this device instance.
   pub(super) message_attributes: ServerDeviceMessageAttributes,
 }
@@ -275,6 +291,11 @@ impl ProtocolDeviceAttributes {
       identifier,
       name,
       display_name,
+      command_rate_limit_ms: None,
+      scalar_ramp_time_ms: None,
+      idle_timeout_ms: None,
+      scalar_adjustments: None,
+      protocol_options: None,
       message_attributes,
       parent,
     }
@@ -286,12 +307,29 @@ impl ProtocolDeviceAttributes {
   /// attributes struct is handed out to the world, it is considered static, so we can provide a
   /// flattened representation.
   pub fn flatten(&self) -> Self {
+    let scalar_adjustments = self.scalar_adjustments();
+    let mut message_attributes = self.message_attributes();
+    // User step counts change what clients see, so they're baked into the message attributes here.
+    if let (Some(adjustments), Some(attrs)) =
+      (&scalar_adjustments, message_attributes.scalar_cmd_mut())
+    {
+      for adjustment in adjustments {
+        if let Some(attr) = attrs.get_mut(adjustment.target_index() as usize) {
+          attr.set_user_step_count(adjustment.step_count());
+        }
+      }
+    }
     Self {
       identifier: self.identifier().clone(),
       parent: None,
       name: Some(self.name().to_owned()),
       display_name: self.display_name(),
-      message_attributes: self.message_attributes(),
+      command_rate_limit_ms: self.command_rate_limit_ms(),
+      scalar_ramp_time_ms: self.scalar_ramp_time_ms(),
+      idle_timeout_ms: self.idle_timeout_ms(),
+      scalar_adjustments,
+      protocol_options: self.protocol_options(),
+      message_attributes,
     }
   }
 
@@ -330,8 +368,103 @@ impl ProtocolDeviceAttributes {
     }
   }
 
+  /// Return the user configured command rate limit for this instance, assuming one exists.
+  pub fn command_rate_limit_ms(&self) -> Option<u32> {
+    if let Some(rate_limit) = self.command_rate_limit_ms {
+      Some(rate_limit)
+    } else if let Some(parent) = &self.parent {
+      parent.command_rate_limit_ms()
+    } else {
+      None
+    }
+  }
+
+  /// Set the user configured command rate limit for this instance.
+  pub fn set_command_rate_limit_ms(&mut self, rate_limit_ms: Option<u32>) {
+    self.command_rate_limit_ms = rate_limit_ms;
+  }
+
+  /// Return the user configured scalar ramp time for this instance, assuming one exists.
+  pub fn scalar_ramp_time_ms(&self) -> Option<u32> {
+    if let Some(ramp_time) = self.scalar_ramp_time_ms {
+      Some(ramp_time)
+    } else if let Some(parent) = &self.parent {
+      parent.scalar_ramp_time_ms()
+    } else {
+      None
+    }
+  }
+
+  /// Set the user configured scalar ramp time for this instance.
+  pub fn set_scalar_ramp_time_ms(&mut self, ramp_time_ms: Option<u32>) {
+    self.scalar_ramp_time_ms = ramp_time_ms;
+  }
+
+  /// Return the user configured idle timeout for this instance, assuming one exists.
+  pub fn idle_timeout_ms(&self) -> Option<u32> {
+    if let Some(idle_timeout) = self.idle_timeout_ms {
+      Some(idle_timeout)
+    } else if let Some(parent) = &self.parent {
+      parent.idle_timeout_ms()
+    } else {
+      None
+    }
+  }
+
+  /// Set the user configured idle timeout for this instance.
+  pub fn set_idle_timeout_ms(&mut self, idle_timeout_ms: Option<u32>) {
+    self.idle_timeout_ms = idle_timeout_ms;
+  }
+
+  /// Return the user configured scalar actuator adjustments for this instance, assuming any exist.
+  pub fn scalar_adjustments(&self) -> Option<Vec<ScalarActuatorAdjustment>> {
+    if let Some(adjustments) = &self.scalar_adjustments {
+      Some(adjustments.clone())
+    } else if let Some(parent) = &self.parent {
+      parent.scalar_adjustments()
+    } else {
+      None
+    }
+  }
+
+  /// Set the user configured scalar actuator adjustments for this instance.
+  pub fn set_scalar_adjustments(&mut self, adjustments: Option<Vec<ScalarActuatorAdjustment>>) {
+    self.scalar_adjustments = adjustments;
+  }
+
+  /// Return the user configured protocol options for this instance, assuming any exist.
+  pub fn protocol_options(&self) -> Option<HashMap<String, Vec<u32>>> {
+    if let Some(options) = &self.protocol_options {
+      Some(options.clone())
+    } else if let Some(parent) = &self.parent {
+      parent.protocol_options()
+    } else {
+      None
+    }
+  }
+
+  /// Return the user configured value of a single protocol option, assuming it exists.
+  pub fn protocol_option(&self, name: &str) -> Option<Vec<u32>> {
+    self
+      .protocol_options()
+      .and_then(|mut options| options.remove(name))
+  }
+
+  /// Set the user configured protocol options for this instance.
+  pub fn set_protocol_options(&mut self, options: Option<HashMap<String, Vec<u32>>>) {
+    self.protocol_options = options;
+  }
+
   /// Check to make sure the message attributes of an instance are valid.
   fn is_valid(&self) -> Result<(), ButtplugDeviceError> {
+    if let Some(adjustments) = &self.scalar_adjustments {
+      let actuator_count = self
+        .message_attributes()
+        .scalar_cmd()
+        .as_ref()
+        .map_or(0, |attrs| attrs.len());
+      scalar_adjustment::check_remaps(adjustments, actuator_count)?;
+    }
     if let Some(attrs) = self.message_attributes.scalar_cmd() {
       for attr in attrs {
         attr.is_valid(&ButtplugDeviceMessageType::ScalarCmd)?;
@@ -369,6 +502,16 @@ impl ProtocolDeviceAttributes {
   pub fn add_raw_messages(&mut self, endpoints: &[Endpoint]) {
     self.message_attributes.add_raw_messages(endpoints);
   }
+
+  /// Allow subscribing to the battery sensor, for devices that can only have their battery read.
+  /// Returns the index of the battery in the SensorSubscribeCmd attributes, if it was added. See
+  /// the [battery](super::battery) module for details.
+  pub(crate) fn add_polled_battery_sensor(&mut self) -> Option<u32> {
+    let mut message_attributes = self.message_attributes();
+    let index = message_attributes.add_polled_battery_sensor()?;
+    self.message_attributes = message_attributes;
+    Some(index)
+  }
 }
 
 #[derive(Default, Clone)]
@@ -388,6 +531,12 @@ pub struct DeviceConfigurationManagerBuilder {
   /// [ServerDeviceIdentifier].
   denied_addresses: Vec<String>,
   reserved_indexes: Vec<(ServerDeviceIdentifier, u32)>,
+  /// Protocols marked as experimental in the device configuration. Devices using these protocols
+  /// are only exposed if the protocol has also been opted into via
+  /// [allowed_experimental_protocols](DeviceConfigurationManagerBuilder::allow_experimental_protocol).
+  experimental_protocols: HashSet<String>,
+  /// Experimental protocols the user has chosen to use anyways.
+  allowed_experimental_protocols: HashSet<String>,
 }
 
 impl DeviceConfigurationManagerBuilder {
@@ -419,6 +568,12 @@ impl DeviceConfigurationManagerBuilder {
       .reserved_indexes
       .extend(other.reserved_indexes.iter().map(|v| (v.clone())));
     self
+      .experimental_protocols
+      .extend(other.experimental_protocols.iter().cloned());
+    self
+      .allowed_experimental_protocols
+      .extend(other.allowed_experimental_protocols.iter().cloned());
+    self
   }
 
   pub fn communication_specifier(
@@ -479,6 +634,23 @@ impl DeviceConfigurationManagerBuilder {
     self
   }
 
+  /// Mark a protocol as experimental, so devices using it won't be exposed unless the protocol is
+  /// explicitly allowed.
+  pub fn experimental_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self
+      .experimental_protocols
+      .insert(protocol_name.to_owned());
+    self
+  }
+
+  /// Opt in to exposing devices that use an experimental protocol.
+  pub fn allow_experimental_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self
+      .allowed_experimental_protocols
+      .insert(protocol_name.to_owned());
+    self
+  }
+
   pub fn finish(&mut self) -> Result<DeviceConfigurationManager, ButtplugDeviceError> {
     // Map of protocol names to their respective protocol instance factories
     let mut protocol_map = if !self.skip_default_protocols {
@@ -494,6 +666,47 @@ impl DeviceConfigurationManagerBuilder {
       protocol_map.insert(name.clone(), protocol.clone());
     }
 
+    let attribute_tree_map = self.build_attribute_tree(&protocol_map)?;
+
+    // Align the implementation, communication specifier, and attribute maps so we only keep what we
+    // can actually use.
+
+    let reserved_indexes = DashMap::new();
+    for (identifier, index) in &self.reserved_indexes {
+      if reserved_indexes.contains_key(identifier) {
+        // TODO Fill in error
+      }
+      if reserved_indexes.iter().any(|pair| *pair == *index) {
+        // TODO Fill in error
+      }
+      reserved_indexes.insert(identifier.clone(), *index);
+    }
+
+    Ok(DeviceConfigurationManager {
+      allow_raw_messages: self.allow_raw_messages,
+      state: RwLock::new(DeviceConfigurationState {
+        communication_specifiers: self.communication_specifiers.clone(),
+        protocol_attributes: attribute_tree_map,
+        allowed_addresses: self.allowed_addresses.clone(),
+        denied_addresses: self.denied_addresses.clone(),
+        experimental_protocols: self.experimental_protocols.clone(),
+        allowed_experimental_protocols: self.allowed_experimental_protocols.clone(),
+      }),
+      protocol_map,
+      reserved_indexes,
+      current_index: AtomicU32::new(0),
+    })
+  }
+
+  /// Build and validate the protocol attributes tree, dropping any attributes for protocols that
+  /// aren't in the protocol map.
+  fn build_attribute_tree(
+    &self,
+    protocol_map: &HashMap<String, Arc<dyn ProtocolIdentifierFactory>>,
+  ) -> Result<
+    HashMap<ProtocolAttributesIdentifier, Arc<ProtocolDeviceAttributes>>,
+    ButtplugDeviceError,
+  > {
     // Build and validate the protocol attributes tree.
     let mut attribute_tree_map = HashMap::new();
 
@@ -568,38 +781,70 @@ impl DeviceConfigurationManagerBuilder {
       }
     }
 
-    // Align the implementation, communication specifier, and attribute maps so we only keep what we
-    // can actually use.
-
-    let reserved_indexes = DashMap::new();
-    for (identifier, index) in &self.reserved_indexes {
-      if reserved_indexes.contains_key(identifier) {
-        // TODO Fill in error
-      }
-      if reserved_indexes.iter().any(|pair| *pair == *index) {
-        // TODO Fill in error
-      }
-      reserved_indexes.insert(identifier.clone(), *index);
-    }
-
     // Make sure it's all valid.
     for attrs in attribute_tree_map.values() {
       attrs.is_valid()?;
     }
 
-    Ok(DeviceConfigurationManager {
-      allow_raw_messages: self.allow_raw_messages,
-      communication_specifiers: self.communication_specifiers.clone(),
-      protocol_attributes: attribute_tree_map,
-      protocol_map,
-      allowed_addresses: self.allowed_addresses.clone(),
-      denied_addresses: self.denied_addresses.clone(),
-      reserved_indexes,
-      current_index: AtomicU32::new(0),
-    })
+    Ok(attribute_tree_map)
   }
 }
 
+/// Changes to communication specifiers after a [DeviceConfigurationManager::reload].
+///
+/// Specifiers are compared using their matching rules, so a specifier that would match the same
+/// devices as an existing one isn't considered added.
+#[derive(Debug, Clone, Default, Getters)]
+#[getset(get = "pub")]
+pub struct DeviceConfigurationDiff {
+  /// Specifiers, keyed by protocol name, that new scans will now match.
+  added_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+  /// Specifiers, keyed by protocol name, that new scans will no longer match.
+  removed_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+}
+
+impl DeviceConfigurationDiff {
+  fn new(
+    old: &HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+    new: &HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+  ) -> Self {
+    let missing_from =
+      |from: &HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+       other: &HashMap<String, Vec<ProtocolCommunicationSpecifier>>| {
+        from
+          .iter()
+          .filter_map(|(name, specifiers)| {
+            let missing: Vec<_> = specifiers
+              .iter()
+              .filter(|spec| !other.get(name).is_some_and(|x| x.contains(spec)))
+              .cloned()
+              .collect();
+            (!missing.is_empty()).then(|| (name.clone(), missing))
+          })
+          .collect()
+      };
+    Self {
+      added_specifiers: missing_from(new, old),
+      removed_specifiers: missing_from(old, new),
+    }
+  }
+
+  /// True if the reload didn't change which devices will be matched by scans.
+  pub fn is_empty(&self) -> bool {
+    self.added_specifiers.is_empty() && self.removed_specifiers.is_empty()
+  }
+}
+
+/// Configuration that can be swapped out at runtime via [DeviceConfigurationManager::reload].
+struct DeviceConfigurationState {
+  communication_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
+  protocol_attributes: HashMap<ProtocolAttributesIdentifier, Arc<ProtocolDeviceAttributes>>,
+  allowed_addresses: Vec<String>,
+  denied_addresses: Vec<String>,
+  experimental_protocols: HashSet<String>,
+  allowed_experimental_protocols: HashSet<String>,
+}
+
 /// Correlates information about protocols and which devices they support.
 ///
 /// The [DeviceConfigurationManager] handles stores information about which device protocols the
@@ -614,12 +859,11 @@ impl DeviceConfigurationManagerBuilder {
 pub struct DeviceConfigurationManager {
   /// If true, add raw message support to connected devices
   allow_raw_messages: bool,
-  communication_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
-  protocol_attributes: HashMap<ProtocolAttributesIdentifier, Arc<ProtocolDeviceAttributes>>,
+  /// Specifiers, attributes, and allow/deny lists. Locked so they can be reloaded without
+  /// rebuilding the server.
+  state: RwLock<DeviceConfigurationState>,
   /// Map of protocol names to their respective protocol instance factories
   protocol_map: HashMap<String, Arc<dyn ProtocolIdentifierFactory>>,
-  allowed_addresses: Vec<String>,
-  denied_addresses: Vec<String>,
   reserved_indexes: DashMap<ServerDeviceIdentifier, u32>,
   current_index: AtomicU32,
 }
@@ -636,17 +880,70 @@ impl Default for DeviceConfigurationManager {
 }
 
 impl DeviceConfigurationManager {
+  fn state(&self) -> RwLockReadGuard<'_, DeviceConfigurationState> {
+    self
+      .state
+      .read()
+      .expect("Configuration lock should never be poisoned")
+  }
+
+  /// Replace communication specifiers, protocol attributes, and allow/deny lists with those from
+  /// a new configuration, usually built by reloading device config files.
+  ///
+  /// Only affects devices found after the reload. Connected devices keep the attributes they were
+  /// created with until they reconnect. Protocol implementations and raw message settings are kept
+  /// from the original configuration, and reserved indexes are only ever added to, so devices
+  /// don't change indexes mid-session.
+  pub fn reload(
+    &self,
+    builder: &DeviceConfigurationManagerBuilder,
+  ) -> Result<DeviceConfigurationDiff, ButtplugDeviceError> {
+    let protocol_attributes = builder.build_attribute_tree(&self.protocol_map)?;
+    for (identifier, index) in &builder.reserved_indexes {
+      if self.reserved_indexes.contains_key(identifier) {
+        continue;
+      }
+      if self.reserved_indexes.iter().any(|pair| *pair == *index) {
+        warn!(
+          "Index {} for {:?} is already in use, not reserving on reload.",
+          index, identifier
+        );
+        continue;
+      }
+      self.reserved_indexes.insert(identifier.clone(), *index);
+    }
+    let mut state = self
+      .state
+      .write()
+      .expect("Configuration lock should never be poisoned");
+    let diff = DeviceConfigurationDiff::new(
+      &state.communication_specifiers,
+      &builder.communication_specifiers,
+    );
+    *state = DeviceConfigurationState {
+      communication_specifiers: builder.communication_specifiers.clone(),
+      protocol_attributes,
+      allowed_addresses: builder.allowed_addresses.clone(),
+      denied_addresses: builder.denied_addresses.clone(),
+      experimental_protocols: builder.experimental_protocols.clone(),
+      allowed_experimental_protocols: builder.allowed_experimental_protocols.clone(),
+    };
+    info!("Device configuration reloaded: {:?}", diff);
+    Ok(diff)
+  }
+
   pub fn address_allowed(&self, address: &str) -> bool {
     let address = address.to_owned();
+    let state = self.state();
     // Make sure the device isn't on the deny list
-    if self.denied_addresses.contains(&address) {
+    if state.denied_addresses.contains(&address) {
       // If device is outright denied, deny
       info!(
         "Device {} denied by configuration, not connecting.",
         address
       );
       false
-    } else if !self.allowed_addresses.is_empty() && !self.allowed_addresses.contains(&address) {
+    } else if !state.allowed_addresses.is_empty() && !state.allowed_addresses.contains(&address) {
       // If device is not on allow list and allow list isn't empty, deny
       info!(
         "Device {} not on allow list and allow list not empty, not connecting.",
@@ -683,7 +980,20 @@ impl DeviceConfigurationManager {
   pub fn protocol_device_configurations(
     &self,
   ) -> HashMap<String, Vec<ProtocolCommunicationSpecifier>> {
-    self.communication_specifiers.clone()
+    self.state().communication_specifiers.clone()
+  }
+
+  /// Names of all protocols with an implementation available.
+  pub fn protocol_names(&self) -> Vec<String> {
+    self.protocol_map.keys().cloned().collect()
+  }
+
+  /// Factory for the named protocol's implementation, if there is one.
+  pub fn protocol_identifier_factory(
+    &self,
+    protocol: &str,
+  ) -> Option<Arc<dyn ProtocolIdentifierFactory>> {
+    self.protocol_map.get(protocol).cloned()
   }
 
   pub fn protocol_specializers(
@@ -695,10 +1005,21 @@ impl DeviceConfigurationManager {
       specifier
     );
     let mut specializers = vec![];
-    for (name, specifiers) in self.communication_specifiers.iter() {
+    let state = self.state();
+    for (name, specifiers) in state.communication_specifiers.iter() {
       if specifiers.contains(specifier) {
         info!("Found protocol {:?} for specifier {:?}.", name, specifier);
 
+        if state.experimental_protocols.contains(name)
+          && !state.allowed_experimental_protocols.contains(name)
+        {
+          info!(
+            "Protocol {:?} is experimental and has not been allowed, skipping.",
+            name
+          );
+          continue;
+        }
+
         if !self.protocol_map.contains_key(name) {
           warn!(
             "No protocol implementation for {:?} found for specifier {:?}.",
@@ -707,6 +1028,7 @@ impl DeviceConfigurationManager {
           continue;
         }
         specializers.push(ProtocolSpecializer::new(
+          name,
           specifiers.clone(),
           self
             .protocol_map
@@ -719,29 +1041,63 @@ impl DeviceConfigurationManager {
     specializers
   }
 
+  /// Identifier for a variant of an identified device, if the configuration has attributes for
+  /// the variant. Variants are looked up like any other identifier, so a user config for the
+  /// device's address takes precedence over the built in one.
+  pub fn variant_identifier(
+    &self,
+    identifier: &ServerDeviceIdentifier,
+    variant: &str,
+  ) -> Option<ServerDeviceIdentifier> {
+    let variant_identifier = ServerDeviceIdentifier::new(
+      identifier.address(),
+      identifier.protocol(),
+      &ProtocolAttributesType::Identifier(variant.to_owned()),
+    );
+    let state = self.state();
+    let configured = state
+      .protocol_attributes
+      .contains_key(&(&variant_identifier).into())
+      || state
+        .protocol_attributes
+        .contains_key(&ProtocolAttributesIdentifier {
+          address: None,
+          attributes_identifier: variant_identifier.attributes_identifier().clone(),
+          protocol: variant_identifier.protocol().clone(),
+        });
+    configured.then_some(variant_identifier)
+  }
+
   pub fn protocol_device_attributes(
     &self,
     identifier: &ServerDeviceIdentifier,
     raw_endpoints: &[Endpoint],
   ) -> Option<ProtocolDeviceAttributes> {
-    let mut flat_attrs = if let Some(attrs) = self.protocol_attributes.get(&identifier.into()) {
+    let state = self.state();
+    let mut flat_attrs = if let Some(attrs) = state.protocol_attributes.get(&identifier.into()) {
       debug!("User device config found for {:?}", identifier);
       attrs.flatten()
-    } else if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
-      address: None,
-      attributes_identifier: identifier.attributes_identifier().clone(),
-      protocol: identifier.protocol().clone(),
-    }) {
+    } else if let Some(attrs) = state
+      .protocol_attributes
+      .get(&ProtocolAttributesIdentifier {
+        address: None,
+        attributes_identifier: identifier.attributes_identifier().clone(),
+        protocol: identifier.protocol().clone(),
+      })
+    {
       debug!(
         "Protocol + Identifier device config found for {:?}",
         identifier
       );
       attrs.flatten()
-    } else if let Some(attrs) = self.protocol_attributes.get(&ProtocolAttributesIdentifier {
-      address: None,
-      attributes_identifier: ProtocolAttributesType::Default,
-      protocol: identifier.protocol().clone(),
-    }) {
+    } else if let Some(attrs) = state
+      .protocol_attributes
+      .get(&ProtocolAttributesIdentifier {
+        address: None,
+        attributes_identifier: ProtocolAttributesType::Default,
+        protocol: identifier.protocol().clone(),
+      })
+    {
       debug!("Protocol device config found for {:?}", identifier);
       attrs.flatten()
     } else {
@@ -771,10 +1127,15 @@ mod test {
   };
 
   fn create_unit_test_dcm(allow_raw_messages: bool) -> DeviceConfigurationManager {
-    let mut builder = DeviceConfigurationManagerBuilder::default();
+    let mut builder = create_unit_test_dcm_builder();
     if allow_raw_messages {
       builder.allow_raw_messages();
     }
+    builder.finish().unwrap()
+  }
+
+  fn create_unit_test_dcm_builder() -> DeviceConfigurationManagerBuilder {
+    let mut builder = DeviceConfigurationManagerBuilder::default();
     let specifiers = ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
       HashSet::from(["LVS-*".to_owned(), "LovenseDummyTestName".to_owned()]),
       vec![],
@@ -809,7 +1170,7 @@ mod test {
         None,
       ),
     );
-    builder.finish().unwrap()
+    builder
   }
 
   #[test]
@@ -834,6 +1195,65 @@ mod test {
     assert!(!config.protocol_specializers(&spec).is_empty());
   }
 
+  #[test]
+  fn test_config_reload() {
+    let dcm = create_unit_test_dcm(false);
+    let old_spec = ProtocolCommunicationSpecifier::BluetoothLE(
+      BluetoothLESpecifier::new_from_device("LVS-Whatever", &HashMap::new(), &[]),
+    );
+    let new_spec = ProtocolCommunicationSpecifier::BluetoothLE(
+      BluetoothLESpecifier::new_from_device("NewDevice", &HashMap::new(), &[]),
+    );
+    assert!(!dcm.protocol_specializers(&old_spec).is_empty());
+    assert!(dcm.protocol_specializers(&new_spec).is_empty());
+
+    let mut builder = DeviceConfigurationManagerBuilder::default();
+    builder.communication_specifier(
+      "lovense",
+      ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new(
+        HashSet::from(["NewDevice".to_owned()]),
+        vec![],
+        HashSet::new(),
+        HashMap::new(),
+      )),
+    );
+    builder.denied_address("DeniedAddress");
+    let diff = dcm.reload(&builder).expect("Test, assuming infallible");
+    assert_eq!(
+      diff.added_specifiers().get("lovense").map(Vec::len),
+      Some(1)
+    );
+    assert_eq!(
+      diff.removed_specifiers().get("lovense").map(Vec::len),
+      Some(1)
+    );
+    assert!(dcm.protocol_specializers(&old_spec).is_empty());
+    assert!(!dcm.protocol_specializers(&new_spec).is_empty());
+    assert!(!dcm.address_allowed("DeniedAddress"));
+
+    // Reloading the same configuration shouldn't change anything.
+    assert!(dcm
+      .reload(&builder)
+      .expect("Test, assuming infallible")
+      .is_empty());
+  }
+
+  #[test]
+  fn test_config_experimental_protocol() {
+    let spec = ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      "LovenseDummyTestName",
+      &HashMap::new(),
+      &[],
+    ));
+    let mut builder = create_unit_test_dcm_builder();
+    builder.experimental_protocol("lovense");
+    let config = builder.finish().unwrap();
+    assert!(config.protocol_specializers(&spec).is_empty());
+    builder.allow_experimental_protocol("lovense");
+    let config = builder.finish().unwrap();
+    assert!(!config.protocol_specializers(&spec).is_empty());
+  }
+
   #[test]
   fn test_specific_device_config_creation() {
     let dcm = create_unit_test_dcm(false);
@@ -925,6 +1345,49 @@ mod test {
     assert!(config.message_attributes().raw_unsubscribe_cmd().is_none());
   }
 
+  #[test]
+  fn test_user_step_count_device_config_creation() {
+    let mut builder = create_unit_test_dcm_builder();
+    let mut user_attrs = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Identifier("P".to_owned()),
+      None,
+      None,
+      ServerDeviceMessageAttributes::default(),
+      None,
+    );
+    let mut adjustment = ScalarActuatorAdjustment::new(1, None, 0.0, 1.0, false);
+    adjustment.set_step_count(Some(5));
+    user_attrs.set_scalar_adjustments(Some(vec![adjustment]));
+    builder.protocol_attributes(
+      ProtocolAttributesIdentifier::new(
+        "lovense",
+        &ProtocolAttributesType::Identifier("P".to_owned()),
+        &Some("Whatever".to_owned()),
+      ),
+      user_attrs,
+    );
+    let dcm = builder.finish().expect("Test, assuming infallible");
+    let config = dcm
+      .protocol_device_attributes(
+        &ServerDeviceIdentifier::new(
+          "Whatever",
+          "lovense",
+          &ProtocolAttributesType::Identifier("P".to_owned()),
+        ),
+        &[],
+      )
+      .expect("Should be found");
+    let step_counts: Vec<u32> = config
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .expect("Test, assuming infallible")
+      .iter()
+      .map(|attr| attr.step_count())
+      .collect();
+    assert_eq!(step_counts, vec![20, 5]);
+  }
+
   /*
       #[test]
       fn test_user_config_loading() {
@@ -0,0 +1,276 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! User configured adjustments for scalar actuators.
+//!
+//! Lets users cap or shift the output range of an actuator, invert it, swap which hardware actuator
+//! a client facing index drives, or cut down how many steps it has, all without the client needing
+//! to know about it.
+//!
+//! Adjustments can also reshape the response curve of an actuator, which helps with cheap motors
+//! that won't start spinning until they get a fair amount of power. Values at or below the
+//! `dead-zone` are treated as off, the rest are stretched back over the full range and raised to
+//! the power of `gamma`, then scaled into `scale-min`-`scale-max`. So for a motor that stalls under
+//! 30%, a `scale-min` of 0.3 makes a client's 5% actually spin it, and a `gamma` below 1.0 spreads
+//! more of the client's range over the low end.
+
+use crate::core::errors::ButtplugDeviceError;
+use getset::{CopyGetters, Setters};
+use serde::{Deserialize, Serialize};
+
+fn default_scale_max() -> f64 {
+  1.0
+}
+
+fn default_gamma() -> f64 {
+  1.0
+}
+
+fn is_default_gamma(gamma: &f64) -> bool {
+  *gamma == default_gamma()
+}
+
+fn is_zero(value: &f64) -> bool {
+  *value == 0.0
+}
+
+/// Adjustment applied to ScalarCmd values for a single actuator before they reach the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, CopyGetters, Setters)]
+#[getset(get_copy = "pub")]
+pub struct ScalarActuatorAdjustment {
+  /// Actuator index, as seen by clients.
+  index: u32,
+  /// Hardware actuator index that commands for this index should be sent to. If not set, commands
+  /// go to the same index.
+  #[serde(default, rename = "remap-to", skip_serializing_if = "Option::is_none")]
+  remap_to: Option<u32>,
+  /// Lowest non-zero output value, 0.0-1.0.
+  #[serde(default, rename = "scale-min")]
+  scale_min: f64,
+  /// Highest output value, 0.0-1.0.
+  #[serde(default = "default_scale_max", rename = "scale-max")]
+  scale_max: f64,
+  /// If true, incoming non-zero values are flipped (0.1 becomes 0.9 and vice versa) before
+  /// scaling. 0.0 still means off.
+  #[serde(default)]
+  invert: bool,
+  /// Incoming values at or below this, 0.0-1.0, are treated as 0.0. Values above it are stretched
+  /// back out over the full range.
+  #[serde(default, rename = "dead-zone", skip_serializing_if = "is_zero")]
+  #[getset(set = "pub")]
+  dead_zone: f64,
+  /// Exponent applied to incoming values before scaling. Below 1.0 gives more resolution at the
+  /// low end, above 1.0 at the high end.
+  #[serde(default = "default_gamma", skip_serializing_if = "is_default_gamma")]
+  #[getset(set = "pub")]
+  gamma: f64,
+  /// Number of steps the hardware actuator this index drives should have, for motors that can't
+  /// make out every step their protocol allows. Clients see this step count, and values are
+  /// quantized to it before being spread over the device's step range. Can only lower the step
+  /// count, anything above the device's own is ignored.
+  #[serde(
+    default,
+    rename = "step-count",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[getset(set = "pub")]
+  step_count: Option<u32>,
+}
+
+impl ScalarActuatorAdjustment {
+  pub fn new(
+    index: u32,
+    remap_to: Option<u32>,
+    scale_min: f64,
+    scale_max: f64,
+    invert: bool,
+  ) -> Self {
+    Self {
+      index,
+      remap_to,
+      scale_min,
+      scale_max,
+      invert,
+      dead_zone: 0.0,
+      gamma: default_gamma(),
+      step_count: None,
+    }
+  }
+
+  /// Hardware actuator index commands for this actuator should go to.
+  pub fn target_index(&self) -> u32 {
+    self.remap_to.unwrap_or(self.index)
+  }
+
+  /// Adjust an incoming scalar value. An incoming 0.0 is always left at 0.0, even on inverted
+  /// actuators, so that a client asking for an actuator to stop never turns it on. Anything that
+  /// ends up at 0.0 after inversion and the dead zone is also left off, so scaling never keeps an
+  /// actuator running.
+  pub fn apply(&self, scalar: f64) -> f64 {
+    let scalar = scalar.clamp(0.0, 1.0);
+    if scalar == 0.0 {
+      return 0.0;
+    }
+    let scalar = if self.invert { 1.0 - scalar } else { scalar };
+    if scalar <= self.dead_zone {
+      return 0.0;
+    }
+    let scalar = ((scalar - self.dead_zone) / (1.0 - self.dead_zone)).powf(self.gamma);
+    self.scale_min + scalar * (self.scale_max - self.scale_min)
+  }
+
+  /// Make sure the adjustment is usable with a device that has the given number of scalar
+  /// actuators.
+  fn is_valid(&self, actuator_count: usize) -> Result<(), ButtplugDeviceError> {
+    if !(0.0..=1.0).contains(&self.scale_min)
+      || !(0.0..=1.0).contains(&self.scale_max)
+      || self.scale_min > self.scale_max
+    {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Scalar adjustment for actuator {} has invalid scale range {}-{}, must be within 0.0-1.0 with min <= max.",
+        self.index, self.scale_min, self.scale_max
+      )));
+    }
+    if !(0.0..1.0).contains(&self.dead_zone) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Scalar adjustment for actuator {} has invalid dead zone {}, must be at least 0.0 and below 1.0.",
+        self.index, self.dead_zone
+      )));
+    }
+    if !self.gamma.is_finite() || self.gamma <= 0.0 {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Scalar adjustment for actuator {} has invalid gamma {}, must be greater than 0.0.",
+        self.index, self.gamma
+      )));
+    }
+    if self.index as usize >= actuator_count || self.target_index() as usize >= actuator_count {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Scalar adjustment for actuator {} (remapped to {}) is out of range for a device with {} actuators.",
+        self.index,
+        self.target_index(),
+        actuator_count
+      )));
+    }
+    if self.step_count == Some(0) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Scalar adjustment for actuator {} has a step count of 0, must be at least 1.",
+        self.index
+      )));
+    }
+    Ok(())
+  }
+}
+
+/// Make sure a device's adjustments only reorder its actuators. Every client index has to drive a
+/// different hardware actuator, otherwise two indexes would fight over one actuator while another
+/// can't be reached at all.
+pub(super) fn check_remaps(
+  adjustments: &[ScalarActuatorAdjustment],
+  actuator_count: usize,
+) -> Result<(), ButtplugDeviceError> {
+  let mut targets: Vec<u32> = (0..actuator_count as u32).collect();
+  let mut adjusted = vec![false; actuator_count];
+  for adjustment in adjustments {
+    adjustment.is_valid(actuator_count)?;
+    let index = adjustment.index() as usize;
+    if adjusted[index] {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Actuator {} has more than one scalar adjustment.",
+        index
+      )));
+    }
+    adjusted[index] = true;
+    targets[index] = adjustment.target_index();
+  }
+  let mut sorted = targets.clone();
+  sorted.sort_unstable();
+  sorted.dedup();
+  if sorted.len() != targets.len() {
+    return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Scalar adjustment remaps {:?} send more than one actuator index to the same hardware actuator, remaps have to swap actuators rather than point several at one.",
+      targets
+    )));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::{check_remaps, ScalarActuatorAdjustment};
+
+  #[test]
+  pub fn test_scalar_adjustment_scaling() {
+    let adjustment = ScalarActuatorAdjustment::new(0, None, 0.2, 0.8, false);
+    assert_eq!(adjustment.apply(0.0), 0.0);
+    assert!((adjustment.apply(0.5) - 0.5).abs() < f64::EPSILON);
+    assert!((adjustment.apply(1.0) - 0.8).abs() < f64::EPSILON);
+    assert!((adjustment.apply(2.0) - 0.8).abs() < f64::EPSILON);
+  }
+
+  #[test]
+  pub fn test_scalar_adjustment_inversion() {
+    let adjustment = ScalarActuatorAdjustment::new(0, Some(1), 0.0, 1.0, true);
+    assert_eq!(adjustment.apply(1.0), 0.0);
+    assert_eq!(adjustment.apply(0.25), 0.75);
+    // Stopping an inverted actuator still stops it.
+    assert_eq!(adjustment.apply(0.0), 0.0);
+    assert_eq!(adjustment.target_index(), 1);
+  }
+
+  #[test]
+  pub fn test_scalar_adjustment_response_curve() {
+    let mut adjustment = ScalarActuatorAdjustment::new(0, None, 0.3, 1.0, false);
+    adjustment.set_dead_zone(0.2);
+    adjustment.set_gamma(0.5);
+    assert_eq!(adjustment.apply(0.1), 0.0);
+    assert_eq!(adjustment.apply(0.2), 0.0);
+    // (0.4 - 0.2) / 0.8 = 0.25, sqrt = 0.5, scaled into 0.3-1.0 = 0.65
+    assert!((adjustment.apply(0.4) - 0.65).abs() < 1e-9);
+    assert!((adjustment.apply(1.0) - 1.0).abs() < 1e-9);
+    // Low intensities still land above the floor, where the motor can actually spin.
+    assert!(adjustment.apply(0.25) > 0.3);
+  }
+
+  #[test]
+  pub fn test_scalar_adjustment_validation() {
+    assert!(ScalarActuatorAdjustment::new(0, None, 0.0, 0.8, false)
+      .is_valid(1)
+      .is_ok());
+    assert!(ScalarActuatorAdjustment::new(0, None, 0.9, 0.8, false)
+      .is_valid(1)
+      .is_err());
+    assert!(ScalarActuatorAdjustment::new(0, Some(2), 0.0, 1.0, false)
+      .is_valid(2)
+      .is_err());
+    let mut adjustment = ScalarActuatorAdjustment::new(0, None, 0.0, 1.0, false);
+    adjustment.set_step_count(Some(0));
+    assert!(adjustment.is_valid(1).is_err());
+    adjustment.set_step_count(Some(10));
+    assert!(adjustment.is_valid(1).is_ok());
+    adjustment.set_dead_zone(1.0);
+    assert!(adjustment.is_valid(1).is_err());
+    adjustment.set_dead_zone(0.1);
+    adjustment.set_gamma(0.0);
+    assert!(adjustment.is_valid(1).is_err());
+  }
+
+  #[test]
+  pub fn test_scalar_adjustment_remaps() {
+    let swap = [
+      ScalarActuatorAdjustment::new(0, Some(1), 0.0, 1.0, false),
+      ScalarActuatorAdjustment::new(1, Some(0), 0.0, 1.0, false),
+    ];
+    assert!(check_remaps(&swap, 2).is_ok());
+    // A one sided remap leaves two indexes on actuator 1, and nothing on actuator 0.
+    assert!(check_remaps(&swap[..1], 2).is_err());
+    let duplicate = [
+      ScalarActuatorAdjustment::new(0, None, 0.0, 0.5, false),
+      ScalarActuatorAdjustment::new(0, None, 0.0, 1.0, false),
+    ];
+    assert!(check_remaps(&duplicate, 2).is_err());
+  }
+}
@@ -90,6 +90,31 @@ impl ServerDeviceMessageAttributes {
     self.raw_subscribe_cmd()
   }
 
+  /// List the battery sensor from SensorReadCmd in SensorSubscribeCmd too, for the server to poll.
+  /// Returns the index of the sensor in SensorSubscribeCmd, or None if there's no battery to read,
+  /// or it can already be subscribed to.
+  pub(crate) fn add_polled_battery_sensor(&mut self) -> Option<u32> {
+    fn is_battery(sensor: &&SensorDeviceMessageAttributes) -> bool {
+      *sensor.sensor_type() == SensorType::Battery
+    }
+    if self
+      .sensor_subscribe_cmd
+      .as_ref()
+      .is_some_and(|sensors| sensors.iter().any(|sensor| is_battery(&sensor)))
+    {
+      return None;
+    }
+    let battery = self
+      .sensor_read_cmd
+      .as_ref()?
+      .iter()
+      .find(is_battery)?
+      .clone();
+    let sensors = self.sensor_subscribe_cmd.get_or_insert_with(Vec::new);
+    sensors.push(battery);
+    Some(sensors.len() as u32 - 1)
+  }
+
   pub fn message_allowed(&self, message_type: &ButtplugDeviceMessageType) -> bool {
     match message_type {
       ButtplugDeviceMessageType::ScalarCmd => self.scalar_cmd.is_some(),
@@ -293,6 +318,17 @@ pub struct ServerGenericDeviceMessageAttributes {
   #[serde(skip_serializing)]
   #[getset(get = "pub", set = "pub")]
   step_range: RangeInclusive<u32>,
+  /// User configured step count, for actuators with more steps than anyone can feel. Set from
+  /// [ScalarActuatorAdjustment](super::ScalarActuatorAdjustment) step counts.
+  #[serde(skip)]
+  #[getset(get = "pub", set = "pub")]
+  user_step_count: Option<u32>,
+  /// Text sent to the device to set this feature, for protocols that build their commands from
+  /// templates in the device config.
+  #[serde(rename = "CommandTemplate")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[getset(get = "pub", set = "pub")]
+  command_template: Option<String>,
 }
 
 impl From<ServerGenericDeviceMessageAttributes> for ClientGenericDeviceMessageAttributes {
@@ -315,11 +351,17 @@ impl ServerGenericDeviceMessageAttributes {
       feature_descriptor: feature_descriptor.to_owned(),
       actuator_type,
       step_range: step_range.clone(),
+      user_step_count: None,
+      command_template: None,
     }
   }
 
+  /// Number of steps across the step range, or the user configured step count if that's lower.
   pub fn step_count(&self) -> u32 {
-    self.step_range.end() - self.step_range.start()
+    let range_steps = self.step_range.end() - self.step_range.start();
+    self
+      .user_step_count
+      .map_or(range_steps, |steps| steps.min(range_steps))
   }
 
   pub fn is_valid(
@@ -351,5 +393,9 @@ mod test {
     assert_eq!(vibrate_attributes.step_count(), 10);
     vibrate_attributes.set_step_range(RangeInclusive::new(3u32, 7));
     assert_eq!(vibrate_attributes.step_count(), 4);
+    vibrate_attributes.set_user_step_count(Some(2));
+    assert_eq!(vibrate_attributes.step_count(), 2);
+    vibrate_attributes.set_user_step_count(Some(20));
+    assert_eq!(vibrate_attributes.step_count(), 4);
   }
 }
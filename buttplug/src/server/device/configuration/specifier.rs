@@ -8,7 +8,10 @@
 use crate::core::message::Endpoint;
 use getset::{Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::{
+  collections::{HashMap, HashSet},
+  str::FromStr,
+};
 use uuid::Uuid;
 
 // Note: There's a ton of extra structs in here just to deserialize the json
@@ -87,8 +90,13 @@ impl PartialEq for BluetoothLEManufacturerData {
 ///
 /// Used by protocols for identifying bluetooth devices via their advertisements, as well as
 /// defining the services and characteristics they are expected to have.
+///
+/// Characteristics in the device configuration can use any endpoint name, not just the names in
+/// [Endpoint]. Names that aren't [Endpoint] names are assigned to an unused generic endpoint, and
+/// protocols look them up by name via [Hardware::named_endpoint](super::super::hardware::Hardware::named_endpoint).
 #[derive(Serialize, Deserialize, Debug, Clone, Getters, MutGetters, Setters)]
 #[getset(get = "pub", set = "pub", get_mut = "pub(crate)")]
+#[serde(try_from = "BluetoothLESpecifierConfig")]
 pub struct BluetoothLESpecifier {
   /// Set of expected advertised names for this device.
   names: HashSet<String>,
@@ -101,7 +109,78 @@ pub struct BluetoothLESpecifier {
   /// Services we expect the device may have. More services may be listed in a specifier than any
   /// one device may have, but we expect at least one to be matched by a device in order to consider
   /// the device part of the protocol that has this specifier.
+  #[serde(default)]
   services: HashMap<Uuid, HashMap<Endpoint, Uuid>>,
+  /// If true, the device is never connected to. All of its information comes from the manufacturer
+  /// data in its advertisements, which is exposed as a read-only Rx endpoint.
+  #[serde(default, rename = "advertisement-only")]
+  advertisement_only: bool,
+  /// Generic endpoints assigned to custom endpoint names used in services.
+  #[serde(default, rename = "named-endpoints")]
+  named_endpoints: HashMap<String, Endpoint>,
+}
+
+/// Bluetooth LE specifier as written in the device configuration, where characteristics can use
+/// custom endpoint names.
+#[derive(Deserialize)]
+struct BluetoothLESpecifierConfig {
+  names: HashSet<String>,
+  #[serde(default, rename = "manufacturer-data")]
+  manufacturer_data: Vec<BluetoothLEManufacturerData>,
+  #[serde(default, rename = "advertised-services")]
+  advertised_services: HashSet<Uuid>,
+  #[serde(default)]
+  services: HashMap<Uuid, HashMap<String, Uuid>>,
+  #[serde(default, rename = "advertisement-only")]
+  advertisement_only: bool,
+  /// Names that were already assigned endpoints, when reading back a serialized specifier.
+  #[serde(default, rename = "named-endpoints")]
+  named_endpoints: HashMap<String, Endpoint>,
+}
+
+impl TryFrom<BluetoothLESpecifierConfig> for BluetoothLESpecifier {
+  type Error = String;
+
+  fn try_from(config: BluetoothLESpecifierConfig) -> Result<Self, Self::Error> {
+    let mut specifier = BluetoothLESpecifier {
+      names: config.names,
+      manufacturer_data: config.manufacturer_data,
+      advertised_services: config.advertised_services,
+      services: HashMap::new(),
+      advertisement_only: config.advertisement_only,
+      named_endpoints: config.named_endpoints,
+    };
+    // Endpoints named directly go in first, so custom names are only given unused endpoints.
+    let mut custom_characteristics = vec![];
+    for (service, characteristics) in config.services {
+      let mut endpoints = HashMap::new();
+      for (name, characteristic) in characteristics {
+        match Endpoint::from_str(&name) {
+          Ok(endpoint) => {
+            endpoints.insert(endpoint, characteristic);
+          }
+          Err(_) => custom_characteristics.push((service, name, characteristic)),
+        }
+      }
+      specifier.services.insert(service, endpoints);
+    }
+    // Sorted, so custom names get the same endpoints every time the file is loaded.
+    custom_characteristics.sort();
+    for (service, name, characteristic) in custom_characteristics {
+      let endpoint = specifier.endpoint_for_name(&name).ok_or_else(|| {
+        format!(
+          "No generic endpoints left to assign custom endpoint name {}",
+          name
+        )
+      })?;
+      specifier
+        .services
+        .entry(service)
+        .or_default()
+        .insert(endpoint, characteristic);
+    }
+    Ok(specifier)
+  }
 }
 
 impl PartialEq for BluetoothLESpecifier {
@@ -165,6 +244,8 @@ impl BluetoothLESpecifier {
       manufacturer_data,
       advertised_services,
       services,
+      advertisement_only: false,
+      named_endpoints: HashMap::new(),
     }
   }
 
@@ -189,7 +270,31 @@ impl BluetoothLESpecifier {
       manufacturer_data: data_vec,
       advertised_services: service_set,
       services: HashMap::new(),
+      advertisement_only: false,
+      named_endpoints: HashMap::new(),
+    }
+  }
+
+  /// Endpoint for a characteristic name, assigning a free generic endpoint to names that aren't
+  /// [Endpoint] names. Returns None if all generic endpoints are taken.
+  fn endpoint_for_name(&mut self, name: &str) -> Option<Endpoint> {
+    if let Ok(endpoint) = Endpoint::from_str(name) {
+      return Some(endpoint);
     }
+    if let Some(endpoint) = self.named_endpoints.get(name) {
+      return Some(*endpoint);
+    }
+    let endpoint = (0..32)
+      .filter_map(|index| Endpoint::from_str(&format!("generic{}", index)).ok())
+      .find(|endpoint| {
+        !self.named_endpoints.values().any(|named| named == endpoint)
+          && !self
+            .services
+            .values()
+            .any(|characteristics| characteristics.contains_key(endpoint))
+      })?;
+    self.named_endpoints.insert(name.to_owned(), endpoint);
+    Some(endpoint)
   }
 
   /// Merge with another BLE specifier, used when loading user configs that extend a protocol
@@ -203,7 +308,34 @@ impl BluetoothLESpecifier {
       .union(&other.advertised_services)
       .cloned()
       .collect();
-    self.services.extend(other.services);
+    // Custom endpoint names in the other specifier may have been assigned generic endpoints this
+    // one already uses, so move them over by name.
+    let renamed: HashMap<Endpoint, String> = other
+      .named_endpoints
+      .iter()
+      .map(|(name, endpoint)| (*endpoint, name.clone()))
+      .collect();
+    for (service, characteristics) in other.services {
+      let mut endpoints = HashMap::new();
+      for (endpoint, characteristic) in characteristics {
+        let endpoint = match renamed.get(&endpoint) {
+          Some(name) => match self.endpoint_for_name(name) {
+            Some(endpoint) => endpoint,
+            None => {
+              warn!(
+                "No generic endpoints left for custom endpoint name {}, ignoring.",
+                name
+              );
+              continue;
+            }
+          },
+          None => endpoint,
+        };
+        endpoints.insert(endpoint, characteristic);
+      }
+      self.services.insert(service, endpoints);
+    }
+    self.advertisement_only |= other.advertisement_only;
   }
 }
 
@@ -232,6 +364,30 @@ impl PartialEq for LovenseConnectServiceSpecifier {
   }
 }
 
+/// Specifier for [Remote Server](crate::server::device::hardware::communication::remote_server)
+/// devices
+///
+/// Devices proxied from another Buttplug server, has no attributes because the remote server
+/// already identified the devices, and they describe their own features.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteServerSpecifier {
+  // Needed for proper deserialization, but clippy will complain.
+  #[allow(dead_code)]
+  exists: bool,
+}
+
+impl Default for RemoteServerSpecifier {
+  fn default() -> Self {
+    Self { exists: true }
+  }
+}
+
+impl PartialEq for RemoteServerSpecifier {
+  fn eq(&self, _other: &Self) -> bool {
+    true
+  }
+}
+
 /// Specifier for [XInput](crate::server::device::communication_manager::xinput) devices
 ///
 /// Network based services, has no attributes because the
@@ -343,6 +499,15 @@ pub struct USBSpecifier {
   product_id: u16,
 }
 
+impl USBSpecifier {
+  pub fn new(vendor_id: u16, product_id: u16) -> Self {
+    Self {
+      vendor_id,
+      product_id,
+    }
+  }
+}
+
 /// Specifier for Websocket Device Manager devices
 ///
 /// The websocket device manager is a network based manager, so we have no info other than possibly
@@ -379,6 +544,141 @@ impl WebsocketSpecifier {
   }
 }
 
+/// Specifier for [Network](crate::server::device::hardware::communication::network) devices
+///
+/// Network devices are defined by the user rather than found by scanning, so all we have to go on is
+/// the device name given in the definition.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub")]
+pub struct NetworkSpecifier {
+  names: HashSet<String>,
+}
+
+impl NetworkSpecifier {
+  pub fn new(names: &[String]) -> NetworkSpecifier {
+    NetworkSpecifier {
+      names: names.iter().cloned().collect(),
+    }
+  }
+}
+
+impl PartialEq for NetworkSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.names.intersection(&other.names).count() > 0
+  }
+}
+
+/// Specifier for [MIDI](crate::server::device::hardware::communication::midi) output devices
+///
+/// MIDI outputs are defined by the user, like network devices, so they're matched on the name given
+/// in the definition rather than the name of the port.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub")]
+pub struct MidiSpecifier {
+  names: HashSet<String>,
+}
+
+impl MidiSpecifier {
+  pub fn new(names: &[String]) -> MidiSpecifier {
+    MidiSpecifier {
+      names: names.iter().cloned().collect(),
+    }
+  }
+}
+
+impl PartialEq for MidiSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.names.intersection(&other.names).count() > 0
+  }
+}
+
+/// Specifier for [audio output](crate::server::device::hardware::communication::audio_output)
+/// devices
+///
+/// Like MIDI outputs, audio outputs are defined by the user and matched on the name given in the
+/// definition rather than the name of the sound card.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub")]
+pub struct AudioOutputSpecifier {
+  names: HashSet<String>,
+}
+
+impl AudioOutputSpecifier {
+  pub fn new(names: &[String]) -> AudioOutputSpecifier {
+    AudioOutputSpecifier {
+      names: names.iter().cloned().collect(),
+    }
+  }
+}
+
+impl PartialEq for AudioOutputSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.names.intersection(&other.names).count() > 0
+  }
+}
+
+/// Specifier for [DMX](crate::server::device::hardware::communication::dmx) devices
+///
+/// DMX fixtures can't be discovered, so like audio outputs they're defined by the user, and matched
+/// on the name given in the definition.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub")]
+pub struct DmxSpecifier {
+  names: HashSet<String>,
+}
+
+impl DmxSpecifier {
+  pub fn new(names: &[String]) -> DmxSpecifier {
+    DmxSpecifier {
+      names: names.iter().cloned().collect(),
+    }
+  }
+}
+
+impl PartialEq for DmxSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.names.intersection(&other.names).count() > 0
+  }
+}
+
+/// How to talk to an [mDNS](crate::server::device::hardware::communication::mdns) device on the
+/// port it announced.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MdnsScheme {
+  #[default]
+  Tcp,
+  Udp,
+  Websocket,
+}
+
+/// Specifier for [mDNS](crate::server::device::hardware::communication::mdns) devices
+///
+/// Devices are matched on the name they announce, and the protocol configuration says which
+/// transport they use.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
+#[getset(get = "pub", set = "pub")]
+pub struct MdnsSpecifier {
+  names: HashSet<String>,
+  #[serde(default)]
+  scheme: MdnsScheme,
+}
+
+impl MdnsSpecifier {
+  pub fn new(names: &[String]) -> MdnsSpecifier {
+    MdnsSpecifier {
+      names: names.iter().cloned().collect(),
+      scheme: MdnsScheme::default(),
+    }
+  }
+}
+
+impl PartialEq for MdnsSpecifier {
+  fn eq(&self, other: &Self) -> bool {
+    self.names.intersection(&other.names).count() > 0
+  }
+}
+
 /// Enum that covers all types of communication specifiers.
 ///
 /// Allows generalization of specifiers to handle checking for equality. Used for testing newly discovered
@@ -393,6 +693,12 @@ pub enum ProtocolCommunicationSpecifier {
   XInput(XInputSpecifier),
   LovenseConnectService(LovenseConnectServiceSpecifier),
   Websocket(WebsocketSpecifier),
+  RemoteServer(RemoteServerSpecifier),
+  Network(NetworkSpecifier),
+  Mdns(MdnsSpecifier),
+  Midi(MidiSpecifier),
+  AudioOutput(AudioOutputSpecifier),
+  Dmx(DmxSpecifier),
 }
 
 impl PartialEq for ProtocolCommunicationSpecifier {
@@ -409,6 +715,12 @@ impl PartialEq for ProtocolCommunicationSpecifier {
       (LovenseConnectService(self_spec), LovenseConnectService(other_spec)) => {
         self_spec == other_spec
       }
+      (RemoteServer(self_spec), RemoteServer(other_spec)) => self_spec == other_spec,
+      (Network(self_spec), Network(other_spec)) => self_spec == other_spec,
+      (Mdns(self_spec), Mdns(other_spec)) => self_spec == other_spec,
+      (Midi(self_spec), Midi(other_spec)) => self_spec == other_spec,
+      (AudioOutput(self_spec), AudioOutput(other_spec)) => self_spec == other_spec,
+      (Dmx(self_spec), Dmx(other_spec)) => self_spec == other_spec,
       _ => false,
     }
   }
@@ -416,3 +728,92 @@ impl PartialEq for ProtocolCommunicationSpecifier {
 
 impl Eq for ProtocolCommunicationSpecifier {
 }
+
+impl ProtocolCommunicationSpecifier {
+  /// Name of the transport the specifier describes, matching the key used for it in device
+  /// configuration files.
+  pub fn transport(&self) -> &'static str {
+    use ProtocolCommunicationSpecifier::*;
+    match self {
+      BluetoothLE(_) => "btle",
+      Evdev(_) => "evdev",
+      HID(_) => "hid",
+      USB(_) => "usb",
+      Serial(_) => "serial",
+      XInput(_) => "xinput",
+      LovenseConnectService(_) => "lovense-connect-service",
+      Websocket(_) => "websocket",
+      RemoteServer(_) => "remote-server",
+      Network(_) => "network",
+      Mdns(_) => "mdns",
+      Midi(_) => "midi",
+      AudioOutput(_) => "audio-output",
+      Dmx(_) => "dmx",
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_custom_endpoint_names() {
+    let mut specifier: BluetoothLESpecifier = serde_json::from_str(
+      r#"{
+        "names": ["Test"],
+        "services": {
+          "00000000-0000-0000-0000-000000000001": {
+            "tx": "00000000-0000-0000-0000-000000000002",
+            "generic0": "00000000-0000-0000-0000-000000000003",
+            "txmode2": "00000000-0000-0000-0000-000000000004"
+          }
+        }
+      }"#,
+    )
+    .expect("Test, assuming infallible.");
+    // generic0 is used directly, so the custom name gets the next one.
+    assert_eq!(
+      specifier.named_endpoints().get("txmode2"),
+      Some(&Endpoint::Generic1)
+    );
+    let service = Uuid::from_u128(1);
+    assert_eq!(
+      specifier.services()[&service].get(&Endpoint::Generic1),
+      Some(&Uuid::from_u128(4))
+    );
+
+    // Serializing and reading back keeps the same endpoints.
+    let round_trip: BluetoothLESpecifier =
+      serde_json::from_str(&serde_json::to_string(&specifier).expect("Test, assuming infallible."))
+        .expect("Test, assuming infallible.");
+    assert_eq!(round_trip.named_endpoints(), specifier.named_endpoints());
+    assert_eq!(round_trip.services(), specifier.services());
+
+    // Merged specifiers have their custom names moved off of endpoints that are already taken.
+    let other: BluetoothLESpecifier = serde_json::from_str(
+      r#"{
+        "names": ["Test"],
+        "services": {
+          "00000000-0000-0000-0000-000000000005": {
+            "txvibrate2": "00000000-0000-0000-0000-000000000006"
+          }
+        }
+      }"#,
+    )
+    .expect("Test, assuming infallible.");
+    assert_eq!(
+      other.named_endpoints().get("txvibrate2"),
+      Some(&Endpoint::Generic0)
+    );
+    specifier.merge(other);
+    assert_eq!(
+      specifier.named_endpoints().get("txvibrate2"),
+      Some(&Endpoint::Generic2)
+    );
+    assert_eq!(
+      specifier.services()[&Uuid::from_u128(5)].get(&Endpoint::Generic2),
+      Some(&Uuid::from_u128(6))
+    );
+  }
+}
@@ -0,0 +1,150 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Limits on how many devices connect at once
+//!
+//! Scanning can turn up a lot of devices at the same time, and trying to connect to all of them at
+//! once can overwhelm a Bluetooth adapter, with every connection timing out instead of a few
+//! succeeding. Connections are queued instead, with only so many running in parallel.
+//!
+//! Each transport gets its own queue, and free connection slots go to the queues in turn, so a
+//! transport that finds a lot of devices can't keep the others from connecting.
+
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+struct SchedulerState<T> {
+  /// Connections currently running.
+  active: usize,
+  /// Queued connections for each transport. Transports are taken from the front, and go to the
+  /// back once one of their connections is started.
+  queues: VecDeque<(String, VecDeque<T>)>,
+}
+
+/// Queue of pending connections, handing them out as connection slots free up. Clones share the
+/// same queue.
+#[derive(Debug)]
+pub(super) struct ConnectionScheduler<T> {
+  max_parallel: usize,
+  state: Arc<Mutex<SchedulerState<T>>>,
+}
+
+impl<T> Clone for ConnectionScheduler<T> {
+  fn clone(&self) -> Self {
+    Self {
+      max_parallel: self.max_parallel,
+      state: self.state.clone(),
+    }
+  }
+}
+
+impl<T> ConnectionScheduler<T> {
+  /// Scheduler running at most `max_parallel` connections at a time. 0 is treated as 1.
+  pub(super) fn new(max_parallel: usize) -> Self {
+    Self {
+      max_parallel: max_parallel.max(1),
+      state: Arc::new(Mutex::new(SchedulerState {
+        active: 0,
+        queues: VecDeque::new(),
+      })),
+    }
+  }
+
+  /// Queue a connection. If a slot is free, it's taken and the connection is handed straight back
+  /// to be run, otherwise it waits for [finish](Self::finish) to hand it out.
+  pub(super) fn enqueue(&self, transport: &str, connection: T) -> Option<T> {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic");
+    if state.active < self.max_parallel {
+      state.active += 1;
+      return Some(connection);
+    }
+    if let Some((_, queue)) = state
+      .queues
+      .iter_mut()
+      .find(|(queue_transport, _)| queue_transport == transport)
+    {
+      queue.push_back(connection);
+    } else {
+      state
+        .queues
+        .push_back((transport.to_owned(), VecDeque::from([connection])));
+    }
+    None
+  }
+
+  /// Mark a running connection as finished. Returns the next queued connection to run in its slot,
+  /// if there is one, otherwise the slot is freed.
+  pub(super) fn finish(&self) -> Option<T> {
+    let mut state = self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic");
+    while let Some((transport, mut queue)) = state.queues.pop_front() {
+      if let Some(connection) = queue.pop_front() {
+        if !queue.is_empty() {
+          state.queues.push_back((transport, queue));
+        }
+        return Some(connection);
+      }
+    }
+    state.active = state.active.saturating_sub(1);
+    None
+  }
+
+  /// Number of connections waiting for a slot.
+  pub(super) fn queued(&self) -> usize {
+    self
+      .state
+      .lock()
+      .expect("Lock is never held across a panic")
+      .queues
+      .iter()
+      .map(|(_, queue)| queue.len())
+      .sum()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_connection_scheduler_limits_parallel_connections() {
+    let scheduler = ConnectionScheduler::new(2);
+    assert_eq!(scheduler.enqueue("btle", 1), Some(1));
+    assert_eq!(scheduler.enqueue("btle", 2), Some(2));
+    assert_eq!(scheduler.enqueue("btle", 3), None);
+    assert_eq!(scheduler.queued(), 1);
+    assert_eq!(scheduler.finish(), Some(3));
+    assert_eq!(scheduler.finish(), None);
+    assert_eq!(scheduler.finish(), None);
+    // Both slots are free again.
+    assert_eq!(scheduler.enqueue("btle", 4), Some(4));
+    assert_eq!(scheduler.enqueue("btle", 5), Some(5));
+  }
+
+  #[test]
+  fn test_connection_scheduler_round_robins_transports() {
+    let scheduler = ConnectionScheduler::new(1);
+    assert_eq!(scheduler.enqueue("btle", "btle 1"), Some("btle 1"));
+    assert_eq!(scheduler.enqueue("btle", "btle 2"), None);
+    assert_eq!(scheduler.enqueue("btle", "btle 3"), None);
+    assert_eq!(scheduler.enqueue("serial", "serial 1"), None);
+    assert_eq!(scheduler.enqueue("hid", "hid 1"), None);
+    assert_eq!(scheduler.finish(), Some("btle 2"));
+    assert_eq!(scheduler.finish(), Some("serial 1"));
+    assert_eq!(scheduler.finish(), Some("hid 1"));
+    assert_eq!(scheduler.finish(), Some("btle 3"));
+    assert_eq!(scheduler.finish(), None);
+  }
+}
@@ -0,0 +1,108 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Named device groups for broadcasting commands
+//!
+//! Apps that want to "send this vibration to everything" would otherwise have to loop over devices
+//! themselves, building a command for each one and collecting errors. A group lets a single
+//! ScalarCmd go to a set of devices instead. Before sending, each device gets only the subcommands
+//! its actuators can handle, and devices that can handle none of them are skipped rather than
+//! failed.
+//!
+//! Groups store device indexes, so membership survives a device reconnecting at a reserved index.
+//! Members that aren't connected when a command is sent are reported as failed.
+//!
+//! Clients manage groups and send to them with the [AddDeviceToGroup], [RemoveDeviceFromGroup] and
+//! [GroupScalarCmd] messages, and get a [GroupCommandResult] back for group commands.
+//!
+//! [AddDeviceToGroup]: crate::core::message::AddDeviceToGroup
+//! [RemoveDeviceFromGroup]: crate::core::message::RemoveDeviceFromGroup
+//! [GroupScalarCmd]: crate::core::message::GroupScalarCmd
+//! [GroupCommandResult]: crate::core::message::GroupCommandResult
+
+use super::ServerDevice;
+use crate::core::{
+  errors::{ButtplugDeviceError, ButtplugError},
+  message::{ScalarCmd, ScalarSubcommand},
+};
+use dashmap::DashMap;
+use std::collections::BTreeSet;
+
+/// Group name to member device index map.
+#[derive(Default)]
+pub(super) struct DeviceGroups {
+  groups: DashMap<String, BTreeSet<u32>>,
+}
+
+impl DeviceGroups {
+  pub(super) fn add(&self, group: &str, device_index: u32) {
+    self
+      .groups
+      .entry(group.to_owned())
+      .or_default()
+      .insert(device_index);
+  }
+
+  /// Removes a device from a group, dropping the group once it's empty.
+  pub(super) fn remove(&self, group: &str, device_index: u32) -> Result<(), ButtplugError> {
+    {
+      let mut members = self
+        .groups
+        .get_mut(group)
+        .ok_or_else(|| ButtplugDeviceError::DeviceGroupNotFound(group.to_owned()))?;
+      if !members.remove(&device_index) {
+        return Err(ButtplugDeviceError::DeviceNotAvailable(device_index).into());
+      }
+    }
+    // Recheck emptiness under the map's lock, as a device may have been added to the group since
+    // the entry above was released.
+    self
+      .groups
+      .remove_if(group, |_, members| members.is_empty());
+    Ok(())
+  }
+
+  pub(super) fn members(&self, group: &str) -> Result<Vec<u32>, ButtplugError> {
+    self
+      .groups
+      .get(group)
+      .map(|members| members.iter().copied().collect())
+      .ok_or_else(|| ButtplugDeviceError::DeviceGroupNotFound(group.to_owned()).into())
+  }
+
+  pub(super) fn names(&self) -> Vec<String> {
+    self
+      .groups
+      .iter()
+      .map(|group| group.key().clone())
+      .collect()
+  }
+}
+
+/// Builds the ScalarCmd a single group member should get, keeping only subcommands that address
+/// an actuator the device has, with a matching actuator type. Returns None if nothing is left.
+pub(super) fn filter_scalar_cmd_for_device(
+  device_index: u32,
+  device: &ServerDevice,
+  subcommands: &[ScalarSubcommand],
+) -> Option<ScalarCmd> {
+  let attrs = device.message_attributes().scalar_cmd().clone()?;
+  let filtered: Vec<ScalarSubcommand> = subcommands
+    .iter()
+    .filter(|subcommand| {
+      attrs
+        .get(subcommand.index() as usize)
+        .is_some_and(|attr| *attr.actuator_type() == subcommand.actuator_type())
+    })
+    .cloned()
+    .collect();
+  if filtered.is_empty() {
+    None
+  } else {
+    Some(ScalarCmd::new(device_index, filtered))
+  }
+}
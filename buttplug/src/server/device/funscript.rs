@@ -0,0 +1,552 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side funscript playback
+//!
+//! Funscripts are the usual format for scripts synced to videos: a JSON file with a list of
+//! `{"at": milliseconds, "pos": 0-100}` actions. Instead of every video player implementing its own
+//! timing loop, a script can be handed to the server, which sends commands to the device as
+//! playback reaches each action. Playback can be paused, resumed, seeked, and run at a different
+//! rate, so players only need to forward their own transport controls.
+//!
+//! Scripts can drive either a linear or a scalar actuator, chosen with [FunscriptOutput]. Linear
+//! actuators are sent a LinearCmd moving to each action's position over the time until that action.
+//! Scalar actuators are set to each action's position as playback passes it.
+//!
+//! Only one script can play on a device at a time. Starting a script replaces whatever script or
+//! [pattern](super::pattern) was playing on the device. Once playback reaches the end of the script,
+//! it pauses there, so it can still be seeked. A StopDeviceCmd/StopAllDevices unloads the script.
+
+use super::ServerDevice;
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
+    message::{
+      ActuatorType,
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessageType,
+      LinearCmd,
+      ScalarCmd,
+      ScalarSubcommand,
+      StopDeviceCmd,
+      VectorSubcommand,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use dashmap::DashMap;
+use futures::{future, select, FutureExt};
+use getset::{CopyGetters, Getters};
+use instant::Instant;
+use serde::{Deserialize, Serialize};
+use std::{
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+    Mutex,
+  },
+  time::Duration,
+};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Highest position a funscript action can have.
+pub const FUNSCRIPT_MAX_POSITION: u8 = 100;
+
+fn default_range() -> u8 {
+  FUNSCRIPT_MAX_POSITION
+}
+
+/// A single point in a funscript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct FunscriptAction {
+  /// Time from the start of the script, in milliseconds
+  at: u32,
+  /// Position at this point, 0-100
+  pos: u8,
+}
+
+impl FunscriptAction {
+  pub fn new(at: u32, pos: u8) -> Self {
+    Self { at, pos }
+  }
+}
+
+/// Timed list of positions, in the funscript file format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct Funscript {
+  #[getset(get = "pub")]
+  actions: Vec<FunscriptAction>,
+  /// If true, positions are flipped, so 0 is the top of a stroke instead of the bottom.
+  #[getset(get_copy = "pub")]
+  #[serde(default)]
+  inverted: bool,
+  /// Position the script treats as its maximum, for scripts that were made for a shorter stroke.
+  #[getset(get_copy = "pub")]
+  #[serde(default = "default_range")]
+  range: u8,
+}
+
+impl Funscript {
+  /// Create a new script. Actions must be non-empty, with positions in the range 0-100, and range
+  /// must be in 1-100. Actions are sorted by time, as not all script editors save them in order.
+  pub fn new(
+    actions: &[FunscriptAction],
+    inverted: bool,
+    range: u8,
+  ) -> Result<Self, ButtplugError> {
+    if actions.is_empty() {
+      return Err(
+        ButtplugMessageError::InvalidMessageContents(
+          "Funscript must have at least one action".to_owned(),
+        )
+        .into(),
+      );
+    }
+    if let Some(action) = actions
+      .iter()
+      .find(|action| action.pos() > FUNSCRIPT_MAX_POSITION)
+    {
+      return Err(
+        ButtplugMessageError::InvalidMessageContents(format!(
+          "Funscript action position {} is outside of range 0-{}",
+          action.pos(),
+          FUNSCRIPT_MAX_POSITION
+        ))
+        .into(),
+      );
+    }
+    if range == 0 || range > FUNSCRIPT_MAX_POSITION {
+      return Err(
+        ButtplugMessageError::InvalidMessageContents(format!(
+          "Funscript range {} is outside of range 1-{}",
+          range, FUNSCRIPT_MAX_POSITION
+        ))
+        .into(),
+      );
+    }
+    let mut actions = actions.to_vec();
+    actions.sort_by_key(|action| action.at());
+    Ok(Self {
+      actions,
+      inverted,
+      range,
+    })
+  }
+
+  /// Load a script from the contents of a .funscript file. Fields other than the actions, range,
+  /// and inversion (metadata, version, etc) are ignored.
+  pub fn from_json(json: &str) -> Result<Self, ButtplugError> {
+    let script: Self = serde_json::from_str(json).map_err(|err| {
+      ButtplugMessageError::InvalidMessageContents(format!("Cannot parse funscript: {}", err))
+    })?;
+    Self::new(&script.actions, script.inverted, script.range)
+  }
+
+  /// Length of the script, which is the time of the last action.
+  pub fn duration(&self) -> Duration {
+    Duration::from_millis(self.actions.last().map_or(0, |action| action.at()) as u64)
+  }
+
+  /// Position of an action as a 0.0-1.0 actuator value, with the script's range and inversion
+  /// applied.
+  pub fn scaled_position(&self, action: &FunscriptAction) -> f64 {
+    let position = (action.pos() as f64 / self.range as f64).min(1.0);
+    if self.inverted {
+      1.0 - position
+    } else {
+      position
+    }
+  }
+
+  /// Index of the first action after a time in the script, or None if the time is at or past the
+  /// end of the script.
+  pub fn next_action_index(&self, time: Duration) -> Option<usize> {
+    self
+      .actions
+      .iter()
+      .position(|action| action.at() as u128 > time.as_millis())
+  }
+}
+
+/// Which actuator a funscript drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunscriptOutput {
+  /// Move the linear actuator at this index between action positions.
+  Linear(u32),
+  /// Set the scalar actuator at this index to action positions.
+  Scalar(u32),
+}
+
+/// Snapshot of funscript playback on a device.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct FunscriptPlaybackState {
+  /// Current time in the script
+  position: Duration,
+  /// Playback speed, 1.0 being the script's own timing
+  rate: f64,
+  paused: bool,
+}
+
+/// Script position, tracked relative to the last time playback was changed.
+struct PlaybackClock {
+  anchor_position: Duration,
+  anchor_time: Instant,
+  rate: f64,
+  paused: bool,
+  duration: Duration,
+}
+
+impl PlaybackClock {
+  fn position(&self) -> Duration {
+    if self.paused {
+      self.anchor_position
+    } else {
+      (self.anchor_position + self.anchor_time.elapsed().mul_f64(self.rate)).min(self.duration)
+    }
+  }
+
+  fn set_position(&mut self, position: Duration) {
+    self.anchor_position = position.min(self.duration);
+    self.anchor_time = Instant::now();
+  }
+
+  fn state(&self) -> FunscriptPlaybackState {
+    FunscriptPlaybackState {
+      position: self.position(),
+      rate: self.rate,
+      paused: self.paused,
+    }
+  }
+}
+
+struct FunscriptPlayback {
+  playback_id: u64,
+  clock: Arc<Mutex<PlaybackClock>>,
+  // Wakes the playback task when the clock is changed, so it can reschedule.
+  changed: Arc<Notify>,
+  token: CancellationToken,
+}
+
+fn funscript_command(
+  device_index: u32,
+  script: &Funscript,
+  output: FunscriptOutput,
+  actuator_type: ActuatorType,
+  next_index: usize,
+  time_to_next: Duration,
+) -> Option<ButtplugDeviceCommandMessageUnion> {
+  match output {
+    FunscriptOutput::Linear(actuator_index) => {
+      let action = &script.actions()[next_index];
+      Some(
+        LinearCmd::new(
+          device_index,
+          vec![VectorSubcommand::new(
+            actuator_index,
+            time_to_next.as_millis() as u32,
+            script.scaled_position(action),
+          )],
+        )
+        .into(),
+      )
+    }
+    // Scalar actuators have no notion of movement, so they hold the position of the action that
+    // was just passed. Nothing has been passed before the first action.
+    FunscriptOutput::Scalar(actuator_index) => next_index.checked_sub(1).map(|index| {
+      ScalarCmd::new(
+        device_index,
+        vec![ScalarSubcommand::new(
+          actuator_index,
+          script.scaled_position(&script.actions()[index]),
+          actuator_type,
+        )],
+      )
+      .into()
+    }),
+  }
+}
+
+/// Tracks which devices are currently playing funscripts, and runs their playback tasks.
+#[derive(Default)]
+pub(super) struct FunscriptPlayer {
+  playing: Arc<DashMap<u32, FunscriptPlayback>>,
+  next_playback_id: AtomicU64,
+}
+
+impl FunscriptPlayer {
+  pub fn play(
+    &self,
+    device_index: u32,
+    device: Arc<ServerDevice>,
+    script: Funscript,
+    output: FunscriptOutput,
+  ) -> Result<(), ButtplugError> {
+    let attrs = device.message_attributes();
+    let (message_type, actuators, actuator_index) = match output {
+      FunscriptOutput::Linear(index) => (
+        ButtplugDeviceMessageType::LinearCmd,
+        attrs.linear_cmd(),
+        index,
+      ),
+      FunscriptOutput::Scalar(index) => (
+        ButtplugDeviceMessageType::ScalarCmd,
+        attrs.scalar_cmd(),
+        index,
+      ),
+    };
+    let actuators = actuators
+      .as_ref()
+      .ok_or(ButtplugDeviceError::MessageNotSupported(message_type))?;
+    let actuator_type = *actuators
+      .get(actuator_index as usize)
+      .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+        actuators.len() as u32,
+        actuator_index,
+      ))?
+      .actuator_type();
+
+    let playback_id = self.next_playback_id.fetch_add(1, Ordering::SeqCst);
+    let clock = Arc::new(Mutex::new(PlaybackClock {
+      anchor_position: Duration::ZERO,
+      anchor_time: Instant::now(),
+      rate: 1.0,
+      paused: false,
+      duration: script.duration(),
+    }));
+    let changed = Arc::new(Notify::new());
+    let token = CancellationToken::new();
+    if let Some(old_playback) = self.playing.insert(
+      device_index,
+      FunscriptPlayback {
+        playback_id,
+        clock: clock.clone(),
+        changed: changed.clone(),
+        token: token.clone(),
+      },
+    ) {
+      old_playback.token.cancel();
+    }
+    let playing = self.playing.clone();
+    async_manager::spawn(async move {
+      let mut device_moving = false;
+      loop {
+        let (position, paused) = {
+          let clock = clock.lock().expect("Lock is never held across a panic");
+          (clock.position(), clock.paused)
+        };
+        let next_index = if paused {
+          None
+        } else {
+          script.next_action_index(position)
+        };
+        let wait = match next_index {
+          Some(next_index) => {
+            let rate = clock
+              .lock()
+              .expect("Lock is never held across a panic")
+              .rate;
+            let time_to_next = Duration::from_millis(script.actions()[next_index].at() as u64)
+              .saturating_sub(position)
+              .div_f64(rate);
+            if let Some(command) = funscript_command(
+              device_index,
+              &script,
+              output,
+              actuator_type,
+              next_index,
+              time_to_next,
+            ) {
+              if let Err(err) = device.parse_message(command).await {
+                error!(
+                  "Error playing funscript on device {}: {:?}",
+                  device_index, err
+                );
+                break;
+              }
+              device_moving = true;
+            }
+            Some(time_to_next)
+          }
+          None => {
+            if !paused {
+              // Ran off the end of the script, hold there until seeked or stopped.
+              let mut clock = clock.lock().expect("Lock is never held across a panic");
+              let duration = clock.duration;
+              clock.set_position(duration);
+              clock.paused = true;
+            }
+            if device_moving {
+              device_moving = false;
+              if let Err(err) = device
+                .parse_message(StopDeviceCmd::new(device_index).into())
+                .await
+              {
+                error!(
+                  "Cannot stop device {} after pausing funscript: {:?}",
+                  device_index, err
+                );
+              }
+            }
+            None
+          }
+        };
+        let wait_fut = async move {
+          match wait {
+            Some(wait) => sleep(wait).await,
+            None => future::pending::<()>().await,
+          }
+        };
+        select! {
+          _ = token.cancelled().fuse() => return,
+          _ = changed.notified().fuse() => {},
+          _ = wait_fut.fuse() => {}
+        }
+      }
+      playing.remove_if(&device_index, |_, playback| {
+        playback.playback_id == playback_id
+      });
+    });
+    Ok(())
+  }
+
+  fn update_clock<F>(&self, device_index: u32, update: F) -> Result<(), ButtplugError>
+  where
+    F: FnOnce(&mut PlaybackClock),
+  {
+    let playback = self
+      .playing
+      .get(&device_index)
+      .ok_or(ButtplugDeviceError::FunscriptNotLoaded(device_index))?;
+    update(
+      &mut playback
+        .clock
+        .lock()
+        .expect("Lock is never held across a panic"),
+    );
+    // notify_one keeps a permit if the task is busy sending a command, so the change isn't missed.
+    playback.changed.notify_one();
+    Ok(())
+  }
+
+  pub fn pause(&self, device_index: u32) -> Result<(), ButtplugError> {
+    self.update_clock(device_index, |clock| {
+      let position = clock.position();
+      clock.set_position(position);
+      clock.paused = true;
+    })
+  }
+
+  pub fn resume(&self, device_index: u32) -> Result<(), ButtplugError> {
+    self.update_clock(device_index, |clock| {
+      clock.anchor_time = Instant::now();
+      clock.paused = false;
+    })
+  }
+
+  pub fn seek(&self, device_index: u32, position: Duration) -> Result<(), ButtplugError> {
+    self.update_clock(device_index, |clock| clock.set_position(position))
+  }
+
+  pub fn set_rate(&self, device_index: u32, rate: f64) -> Result<(), ButtplugError> {
+    if !rate.is_finite() || rate <= 0.0 {
+      return Err(
+        ButtplugMessageError::InvalidMessageContents(format!(
+          "Funscript playback rate must be greater than 0, got {}",
+          rate
+        ))
+        .into(),
+      );
+    }
+    self.update_clock(device_index, |clock| {
+      let position = clock.position();
+      clock.set_position(position);
+      clock.rate = rate;
+    })
+  }
+
+  /// Cancel playback on a device. Returns true if a script was loaded.
+  pub fn stop(&self, device_index: u32) -> bool {
+    if let Some((_, playback)) = self.playing.remove(&device_index) {
+      playback.token.cancel();
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn stop_all(&self) {
+    for entry in self.playing.iter() {
+      entry.value().token.cancel();
+    }
+    self.playing.clear();
+  }
+
+  pub fn state(&self, device_index: u32) -> Option<FunscriptPlaybackState> {
+    self.playing.get(&device_index).map(|playback| {
+      playback
+        .clock
+        .lock()
+        .expect("Lock is never held across a panic")
+        .state()
+    })
+  }
+}
+
+impl Drop for FunscriptPlayer {
+  fn drop(&mut self) {
+    self.stop_all();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Funscript, FunscriptAction};
+  use std::time::Duration;
+
+  #[test]
+  pub fn test_funscript_parsing() {
+    let script = Funscript::from_json(
+      r#"{
+        "version": "1.0",
+        "range": 50,
+        "metadata": { "title": "Test" },
+        "actions": [
+          { "at": 500, "pos": 50 },
+          { "at": 0, "pos": 0 },
+          { "at": 250, "pos": 25 }
+        ]
+      }"#,
+    )
+    .expect("Test, assuming infallible.");
+    assert_eq!(script.duration(), Duration::from_millis(500));
+    assert_eq!(script.actions()[1], FunscriptAction::new(250, 25));
+    assert_eq!(script.scaled_position(&script.actions()[1]), 0.5);
+    assert_eq!(script.scaled_position(&script.actions()[2]), 1.0);
+    assert_eq!(script.next_action_index(Duration::ZERO), Some(1));
+    assert_eq!(
+      script.next_action_index(Duration::from_millis(250)),
+      Some(2)
+    );
+    assert_eq!(script.next_action_index(Duration::from_millis(500)), None);
+
+    let inverted = Funscript::new(&[FunscriptAction::new(0, 100)], true, 100)
+      .expect("Test, assuming infallible.");
+    assert_eq!(inverted.scaled_position(&inverted.actions()[0]), 0.0);
+  }
+
+  #[test]
+  pub fn test_funscript_validation() {
+    assert!(Funscript::new(&[], false, 100).is_err());
+    assert!(Funscript::new(&[FunscriptAction::new(0, 101)], false, 100).is_err());
+    assert!(Funscript::new(&[FunscriptAction::new(0, 50)], false, 0).is_err());
+    assert!(Funscript::from_json(r#"{ "actions": [{ "at": -1, "pos": 50 }] }"#).is_err());
+    assert!(Funscript::from_json("not a funscript").is_err());
+  }
+}
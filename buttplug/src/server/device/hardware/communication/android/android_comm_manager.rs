@@ -0,0 +1,116 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::ButtplugResultFuture,
+  server::device::hardware::communication::{
+    btleplug::btleplug_comm_manager::BtlePlugCommunicationManager,
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+};
+use futures::future::{self, FutureExt};
+use tokio::sync::mpsc::Sender;
+
+#[derive(Default, Clone)]
+pub struct AndroidBleCommunicationManagerBuilder {
+  require_keepalive: bool,
+}
+
+impl AndroidBleCommunicationManagerBuilder {
+  pub fn requires_keepalive(&mut self, require: bool) -> &mut Self {
+    self.require_keepalive = require;
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for AndroidBleCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(AndroidBleCommunicationManager::new(
+      sender,
+      self.require_keepalive,
+    ))
+  }
+}
+
+/// Bluetooth LE comm manager for Android. Does the same thing as the btleplug comm manager on other
+/// platforms, but holds off on scanning while the app is in the background. Apps drive this by
+/// calling [ButtplugServer::enter_background](crate::server::ButtplugServer::enter_background) and
+/// [ButtplugServer::enter_foreground](crate::server::ButtplugServer::enter_foreground) from the
+/// activity's `onPause`/`onResume`.
+pub struct AndroidBleCommunicationManager {
+  btleplug_manager: BtlePlugCommunicationManager,
+  in_background: bool,
+  /// True if a scan was requested while in the background, started once back in the foreground.
+  deferred_scan: bool,
+}
+
+impl AndroidBleCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>, require_keepalive: bool) -> Self {
+    Self {
+      btleplug_manager: BtlePlugCommunicationManager::new(sender, require_keepalive, None),
+      in_background: false,
+      deferred_scan: false,
+    }
+  }
+}
+
+impl HardwareCommunicationManager for AndroidBleCommunicationManager {
+  fn name(&self) -> &'static str {
+    "AndroidBleCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    if self.in_background {
+      info!("Android app is in the background, scanning will start in the foreground.");
+      self.deferred_scan = true;
+      return future::ready(Ok(())).boxed();
+    }
+    self.btleplug_manager.start_scanning()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    self.deferred_scan = false;
+    self.btleplug_manager.stop_scanning()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.btleplug_manager.scanning_status()
+  }
+
+  fn can_scan(&self) -> bool {
+    self.btleplug_manager.can_scan()
+  }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("btle")
+  }
+
+  fn enter_background(&mut self) -> ButtplugResultFuture {
+    self.in_background = true;
+    // The device manager stops scans before calling this, but Android will throttle or kill a
+    // background scan, so make sure nothing is left running. Connected devices are kept.
+    if self.btleplug_manager.scanning_status() {
+      info!("Android app entered background, stopping bluetooth scan.");
+      return self.btleplug_manager.stop_scanning();
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  fn enter_foreground(&mut self) -> ButtplugResultFuture {
+    self.in_background = false;
+    if std::mem::take(&mut self.deferred_scan) {
+      info!("Android app entered foreground, starting deferred bluetooth scan.");
+      return self.btleplug_manager.start_scanning();
+    }
+    future::ready(Ok(())).boxed()
+  }
+}
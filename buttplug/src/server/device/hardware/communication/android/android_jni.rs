@@ -0,0 +1,25 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::hardware::communication::HardwareSpecificError,
+};
+use jni::JNIEnv;
+
+/// Hook btleplug up to the JVM. Must be called once, from a thread attached to the JVM, before any
+/// [AndroidBleCommunicationManager](super::AndroidBleCommunicationManager) is built. Calling it
+/// again is harmless.
+pub fn initialize(env: &JNIEnv) -> Result<(), ButtplugDeviceError> {
+  btleplug::platform::init(env).map_err(|err| {
+    error!("Cannot initialize Android bluetooth: {:?}", err);
+    ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::BtleplugError(format!(
+      "{}",
+      err
+    )))
+  })
+}
@@ -0,0 +1,26 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Bluetooth LE support for Android apps.
+//!
+//! Scanning and GATT access go through btleplug's Android backend, which talks to the Android
+//! Bluetooth APIs over JNI. That backend needs the JVM before anything else happens, so apps have to
+//! call [initialize] from native code (usually `JNI_OnLoad` or the app's own native init method)
+//! before building a server. The app also needs to ship btleplug's Java support classes.
+//!
+//! Android apps shouldn't keep scanning while in the background, so apps should call
+//! [ButtplugServer::enter_background](crate::server::ButtplugServer::enter_background) and
+//! [ButtplugServer::enter_foreground](crate::server::ButtplugServer::enter_foreground) from the
+//! activity's `onPause`/`onResume`.
+
+pub mod android_comm_manager;
+pub mod android_jni;
+pub use android_comm_manager::{
+  AndroidBleCommunicationManager,
+  AndroidBleCommunicationManagerBuilder,
+};
+pub use android_jni::initialize;
@@ -0,0 +1,241 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::audio_output_hardware::AudioOutputHardwareConnector;
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::{
+    hardware::{
+      communication::{
+        HardwareCommunicationManager,
+        HardwareCommunicationManagerBuilder,
+        HardwareCommunicationManagerEvent,
+        TimedRetryCommunicationManager,
+        TimedRetryCommunicationManagerImpl,
+      },
+      waveform::HapticWaveform,
+    },
+    protocol::audio_output::AUDIO_OUTPUT_PACKET_WAVEFORM,
+  },
+};
+use async_trait::async_trait;
+use cpal::traits::{DeviceTrait, HostTrait};
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+
+// Most bass shakers are at their strongest somewhere around 30-60Hz.
+const DEFAULT_FREQUENCY_HZ: f64 = 40.0;
+
+/// Which audio output to use as a device, and how its actuators play on it.
+#[derive(Debug, Clone)]
+pub struct AudioOutputDefinition {
+  name: String,
+  device_name: String,
+  channels: HashMap<u8, u16>,
+  frequencies: HashMap<u8, f64>,
+}
+
+impl AudioOutputDefinition {
+  /// Define a device named name (which is matched against the `audio-output` names in the device
+  /// configuration), using the first output device with device_name in its name. An empty
+  /// device_name uses the system's default output.
+  ///
+  /// Until set otherwise, every actuator plays on all channels, at 40Hz.
+  pub fn new(name: &str, device_name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      device_name: device_name.to_owned(),
+      channels: HashMap::new(),
+      frequencies: HashMap::new(),
+    }
+  }
+
+  /// Play the actuator at index only on channel (with 0 being the first channel of the output).
+  pub fn channel(&mut self, index: u8, channel: u16) -> &mut Self {
+    self.channels.insert(index, channel);
+    self
+  }
+
+  /// Play the actuator at index at frequency Hz, unless the device has a Frequency actuator that
+  /// sets one.
+  pub fn frequency(&mut self, index: u8, frequency: f64) -> &mut Self {
+    self.frequencies.insert(index, frequency);
+    self
+  }
+
+  pub(super) fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Output channel for each actuator that has one set.
+  pub(super) fn routes(&self) -> impl Iterator<Item = (usize, u16)> + '_ {
+    self
+      .channels
+      .iter()
+      .map(|(index, channel)| (*index as usize, *channel))
+  }
+
+  /// Turns a packet from the audio-output protocol into the actuator index and waveform to play.
+  pub(super) fn waveform(
+    &self,
+    packet: &[u8],
+  ) -> Result<(usize, HapticWaveform), ButtplugDeviceError> {
+    match *packet {
+      [AUDIO_OUTPUT_PACKET_WAVEFORM, index, amplitude_lsb, amplitude_msb, frequency_lsb, frequency_msb] =>
+      {
+        let amplitude = u16::from_le_bytes([amplitude_lsb, amplitude_msb]) as f64 / u16::MAX as f64;
+        let frequency = match u16::from_le_bytes([frequency_lsb, frequency_msb]) {
+          0 => self
+            .frequencies
+            .get(&index)
+            .copied()
+            .unwrap_or(DEFAULT_FREQUENCY_HZ),
+          frequency => frequency as f64,
+        };
+        Ok((index as usize, HapticWaveform::new(amplitude, frequency)))
+      }
+      _ => Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+        "Cannot turn {:?} into a waveform",
+        packet
+      ))),
+    }
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct AudioOutputCommunicationManagerBuilder {
+  devices: Vec<AudioOutputDefinition>,
+}
+
+impl AudioOutputCommunicationManagerBuilder {
+  /// Add an audio output to look for while scanning.
+  pub fn device(&mut self, definition: AudioOutputDefinition) -> &mut Self {
+    self.devices.push(definition);
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for AudioOutputCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(TimedRetryCommunicationManager::new(
+      AudioOutputCommunicationManager::new(sender, self.devices.clone()),
+    ))
+  }
+}
+
+pub struct AudioOutputCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<AudioOutputDefinition>,
+}
+
+impl AudioOutputCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    devices: Vec<AudioOutputDefinition>,
+  ) -> Self {
+    Self { sender, devices }
+  }
+}
+
+#[async_trait]
+impl TimedRetryCommunicationManagerImpl for AudioOutputCommunicationManager {
+  fn name(&self) -> &'static str {
+    "AudioOutputCommunicationManager"
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("audio-output")
+  }
+
+  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+    // Audio hosts can't be held across awaits on every platform, so only the device names are kept
+    // around.
+    let (default_name, device_names) = {
+      let host = cpal::default_host();
+      let default_name = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+      let device_names: Vec<String> = host
+        .output_devices()
+        .map_err(|err| {
+          ButtplugDeviceError::DeviceConnectionError(format!(
+            "Cannot list audio outputs: {}",
+            err
+          ))
+        })?
+        .filter_map(|device| device.name().ok())
+        .collect();
+      (default_name, device_names)
+    };
+    for definition in &self.devices {
+      let device_name = if definition.device_name.is_empty() {
+        default_name.as_ref()
+      } else {
+        device_names
+          .iter()
+          .find(|device_name| device_name.contains(&definition.device_name))
+      };
+      let Some(device_name) = device_name else {
+        continue;
+      };
+      // Outputs are found again every scan, the device manager ignores the ones that are already
+      // connected.
+      if self
+        .sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name: definition.name.clone(),
+          address: device_name.clone(),
+          creator: Box::new(AudioOutputHardwareConnector::new(
+            definition.clone(),
+            device_name,
+          )),
+        })
+        .await
+        .is_err()
+      {
+        error!("Error sending device found message from Audio Output Manager.");
+        break;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_audio_output_waveforms() {
+    let mut definition = AudioOutputDefinition::new("Bass Shaker", "");
+    let (index, waveform) = definition
+      .waveform(&[AUDIO_OUTPUT_PACKET_WAVEFORM, 1, 0xff, 0xff, 0x50, 0x00])
+      .expect("Test, assuming infallible.");
+    assert_eq!(index, 1);
+    assert_eq!(waveform, HapticWaveform::new(1.0, 80.0));
+
+    // A frequency of 0 falls back to the one set for the actuator, or the default.
+    let (_, waveform) = definition
+      .waveform(&[AUDIO_OUTPUT_PACKET_WAVEFORM, 1, 0x00, 0x00, 0x00, 0x00])
+      .expect("Test, assuming infallible.");
+    assert_eq!(waveform.frequency(), DEFAULT_FREQUENCY_HZ);
+    definition.frequency(1, 55.0).channel(1, 1);
+    let (_, waveform) = definition
+      .waveform(&[AUDIO_OUTPUT_PACKET_WAVEFORM, 1, 0x00, 0x00, 0x00, 0x00])
+      .expect("Test, assuming infallible.");
+    assert_eq!(waveform.frequency(), 55.0);
+    assert_eq!(definition.routes().collect::<Vec<_>>(), vec![(1, 1)]);
+    assert!(definition.waveform(&[0x05, 0]).is_err());
+  }
+}
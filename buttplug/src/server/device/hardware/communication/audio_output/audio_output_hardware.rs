@@ -0,0 +1,331 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::audio_output_comm_manager::AudioOutputDefinition;
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{AudioOutputSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      waveform::WaveformMixer,
+      GenericHardwareSpecializer,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use cpal::{
+  traits::{DeviceTrait, HostTrait, StreamTrait},
+  SampleFormat,
+  Stream,
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  fmt::{self, Debug},
+  sync::{mpsc, Arc, Mutex},
+  thread,
+  time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+/// How often the output thread checks if it should stop.
+const OUTPUT_STOP_POLL_MS: u64 = 100;
+
+fn connection_error(device_name: &str, err: impl fmt::Display) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceConnectionError(format!(
+    "Cannot open audio output {}: {}",
+    device_name, err
+  ))
+}
+
+/// Open an output stream on the named device, playing whatever is in the mixer.
+fn open_stream(
+  device_name: &str,
+  definition: &AudioOutputDefinition,
+) -> Result<(Stream, Arc<Mutex<WaveformMixer>>), ButtplugDeviceError> {
+  let device = cpal::default_host()
+    .output_devices()
+    .map_err(|err| connection_error(device_name, err))?
+    .find(|device| device.name().is_ok_and(|name| name == device_name))
+    .ok_or_else(|| connection_error(device_name, "device went away"))?;
+  let supported_config = device
+    .default_output_config()
+    .map_err(|err| connection_error(device_name, err))?;
+  let sample_format = supported_config.sample_format();
+  let config = supported_config.config();
+  info!(
+    "Playing haptics on {} ({} channels at {}hz, {:?})",
+    device_name, config.channels, config.sample_rate.0, sample_format
+  );
+  let mut mixer = WaveformMixer::new(config.sample_rate.0, config.channels);
+  for (index, channel) in definition.routes() {
+    mixer.set_route(index, Some(channel));
+  }
+  let mixer = Arc::new(Mutex::new(mixer));
+  let on_error = |err: cpal::StreamError| error!("Audio output error: {}", err);
+  let stream_mixer = mixer.clone();
+  let render = move |buffer: &mut [f32]| {
+    stream_mixer
+      .lock()
+      .expect("Lock is never held across a panic")
+      .render(buffer)
+  };
+  let stream = match sample_format {
+    SampleFormat::F32 => device.build_output_stream(
+      &config,
+      move |data: &mut [f32], _: &cpal::OutputCallbackInfo| render(data),
+      on_error,
+      None,
+    ),
+    SampleFormat::I16 => {
+      let mut samples = vec![];
+      device.build_output_stream(
+        &config,
+        move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+          samples.resize(data.len(), 0.0);
+          render(&mut samples);
+          for (out, sample) in data.iter_mut().zip(samples.iter()) {
+            *out = (sample * i16::MAX as f32) as i16;
+          }
+        },
+        on_error,
+        None,
+      )
+    }
+    SampleFormat::U16 => {
+      let mut samples = vec![];
+      device.build_output_stream(
+        &config,
+        move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
+          samples.resize(data.len(), 0.0);
+          render(&mut samples);
+          for (out, sample) in data.iter_mut().zip(samples.iter()) {
+            *out = ((sample * i16::MAX as f32) as i32 + 32768) as u16;
+          }
+        },
+        on_error,
+        None,
+      )
+    }
+    format => {
+      return Err(connection_error(
+        device_name,
+        format!("unsupported sample format {:?}", format),
+      ))
+    }
+  }
+  .map_err(|err| connection_error(device_name, err))?;
+  stream
+    .play()
+    .map_err(|err| connection_error(device_name, err))?;
+  Ok((stream, mixer))
+}
+
+/// Play on the named device until the token is cancelled, returning the mixer feeding it.
+///
+/// Streams can't be moved between threads on every platform, so the stream lives on its own
+/// thread. Opening is waited on, so errors are returned to the caller.
+fn start_output(
+  device_name: &str,
+  definition: &AudioOutputDefinition,
+  token: CancellationToken,
+) -> Result<Arc<Mutex<WaveformMixer>>, ButtplugDeviceError> {
+  let (result_sender, result_receiver) = mpsc::channel();
+  let device_name = device_name.to_owned();
+  let definition = definition.clone();
+  thread::Builder::new()
+    .name("buttplug-audio-output".to_owned())
+    .spawn(move || {
+      let stream = match open_stream(&device_name, &definition) {
+        Ok((stream, mixer)) => {
+          let _ = result_sender.send(Ok(mixer));
+          stream
+        }
+        Err(err) => {
+          let _ = result_sender.send(Err(err));
+          return;
+        }
+      };
+      while !token.is_cancelled() {
+        thread::sleep(Duration::from_millis(OUTPUT_STOP_POLL_MS));
+      }
+      drop(stream);
+      debug!("Audio output {} stopped.", device_name);
+    })
+    .map_err(|err| ButtplugDeviceError::DeviceConnectionError(err.to_string()))?;
+  result_receiver.recv().map_err(|_| {
+    ButtplugDeviceError::DeviceConnectionError("Audio output thread exited".to_owned())
+  })?
+}
+
+pub struct AudioOutputHardwareConnector {
+  definition: AudioOutputDefinition,
+  device_name: String,
+}
+
+impl AudioOutputHardwareConnector {
+  pub(super) fn new(definition: AudioOutputDefinition, device_name: &str) -> Self {
+    Self {
+      definition,
+      device_name: device_name.to_owned(),
+    }
+  }
+}
+
+impl Debug for AudioOutputHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("AudioOutputHardwareConnector")
+      .field("name", &self.definition.name())
+      .field("device_name", &self.device_name)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for AudioOutputHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::AudioOutput(AudioOutputSpecifier::new(&[self
+      .definition
+      .name()
+      .to_owned()]))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    let token = CancellationToken::new();
+    let mixer = start_output(&self.device_name, &self.definition, token.clone())?;
+    let hardware = Hardware::new(
+      self.definition.name(),
+      &self.device_name,
+      &[Endpoint::Tx],
+      Box::new(AudioOutputHardware::new(
+        &self.device_name,
+        self.definition.clone(),
+        mixer,
+        token,
+      )),
+    );
+    Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
+  }
+}
+
+pub struct AudioOutputHardware {
+  address: String,
+  definition: AudioOutputDefinition,
+  mixer: Arc<Mutex<WaveformMixer>>,
+  // Cancelled when the device is disconnected, which stops the output thread.
+  token: CancellationToken,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl AudioOutputHardware {
+  fn new(
+    address: &str,
+    definition: AudioOutputDefinition,
+    mixer: Arc<Mutex<WaveformMixer>>,
+    token: CancellationToken,
+  ) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      address: address.to_owned(),
+      definition,
+      mixer,
+      token,
+      event_sender,
+    }
+  }
+}
+
+impl Drop for AudioOutputHardware {
+  fn drop(&mut self) {
+    self.token.cancel();
+  }
+}
+
+impl HardwareInternal for AudioOutputHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.token.cancel();
+    let _ = self.event_sender.send(HardwareEvent::Disconnected(
+      self.address.clone(),
+      DeviceRemovedReason::UserDisconnect,
+      false,
+    ));
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Audio outputs do not support read".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::Tx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
+    if self.token.is_cancelled() {
+      return future::ready(Err(ButtplugDeviceError::DeviceNotConnected(
+        self.address.clone(),
+      )))
+      .boxed();
+    }
+    let result = self
+      .definition
+      .waveform(msg.data())
+      .map(|(index, waveform)| {
+        self
+          .mixer
+          .lock()
+          .expect("Lock is never held across a panic")
+          .set_waveform(index, waveform)
+      });
+    future::ready(result).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Audio outputs do not support subscribe".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Audio outputs do not support unsubscribe".to_owned(),
+    )))
+    .boxed()
+  }
+}
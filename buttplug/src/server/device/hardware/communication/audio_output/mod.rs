@@ -0,0 +1,27 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Audio outputs as devices
+//!
+//! Turns sound cards into haptic hardware, for bass shakers like the Buttkicker, or haptic vests
+//! driven by an amplifier. Outputs are defined by the user: the name the device is matched on in
+//! the device configuration ("Bass Shaker" for the built in `audio-output` protocol), the output
+//! device to open, and which channel and frequency each actuator plays on. The manager opens the
+//! output once it shows up while scanning.
+//!
+//! The [audio-output protocol](crate::server::device::protocol::audio_output) writes the amplitude
+//! and frequency of each actuator to Tx, and the hardware plays them as sine waves, using a
+//! [WaveformMixer](crate::server::device::hardware::waveform::WaveformMixer).
+
+mod audio_output_comm_manager;
+mod audio_output_hardware;
+pub use audio_output_comm_manager::{
+  AudioOutputCommunicationManager,
+  AudioOutputCommunicationManagerBuilder,
+  AudioOutputDefinition,
+};
+pub use audio_output_hardware::AudioOutputHardware;
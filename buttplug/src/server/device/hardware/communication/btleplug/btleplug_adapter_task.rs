@@ -5,13 +5,19 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::btleplug_hardware::BtleplugHardwareConnector;
-use crate::server::device::hardware::communication::HardwareCommunicationManagerEvent;
+use super::{
+  btleplug_comm_manager::BtleplugAdapterSelection,
+  btleplug_hardware::BtleplugHardwareConnector,
+};
+use crate::server::device::{
+  hardware::communication::HardwareCommunicationManagerEvent,
+  RememberedDeviceStore,
+};
 use btleplug::{
-  api::{Central, CentralEvent, Manager as _, Peripheral, ScanFilter},
+  api::{BDAddr, Central, CentralEvent, Manager as _, Peripheral, ScanFilter},
   platform::{Adapter, Manager, PeripheralId},
 };
-use futures::{future::FutureExt, StreamExt};
+use futures::{future::FutureExt, stream, StreamExt};
 use std::{
   collections::HashMap,
   sync::{
@@ -44,6 +50,12 @@ pub struct BtleplugAdapterTask {
   command_receiver: Receiver<BtleplugAdapterCommand>,
   adapter_connected: Arc<AtomicBool>,
   requires_keepalive: bool,
+  gatt_cache: Option<RememberedDeviceStore>,
+  adapter_selection: BtleplugAdapterSelection,
+  /// Which adapter each device was found on, along with its id there. When scanning on more than
+  /// one adapter, a device is only ever reported from the adapter that saw it first, so it's always
+  /// connected through that adapter.
+  device_adapters: HashMap<BDAddr, (usize, PeripheralId)>,
 }
 
 impl BtleplugAdapterTask {
@@ -52,18 +64,44 @@ impl BtleplugAdapterTask {
     command_receiver: Receiver<BtleplugAdapterCommand>,
     adapter_connected: Arc<AtomicBool>,
     requires_keepalive: bool,
+    gatt_cache: Option<RememberedDeviceStore>,
+    adapter_selection: BtleplugAdapterSelection,
   ) -> Self {
     Self {
       event_sender,
       command_receiver,
       adapter_connected,
       requires_keepalive,
+      gatt_cache,
+      adapter_selection,
+      device_adapters: HashMap::new(),
+    }
+  }
+
+  /// Narrow the system's adapters down to the selected ones.
+  async fn select_adapters(&self, adapters: Vec<Adapter>) -> Vec<Adapter> {
+    match &self.adapter_selection {
+      BtleplugAdapterSelection::First => adapters.into_iter().take(1).collect(),
+      BtleplugAdapterSelection::Index(index) => adapters.into_iter().skip(*index).take(1).collect(),
+      BtleplugAdapterSelection::Name(name) => {
+        let name = name.to_lowercase();
+        for adapter in adapters {
+          if let Ok(info) = adapter.adapter_info().await {
+            if info.to_lowercase().contains(&name) {
+              return vec![adapter];
+            }
+          }
+        }
+        vec![]
+      }
+      BtleplugAdapterSelection::All => adapters,
     }
   }
 
   async fn maybe_add_peripheral(
-    &self,
+    &mut self,
     peripheral_id: &PeripheralId,
+    adapter_index: usize,
     adapter: &Adapter,
     tried_addresses: &mut Vec<PeripheralInfo>,
   ) {
@@ -84,6 +122,18 @@ impl BtleplugAdapterTask {
       return;
     };
 
+    if let Some((owner, _)) = self.device_adapters.get(&properties.address) {
+      if *owner != adapter_index {
+        trace!(
+          "Device {} already found on adapter {}, ignoring it on adapter {}.",
+          properties.address,
+          owner,
+          adapter_index
+        );
+        return;
+      }
+    }
+
     let device_name = if let Some(name) = &properties.local_name {
       name.clone()
     } else {
@@ -112,6 +162,9 @@ impl BtleplugAdapterTask {
         peripheral_info
       );
       tried_addresses.push(peripheral_info.clone());
+      self
+        .device_adapters
+        .insert(properties.address, (adapter_index, peripheral_id.clone()));
       let device_creator = Box::new(BtleplugHardwareConnector::new(
         &device_name,
         &properties.manufacturer_data,
@@ -119,6 +172,7 @@ impl BtleplugAdapterTask {
         peripheral.clone(),
         adapter.clone(),
         self.requires_keepalive,
+        self.gatt_cache.clone(),
       ));
       if self
         .event_sender
@@ -153,17 +207,18 @@ impl BtleplugAdapterTask {
     // message then loop while trying to find it.
     self.adapter_connected.store(true, Ordering::SeqCst);
 
-    let adapter;
+    let adapters;
 
     loop {
       let adapter_found = self.adapter_connected.load(Ordering::SeqCst);
       if !adapter_found {
         sleep(Duration::from_secs(1)).await;
       }
-      adapter = match manager.adapters().await {
+      adapters = match manager.adapters().await {
         Ok(adapters) => {
-          if let Some(adapter) = adapters.into_iter().next() {
-            info!("Bluetooth LE adapter found.");
+          let adapters = self.select_adapters(adapters).await;
+          if !adapters.is_empty() {
+            info!("{} Bluetooth LE adapter(s) found.", adapters.len());
             // Bluetooth dongle identification for Windows
             #[cfg(target_os = "windows")]
             {
@@ -199,7 +254,7 @@ impl BtleplugAdapterTask {
                 device_manufacturer
               );
             }
-            adapter
+            adapters
           } else {
             if adapter_found {
               self.adapter_connected.store(false, Ordering::SeqCst);
@@ -219,10 +274,15 @@ impl BtleplugAdapterTask {
       break;
     }
 
-    let mut events = adapter
-      .events()
-      .await
-      .expect("Should always be able to retreive stream.");
+    let mut adapter_events = vec![];
+    for (index, adapter) in adapters.iter().enumerate() {
+      let events = adapter
+        .events()
+        .await
+        .expect("Should always be able to retreive stream.");
+      adapter_events.push(events.map(move |event| (index, event)));
+    }
+    let mut events = stream::select_all(adapter_events);
 
     let mut tried_addresses = vec![];
 
@@ -231,14 +291,15 @@ impl BtleplugAdapterTask {
 
       select! {
         event = event_fut.fuse() => {
-            if let Some(event) = event {
+            if let Some((index, event)) = event {
               match event {
                 CentralEvent::DeviceDiscovered(peripheral_id) | CentralEvent::DeviceUpdated(peripheral_id) => {
-                  self.maybe_add_peripheral(&peripheral_id, &adapter, &mut tried_addresses).await;
+                  self.maybe_add_peripheral(&peripheral_id, index, &adapters[index], &mut tried_addresses).await;
                 }
                 CentralEvent::DeviceDisconnected(peripheral_id) => {
                   debug!("BTLEPlug Device disconnected: {:?}", peripheral_id);
                   tried_addresses.retain(|info| info.peripheral_id != peripheral_id);
+                  self.device_adapters.retain(|_, (owner, id)| *owner != index || *id != peripheral_id);
                 }
                 event => {
                   trace!("Unhandled btleplug central event: {:?}", event)
@@ -254,13 +315,17 @@ impl BtleplugAdapterTask {
             match cmd {
               BtleplugAdapterCommand::StartScanning => {
                 tried_addresses.clear();
-                if let Err(err) = adapter.start_scan(ScanFilter::default()).await {
-                  error!("Start scanning request failed: {}", err);
+                for adapter in &adapters {
+                  if let Err(err) = adapter.start_scan(ScanFilter::default()).await {
+                    error!("Start scanning request failed: {}", err);
+                  }
                 }
               }
               BtleplugAdapterCommand::StopScanning => {
-                if let Err(err) = adapter.stop_scan().await {
-                  error!("Stop scanning request failed: {}", err);
+                for adapter in &adapters {
+                  if let Err(err) = adapter.stop_scan().await {
+                    error!("Stop scanning request failed: {}", err);
+                  }
                 }
               }
             }
@@ -0,0 +1,250 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Hardware for devices that only broadcast their state in advertisements.
+//!
+//! Some devices (sensors, buttons, etc) never expect a connection. Everything they have to say is
+//! put in the manufacturer data of their advertisements. For these, we skip GATT entirely and
+//! expose the latest manufacturer data payload as a read-only Rx endpoint. Reads return the last
+//! payload seen, and subscribing to Rx emits a notification every time a new advertisement comes
+//! in.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::hardware::{
+    HardwareEvent,
+    HardwareInternal,
+    HardwareReadCmd,
+    HardwareReading,
+    HardwareSubscribeCmd,
+    HardwareUnsubscribeCmd,
+    HardwareWriteCmd,
+  },
+  util::async_manager,
+};
+use btleplug::{
+  api::{Central, CentralEvent},
+  platform::{Adapter, PeripheralId},
+};
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  select,
+  StreamExt,
+};
+use instant::Instant;
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::{
+  sync::{broadcast, RwLock},
+  time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+
+/// If we haven't seen an advertisement from a device in this long, consider it gone.
+const ADVERTISEMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pick the payload we care about out of an advertisement's manufacturer data. If the specifier
+/// listed company IDs, only those are considered, otherwise the first payload found is used.
+fn select_payload(
+  company_ids: &[u16],
+  manufacturer_data: &HashMap<u16, Vec<u8>>,
+) -> Option<Vec<u8>> {
+  if company_ids.is_empty() {
+    manufacturer_data.values().next().cloned()
+  } else {
+    company_ids
+      .iter()
+      .find_map(|company| manufacturer_data.get(company).cloned())
+  }
+}
+
+pub struct BtleplugAdvertisementHardware {
+  latest_payload: Arc<RwLock<Vec<u8>>>,
+  subscribed: Arc<AtomicBool>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  cancellation_token: CancellationToken,
+}
+
+impl BtleplugAdvertisementHardware {
+  pub fn new(
+    address: &str,
+    peripheral_id: PeripheralId,
+    adapter: Adapter,
+    company_ids: Vec<u16>,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+  ) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let latest_payload = Arc::new(RwLock::new(
+      select_payload(&company_ids, manufacturer_data).unwrap_or_default(),
+    ));
+    let subscribed = Arc::new(AtomicBool::new(false));
+    let cancellation_token = CancellationToken::new();
+
+    let address = address.to_owned();
+    let task_payload = latest_payload.clone();
+    let task_subscribed = subscribed.clone();
+    let task_sender = event_sender.clone();
+    let task_token = cancellation_token.child_token();
+    async_manager::spawn(async move {
+      let mut events = match adapter.events().await {
+        Ok(events) => events,
+        Err(err) => {
+          error!(
+            "Cannot listen for advertisements for {}: {:?}",
+            address, err
+          );
+          let _ = task_sender.send(HardwareEvent::Disconnected(
+            address,
+            DeviceRemovedReason::LinkLost,
+            false,
+          ));
+          return;
+        }
+      };
+      let mut last_seen = Instant::now();
+      loop {
+        select! {
+          event = events.next().fuse() => match event {
+            Some(CentralEvent::ManufacturerDataAdvertisement { id, manufacturer_data }) if id == peripheral_id => {
+              if let Some(payload) = select_payload(&company_ids, &manufacturer_data) {
+                last_seen = Instant::now();
+                *task_payload.write().await = payload.clone();
+                if task_subscribed.load(Ordering::SeqCst) {
+                  let _ = task_sender.send(HardwareEvent::Notification(address.clone(), Endpoint::Rx, payload));
+                }
+              }
+            }
+            Some(_) => {}
+            None => {
+              info!("Adapter event stream closed, ending advertisement listener for {}", address);
+              break;
+            }
+          },
+          _ = sleep(Duration::from_secs(1)).fuse() => {}
+          _ = task_token.cancelled().fuse() => return,
+        }
+        if Instant::now().duration_since(last_seen) > ADVERTISEMENT_TIMEOUT {
+          info!(
+            "No advertisements from {} in {:?}, considering it gone.",
+            address, ADVERTISEMENT_TIMEOUT
+          );
+          break;
+        }
+      }
+      let _ = task_sender.send(HardwareEvent::Disconnected(
+        address,
+        DeviceRemovedReason::LinkLost,
+        false,
+      ));
+    });
+
+    Self {
+      latest_payload,
+      subscribed,
+      event_sender,
+      cancellation_token,
+    }
+  }
+}
+
+impl HardwareInternal for BtleplugAdvertisementHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    // Nothing to disconnect from, just stop listening.
+    self.cancellation_token.cancel();
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    if msg.endpoint != Endpoint::Rx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+    }
+    let latest_payload = self.latest_payload.clone();
+    async move {
+      Ok(HardwareReading::new(
+        Endpoint::Rx,
+        &latest_payload.read().await,
+      ))
+    }
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    _msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Advertisement only devices are read-only".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint != Endpoint::Rx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+    }
+    self.subscribed.store(true, Ordering::SeqCst);
+    future::ready(Ok(())).boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint != Endpoint::Rx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint))).boxed();
+    }
+    self.subscribed.store(false, Ordering::SeqCst);
+    future::ready(Ok(())).boxed()
+  }
+}
+
+impl Drop for BtleplugAdvertisementHardware {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::select_payload;
+  use std::collections::HashMap;
+
+  #[test]
+  pub fn test_select_payload() {
+    let mut data = HashMap::new();
+    data.insert(0x0059u16, vec![1, 2, 3]);
+    data.insert(0x004cu16, vec![4, 5]);
+    assert_eq!(select_payload(&[0x004c], &data), Some(vec![4, 5]));
+    assert_eq!(
+      select_payload(&[0x1234, 0x0059], &data),
+      Some(vec![1, 2, 3])
+    );
+    assert_eq!(select_payload(&[0x1234], &data), None);
+    let mut single = HashMap::new();
+    single.insert(0x0059u16, vec![1]);
+    assert_eq!(select_payload(&[], &single), Some(vec![1]));
+  }
+}
@@ -8,13 +8,20 @@
 use super::btleplug_adapter_task::{BtleplugAdapterCommand, BtleplugAdapterTask};
 use crate::{
   core::{errors::ButtplugDeviceError, ButtplugResultFuture},
-  server::device::hardware::communication::{
-    HardwareCommunicationManager,
-    HardwareCommunicationManagerBuilder,
-    HardwareCommunicationManagerEvent,
+  server::device::{
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+    },
+    RememberedDeviceStore,
   },
   util::async_manager,
 };
+use btleplug::{
+  api::{Central, Manager as _},
+  platform::Manager,
+};
 use futures::future::FutureExt;
 use std::sync::{
   atomic::{AtomicBool, Ordering},
@@ -22,9 +29,39 @@ use std::sync::{
 };
 use tokio::sync::mpsc::{channel, Sender};
 
+/// Which bluetooth adapters to scan and connect with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum BtleplugAdapterSelection {
+  /// The first adapter the system lists.
+  #[default]
+  First,
+  /// The adapter at this position in the list from [bluetooth_adapters].
+  Index(usize),
+  /// The first adapter whose description from [bluetooth_adapters] contains this text, ignoring
+  /// case.
+  Name(String),
+  /// Every adapter at once. Devices stay with whichever adapter saw them first.
+  All,
+}
+
+/// Descriptions of the bluetooth adapters on the system, in the order used by
+/// [BtleplugAdapterSelection::Index].
+pub async fn bluetooth_adapters() -> Result<Vec<String>, ButtplugDeviceError> {
+  let connection_error =
+    |err: btleplug::Error| ButtplugDeviceError::DeviceConnectionError(err.to_string());
+  let manager = Manager::new().await.map_err(connection_error)?;
+  let mut descriptions = vec![];
+  for adapter in manager.adapters().await.map_err(connection_error)? {
+    descriptions.push(adapter.adapter_info().await.map_err(connection_error)?);
+  }
+  Ok(descriptions)
+}
+
 #[derive(Default, Clone)]
 pub struct BtlePlugCommunicationManagerBuilder {
   require_keepalive: bool,
+  gatt_cache: Option<RememberedDeviceStore>,
+  adapter_selection: BtleplugAdapterSelection,
 }
 
 impl BtlePlugCommunicationManagerBuilder {
@@ -32,6 +69,20 @@ impl BtlePlugCommunicationManagerBuilder {
     self.require_keepalive = require;
     self
   }
+
+  /// Cache the GATT services of connected devices in the remembered device store. Reconnecting
+  /// devices skip service discovery if their cached services are still there, and the cache is
+  /// cleared for devices where it turns out to be stale.
+  pub fn gatt_cache(&mut self, store: RememberedDeviceStore) -> &mut Self {
+    self.gatt_cache = Some(store);
+    self
+  }
+
+  /// Pick the adapter(s) to use, instead of the first one the system lists.
+  pub fn adapter(&mut self, selection: BtleplugAdapterSelection) -> &mut Self {
+    self.adapter_selection = selection;
+    self
+  }
 }
 
 impl HardwareCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder {
@@ -42,6 +93,8 @@ impl HardwareCommunicationManagerBuilder for BtlePlugCommunicationManagerBuilder
     Box::new(BtlePlugCommunicationManager::new(
       sender,
       self.require_keepalive,
+      self.gatt_cache.clone(),
+      self.adapter_selection.clone(),
     ))
   }
 }
@@ -56,6 +109,8 @@ impl BtlePlugCommunicationManager {
   pub fn new(
     event_sender: Sender<HardwareCommunicationManagerEvent>,
     require_keepalive: bool,
+    gatt_cache: Option<RememberedDeviceStore>,
+    adapter_selection: BtleplugAdapterSelection,
   ) -> Self {
     let (sender, receiver) = channel(256);
     let adapter_connected = Arc::new(AtomicBool::new(false));
@@ -66,6 +121,8 @@ impl BtlePlugCommunicationManager {
         receiver,
         adapter_connected_clone,
         require_keepalive,
+        gatt_cache,
+        adapter_selection,
       );
       task.run().await;
     });
@@ -139,6 +196,10 @@ impl HardwareCommunicationManager for BtlePlugCommunicationManager {
   fn can_scan(&self) -> bool {
     self.adapter_connected.load(Ordering::SeqCst)
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("btle")
+  }
 }
 /*
 impl Drop for BtlePlugCommunicationManager {
@@ -5,13 +5,18 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+use super::btleplug_advertisement_hardware::BtleplugAdvertisementHardware;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::hardware::communication::HardwareSpecificError,
   server::device::{
     configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
     hardware::{
       Hardware,
+      HardwareConnectionParameters,
       HardwareConnector,
       HardwareEvent,
       HardwareInternal,
@@ -22,13 +27,15 @@ use crate::{
       HardwareUnsubscribeCmd,
       HardwareWriteCmd,
     },
+    GattServiceCache,
+    RememberedDeviceStore,
   },
   util::async_manager,
 };
 use async_trait::async_trait;
 use btleplug::api::CharPropFlags;
 use btleplug::{
-  api::{Central, CentralEvent, Characteristic, Peripheral, ValueNotification, WriteType},
+  api::{Central, CentralEvent, Characteristic, Peripheral, Service, ValueNotification, WriteType},
   platform::Adapter,
 };
 use dashmap::DashSet;
@@ -38,7 +45,7 @@ use futures::{
   StreamExt,
 };
 use std::{
-  collections::HashMap,
+  collections::{BTreeSet, HashMap},
   fmt::{self, Debug},
   pin::Pin,
   sync::Arc,
@@ -56,6 +63,7 @@ pub(super) struct BtleplugHardwareConnector<T: Peripheral + 'static> {
   device: T,
   adapter: Adapter,
   requires_keepalive: bool,
+  gatt_cache: Option<RememberedDeviceStore>,
 }
 
 impl<T: Peripheral> BtleplugHardwareConnector<T> {
@@ -66,6 +74,7 @@ impl<T: Peripheral> BtleplugHardwareConnector<T> {
     device: T,
     adapter: Adapter,
     requires_keepalive: bool,
+    gatt_cache: Option<RememberedDeviceStore>,
   ) -> Self {
     Self {
       name: name.to_owned(),
@@ -74,6 +83,7 @@ impl<T: Peripheral> BtleplugHardwareConnector<T> {
       device,
       adapter,
       requires_keepalive,
+      gatt_cache,
     }
   }
 }
@@ -98,51 +108,204 @@ impl<T: Peripheral> HardwareConnector for BtleplugHardwareConnector<T> {
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
-    if !self
-      .device
-      .is_connected()
-      .await
-      .expect("If we crash here it's Bluez's fault. Use something else please.")
-    {
-      if let Err(err) = self.device.connect().await {
-        let return_err = ButtplugDeviceError::DeviceSpecificError(
-          HardwareSpecificError::BtleplugError(format!("{:?}", err)),
-        );
-        return Err(return_err);
-      }
-      if let Err(err) = self.device.discover_services().await {
-        error!("BTLEPlug error discovering characteristics: {:?}", err);
-        return Err(ButtplugDeviceError::DeviceConnectionError(format!(
-          "BTLEPlug error discovering characteristics: {:?}",
-          err
-        )));
-      }
-    }
+    // Connecting happens during specialization, as advertisement only devices never get a GATT
+    // connection, and we won't know if that's what we have until we've matched a specifier.
+    // Connection and discovery failures come back as DeviceSpecificError, which stops protocol
+    // matching, same as failing here would.
     Ok(Box::new(BtleplugHardwareSpecializer::new(
       &self.name,
+      &self.manufacturer_data,
       self.device.clone(),
       self.adapter.clone(),
       self.requires_keepalive,
+      self.gatt_cache.clone(),
     )))
   }
 }
 
 pub struct BtleplugHardwareSpecializer<T: Peripheral + 'static> {
   name: String,
+  manufacturer_data: HashMap<u16, Vec<u8>>,
   device: T,
   adapter: Adapter,
   requires_keepalive: bool,
+  gatt_cache: Option<RememberedDeviceStore>,
 }
 
 impl<T: Peripheral> BtleplugHardwareSpecializer<T> {
-  pub(super) fn new(name: &str, device: T, adapter: Adapter, requires_keepalive: bool) -> Self {
+  pub(super) fn new(
+    name: &str,
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+    device: T,
+    adapter: Adapter,
+    requires_keepalive: bool,
+    gatt_cache: Option<RememberedDeviceStore>,
+  ) -> Self {
     Self {
       name: name.to_owned(),
+      manufacturer_data: manufacturer_data.clone(),
       device,
       adapter,
       requires_keepalive,
+      gatt_cache,
+    }
+  }
+
+  fn address(&self) -> String {
+    format!("{:?}", self.device.id())
+  }
+
+  /// Connect to the device if we aren't already. Returns true if a new connection was made.
+  async fn connect_gatt(&self) -> Result<bool, ButtplugDeviceError> {
+    if self
+      .device
+      .is_connected()
+      .await
+      .expect("If we crash here it's Bluez's fault. Use something else please.")
+    {
+      return Ok(false);
+    }
+    if let Err(err) = self.device.connect().await {
+      let return_err = ButtplugDeviceError::DeviceSpecificError(
+        HardwareSpecificError::BtleplugError(format!("{:?}", err)),
+      );
+      return Err(return_err);
+    }
+    Ok(true)
+  }
+
+  /// Run full service discovery, and cache whatever it finds.
+  async fn discover_and_cache_services(&self) -> Result<(), ButtplugDeviceError> {
+    if let Err(err) = self.device.discover_services().await {
+      error!("BTLEPlug error discovering characteristics: {:?}", err);
+      self.invalidate_cached_services();
+      return Err(ButtplugDeviceError::DeviceSpecificError(
+        HardwareSpecificError::BtleplugError(format!(
+          "BTLEPlug error discovering characteristics: {:?}",
+          err
+        )),
+      ));
+    }
+    if let Some(cache) = &self.gatt_cache {
+      let services = gatt_service_cache(&self.device.services());
+      if let Err(err) = cache.set_gatt_services(&self.address(), Some(services)) {
+        warn!("Cannot cache GATT services for {}: {}", self.name, err);
+      }
+    }
+    Ok(())
+  }
+
+  /// Build the endpoint map from the GATT services persisted for the device on an earlier
+  /// connection. Returns false, leaving the maps empty, if there's no cache or it's missing any
+  /// characteristic the protocol uses.
+  fn map_cached_endpoints(
+    &self,
+    btle: &BluetoothLESpecifier,
+    endpoints: &mut HashMap<Endpoint, Characteristic>,
+    uuid_map: &mut HashMap<Uuid, Endpoint>,
+  ) -> bool {
+    let Some(cached) = self
+      .gatt_cache
+      .as_ref()
+      .and_then(|cache| cache.gatt_services(&self.address()))
+    else {
+      return false;
+    };
+    match endpoints_from_gatt_cache(btle, &cached) {
+      Some((cached_endpoints, cached_uuid_map)) => {
+        *endpoints = cached_endpoints;
+        *uuid_map = cached_uuid_map;
+        true
+      }
+      None => {
+        info!(
+          "Cached GATT services for {} {} are stale, rediscovering.",
+          self.name,
+          self.address()
+        );
+        false
+      }
+    }
+  }
+
+  fn invalidate_cached_services(&self) {
+    if let Some(cache) = &self.gatt_cache {
+      if let Err(err) = cache.set_gatt_services(&self.address(), None) {
+        warn!(
+          "Cannot clear cached GATT services for {}: {}",
+          self.name, err
+        );
+      }
     }
   }
+
+  /// Find the characteristics for each endpoint the protocol uses in the services the platform
+  /// knows for the peripheral.
+  fn map_endpoints(
+    &self,
+    btle: &BluetoothLESpecifier,
+    endpoints: &mut HashMap<Endpoint, Characteristic>,
+    uuid_map: &mut HashMap<Uuid, Endpoint>,
+  ) {
+    for (proto_uuid, proto_service) in btle.services() {
+      for service in self.device.services() {
+        if service.uuid != *proto_uuid {
+          continue;
+        }
+
+        debug!("Found required service {} {:?}", service.uuid, service);
+        for (chr_name, chr_uuid) in proto_service.iter() {
+          if let Some(chr) = service.characteristics.iter().find(|c| c.uuid == *chr_uuid) {
+            debug!(
+              "Found characteristic {} for endpoint {}",
+              chr.uuid, *chr_name
+            );
+            endpoints.insert(*chr_name, chr.clone());
+            uuid_map.insert(*chr_uuid, *chr_name);
+          } else {
+            error!(
+              "Characteristic {} ({}) not found, may cause issues in connection.",
+              chr_name, chr_uuid
+            );
+          }
+        }
+      }
+    }
+  }
+
+  /// Last RSSI reported for the peripheral, if the platform gives us one.
+  async fn rssi(&self) -> Option<i32> {
+    match self.device.properties().await {
+      Ok(Some(properties)) => properties.rssi.map(i32::from),
+      _ => None,
+    }
+  }
+
+  fn specialize_advertisement_only(&self, btle: &BluetoothLESpecifier) -> Hardware {
+    let address = self.device.id();
+    info!(
+      "Device {} {:?} is advertisement only, skipping connection.",
+      self.name, address
+    );
+    let company_ids = btle
+      .manufacturer_data()
+      .iter()
+      .map(|data| *data.company())
+      .collect();
+    let device_internal_impl = BtleplugAdvertisementHardware::new(
+      &format!("{:?}", address),
+      address.clone(),
+      self.adapter.clone(),
+      company_ids,
+      &self.manufacturer_data,
+    );
+    Hardware::new(
+      &self.name,
+      &format!("{:?}", address),
+      &[Endpoint::Rx],
+      Box::new(device_internal_impl),
+    )
+  }
 }
 
 #[async_trait]
@@ -160,29 +323,22 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
       .iter()
       .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
     {
-      for (proto_uuid, proto_service) in btle.services() {
-        for service in self.device.services() {
-          if service.uuid != *proto_uuid {
-            continue;
-          }
-
-          debug!("Found required service {} {:?}", service.uuid, service);
-          for (chr_name, chr_uuid) in proto_service.iter() {
-            if let Some(chr) = service.characteristics.iter().find(|c| c.uuid == *chr_uuid) {
-              debug!(
-                "Found characteristic {} for endpoint {}",
-                chr.uuid, *chr_name
-              );
-              endpoints.insert(*chr_name, chr.clone());
-              uuid_map.insert(*chr_uuid, *chr_name);
-            } else {
-              error!(
-                "Characteristic {} ({}) not found, may cause issues in connection.",
-                chr_name, chr_uuid
-              );
-            }
-          }
+      if *btle.advertisement_only() {
+        let mut hardware = self.specialize_advertisement_only(btle);
+        hardware.set_rssi(self.rssi().await);
+        return Ok(hardware);
+      }
+      let newly_connected = self.connect_gatt().await?;
+      if self.map_cached_endpoints(btle, &mut endpoints, &mut uuid_map) {
+        debug!(
+          "Found all endpoints for {} {:?} in cached GATT services, skipping service discovery.",
+          self.name, address
+        );
+      } else {
+        if newly_connected {
+          self.discover_and_cache_services().await?;
         }
+        self.map_endpoints(btle, &mut endpoints, &mut uuid_map);
       }
     } else {
       error!(
@@ -218,6 +374,7 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
       &endpoints.keys().cloned().collect::<Vec<Endpoint>>(),
       Box::new(device_internal_impl),
     );
+    hardware.set_rssi(self.rssi().await);
 
     // Let the hardware know if we need command resends or whatever. Fucking iOS.
     if self.requires_keepalive {
@@ -227,6 +384,50 @@ impl<T: Peripheral> HardwareSpecializer for BtleplugHardwareSpecializer<T> {
   }
 }
 
+/// Characteristics of the services, in the form they're cached in.
+fn gatt_service_cache(services: &BTreeSet<Service>) -> GattServiceCache {
+  services
+    .iter()
+    .map(|service| {
+      (
+        service.uuid,
+        service
+          .characteristics
+          .iter()
+          .map(|characteristic| (characteristic.uuid, characteristic.properties.bits()))
+          .collect(),
+      )
+    })
+    .collect()
+}
+
+/// Rebuild the characteristics for each endpoint the protocol uses from cached GATT services.
+/// Returns None if any of them is missing from the cache.
+fn endpoints_from_gatt_cache(
+  btle: &BluetoothLESpecifier,
+  cached: &GattServiceCache,
+) -> Option<(HashMap<Endpoint, Characteristic>, HashMap<Uuid, Endpoint>)> {
+  let mut endpoints = HashMap::new();
+  let mut uuid_map = HashMap::new();
+  for (proto_uuid, proto_service) in btle.services() {
+    let characteristics = cached.get(proto_uuid)?;
+    for (chr_name, chr_uuid) in proto_service.iter() {
+      let properties = characteristics.get(chr_uuid)?;
+      endpoints.insert(
+        *chr_name,
+        Characteristic {
+          uuid: *chr_uuid,
+          service_uuid: *proto_uuid,
+          properties: CharPropFlags::from_bits_truncate(*properties),
+          descriptors: BTreeSet::new(),
+        },
+      );
+      uuid_map.insert(*chr_uuid, *chr_name);
+    }
+  }
+  Some((endpoints, uuid_map))
+}
+
 pub struct BtlePlugHardware<T: Peripheral + 'static> {
   device: T,
   event_stream: broadcast::Sender<HardwareEvent>,
@@ -292,7 +493,9 @@ impl<T: Peripheral + 'static> BtlePlugHardware<T> {
                 if event_stream_clone.receiver_count() != 0 {
                   if let Err(err) = event_stream_clone
                   .send(HardwareEvent::Disconnected(
-                    format!("{:?}", address)
+                    format!("{:?}", address),
+                    DeviceRemovedReason::LinkLost,
+                    false,
                   )) {
                     error!(
                       "Cannot send notification, device object disappeared: {:?}",
@@ -398,6 +601,45 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     .boxed()
   }
 
+  fn write_values(
+    &self,
+    msgs: &[HardwareWriteCmd],
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    // Writes with response take a full connection event round trip each. Everything but the last
+    // write in the batch goes without response where the characteristic allows it, so the writes
+    // can go out back to back, and the last write still tells us the batch arrived if it asked to.
+    let writes: Vec<_> = msgs
+      .iter()
+      .enumerate()
+      .map(|(index, msg)| {
+        let without_response_supported = self.endpoints.get(&msg.endpoint).is_some_and(|chr| {
+          chr
+            .properties
+            .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        });
+        if index + 1 < msgs.len() && msg.write_with_response && without_response_supported {
+          self.write_value(&HardwareWriteCmd::new(
+            msg.endpoint,
+            msg.data.clone(),
+            false,
+          ))
+        } else {
+          self.write_value(msg)
+        }
+      })
+      .collect();
+    // Each write is sent once the one before it is done, so they reach the platform in order, and
+    // nothing is sent after a failed write. Writes without response are done as soon as the
+    // platform has queued them, so this doesn't wait on the device.
+    async move {
+      for write in writes {
+        write.await?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
   fn read_value(
     &self,
     msg: &HardwareReadCmd,
@@ -494,6 +736,18 @@ impl<T: Peripheral + 'static> HardwareInternal for BtlePlugHardware<T> {
     }
     .boxed()
   }
+
+  fn request_connection_parameters(
+    &self,
+    _params: &HardwareConnectionParameters,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    // btleplug leaves MTU and connection interval negotiation to the OS, and has no API for
+    // requesting either.
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "btleplug cannot set connection parameters".to_owned(),
+    )))
+    .boxed()
+  }
 }
 
 impl<T: Peripheral> Drop for BtlePlugHardware<T> {
@@ -506,3 +760,232 @@ impl<T: Peripheral> Drop for BtlePlugHardware<T> {
     });
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use btleplug::{
+    api::{BDAddr, Descriptor, PeripheralProperties},
+    platform::PeripheralId,
+  };
+  use std::{collections::HashSet, sync::Mutex};
+
+  // Writing this byte fails.
+  const FAILING_WRITE: u8 = 0xff;
+
+  type RecordedWrites = Arc<Mutex<Vec<(Vec<u8>, WriteType)>>>;
+
+  #[derive(Debug, Clone, Default)]
+  struct MockPeripheral {
+    writes: RecordedWrites,
+  }
+
+  #[async_trait]
+  impl Peripheral for MockPeripheral {
+    fn id(&self) -> PeripheralId {
+      unimplemented!("Not used by hardware writes")
+    }
+
+    fn address(&self) -> BDAddr {
+      BDAddr::default()
+    }
+
+    async fn properties(&self) -> btleplug::Result<Option<PeripheralProperties>> {
+      Ok(None)
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+      BTreeSet::new()
+    }
+
+    async fn is_connected(&self) -> btleplug::Result<bool> {
+      Ok(true)
+    }
+
+    async fn connect(&self) -> btleplug::Result<()> {
+      Ok(())
+    }
+
+    async fn disconnect(&self) -> btleplug::Result<()> {
+      Ok(())
+    }
+
+    async fn discover_services(&self) -> btleplug::Result<()> {
+      Ok(())
+    }
+
+    async fn write(
+      &self,
+      _: &Characteristic,
+      data: &[u8],
+      write_type: WriteType,
+    ) -> btleplug::Result<()> {
+      if data == [FAILING_WRITE] {
+        return Err(btleplug::Error::NotConnected);
+      }
+      self
+        .writes
+        .lock()
+        .expect("Lock is never held across a panic")
+        .push((data.to_vec(), write_type));
+      Ok(())
+    }
+
+    async fn read(&self, _: &Characteristic) -> btleplug::Result<Vec<u8>> {
+      Ok(vec![])
+    }
+
+    async fn subscribe(&self, _: &Characteristic) -> btleplug::Result<()> {
+      Ok(())
+    }
+
+    async fn unsubscribe(&self, _: &Characteristic) -> btleplug::Result<()> {
+      Ok(())
+    }
+
+    async fn notifications(
+      &self,
+    ) -> btleplug::Result<Pin<Box<dyn Stream<Item = ValueNotification> + Send>>> {
+      Ok(Box::pin(futures::stream::empty()))
+    }
+
+    async fn write_descriptor(&self, _: &Descriptor, _: &[u8]) -> btleplug::Result<()> {
+      Ok(())
+    }
+
+    async fn read_descriptor(&self, _: &Descriptor) -> btleplug::Result<Vec<u8>> {
+      Ok(vec![])
+    }
+  }
+
+  fn mock_hardware(
+    properties: CharPropFlags,
+  ) -> (BtlePlugHardware<MockPeripheral>, MockPeripheral) {
+    let device = MockPeripheral::default();
+    let hardware = BtlePlugHardware {
+      device: device.clone(),
+      event_stream: broadcast::channel(1).0,
+      endpoints: HashMap::from([(
+        Endpoint::Tx,
+        Characteristic {
+          uuid: Uuid::from_u128(0x2),
+          service_uuid: Uuid::from_u128(0x1),
+          properties,
+          descriptors: BTreeSet::new(),
+        },
+      )]),
+      subscribed_endpoints: Arc::new(DashSet::new()),
+    };
+    (hardware, device)
+  }
+
+  fn writes(data: &[u8], write_with_response: bool) -> Vec<HardwareWriteCmd> {
+    data
+      .iter()
+      .map(|byte| HardwareWriteCmd::new(Endpoint::Tx, vec![*byte], write_with_response))
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn test_write_values_pipelines_writes_with_response() {
+    let (hardware, device) =
+      mock_hardware(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE);
+    hardware
+      .write_values(&writes(&[1, 2, 3], true))
+      .await
+      .expect("Test, assuming infallible.");
+    // Only the last write waits for a response.
+    assert_eq!(
+      *device.writes.lock().expect("Test, assuming infallible."),
+      vec![
+        (vec![1], WriteType::WithoutResponse),
+        (vec![2], WriteType::WithoutResponse),
+        (vec![3], WriteType::WithResponse),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_write_values_keeps_write_type_when_unsupported() {
+    let (hardware, device) = mock_hardware(CharPropFlags::WRITE);
+    hardware
+      .write_values(&writes(&[1, 2], true))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      *device.writes.lock().expect("Test, assuming infallible."),
+      vec![
+        (vec![1], WriteType::WithResponse),
+        (vec![2], WriteType::WithResponse),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_write_values_stops_after_failed_write() {
+    let (hardware, device) = mock_hardware(CharPropFlags::WRITE_WITHOUT_RESPONSE);
+    assert!(hardware
+      .write_values(&writes(&[1, FAILING_WRITE, 3], false))
+      .await
+      .is_err());
+    assert_eq!(
+      *device.writes.lock().expect("Test, assuming infallible."),
+      vec![(vec![1], WriteType::WithoutResponse)]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_connection_parameters_unsupported() {
+    let (hardware, _) = mock_hardware(CharPropFlags::WRITE);
+    let mut params = HardwareConnectionParameters::default();
+    params.set_mtu(Some(247));
+    assert!(matches!(
+      hardware.request_connection_parameters(&params).await,
+      Err(ButtplugDeviceError::UnhandledCommand(_))
+    ));
+  }
+
+  fn specifier(service: Uuid, characteristics: &[(Endpoint, Uuid)]) -> BluetoothLESpecifier {
+    BluetoothLESpecifier::new(
+      HashSet::new(),
+      vec![],
+      HashSet::new(),
+      HashMap::from([(service, characteristics.iter().cloned().collect())]),
+    )
+  }
+
+  #[test]
+  fn test_endpoints_from_gatt_cache() {
+    let service = Uuid::from_u128(0x1);
+    let tx = Uuid::from_u128(0x2);
+    let rx = Uuid::from_u128(0x3);
+    let cached = GattServiceCache::from([(
+      service,
+      [
+        (tx, CharPropFlags::WRITE_WITHOUT_RESPONSE.bits()),
+        (rx, CharPropFlags::NOTIFY.bits()),
+      ]
+      .into(),
+    )]);
+    let (endpoints, uuid_map) = endpoints_from_gatt_cache(
+      &specifier(service, &[(Endpoint::Tx, tx), (Endpoint::Rx, rx)]),
+      &cached,
+    )
+    .expect("Test, assuming infallible.");
+    let tx_chr = &endpoints[&Endpoint::Tx];
+    assert_eq!(tx_chr.uuid, tx);
+    assert_eq!(tx_chr.service_uuid, service);
+    assert_eq!(tx_chr.properties, CharPropFlags::WRITE_WITHOUT_RESPONSE);
+    assert_eq!(endpoints[&Endpoint::Rx].properties, CharPropFlags::NOTIFY);
+    assert_eq!(uuid_map[&rx], Endpoint::Rx);
+
+    // Anything the protocol needs that isn't cached means the cache is stale.
+    let command = Uuid::from_u128(0x4);
+    assert!(endpoints_from_gatt_cache(
+      &specifier(service, &[(Endpoint::Tx, tx), (Endpoint::Command, command)]),
+      &cached,
+    )
+    .is_none());
+    assert!(endpoints_from_gatt_cache(&specifier(Uuid::from_u128(0x5), &[]), &cached).is_none());
+  }
+}
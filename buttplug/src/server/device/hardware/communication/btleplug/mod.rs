@@ -6,6 +6,11 @@
 // for full license information.
 
 pub mod btleplug_comm_manager;
-pub use btleplug_comm_manager::BtlePlugCommunicationManagerBuilder;
+pub use btleplug_comm_manager::{
+  bluetooth_adapters,
+  BtlePlugCommunicationManagerBuilder,
+  BtleplugAdapterSelection,
+};
 mod btleplug_adapter_task;
+mod btleplug_advertisement_hardware;
 pub mod btleplug_hardware;
@@ -0,0 +1,232 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  dmx_hardware::DmxHardwareConnector,
+  dmx_universe::{DmxProtocol, DmxUniverses, DMX_UNIVERSE_SIZE},
+};
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager, HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent, TimedRetryCommunicationManager,
+    TimedRetryCommunicationManagerImpl,
+  },
+};
+use async_trait::async_trait;
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::mpsc::Sender;
+
+/// Which DMX universe and channels a device uses.
+#[derive(Debug, Clone)]
+pub struct DmxDefinition {
+  name: String,
+  protocol: DmxProtocol,
+  universe: u16,
+  start_channel: u16,
+  node: Option<IpAddr>,
+}
+
+impl DmxDefinition {
+  /// Define a device named name (which is matched against the `dmx` names in the device
+  /// configuration), on universe, sent over protocol.
+  ///
+  /// Until set otherwise, the device's channels start at channel 1, and the universe is broadcast
+  /// (Art-Net) or multicast (sACN) rather than sent to a specific node.
+  pub fn new(name: &str, protocol: DmxProtocol, universe: u16) -> Self {
+    Self {
+      name: name.to_owned(),
+      protocol,
+      universe,
+      start_channel: 1,
+      node: None,
+    }
+  }
+
+  /// First channel the device uses, numbered from 1 to 512 like on fixtures. The device's actuators
+  /// use the channels from there on, in order.
+  pub fn start_channel(&mut self, channel: u16) -> &mut Self {
+    self.start_channel = channel;
+    self
+  }
+
+  /// Send the universe to the node at address, on the protocol's standard port.
+  pub fn node(&mut self, address: IpAddr) -> &mut Self {
+    self.node = Some(address);
+    self
+  }
+
+  pub(super) fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub(super) fn protocol(&self) -> DmxProtocol {
+    self.protocol
+  }
+
+  pub(super) fn universe(&self) -> u16 {
+    self.universe
+  }
+
+  /// Index of the first channel in the universe, counting from 0.
+  pub(super) fn start_index(&self) -> usize {
+    self.start_channel as usize - 1
+  }
+
+  pub(super) fn target(&self) -> SocketAddr {
+    SocketAddr::new(
+      self
+        .node
+        .unwrap_or_else(|| self.protocol.default_target(self.universe)),
+      self.protocol.port(),
+    )
+  }
+
+  /// Unique for every set of channels, so devices can share universes.
+  pub(super) fn address(&self) -> String {
+    format!(
+      "{}://{}/{}/{}",
+      self.protocol.name(),
+      self.target().ip(),
+      self.universe,
+      self.start_channel
+    )
+  }
+
+  fn validate(&self) -> Result<(), ButtplugDeviceError> {
+    if !self.protocol.is_valid_universe(self.universe) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Universe {} is not valid for {}",
+        self.universe,
+        self.protocol.name()
+      )));
+    }
+    if !(1..=DMX_UNIVERSE_SIZE as u16).contains(&self.start_channel) {
+      return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Start channel {} is not between 1 and {}",
+        self.start_channel, DMX_UNIVERSE_SIZE
+      )));
+    }
+    Ok(())
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct DmxCommunicationManagerBuilder {
+  devices: Vec<DmxDefinition>,
+}
+
+impl DmxCommunicationManagerBuilder {
+  /// Add a device, which is found on every scan.
+  pub fn device(&mut self, definition: DmxDefinition) -> &mut Self {
+    self.devices.push(definition);
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for DmxCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    let devices = self
+      .devices
+      .iter()
+      .filter(|definition| match definition.validate() {
+        Ok(()) => true,
+        Err(err) => {
+          error!("Cannot set up DMX device {}: {}", definition.name, err);
+          false
+        }
+      })
+      .cloned()
+      .collect();
+    Box::new(TimedRetryCommunicationManager::new(
+      DmxCommunicationManager::new(sender, devices),
+    ))
+  }
+}
+
+pub struct DmxCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<DmxDefinition>,
+  universes: DmxUniverses,
+}
+
+impl DmxCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>, devices: Vec<DmxDefinition>) -> Self {
+    Self {
+      sender,
+      devices,
+      universes: DmxUniverses::default(),
+    }
+  }
+}
+
+#[async_trait]
+impl TimedRetryCommunicationManagerImpl for DmxCommunicationManager {
+  fn name(&self) -> &'static str {
+    "DmxCommunicationManager"
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("dmx")
+  }
+
+  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+    // DMX is send only, so there's no way to tell whether anything is listening. Devices are found
+    // every scan, the device manager ignores the ones that are already connected.
+    for definition in &self.devices {
+      if self
+        .sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name: definition.name.clone(),
+          address: definition.address(),
+          creator: Box::new(DmxHardwareConnector::new(
+            definition.clone(),
+            self.universes.clone(),
+          )),
+        })
+        .await
+        .is_err()
+      {
+        error!("Error sending device found message from DMX Manager.");
+        break;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::net::Ipv4Addr;
+
+  #[test]
+  fn test_dmx_definitions() {
+    let mut definition = DmxDefinition::new("DMX Fixture", DmxProtocol::Sacn, 2);
+    assert_eq!(definition.address(), "sacn://239.255.0.2/2/1");
+    definition
+      .start_channel(10)
+      .node(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5)));
+    assert_eq!(definition.start_index(), 9);
+    assert_eq!(
+      definition.target(),
+      "10.0.0.5:5568".parse().expect("Test, assuming infallible.")
+    );
+    assert!(definition.validate().is_ok());
+    assert!(definition.start_channel(513).validate().is_err());
+    assert!(DmxDefinition::new("DMX Fixture", DmxProtocol::Sacn, 0)
+      .validate()
+      .is_err());
+  }
+}
@@ -0,0 +1,197 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  dmx_comm_manager::DmxDefinition,
+  dmx_universe::{shared_universe, DmxUniverse, DmxUniverses},
+};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{DmxSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      GenericHardwareSpecializer, Hardware, HardwareConnector, HardwareEvent, HardwareInternal,
+      HardwareReadCmd, HardwareReading, HardwareSpecializer, HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd, HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  fmt::{self, Debug},
+  sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::broadcast;
+
+pub struct DmxHardwareConnector {
+  definition: DmxDefinition,
+  universes: DmxUniverses,
+}
+
+impl DmxHardwareConnector {
+  pub(super) fn new(definition: DmxDefinition, universes: DmxUniverses) -> Self {
+    Self {
+      definition,
+      universes,
+    }
+  }
+}
+
+impl Debug for DmxHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DmxHardwareConnector")
+      .field("definition", &self.definition)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for DmxHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::Dmx(DmxSpecifier::new(&[self.definition.name().to_owned()]))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    let universe = shared_universe(
+      &self.universes,
+      self.definition.protocol(),
+      self.definition.target(),
+      self.definition.universe(),
+    )?;
+    let address = self.definition.address();
+    let hardware = Hardware::new(
+      self.definition.name(),
+      &address,
+      &[Endpoint::Tx],
+      Box::new(DmxHardware::new(
+        &address,
+        universe,
+        self.definition.start_index(),
+      )),
+    );
+    Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
+  }
+}
+
+pub struct DmxHardware {
+  address: String,
+  universe: Arc<DmxUniverse>,
+  start_index: usize,
+  // Highest number of channels written, which are the ones blacked out on disconnect.
+  channel_count: AtomicUsize,
+  connected: AtomicBool,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl DmxHardware {
+  fn new(address: &str, universe: Arc<DmxUniverse>, start_index: usize) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      address: address.to_owned(),
+      universe,
+      start_index,
+      channel_count: AtomicUsize::new(0),
+      connected: AtomicBool::new(true),
+      event_sender,
+    }
+  }
+
+  /// Zero all of the device's channels, so nothing is left running once it's gone.
+  fn blackout(&self) {
+    let channel_count = self.channel_count.load(Ordering::SeqCst);
+    if let Err(err) = self
+      .universe
+      .set_channels(self.start_index, &vec![0; channel_count])
+    {
+      error!("Cannot black out DMX device {}: {}", self.address, err);
+    }
+  }
+}
+
+impl Drop for DmxHardware {
+  fn drop(&mut self) {
+    self.blackout();
+  }
+}
+
+impl HardwareInternal for DmxHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.connected.store(false, Ordering::SeqCst);
+    self.blackout();
+    let _ = self.event_sender.send(HardwareEvent::Disconnected(
+      self.address.clone(),
+      DeviceRemovedReason::UserDisconnect,
+      false,
+    ));
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "DMX devices do not support read".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::Tx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
+    if !self.connected.load(Ordering::SeqCst) {
+      return future::ready(Err(ButtplugDeviceError::DeviceNotConnected(
+        self.address.clone(),
+      )))
+      .boxed();
+    }
+    let result = self
+      .universe
+      .set_channels(self.start_index, msg.data())
+      .map(|_| {
+        self
+          .channel_count
+          .fetch_max(msg.data().len(), Ordering::SeqCst);
+      });
+    future::ready(result).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "DMX devices do not support subscribe".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "DMX devices do not support unsubscribe".to_owned(),
+    )))
+    .boxed()
+  }
+}
@@ -0,0 +1,381 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{core::errors::ButtplugDeviceError, util::async_manager};
+use futures::FutureExt;
+use std::{
+  collections::HashMap,
+  net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket as StdUdpSocket},
+  sync::{Arc, Mutex, Weak},
+  time::Duration,
+};
+use tokio::{net::UdpSocket, sync::Notify, time::sleep};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// Number of channels in a DMX universe.
+pub const DMX_UNIVERSE_SIZE: usize = 512;
+
+const ART_NET_PORT: u16 = 6454;
+const ART_NET_OPCODE_DMX: u16 = 0x5000;
+const ART_NET_PROTOCOL_VERSION: u16 = 14;
+
+const SACN_PORT: u16 = 5568;
+const SACN_SOURCE_NAME: &str = "Buttplug";
+const SACN_PRIORITY: u8 = 100;
+
+/// DMX512 tops out around 44 frames a second, so updates are coalesced into frames at most this
+/// often.
+const DMX_MIN_FRAME_INTERVAL: Duration = Duration::from_millis(25);
+/// Nodes treat a source as gone if they don't hear from it for a few seconds (2.5s for sACN), so
+/// unchanged universes are sent again this often.
+const DMX_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Network protocol DMX universes are sent over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmxProtocol {
+  /// Art-Net, universes 0-32767.
+  ArtNet,
+  /// sACN (E1.31), universes 1-63999.
+  Sacn,
+}
+
+impl DmxProtocol {
+  pub(super) fn name(&self) -> &'static str {
+    match self {
+      DmxProtocol::ArtNet => "artnet",
+      DmxProtocol::Sacn => "sacn",
+    }
+  }
+
+  pub(super) fn is_valid_universe(&self, universe: u16) -> bool {
+    match self {
+      DmxProtocol::ArtNet => universe <= 0x7fff,
+      DmxProtocol::Sacn => (1..=63999).contains(&universe),
+    }
+  }
+
+  /// Nodes always listen on their protocol's port.
+  pub(super) fn port(&self) -> u16 {
+    match self {
+      DmxProtocol::ArtNet => ART_NET_PORT,
+      DmxProtocol::Sacn => SACN_PORT,
+    }
+  }
+
+  /// Where frames go if no node is given: broadcast for Art-Net, or the universe's multicast group
+  /// for sACN.
+  pub(super) fn default_target(&self, universe: u16) -> IpAddr {
+    match self {
+      DmxProtocol::ArtNet => IpAddr::V4(Ipv4Addr::BROADCAST),
+      DmxProtocol::Sacn => {
+        let [high, low] = universe.to_be_bytes();
+        IpAddr::V4(Ipv4Addr::new(239, 255, high, low))
+      }
+    }
+  }
+}
+
+/// ArtDmx packet carrying a full universe.
+fn art_net_packet(universe: u16, sequence: u8, channels: &[u8; DMX_UNIVERSE_SIZE]) -> Vec<u8> {
+  let mut packet = Vec::with_capacity(18 + DMX_UNIVERSE_SIZE);
+  packet.extend_from_slice(b"Art-Net\0");
+  packet.extend_from_slice(&ART_NET_OPCODE_DMX.to_le_bytes());
+  packet.extend_from_slice(&ART_NET_PROTOCOL_VERSION.to_be_bytes());
+  packet.push(sequence);
+  // Physical input port, informational only.
+  packet.push(0);
+  // 15 bit port address, low byte first.
+  packet.push((universe & 0xff) as u8);
+  packet.push(((universe >> 8) & 0x7f) as u8);
+  packet.extend_from_slice(&(DMX_UNIVERSE_SIZE as u16).to_be_bytes());
+  packet.extend_from_slice(channels);
+  packet
+}
+
+/// E1.31 data packet carrying a full universe. Every PDU starts with its flags (0x7) and its length,
+/// counted from the start of the PDU to the end of the packet.
+fn sacn_packet(
+  cid: &[u8; 16],
+  universe: u16,
+  sequence: u8,
+  channels: &[u8; DMX_UNIVERSE_SIZE],
+) -> Vec<u8> {
+  const ROOT_LAYER_START: usize = 16;
+  const FRAMING_LAYER_START: usize = 38;
+  const DMP_LAYER_START: usize = 115;
+  let packet_length = 126 + DMX_UNIVERSE_SIZE;
+  let flags_and_length = |start: usize| (0x7000 | (packet_length - start) as u16).to_be_bytes();
+
+  let mut packet = Vec::with_capacity(packet_length);
+  // Root layer
+  packet.extend_from_slice(&0x0010u16.to_be_bytes());
+  packet.extend_from_slice(&0x0000u16.to_be_bytes());
+  packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+  packet.extend_from_slice(&flags_and_length(ROOT_LAYER_START));
+  packet.extend_from_slice(&0x0000_0004u32.to_be_bytes());
+  packet.extend_from_slice(cid);
+  // Framing layer
+  packet.extend_from_slice(&flags_and_length(FRAMING_LAYER_START));
+  packet.extend_from_slice(&0x0000_0002u32.to_be_bytes());
+  let mut source_name = [0u8; 64];
+  source_name[..SACN_SOURCE_NAME.len()].copy_from_slice(SACN_SOURCE_NAME.as_bytes());
+  packet.extend_from_slice(&source_name);
+  packet.push(SACN_PRIORITY);
+  // Synchronization address, unused.
+  packet.extend_from_slice(&0u16.to_be_bytes());
+  packet.push(sequence);
+  // Options
+  packet.push(0);
+  packet.extend_from_slice(&universe.to_be_bytes());
+  // DMP layer
+  packet.extend_from_slice(&flags_and_length(DMP_LAYER_START));
+  packet.push(0x02);
+  packet.push(0xa1);
+  // First property address and address increment.
+  packet.extend_from_slice(&0x0000u16.to_be_bytes());
+  packet.extend_from_slice(&0x0001u16.to_be_bytes());
+  // Start code plus channels.
+  packet.extend_from_slice(&(DMX_UNIVERSE_SIZE as u16 + 1).to_be_bytes());
+  packet.push(0);
+  packet.extend_from_slice(channels);
+  packet
+}
+
+/// A universe being sent to a node. Devices on the same universe share it, each setting their own
+/// channels. Sending stops, after one last frame, when the last device drops it.
+pub(super) struct DmxUniverse {
+  channels: Arc<Mutex<[u8; DMX_UNIVERSE_SIZE]>>,
+  updated: Arc<Notify>,
+  _cancel_guard: DropGuard,
+}
+
+impl DmxUniverse {
+  fn new(
+    protocol: DmxProtocol,
+    target: SocketAddr,
+    universe: u16,
+  ) -> Result<Self, ButtplugDeviceError> {
+    let socket = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+      .and_then(|socket| {
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket)
+      })
+      .map_err(|err| {
+        ButtplugDeviceError::DeviceConnectionError(format!("Cannot open DMX socket: {}", err))
+      })?;
+    let channels = Arc::new(Mutex::new([0u8; DMX_UNIVERSE_SIZE]));
+    let updated = Arc::new(Notify::new());
+    let token = CancellationToken::new();
+    async_manager::spawn(run_universe_output(
+      socket,
+      protocol,
+      target,
+      universe,
+      channels.clone(),
+      updated.clone(),
+      token.child_token(),
+    ));
+    Ok(Self {
+      channels,
+      updated,
+      _cancel_guard: token.drop_guard(),
+    })
+  }
+
+  /// Set channels starting at start (0 being the first channel of the universe). The change goes
+  /// out with the next frame.
+  pub(super) fn set_channels(
+    &self,
+    start: usize,
+    values: &[u8],
+  ) -> Result<(), ButtplugDeviceError> {
+    if start + values.len() > DMX_UNIVERSE_SIZE {
+      return Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+        "Cannot set {} channels from channel {}, universes only have {} channels",
+        values.len(),
+        start + 1,
+        DMX_UNIVERSE_SIZE
+      )));
+    }
+    self
+      .channels
+      .lock()
+      .expect("Lock is never held across a panic")[start..start + values.len()]
+      .copy_from_slice(values);
+    self.updated.notify_one();
+    Ok(())
+  }
+}
+
+/// Universes being sent, so devices on the same universe share one.
+pub(super) type DmxUniverses =
+  Arc<Mutex<HashMap<(DmxProtocol, SocketAddr, u16), Weak<DmxUniverse>>>>;
+
+/// Get the universe being sent to target, starting to send it if no device is using it yet.
+pub(super) fn shared_universe(
+  universes: &DmxUniverses,
+  protocol: DmxProtocol,
+  target: SocketAddr,
+  universe: u16,
+) -> Result<Arc<DmxUniverse>, ButtplugDeviceError> {
+  let mut universes = universes.lock().expect("Lock is never held across a panic");
+  let key = (protocol, target, universe);
+  if let Some(output) = universes.get(&key).and_then(Weak::upgrade) {
+    return Ok(output);
+  }
+  let output = Arc::new(DmxUniverse::new(protocol, target, universe)?);
+  universes.insert(key, Arc::downgrade(&output));
+  Ok(output)
+}
+
+async fn run_universe_output(
+  socket: UdpSocket,
+  protocol: DmxProtocol,
+  target: SocketAddr,
+  universe: u16,
+  channels: Arc<Mutex<[u8; DMX_UNIVERSE_SIZE]>>,
+  updated: Arc<Notify>,
+  token: CancellationToken,
+) {
+  info!(
+    "Sending DMX universe {} to {} over {}",
+    universe,
+    target,
+    protocol.name()
+  );
+  let cid: [u8; 16] = rand::random();
+  let mut sequence = 0u8;
+  loop {
+    let stopping = select! {
+      _ = token.cancelled().fuse() => true,
+      _ = updated.notified().fuse() => false,
+      _ = sleep(DMX_REFRESH_INTERVAL).fuse() => false,
+    };
+    let frame = *channels.lock().expect("Lock is never held across a panic");
+    let packet = match protocol {
+      // Art-Net uses 0 for "not sequenced", so it's skipped.
+      DmxProtocol::ArtNet => {
+        sequence = sequence.checked_add(1).unwrap_or(1);
+        art_net_packet(universe, sequence, &frame)
+      }
+      DmxProtocol::Sacn => {
+        sequence = sequence.wrapping_add(1);
+        sacn_packet(&cid, universe, sequence, &frame)
+      }
+    };
+    if let Err(err) = socket.send_to(&packet, target).await {
+      warn!(
+        "Cannot send DMX universe {} to {}: {}",
+        universe, target, err
+      );
+    }
+    if stopping {
+      break;
+    }
+    // Updates that come in while waiting are coalesced into the next frame.
+    select! {
+      _ = token.cancelled().fuse() => {},
+      _ = sleep(DMX_MIN_FRAME_INTERVAL).fuse() => {},
+    }
+  }
+  debug!("Stopped sending DMX universe {} to {}", universe, target);
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_dmx_packets() {
+    let mut channels = [0u8; DMX_UNIVERSE_SIZE];
+    channels[0] = 255;
+    channels[511] = 7;
+
+    let packet = art_net_packet(0x1234, 5, &channels);
+    assert_eq!(packet.len(), 18 + DMX_UNIVERSE_SIZE);
+    assert_eq!(
+      &packet[..18],
+      b"Art-Net\0\x00\x50\x00\x0e\x05\x00\x34\x12\x02\x00"
+    );
+    assert_eq!(packet[18], 255);
+    assert_eq!(packet[18 + 511], 7);
+
+    let packet = sacn_packet(&[0xcc; 16], 1, 9, &channels);
+    assert_eq!(packet.len(), 638);
+    assert_eq!(&packet[4..16], b"ASC-E1.17\0\0\0");
+    assert_eq!(&packet[16..18], &[0x72, 0x6e]);
+    assert_eq!(&packet[38..40], &[0x72, 0x58]);
+    assert_eq!(&packet[44..52], b"Buttplug");
+    // Priority, sync address, sequence, options, universe.
+    assert_eq!(&packet[108..115], &[100, 0, 0, 9, 0, 0, 1]);
+    assert_eq!(&packet[115..117], &[0x72, 0x0b]);
+    assert_eq!(&packet[123..126], &[0x02, 0x01, 0x00]);
+    assert_eq!(packet[126], 255);
+    assert_eq!(packet[637], 7);
+  }
+
+  #[test]
+  fn test_dmx_default_targets() {
+    assert_eq!(
+      DmxProtocol::Sacn.default_target(0x0102),
+      IpAddr::V4(Ipv4Addr::new(239, 255, 1, 2))
+    );
+    assert!(!DmxProtocol::Sacn.is_valid_universe(0));
+    assert!(DmxProtocol::ArtNet.is_valid_universe(0));
+    assert!(!DmxProtocol::ArtNet.is_valid_universe(0x8000));
+  }
+
+  #[tokio::test]
+  async fn test_dmx_universe_output() {
+    let node = UdpSocket::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let target = node.local_addr().expect("Test, assuming infallible.");
+    let universes = DmxUniverses::default();
+    let output = shared_universe(&universes, DmxProtocol::ArtNet, target, 3)
+      .expect("Test, assuming infallible.");
+    // Devices on the same universe share the output.
+    let other_output = shared_universe(&universes, DmxProtocol::ArtNet, target, 3)
+      .expect("Test, assuming infallible.");
+    assert!(Arc::ptr_eq(&output, &other_output));
+    assert!(output.set_channels(510, &[1, 2, 3]).is_err());
+
+    output
+      .set_channels(2, &[10, 20])
+      .expect("Test, assuming infallible.");
+    let mut packet = [0u8; 1024];
+    let len = node
+      .recv(&mut packet)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(len, 18 + DMX_UNIVERSE_SIZE);
+    assert_eq!(&packet[18..22], &[0, 0, 10, 20]);
+
+    // Dropping the last user sends a final frame, then stops.
+    output
+      .set_channels(2, &[0, 0])
+      .expect("Test, assuming infallible.");
+    drop(output);
+    drop(other_output);
+    let mut last_frame = None;
+    while let Ok(Ok(len)) =
+      tokio::time::timeout(Duration::from_millis(200), node.recv(&mut packet)).await
+    {
+      last_frame = Some(packet[18..len].to_vec());
+    }
+    assert_eq!(
+      last_frame.expect("Test, assuming infallible."),
+      vec![0; DMX_UNIVERSE_SIZE]
+    );
+    assert!(universes
+      .lock()
+      .expect("Test, assuming infallible.")
+      .values()
+      .all(|universe| universe.upgrade().is_none()));
+  }
+}
@@ -0,0 +1,27 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! DMX universes as devices
+//!
+//! Lets interactive installations drive haptic hardware hooked up to DMX dimmers and controllers,
+//! sending universes over Art-Net or sACN. Devices are defined by the user: the name the device is
+//! matched on in the device configuration ("DMX Fixture", with 4 channels, for the built in `dmx`
+//! protocol), the universe, and the channel the device's actuators start at. Devices can share a
+//! universe, as long as their channels don't overlap.
+//!
+//! The [dmx protocol](crate::server::device::protocol::dmx) writes channel values to Tx. Updates are
+//! coalesced into frames at DMX's refresh rate, and universes are sent again every second so nodes
+//! don't time out. A device's channels are set to 0 when it disconnects or the server stops.
+
+mod dmx_comm_manager;
+mod dmx_hardware;
+mod dmx_universe;
+pub use dmx_comm_manager::{
+  DmxCommunicationManager, DmxCommunicationManagerBuilder, DmxDefinition,
+};
+pub use dmx_hardware::DmxHardware;
+pub use dmx_universe::{DmxProtocol, DMX_UNIVERSE_SIZE};
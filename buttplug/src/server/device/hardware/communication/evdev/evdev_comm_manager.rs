@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use std::fs;
 use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
   core::errors::ButtplugDeviceError,
@@ -35,6 +36,48 @@ impl EvdevCommunicationManager {
   }
 }
 
+fn scan_devices(
+  device_sender: &Sender<HardwareCommunicationManagerEvent>,
+  token: &CancellationToken,
+) -> Result<(), ButtplugDeviceError> {
+  let events_dir = fs::read_dir("/dev/input/").map_err(|err| {
+    ButtplugDeviceError::DeviceConnectionError(format!("Cannot read /dev/input: {}", err))
+  })?;
+
+  for file in events_dir.flatten() {
+    if token.is_cancelled() {
+      debug!("Evdev scan stopped before finishing /dev/input");
+      break;
+    }
+    // Check if device is a vaild event thingy
+    if !file.file_name().to_string_lossy().starts_with("event") {
+      continue;
+    }
+
+    let Ok(device) = evdev::Device::open(file.path()) else {
+      continue;
+    };
+    // TODO: Check more?
+    if device.supported_ff().is_none() {
+      continue;
+    }
+
+    if device_sender
+      .blocking_send(HardwareCommunicationManagerEvent::DeviceFound {
+        name: device.name().unwrap_or("Unnamed device").to_string(),
+        address: device.input_id().product().to_string(),
+        creator: Box::new(EvdevHardwareConnector::new(device)),
+      })
+      .is_err()
+    {
+      error!("Device manager event receiver dropped, stopping evdev scan.");
+      break;
+    }
+  }
+
+  Ok(())
+}
+
 #[async_trait]
 impl TimedRetryCommunicationManagerImpl for EvdevCommunicationManager {
   fn name(&self) -> &'static str {
@@ -42,46 +85,23 @@ impl TimedRetryCommunicationManagerImpl for EvdevCommunicationManager {
   }
 
   async fn scan(&self) -> Result<(), ButtplugDeviceError> {
-    // TODO: Is this blocking? should we try to run this in another thread?
     let device_sender = self.sender.clone();
-    let events_dir = fs::read_dir("/dev/input/").expect("owo?");
-
-    for file in events_dir {
-      // Check if device is a vaild event thingy
-      if file.is_err() {
-        continue;
-      }
-      let event = file.unwrap();
-      if !event.file_name().to_str().expect(":<").starts_with("event") {
-        continue;
-      }
-
-      let device = evdev::Device::open(event.path());
-      if let Ok(device) = device {
-        // TODO: Check more?
-        if device.supported_ff().is_none() {
-          continue;
-        }
-
-        if device_sender
-          .send(HardwareCommunicationManagerEvent::DeviceFound {
-            name: device.name().unwrap_or("Unnamed device").to_string(),
-            address: device.input_id().product().to_string(),
-            creator: Box::new(EvdevHardwareConnector::new(device)),
-          })
-          .await
-          .is_err()
-        {
-          error!("Oh no.");
-          return Ok(());
-        }
-      }
-    }
-
-    Ok(())
+    // Opening devices blocks, so the walk runs on the blocking pool. If scanning is stopped, this
+    // future is dropped, and the guard tells the walk to stop at the next device.
+    let token = CancellationToken::new();
+    let _scan_guard = token.clone().drop_guard();
+    tokio::task::spawn_blocking(move || scan_devices(&device_sender, &token))
+      .await
+      .map_err(|err| {
+        ButtplugDeviceError::DeviceCommunicationError(format!("Evdev scan task failed: {}", err))
+      })?
   }
 
   fn can_scan(&self) -> bool {
     true
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("evdev")
+  }
 }
@@ -10,8 +10,11 @@ use std::{
 
 use async_trait::async_trait;
 use byteorder::{LittleEndian, ReadBytesExt};
-use evdev::{FFReplay, FFTrigger};
-use futures_util::{future::BoxFuture, FutureExt};
+use evdev::{FFEffectType, FFEnvelope, FFReplay, FFTrigger, FFWaveform};
+use futures_util::{
+  future::{self, BoxFuture},
+  FutureExt,
+};
 use tokio::sync::{broadcast, mpsc};
 
 use crate::{
@@ -26,6 +29,36 @@ use crate::{
   },
 };
 
+/// True if the device can play periodic sine effects, used for waveforms with a frequency.
+fn supports_periodic(device: &evdev::Device) -> bool {
+  device.supported_ff().is_some_and(|ff| {
+    ff.contains(FFEffectType::FF_PERIODIC) && ff.contains(FFEffectType::FF_SINE)
+  })
+}
+
+/// Endpoints backed by what the device can actually do. Rumble and periodic force feedback are
+/// written to Tx, and devices that send key or absolute axis events get an Rx.
+fn evdev_endpoints(device: &evdev::Device) -> Vec<Endpoint> {
+  let mut endpoints = vec![];
+  if device
+    .supported_ff()
+    .is_some_and(|ff| ff.contains(FFEffectType::FF_RUMBLE))
+    || supports_periodic(device)
+  {
+    endpoints.push(Endpoint::Tx);
+  }
+  if device
+    .supported_keys()
+    .is_some_and(|keys| keys.iter().next().is_some())
+    || device
+      .supported_absolute_axes()
+      .is_some_and(|axes| axes.iter().next().is_some())
+  {
+    endpoints.push(Endpoint::Rx);
+  }
+  endpoints
+}
+
 pub struct EvdevHardwareConnector {
   device: Arc<Mutex<evdev::Device>>,
 }
@@ -69,11 +102,18 @@ impl HardwareConnector for EvdevHardwareConnector {
       "New Evdev device created: {}",
       &device.name().unwrap_or("Unnamed Device")
     );
+    let endpoints = evdev_endpoints(&device);
+    if endpoints.is_empty() {
+      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+        "Evdev device {} has no rumble support or input events to use",
+        device.name().unwrap_or("Unnamed Device")
+      )));
+    }
     let hardware = Hardware::new(
       &device.name().unwrap_or("Unnamed Device"),
       &device.input_id().product().to_string().as_str(),
-      &[Endpoint::Rx, Endpoint::Tx],
-      Box::new(EvdevDeviceImpl::new(self.device.clone())),
+      &endpoints,
+      Box::new(EvdevDeviceImpl::new(self.device.clone(), &endpoints)),
     );
     Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
   }
@@ -83,6 +123,7 @@ pub struct EvdevDeviceImpl {
   connected: Arc<AtomicBool>,
   device_event_sender: broadcast::Sender<HardwareEvent>, // TODO: Do we need this?
   write_sender: mpsc::Sender<Vec<u8>>,
+  endpoints: Vec<Endpoint>,
 
   // TODO: Do we need to keep these?
   _write_thread: thread::JoinHandle<()>,
@@ -90,7 +131,7 @@ pub struct EvdevDeviceImpl {
 }
 
 impl EvdevDeviceImpl {
-  pub fn new(device: Arc<Mutex<evdev::Device>>) -> Self {
+  pub fn new(device: Arc<Mutex<evdev::Device>>, endpoints: &[Endpoint]) -> Self {
     let (device_event_sender, _) = broadcast::channel(256);
     let (write_sender, write_receiver) = mpsc::channel(256);
 
@@ -105,6 +146,7 @@ impl EvdevDeviceImpl {
     Self {
       device,
       write_sender,
+      endpoints: endpoints.to_vec(),
       _write_thread: write_thread,
       connected: Arc::new(AtomicBool::new(true)),
       device_event_sender,
@@ -112,19 +154,45 @@ impl EvdevDeviceImpl {
   }
 }
 
-fn vibrate(
-  device: &mut evdev::Device,
-  magnitude: &Vec<u8>,
-  length_ms: u16,
-) -> io::Result<evdev::FFEffect> {
-  let mut cursor = Cursor::new(magnitude);
+// Effect length used if the protocol doesn't send one.
+const DEFAULT_EFFECT_LENGTH_MS: u16 = 100;
+
+// Packets are a u16 magnitude, followed by the effect length in milliseconds, and optionally the
+// period of a sine wave in milliseconds. Effects stop once their length runs out, so the protocol
+// keeps them going with keepalives. Packets with a period play as periodic effects on devices that
+// support them, and fall back to rumble on those that don't.
+fn force_feedback_effect(data: &[u8], periodic: bool) -> io::Result<evdev::FFEffectData> {
+  let mut cursor = Cursor::new(data);
   //TODO: Maybe we can use both motors?
-  let magnitude = cursor
+  let magnitude = cursor.read_u16::<LittleEndian>()?;
+  let length_ms = cursor
     .read_u16::<LittleEndian>()
-    .expect("Packed in protocol, infallible");
-  println!("[Evdev] Vibrating at: {magnitude} for {length_ms}ms");
-  let effect = device.upload_ff_effect(evdev::FFEffectData {
-    // direction: 0x4000,
+    .unwrap_or(DEFAULT_EFFECT_LENGTH_MS);
+  let period_ms = cursor.read_u16::<LittleEndian>().unwrap_or(0);
+  let kind = if periodic && period_ms != 0 && magnitude != 0 {
+    trace!("[Evdev] Vibrating at: {magnitude} with a {period_ms}ms period for {length_ms}ms");
+    evdev::FFEffectKind::Periodic {
+      waveform: FFWaveform::Sine,
+      period: period_ms,
+      // Periodic magnitudes are signed, so halve the rumble range to fit.
+      magnitude: (magnitude / 2) as i16,
+      offset: 0,
+      phase: 0,
+      envelope: FFEnvelope {
+        attack_length: 0,
+        attack_level: 0,
+        fade_length: 0,
+        fade_level: 0,
+      },
+    }
+  } else {
+    trace!("[Evdev] Vibrating at: {magnitude} for {length_ms}ms");
+    evdev::FFEffectKind::Rumble {
+      weak_magnitude: magnitude,
+      strong_magnitude: magnitude,
+    }
+  };
+  Ok(evdev::FFEffectData {
     direction: 0,
     trigger: FFTrigger {
       button: 0,
@@ -134,29 +202,45 @@ fn vibrate(
       delay: 0,
       length: length_ms,
     },
-    // kind: evdev::FFEffectKind::Periodic {
-    //   waveform: evdev::FFWaveform::Sine,
-    //   period: 100,
-    //   magnitude: magnitude as i16,
-    //   offset: 0,
-    //   phase: 0,
-    //   envelope: evdev::FFEnvelope {
-    //     attack_length: 0,
-    //     attack_level: u16::MAX,
-    //     fade_length: 0,
-    //     fade_level: u16::MAX,
-    //   },
-    // },
-    kind: evdev::FFEffectKind::Rumble {
-      weak_magnitude: magnitude,
-      // strong_magnitude: magnitude as u16,
-      strong_magnitude: magnitude,
-    },
-  })?;
+    kind,
+  })
+}
+
+/// The force feedback effect uploaded to the device, if there is one.
+///
+/// Devices only have a handful of effect slots, and some drivers don't free them reliably, so
+/// the effect is uploaded once and updated in place for later writes. Uploading a new effect is
+/// only a fallback for drivers that can't update, or for switching between rumble and periodic
+/// effects.
+#[derive(Default)]
+struct RumbleEffectSlot {
+  effect: Option<evdev::FFEffect>,
+}
+
+impl RumbleEffectSlot {
+  fn play(&mut self, device: &mut evdev::Device, data: evdev::FFEffectData) -> io::Result<()> {
+    if let Some(effect) = self.effect.as_mut() {
+      match effect.update(data) {
+        Ok(()) => return effect.play(1),
+        Err(err) => {
+          debug!("Cannot update evdev effect, uploading a new one: {}", err);
+          // Free the old slot before asking for a new one.
+          self.effect = None;
+        }
+      }
+    }
+    let mut effect = device.upload_ff_effect(data)?;
+    effect.play(1)?;
+    self.effect = Some(effect);
+    Ok(())
+  }
 
-  // effect.play(i32::MAX)?; //TODO: Change this?
-  // thread::sleep(Duration::from_millis(length_ms as u64 + 10000));
-  Ok(effect)
+  fn stop(&mut self) -> io::Result<()> {
+    match self.effect.as_mut() {
+      Some(effect) => effect.stop(),
+      None => Ok(()),
+    }
+  }
 }
 
 fn write_thread(device: Arc<Mutex<evdev::Device>>, receiver: mpsc::Receiver<Vec<u8>>) {
@@ -166,22 +250,30 @@ fn write_thread(device: Arc<Mutex<evdev::Device>>, receiver: mpsc::Receiver<Vec<
   //
   // This is a blocking recv so we don't have to worry about the port.
   let mut device = device.lock().expect("Couldnt lock device :<");
-  // Dont drop effect else it stops
-  let mut effect_nodrop = None;
+  let periodic = supports_periodic(&device);
+  // The effect is removed from the device when the slot is dropped, so it lives as long as the
+  // thread does.
+  let mut slot = RumbleEffectSlot::default();
   while let Some(v) = recv.blocking_recv() {
-    match vibrate(&mut device, &v, 100) {
-      Ok(mut effect) => {
-        drop(effect_nodrop.take());
-        effect.play(i32::MAX).expect("Ohno :<");
-        effect_nodrop = Some(effect);
-      }
+    let data = match force_feedback_effect(&v, periodic) {
+      Ok(data) => data,
       Err(err) => {
-        error!("Cannot vibrate, exiting thread: {}", err);
-        return;
+        warn!("Ignoring malformed evdev packet {:?}: {}", v, err);
+        continue;
       }
+    };
+    let result = match data.kind {
+      evdev::FFEffectKind::Rumble {
+        weak_magnitude: 0,
+        strong_magnitude: 0,
+      } => slot.stop(),
+      _ => slot.play(&mut device, data),
+    };
+    if let Err(err) = result {
+      error!("Cannot vibrate, exiting thread: {}", err);
+      return;
     }
   }
-  drop(effect_nodrop);
 }
 
 impl HardwareInternal for EvdevDeviceImpl {
@@ -201,22 +293,28 @@ impl HardwareInternal for EvdevDeviceImpl {
     &self,
     _msg: &HardwareReadCmd,
   ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
-    unimplemented!();
+    // Force feedback devices are write only, there's nothing to read back.
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Evdev devices do not support read".to_owned(),
+    )))
+    .boxed()
   }
 
   fn write_value(
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::Tx || !self.endpoints.contains(&Endpoint::Tx) {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
     let sender = self.write_sender.clone();
     let data = msg.data.clone();
-    // TODO Should check endpoint validity
     async move {
-      sender
-        .send(data)
-        .await
-        .expect("Tasks should exist if we get here.");
-      Ok(())
+      sender.send(data).await.map_err(|_| {
+        ButtplugDeviceError::DeviceNotConnected(
+          "Evdev write thread exited, device no longer available".to_owned(),
+        )
+      })
     }
     .boxed()
   }
@@ -225,13 +323,19 @@ impl HardwareInternal for EvdevDeviceImpl {
     &self,
     _msg: &HardwareSubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    unimplemented!();
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Evdev devices do not support subscribe".to_owned(),
+    )))
+    .boxed()
   }
 
   fn unsubscribe(
     &self,
     _msg: &HardwareUnsubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    unimplemented!();
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Evdev devices do not support unsubscribe".to_owned(),
+    )))
+    .boxed()
   }
 }
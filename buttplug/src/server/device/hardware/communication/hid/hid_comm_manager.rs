@@ -10,10 +10,10 @@ use crate::{
 };
 use async_trait::async_trait;
 use hidapi::HidApi;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc::Sender;
 
-use super::hid_device_impl::HidHardwareConnector;
+use super::hid_device_impl::{hid_device_address, HidHardwareConnector};
 
 #[derive(Default)]
 pub struct HidCommunicationManagerBuilder {}
@@ -31,14 +31,14 @@ impl HardwareCommunicationManagerBuilder for HidCommunicationManagerBuilder {
 
 pub struct HidCommunicationManager {
   sender: Sender<HardwareCommunicationManagerEvent>,
-  hidapi: Arc<HidApi>,
+  hidapi: Arc<Mutex<HidApi>>,
 }
 
 impl HidCommunicationManager {
   fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
     Self {
       sender,
-      hidapi: Arc::new(HidApi::new().unwrap()),
+      hidapi: Arc::new(Mutex::new(HidApi::new().unwrap())),
     }
   }
 }
@@ -52,23 +52,34 @@ impl TimedRetryCommunicationManagerImpl for HidCommunicationManager {
   async fn scan(&self) -> Result<(), ButtplugDeviceError> {
     // TODO Does this block? Should it run in one of our threads?
     let device_sender = self.sender.clone();
-    let api = self.hidapi.clone();
+    // Re-enumerate every scan, so devices plugged in after we started show up.
+    let devices = {
+      let mut api = self
+        .hidapi
+        .lock()
+        .expect("HID API lock should never be poisoned");
+      if let Err(err) = api.refresh_devices() {
+        error!("Cannot refresh HID device list: {}", err);
+      }
+      api.device_list().cloned().collect::<Vec<_>>()
+    };
 
     let mut seen_addresses = vec![];
-    for device in api.device_list() {
-      if let None = device.serial_number() {
-        continue;
-      }
-      let serial_number = device.serial_number().unwrap().to_owned();
-      if seen_addresses.contains(&serial_number) {
+    for device in devices {
+      // Devices with multiple interfaces show up once per interface, only use the first.
+      let address = hid_device_address(&device);
+      if seen_addresses.contains(&address) {
         continue;
       }
-      seen_addresses.push(serial_number.clone());
-      let device_creator = HidHardwareConnector::new(api.clone(), &device);
+      seen_addresses.push(address.clone());
+      let device_creator = HidHardwareConnector::new(self.hidapi.clone(), &device);
       if device_sender
         .send(HardwareCommunicationManagerEvent::DeviceFound {
-          name: device.product_string().unwrap().to_owned(),
-          address: serial_number,
+          name: device
+            .product_string()
+            .unwrap_or("Unknown HID Device")
+            .to_owned(),
+          address,
           creator: Box::new(device_creator),
         })
         .await
@@ -84,4 +95,8 @@ impl TimedRetryCommunicationManagerImpl for HidCommunicationManager {
   fn can_scan(&self) -> bool {
     true
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("hid")
+  }
 }
@@ -1,6 +1,5 @@
-use super::hidapi_async::HidAsyncDevice;
 use crate::{
-  core::errors::ButtplugDeviceError,
+  core::{errors::ButtplugDeviceError, message::DeviceRemovedReason},
   server::device::{
     configuration::{HIDSpecifier, ProtocolCommunicationSpecifier},
     hardware::{
@@ -20,29 +19,55 @@ use crate::{
   },
 };
 use async_trait::async_trait;
-use futures::{future::BoxFuture, AsyncWriteExt};
-use hidapi::{DeviceInfo, HidApi};
+use futures::future::{self, BoxFuture, FutureExt};
+use hidapi::{DeviceInfo, HidApi, HidDevice};
 use std::{
   fmt::{self, Debug},
   sync::{
-    atomic::{AtomicBool, Ordering},
+    mpsc::{self, RecvTimeoutError},
     Arc,
+    Mutex,
   },
+  thread,
+  time::Duration,
 };
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, oneshot};
+
+/// How long the device thread waits on input reports before checking for new commands. Also the
+/// worst case latency for writes.
+const HID_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Largest input report we'll read when subscribed. Covers full speed USB, Bluetooth HID can be a
+/// little larger.
+const HID_MAX_REPORT_SIZE: usize = 128;
+
+/// Address for a HID device. Not all devices have serial numbers, so fall back to the OS path.
+pub(super) fn hid_device_address(device_info: &DeviceInfo) -> String {
+  match device_info.serial_number() {
+    Some(serial) if !serial.is_empty() => serial.to_owned(),
+    _ => device_info.path().to_string_lossy().into_owned(),
+  }
+}
 
 pub struct HidHardwareConnector {
-  hid_instance: Arc<HidApi>,
+  hid_instance: Arc<Mutex<HidApi>>,
   device_info: DeviceInfo,
 }
 
 impl HidHardwareConnector {
-  pub fn new(hid_instance: Arc<HidApi>, device_info: &DeviceInfo) -> Self {
+  pub fn new(hid_instance: Arc<Mutex<HidApi>>, device_info: &DeviceInfo) -> Self {
     Self {
       hid_instance,
       device_info: device_info.clone(),
     }
   }
+
+  fn name(&self) -> String {
+    self
+      .device_info
+      .product_string()
+      .unwrap_or("Unknown HID Device")
+      .to_owned()
+  }
 }
 
 impl Debug for HidHardwareConnector {
@@ -59,7 +84,7 @@ impl HardwareConnector for HidHardwareConnector {
   fn specifier(&self) -> ProtocolCommunicationSpecifier {
     info!(
       "Specifier for {}: {:#04x} {:#04x}",
-      self.device_info.product_string().unwrap(),
+      self.name(),
       self.device_info.vendor_id(),
       self.device_info.product_id()
     );
@@ -70,36 +95,199 @@ impl HardwareConnector for HidHardwareConnector {
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
-    let device = self.device_info.open_device(&self.hid_instance).unwrap();
-    let device_impl_internal = HIDDeviceImpl::new(HidAsyncDevice::new(device).unwrap());
-    info!(
-      "New HID device created: {}",
-      self.device_info.product_string().unwrap()
-    );
+    let device = {
+      let api = self
+        .hid_instance
+        .lock()
+        .expect("HID API lock should never be poisoned");
+      self.device_info.open_device(&api).map_err(|err| {
+        ButtplugDeviceError::DeviceConnectionError(format!(
+          "Cannot open HID device {}: {}",
+          self.name(),
+          err
+        ))
+      })?
+    };
+    let address = hid_device_address(&self.device_info);
+    info!("New HID device created: {}", self.name());
     let hardware = Hardware::new(
-      &self.device_info.product_string().unwrap(),
-      &self.device_info.serial_number().unwrap(),
-      &[Endpoint::Rx, Endpoint::Tx],
-      Box::new(device_impl_internal),
+      &self.name(),
+      &address,
+      &[Endpoint::Rx, Endpoint::Tx, Endpoint::Feature],
+      Box::new(HIDDeviceImpl::new(&address, device)),
     );
     Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
   }
 }
 
+type HidResultSender<T> = oneshot::Sender<Result<T, ButtplugDeviceError>>;
+
+enum HidDeviceCommand {
+  Write(Vec<u8>, HidResultSender<()>),
+  SendFeatureReport(Vec<u8>, HidResultSender<()>),
+  Read(HardwareReadCmd, HidResultSender<HardwareReading>),
+  GetFeatureReport(HardwareReadCmd, HidResultSender<HardwareReading>),
+  Subscribe,
+  Unsubscribe,
+}
+
+fn hid_error(action: &str, err: hidapi::HidError) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceCommunicationError(format!("Cannot {} HID device: {}", action, err))
+}
+
+/// Owns the HID device. hidapi calls block, so everything happens on this thread, which exits when
+/// the device is dropped or unplugged.
+fn hid_device_thread(
+  address: String,
+  device: HidDevice,
+  receiver: mpsc::Receiver<HidDeviceCommand>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+) {
+  let mut subscribed = false;
+  let mut buf = [0u8; HID_MAX_REPORT_SIZE];
+  loop {
+    // Always keep reading input reports, even when no one is subscribed. Otherwise they'll queue up
+    // in the OS, and reading is how we notice the device being unplugged.
+    match device.read_timeout(&mut buf, 0) {
+      Ok(0) => {}
+      Ok(len) => {
+        if subscribed {
+          let _ = event_sender.send(HardwareEvent::Notification(
+            address.clone(),
+            Endpoint::Rx,
+            buf[..len].to_vec(),
+          ));
+        }
+        // There may be more reports waiting, get them before blocking on commands.
+        continue;
+      }
+      Err(err) => {
+        info!(
+          "HID device {} lost, exiting device thread: {}",
+          address, err
+        );
+        break;
+      }
+    }
+    let command = match receiver.recv_timeout(HID_POLL_INTERVAL) {
+      Ok(command) => command,
+      Err(RecvTimeoutError::Timeout) => continue,
+      Err(RecvTimeoutError::Disconnected) => {
+        debug!("HID device {} dropped, exiting device thread.", address);
+        return;
+      }
+    };
+    match command {
+      HidDeviceCommand::Write(data, reply) => {
+        let _ = reply.send(
+          device
+            .write(&data)
+            .map(|_| ())
+            .map_err(|err| hid_error("write to", err)),
+        );
+      }
+      HidDeviceCommand::SendFeatureReport(data, reply) => {
+        let _ = reply.send(
+          device
+            .send_feature_report(&data)
+            .map_err(|err| hid_error("send feature report to", err)),
+        );
+      }
+      HidDeviceCommand::Read(msg, reply) => {
+        let mut data = vec![0u8; msg.length() as usize];
+        let result = device
+          .read_timeout(&mut data, msg.timeout_ms() as i32)
+          .map(|len| HardwareReading::new(Endpoint::Rx, &data[..len]))
+          .map_err(|err| hid_error("read from", err));
+        let _ = reply.send(result);
+      }
+      HidDeviceCommand::GetFeatureReport(msg, reply) => {
+        // The first byte is the report ID. We only read report 0, which is what devices without
+        // numbered reports use.
+        let mut data = vec![0u8; msg.length() as usize + 1];
+        let result = device
+          .get_feature_report(&mut data)
+          .map(|len| HardwareReading::new(Endpoint::Feature, &data[..len]))
+          .map_err(|err| hid_error("get feature report from", err));
+        let _ = reply.send(result);
+      }
+      HidDeviceCommand::Subscribe => subscribed = true,
+      HidDeviceCommand::Unsubscribe => subscribed = false,
+    }
+  }
+  let _ = event_sender.send(HardwareEvent::Disconnected(
+    address,
+    DeviceRemovedReason::LinkLost,
+    false,
+  ));
+}
+
 pub struct HIDDeviceImpl {
-  connected: Arc<AtomicBool>,
   device_event_sender: broadcast::Sender<HardwareEvent>,
-  device: Arc<Mutex<HidAsyncDevice>>,
+  command_sender: Mutex<mpsc::Sender<HidDeviceCommand>>,
 }
 
 impl HIDDeviceImpl {
-  pub fn new(device: HidAsyncDevice) -> Self {
+  pub fn new(address: &str, device: HidDevice) -> Self {
     let (device_event_sender, _) = broadcast::channel(256);
+    let (command_sender, command_receiver) = mpsc::channel();
+    let thread_address = address.to_owned();
+    let thread_event_sender = device_event_sender.clone();
+    thread::Builder::new()
+      .name("HID Device Thread".to_string())
+      .spawn(move || {
+        hid_device_thread(
+          thread_address,
+          device,
+          command_receiver,
+          thread_event_sender,
+        )
+      })
+      .expect("Should always be able to create thread");
     Self {
-      device: Arc::new(Mutex::new(device)),
-      connected: Arc::new(AtomicBool::new(true)),
       device_event_sender,
+      command_sender: Mutex::new(command_sender),
+    }
+  }
+
+  fn send_command(&self, command: HidDeviceCommand) -> Result<(), ButtplugDeviceError> {
+    self
+      .command_sender
+      .lock()
+      .expect("HID command lock should never be poisoned")
+      .send(command)
+      .map_err(|_| {
+        ButtplugDeviceError::DeviceNotConnected("HID device thread has exited.".to_owned())
+      })
+  }
+
+  fn command<T: Send + 'static>(
+    &self,
+    command: impl FnOnce(HidResultSender<T>) -> HidDeviceCommand,
+  ) -> BoxFuture<'static, Result<T, ButtplugDeviceError>> {
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    if let Err(err) = self.send_command(command(reply_sender)) {
+      return future::ready(Err(err)).boxed();
+    }
+    async move {
+      reply_receiver.await.unwrap_or_else(|_| {
+        Err(ButtplugDeviceError::DeviceNotConnected(
+          "HID device thread exited before replying.".to_owned(),
+        ))
+      })
     }
+    .boxed()
+  }
+
+  fn set_subscribed(
+    &self,
+    endpoint: Endpoint,
+    command: HidDeviceCommand,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if endpoint != Endpoint::Rx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(endpoint))).boxed();
+    }
+    future::ready(self.send_command(command)).boxed()
   }
 }
 
@@ -109,48 +297,49 @@ impl HardwareInternal for HIDDeviceImpl {
   }
 
   fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    let connected = self.connected.clone();
-    Box::pin(async move {
-      connected.store(false, Ordering::SeqCst);
-      Ok(())
-    })
+    // The device thread exits, closing the device, once we're dropped.
+    future::ready(Ok(())).boxed()
   }
 
   fn read_value(
     &self,
-    _msg: &HardwareReadCmd,
+    msg: &HardwareReadCmd,
   ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
-    unimplemented!();
+    let msg = *msg;
+    match msg.endpoint() {
+      Endpoint::Rx => self.command(move |reply| HidDeviceCommand::Read(msg, reply)),
+      Endpoint::Feature => {
+        self.command(move |reply| HidDeviceCommand::GetFeatureReport(msg, reply))
+      }
+      endpoint => future::ready(Err(ButtplugDeviceError::InvalidEndpoint(endpoint))).boxed(),
+    }
   }
 
   fn write_value(
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    let device = self.device.clone();
-    let data = msg.data.clone();
-    Box::pin(async move {
-      device.lock().await.write(&data).await.map_err(|e| {
-        ButtplugDeviceError::DeviceCommunicationError(format!(
-          "Cannot write to HID Device: {:?}.",
-          e
-        ))
-      })?;
-      Ok(())
-    })
+    let data = msg.data().clone();
+    match msg.endpoint() {
+      Endpoint::Tx => self.command(move |reply| HidDeviceCommand::Write(data, reply)),
+      Endpoint::Feature => {
+        self.command(move |reply| HidDeviceCommand::SendFeatureReport(data, reply))
+      }
+      endpoint => future::ready(Err(ButtplugDeviceError::InvalidEndpoint(endpoint))).boxed(),
+    }
   }
 
   fn subscribe(
     &self,
-    _msg: &HardwareSubscribeCmd,
+    msg: &HardwareSubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    unimplemented!();
+    self.set_subscribed(msg.endpoint(), HidDeviceCommand::Subscribe)
   }
 
   fn unsubscribe(
     &self,
-    _msg: &HardwareUnsubscribeCmd,
+    msg: &HardwareUnsubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    unimplemented!();
+    self.set_subscribed(msg.endpoint(), HidDeviceCommand::Unsubscribe)
   }
 }
@@ -1,5 +1,4 @@
 pub mod hid_comm_manager;
 pub mod hid_device_impl;
-mod hidapi_async;
 
 pub use hid_comm_manager::{HidCommunicationManager, HidCommunicationManagerBuilder};
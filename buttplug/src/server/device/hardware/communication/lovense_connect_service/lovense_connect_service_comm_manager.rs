@@ -267,4 +267,8 @@ impl TimedRetryCommunicationManagerImpl for LovenseConnectServiceCommunicationMa
   fn can_scan(&self) -> bool {
     true
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("lovense-connect-service")
+  }
 }
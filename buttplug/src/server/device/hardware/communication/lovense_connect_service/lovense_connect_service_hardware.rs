@@ -7,7 +7,10 @@
 
 use super::lovense_connect_service_comm_manager::{get_local_info, LovenseServiceToyInfo};
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::{
     configuration::{LovenseConnectServiceSpecifier, ProtocolCommunicationSpecifier},
     hardware::{
@@ -103,7 +106,11 @@ impl LovenseServiceHardware {
                 continue;
               }
               if !toy.connected {
-                let _ = sender_clone.send(HardwareEvent::Disconnected(toy_id.clone()));
+                let _ = sender_clone.send(HardwareEvent::Disconnected(
+                  toy_id.clone(),
+                  DeviceRemovedReason::LinkLost,
+                  false,
+                ));
                 info!("Exiting lovense service device connection check loop.");
                 break;
               }
@@ -112,7 +119,11 @@ impl LovenseServiceHardware {
             }
           }
           None => {
-            let _ = sender_clone.send(HardwareEvent::Disconnected(toy_id.clone()));
+            let _ = sender_clone.send(HardwareEvent::Disconnected(
+              toy_id.clone(),
+              DeviceRemovedReason::LinkLost,
+              false,
+            ));
             info!("Exiting lovense service device connection check loop.");
             break;
           }
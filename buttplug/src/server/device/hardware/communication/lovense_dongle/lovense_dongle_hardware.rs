@@ -13,7 +13,10 @@ use super::lovense_dongle_messages::{
   OutgoingLovenseData,
 };
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::{
     configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
     hardware::{
@@ -30,10 +33,13 @@ use crate::{
       HardwareWriteCmd,
     },
   },
-  util::async_manager,
+  util::{async_manager, sleep},
 };
 use async_trait::async_trait;
-use futures::future::{self, BoxFuture, FutureExt};
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  select,
+};
 use std::{
   collections::HashMap,
   fmt::{self, Debug},
@@ -41,9 +47,14 @@ use std::{
     atomic::{AtomicBool, Ordering},
     Arc,
   },
+  time::Duration,
 };
 use tokio::sync::{broadcast, mpsc};
 
+// Used when a read comes in without a timeout set. The dongle can take a while
+// to relay toy responses, so this is a bit longer than you'd expect for BLE.
+const LOVENSE_DONGLE_DEFAULT_READ_TIMEOUT_MS: u64 = 1000;
+
 pub struct LovenseDongleHardwareConnector {
   specifier: ProtocolCommunicationSpecifier,
   id: String,
@@ -153,7 +164,11 @@ impl LovenseDongleHardware {
       }
       info!("Lovense dongle device disconnected",);
       if device_event_sender_clone
-        .send(HardwareEvent::Disconnected(address_clone.clone()))
+        .send(HardwareEvent::Disconnected(
+          address_clone.clone(),
+          DeviceRemovedReason::LinkLost,
+          false,
+        ))
         .is_err()
       {
         error!("Device Manager no longer alive, cannot send removed event.");
@@ -184,11 +199,65 @@ impl HardwareInternal for LovenseDongleHardware {
 
   fn read_value(
     &self,
-    _msg: &HardwareReadCmd,
+    msg: &HardwareReadCmd,
   ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
-    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Lovense Dongle does not support read".to_owned(),
-    )))
+    if msg.endpoint() != Endpoint::Rx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
+    let port_sender = self.device_outgoing.clone();
+    let address = self.address.clone();
+    let timeout_ms = if msg.timeout_ms() == 0 {
+      LOVENSE_DONGLE_DEFAULT_READ_TIMEOUT_MS
+    } else {
+      msg.timeout_ms() as u64
+    };
+    // Subscribe before sending, otherwise the response could arrive before we're listening for it.
+    let mut event_receiver = self.event_sender.subscribe();
+    async move {
+      // The dongle doesn't have a concept of reading a characteristic. Instead, we send an eager
+      // command, which causes the dongle to flush whatever the toy has queued up to us as toyData.
+      let outgoing_msg = LovenseDongleOutgoingMessage {
+        func: LovenseDongleMessageFunc::Command,
+        message_type: LovenseDongleMessageType::Toy,
+        id: Some(address.clone()),
+        command: None,
+        eager: Some(1),
+      };
+      port_sender
+        .send(OutgoingLovenseData::Message(outgoing_msg))
+        .await
+        .map_err(|_| {
+          error!("Port closed during reading.");
+          ButtplugDeviceError::DeviceNotConnected("Port closed during reading".to_owned())
+        })?;
+      let read_fut = async move {
+        loop {
+          match event_receiver.recv().await {
+            Ok(HardwareEvent::Notification(id, endpoint, data))
+              if id == address && endpoint == Endpoint::Rx =>
+            {
+              return Ok(HardwareReading::new(Endpoint::Rx, &data));
+            }
+            Ok(HardwareEvent::Disconnected(..)) | Err(broadcast::error::RecvError::Closed) => {
+              return Err(ButtplugDeviceError::DeviceNotConnected(
+                "Lovense dongle device disconnected during reading".to_owned(),
+              ));
+            }
+            // Either a notification for someone else, or we lagged. Either way, keep waiting.
+            _ => continue,
+          }
+        }
+      };
+      select! {
+        result = read_fut.fuse() => result,
+        _ = sleep(Duration::from_millis(timeout_ms)).fuse() => {
+          Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Lovense dongle device did not respond to read within {}ms",
+            timeout_ms
+          )))
+        }
+      }
+    }
     .boxed()
   }
 
@@ -196,6 +265,9 @@ impl HardwareInternal for LovenseDongleHardware {
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::Tx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
     let port_sender = self.device_outgoing.clone();
     let address = self.address.clone();
     let data = msg.data.clone();
@@ -248,3 +320,172 @@ impl HardwareInternal for LovenseDongleHardware {
     future::ready(Ok(())).boxed()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{super::lovense_dongle_messages::LovenseDongleIncomingData, *};
+  use crate::{
+    core::message::ActuatorType,
+    server::device::{
+      hardware::scripted::{send_commands, setup_protocol, HardwareScript},
+      protocol::lovense::setup::LovenseIdentifierFactory,
+    },
+  };
+
+  const TOY_ID: &str = "dongle-toy";
+
+  fn toy_data(data: Vec<u8>) -> LovenseDongleIncomingMessage {
+    LovenseDongleIncomingMessage {
+      message_type: LovenseDongleMessageType::Toy,
+      func: LovenseDongleMessageFunc::ToyData,
+      id: None,
+      command: None,
+      eager: None,
+      result: None,
+      data: Some(LovenseDongleIncomingData {
+        id: Some(TOY_ID.to_owned()),
+        data: Some(String::from_utf8(data).expect("Test, assuming infallible.")),
+        status: None,
+      }),
+      message: None,
+    }
+  }
+
+  /// Stand in for the dongle port, checking toy commands against the script. Eager commands are
+  /// checked as reads from rx, and replies are sent back as toy data.
+  fn scripted_dongle(script: HardwareScript) -> Arc<Hardware> {
+    let (outgoing_sender, mut outgoing_receiver) = mpsc::channel(256);
+    let (incoming_sender, incoming_receiver) = mpsc::channel(256);
+    async_manager::spawn(async move {
+      while let Some(data) = outgoing_receiver.recv().await {
+        let OutgoingLovenseData::Message(msg) = data else {
+          panic!("Devices should only send messages");
+        };
+        assert_eq!(msg.id.as_deref(), Some(TOY_ID));
+        let replies = match (&msg.command, msg.eager) {
+          (Some(command), None) => script
+            .write(Endpoint::Tx, command.as_bytes())
+            .map(|replies| replies.into_iter().map(|(_, data)| data).collect())
+            .unwrap_or_default(),
+          (None, Some(1)) => script
+            .read(Endpoint::Rx)
+            .map(|data| vec![data])
+            .unwrap_or_default(),
+          _ => panic!("Unexpected dongle message {:?}", msg),
+        };
+        for reply in replies {
+          if incoming_sender.send(toy_data(reply)).await.is_err() {
+            return;
+          }
+        }
+      }
+    });
+    Arc::new(Hardware::new(
+      "Lovense Dongle Device",
+      TOY_ID,
+      &[Endpoint::Rx, Endpoint::Tx],
+      Box::new(LovenseDongleHardware::new(
+        TOY_ID,
+        outgoing_sender,
+        incoming_receiver,
+      )),
+    ))
+  }
+
+  #[tokio::test]
+  async fn test_lovense_over_dongle() {
+    let mut script = HardwareScript::default();
+    // Subscriptions are handled by the dongle state machine, so they never reach the port.
+    script
+      .expect_write(Endpoint::Tx, b"DeviceType;")
+      .reply(Endpoint::Rx, b"P:23:0082059AD3BD;")
+      .expect_write(Endpoint::Tx, b"Vibrate1:5;")
+      .expect_write(Endpoint::Tx, b"Vibrate2:10;")
+      .expect_read(Endpoint::Rx, b"s72;");
+    let hardware = scripted_dongle(script.clone());
+    let (_, handler) = setup_protocol(&LovenseIdentifierFactory::default(), hardware.clone())
+      .await
+      .expect("Test, assuming infallible.");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 5)),
+        Some((ActuatorType::Vibrate, 10)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    let reading = hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(reading.data(), b"s72;");
+    // Only rx and tx exist on dongle devices.
+    assert!(matches!(
+      hardware
+        .write_value(&HardwareWriteCmd::new(Endpoint::Rx, vec![], false))
+        .await,
+      Err(ButtplugDeviceError::InvalidEndpoint(Endpoint::Rx))
+    ));
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_dongle_read_errors() {
+    let (outgoing_sender, mut outgoing_receiver) = mpsc::channel(256);
+    let (incoming_sender, incoming_receiver) = mpsc::channel(256);
+    let hardware = LovenseDongleHardware::new(TOY_ID, outgoing_sender, incoming_receiver);
+    // Reads only ever come back on rx.
+    assert!(matches!(
+      hardware
+        .read_value(&HardwareReadCmd::new(Endpoint::Tx, 0, 0))
+        .await,
+      Err(ButtplugDeviceError::InvalidEndpoint(Endpoint::Tx))
+    ));
+    // The toy never answers the eager command.
+    assert!(matches!(
+      hardware
+        .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 50))
+        .await,
+      Err(ButtplugDeviceError::DeviceCommunicationError(_))
+    ));
+    assert!(matches!(
+      outgoing_receiver.recv().await,
+      Some(OutgoingLovenseData::Message(LovenseDongleOutgoingMessage {
+        eager: Some(1),
+        ..
+      }))
+    ));
+    // The toy goes away while we're waiting on it.
+    let read_fut = hardware.read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0));
+    drop(incoming_sender);
+    assert!(matches!(
+      read_fut.await,
+      Err(ButtplugDeviceError::DeviceNotConnected(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_dongle_port_closed() {
+    let (outgoing_sender, outgoing_receiver) = mpsc::channel(256);
+    let (_incoming_sender, incoming_receiver) = mpsc::channel(256);
+    let hardware = LovenseDongleHardware::new(TOY_ID, outgoing_sender, incoming_receiver);
+    drop(outgoing_receiver);
+    assert!(matches!(
+      hardware
+        .write_value(&HardwareWriteCmd::new(
+          Endpoint::Tx,
+          b"Vibrate:1;".to_vec(),
+          false
+        ))
+        .await,
+      Err(ButtplugDeviceError::DeviceNotConnected(_))
+    ));
+    assert!(matches!(
+      hardware
+        .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+        .await,
+      Err(ButtplugDeviceError::DeviceNotConnected(_))
+    ));
+  }
+}
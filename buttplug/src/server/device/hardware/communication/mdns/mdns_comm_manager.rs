@@ -0,0 +1,173 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::mdns_hardware::MdnsHardwareConnector;
+use crate::{
+  core::{errors::ButtplugDeviceError, ButtplugResultFuture},
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+  util::async_manager,
+};
+use futures::future::{self, FutureExt};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::Sender;
+
+/// Service type that Buttplug compatible devices advertise.
+pub const BUTTPLUG_MDNS_SERVICE_TYPE: &str = "_buttplug._tcp.local.";
+
+#[derive(Default, Clone)]
+pub struct MdnsCommunicationManagerBuilder {}
+
+impl HardwareCommunicationManagerBuilder for MdnsCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(MdnsCommunicationManager::new(sender))
+  }
+}
+
+fn device_found_event(info: &ServiceInfo) -> HardwareCommunicationManagerEvent {
+  let instance = info
+    .get_fullname()
+    .trim_end_matches(BUTTPLUG_MDNS_SERVICE_TYPE)
+    .trim_end_matches('.');
+  let name = info.get_property_val_str("name").unwrap_or(instance);
+  // Prefer IPv4, since that's what most microcontroller network stacks are best at.
+  let mut addresses: Vec<_> = info.get_addresses().iter().copied().collect();
+  addresses.sort_by_key(|address| !address.is_ipv4());
+  HardwareCommunicationManagerEvent::DeviceFound {
+    name: name.to_owned(),
+    address: instance.to_owned(),
+    creator: Box::new(MdnsHardwareConnector::new(
+      name,
+      instance,
+      &addresses,
+      info.get_port(),
+    )),
+  }
+}
+
+pub struct MdnsCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  daemon: Option<ServiceDaemon>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl MdnsCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+    Self {
+      sender,
+      daemon: None,
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+impl HardwareCommunicationManager for MdnsCommunicationManager {
+  fn name(&self) -> &'static str {
+    "MdnsCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    if self.is_scanning.load(Ordering::SeqCst) {
+      return future::ready(Ok(())).boxed();
+    }
+    // The daemon owns the mDNS sockets, so only bring it up once we're asked to look for devices.
+    if self.daemon.is_none() {
+      match ServiceDaemon::new() {
+        Ok(daemon) => self.daemon = Some(daemon),
+        Err(err) => {
+          return future::ready(Err(
+            ButtplugDeviceError::DeviceConnectionError(format!(
+              "Cannot start mDNS daemon: {}",
+              err
+            ))
+            .into(),
+          ))
+          .boxed()
+        }
+      }
+    }
+    let daemon = self.daemon.as_ref().expect("Created above.");
+    let receiver = match daemon.browse(BUTTPLUG_MDNS_SERVICE_TYPE) {
+      Ok(receiver) => receiver,
+      Err(err) => {
+        return future::ready(Err(
+          ButtplugDeviceError::DeviceConnectionError(format!(
+            "Cannot browse for mDNS devices: {}",
+            err
+          ))
+          .into(),
+        ))
+        .boxed()
+      }
+    };
+    self.is_scanning.store(true, Ordering::SeqCst);
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async_manager::spawn(async move {
+      while let Ok(event) = receiver.recv_async().await {
+        let event = match event {
+          // Devices that were already found show up again if they re-announce, the device manager
+          // ignores the ones that are still connected.
+          ServiceEvent::ServiceResolved(info) => device_found_event(&info),
+          ServiceEvent::SearchStopped(_) => break,
+          _ => continue,
+        };
+        if sender.send(event).await.is_err() {
+          debug!("Device manager event channel closed, exiting mDNS event loop.");
+          return;
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        debug!("Device manager event channel closed, cannot send scanning finished.");
+      }
+    });
+    future::ready(Ok(())).boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    if let Some(daemon) = &self.daemon {
+      if self.is_scanning.load(Ordering::SeqCst) {
+        if let Err(err) = daemon.stop_browse(BUTTPLUG_MDNS_SERVICE_TYPE) {
+          error!("Cannot stop mDNS browse: {}", err);
+        }
+      }
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
+
+impl Drop for MdnsCommunicationManager {
+  fn drop(&mut self) {
+    if let Some(daemon) = &self.daemon {
+      // We don't need to wait around for the daemon to finish shutting down.
+      let _ = daemon.shutdown();
+    }
+  }
+}
@@ -0,0 +1,474 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{MdnsScheme, MdnsSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::async_manager,
+};
+use async_trait::async_trait;
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  SinkExt,
+  StreamExt,
+};
+use std::{
+  fmt::{self, Debug},
+  net::{IpAddr, SocketAddr},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpStream, UdpSocket},
+  sync::{broadcast, mpsc},
+};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_util::sync::CancellationToken;
+
+pub struct MdnsHardwareConnector {
+  name: String,
+  address: String,
+  ip_addresses: Vec<IpAddr>,
+  port: u16,
+}
+
+impl MdnsHardwareConnector {
+  pub(super) fn new(name: &str, address: &str, ip_addresses: &[IpAddr], port: u16) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: address.to_owned(),
+      ip_addresses: ip_addresses.to_vec(),
+      port,
+    }
+  }
+}
+
+impl Debug for MdnsHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MdnsHardwareConnector")
+      .field("name", &self.name)
+      .field("ip_addresses", &self.ip_addresses)
+      .field("port", &self.port)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for MdnsHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::Mdns(MdnsSpecifier::new(std::slice::from_ref(&self.name)))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    Ok(Box::new(MdnsHardwareSpecializer {
+      name: self.name.clone(),
+      address: self.address.clone(),
+      ip_addresses: self.ip_addresses.clone(),
+      port: self.port,
+    }))
+  }
+}
+
+pub struct MdnsHardwareSpecializer {
+  name: String,
+  address: String,
+  ip_addresses: Vec<IpAddr>,
+  port: u16,
+}
+
+#[async_trait]
+impl HardwareSpecializer for MdnsHardwareSpecializer {
+  // Like serial, we don't know how to talk to the device until we know its protocol, so connection
+  // happens during specialization.
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let scheme = specifiers
+      .iter()
+      .find_map(|specifier| match specifier {
+        ProtocolCommunicationSpecifier::Mdns(mdns) => Some(*mdns.scheme()),
+        _ => None,
+      })
+      .unwrap_or_default();
+    let mut last_error = "Device announced no addresses".to_owned();
+    for ip in &self.ip_addresses {
+      match MdnsConnection::connect(scheme, SocketAddr::new(*ip, self.port)).await {
+        Ok(connection) => {
+          let hardware_internal = MdnsHardware::new(&self.address, connection);
+          return Ok(Hardware::new(
+            &self.name,
+            &self.address,
+            &[Endpoint::Rx, Endpoint::Tx],
+            Box::new(hardware_internal),
+          ));
+        }
+        Err(err) => {
+          debug!("Cannot connect to {} at {}: {}", self.name, ip, err);
+          last_error = err;
+        }
+      }
+    }
+    Err(ButtplugDeviceError::DeviceConnectionError(format!(
+      "Cannot connect to {}: {}",
+      self.name, last_error
+    )))
+  }
+}
+
+enum MdnsConnection {
+  Tcp(TcpStream),
+  Udp(UdpSocket),
+  Websocket(Box<WebSocketStream<MaybeTlsStream<TcpStream>>>),
+}
+
+impl MdnsConnection {
+  async fn connect(scheme: MdnsScheme, address: SocketAddr) -> Result<Self, String> {
+    match scheme {
+      MdnsScheme::Tcp => TcpStream::connect(address)
+        .await
+        .map(Self::Tcp)
+        .map_err(|err| err.to_string()),
+      MdnsScheme::Udp => {
+        let local: SocketAddr = if address.is_ipv4() {
+          ([0, 0, 0, 0], 0).into()
+        } else {
+          ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)
+          .await
+          .map_err(|err| err.to_string())?;
+        socket
+          .connect(address)
+          .await
+          .map_err(|err| err.to_string())?;
+        Ok(Self::Udp(socket))
+      }
+      MdnsScheme::Websocket => tokio_tungstenite::connect_async(format!("ws://{}", address))
+        .await
+        .map(|(stream, _)| Self::Websocket(Box::new(stream)))
+        .map_err(|err| err.to_string()),
+    }
+  }
+
+  /// Pass data between the hardware and the device until the connection drops, the hardware goes
+  /// away, or the token is cancelled.
+  async fn run(
+    self,
+    mut outgoing: mpsc::Receiver<Vec<u8>>,
+    incoming: impl Fn(Vec<u8>),
+    token: CancellationToken,
+  ) {
+    let mut buf = [0u8; 1024];
+    match self {
+      Self::Tcp(stream) => {
+        let (mut reader, mut writer) = stream.into_split();
+        loop {
+          tokio::select! {
+            data = outgoing.recv() => {
+              let Some(data) = data else { break };
+              if let Err(err) = writer.write_all(&data).await {
+                error!("Cannot write to mDNS TCP device: {}", err);
+                break;
+              }
+            }
+            result = reader.read(&mut buf) => match result {
+              Ok(len) if len > 0 => incoming(buf[..len].to_vec()),
+              _ => break,
+            },
+            _ = token.cancelled() => break,
+          }
+        }
+      }
+      Self::Udp(socket) => loop {
+        tokio::select! {
+          data = outgoing.recv() => {
+            let Some(data) = data else { break };
+            if let Err(err) = socket.send(&data).await {
+              error!("Cannot write to mDNS UDP device: {}", err);
+              break;
+            }
+          }
+          result = socket.recv(&mut buf) => match result {
+            Ok(len) => incoming(buf[..len].to_vec()),
+            // UDP has no connection to lose, but errors like ICMP port unreachable mean the device
+            // is gone.
+            Err(_) => break,
+          },
+          _ = token.cancelled() => break,
+        }
+      },
+      Self::Websocket(stream) => {
+        let (mut writer, mut reader) = stream.split();
+        loop {
+          tokio::select! {
+            data = outgoing.recv() => {
+              let Some(data) = data else { break };
+              if let Err(err) = writer.send(Message::Binary(data)).await {
+                error!("Cannot write to mDNS websocket device: {}", err);
+                break;
+              }
+            }
+            message = reader.next() => match message {
+              Some(Ok(Message::Binary(data))) => incoming(data),
+              Some(Ok(Message::Text(text))) => incoming(text.into_bytes()),
+              Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+              // Pings are answered by tungstenite.
+              Some(Ok(_)) => {}
+            },
+            _ = token.cancelled() => break,
+          }
+        }
+      }
+    }
+  }
+}
+
+pub struct MdnsHardware {
+  outgoing_sender: mpsc::Sender<Vec<u8>>,
+  subscribed: Arc<AtomicBool>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  connection_token: CancellationToken,
+}
+
+impl MdnsHardware {
+  fn new(address: &str, connection: MdnsConnection) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let (outgoing_sender, outgoing_receiver) = mpsc::channel(256);
+    let subscribed = Arc::new(AtomicBool::new(false));
+    let connection_token = CancellationToken::new();
+    let address = address.to_owned();
+    let sender_clone = event_sender.clone();
+    let subscribed_clone = subscribed.clone();
+    let token = connection_token.child_token();
+    async_manager::spawn(async move {
+      let notification_address = address.clone();
+      let notification_sender = sender_clone.clone();
+      let incoming = move |data: Vec<u8>| {
+        if subscribed_clone.load(Ordering::SeqCst) {
+          // We don't really care if there's no one to send the data to here.
+          let _ = notification_sender.send(HardwareEvent::Notification(
+            notification_address.clone(),
+            Endpoint::Rx,
+            data,
+          ));
+        }
+      };
+      connection
+        .run(outgoing_receiver, incoming, token.clone())
+        .await;
+      if !token.is_cancelled() {
+        info!("mDNS device {} disconnected.", address);
+        let _ = sender_clone.send(HardwareEvent::Disconnected(
+          address,
+          DeviceRemovedReason::LinkLost,
+          false,
+        ));
+      }
+    });
+    Self {
+      outgoing_sender,
+      subscribed,
+      event_sender,
+      connection_token,
+    }
+  }
+}
+
+impl Drop for MdnsHardware {
+  fn drop(&mut self) {
+    self.connection_token.cancel();
+  }
+}
+
+impl HardwareInternal for MdnsHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.connection_token.cancel();
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "mDNS devices do not support read, subscribe to Rx instead".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let sender = self.outgoing_sender.clone();
+    let data = msg.data.clone();
+    async move {
+      sender.send(data).await.map_err(|_| {
+        ButtplugDeviceError::DeviceNotConnected("mDNS device connection closed.".to_owned())
+      })
+    }
+    .boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.subscribed.store(true, Ordering::SeqCst);
+    future::ready(Ok(())).boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.subscribed.store(false, Ordering::SeqCst);
+    future::ready(Ok(())).boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use tokio::net::TcpListener;
+
+  async fn specialize(scheme: MdnsScheme, port: u16) -> Hardware {
+    let mut specifier = MdnsSpecifier::new(&["Test Toy".to_owned()]);
+    specifier.set_scheme(scheme);
+    let mut connector = MdnsHardwareConnector::new(
+      "Test Toy",
+      "test-toy",
+      &[IpAddr::from([127, 0, 0, 1])],
+      port,
+    );
+    connector
+      .connect()
+      .await
+      .expect("Test, assuming infallible.")
+      .specialize(&[ProtocolCommunicationSpecifier::Mdns(specifier)])
+      .await
+      .expect("Test, assuming infallible.")
+  }
+
+  async fn expect_notification(events: &mut broadcast::Receiver<HardwareEvent>, data: &[u8]) {
+    match events.recv().await.expect("Test, assuming infallible.") {
+      HardwareEvent::Notification(_, endpoint, notification) => {
+        assert_eq!(endpoint, Endpoint::Rx);
+        assert_eq!(notification, data);
+      }
+      event => panic!("Unexpected event {:?}", event),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_mdns_tcp_device() {
+    let listener = TcpListener::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let port = listener
+      .local_addr()
+      .expect("Test, assuming infallible.")
+      .port();
+    let hardware = specialize(MdnsScheme::Tcp, port).await;
+    let (mut device, _) = listener.accept().await.expect("Test, assuming infallible.");
+    let mut events = hardware.event_stream();
+
+    hardware
+      .write_value(&HardwareWriteCmd::new(
+        Endpoint::Tx,
+        b"L0500\n".to_vec(),
+        false,
+      ))
+      .await
+      .expect("Test, assuming infallible.");
+    let mut buf = [0u8; 6];
+    device
+      .read_exact(&mut buf)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(&buf, b"L0500\n");
+
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+      .await
+      .expect("Test, assuming infallible.");
+    device
+      .write_all(b"ok\n")
+      .await
+      .expect("Test, assuming infallible.");
+    expect_notification(&mut events, b"ok\n").await;
+
+    drop(device);
+    assert!(matches!(
+      events.recv().await.expect("Test, assuming infallible."),
+      HardwareEvent::Disconnected(address, ..) if address == "test-toy"
+    ));
+  }
+
+  #[tokio::test]
+  async fn test_mdns_udp_device() {
+    let device = UdpSocket::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let port = device
+      .local_addr()
+      .expect("Test, assuming infallible.")
+      .port();
+    let hardware = specialize(MdnsScheme::Udp, port).await;
+    let mut events = hardware.event_stream();
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+      .await
+      .expect("Test, assuming infallible.");
+
+    hardware
+      .write_value(&HardwareWriteCmd::new(
+        Endpoint::Tx,
+        b"L0500\n".to_vec(),
+        false,
+      ))
+      .await
+      .expect("Test, assuming infallible.");
+    let mut buf = [0u8; 64];
+    let (len, hardware_address) = device
+      .recv_from(&mut buf)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(&buf[..len], b"L0500\n");
+
+    device
+      .send_to(b"ok\n", hardware_address)
+      .await
+      .expect("Test, assuming infallible.");
+    expect_notification(&mut events, b"ok\n").await;
+  }
+}
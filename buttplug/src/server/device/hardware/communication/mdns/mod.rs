@@ -0,0 +1,26 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Devices that announce themselves on the local network
+//!
+//! DIY toys (usually ESP32 based) can advertise a `_buttplug._tcp` service over mDNS/zeroconf, so
+//! users don't need to enter their addresses by hand. Devices are identified by the `name` TXT
+//! record in their announcement (falling back to the service instance name), which is matched
+//! against the `mdns` names in the device configuration. The configuration also declares whether
+//! the device talks raw TCP, UDP, or websockets on the announced port.
+//!
+//! Whatever the transport, devices are exposed with a Tx endpoint for data going to the device, and
+//! an Rx endpoint for data coming back.
+
+mod mdns_comm_manager;
+mod mdns_hardware;
+pub use mdns_comm_manager::{
+  MdnsCommunicationManager,
+  MdnsCommunicationManagerBuilder,
+  BUTTPLUG_MDNS_SERVICE_TYPE,
+};
+pub use mdns_hardware::MdnsHardware;
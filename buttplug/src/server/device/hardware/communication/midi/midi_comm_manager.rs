@@ -0,0 +1,238 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::midi_hardware::MidiHardwareConnector;
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::{
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+      TimedRetryCommunicationManager,
+      TimedRetryCommunicationManagerImpl,
+    },
+    protocol::midi::{MIDI_PACKET_CONTROL_CHANGE, MIDI_PACKET_PITCH_BEND},
+  },
+};
+use async_trait::async_trait;
+use midir::MidiOutput;
+use std::collections::HashMap;
+use tokio::sync::mpsc::Sender;
+
+// Name the server shows up as to the MIDI system.
+pub(super) const MIDI_CLIENT_NAME: &str = "Buttplug";
+
+// Controllers 20-31 are left undefined by the MIDI spec, so they're free for general use.
+const DEFAULT_FIRST_CONTROLLER: u8 = 20;
+
+const MIDI_CONTROL_CHANGE: u8 = 0xb0;
+const MIDI_PITCH_BEND: u8 = 0xe0;
+
+/// Which MIDI output port to use as a device, and where its actuators send values.
+#[derive(Debug, Clone)]
+pub struct MidiOutputDefinition {
+  name: String,
+  port_name: String,
+  controls: HashMap<u8, (u8, u8)>,
+  pitch_bend_channels: HashMap<u8, u8>,
+}
+
+impl MidiOutputDefinition {
+  /// Define a device named name (which is matched against the `midi` names in the device
+  /// configuration), using the first output port with port_name in its name.
+  ///
+  /// Until set otherwise, scalar actuator N sends Control Change 20 + N on channel 1, and linear
+  /// actuator N sends pitch bend on channel N + 1.
+  pub fn new(name: &str, port_name: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      port_name: port_name.to_owned(),
+      controls: HashMap::new(),
+      pitch_bend_channels: HashMap::new(),
+    }
+  }
+
+  /// Send the values of the scalar actuator at index as controller (0-127) on channel (0-15, with
+  /// 0 being MIDI channel 1).
+  pub fn control(&mut self, index: u8, channel: u8, controller: u8) -> &mut Self {
+    self
+      .controls
+      .insert(index, (channel & 0x0f, controller & 0x7f));
+    self
+  }
+
+  /// Send the positions of the linear actuator at index as pitch bend on channel (0-15, with 0
+  /// being MIDI channel 1).
+  pub fn pitch_bend_channel(&mut self, index: u8, channel: u8) -> &mut Self {
+    self.pitch_bend_channels.insert(index, channel & 0x0f);
+    self
+  }
+
+  pub(super) fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Turns a packet from the midi protocol into the MIDI message for it.
+  pub(super) fn midi_message(&self, packet: &[u8]) -> Result<Vec<u8>, ButtplugDeviceError> {
+    match *packet {
+      [MIDI_PACKET_CONTROL_CHANGE, index, value] => {
+        let (channel, controller) = self
+          .controls
+          .get(&index)
+          .copied()
+          .unwrap_or((0, DEFAULT_FIRST_CONTROLLER.saturating_add(index) & 0x7f));
+        Ok(vec![
+          MIDI_CONTROL_CHANGE | channel,
+          controller,
+          value & 0x7f,
+        ])
+      }
+      [MIDI_PACKET_PITCH_BEND, index, lsb, msb] => {
+        let channel = self
+          .pitch_bend_channels
+          .get(&index)
+          .copied()
+          .unwrap_or(index & 0x0f);
+        Ok(vec![MIDI_PITCH_BEND | channel, lsb & 0x7f, msb & 0x7f])
+      }
+      _ => Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+        "Cannot turn {:?} into a MIDI message",
+        packet
+      ))),
+    }
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct MidiCommunicationManagerBuilder {
+  devices: Vec<MidiOutputDefinition>,
+}
+
+impl MidiCommunicationManagerBuilder {
+  /// Add a MIDI output to look for while scanning.
+  pub fn device(&mut self, definition: MidiOutputDefinition) -> &mut Self {
+    self.devices.push(definition);
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for MidiCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(TimedRetryCommunicationManager::new(
+      MidiCommunicationManager::new(sender, self.devices.clone()),
+    ))
+  }
+}
+
+pub struct MidiCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<MidiOutputDefinition>,
+}
+
+impl MidiCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    devices: Vec<MidiOutputDefinition>,
+  ) -> Self {
+    Self { sender, devices }
+  }
+}
+
+#[async_trait]
+impl TimedRetryCommunicationManagerImpl for MidiCommunicationManager {
+  fn name(&self) -> &'static str {
+    "MidiCommunicationManager"
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("midi")
+  }
+
+  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+    // The MIDI client can't be held across awaits on every platform, so only the port names are
+    // kept around.
+    let port_names: Vec<String> = {
+      let output = MidiOutput::new(MIDI_CLIENT_NAME).map_err(|err| {
+        ButtplugDeviceError::DeviceConnectionError(format!("Cannot open MIDI system: {}", err))
+      })?;
+      output
+        .ports()
+        .iter()
+        .filter_map(|port| output.port_name(port).ok())
+        .collect()
+    };
+    for definition in &self.devices {
+      let Some(port_name) = port_names
+        .iter()
+        .find(|port_name| port_name.contains(&definition.port_name))
+      else {
+        continue;
+      };
+      // Outputs are found again every scan, the device manager ignores the ones that are already
+      // connected.
+      if self
+        .sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name: definition.name.clone(),
+          address: port_name.clone(),
+          creator: Box::new(MidiHardwareConnector::new(definition.clone(), port_name)),
+        })
+        .await
+        .is_err()
+      {
+        error!("Error sending device found message from MIDI Manager.");
+        break;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_midi_messages() {
+    let mut definition = MidiOutputDefinition::new("MIDI Output", "Test Port");
+    assert_eq!(
+      definition
+        .midi_message(&[MIDI_PACKET_CONTROL_CHANGE, 1, 64])
+        .expect("Test, assuming infallible."),
+      vec![0xb0, 21, 64]
+    );
+    assert_eq!(
+      definition
+        .midi_message(&[MIDI_PACKET_PITCH_BEND, 1, 0x00, 0x40])
+        .expect("Test, assuming infallible."),
+      vec![0xe1, 0x00, 0x40]
+    );
+
+    definition.control(1, 9, 74).pitch_bend_channel(1, 15);
+    assert_eq!(
+      definition
+        .midi_message(&[MIDI_PACKET_CONTROL_CHANGE, 1, 64])
+        .expect("Test, assuming infallible."),
+      vec![0xb9, 74, 64]
+    );
+    assert_eq!(
+      definition
+        .midi_message(&[MIDI_PACKET_PITCH_BEND, 1, 0x7f, 0x7f])
+        .expect("Test, assuming infallible."),
+      vec![0xef, 0x7f, 0x7f]
+    );
+    assert!(definition.midi_message(&[0x05, 0]).is_err());
+  }
+}
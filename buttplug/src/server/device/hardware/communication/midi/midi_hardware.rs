@@ -0,0 +1,211 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::midi_comm_manager::{MidiOutputDefinition, MIDI_CLIENT_NAME};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{MidiSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      GenericHardwareSpecializer,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use midir::{MidiOutput, MidiOutputConnection};
+use std::{
+  fmt::{self, Debug},
+  sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+
+fn open_port(port_name: &str) -> Result<MidiOutputConnection, ButtplugDeviceError> {
+  let output = MidiOutput::new(MIDI_CLIENT_NAME).map_err(|err| {
+    ButtplugDeviceError::DeviceConnectionError(format!("Cannot open MIDI system: {}", err))
+  })?;
+  let port = output
+    .ports()
+    .into_iter()
+    .find(|port| output.port_name(port).is_ok_and(|name| name == port_name))
+    .ok_or_else(|| {
+      ButtplugDeviceError::DeviceConnectionError(format!("MIDI port {} went away", port_name))
+    })?;
+  output.connect(&port, "buttplug-output").map_err(|err| {
+    ButtplugDeviceError::DeviceConnectionError(format!(
+      "Cannot open MIDI port {}: {}",
+      port_name, err
+    ))
+  })
+}
+
+pub struct MidiHardwareConnector {
+  definition: MidiOutputDefinition,
+  port_name: String,
+}
+
+impl MidiHardwareConnector {
+  pub(super) fn new(definition: MidiOutputDefinition, port_name: &str) -> Self {
+    Self {
+      definition,
+      port_name: port_name.to_owned(),
+    }
+  }
+}
+
+impl Debug for MidiHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("MidiHardwareConnector")
+      .field("name", &self.definition.name())
+      .field("port_name", &self.port_name)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for MidiHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::Midi(MidiSpecifier::new(&[self.definition.name().to_owned()]))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    let connection = open_port(&self.port_name)?;
+    info!("Opened MIDI output port {}", self.port_name);
+    let hardware = Hardware::new(
+      self.definition.name(),
+      &self.port_name,
+      &[Endpoint::Tx],
+      Box::new(MidiHardware::new(
+        &self.port_name,
+        self.definition.clone(),
+        connection,
+      )),
+    );
+    Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
+  }
+}
+
+pub struct MidiHardware {
+  address: String,
+  definition: MidiOutputDefinition,
+  // Taken out when the device is disconnected, which closes the port.
+  connection: Arc<Mutex<Option<MidiOutputConnection>>>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl MidiHardware {
+  fn new(
+    address: &str,
+    definition: MidiOutputDefinition,
+    connection: MidiOutputConnection,
+  ) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      address: address.to_owned(),
+      definition,
+      connection: Arc::new(Mutex::new(Some(connection))),
+      event_sender,
+    }
+  }
+}
+
+impl HardwareInternal for MidiHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if let Some(connection) = self
+      .connection
+      .lock()
+      .expect("Lock is never held across a panic")
+      .take()
+    {
+      connection.close();
+    }
+    let _ = self.event_sender.send(HardwareEvent::Disconnected(
+      self.address.clone(),
+      DeviceRemovedReason::UserDisconnect,
+      false,
+    ));
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "MIDI outputs do not support read".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::Tx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
+    let result = self
+      .definition
+      .midi_message(msg.data())
+      .and_then(|message| {
+        match self
+          .connection
+          .lock()
+          .expect("Lock is never held across a panic")
+          .as_mut()
+        {
+          Some(connection) => connection.send(&message).map_err(|err| {
+            ButtplugDeviceError::DeviceCommunicationError(format!(
+              "Cannot send to MIDI port {}: {}",
+              self.address, err
+            ))
+          }),
+          None => Err(ButtplugDeviceError::DeviceNotConnected(
+            self.address.clone(),
+          )),
+        }
+      });
+    future::ready(result).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "MIDI outputs do not support subscribe".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "MIDI outputs do not support unsubscribe".to_owned(),
+    )))
+    .boxed()
+  }
+}
@@ -0,0 +1,26 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! MIDI output ports as devices
+//!
+//! Lets Buttplug apps drive anything that listens to MIDI, like DAWs, lighting rigs, or DIY
+//! hardware. Outputs are defined by the user: the name the device is matched on in the device
+//! configuration ("MIDI Output" for the built in `midi` protocol), the port to open, and which
+//! channels and controllers the actuators send on. The manager opens the port once it shows up
+//! while scanning.
+//!
+//! The [midi protocol](crate::server::device::protocol::midi) writes actuator values to Tx, and the
+//! hardware turns them into Control Change and pitch bend messages using the output definition.
+
+mod midi_comm_manager;
+mod midi_hardware;
+pub use midi_comm_manager::{
+  MidiCommunicationManager,
+  MidiCommunicationManagerBuilder,
+  MidiOutputDefinition,
+};
+pub use midi_hardware::MidiHardware;
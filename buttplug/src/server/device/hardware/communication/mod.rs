@@ -10,6 +10,18 @@
 pub mod lovense_connect_service;
 #[cfg(feature = "websocket-server-manager")]
 pub mod websocket_server;
+#[cfg(feature = "remote-server-manager")]
+pub mod remote_server;
+#[cfg(feature = "network-manager")]
+pub mod network;
+#[cfg(feature = "mdns-manager")]
+pub mod mdns;
+#[cfg(feature = "dmx-manager")]
+pub mod dmx;
+
+// Simulated devices don't touch any hardware, so they also work everywhere
+#[cfg(feature = "simulated-manager")]
+pub mod simulated;
 
 // BTLEPlug works on anything not WASM
 #[cfg(all(
@@ -24,6 +36,10 @@ pub mod websocket_server;
 ))]
 pub mod btleplug;
 
+// Android needs some extra setup on top of BTLEPlug
+#[cfg(all(feature = "android-manager", target_os = "android"))]
+pub mod android;
+
 // Lovense Dongles and Serial Ports work on all desktop platforms
 #[cfg(all(
   feature = "lovense-dongle-manager",
@@ -41,6 +57,16 @@ pub mod serialport;
   any(target_os = "windows", target_os = "macos", target_os = "linux")
 ))]
 pub mod hid;
+#[cfg(all(
+  feature = "midi-manager",
+  any(target_os = "windows", target_os = "macos", target_os = "linux")
+))]
+pub mod midi;
+#[cfg(all(
+  feature = "audio-output-manager",
+  any(target_os = "windows", target_os = "macos", target_os = "linux")
+))]
+pub mod audio_output;
 
 // XInput is windows only
 #[cfg(all(feature = "xinput-manager", target_os = "windows"))]
@@ -50,6 +76,10 @@ pub mod xinput;
 #[cfg(all(feature = "evdev-manager", target_os = "linux"))]
 pub mod evdev;
 
+// WebBluetooth only exists in browsers
+#[cfg(all(feature = "webbluetooth-manager", target_arch = "wasm32"))]
+pub mod webbluetooth;
+
 use crate::{
   core::{errors::ButtplugDeviceError, ButtplugResultFuture},
   server::device::hardware::HardwareConnector,
@@ -90,6 +120,23 @@ pub trait HardwareCommunicationManager: Send + Sync {
     false
   }
   fn can_scan(&self) -> bool;
+  /// Transport of the devices this manager finds (btle, serial, etc...), if it only finds one
+  /// kind. Scans filtered to other transports skip managers that return a transport here.
+  fn transport(&self) -> Option<&'static str> {
+    None
+  }
+  /// Called when the app embedding the server moves to the background (e.g. iOS suspending the app
+  /// with the bluetooth-central background mode). Scanning has already been stopped by the device
+  /// manager, managers only need to override this if they hold other platform resources that must
+  /// be paused. Connected devices should be kept.
+  fn enter_background(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+  /// Called when the app embedding the server returns to the foreground, before any suspended scan
+  /// is resumed.
+  fn enter_foreground(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
   // Events happen via channel senders passed to the comm manager.
 }
 
@@ -118,12 +165,18 @@ pub enum HardwareSpecificError {
   ))]
   #[error("Serial error: {0}")]
   SerialError(String),
+  #[cfg(all(feature = "webbluetooth-manager", target_arch = "wasm32"))]
+  #[error("WebBluetooth error: {0}")]
+  WebBluetoothError(String),
 }
 
 #[async_trait]
 pub trait TimedRetryCommunicationManagerImpl: Sync + Send {
   fn name(&self) -> &'static str;
   fn can_scan(&self) -> bool;
+  fn transport(&self) -> Option<&'static str> {
+    None
+  }
   fn rescan_wait_duration(&self) -> Duration {
     Duration::from_secs(1)
   }
@@ -163,7 +216,12 @@ impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
     async move {
       async_manager::spawn(async move {
         loop {
-          if let Err(err) = comm_manager.scan().await {
+          // Stopping scanning shouldn't have to wait on a slow scan to finish.
+          let result = tokio::select! {
+            result = comm_manager.scan() => result,
+            _ = child_token.cancelled() => break,
+          };
+          if let Err(err) = result {
             error!("Timed Device Communication Manager Failure: {}", err);
             break;
           }
@@ -192,6 +250,9 @@ impl<T: TimedRetryCommunicationManagerImpl> HardwareCommunicationManager
   fn can_scan(&self) -> bool {
     self.comm_manager.can_scan()
   }
+  fn transport(&self) -> Option<&'static str> {
+    self.comm_manager.transport()
+  }
 }
 
 impl<T: TimedRetryCommunicationManagerImpl> Drop for TimedRetryCommunicationManager<T> {
@@ -201,3 +262,58 @@ impl<T: TimedRetryCommunicationManagerImpl> Drop for TimedRetryCommunicationMana
     let _ = self.stop_scanning();
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::sync::atomic::{AtomicBool, Ordering};
+
+  struct DropFlag(Arc<AtomicBool>);
+
+  impl Drop for DropFlag {
+    fn drop(&mut self) {
+      self.0.store(true, Ordering::SeqCst);
+    }
+  }
+
+  // Scan that never finishes, like a walk over a device tree that's stuck on one device.
+  struct StuckScanCommunicationManager {
+    scan_dropped: Arc<AtomicBool>,
+  }
+
+  #[async_trait]
+  impl TimedRetryCommunicationManagerImpl for StuckScanCommunicationManager {
+    fn name(&self) -> &'static str {
+      "StuckScanCommunicationManager"
+    }
+
+    fn can_scan(&self) -> bool {
+      true
+    }
+
+    async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+      let _flag = DropFlag(self.scan_dropped.clone());
+      future::pending().await
+    }
+  }
+
+  #[tokio::test]
+  async fn test_stop_scanning_interrupts_scan() {
+    let scan_dropped = Arc::new(AtomicBool::new(false));
+    let mut manager = TimedRetryCommunicationManager::new(StuckScanCommunicationManager {
+      scan_dropped: scan_dropped.clone(),
+    });
+    manager
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    sleep(Duration::from_millis(50)).await;
+    assert!(!scan_dropped.load(Ordering::SeqCst));
+    manager
+      .stop_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    sleep(Duration::from_millis(50)).await;
+    assert!(scan_dropped.load(Ordering::SeqCst));
+  }
+}
@@ -0,0 +1,26 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Devices controlled over HTTP
+//!
+//! Some devices, like the Autoblow AI Ultra, sit on Wi-Fi and are controlled through a REST API
+//! instead of over Bluetooth. There's no way to scan for them, so the user tells the network
+//! communication manager where each device lives and how to authenticate with it, and the manager
+//! checks in on them while scanning.
+//!
+//! Protocols build requests as text writes to the Tx endpoint. The first line is the method and
+//! path (relative to the device's base URL), and anything after it is sent as a JSON body, i.e.
+//! `PUT autoblow/oscillate\n{"speed":50}`.
+
+mod network_comm_manager;
+mod network_hardware;
+pub use network_comm_manager::{
+  NetworkCommunicationManager,
+  NetworkCommunicationManagerBuilder,
+  NetworkDeviceDefinition,
+};
+pub use network_hardware::NetworkHardware;
@@ -0,0 +1,227 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::network_hardware::NetworkHardwareConnector;
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+    TimedRetryCommunicationManager,
+    TimedRetryCommunicationManagerImpl,
+  },
+};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+// Autoblow's cloud API, which relays commands to devices paired with it.
+const AUTOBLOW_CLOUD_URL: &str = "https://latency.autoblowapi.com";
+
+/// Where to find an HTTP controlled device, and how to talk to it.
+#[derive(Debug, Clone)]
+pub struct NetworkDeviceDefinition {
+  name: String,
+  address: String,
+  url: String,
+  probe_path: String,
+  headers: Vec<(String, String)>,
+}
+
+impl NetworkDeviceDefinition {
+  /// Define a device named name (which is matched against the `network` names in the device
+  /// configuration), reachable at url. Address needs to be unique for each device.
+  pub fn new(name: &str, address: &str, url: &str) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: address.to_owned(),
+      url: url.trim_end_matches('/').to_owned(),
+      probe_path: String::new(),
+      headers: vec![],
+    }
+  }
+
+  /// Define an Autoblow AI Ultra, using the device token shown in the Autoblow app when pairing.
+  /// Commands go through Autoblow's cloud unless the url is changed to the device's local address.
+  ///
+  /// The token works as a password for the device, and addresses end up in logs, user configs and
+  /// messages to clients, so the address is derived from a hash of the token instead.
+  pub fn autoblow(token: &str) -> Self {
+    let hash = Sha256::digest(token.as_bytes());
+    let address: String = hash[..8]
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect();
+    let mut definition = Self::new(
+      "Autoblow AI Ultra",
+      &format!("autoblow-{}", address),
+      AUTOBLOW_CLOUD_URL,
+    );
+    definition
+      .probe_path("autoblow/connected")
+      .header("x-device-token", token);
+    definition
+  }
+
+  /// Address the device will be known by.
+  pub fn address(&self) -> &str {
+    &self.address
+  }
+
+  /// Base URL of the device's API.
+  pub fn url(&mut self, url: &str) -> &mut Self {
+    self.url = url.trim_end_matches('/').to_owned();
+    self
+  }
+
+  /// Path that's requested while scanning to check whether the device is online. The device is
+  /// found once it answers with a success status. Defaults to the base URL.
+  pub fn probe_path(&mut self, path: &str) -> &mut Self {
+    self.probe_path = path.to_owned();
+    self
+  }
+
+  /// Header sent with every request to the device, usually for authentication.
+  pub fn header(&mut self, name: &str, value: &str) -> &mut Self {
+    self.headers.push((name.to_owned(), value.to_owned()));
+    self
+  }
+
+  fn http_client(&self) -> Result<reqwest::Client, ButtplugDeviceError> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &self.headers {
+      let name = HeaderName::from_bytes(name.as_bytes()).map_err(|err| {
+        ButtplugDeviceError::DeviceConfigurationError(format!("Invalid header {}: {}", name, err))
+      })?;
+      let value = HeaderValue::from_str(value).map_err(|err| {
+        ButtplugDeviceError::DeviceConfigurationError(format!("Invalid header {}: {}", name, err))
+      })?;
+      headers.insert(name, value);
+    }
+    reqwest::Client::builder()
+      .default_headers(headers)
+      .build()
+      .map_err(|err| ButtplugDeviceError::DeviceConfigurationError(err.to_string()))
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct NetworkCommunicationManagerBuilder {
+  devices: Vec<NetworkDeviceDefinition>,
+}
+
+impl NetworkCommunicationManagerBuilder {
+  /// Add a device to check for while scanning.
+  pub fn device(&mut self, definition: NetworkDeviceDefinition) -> &mut Self {
+    self.devices.push(definition);
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for NetworkCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    let devices = self
+      .devices
+      .iter()
+      .filter_map(|definition| match definition.http_client() {
+        Ok(client) => Some(NetworkDevice {
+          definition: definition.clone(),
+          client,
+        }),
+        Err(err) => {
+          error!("Cannot set up network device {}: {}", definition.name, err);
+          None
+        }
+      })
+      .collect();
+    Box::new(TimedRetryCommunicationManager::new(
+      NetworkCommunicationManager::new(sender, devices),
+    ))
+  }
+}
+
+struct NetworkDevice {
+  definition: NetworkDeviceDefinition,
+  client: reqwest::Client,
+}
+
+pub struct NetworkCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<NetworkDevice>,
+}
+
+impl NetworkCommunicationManager {
+  fn new(sender: Sender<HardwareCommunicationManagerEvent>, devices: Vec<NetworkDevice>) -> Self {
+    Self { sender, devices }
+  }
+}
+
+/// Check whether a device is answering requests.
+pub(super) async fn probe_device(client: &reqwest::Client, url: &str, probe_path: &str) -> bool {
+  match client.get(format!("{}/{}", url, probe_path)).send().await {
+    Ok(res) if res.status().is_success() => true,
+    Ok(res) => {
+      debug!("Network device at {} returned {}", url, res.status());
+      false
+    }
+    Err(err) => {
+      debug!("Network device at {} unreachable: {}", url, err);
+      false
+    }
+  }
+}
+
+#[async_trait]
+impl TimedRetryCommunicationManagerImpl for NetworkCommunicationManager {
+  fn name(&self) -> &'static str {
+    "NetworkCommunicationManager"
+  }
+
+  fn rescan_wait_duration(&self) -> Duration {
+    Duration::from_secs(5)
+  }
+
+  async fn scan(&self) -> Result<(), ButtplugDeviceError> {
+    for device in &self.devices {
+      let definition = &device.definition;
+      if !probe_device(&device.client, &definition.url, &definition.probe_path).await {
+        continue;
+      }
+      // Devices are found again every scan, the device manager ignores the ones that are already
+      // connected.
+      if self
+        .sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name: definition.name.clone(),
+          address: definition.address.clone(),
+          creator: Box::new(NetworkHardwareConnector::new(
+            &definition.name,
+            &definition.address,
+            &definition.url,
+            &definition.probe_path,
+            device.client.clone(),
+          )),
+        })
+        .await
+        .is_err()
+      {
+        error!("Error sending device found message from Network Manager.");
+      }
+    }
+    Ok(())
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
@@ -0,0 +1,233 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::network_comm_manager::probe_device;
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{NetworkSpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      GenericHardwareSpecializer,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::async_manager,
+};
+use async_trait::async_trait;
+use futures::future::{self, BoxFuture, FutureExt};
+use reqwest::{header::CONTENT_TYPE, Method};
+use std::{
+  fmt::{self, Debug},
+  time::Duration,
+};
+use tokio::sync::broadcast;
+
+// How often to check that a connected device is still online.
+const NETWORK_DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct NetworkHardwareConnector {
+  name: String,
+  address: String,
+  url: String,
+  probe_path: String,
+  client: reqwest::Client,
+}
+
+impl NetworkHardwareConnector {
+  pub(super) fn new(
+    name: &str,
+    address: &str,
+    url: &str,
+    probe_path: &str,
+    client: reqwest::Client,
+  ) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: address.to_owned(),
+      url: url.to_owned(),
+      probe_path: probe_path.to_owned(),
+      client,
+    }
+  }
+}
+
+impl Debug for NetworkHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("NetworkHardwareConnector")
+      .field("name", &self.name)
+      .field("url", &self.url)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for NetworkHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::Network(NetworkSpecifier::new(std::slice::from_ref(&self.name)))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    let hardware_internal = NetworkHardware::new(
+      &self.address,
+      &self.url,
+      &self.probe_path,
+      self.client.clone(),
+    );
+    let hardware = Hardware::new(
+      &self.name,
+      &self.address,
+      &[Endpoint::Tx],
+      Box::new(hardware_internal),
+    );
+    Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct NetworkHardware {
+  event_sender: broadcast::Sender<HardwareEvent>,
+  url: String,
+  client: reqwest::Client,
+}
+
+impl NetworkHardware {
+  fn new(address: &str, url: &str, probe_path: &str, client: reqwest::Client) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let sender_clone = event_sender.clone();
+    let address = address.to_owned();
+    let check_url = url.to_owned();
+    let probe_path = probe_path.to_owned();
+    let check_client = client.clone();
+    async_manager::spawn(async move {
+      loop {
+        tokio::time::sleep(NETWORK_DEVICE_CHECK_INTERVAL).await;
+        // Once nothing is listening, the device has been dropped.
+        if sender_clone.receiver_count() == 0 {
+          break;
+        }
+        if !probe_device(&check_client, &check_url, &probe_path).await {
+          info!("Network device {} went offline.", address);
+          let _ = sender_clone.send(HardwareEvent::Disconnected(
+            address.clone(),
+            DeviceRemovedReason::LinkLost,
+            false,
+          ));
+          break;
+        }
+      }
+    });
+    Self {
+      event_sender,
+      url: url.to_owned(),
+      client,
+    }
+  }
+}
+
+impl HardwareInternal for NetworkHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    _msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Network devices do not support read".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let request = match std::str::from_utf8(&msg.data) {
+      Ok(request) => request,
+      Err(err) => {
+        return future::ready(Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+          "Network device commands must be UTF-8: {}",
+          err
+        ))))
+        .boxed()
+      }
+    };
+    let (request_line, body) = request.split_once('\n').unwrap_or((request, ""));
+    let (method, path) = request_line
+      .split_once(' ')
+      .unwrap_or(("GET", request_line));
+    let method = match Method::from_bytes(method.as_bytes()) {
+      Ok(method) => method,
+      Err(err) => {
+        return future::ready(Err(ButtplugDeviceError::DeviceCommunicationError(
+          err.to_string(),
+        )))
+        .boxed()
+      }
+    };
+    let mut request = self
+      .client
+      .request(method, format!("{}/{}", self.url, path));
+    if !body.is_empty() {
+      request = request
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_owned());
+    }
+    trace!("Sending network device command: {}", request_line);
+    async move {
+      let res = request.send().await.map_err(|err| {
+        error!("Got http error: {}", err);
+        ButtplugDeviceError::DeviceCommunicationError(err.to_string())
+      })?;
+      if !res.status().is_success() {
+        return Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+          "Network device returned {}",
+          res.status()
+        )));
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Network devices do not support subscribe".to_owned(),
+    )))
+    .boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Network devices do not support unsubscribe".to_owned(),
+    )))
+    .boxed()
+  }
+}
@@ -0,0 +1,21 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Federates devices from another Buttplug server
+//!
+//! The remote server communication manager connects to another Buttplug server as a client, and
+//! re-exposes that server's devices as local hardware. Commands sent to the proxied devices are
+//! forwarded to the remote server, and sensor readings come back through the local device, so apps
+//! connected to the local server see one device list covering both servers.
+
+mod remote_server_comm_manager;
+mod remote_server_hardware;
+pub use remote_server_comm_manager::{
+  RemoteServerCommunicationManager,
+  RemoteServerCommunicationManagerBuilder,
+};
+pub use remote_server_hardware::RemoteServerHardware;
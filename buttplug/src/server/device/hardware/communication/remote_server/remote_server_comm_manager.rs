@@ -0,0 +1,237 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::remote_server_hardware::RemoteServerHardwareConnector;
+use crate::{
+  client::{ButtplugClient, ButtplugClientDevice, ButtplugClientError, ButtplugClientEvent},
+  core::{
+    connector::ButtplugConnector,
+    errors::ButtplugDeviceError,
+    message::{ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage},
+    ButtplugResultFuture,
+  },
+  server::device::hardware::communication::{
+    HardwareCommunicationManager,
+    HardwareCommunicationManagerBuilder,
+    HardwareCommunicationManagerEvent,
+  },
+  util::async_manager,
+};
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  StreamExt,
+};
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::{mpsc::Sender, Mutex};
+
+type ConnectFuture = BoxFuture<'static, Result<(), ButtplugClientError>>;
+
+pub struct RemoteServerCommunicationManagerBuilder<T> {
+  connector: Option<T>,
+  client_name: String,
+  address_prefix: String,
+}
+
+impl<T> RemoteServerCommunicationManagerBuilder<T>
+where
+  T:
+    ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage> + 'static,
+{
+  /// Create a builder that will connect to the remote server using connector. The connection is
+  /// made the first time the local server starts scanning.
+  pub fn new(connector: T) -> Self {
+    Self {
+      connector: Some(connector),
+      client_name: "Buttplug Remote Server Manager".to_owned(),
+      address_prefix: "remote".to_owned(),
+    }
+  }
+
+  /// Name to use when connecting to the remote server.
+  pub fn client_name(&mut self, name: &str) -> &mut Self {
+    self.client_name = name.to_owned();
+    self
+  }
+
+  /// Prefix for the addresses of proxied devices. Addresses are built as `<prefix>-<remote index>`,
+  /// so when federating multiple servers, each needs its own prefix.
+  pub fn address_prefix(&mut self, prefix: &str) -> &mut Self {
+    self.address_prefix = prefix.to_owned();
+    self
+  }
+}
+
+impl<T> HardwareCommunicationManagerBuilder for RemoteServerCommunicationManagerBuilder<T>
+where
+  T:
+    ButtplugConnector<ButtplugCurrentSpecClientMessage, ButtplugCurrentSpecServerMessage> + 'static,
+{
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    let client = Arc::new(ButtplugClient::new(&self.client_name));
+    let connect = self.connector.take().map(|connector| {
+      let client = client.clone();
+      async move { client.connect(connector).await }.boxed()
+    });
+    Box::new(RemoteServerCommunicationManager::new(
+      sender,
+      client,
+      &self.address_prefix,
+      connect,
+    ))
+  }
+}
+
+fn device_found_event(
+  prefix: &str,
+  device: Arc<ButtplugClientDevice>,
+) -> HardwareCommunicationManagerEvent {
+  let address = format!("{}-{}", prefix, device.index());
+  HardwareCommunicationManagerEvent::DeviceFound {
+    name: device.name().to_owned(),
+    address: address.clone(),
+    creator: Box::new(RemoteServerHardwareConnector::new(&address, device)),
+  }
+}
+
+pub struct RemoteServerCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  client: Arc<ButtplugClient>,
+  address_prefix: String,
+  connect: Arc<Mutex<Option<ConnectFuture>>>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl RemoteServerCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    client: Arc<ButtplugClient>,
+    address_prefix: &str,
+    connect: Option<ConnectFuture>,
+  ) -> Self {
+    let is_scanning = Arc::new(AtomicBool::new(false));
+    // Listen before connecting, so devices the remote server already has when we connect show up
+    // right away. The stream ends when the manager, and with it the client, goes away.
+    let mut client_events = client.event_stream();
+    let event_sender = sender.clone();
+    let prefix = address_prefix.to_owned();
+    let scanning = is_scanning.clone();
+    async_manager::spawn(async move {
+      while let Some(event) = client_events.next().await {
+        let event = match event {
+          ButtplugClientEvent::DeviceAdded(device) => device_found_event(&prefix, device),
+          ButtplugClientEvent::ScanningFinished => {
+            scanning.store(false, Ordering::SeqCst);
+            HardwareCommunicationManagerEvent::ScanningFinished
+          }
+          ButtplugClientEvent::ServerDisconnect => {
+            warn!("Remote server disconnected, its devices are no longer available.");
+            if !scanning.swap(false, Ordering::SeqCst) {
+              continue;
+            }
+            HardwareCommunicationManagerEvent::ScanningFinished
+          }
+          _ => continue,
+        };
+        if event_sender.send(event).await.is_err() {
+          debug!("Device manager event channel closed, exiting remote server event loop.");
+          return;
+        }
+      }
+    });
+    Self {
+      sender,
+      client,
+      address_prefix: address_prefix.to_owned(),
+      connect: Arc::new(Mutex::new(connect)),
+      is_scanning,
+    }
+  }
+}
+
+impl HardwareCommunicationManager for RemoteServerCommunicationManager {
+  fn name(&self) -> &'static str {
+    "RemoteServerCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    let connect = self.connect.clone();
+    let client = self.client.clone();
+    let sender = self.sender.clone();
+    let prefix = self.address_prefix.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      // The connector can only be used once, so if the first connection fails, the manager stays
+      // disconnected.
+      if let Some(connect) = connect.lock().await.take() {
+        connect.await.map_err(|err| {
+          ButtplugDeviceError::DeviceConnectionError(format!(
+            "Cannot connect to remote server: {}",
+            err
+          ))
+        })?;
+      }
+      if !client.connected() {
+        return Err(
+          ButtplugDeviceError::DeviceConnectionError("Not connected to remote server.".to_owned())
+            .into(),
+        );
+      }
+      is_scanning.store(true, Ordering::SeqCst);
+      // Devices dropped locally while still connected remotely can be found again, same as with
+      // any other manager. The device manager ignores the ones that are still connected.
+      for device in client.devices() {
+        if sender
+          .send(device_found_event(&prefix, device))
+          .await
+          .is_err()
+        {
+          error!("Device manager event channel closed, cannot send remote devices.");
+        }
+      }
+      client.start_scanning().await.map_err(|err| {
+        is_scanning.store(false, Ordering::SeqCst);
+        ButtplugDeviceError::DeviceConnectionError(format!(
+          "Cannot start scanning on remote server: {}",
+          err
+        ))
+      })?;
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    if !self.client.connected() {
+      return future::ready(Ok(())).boxed();
+    }
+    let stop = self.client.stop_scanning();
+    async move {
+      stop.await.map_err(|err| {
+        ButtplugDeviceError::DeviceConnectionError(format!(
+          "Cannot stop scanning on remote server: {}",
+          err
+        ))
+      })?;
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
@@ -0,0 +1,273 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  client::{ButtplugClientDevice, ButtplugClientDeviceEvent, ButtplugClientError},
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      ButtplugCurrentSpecClientMessage,
+      ButtplugCurrentSpecServerMessage,
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      DeviceRemovedReason,
+      Endpoint,
+      SensorReading,
+    },
+  },
+  server::device::{
+    configuration::{ProtocolCommunicationSpecifier, RemoteServerSpecifier},
+    hardware::{
+      GenericHardwareSpecializer,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+  util::async_manager,
+};
+use async_trait::async_trait;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
+};
+use std::{
+  fmt::{self, Debug},
+  sync::Arc,
+};
+use tokio::sync::broadcast;
+
+pub(super) struct RemoteServerHardwareConnector {
+  address: String,
+  device: Arc<ButtplugClientDevice>,
+}
+
+impl RemoteServerHardwareConnector {
+  pub(super) fn new(address: &str, device: Arc<ButtplugClientDevice>) -> Self {
+    Self {
+      address: address.to_owned(),
+      device,
+    }
+  }
+}
+
+impl Debug for RemoteServerHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RemoteServerHardwareConnector")
+      .field("address", &self.address)
+      .field("name", self.device.name())
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for RemoteServerHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::RemoteServer(RemoteServerSpecifier::default())
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    if !self.device.connected() {
+      return Err(ButtplugDeviceError::DeviceNotConnected(
+        self.device.name().to_owned(),
+      ));
+    }
+    let hardware_internal = RemoteServerHardware::new(&self.address, self.device.clone());
+    let hardware = Hardware::new(
+      self.device.name(),
+      &self.address,
+      &[Endpoint::Tx, Endpoint::Rx],
+      Box::new(hardware_internal),
+    );
+    Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
+  }
+}
+
+fn client_error_to_device_error(err: ButtplugClientError) -> ButtplugDeviceError {
+  match err {
+    ButtplugClientError::ButtplugError(ButtplugError::ButtplugDeviceError(err)) => err,
+    err => ButtplugDeviceError::DeviceCommunicationError(format!(
+      "Error talking to remote server: {}",
+      err
+    )),
+  }
+}
+
+fn reading_notification(address: &str, reading: &SensorReading) -> HardwareEvent {
+  HardwareEvent::Notification(
+    address.to_owned(),
+    Endpoint::Rx,
+    serde_json::to_vec(reading).expect("Type is always serializable"),
+  )
+}
+
+/// Hardware for a device on a remote Buttplug server.
+///
+/// Writes to [Endpoint::Tx] are JSON device messages, which get sent to the remote server with the
+/// remote device index filled in. Sensor readings from the remote device, whether replies to reads
+/// or subscription updates, show up as JSON notifications on [Endpoint::Rx].
+#[derive(Clone)]
+pub struct RemoteServerHardware {
+  address: String,
+  device: Arc<ButtplugClientDevice>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl Debug for RemoteServerHardware {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("RemoteServerHardware")
+      .field("address", &self.address)
+      .finish()
+  }
+}
+
+impl RemoteServerHardware {
+  fn new(address: &str, device: Arc<ButtplugClientDevice>) -> Self {
+    let (event_sender, _) = broadcast::channel(256);
+    let sender_clone = event_sender.clone();
+    let address_clone = address.to_owned();
+    let mut device_events = device.event_stream();
+    let remote_device = device.clone();
+    async_manager::spawn(async move {
+      while let Some(event) = device_events.next().await {
+        match event {
+          ButtplugClientDeviceEvent::Message(ButtplugCurrentSpecServerMessage::SensorReading(
+            reading,
+          )) => {
+            // No receivers just means nothing is subscribed right now.
+            let _ = sender_clone.send(reading_notification(&address_clone, &reading));
+          }
+          ButtplugClientDeviceEvent::DeviceRemoved
+          | ButtplugClientDeviceEvent::ClientDisconnect => {
+            // Pass on what the remote server said about the removal, losing the connection to the
+            // remote server is the same as losing the device.
+            let _ = sender_clone.send(HardwareEvent::Disconnected(
+              address_clone.clone(),
+              remote_device
+                .removed_reason()
+                .unwrap_or(DeviceRemovedReason::LinkLost),
+              remote_device.will_reconnect(),
+            ));
+            info!(
+              "Remote device {} went away, exiting event loop.",
+              address_clone
+            );
+            return;
+          }
+          _ => {}
+        }
+      }
+    });
+    Self {
+      address: address.to_owned(),
+      device,
+      event_sender,
+    }
+  }
+}
+
+impl HardwareInternal for RemoteServerHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    // The device belongs to the remote server, so there's nothing to tear down on our side. Stop it
+    // though, so it isn't left running once nothing here can control it.
+    let stop = self.device.stop();
+    async move {
+      let _ = stop.await;
+      Ok(())
+    }
+    .boxed()
+  }
+
+  // Reading Rx hands back the attributes the remote server reported for the device, which the
+  // protocol uses in place of device configuration.
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    if msg.endpoint() != Endpoint::Rx {
+      return future::ready(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint()))).boxed();
+    }
+    let attributes =
+      serde_json::to_vec(self.device.message_attributes()).expect("Type is always serializable");
+    future::ready(Ok(HardwareReading::new(Endpoint::Rx, &attributes))).boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let mut device_msg: ButtplugDeviceCommandMessageUnion = match serde_json::from_slice(&msg.data)
+    {
+      Ok(device_msg) => device_msg,
+      Err(err) => {
+        return future::ready(Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+          "Cannot parse message for remote device: {}",
+          err
+        ))))
+        .boxed()
+      }
+    };
+    device_msg.set_device_index(self.device.index());
+    let client_msg: ButtplugCurrentSpecClientMessage = match device_msg {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(m) => m.into(),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(m) => m.into(),
+      ButtplugDeviceCommandMessageUnion::LinearCmd(m) => m.into(),
+      ButtplugDeviceCommandMessageUnion::StopDeviceCmd(m) => m.into(),
+      ButtplugDeviceCommandMessageUnion::SensorReadCmd(m) => m.into(),
+      ButtplugDeviceCommandMessageUnion::SensorSubscribeCmd(m) => m.into(),
+      ButtplugDeviceCommandMessageUnion::SensorUnsubscribeCmd(m) => m.into(),
+      other => {
+        return future::ready(Err(ButtplugDeviceError::UnhandledCommand(format!(
+          "Remote devices cannot forward {:?}",
+          other
+        ))))
+        .boxed()
+      }
+    };
+    let reply = self.device.send_message(client_msg);
+    let address = self.address.clone();
+    let sender = self.event_sender.clone();
+    async move {
+      match reply.await.map_err(client_error_to_device_error)? {
+        ButtplugCurrentSpecServerMessage::SensorReading(reading) => {
+          let _ = sender.send(reading_notification(&address, &reading));
+          Ok(())
+        }
+        _ => Ok(()),
+      }
+    }
+    .boxed()
+  }
+
+  // Remote sensor readings are always forwarded to Rx, so there's no subscription to manage here.
+  // The protocol subscribes to the sensors themselves through write_value.
+  fn subscribe(
+    &self,
+    _msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    _msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Ok(())).boxed()
+  }
+}
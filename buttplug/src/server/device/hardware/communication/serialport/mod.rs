@@ -11,5 +11,8 @@ mod serialport_hardware;
 pub use serialport_comm_manager::{
   SerialPortCommunicationManager,
   SerialPortCommunicationManagerBuilder,
+  SerialPortOverride,
+  SerialPortReconnectPolicy,
+  SerialPortScanConfig,
 };
 pub use serialport_hardware::{SerialPortHardware, SerialPortHardwareConnector};
@@ -5,25 +5,225 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use std::time::Duration;
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Arc,
+  time::Duration,
+};
 
 use super::SerialPortHardwareConnector;
 use crate::{
   core::errors::ButtplugDeviceError,
-  server::device::hardware::communication::{
-    HardwareCommunicationManager,
-    HardwareCommunicationManagerBuilder,
-    HardwareCommunicationManagerEvent,
-    TimedRetryCommunicationManager,
-    TimedRetryCommunicationManagerImpl,
+  server::device::{
+    configuration::USBSpecifier,
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+      TimedRetryCommunicationManager,
+      TimedRetryCommunicationManagerImpl,
+    },
   },
+  util::{self, async_manager},
 };
 use async_trait::async_trait;
-use serialport::available_ports;
-use tokio::sync::mpsc::Sender;
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+use serialport::{available_ports, SerialPortInfo, SerialPortType};
+use tokio::sync::mpsc::{self, Sender};
+
+/// How to go about bringing back a serial device whose port drops out while connected.
+#[derive(Debug, Clone, Copy, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct SerialPortReconnectPolicy {
+  /// Number of times to look for the port before giving up.
+  max_attempts: u32,
+  /// Wait before the first attempt. Doubles after each failed attempt.
+  initial_delay: Duration,
+  /// Upper bound on the wait between attempts.
+  max_delay: Duration,
+}
+
+impl Default for SerialPortReconnectPolicy {
+  fn default() -> Self {
+    Self {
+      max_attempts: 10,
+      initial_delay: Duration::from_secs(1),
+      max_delay: Duration::from_secs(30),
+    }
+  }
+}
+
+impl SerialPortReconnectPolicy {
+  pub fn new(max_attempts: u32, initial_delay: Duration, max_delay: Duration) -> Self {
+    Self {
+      max_attempts,
+      initial_delay,
+      max_delay,
+    }
+  }
+
+  /// Wait time before a reconnect attempt. Attempts are numbered from 0.
+  pub fn delay_for_attempt(&self, attempt: u32) -> Duration {
+    self
+      .initial_delay
+      .saturating_mul(2u32.saturating_pow(attempt))
+      .min(self.max_delay)
+  }
+}
+
+/// Serial settings for one port that replace the ones from the protocol's device configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct SerialPortOverride {
+  #[serde(rename = "baud-rate", default, skip_serializing_if = "Option::is_none")]
+  baud_rate: Option<u32>,
+  /// 'N' (none), 'E' (even), or 'O' (odd).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  parity: Option<char>,
+}
+
+impl SerialPortOverride {
+  pub fn new(baud_rate: Option<u32>, parity: Option<char>) -> Self {
+    Self { baud_rate, parity }
+  }
+}
+
+/// Which serial ports get scanned, and any settings overrides for them. Meant to be loaded from
+/// user configuration, so that ports with things that aren't toys on them (say, an Arduino that's
+/// being flashed) can be left alone.
+///
+/// Denied ports are never scanned. If any ports or USB devices are allowed, only those are
+/// scanned, otherwise every port that isn't denied is.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct SerialPortScanConfig {
+  #[serde(
+    rename = "allowed-ports",
+    default,
+    skip_serializing_if = "HashSet::is_empty"
+  )]
+  allowed_ports: HashSet<String>,
+  #[serde(
+    rename = "denied-ports",
+    default,
+    skip_serializing_if = "HashSet::is_empty"
+  )]
+  denied_ports: HashSet<String>,
+  #[serde(
+    rename = "allowed-usb-devices",
+    default,
+    skip_serializing_if = "Vec::is_empty"
+  )]
+  allowed_usb_devices: Vec<USBSpecifier>,
+  #[serde(
+    rename = "denied-usb-devices",
+    default,
+    skip_serializing_if = "Vec::is_empty"
+  )]
+  denied_usb_devices: Vec<USBSpecifier>,
+  #[serde(
+    rename = "port-overrides",
+    default,
+    skip_serializing_if = "HashMap::is_empty"
+  )]
+  port_overrides: HashMap<String, SerialPortOverride>,
+}
+
+impl SerialPortScanConfig {
+  pub fn allow_port(&mut self, port: &str) -> &mut Self {
+    self.allowed_ports.insert(port.to_owned());
+    self
+  }
+
+  pub fn deny_port(&mut self, port: &str) -> &mut Self {
+    self.denied_ports.insert(port.to_owned());
+    self
+  }
+
+  pub fn allow_usb_device(&mut self, vendor_id: u16, product_id: u16) -> &mut Self {
+    self
+      .allowed_usb_devices
+      .push(USBSpecifier::new(vendor_id, product_id));
+    self
+  }
+
+  pub fn deny_usb_device(&mut self, vendor_id: u16, product_id: u16) -> &mut Self {
+    self
+      .denied_usb_devices
+      .push(USBSpecifier::new(vendor_id, product_id));
+    self
+  }
+
+  pub fn port_override(&mut self, port: &str, port_override: SerialPortOverride) -> &mut Self {
+    self.port_overrides.insert(port.to_owned(), port_override);
+    self
+  }
+
+  /// Whether a port should be scanned, given its name and, for USB serial adapters, its USB vendor
+  /// and product ids.
+  pub fn allows(&self, port: &str, usb_ids: Option<(u16, u16)>) -> bool {
+    let usb_matches = |devices: &[USBSpecifier]| {
+      usb_ids.is_some_and(|(vendor_id, product_id)| {
+        devices
+          .iter()
+          .any(|d| *d.vendor_id() == vendor_id && *d.product_id() == product_id)
+      })
+    };
+    if self.denied_ports.contains(port) || usb_matches(&self.denied_usb_devices) {
+      return false;
+    }
+    if self.allowed_ports.is_empty() && self.allowed_usb_devices.is_empty() {
+      return true;
+    }
+    self.allowed_ports.contains(port) || usb_matches(&self.allowed_usb_devices)
+  }
+
+  fn allows_port_info(&self, port_info: &SerialPortInfo) -> bool {
+    let usb_ids = if let SerialPortType::UsbPort(usb_info) = &port_info.port_type {
+      Some((usb_info.vid, usb_info.pid))
+    } else {
+      None
+    };
+    self.allows(&port_info.port_name, usb_ids)
+  }
+
+  fn connector(
+    &self,
+    port_info: &SerialPortInfo,
+    reconnect_sender: Option<Sender<String>>,
+  ) -> SerialPortHardwareConnector {
+    let mut connector = SerialPortHardwareConnector::new(port_info);
+    if let Some(port_override) = self.port_overrides.get(&port_info.port_name) {
+      connector = connector.with_port_override(*port_override);
+    }
+    if let Some(reconnect_sender) = reconnect_sender {
+      connector = connector.with_reconnect_sender(reconnect_sender);
+    }
+    connector
+  }
+}
 
 #[derive(Default, Clone)]
-pub struct SerialPortCommunicationManagerBuilder {}
+pub struct SerialPortCommunicationManagerBuilder {
+  reconnect_policy: Option<SerialPortReconnectPolicy>,
+  scan_config: SerialPortScanConfig,
+}
+
+impl SerialPortCommunicationManagerBuilder {
+  /// If set, devices whose serial port disappears while connected will be re-added automatically
+  /// if the port comes back, without needing a new scan.
+  pub fn reconnect_policy(mut self, policy: SerialPortReconnectPolicy) -> Self {
+    self.reconnect_policy = Some(policy);
+    self
+  }
+
+  /// Limit which ports are scanned, and override serial settings for specific ports.
+  pub fn scan_config(mut self, config: SerialPortScanConfig) -> Self {
+    self.scan_config = config;
+    self
+  }
+}
 
 impl HardwareCommunicationManagerBuilder for SerialPortCommunicationManagerBuilder {
   fn finish(
@@ -31,20 +231,103 @@ impl HardwareCommunicationManagerBuilder for SerialPortCommunicationManagerBuild
     sender: Sender<HardwareCommunicationManagerEvent>,
   ) -> Box<dyn HardwareCommunicationManager> {
     Box::new(TimedRetryCommunicationManager::new(
-      SerialPortCommunicationManager::new(sender),
+      SerialPortCommunicationManager::new(
+        sender,
+        self.reconnect_policy,
+        Arc::new(self.scan_config.clone()),
+      ),
     ))
   }
 }
 
 pub struct SerialPortCommunicationManager {
   sender: Sender<HardwareCommunicationManagerEvent>,
+  reconnect_sender: Option<Sender<String>>,
+  scan_config: Arc<SerialPortScanConfig>,
 }
 
 impl SerialPortCommunicationManager {
-  fn new(sender: Sender<HardwareCommunicationManagerEvent>) -> Self {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    reconnect_policy: Option<SerialPortReconnectPolicy>,
+    scan_config: Arc<SerialPortScanConfig>,
+  ) -> Self {
     trace!("Serial port created.");
-    Self { sender }
+    let reconnect_sender = reconnect_policy.map(|policy| {
+      let (reconnect_sender, mut reconnect_receiver) = mpsc::channel(256);
+      let event_sender = sender.clone();
+      let reconnect_scan_config = scan_config.clone();
+      // Only hold a weak sender here, so this task ends once the manager and every serial device
+      // it created have been dropped.
+      let weak_reconnect_sender = reconnect_sender.downgrade();
+      async_manager::spawn(async move {
+        while let Some(port_name) = reconnect_receiver.recv().await {
+          let Some(reconnect_sender) = weak_reconnect_sender.upgrade() else {
+            break;
+          };
+          async_manager::spawn(reconnect_port(
+            port_name,
+            policy,
+            reconnect_scan_config.clone(),
+            event_sender.clone(),
+            reconnect_sender,
+          ));
+        }
+      });
+      reconnect_sender
+    });
+    Self {
+      sender,
+      reconnect_sender,
+      scan_config,
+    }
+  }
+}
+
+/// Wait for a lost port to show back up, then hand it back to the device manager as a newly found
+/// device.
+async fn reconnect_port(
+  port_name: String,
+  policy: SerialPortReconnectPolicy,
+  scan_config: Arc<SerialPortScanConfig>,
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  reconnect_sender: Sender<String>,
+) {
+  for attempt in 0..policy.max_attempts() {
+    util::sleep(policy.delay_for_attempt(attempt)).await;
+    let port = available_ports()
+      .ok()
+      .and_then(|ports| ports.into_iter().find(|p| p.port_name == port_name));
+    if let Some(port) = port {
+      info!(
+        "Serial port {} is back after {} attempt(s), reconnecting.",
+        port_name,
+        attempt + 1
+      );
+      if sender
+        .send(HardwareCommunicationManagerEvent::DeviceFound {
+          name: format!("Serial Port Device {}", port.port_name),
+          address: port.port_name.clone(),
+          creator: Box::new(scan_config.connector(&port, Some(reconnect_sender))),
+        })
+        .await
+        .is_err()
+      {
+        debug!("Device manager disappeared, exiting.");
+      }
+      return;
+    }
+    debug!(
+      "Serial port {} not available on reconnect attempt {}.",
+      port_name,
+      attempt + 1
+    );
   }
+  warn!(
+    "Serial port {} did not come back after {} attempts, giving up.",
+    port_name,
+    policy.max_attempts()
+  );
 }
 
 #[async_trait]
@@ -63,6 +346,13 @@ impl TimedRetryCommunicationManagerImpl for SerialPortCommunicationManager {
       Ok(ports) => {
         debug!("Got {} serial ports back", ports.len());
         for p in ports {
+          if !self.scan_config.allows_port_info(&p) {
+            debug!(
+              "Serial port {} is not allowed by scan config, skipping.",
+              p.port_name
+            );
+            continue;
+          }
           trace!(
             "Sending serial port {:?} for possible device connection.",
             p
@@ -72,7 +362,11 @@ impl TimedRetryCommunicationManagerImpl for SerialPortCommunicationManager {
             .send(HardwareCommunicationManagerEvent::DeviceFound {
               name: format!("Serial Port Device {}", p.port_name),
               address: p.port_name.clone(),
-              creator: Box::new(SerialPortHardwareConnector::new(&p)),
+              creator: Box::new(
+                self
+                  .scan_config
+                  .connector(&p, self.reconnect_sender.clone()),
+              ),
             })
             .await
             .is_err()
@@ -93,4 +387,66 @@ impl TimedRetryCommunicationManagerImpl for SerialPortCommunicationManager {
   fn can_scan(&self) -> bool {
     true
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("serial")
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{SerialPortOverride, SerialPortReconnectPolicy, SerialPortScanConfig};
+  use std::time::Duration;
+
+  #[test]
+  pub fn test_reconnect_backoff() {
+    let policy =
+      SerialPortReconnectPolicy::new(10, Duration::from_millis(500), Duration::from_secs(3));
+    assert_eq!(policy.delay_for_attempt(0), Duration::from_millis(500));
+    assert_eq!(policy.delay_for_attempt(1), Duration::from_secs(1));
+    assert_eq!(policy.delay_for_attempt(2), Duration::from_secs(2));
+    assert_eq!(policy.delay_for_attempt(3), Duration::from_secs(3));
+    assert_eq!(policy.delay_for_attempt(40), Duration::from_secs(3));
+  }
+
+  #[test]
+  pub fn test_scan_config_filters() {
+    let mut config = SerialPortScanConfig::default();
+    assert!(config.allows("/dev/ttyUSB0", None));
+    config
+      .deny_port("/dev/ttyACM0")
+      .deny_usb_device(0x2341, 0x0043);
+    assert!(!config.allows("/dev/ttyACM0", None));
+    assert!(!config.allows("/dev/ttyUSB1", Some((0x2341, 0x0043))));
+    assert!(config.allows("/dev/ttyUSB0", Some((0x0403, 0x6001))));
+
+    // Once anything is allowed, only allowed ports are scanned, and denies still win.
+    config
+      .allow_port("/dev/ttyUSB0")
+      .allow_usb_device(0x0403, 0x6001);
+    assert!(config.allows("/dev/ttyUSB0", None));
+    assert!(config.allows("/dev/ttyUSB2", Some((0x0403, 0x6001))));
+    assert!(!config.allows("/dev/ttyUSB3", Some((0x1a86, 0x7523))));
+    config.deny_port("/dev/ttyUSB0");
+    assert!(!config.allows("/dev/ttyUSB0", None));
+  }
+
+  #[test]
+  pub fn test_scan_config_json() {
+    let config: SerialPortScanConfig = serde_json::from_str(
+      r#"{
+        "denied-ports": ["/dev/ttyACM0"],
+        "allowed-usb-devices": [{"vendor-id": 1027, "product-id": 24577}],
+        "port-overrides": {"/dev/ttyUSB0": {"baud-rate": 115200, "parity": "E"}}
+      }"#,
+    )
+    .expect("Test, assuming infallible.");
+    assert!(!config.allows("/dev/ttyACM0", Some((0x0403, 0x6001))));
+    assert!(config.allows("/dev/ttyUSB0", Some((0x0403, 0x6001))));
+    assert!(!config.allows("/dev/ttyUSB0", None));
+    assert_eq!(
+      config.port_overrides().get("/dev/ttyUSB0"),
+      Some(&SerialPortOverride::new(Some(115200), Some('E')))
+    );
+  }
 }
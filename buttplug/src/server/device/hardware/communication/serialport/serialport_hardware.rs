@@ -5,8 +5,12 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+use super::SerialPortOverride;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::hardware::communication::HardwareSpecificError,
   server::device::{
     configuration::{ProtocolCommunicationSpecifier, SerialSpecifier},
@@ -27,8 +31,8 @@ use crate::{
 };
 use async_trait::async_trait;
 use futures::future;
-use futures::{future::BoxFuture, FutureExt};
-use serialport::{SerialPort, SerialPortInfo};
+use futures::{future::BoxFuture, select, FutureExt};
+use serialport::{Parity, SerialPort, SerialPortInfo};
 use std::{
   fmt::{self, Debug},
   io::ErrorKind,
@@ -45,6 +49,8 @@ use tokio_util::sync::CancellationToken;
 pub struct SerialPortHardwareConnector {
   specifier: ProtocolCommunicationSpecifier,
   port_info: SerialPortInfo,
+  port_override: Option<SerialPortOverride>,
+  reconnect_sender: Option<mpsc::Sender<String>>,
 }
 
 impl SerialPortHardwareConnector {
@@ -54,8 +60,23 @@ impl SerialPortHardwareConnector {
         &port_info.port_name,
       )),
       port_info: port_info.clone(),
+      port_override: None,
+      reconnect_sender: None,
     }
   }
+
+  /// Use these settings instead of the ones from the protocol's device configuration.
+  pub(super) fn with_port_override(mut self, port_override: SerialPortOverride) -> Self {
+    self.port_override = Some(port_override);
+    self
+  }
+
+  /// If the port drops out from under us, send its name here so the comm manager can try to bring
+  /// the device back.
+  pub(super) fn with_reconnect_sender(mut self, reconnect_sender: mpsc::Sender<String>) -> Self {
+    self.reconnect_sender = Some(reconnect_sender);
+    self
+  }
 }
 
 impl Debug for SerialPortHardwareConnector {
@@ -75,18 +96,28 @@ impl HardwareConnector for SerialPortHardwareConnector {
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
     Ok(Box::new(SerialPortHardwareSpecialzier::new(
       &self.port_info,
+      self.port_override,
+      self.reconnect_sender.clone(),
     )))
   }
 }
 
 pub struct SerialPortHardwareSpecialzier {
   port_info: SerialPortInfo,
+  port_override: Option<SerialPortOverride>,
+  reconnect_sender: Option<mpsc::Sender<String>>,
 }
 
 impl SerialPortHardwareSpecialzier {
-  pub fn new(port_info: &SerialPortInfo) -> Self {
+  pub fn new(
+    port_info: &SerialPortInfo,
+    port_override: Option<SerialPortOverride>,
+    reconnect_sender: Option<mpsc::Sender<String>>,
+  ) -> Self {
     Self {
       port_info: port_info.clone(),
+      port_override,
+      reconnect_sender,
     }
   }
 }
@@ -99,7 +130,13 @@ impl HardwareSpecializer for SerialPortHardwareSpecialzier {
     &mut self,
     specifiers: &[ProtocolCommunicationSpecifier],
   ) -> Result<Hardware, ButtplugDeviceError> {
-    let hardware_internal = SerialPortHardware::try_create(&self.port_info, specifiers).await?;
+    let hardware_internal = SerialPortHardware::try_create(
+      &self.port_info,
+      specifiers,
+      self.port_override,
+      self.reconnect_sender.take(),
+    )
+    .await?;
     let hardware = Hardware::new(
       &self.port_info.port_name,
       &self.port_info.port_name,
@@ -128,6 +165,7 @@ fn serial_read_thread(
   mut port: Box<dyn SerialPort>,
   sender: mpsc::Sender<Vec<u8>>,
   token: CancellationToken,
+  device_lost_token: CancellationToken,
 ) {
   while !token.is_cancelled() {
     // TODO This is probably too small
@@ -147,23 +185,40 @@ fn serial_read_thread(
             }
           }
           Err(e) => {
-            if e.kind() == ErrorKind::TimedOut {
+            if matches!(
+              e.kind(),
+              ErrorKind::TimedOut | ErrorKind::Interrupted | ErrorKind::WouldBlock
+            ) {
               continue;
             }
-            error!("{:?}", e);
+            error!("Serial port read failed, assuming device is gone: {:?}", e);
+            device_lost_token.cancel();
+            break;
           }
         }
       }
       Err(e) => {
         warn!("Error reading from serial port: {:?}", e);
-        if e.kind() == serialport::ErrorKind::NoDevice {
-          info!("Serial device gone, breaking out of read loop.");
-        }
+        info!("Serial device gone, breaking out of read loop.");
+        device_lost_token.cancel();
+        break;
       }
     }
   }
 }
 
+fn parse_parity(parity: char) -> Result<Parity, ButtplugDeviceError> {
+  match parity.to_ascii_uppercase() {
+    'N' => Ok(Parity::None),
+    'E' => Ok(Parity::Even),
+    'O' => Ok(Parity::Odd),
+    _ => Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Serial parity {:?} is not one of N, E, or O.",
+      parity
+    ))),
+  }
+}
+
 pub struct SerialPortHardware {
   address: String,
   port_receiver: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
@@ -181,6 +236,8 @@ impl SerialPortHardware {
   pub async fn try_create(
     port_info: &SerialPortInfo,
     specifiers: &[ProtocolCommunicationSpecifier],
+    port_override: Option<SerialPortOverride>,
+    reconnect_sender: Option<mpsc::Sender<String>>,
   ) -> Result<Self, ButtplugDeviceError> {
     let (device_event_sender, _) = broadcast::channel(256);
     // If we've gotten this far, we can expect we have a serial port definition.
@@ -194,6 +251,14 @@ impl SerialPortHardware {
       }
     }
     let port_def = port_def.expect("We'll always have a port definition by this point");
+    let port_override = port_override.unwrap_or_default();
+    let baud_rate = port_override
+      .baud_rate()
+      .unwrap_or_else(|| *port_def.baud_rate());
+    let parity = match port_override.parity() {
+      Some(parity) => parse_parity(parity)?,
+      None => Parity::None,
+    };
 
     // This seems like it should be a oneshot, but there's no way to await a
     // value on those?
@@ -204,13 +269,15 @@ impl SerialPortHardware {
     settings.data_bits = port_def.data_bits;
     settings.parity = port_def.parity;
     */
-    // TODO for now, assume 8/N/1. Not really sure when/if this would ever change.
+    // TODO for now, assume 8 data bits and 1 stop bit, with no parity unless the port's settings are
+    // overridden.
     let port_name = port_info.port_name.clone();
     thread::Builder::new()
       .name("Serial Port Connection Thread".to_string())
       .spawn(move || {
         debug!("Starting serial port connection thread for {}", port_name);
-        let port_result = serialport::new(&port_name, *port_def.baud_rate())
+        let port_result = serialport::new(&port_name, baud_rate)
+          .parity(parity)
           .timeout(Duration::from_millis(100))
           .open();
         if port_sender.blocking_send(port_result)
@@ -234,13 +301,15 @@ impl SerialPortHardware {
 
     let token = CancellationToken::new();
     let read_token = token.child_token();
+    let device_lost_token = CancellationToken::new();
+    let read_device_lost_token = device_lost_token.clone();
     let read_port = (*port)
       .try_clone()
       .expect("Should always be able to clone port");
     let read_thread = thread::Builder::new()
       .name("Serial Reader Thread".to_string())
       .spawn(move || {
-        serial_read_thread(read_port, reader_sender, read_token);
+        serial_read_thread(read_port, reader_sender, read_token, read_device_lost_token);
       })
       .expect("Should always be able to create thread");
 
@@ -254,16 +323,47 @@ impl SerialPortHardware {
       })
       .expect("Should always be able to create thread");
 
+    let address = port
+      .name()
+      .unwrap_or_else(|| "Default Serial Port Device (No Name Given)".to_owned());
+    let connected = Arc::new(AtomicBool::new(true));
+
+    // Watch for the read thread losing the port. Once that happens the device is gone as far as the
+    // server is concerned, but if we have a reconnect policy, the comm manager will keep looking for
+    // the port and bring the device back when it shows up again.
+    {
+      let hardware_token = token.clone();
+      let connected = connected.clone();
+      let event_sender = device_event_sender.clone();
+      let address = address.clone();
+      let port_name = port_info.port_name.clone();
+      async_manager::spawn(async move {
+        select! {
+          _ = hardware_token.cancelled().fuse() => return,
+          _ = device_lost_token.cancelled().fuse() => {}
+        }
+        connected.store(false, Ordering::SeqCst);
+        let _ = event_sender.send(HardwareEvent::Disconnected(
+          address,
+          DeviceRemovedReason::LinkLost,
+          reconnect_sender.is_some(),
+        ));
+        if let Some(reconnect_sender) = reconnect_sender {
+          if reconnect_sender.send(port_name).await.is_err() {
+            debug!("Serial port comm manager gone, cannot schedule reconnect.");
+          }
+        }
+      });
+    }
+
     Ok(Self {
-      address: port
-        .name()
-        .unwrap_or_else(|| "Default Serial Port Device (No Name Given)".to_owned()),
+      address,
       _read_thread: read_thread,
       _write_thread: write_thread,
       port_receiver: Arc::new(Mutex::new(reader_receiver)),
       port_sender: writer_sender,
       _port: Arc::new(Mutex::new(port)),
-      connected: Arc::new(AtomicBool::new(true)),
+      connected,
       device_event_sender,
       thread_cancellation_token: token,
     })
@@ -0,0 +1,28 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Simulated devices, for testing applications against a real server without physical hardware.
+//!
+//! Simulated devices show up as Bluetooth LE devices advertising whatever name they're configured
+//! with, so they'll be matched to protocols the same way real BLE hardware would. Everything the
+//! server sends to a device is echoed to its [SimulatedDeviceHandle], and the handle can be used
+//! to send notifications, set read values, or disconnect the device.
+//!
+//! Devices can also be built from a
+//! [HardwareTrafficRecording](crate::server::device::hardware::recording::HardwareTrafficRecording)
+//! using [SimulatedDeviceConfig::from_recording], to replay a session captured from real hardware
+//! against a protocol.
+
+pub mod simulated_comm_manager;
+pub mod simulated_hardware;
+
+pub use simulated_comm_manager::{
+  SimulatedDeviceCommunicationManager,
+  SimulatedDeviceCommunicationManagerBuilder,
+  SimulatedDeviceConfig,
+};
+pub use simulated_hardware::{SimulatedDeviceHandle, SimulatedDeviceResponse};
@@ -0,0 +1,229 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::simulated_hardware::{
+  SimulatedDeviceHandle,
+  SimulatedDeviceResponse,
+  SimulatedHardwareConnector,
+};
+use crate::{
+  core::{errors::ButtplugDeviceError, ButtplugResultFuture},
+  server::device::hardware::{
+    communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+    },
+    recording::{HardwareTrafficEvent, HardwareTrafficRecording},
+  },
+};
+use futures::future::{self, FutureExt};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+  atomic::{AtomicBool, AtomicU32, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::Sender;
+
+static SIMULATED_DEVICE_COUNT: AtomicU32 = AtomicU32::new(0);
+
+fn generate_address() -> String {
+  format!(
+    "simulated-{}",
+    SIMULATED_DEVICE_COUNT.fetch_add(1, Ordering::SeqCst)
+  )
+}
+
+/// Configuration for a single simulated device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub")]
+pub struct SimulatedDeviceConfig {
+  /// Bluetooth LE name the device advertises. This is what picks the protocol, so it should match
+  /// a name in the device configuration file.
+  name: String,
+  /// Device address. If not set, a unique address is generated.
+  #[serde(default = "generate_address")]
+  address: String,
+  /// Canned replies to send when specific writes come in.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  responses: Vec<SimulatedDeviceResponse>,
+}
+
+impl SimulatedDeviceConfig {
+  pub fn new(name: &str, address: Option<&str>) -> Self {
+    Self {
+      name: name.to_owned(),
+      address: address.map_or_else(generate_address, |address| address.to_owned()),
+      responses: vec![],
+    }
+  }
+
+  pub fn add_response(&mut self, response: SimulatedDeviceResponse) -> &mut Self {
+    self.responses.push(response);
+    self
+  }
+
+  /// Build a device that replays a [HardwareTrafficRecording]. Every notification in the recording
+  /// becomes a reply to the write that came right before it, so the protocol sees the same answers
+  /// the real device gave. Recordings with more than one device should be narrowed down with
+  /// [HardwareTrafficRecording::for_address] first.
+  pub fn from_recording(name: &str, recording: &HardwareTrafficRecording) -> Self {
+    let mut config = Self::new(name, None);
+    let mut last_write = None;
+    for record in recording.records() {
+      match record.event() {
+        HardwareTrafficEvent::Write(cmd) => last_write = Some(cmd),
+        HardwareTrafficEvent::Notification { endpoint, data } => {
+          if let Some(write) = last_write {
+            let response =
+              SimulatedDeviceResponse::new(write.endpoint(), write.data(), *endpoint, data);
+            if !config.responses.contains(&response) {
+              config.responses.push(response);
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+    config
+  }
+}
+
+/// Format of JSON simulated device configurations.
+#[derive(Deserialize)]
+struct SimulatedDeviceConfigFile {
+  devices: Vec<SimulatedDeviceConfig>,
+}
+
+#[derive(Default, Clone)]
+pub struct SimulatedDeviceCommunicationManagerBuilder {
+  devices: Vec<SimulatedDeviceHandle>,
+}
+
+impl SimulatedDeviceCommunicationManagerBuilder {
+  /// Add a simulated device, returning the handle used to inspect and drive it.
+  pub fn add_device(&mut self, config: &SimulatedDeviceConfig) -> SimulatedDeviceHandle {
+    let handle = SimulatedDeviceHandle::new(config);
+    self.devices.push(handle.clone());
+    handle
+  }
+
+  /// Add simulated devices from a JSON configuration, in the format
+  /// `{"devices": [{"name": "...", "address": "...", "responses": [...]}]}`.
+  pub fn add_devices_from_json(
+    &mut self,
+    json: &str,
+  ) -> Result<Vec<SimulatedDeviceHandle>, ButtplugDeviceError> {
+    let config: SimulatedDeviceConfigFile = serde_json::from_str(json).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot parse simulated device configuration: {}",
+        err
+      ))
+    })?;
+    Ok(
+      config
+        .devices
+        .iter()
+        .map(|device| self.add_device(device))
+        .collect(),
+    )
+  }
+
+  /// Handles for all devices added so far.
+  pub fn devices(&self) -> &[SimulatedDeviceHandle] {
+    &self.devices
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for SimulatedDeviceCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(SimulatedDeviceCommunicationManager::new(
+      sender,
+      self.devices.clone(),
+    ))
+  }
+}
+
+pub struct SimulatedDeviceCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  devices: Vec<SimulatedDeviceHandle>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl SimulatedDeviceCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    devices: Vec<SimulatedDeviceHandle>,
+  ) -> Self {
+    Self {
+      sender,
+      devices,
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+impl HardwareCommunicationManager for SimulatedDeviceCommunicationManager {
+  fn name(&self) -> &'static str {
+    "SimulatedDeviceCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    // Every scan finds all simulated devices that aren't currently connected, so devices that have
+    // been disconnected can be found again.
+    let events: Vec<HardwareCommunicationManagerEvent> = self
+      .devices
+      .iter()
+      .filter(|device| !device.connected())
+      .map(|device| HardwareCommunicationManagerEvent::DeviceFound {
+        name: device.name().to_owned(),
+        address: device.address().to_owned(),
+        creator: Box::new(SimulatedHardwareConnector::new(device.clone())),
+      })
+      .collect();
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    async move {
+      is_scanning.store(true, Ordering::SeqCst);
+      for event in events {
+        if sender.send(event).await.is_err() {
+          error!("Device manager event channel closed, cannot send simulated devices.");
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("btle")
+  }
+}
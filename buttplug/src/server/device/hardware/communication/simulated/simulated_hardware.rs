@@ -0,0 +1,347 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::simulated_comm_manager::SimulatedDeviceConfig;
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
+use futures::future::{self, BoxFuture, FutureExt};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::broadcast;
+
+/// Canned reply a simulated device sends when it receives a specific write. Useful for protocols
+/// that expect an answer during identification or initialization.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub")]
+pub struct SimulatedDeviceResponse {
+  /// Endpoint the write has to arrive on.
+  endpoint: Endpoint,
+  /// Write data to match against.
+  data: Vec<u8>,
+  /// Endpoint the reply is sent as a notification from.
+  #[serde(rename = "reply-endpoint")]
+  reply_endpoint: Endpoint,
+  /// Notification data to send back.
+  reply: Vec<u8>,
+}
+
+impl SimulatedDeviceResponse {
+  pub fn new(endpoint: Endpoint, data: &[u8], reply_endpoint: Endpoint, reply: &[u8]) -> Self {
+    Self {
+      endpoint,
+      data: data.to_vec(),
+      reply_endpoint,
+      reply: reply.to_vec(),
+    }
+  }
+}
+
+struct SimulatedDeviceState {
+  command_sender: broadcast::Sender<HardwareCommand>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  subscribed_endpoints: DashSet<Endpoint>,
+  read_values: DashMap<Endpoint, Vec<u8>>,
+  responses: Vec<SimulatedDeviceResponse>,
+  connected: AtomicBool,
+}
+
+/// Application side of a simulated device. Can be cloned freely, all clones refer to the same
+/// device.
+#[derive(Clone)]
+pub struct SimulatedDeviceHandle {
+  name: String,
+  address: String,
+  state: Arc<SimulatedDeviceState>,
+}
+
+impl SimulatedDeviceHandle {
+  pub(super) fn new(config: &SimulatedDeviceConfig) -> Self {
+    let (command_sender, _) = broadcast::channel(256);
+    let (event_sender, _) = broadcast::channel(256);
+    Self {
+      name: config.name().clone(),
+      address: config.address().clone(),
+      state: Arc::new(SimulatedDeviceState {
+        command_sender,
+        event_sender,
+        subscribed_endpoints: DashSet::new(),
+        read_values: DashMap::new(),
+        responses: config.responses().clone(),
+        connected: AtomicBool::new(false),
+      }),
+    }
+  }
+
+  /// Name the device advertises.
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Address the device advertises.
+  pub fn address(&self) -> &str {
+    &self.address
+  }
+
+  /// True if the server currently has the device connected.
+  pub fn connected(&self) -> bool {
+    self.state.connected.load(Ordering::SeqCst)
+  }
+
+  /// Stream of every write, subscribe, and unsubscribe the server sends to the device from now on.
+  pub fn command_stream(&self) -> broadcast::Receiver<HardwareCommand> {
+    self.state.command_sender.subscribe()
+  }
+
+  /// Send data from the device to the server, as if it had come in over a notification. Dropped
+  /// if the server hasn't subscribed to the endpoint.
+  pub fn notify(&self, endpoint: Endpoint, data: &[u8]) {
+    if self.state.subscribed_endpoints.contains(&endpoint) {
+      let _ = self.state.event_sender.send(HardwareEvent::Notification(
+        self.address.clone(),
+        endpoint,
+        data.to_vec(),
+      ));
+    }
+  }
+
+  /// Set the data returned when the server reads from an endpoint.
+  pub fn set_read_value(&self, endpoint: Endpoint, data: &[u8]) {
+    self.state.read_values.insert(endpoint, data.to_vec());
+  }
+
+  /// Disconnect the device, as if it had been turned off or gone out of range.
+  pub fn disconnect(&self) {
+    if self.state.connected.swap(false, Ordering::SeqCst) {
+      let _ = self.state.event_sender.send(HardwareEvent::Disconnected(
+        self.address.clone(),
+        DeviceRemovedReason::LinkLost,
+        false,
+      ));
+    }
+  }
+}
+
+pub struct SimulatedHardwareConnector {
+  handle: SimulatedDeviceHandle,
+}
+
+impl SimulatedHardwareConnector {
+  pub(super) fn new(handle: SimulatedDeviceHandle) -> Self {
+    Self { handle }
+  }
+}
+
+impl Debug for SimulatedHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("SimulatedHardwareConnector")
+      .field("name", &self.handle.name)
+      .field("address", &self.handle.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for SimulatedHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      &self.handle.name,
+      &HashMap::new(),
+      &[],
+    ))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    Ok(Box::new(SimulatedHardwareSpecializer {
+      handle: self.handle.clone(),
+    }))
+  }
+}
+
+pub struct SimulatedHardwareSpecializer {
+  handle: SimulatedDeviceHandle,
+}
+
+#[async_trait]
+impl HardwareSpecializer for SimulatedHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    // Simulated devices have whatever endpoints the protocol expects them to have.
+    let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+      .iter()
+      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    else {
+      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+        "Can't find btle protocol specifier mapping for simulated device {}",
+        self.handle.name
+      )));
+    };
+    let endpoints: Vec<Endpoint> = btle
+      .services()
+      .values()
+      .flat_map(|chrs| chrs.keys().cloned())
+      .collect();
+    let state = self.handle.state.clone();
+    state.subscribed_endpoints.clear();
+    state.connected.store(true, Ordering::SeqCst);
+    Ok(Hardware::new(
+      &self.handle.name,
+      &self.handle.address,
+      &endpoints,
+      Box::new(SimulatedHardware {
+        address: self.handle.address.clone(),
+        endpoints: endpoints.clone(),
+        state,
+      }),
+    ))
+  }
+}
+
+pub struct SimulatedHardware {
+  address: String,
+  endpoints: Vec<Endpoint>,
+  state: Arc<SimulatedDeviceState>,
+}
+
+impl SimulatedHardware {
+  fn check_endpoint(&self, endpoint: Endpoint) -> Result<(), ButtplugDeviceError> {
+    if self.endpoints.contains(&endpoint) {
+      Ok(())
+    } else {
+      Err(ButtplugDeviceError::InvalidEndpoint(endpoint))
+    }
+  }
+
+  fn echo(&self, command: HardwareCommand) {
+    // No one listening is fine, the device still works.
+    let _ = self.state.command_sender.send(command);
+  }
+}
+
+impl HardwareInternal for SimulatedHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.state.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.state.connected.swap(false, Ordering::SeqCst) {
+      let _ = self.state.event_sender.send(HardwareEvent::Disconnected(
+        self.address.clone(),
+        DeviceRemovedReason::UserDisconnect,
+        false,
+      ));
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    if let Err(err) = self.check_endpoint(msg.endpoint()) {
+      return future::ready(Err(err)).boxed();
+    }
+    let data = self
+      .state
+      .read_values
+      .get(&msg.endpoint())
+      .map(|data| data.clone())
+      .unwrap_or_default();
+    future::ready(Ok(HardwareReading::new(msg.endpoint(), &data))).boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if let Err(err) = self.check_endpoint(msg.endpoint()) {
+      return future::ready(Err(err)).boxed();
+    }
+    self.echo(msg.clone().into());
+    for response in self
+      .state
+      .responses
+      .iter()
+      .filter(|response| response.endpoint == msg.endpoint() && response.data == *msg.data())
+    {
+      if self
+        .state
+        .subscribed_endpoints
+        .contains(&response.reply_endpoint)
+      {
+        let _ = self.state.event_sender.send(HardwareEvent::Notification(
+          self.address.clone(),
+          response.reply_endpoint,
+          response.reply.clone(),
+        ));
+      }
+    }
+    future::ready(Ok(())).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if let Err(err) = self.check_endpoint(msg.endpoint()) {
+      return future::ready(Err(err)).boxed();
+    }
+    self.state.subscribed_endpoints.insert(msg.endpoint());
+    self.echo((*msg).into());
+    future::ready(Ok(())).boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if let Err(err) = self.check_endpoint(msg.endpoint()) {
+      return future::ready(Err(err)).boxed();
+    }
+    self.state.subscribed_endpoints.remove(&msg.endpoint());
+    self.echo((*msg).into());
+    future::ready(Ok(())).boxed()
+  }
+}
+
+impl Drop for SimulatedHardware {
+  fn drop(&mut self) {
+    // Let the device be found again on the next scan.
+    self.state.connected.store(false, Ordering::SeqCst);
+  }
+}
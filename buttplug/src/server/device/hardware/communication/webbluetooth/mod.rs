@@ -0,0 +1,18 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Bluetooth LE support for servers running in a browser, via the WebBluetooth API.
+//!
+//! web-sys still considers WebBluetooth unstable, so building this requires
+//! `RUSTFLAGS=--cfg=web_sys_unstable_apis`.
+
+pub mod webbluetooth_comm_manager;
+pub use webbluetooth_comm_manager::{
+  WebBluetoothCommunicationManager,
+  WebBluetoothCommunicationManagerBuilder,
+};
+pub mod webbluetooth_hardware;
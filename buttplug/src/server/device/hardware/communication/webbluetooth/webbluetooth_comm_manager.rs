@@ -0,0 +1,227 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::webbluetooth_hardware::WebBluetoothHardwareConnector;
+use crate::{
+  core::{errors::ButtplugDeviceError, ButtplugResultFuture},
+  server::device::{
+    configuration::ProtocolCommunicationSpecifier,
+    hardware::communication::{
+      HardwareCommunicationManager,
+      HardwareCommunicationManagerBuilder,
+      HardwareCommunicationManagerEvent,
+    },
+  },
+  util::device_configuration::load_protocol_configs,
+};
+use futures::future::{self, FutureExt};
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+use tokio::sync::mpsc::Sender;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{js_sys::JsString, BluetoothDevice, BluetoothLeScanFilterInit, RequestDeviceOptions};
+
+#[derive(Default, Clone)]
+pub struct WebBluetoothCommunicationManagerBuilder {
+  user_device_configuration_json: Option<String>,
+}
+
+impl WebBluetoothCommunicationManagerBuilder {
+  /// User device configuration to include when building the browser's device filters, so devices
+  /// only listed in user configs can be picked. Should match what's passed to the server.
+  pub fn user_device_configuration_json(&mut self, config_json: Option<String>) -> &mut Self {
+    self.user_device_configuration_json = config_json;
+    self
+  }
+}
+
+impl HardwareCommunicationManagerBuilder for WebBluetoothCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(WebBluetoothCommunicationManager::new(
+      sender,
+      self.user_device_configuration_json.clone(),
+    ))
+  }
+}
+
+/// Names and services the browser device chooser will filter on.
+#[derive(Default)]
+struct WebBluetoothFilters {
+  names: Vec<String>,
+  name_prefixes: Vec<String>,
+  services: Vec<String>,
+}
+
+impl WebBluetoothFilters {
+  fn from_configs(user_config_json: Option<String>) -> Result<Self, ButtplugDeviceError> {
+    let dcm = load_protocol_configs(None, user_config_json, false)?.finish()?;
+    let mut filters = Self::default();
+    for specifiers in dcm.protocol_device_configurations().values() {
+      for specifier in specifiers {
+        let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier else {
+          continue;
+        };
+        for name in btle.names() {
+          if let Some(prefix) = name.strip_suffix('*') {
+            filters.name_prefixes.push(prefix.to_owned());
+          } else {
+            filters.names.push(name.clone());
+          }
+        }
+        // WebBluetooth only lets us touch services we've declared up front.
+        for service in btle.services().keys() {
+          filters.services.push(service.to_string());
+        }
+      }
+    }
+    filters.names.sort();
+    filters.names.dedup();
+    filters.name_prefixes.sort();
+    filters.name_prefixes.dedup();
+    filters.services.sort();
+    filters.services.dedup();
+    Ok(filters)
+  }
+
+  fn request_device_options(&self) -> RequestDeviceOptions {
+    let mut filters = vec![];
+    for name in &self.names {
+      let filter = BluetoothLeScanFilterInit::new();
+      filter.set_name(name);
+      filters.push(filter);
+    }
+    for prefix in &self.name_prefixes {
+      let filter = BluetoothLeScanFilterInit::new();
+      filter.set_name_prefix(prefix);
+      filters.push(filter);
+    }
+    let optional_services: Vec<JsString> = self
+      .services
+      .iter()
+      .map(|service| JsString::from(service.as_str()))
+      .collect();
+    let options = RequestDeviceOptions::new();
+    options.set_filters(&filters);
+    options.set_optional_services(&optional_services);
+    options
+  }
+}
+
+/// Finds devices using the browser's WebBluetooth API.
+///
+/// Browsers don't allow scanning in the background. Each call to start scanning brings up the
+/// browser's device chooser, and reports the device the user picks (if any) before finishing.
+/// Browsers will only show the chooser in response to a user action, so scanning should be
+/// started from something like a button click handler.
+pub struct WebBluetoothCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  user_config_json: Option<String>,
+  is_scanning: Arc<AtomicBool>,
+}
+
+impl WebBluetoothCommunicationManager {
+  fn new(
+    sender: Sender<HardwareCommunicationManagerEvent>,
+    user_config_json: Option<String>,
+  ) -> Self {
+    Self {
+      sender,
+      user_config_json,
+      is_scanning: Arc::new(AtomicBool::new(false)),
+    }
+  }
+}
+
+async fn request_device(filters: WebBluetoothFilters) -> Option<BluetoothDevice> {
+  let Some(bluetooth) = web_sys::window().and_then(|window| window.navigator().bluetooth()) else {
+    error!("WebBluetooth is not available in this browser.");
+    return None;
+  };
+  match JsFuture::from(bluetooth.request_device(&filters.request_device_options())).await {
+    Ok(device) => Some(device.into()),
+    Err(err) => {
+      // This is also what happens when the user closes the chooser without picking anything.
+      info!("No WebBluetooth device chosen: {:?}", err);
+      None
+    }
+  }
+}
+
+impl HardwareCommunicationManager for WebBluetoothCommunicationManager {
+  fn name(&self) -> &'static str {
+    "WebBluetoothCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    if self.is_scanning.swap(true, Ordering::SeqCst) {
+      // Chooser is already up.
+      return future::ready(Ok(())).boxed();
+    }
+    let filters = match WebBluetoothFilters::from_configs(self.user_config_json.clone()) {
+      Ok(filters) => filters,
+      Err(err) => {
+        self.is_scanning.store(false, Ordering::SeqCst);
+        return future::ready(Err(err.into())).boxed();
+      }
+    };
+    let sender = self.sender.clone();
+    let is_scanning = self.is_scanning.clone();
+    spawn_local(async move {
+      if let Some(device) = request_device(filters).await {
+        match device.name() {
+          Some(name) => {
+            let address = device.id();
+            let creator = Box::new(WebBluetoothHardwareConnector::new(&name, device));
+            if sender
+              .send(HardwareCommunicationManagerEvent::DeviceFound {
+                name,
+                address,
+                creator,
+              })
+              .await
+              .is_err()
+            {
+              error!("Device manager receiver dropped, cannot send device found message.");
+            }
+          }
+          None => warn!("WebBluetooth device {} has no name, ignoring.", device.id()),
+        }
+      }
+      is_scanning.store(false, Ordering::SeqCst);
+      if sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .is_err()
+      {
+        error!("Error sending scanning finished. Scanning may not register as finished now!");
+      }
+    });
+    future::ready(Ok(())).boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    // There's no way to close the browser chooser, it finishes when the user is done with it.
+    future::ready(Ok(())).boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.is_scanning.load(Ordering::SeqCst)
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("btle")
+  }
+}
@@ -0,0 +1,435 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{BluetoothLESpecifier, ProtocolCommunicationSpecifier},
+    hardware::{
+      communication::HardwareSpecificError,
+      Hardware,
+      HardwareConnector,
+      HardwareEvent,
+      HardwareInternal,
+      HardwareReadCmd,
+      HardwareReading,
+      HardwareSpecializer,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  },
+};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+  collections::HashMap,
+  fmt::{self, Debug},
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use uuid::Uuid;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+  js_sys::{DataView, Uint8Array},
+  BluetoothDevice,
+  BluetoothRemoteGattCharacteristic,
+  BluetoothRemoteGattServer,
+  BluetoothRemoteGattService,
+  Event,
+};
+
+type WebBluetoothResultSender<T> = oneshot::Sender<Result<T, ButtplugDeviceError>>;
+
+/// Commands sent from the Send/Sync side of the hardware to the task that owns the JS objects.
+/// WebBluetooth objects can't leave the thread they were created on, so everything that touches
+/// them lives in a single local task per device.
+enum WebBluetoothDeviceCommand {
+  Connect(
+    HashMap<Uuid, HashMap<Endpoint, Uuid>>,
+    WebBluetoothResultSender<Vec<Endpoint>>,
+  ),
+  Write(HardwareWriteCmd, WebBluetoothResultSender<()>),
+  Read(HardwareReadCmd, WebBluetoothResultSender<HardwareReading>),
+  Subscribe(HardwareSubscribeCmd, WebBluetoothResultSender<()>),
+  Unsubscribe(HardwareUnsubscribeCmd, WebBluetoothResultSender<()>),
+  Disconnect,
+}
+
+fn webbluetooth_error(err: JsValue) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceSpecificError(HardwareSpecificError::WebBluetoothError(format!(
+    "{:?}",
+    err
+  )))
+}
+
+fn data_view_to_vec(view: &DataView) -> Vec<u8> {
+  Uint8Array::new_with_byte_offset_and_length(
+    &view.buffer(),
+    view.byte_offset() as u32,
+    view.byte_length() as u32,
+  )
+  .to_vec()
+}
+
+async fn connect_gatt(
+  device: &BluetoothDevice,
+  services: &HashMap<Uuid, HashMap<Endpoint, Uuid>>,
+) -> Result<HashMap<Endpoint, BluetoothRemoteGattCharacteristic>, ButtplugDeviceError> {
+  let gatt = device.gatt().ok_or_else(|| {
+    ButtplugDeviceError::DeviceConnectionError(format!(
+      "WebBluetooth device {} has no GATT server.",
+      device.id()
+    ))
+  })?;
+  let server: BluetoothRemoteGattServer = JsFuture::from(gatt.connect())
+    .await
+    .map_err(webbluetooth_error)?
+    .unchecked_into();
+  let mut characteristics = HashMap::new();
+  for (service_uuid, chrs) in services {
+    // Protocols list every service a device might have, so missing ones are expected.
+    let service: BluetoothRemoteGattService =
+      match JsFuture::from(server.get_primary_service_with_str(&service_uuid.to_string())).await {
+        Ok(service) => service.unchecked_into(),
+        Err(_) => continue,
+      };
+    debug!("Found required service {}", service_uuid);
+    for (endpoint, chr_uuid) in chrs {
+      match JsFuture::from(service.get_characteristic_with_str(&chr_uuid.to_string())).await {
+        Ok(chr) => {
+          debug!(
+            "Found characteristic {} for endpoint {}",
+            chr_uuid, endpoint
+          );
+          characteristics.insert(*endpoint, chr.unchecked_into());
+        }
+        Err(_) => error!(
+          "Characteristic {} ({}) not found, may cause issues in connection.",
+          endpoint, chr_uuid
+        ),
+      }
+    }
+  }
+  Ok(characteristics)
+}
+
+async fn run_webbluetooth_loop(
+  device: BluetoothDevice,
+  event_sender: broadcast::Sender<HardwareEvent>,
+  mut command_receiver: mpsc::Receiver<WebBluetoothDeviceCommand>,
+) {
+  let address = device.id();
+  let mut characteristics = HashMap::<Endpoint, BluetoothRemoteGattCharacteristic>::new();
+  // Closures have to outlive their registration with the browser, so hold on to them until the
+  // endpoint is unsubscribed or the device goes away.
+  let mut notification_handlers = HashMap::<Endpoint, Closure<dyn FnMut(Event)>>::new();
+
+  let disconnect_sender = event_sender.clone();
+  let disconnect_address = address.clone();
+  let disconnect_handler = Closure::wrap(Box::new(move |_: Event| {
+    info!("WebBluetooth device {} disconnected", disconnect_address);
+    let _ = disconnect_sender.send(HardwareEvent::Disconnected(
+      disconnect_address.clone(),
+      DeviceRemovedReason::LinkLost,
+      false,
+    ));
+  }) as Box<dyn FnMut(Event)>);
+  device.set_ongattserverdisconnected(Some(disconnect_handler.as_ref().unchecked_ref()));
+
+  while let Some(command) = command_receiver.recv().await {
+    match command {
+      WebBluetoothDeviceCommand::Connect(services, reply) => {
+        let result = connect_gatt(&device, &services).await.map(|chrs| {
+          let endpoints = chrs.keys().cloned().collect();
+          characteristics = chrs;
+          endpoints
+        });
+        let _ = reply.send(result);
+      }
+      WebBluetoothDeviceCommand::Write(msg, reply) => {
+        let Some(chr) = characteristics.get(&msg.endpoint()) else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint())));
+          continue;
+        };
+        let result = match chr.write_value_with_u8_array(&Uint8Array::from(msg.data().as_slice())) {
+          Ok(promise) => JsFuture::from(promise)
+            .await
+            .map(|_| ())
+            .map_err(webbluetooth_error),
+          Err(err) => Err(webbluetooth_error(err)),
+        };
+        let _ = reply.send(result);
+      }
+      WebBluetoothDeviceCommand::Read(msg, reply) => {
+        let Some(chr) = characteristics.get(&msg.endpoint()) else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(msg.endpoint())));
+          continue;
+        };
+        let result = JsFuture::from(chr.read_value())
+          .await
+          .map(|value| {
+            HardwareReading::new(
+              msg.endpoint(),
+              &data_view_to_vec(&value.unchecked_into::<DataView>()),
+            )
+          })
+          .map_err(webbluetooth_error);
+        let _ = reply.send(result);
+      }
+      WebBluetoothDeviceCommand::Subscribe(msg, reply) => {
+        let endpoint = msg.endpoint();
+        let Some(chr) = characteristics.get(&endpoint) else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(endpoint)));
+          continue;
+        };
+        let notification_sender = event_sender.clone();
+        let notification_address = address.clone();
+        let notification_chr = chr.clone();
+        let handler = Closure::wrap(Box::new(move |_: Event| {
+          if let Some(value) = notification_chr.value() {
+            let _ = notification_sender.send(HardwareEvent::Notification(
+              notification_address.clone(),
+              endpoint,
+              data_view_to_vec(&value),
+            ));
+          }
+        }) as Box<dyn FnMut(Event)>);
+        chr.set_oncharacteristicvaluechanged(Some(handler.as_ref().unchecked_ref()));
+        notification_handlers.insert(endpoint, handler);
+        let result = JsFuture::from(chr.start_notifications())
+          .await
+          .map(|_| ())
+          .map_err(webbluetooth_error);
+        let _ = reply.send(result);
+      }
+      WebBluetoothDeviceCommand::Unsubscribe(msg, reply) => {
+        let endpoint = msg.endpoint();
+        let Some(chr) = characteristics.get(&endpoint) else {
+          let _ = reply.send(Err(ButtplugDeviceError::InvalidEndpoint(endpoint)));
+          continue;
+        };
+        let result = JsFuture::from(chr.stop_notifications())
+          .await
+          .map(|_| ())
+          .map_err(webbluetooth_error);
+        chr.set_oncharacteristicvaluechanged(None);
+        notification_handlers.remove(&endpoint);
+        let _ = reply.send(result);
+      }
+      WebBluetoothDeviceCommand::Disconnect => {
+        if let Some(gatt) = device.gatt() {
+          gatt.disconnect();
+        }
+        break;
+      }
+    }
+  }
+  // Either we disconnected, or the device was dropped without disconnecting. Either way, nothing
+  // should call back into handlers we're about to drop.
+  for chr in characteristics.values() {
+    chr.set_oncharacteristicvaluechanged(None);
+  }
+  device.set_ongattserverdisconnected(None);
+  debug!("WebBluetooth device loop for {} exiting", address);
+}
+
+pub struct WebBluetoothHardwareConnector {
+  name: String,
+  address: String,
+  // Only used until connection, at which point it's handed to the hardware.
+  command_sender: mpsc::Sender<WebBluetoothDeviceCommand>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl WebBluetoothHardwareConnector {
+  /// Must be called from the browser thread, as it starts the task that owns the device.
+  pub(super) fn new(name: &str, device: BluetoothDevice) -> Self {
+    let address = device.id();
+    let (command_sender, command_receiver) = mpsc::channel(256);
+    let (event_sender, _) = broadcast::channel(256);
+    spawn_local(run_webbluetooth_loop(
+      device,
+      event_sender.clone(),
+      command_receiver,
+    ));
+    Self {
+      name: name.to_owned(),
+      address,
+      command_sender,
+      event_sender,
+    }
+  }
+}
+
+impl Debug for WebBluetoothHardwareConnector {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("WebBluetoothHardwareConnector")
+      .field("name", &self.name)
+      .field("address", &self.address)
+      .finish()
+  }
+}
+
+#[async_trait]
+impl HardwareConnector for WebBluetoothHardwareConnector {
+  fn specifier(&self) -> ProtocolCommunicationSpecifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(BluetoothLESpecifier::new_from_device(
+      &self.name,
+      &HashMap::new(),
+      &[],
+    ))
+  }
+
+  async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
+    Ok(Box::new(WebBluetoothHardwareSpecializer {
+      name: self.name.clone(),
+      address: self.address.clone(),
+      command_sender: self.command_sender.clone(),
+      event_sender: self.event_sender.clone(),
+    }))
+  }
+}
+
+pub struct WebBluetoothHardwareSpecializer {
+  name: String,
+  address: String,
+  command_sender: mpsc::Sender<WebBluetoothDeviceCommand>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+#[async_trait]
+impl HardwareSpecializer for WebBluetoothHardwareSpecializer {
+  async fn specialize(
+    &mut self,
+    specifiers: &[ProtocolCommunicationSpecifier],
+  ) -> Result<Hardware, ButtplugDeviceError> {
+    let Some(ProtocolCommunicationSpecifier::BluetoothLE(btle)) = specifiers
+      .iter()
+      .find(|x| matches!(x, ProtocolCommunicationSpecifier::BluetoothLE(_)))
+    else {
+      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+        "Can't find btle protocol specifier mapping for device {} {}",
+        self.name, self.address
+      )));
+    };
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    send_command(
+      &self.command_sender,
+      WebBluetoothDeviceCommand::Connect(btle.services().clone(), reply_sender),
+      reply_receiver,
+    )
+    .await
+    .map(|endpoints| {
+      Hardware::new(
+        &self.name,
+        &self.address,
+        &endpoints,
+        Box::new(WebBluetoothHardware {
+          command_sender: self.command_sender.clone(),
+          event_sender: self.event_sender.clone(),
+        }),
+      )
+    })
+  }
+}
+
+async fn send_command<T>(
+  command_sender: &mpsc::Sender<WebBluetoothDeviceCommand>,
+  command: WebBluetoothDeviceCommand,
+  reply_receiver: oneshot::Receiver<Result<T, ButtplugDeviceError>>,
+) -> Result<T, ButtplugDeviceError> {
+  if command_sender.send(command).await.is_err() {
+    return Err(ButtplugDeviceError::DeviceNotConnected(
+      "WebBluetooth device loop has exited.".to_owned(),
+    ));
+  }
+  reply_receiver.await.unwrap_or_else(|_| {
+    Err(ButtplugDeviceError::DeviceNotConnected(
+      "WebBluetooth device loop exited before replying.".to_owned(),
+    ))
+  })
+}
+
+pub struct WebBluetoothHardware {
+  command_sender: mpsc::Sender<WebBluetoothDeviceCommand>,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl WebBluetoothHardware {
+  fn command<T: Send + 'static>(
+    &self,
+    command: impl FnOnce(WebBluetoothResultSender<T>) -> WebBluetoothDeviceCommand,
+  ) -> BoxFuture<'static, Result<T, ButtplugDeviceError>> {
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    let command_sender = self.command_sender.clone();
+    let command = command(reply_sender);
+    async move { send_command(&command_sender, command, reply_receiver).await }.boxed()
+  }
+}
+
+impl HardwareInternal for WebBluetoothHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let command_sender = self.command_sender.clone();
+    async move {
+      // If the loop is already gone, so is the device.
+      let _ = command_sender
+        .send(WebBluetoothDeviceCommand::Disconnect)
+        .await;
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let msg = *msg;
+    self.command(move |reply| WebBluetoothDeviceCommand::Read(msg, reply))
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = msg.clone();
+    self.command(move |reply| WebBluetoothDeviceCommand::Write(msg, reply))
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = *msg;
+    self.command(move |reply| WebBluetoothDeviceCommand::Subscribe(msg, reply))
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let msg = *msg;
+    self.command(move |reply| WebBluetoothDeviceCommand::Unsubscribe(msg, reply))
+  }
+}
+
+impl Drop for WebBluetoothHardware {
+  fn drop(&mut self) {
+    // Shut down the device loop so the browser lets go of the connection.
+    let _ = self
+      .command_sender
+      .try_send(WebBluetoothDeviceCommand::Disconnect);
+  }
+}
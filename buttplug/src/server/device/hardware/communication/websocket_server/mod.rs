@@ -7,3 +7,4 @@
 
 pub mod websocket_server_comm_manager;
 pub mod websocket_server_hardware;
+pub mod websocket_server_messages;
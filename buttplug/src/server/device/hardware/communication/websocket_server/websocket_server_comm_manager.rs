@@ -7,7 +7,7 @@
 
 use super::websocket_server_hardware::WebsocketServerHardwareConnector;
 use crate::{
-  core::ButtplugResultFuture,
+  core::{message::Endpoint, ButtplugResultFuture},
   server::device::hardware::communication::{
     HardwareCommunicationManager,
     HardwareCommunicationManagerBuilder,
@@ -15,12 +15,30 @@ use crate::{
   },
   util::async_manager,
 };
+use dashmap::DashMap;
 use futures::{FutureExt, StreamExt};
 use getset::{CopyGetters, Getters};
 use serde::{Deserialize, Serialize};
-use tokio::{net::TcpListener, sync::mpsc::Sender};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+  net::{TcpListener, TcpStream},
+  sync::mpsc::{self, error::SendError, Sender},
+};
+use tokio_tungstenite::WebSocketStream;
 use tokio_util::sync::CancellationToken;
 
+/// First version of the device protocol using JSON messages, see
+/// [websocket_server_messages](super::websocket_server_messages).
+pub const WEBSOCKET_DEVICE_JSON_PROTOCOL_VERSION: u32 = 2;
+
+/// How long version 2 devices have to reconnect before they're considered disconnected.
+const DEFAULT_RECONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection loops of version 2 devices, by address, waiting for new streams if their device
+/// reconnects.
+pub(super) type ReconnectionRegistry =
+  Arc<DashMap<String, mpsc::Sender<WebSocketStream<TcpStream>>>>;
+
 // Packet format received from external devices.
 #[derive(Serialize, Deserialize, Debug, Clone, Getters, CopyGetters)]
 pub struct WebsocketServerDeviceCommManagerInitInfo {
@@ -30,12 +48,24 @@ pub struct WebsocketServerDeviceCommManagerInitInfo {
   address: String,
   #[getset(get_copy = "pub")]
   version: u32,
+  /// Endpoints the device has. Devices that don't declare any get Rx and Tx.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  #[getset(get = "pub")]
+  endpoints: Option<Vec<Endpoint>>,
+}
+
+impl WebsocketServerDeviceCommManagerInitInfo {
+  /// True if the device talks JSON messages instead of raw binary frames.
+  pub fn uses_json_protocol(&self) -> bool {
+    self.version >= WEBSOCKET_DEVICE_JSON_PROTOCOL_VERSION
+  }
 }
 
 #[derive(Clone)]
 pub struct WebsocketServerDeviceCommunicationManagerBuilder {
   listen_on_all_interfaces: bool,
   server_port: u16,
+  reconnect_timeout: Duration,
 }
 
 impl Default for WebsocketServerDeviceCommunicationManagerBuilder {
@@ -43,6 +73,7 @@ impl Default for WebsocketServerDeviceCommunicationManagerBuilder {
     Self {
       listen_on_all_interfaces: false,
       server_port: 54817,
+      reconnect_timeout: DEFAULT_RECONNECT_TIMEOUT,
     }
   }
 }
@@ -57,6 +88,13 @@ impl WebsocketServerDeviceCommunicationManagerBuilder {
     self.server_port = port;
     self
   }
+
+  /// How long version 2 devices have to reconnect with the same address after losing their
+  /// connection, before they're removed.
+  pub fn reconnect_timeout(mut self, timeout: Duration) -> Self {
+    self.reconnect_timeout = timeout;
+    self
+  }
 }
 
 impl HardwareCommunicationManagerBuilder for WebsocketServerDeviceCommunicationManagerBuilder {
@@ -68,6 +106,7 @@ impl HardwareCommunicationManagerBuilder for WebsocketServerDeviceCommunicationM
       sender,
       self.server_port,
       self.listen_on_all_interfaces,
+      self.reconnect_timeout,
     ))
   }
 }
@@ -81,10 +120,12 @@ impl WebsocketServerDeviceCommunicationManager {
     sender: Sender<HardwareCommunicationManagerEvent>,
     port: u16,
     listen_on_all_interfaces: bool,
+    reconnect_timeout: Duration,
   ) -> Self {
     trace!("Websocket server port created.");
     let server_cancellation_token = CancellationToken::new();
     let child_token = server_cancellation_token.child_token();
+    let registry = ReconnectionRegistry::default();
     async_manager::spawn(async move {
       let base_addr = if listen_on_all_interfaces {
         "0.0.0.0"
@@ -128,6 +169,8 @@ impl WebsocketServerDeviceCommunicationManager {
             // wait for the first packet. We'll have to pass our device event sender off to the newly
             // created event loop, so that it can fire once the info packet is received.
             let sender_clone = sender.clone();
+            let registry = registry.clone();
+            let manager_token = child_token.clone();
             tokio::spawn(async move {
               // TODO Implement a receive timeout here so we don't wait forever
               if let Some(Ok(tokio_tungstenite::tungstenite::Message::Text(info_message))) =
@@ -143,6 +186,23 @@ impl WebsocketServerDeviceCommunicationManager {
                     }
                     return;
                   };
+                // Version 2 devices that reconnect while their last connection is still
+                // remembered are handed to the existing connection loop, keeping the device.
+                if info_packet.uses_json_protocol() {
+                  let reconnect_sender = registry
+                    .get(info_packet.address())
+                    .map(|sender| sender.clone());
+                  if let Some(reconnect_sender) = reconnect_sender {
+                    match reconnect_sender.send(ws_stream).await {
+                      Ok(()) => {
+                        info!("Websocket device {} reconnected.", info_packet.address());
+                        return;
+                      }
+                      // The old connection loop has already exited, connect as a new device.
+                      Err(SendError(stream)) => ws_stream = stream,
+                    }
+                  }
+                }
                 if sender_clone
                   .send(HardwareCommunicationManagerEvent::DeviceFound {
                     name: format!("Websocket Device {}", info_packet.identifier),
@@ -150,6 +210,9 @@ impl WebsocketServerDeviceCommunicationManager {
                     creator: Box::new(WebsocketServerHardwareConnector::new(
                       info_packet,
                       ws_stream,
+                      registry,
+                      reconnect_timeout,
+                      manager_token,
                     )),
                   })
                   .await
@@ -193,6 +256,10 @@ impl HardwareCommunicationManager for WebsocketServerDeviceCommunicationManager
   fn can_scan(&self) -> bool {
     true
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("websocket")
+  }
 }
 
 impl Drop for WebsocketServerDeviceCommunicationManager {
@@ -5,9 +5,18 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::websocket_server_comm_manager::WebsocketServerDeviceCommManagerInitInfo;
+use super::{
+  websocket_server_comm_manager::{ReconnectionRegistry, WebsocketServerDeviceCommManagerInitInfo},
+  websocket_server_messages::{
+    WebsocketServerDeviceIncomingMessage,
+    WebsocketServerDeviceOutgoingMessage,
+  },
+};
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::{
     configuration::{ProtocolCommunicationSpecifier, WebsocketSpecifier},
     hardware::{
@@ -27,6 +36,7 @@ use crate::{
   util::async_manager,
 };
 use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
 use futures::{
   future::{self, BoxFuture},
   FutureExt,
@@ -36,7 +46,7 @@ use futures::{
 use std::{
   fmt::{self, Debug},
   sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU32, Ordering},
     Arc,
   },
   time::Duration,
@@ -45,110 +55,280 @@ use tokio::{
   net::TcpStream,
   sync::{
     broadcast,
-    mpsc::{channel, Receiver, Sender},
+    mpsc::{self, channel, Receiver, Sender},
+    oneshot,
     Mutex,
   },
   time::sleep,
 };
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use tokio_util::sync::CancellationToken;
 
-async fn run_connection_loop(
-  address: &str,
+/// State shared between version 2 devices and their connection loop.
+#[derive(Default)]
+struct JsonProtocolState {
+  /// Endpoints the device should send notifications for. Sent to the device again if it
+  /// reconnects.
+  subscribed_endpoints: DashSet<Endpoint>,
+  /// Reads waiting for their reading, by read id.
+  pending_reads: DashMap<u32, oneshot::Sender<HardwareReading>>,
+  next_read_id: AtomicU32,
+}
+
+fn json_message(msg: &WebsocketServerDeviceOutgoingMessage) -> Message {
+  Message::Text(serde_json::to_string(msg).expect("Infallible serialization"))
+}
+
+/// Why a websocket connection to a device ended.
+enum ConnectionEnd {
+  /// Connection errored or the device stopped answering pings. Version 2 devices can reconnect.
+  Lost,
+  /// Device closed the connection itself.
+  Closed,
+  /// Device was disconnected or dropped on our side, or the comm manager shut down.
+  Shutdown,
+}
+
+/// Lets a version 2 device's connection loop pick up new connections from the same device.
+struct Reconnection {
+  registry: ReconnectionRegistry,
+  stream_receiver: mpsc::Receiver<WebSocketStream<TcpStream>>,
+  timeout: Duration,
+}
+
+struct ConnectionLoop {
+  address: String,
+  json_protocol: bool,
+  json_state: Arc<JsonProtocolState>,
   event_sender: broadcast::Sender<HardwareEvent>,
-  ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
-  mut request_receiver: Receiver<Vec<u8>>,
+  request_receiver: Receiver<Message>,
   response_sender: broadcast::Sender<Vec<u8>>,
-) {
-  info!("Starting websocket server connection event loop.");
+  cancellation_token: CancellationToken,
+  /// Cancelled when the comm manager shuts down, which also cancels cancellation_token.
+  manager_token: CancellationToken,
+}
+
+impl ConnectionLoop {
+  async fn run(
+    mut self,
+    mut ws_stream: WebSocketStream<TcpStream>,
+    mut reconnection: Option<Reconnection>,
+  ) {
+    info!("Starting websocket server connection event loop.");
+    loop {
+      let connection_end = self.run_connection(ws_stream).await;
+      let Some(reconnection) = reconnection.as_mut() else {
+        break;
+      };
+      if !matches!(connection_end, ConnectionEnd::Lost) {
+        break;
+      }
+      info!(
+        "Websocket device {} lost its connection, waiting {:?} for it to reconnect.",
+        self.address, reconnection.timeout
+      );
+      select! {
+        stream = reconnection.stream_receiver.recv().fuse() => match stream {
+          Some(stream) => ws_stream = stream,
+          None => break,
+        },
+        _ = sleep(reconnection.timeout).fuse() => {
+          info!("Websocket device {} did not reconnect in time.", self.address);
+          break;
+        }
+        _ = self.cancellation_token.cancelled().fuse() => break,
+      }
+    }
 
-  let (mut websocket_server_sender, mut websocket_server_receiver) = ws_stream.split();
+    if let Some(Reconnection {
+      registry,
+      stream_receiver,
+      ..
+    }) = reconnection
+    {
+      // Only remove our own entry, the device may have reconnected as a new device already.
+      drop(stream_receiver);
+      registry.remove_if(&self.address, |_, sender| sender.is_closed());
+    }
+    let reason = if self.manager_token.is_cancelled() {
+      DeviceRemovedReason::CommManagerShutdown
+    } else if self.cancellation_token.is_cancelled() {
+      DeviceRemovedReason::UserDisconnect
+    } else {
+      DeviceRemovedReason::LinkLost
+    };
+    // Drop the error if no one receives the message, we're exiting anyways.
+    let _ = self.event_sender.send(HardwareEvent::Disconnected(
+      self.address.clone(),
+      reason,
+      false,
+    ));
+    debug!("Exiting Websocket Server Device control loop.");
+  }
 
-  // Start pong count at 1, so we'll clear it after sending our first ping.
-  let mut pong_count = 1u32;
+  async fn run_connection(&mut self, ws_stream: WebSocketStream<TcpStream>) -> ConnectionEnd {
+    let (mut websocket_server_sender, mut websocket_server_receiver) = ws_stream.split();
 
-  loop {
-    select! {
-      _ = sleep(Duration::from_millis(10000)).fuse() => {
-        if pong_count == 0 {
-          error!("No pongs received, considering connection closed.");
-          break;
+    // Devices forget their subscriptions when they reconnect, so remind them.
+    let subscribed_endpoints: Vec<Endpoint> = self
+      .json_state
+      .subscribed_endpoints
+      .iter()
+      .map(|endpoint| *endpoint)
+      .collect();
+    for endpoint in subscribed_endpoints {
+      let subscribe = WebsocketServerDeviceOutgoingMessage::Subscribe { endpoint };
+      if websocket_server_sender
+        .send(json_message(&subscribe))
+        .await
+        .is_err()
+      {
+        return ConnectionEnd::Lost;
+      }
+    }
+
+    // Start pong count at 1, so we'll clear it after sending our first ping.
+    let mut pong_count = 1u32;
+
+    let connection_end = loop {
+      select! {
+        _ = self.cancellation_token.cancelled().fuse() => {
+          info!("Websocket server device disconnected, closing websocket connection.");
+          break ConnectionEnd::Shutdown;
         }
-        pong_count = 0;
-        if websocket_server_sender
-          .send(tokio_tungstenite::tungstenite::Message::Ping(vec!(0)))
-          .await
-          .is_err() {
-          error!("Cannot send ping to client, considering connection closed.");
-          break;
+        _ = sleep(Duration::from_millis(10000)).fuse() => {
+          if pong_count == 0 {
+            error!("No pongs received, considering connection closed.");
+            break ConnectionEnd::Lost;
+          }
+          pong_count = 0;
+          let ping = if self.json_protocol {
+            json_message(&WebsocketServerDeviceOutgoingMessage::Ping {})
+          } else {
+            Message::Ping(vec!(0))
+          };
+          if websocket_server_sender.send(ping).await.is_err() {
+            error!("Cannot send ping to client, considering connection closed.");
+            break ConnectionEnd::Lost;
+          }
         }
-      }
-      ws_msg = request_receiver.recv().fuse() => {
-        if let Some(binary_msg) = ws_msg {
-          if websocket_server_sender
-            .send(tokio_tungstenite::tungstenite::Message::Binary(binary_msg))
-            .await
-            .is_err() {
-            error!("Cannot send binary value to client, considering connection closed.");
-            break;
+        ws_msg = self.request_receiver.recv().fuse() => {
+          if let Some(msg) = ws_msg {
+            if websocket_server_sender.send(msg).await.is_err() {
+              error!("Cannot send value to client, considering connection closed.");
+              break ConnectionEnd::Lost;
+            }
+          } else {
+            info!("Websocket server connector owner dropped, disconnecting websocket connection.");
+            break ConnectionEnd::Shutdown;
           }
-        } else {
-          info!("Websocket server connector owner dropped, disconnecting websocket connection.");
-          break;
         }
-      }
-      websocket_server_msg = websocket_server_receiver.next().fuse() => match websocket_server_msg {
-        Some(ws_data) => {
-          match ws_data {
-            Ok(msg) => {
-              match msg {
-                tokio_tungstenite::tungstenite::Message::Text(text_msg) => {
-                  // If someone accidentally packs text, politely turn it into binary for them.
-                  let _ = response_sender.send(text_msg.as_bytes().to_vec());
-                }
-                tokio_tungstenite::tungstenite::Message::Binary(binary_msg) => {
-                  // If no one is listening, ignore output.
-                  let _ = response_sender.send(binary_msg);
-                }
-                tokio_tungstenite::tungstenite::Message::Close(_) => {
-                  // Drop the error if no one receives the message, we're breaking anyways.
-                  let _ = event_sender
-                    .send(HardwareEvent::Disconnected(
-                      address.to_owned()
-                    ));
-                  break;
-                }
-                tokio_tungstenite::tungstenite::Message::Ping(_) => {
-                  // noop
-                  continue;
-                }
-                tokio_tungstenite::tungstenite::Message::Frame(_) => {
-                  // noop
-                  continue;
-                }
-                tokio_tungstenite::tungstenite::Message::Pong(_) => {
-                  pong_count += 1;
-                  continue;
+        websocket_server_msg = websocket_server_receiver.next().fuse() => match websocket_server_msg {
+          Some(ws_data) => {
+            match ws_data {
+              Ok(msg) => {
+                match msg {
+                  Message::Text(text_msg) => {
+                    if self.json_protocol {
+                      // Anything from the device shows it's still there.
+                      pong_count += 1;
+                      if let Some(reply) = self.handle_json_message(&text_msg) {
+                        if websocket_server_sender.send(reply).await.is_err() {
+                          error!("Cannot send reply to client, considering connection closed.");
+                          break ConnectionEnd::Lost;
+                        }
+                      }
+                    } else {
+                      // If someone accidentally packs text, politely turn it into binary for them.
+                      let _ = self.response_sender.send(text_msg.as_bytes().to_vec());
+                    }
+                  }
+                  Message::Binary(binary_msg) => {
+                    if self.json_protocol {
+                      warn!("Websocket device {} sent a binary frame, ignoring.", self.address);
+                    } else {
+                      // If no one is listening, ignore output.
+                      let _ = self.response_sender.send(binary_msg);
+                    }
+                  }
+                  Message::Close(_) => {
+                    break ConnectionEnd::Closed;
+                  }
+                  Message::Ping(_) => {
+                    // noop
+                    continue;
+                  }
+                  Message::Frame(_) => {
+                    // noop
+                    continue;
+                  }
+                  Message::Pong(_) => {
+                    pong_count += 1;
+                    continue;
+                  }
                 }
+              },
+              Err(err) => {
+                error!("Error from websocket server, assuming disconnection: {:?}", err);
+                break ConnectionEnd::Lost;
               }
-            },
-            Err(err) => {
-              error!("Error from websocket server, assuming disconnection: {:?}", err);
-              break;
             }
+          },
+          None => {
+            error!("Websocket channel closed, breaking");
+            break ConnectionEnd::Lost;
           }
-        },
-        None => {
-          error!("Websocket channel closed, breaking");
-          break;
         }
       }
+    };
+
+    if let Err(e) = websocket_server_sender.close().await {
+      debug!("Error closing websocket: {}", e);
     }
+    connection_end
   }
 
-  if let Err(e) = websocket_server_sender.close().await {
-    error!("Error closing websocket: {}", e);
+  /// Handles a message from a version 2 device, returning the reply to send, if any.
+  fn handle_json_message(&self, text_msg: &str) -> Option<Message> {
+    match serde_json::from_str::<WebsocketServerDeviceIncomingMessage>(text_msg) {
+      Ok(WebsocketServerDeviceIncomingMessage::Notification { endpoint, data }) => {
+        if self.json_state.subscribed_endpoints.contains(&endpoint) {
+          // We don't really care if there's no one to send the notification to here.
+          let _ = self.event_sender.send(HardwareEvent::Notification(
+            self.address.clone(),
+            endpoint,
+            data,
+          ));
+        } else {
+          debug!(
+            "Websocket device {} sent notification for unsubscribed endpoint {}, ignoring.",
+            self.address, endpoint
+          );
+        }
+      }
+      Ok(WebsocketServerDeviceIncomingMessage::Reading { id, endpoint, data }) => {
+        if let Some((_, reading_sender)) = self.json_state.pending_reads.remove(&id) {
+          let _ = reading_sender.send(HardwareReading::new(endpoint, &data));
+        } else {
+          warn!(
+            "Websocket device {} sent reading for unknown read {}, ignoring.",
+            self.address, id
+          );
+        }
+      }
+      Ok(WebsocketServerDeviceIncomingMessage::Ping {}) => {
+        return Some(json_message(&WebsocketServerDeviceOutgoingMessage::Pong {}));
+      }
+      Ok(WebsocketServerDeviceIncomingMessage::Pong {}) => {}
+      Err(err) => {
+        error!(
+          "Cannot parse message from websocket device {}, ignoring: {}",
+          self.address, err
+        );
+      }
+    }
+    None
   }
-  debug!("Exiting Websocket Server Device control loop.");
 }
 
 impl Debug for WebsocketServerHardwareConnector {
@@ -161,37 +341,55 @@ impl Debug for WebsocketServerHardwareConnector {
 
 pub struct WebsocketServerHardwareConnector {
   info: WebsocketServerDeviceCommManagerInitInfo,
-  outgoing_sender: Sender<Vec<u8>>,
+  outgoing_sender: Sender<Message>,
   incoming_broadcaster: broadcast::Sender<Vec<u8>>,
   device_event_sender: broadcast::Sender<HardwareEvent>,
+  json_state: Arc<JsonProtocolState>,
+  cancellation_token: CancellationToken,
 }
 
 impl WebsocketServerHardwareConnector {
-  pub fn new(
+  pub(super) fn new(
     info: WebsocketServerDeviceCommManagerInitInfo,
-    ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
+    ws_stream: WebSocketStream<TcpStream>,
+    registry: ReconnectionRegistry,
+    reconnect_timeout: Duration,
+    manager_token: CancellationToken,
   ) -> Self {
     let (outgoing_sender, outgoing_receiver) = channel(256);
     let (incoming_broadcaster, _) = broadcast::channel(256);
-    let incoming_broadcaster_clone = incoming_broadcaster.clone();
     let (device_event_sender, _) = broadcast::channel(256);
-    let device_event_sender_clone = device_event_sender.clone();
-    let address = info.address().clone();
-    tokio::spawn(async move {
-      run_connection_loop(
-        &address,
-        device_event_sender_clone,
-        ws_stream,
-        outgoing_receiver,
-        incoming_broadcaster_clone,
-      )
-      .await;
-    });
+    let json_state = Arc::new(JsonProtocolState::default());
+    let cancellation_token = manager_token.child_token();
+    let reconnection = if info.uses_json_protocol() {
+      let (stream_sender, stream_receiver) = mpsc::channel(1);
+      registry.insert(info.address().clone(), stream_sender);
+      Some(Reconnection {
+        registry,
+        stream_receiver,
+        timeout: reconnect_timeout,
+      })
+    } else {
+      None
+    };
+    let connection_loop = ConnectionLoop {
+      address: info.address().clone(),
+      json_protocol: info.uses_json_protocol(),
+      json_state: json_state.clone(),
+      event_sender: device_event_sender.clone(),
+      request_receiver: outgoing_receiver,
+      response_sender: incoming_broadcaster.clone(),
+      cancellation_token: cancellation_token.clone(),
+      manager_token,
+    };
+    tokio::spawn(connection_loop.run(ws_stream, reconnection));
     Self {
       info,
       outgoing_sender,
       incoming_broadcaster,
       device_event_sender,
+      json_state,
+      cancellation_token,
     }
   }
 }
@@ -206,16 +404,27 @@ impl HardwareConnector for WebsocketServerHardwareConnector {
   }
 
   async fn connect(&mut self) -> Result<Box<dyn HardwareSpecializer>, ButtplugDeviceError> {
-    let hardware_internal = WebsocketServerHardware::new(
-      self.device_event_sender.clone(),
-      self.info.clone(),
-      self.outgoing_sender.clone(),
-      self.incoming_broadcaster.clone(),
-    );
+    let endpoints = self
+      .info
+      .endpoints()
+      .clone()
+      .unwrap_or_else(|| vec![Endpoint::Rx, Endpoint::Tx]);
+    let hardware_internal = WebsocketServerHardware {
+      connected: Arc::new(AtomicBool::new(true)),
+      subscribed: Arc::new(AtomicBool::new(false)),
+      subscribe_token: Arc::new(Mutex::new(None)),
+      info: self.info.clone(),
+      endpoints: endpoints.clone(),
+      outgoing_sender: self.outgoing_sender.clone(),
+      incoming_broadcaster: self.incoming_broadcaster.clone(),
+      device_event_sender: self.device_event_sender.clone(),
+      json_state: self.json_state.clone(),
+      cancellation_token: self.cancellation_token.clone(),
+    };
     let hardware = Hardware::new(
       self.info.identifier(),
       self.info.address(),
-      &[Endpoint::Rx, Endpoint::Tx],
+      &endpoints,
       Box::new(hardware_internal),
     );
     Ok(Box::new(GenericHardwareSpecializer::new(hardware)))
@@ -227,28 +436,39 @@ pub struct WebsocketServerHardware {
   subscribed: Arc<AtomicBool>,
   subscribe_token: Arc<Mutex<Option<CancellationToken>>>,
   info: WebsocketServerDeviceCommManagerInitInfo,
-  outgoing_sender: Sender<Vec<u8>>,
+  endpoints: Vec<Endpoint>,
+  outgoing_sender: Sender<Message>,
   incoming_broadcaster: broadcast::Sender<Vec<u8>>,
   device_event_sender: broadcast::Sender<HardwareEvent>,
+  json_state: Arc<JsonProtocolState>,
+  cancellation_token: CancellationToken,
 }
 
 impl WebsocketServerHardware {
-  pub fn new(
-    device_event_sender: broadcast::Sender<HardwareEvent>,
-    info: WebsocketServerDeviceCommManagerInitInfo,
-    outgoing_sender: Sender<Vec<u8>>,
-    incoming_broadcaster: broadcast::Sender<Vec<u8>>,
-  ) -> Self {
-    Self {
-      connected: Arc::new(AtomicBool::new(true)),
-      info,
-      outgoing_sender,
-      incoming_broadcaster,
-      device_event_sender,
-      subscribed: Arc::new(AtomicBool::new(false)),
-      subscribe_token: Arc::new(Mutex::new(None)),
+  /// Version 2 devices declare their endpoints, so commands for any others can be refused here.
+  fn check_endpoint(&self, endpoint: Endpoint) -> Result<(), ButtplugDeviceError> {
+    if self.info.uses_json_protocol() && !self.endpoints.contains(&endpoint) {
+      Err(ButtplugDeviceError::InvalidEndpoint(endpoint))
+    } else {
+      Ok(())
     }
   }
+
+  fn send_json(
+    &self,
+    msg: WebsocketServerDeviceOutgoingMessage,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let sender = self.outgoing_sender.clone();
+    async move {
+      sender.send(json_message(&msg)).await.map_err(|err| {
+        ButtplugDeviceError::DeviceCommunicationError(format!(
+          "Could not send message to websocket device: {}",
+          err
+        ))
+      })
+    }
+    .boxed()
+  }
 }
 
 impl HardwareInternal for WebsocketServerHardware {
@@ -258,8 +478,10 @@ impl HardwareInternal for WebsocketServerHardware {
 
   fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     let connected = self.connected.clone();
+    let cancellation_token = self.cancellation_token.clone();
     async move {
       connected.store(false, Ordering::SeqCst);
+      cancellation_token.cancel();
       Ok(())
     }
     .boxed()
@@ -267,11 +489,42 @@ impl HardwareInternal for WebsocketServerHardware {
 
   fn read_value(
     &self,
-    _msg: &HardwareReadCmd,
+    msg: &HardwareReadCmd,
   ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
-    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Websocket Hardware does not support read".to_owned(),
-    )))
+    if !self.info.uses_json_protocol() {
+      return future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+        "Websocket Hardware does not support read".to_owned(),
+      )))
+      .boxed();
+    }
+    if let Err(err) = self.check_endpoint(msg.endpoint()) {
+      return future::ready(Err(err)).boxed();
+    }
+    let id = self.json_state.next_read_id.fetch_add(1, Ordering::SeqCst);
+    let (reading_sender, reading_receiver) = oneshot::channel();
+    self.json_state.pending_reads.insert(id, reading_sender);
+    let json_state = self.json_state.clone();
+    let send_fut = self.send_json(WebsocketServerDeviceOutgoingMessage::Read {
+      id,
+      endpoint: msg.endpoint(),
+      length: msg.length(),
+    });
+    let timeout = Duration::from_millis(msg.timeout_ms().into());
+    async move {
+      send_fut.await?;
+      let result = select! {
+        reading = reading_receiver.fuse() => reading.map_err(|_| {
+          ButtplugDeviceError::DeviceCommunicationError(
+            "Websocket device disconnected before sending reading".to_owned(),
+          )
+        }),
+        _ = sleep(timeout).fuse() => Err(ButtplugDeviceError::DeviceCommunicationError(
+          "Websocket device did not send reading in time".to_owned(),
+        )),
+      };
+      json_state.pending_reads.remove(&id);
+      result
+    }
     .boxed()
   }
 
@@ -279,11 +532,20 @@ impl HardwareInternal for WebsocketServerHardware {
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if let Err(err) = self.check_endpoint(msg.endpoint()) {
+      return future::ready(Err(err)).boxed();
+    }
+    if self.info.uses_json_protocol() {
+      return self.send_json(WebsocketServerDeviceOutgoingMessage::Write {
+        endpoint: msg.endpoint(),
+        data: msg.data().clone(),
+        write_with_response: msg.write_with_response(),
+      });
+    }
     let sender = self.outgoing_sender.clone();
-    let data = msg.data.clone();
-    // TODO Should check endpoint validity
+    let data = msg.data().clone();
     async move {
-      sender.send(data).await.map_err(|err| {
+      sender.send(Message::Binary(data)).await.map_err(|err| {
         ButtplugDeviceError::DeviceCommunicationError(format!(
           "Could not write value to websocket device: {}",
           err
@@ -295,13 +557,22 @@ impl HardwareInternal for WebsocketServerHardware {
 
   fn subscribe(
     &self,
-    _msg: &HardwareSubscribeCmd,
+    msg: &HardwareSubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.info.uses_json_protocol() {
+      if let Err(err) = self.check_endpoint(msg.endpoint()) {
+        return future::ready(Err(err)).boxed();
+      }
+      self.json_state.subscribed_endpoints.insert(msg.endpoint());
+      return self.send_json(WebsocketServerDeviceOutgoingMessage::Subscribe {
+        endpoint: msg.endpoint(),
+      });
+    }
     if self.subscribed.load(Ordering::SeqCst) {
       error!("Endpoint already subscribed somehow!");
       return future::ready(Ok(())).boxed();
     }
-    // TODO Should check endpoint validity
+    // Version 1 devices only have a single stream of data, which we report as coming from Tx.
     let mut data_receiver = self.incoming_broadcaster.subscribe();
     let event_sender = self.device_event_sender.clone();
     let address = self.info.address().clone();
@@ -343,8 +614,24 @@ impl HardwareInternal for WebsocketServerHardware {
 
   fn unsubscribe(
     &self,
-    _msg: &HardwareUnsubscribeCmd,
+    msg: &HardwareUnsubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    if self.info.uses_json_protocol() {
+      if self
+        .json_state
+        .subscribed_endpoints
+        .remove(&msg.endpoint())
+        .is_none()
+      {
+        return future::ready(Err(ButtplugDeviceError::DeviceCommunicationError(
+          "Device not subscribed.".to_owned(),
+        )))
+        .boxed();
+      }
+      return self.send_json(WebsocketServerDeviceOutgoingMessage::Unsubscribe {
+        endpoint: msg.endpoint(),
+      });
+    }
     if self.subscribed.load(Ordering::SeqCst) {
       let subscribed = self.subscribed.clone();
       let subscribed_token = self.subscribe_token.clone();
@@ -0,0 +1,103 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Messages for version 2 of the websocket device protocol
+//!
+//! Version 1 devices send an info packet, then exchange raw binary frames with the server, with
+//! everything the device sends treated as a notification.
+//!
+//! Version 2 devices declare the endpoints they have in the info packet, then exchange JSON text
+//! frames, one message per frame, in the same externally tagged format as Buttplug messages:
+//!
+//! ```json
+//! {"identifier": "diy-stroker", "address": "esp32-01", "version": 2, "endpoints": ["tx", "rx"]}
+//! {"Write": {"endpoint": "tx", "data": [1, 2, 3], "write_with_response": false}}
+//! {"Subscribe": {"endpoint": "rx"}}
+//! {"Notification": {"endpoint": "rx", "data": [10, 0]}}
+//! ```
+//!
+//! Devices only send notifications for endpoints the server has subscribed to, and have to answer
+//! `Ping` with `Pong`, or they'll be considered disconnected. They can ping the server the same way.
+//! A device that drops its connection can reconnect with the same address for a short while
+//! afterwards, and picks up where it left off, subscriptions included.
+
+use crate::core::message::Endpoint;
+use serde::{Deserialize, Serialize};
+
+/// Messages sent from the server to version 2 devices.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WebsocketServerDeviceOutgoingMessage {
+  Write {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+    write_with_response: bool,
+  },
+  /// Ask the device for data from an endpoint. The device answers with a `Reading` with the same
+  /// id.
+  Read {
+    id: u32,
+    endpoint: Endpoint,
+    length: u32,
+  },
+  Subscribe {
+    endpoint: Endpoint,
+  },
+  Unsubscribe {
+    endpoint: Endpoint,
+  },
+  Ping {},
+  Pong {},
+}
+
+/// Messages sent from version 2 devices to the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WebsocketServerDeviceIncomingMessage {
+  /// Data from a subscribed endpoint, sent whenever the device has some, like sensor readings.
+  Notification {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+  /// Answer to a `Read`.
+  Reading {
+    id: u32,
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+  Ping {},
+  Pong {},
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_websocket_device_message_format() {
+    assert_eq!(
+      serde_json::to_string(&WebsocketServerDeviceOutgoingMessage::Subscribe {
+        endpoint: Endpoint::Rx
+      })
+      .expect("Test, assuming infallible."),
+      r#"{"Subscribe":{"endpoint":"rx"}}"#
+    );
+    assert_eq!(
+      serde_json::to_string(&WebsocketServerDeviceOutgoingMessage::Ping {})
+        .expect("Test, assuming infallible."),
+      r#"{"Ping":{}}"#
+    );
+    assert_eq!(
+      serde_json::from_str::<WebsocketServerDeviceIncomingMessage>(
+        r#"{"Notification":{"endpoint":"rx","data":[10,0]}}"#
+      )
+      .expect("Test, assuming infallible."),
+      WebsocketServerDeviceIncomingMessage::Notification {
+        endpoint: Endpoint::Rx,
+        data: vec![10, 0]
+      }
+    );
+  }
+}
@@ -106,4 +106,8 @@ impl TimedRetryCommunicationManagerImpl for XInputDeviceCommunicationManager {
   fn can_scan(&self) -> bool {
     true
   }
+
+  fn transport(&self) -> Option<&'static str> {
+    Some("xinput")
+  }
 }
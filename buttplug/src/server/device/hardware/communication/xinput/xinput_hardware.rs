@@ -7,7 +7,10 @@
 
 use super::xinput_device_comm_manager::XInputControllerIndex;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::hardware::communication::HardwareSpecificError,
   server::device::{
     configuration::{ProtocolCommunicationSpecifier, XInputSpecifier},
@@ -55,7 +58,11 @@ async fn check_gamepad_connectivity(
     if handle.get_state(index as u32).is_err() {
       info!("XInput gamepad {} has disconnected.", index);
       // If this fails, we don't care because we're exiting anyways.
-      let _ = sender.send(HardwareEvent::Disconnected(create_address(index)));
+      let _ = sender.send(HardwareEvent::Disconnected(
+        create_address(index),
+        DeviceRemovedReason::LinkLost,
+        false,
+      ));
       return;
     }
     tokio::select! {
@@ -1,21 +1,35 @@
 pub mod communication;
+pub mod recording;
+pub mod waveform;
+#[cfg(test)]
+pub(crate) mod scripted;
 
-use std::{fmt::Debug, sync::Arc, time::Duration};
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
 
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{Endpoint, RawReadCmd, RawReading, RawSubscribeCmd, RawUnsubscribeCmd, RawWriteCmd},
+    message::{
+      DeviceRemovedReason,
+      Endpoint,
+      RawReadCmd,
+      RawReading,
+      RawSubscribeCmd,
+      RawUnsubscribeCmd,
+      RawWriteCmd,
+    },
   },
   server::device::configuration::ProtocolCommunicationSpecifier,
 };
 use async_trait::async_trait;
-use futures::future::BoxFuture;
+use futures::future::{self, BoxFuture};
 use futures_util::FutureExt;
-use getset::{CopyGetters, Getters};
+use getset::{CopyGetters, Getters, Setters};
 use instant::Instant;
+use recording::{HardwareTrafficEvent, HardwareTrafficRecorder};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{broadcast, RwLock};
+use tokio_util::sync::DropGuard;
 
 /// Parameters for reading data from a [Hardware](crate::device::Hardware) endpoint
 ///
@@ -153,7 +167,7 @@ impl From<RawUnsubscribeCmd> for HardwareUnsubscribeCmd {
 
 /// Enumeration of all possible commands that can be sent to a
 /// [Hardware](crate::device::Hardware).
-#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum HardwareCommand {
   Write(HardwareWriteCmd),
   // Read not included here because it needs to be called directly so the response can be handled.
@@ -219,13 +233,43 @@ impl From<HardwareReading> for RawReading {
   }
 }
 
+/// Preferred link parameters to request from a [Hardware](crate::device::Hardware) after
+/// connection.
+///
+/// Mostly relevant to Bluetooth LE, where faster connection intervals lower command latency and
+/// larger MTUs speed up bulk transfers like firmware updates. All values are requests, the
+/// platform and the device may negotiate something different (or ignore them entirely). Any value
+/// left as None is left at whatever the platform chooses.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct HardwareConnectionParameters {
+  /// Requested maximum transmission unit, in bytes.
+  mtu: Option<u16>,
+  /// Minimum acceptable connection interval.
+  min_connection_interval: Option<Duration>,
+  /// Maximum acceptable connection interval.
+  max_connection_interval: Option<Duration>,
+  /// Number of connection events the peripheral may skip.
+  peripheral_latency: Option<u16>,
+  /// Time without communication before the link is considered lost.
+  supervision_timeout: Option<Duration>,
+}
+
+impl HardwareConnectionParameters {
+  /// True if no parameters have been set, meaning there's nothing to request.
+  pub fn is_empty(&self) -> bool {
+    *self == Self::default()
+  }
+}
+
 /// Events that can be emitted from a [Hardware](crate::device::Hardware).
 #[derive(Debug, Clone)]
 pub enum HardwareEvent {
   /// Device received data
   Notification(String, Endpoint, Vec<u8>),
-  /// Device disconnected
-  Disconnected(String),
+  /// Device disconnected. Carries the address, why it disconnected, and whether the comm manager
+  /// will try to connect it again.
+  Disconnected(String, DeviceRemovedReason, bool),
 }
 
 /// Hardware implementation and communication portion of a
@@ -241,12 +285,20 @@ pub struct Hardware {
   address: String,
   /// Communication endpoints
   endpoints: Vec<Endpoint>,
+  /// Endpoints given custom names in the device configuration
+  named_endpoints: HashMap<String, Endpoint>,
   /// Internal implementation details
   internal_impl: Box<dyn HardwareInternal>,
   /// Requires a keepalive signal to be sent by the Server Device class
   #[getset(get_copy = "pub")]
   requires_keepalive: bool,
+  /// Signal strength at connection time, if the transport reports one
+  #[getset(get_copy = "pub")]
+  rssi: Option<i32>,
   last_write_time: Arc<RwLock<Instant>>,
+  /// Records traffic to and from the device, if recording is turned on. Each recorder is paired
+  /// with the guard of the task recording device events for it, so detaching a recorder stops it.
+  traffic_recorders: std::sync::RwLock<Vec<(HardwareTrafficRecorder, DropGuard)>>,
 }
 
 impl Hardware {
@@ -260,9 +312,12 @@ impl Hardware {
       name: name.to_owned(),
       address: address.to_owned(),
       endpoints: endpoints.into(),
+      named_endpoints: HashMap::new(),
       internal_impl,
       requires_keepalive: false,
+      rssi: None,
       last_write_time: Arc::new(RwLock::new(Instant::now())),
+      traffic_recorders: std::sync::RwLock::new(vec![]),
     }
   }
 
@@ -274,6 +329,67 @@ impl Hardware {
     self.requires_keepalive = true;
   }
 
+  pub fn set_rssi(&mut self, rssi: Option<i32>) {
+    self.rssi = rssi;
+  }
+
+  /// Record all traffic to and from this device from here on. Can be called more than once, to
+  /// record to more than one place.
+  pub fn set_traffic_recorder(&self, recorder: HardwareTrafficRecorder) {
+    let event_task = recorder.record_events(self.internal_impl.event_stream());
+    self
+      .traffic_recorders
+      .write()
+      .expect("Lock is never held across a panic")
+      .push((recorder, event_task));
+  }
+
+  /// Stop recording traffic to a recorder attached with [Hardware::set_traffic_recorder]. Clones
+  /// of the recorder count as the same recorder.
+  pub fn detach_traffic_recorder(&self, recorder: &HardwareTrafficRecorder) {
+    self
+      .traffic_recorders
+      .write()
+      .expect("Lock is never held across a panic")
+      .retain(|(attached, _)| !attached.is_same_recorder(recorder));
+  }
+
+  fn traffic_recorders(&self) -> Vec<HardwareTrafficRecorder> {
+    self
+      .traffic_recorders
+      .read()
+      .expect("Lock is never held across a panic")
+      .iter()
+      .map(|(recorder, _)| recorder.clone())
+      .collect()
+  }
+
+  /// Record the traffic for a command once the hardware has handled it, so recordings only have
+  /// traffic that actually went to the device. Events are only built when a recorder is attached,
+  /// so writes aren't copied for nothing.
+  fn record_traffic_after(
+    &self,
+    command: BoxFuture<'static, Result<(), ButtplugDeviceError>>,
+    events: impl FnOnce() -> Vec<HardwareTrafficEvent>,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let recorders = self.traffic_recorders();
+    if recorders.is_empty() {
+      return command;
+    }
+    let events = events();
+    let address = self.address.clone();
+    async move {
+      command.await?;
+      for event in events {
+        for recorder in &recorders {
+          recorder.record(&address, event.clone());
+        }
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
   /// Returns the device name
   pub fn name(&self) -> &str {
     &self.name
@@ -289,6 +405,22 @@ impl Hardware {
     self.endpoints.clone()
   }
 
+  /// Returns the endpoint with the given custom name in the device configuration, if the device has
+  /// it. Lets protocols use names like "txmode2" without adding them to [Endpoint].
+  pub fn named_endpoint(&self, name: &str) -> Option<Endpoint> {
+    self.named_endpoints.get(name).copied()
+  }
+
+  /// Set the custom endpoint names the device configuration has for this device. Names for
+  /// endpoints the device doesn't have are left out.
+  pub fn set_named_endpoints(&mut self, named_endpoints: &HashMap<String, Endpoint>) {
+    self.named_endpoints = named_endpoints
+      .iter()
+      .filter(|(_, endpoint)| self.endpoints.contains(endpoint))
+      .map(|(name, endpoint)| (name.clone(), *endpoint))
+      .collect();
+  }
+
   /// Returns a receiver for any events the device may emit.
   ///
   /// This uses a broadcast channel and can be called multiple times to create multiple streams if
@@ -318,7 +450,27 @@ impl Hardware {
     &self,
     msg: &HardwareReadCmd,
   ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
-    self.internal_impl.read_value(msg)
+    let read_fut = self.internal_impl.read_value(msg);
+    let recorders = self.traffic_recorders();
+    if !recorders.is_empty() {
+      let address = self.address.clone();
+      async move {
+        let reading = read_fut.await?;
+        for recorder in recorders {
+          recorder.record(
+            &address,
+            HardwareTrafficEvent::Read {
+              endpoint: *reading.endpoint(),
+              data: reading.data().clone(),
+            },
+          );
+        }
+        Ok(reading)
+      }
+      .boxed()
+    } else {
+      read_fut
+    }
   }
 
   /// Write a value to the device
@@ -327,16 +479,41 @@ impl Hardware {
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     let write_fut = self.internal_impl.write_value(msg);
-    if self.requires_keepalive {
-      let last_write_time = self.last_write_time.clone();
+    // Tracked for every device, as protocol keepalives are scheduled off of it.
+    let last_write_time = self.last_write_time.clone();
+    self.record_traffic_after(
       async move {
         *last_write_time.write().await = Instant::now();
         write_fut.await
       }
-      .boxed()
-    } else {
-      write_fut
-    }
+      .boxed(),
+      || vec![HardwareTrafficEvent::Write(msg.clone())],
+    )
+  }
+
+  /// Write a batch of values to the device, in order. Unlike writing each value and waiting for it
+  /// to finish before the next, this lets the hardware pipeline the writes. See
+  /// [HardwareInternal::write_values].
+  pub fn write_values(
+    &self,
+    msgs: &[HardwareWriteCmd],
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let write_fut = self.internal_impl.write_values(msgs);
+    let last_write_time = self.last_write_time.clone();
+    self.record_traffic_after(
+      async move {
+        *last_write_time.write().await = Instant::now();
+        write_fut.await
+      }
+      .boxed(),
+      || {
+        msgs
+          .iter()
+          .cloned()
+          .map(HardwareTrafficEvent::Write)
+          .collect()
+      },
+    )
   }
 
   /// Subscribe to a device endpoint, if it exists
@@ -344,7 +521,9 @@ impl Hardware {
     &self,
     msg: &HardwareSubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    self.internal_impl.subscribe(msg)
+    self.record_traffic_after(self.internal_impl.subscribe(msg), || {
+      vec![HardwareTrafficEvent::Subscribe(*msg)]
+    })
   }
 
   /// Unsubscribe from a device endpoint, if it exists
@@ -352,7 +531,18 @@ impl Hardware {
     &self,
     msg: &HardwareUnsubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
-    self.internal_impl.unsubscribe(msg)
+    self.record_traffic_after(self.internal_impl.unsubscribe(msg), || {
+      vec![HardwareTrafficEvent::Unsubscribe(*msg)]
+    })
+  }
+
+  /// Request new connection parameters (MTU, connection interval, etc...) from the device, if the
+  /// underlying platform supports it.
+  pub fn request_connection_parameters(
+    &self,
+    params: &HardwareConnectionParameters,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    self.internal_impl.request_connection_parameters(params)
   }
 }
 
@@ -377,6 +567,23 @@ pub trait HardwareInternal: Sync + Send {
     &self,
     msg: &HardwareWriteCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>>;
+  /// Write a batch of values to the device, in order. Backends that can have several writes in
+  /// flight at once (like Bluetooth LE writes without response) should override this to send the
+  /// batch without waiting on each write. The default implementation builds each write in order,
+  /// then waits on them one at a time.
+  fn write_values(
+    &self,
+    msgs: &[HardwareWriteCmd],
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let writes: Vec<_> = msgs.iter().map(|msg| self.write_value(msg)).collect();
+    async move {
+      for write in writes {
+        write.await?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
   /// Subscribe to a device endpoint, if it exists
   fn subscribe(
     &self,
@@ -387,6 +594,17 @@ pub trait HardwareInternal: Sync + Send {
     &self,
     msg: &HardwareUnsubscribeCmd,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>>;
+  /// Request new connection parameters from the device. Most platforms don't expose this, so the
+  /// default implementation reports it as unsupported.
+  fn request_connection_parameters(
+    &self,
+    _params: &HardwareConnectionParameters,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+      "Hardware does not support setting connection parameters".to_owned(),
+    )))
+    .boxed()
+  }
 }
 
 #[async_trait]
@@ -405,6 +623,10 @@ pub trait HardwareSpecializer: Sync + Send {
   /// [ProtocolDeviceConfiguration](crate::server::device::configuration::ProtocolDeviceConfiguration)
   /// which will contain information about what a protocol needs to communicate with a device, try
   /// to identify all required endpoints on the hardware.
+  ///
+  /// Return [ButtplugDeviceError::DeviceSpecificError] if the hardware itself failed, e.g. a
+  /// connection made during specialization didn't come up. Any other error means the hardware just
+  /// doesn't match the protocol, and the next protocol will be tried.
   async fn specialize(
     &mut self,
     protocol: &[ProtocolCommunicationSpecifier],
@@ -0,0 +1,519 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Recording of hardware traffic, for debugging protocols.
+//!
+//! When a [HardwareTrafficRecorder] is handed to the device manager, every write, read, subscribe
+//! and unsubscribe the hardware has handled, as well as every notification and disconnect coming
+//! back, is written to a JSONL file, one [HardwareTrafficRecord] per line. Recordings can be loaded back in
+//! as a [HardwareTrafficRecording], to be attached to bug reports or replayed against a protocol.
+
+use super::{
+  HardwareCommand,
+  HardwareEvent,
+  HardwareSubscribeCmd,
+  HardwareUnsubscribeCmd,
+  HardwareWriteCmd,
+};
+use crate::{
+  core::{errors::ButtplugDeviceError, message::Endpoint},
+  util::async_manager,
+};
+use futures::FutureExt;
+use getset::{CopyGetters, Getters};
+use instant::Instant;
+use serde::{Deserialize, Serialize};
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::Path,
+  sync::{Arc, Mutex},
+};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// Single piece of traffic between the server and a device.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum HardwareTrafficEvent {
+  Write(HardwareWriteCmd),
+  Subscribe(HardwareSubscribeCmd),
+  Unsubscribe(HardwareUnsubscribeCmd),
+  /// Data returned by a read from the device.
+  Read {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+  /// Data the device sent on its own.
+  Notification {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+  Disconnected,
+}
+
+impl HardwareTrafficEvent {
+  /// True for traffic sent from the server to the device.
+  pub fn is_outbound(&self) -> bool {
+    matches!(
+      self,
+      HardwareTrafficEvent::Write(_)
+        | HardwareTrafficEvent::Subscribe(_)
+        | HardwareTrafficEvent::Unsubscribe(_)
+    )
+  }
+}
+
+impl From<HardwareCommand> for HardwareTrafficEvent {
+  fn from(command: HardwareCommand) -> Self {
+    match command {
+      HardwareCommand::Write(cmd) => HardwareTrafficEvent::Write(cmd),
+      HardwareCommand::Subscribe(cmd) => HardwareTrafficEvent::Subscribe(cmd),
+      HardwareCommand::Unsubscribe(cmd) => HardwareTrafficEvent::Unsubscribe(cmd),
+    }
+  }
+}
+
+/// Line of a hardware traffic recording.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct HardwareTrafficRecord {
+  /// Time since the recorder was created, in milliseconds.
+  #[getset(get_copy = "pub")]
+  timestamp_ms: u64,
+  /// Address of the device the traffic went to or came from.
+  #[getset(get = "pub")]
+  address: String,
+  #[getset(get = "pub")]
+  event: HardwareTrafficEvent,
+}
+
+impl HardwareTrafficRecord {
+  pub fn new(timestamp_ms: u64, address: &str, event: HardwareTrafficEvent) -> Self {
+    Self {
+      timestamp_ms,
+      address: address.to_owned(),
+      event,
+    }
+  }
+}
+
+enum FileWriterMessage {
+  Record(HardwareTrafficRecord),
+  /// Flush the file, then signal that everything before this has been written.
+  Flush(oneshot::Sender<()>),
+}
+
+/// Where a recorder puts its records.
+enum RecorderSink {
+  /// Records are handed to a thread that writes the file, so recording traffic never waits on
+  /// file I/O.
+  File(mpsc::UnboundedSender<FileWriterMessage>),
+  /// Records kept around until taken, after which any further records are dropped.
+  Memory(Mutex<Option<Vec<HardwareTrafficRecord>>>),
+}
+
+/// Writes records to the file until every sender is dropped, flushing whenever it has caught up.
+fn run_file_writer(file: File, mut receiver: mpsc::UnboundedReceiver<FileWriterMessage>) {
+  let mut writer = BufWriter::new(file);
+  while let Some(message) = receiver.blocking_recv() {
+    let mut next = Some(message);
+    while let Some(message) = next {
+      match message {
+        FileWriterMessage::Record(record) => match serde_json::to_string(&record) {
+          Ok(line) => {
+            if let Err(err) = writeln!(writer, "{}", line) {
+              error!("Cannot write hardware traffic record: {:?}", err);
+            }
+          }
+          Err(err) => error!("Cannot serialize hardware traffic record: {:?}", err),
+        },
+        FileWriterMessage::Flush(done) => {
+          if let Err(err) = writer.flush() {
+            error!("Cannot write hardware traffic record: {:?}", err);
+          }
+          let _ = done.send(());
+        }
+      }
+      next = receiver.try_recv().ok();
+    }
+    // Recordings are most useful right after something has gone wrong, so don't leave records
+    // sitting in the buffer.
+    if let Err(err) = writer.flush() {
+      error!("Cannot write hardware traffic record: {:?}", err);
+    }
+  }
+}
+
+/// Writes hardware traffic to a JSONL file, or keeps it in memory. Clones share the same file, so
+/// one recorder can be used for all devices.
+#[derive(Clone)]
+pub struct HardwareTrafficRecorder {
+  start: Instant,
+  sink: Arc<RecorderSink>,
+}
+
+impl HardwareTrafficRecorder {
+  /// Create a recorder, truncating whatever file is at the path.
+  pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, ButtplugDeviceError> {
+    let file = File::create(path.as_ref()).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot create hardware traffic recording file {:?}: {}",
+        path.as_ref(),
+        err
+      ))
+    })?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    std::thread::Builder::new()
+      .name("buttplug-traffic-recorder".to_owned())
+      .spawn(move || run_file_writer(file, receiver))
+      .map_err(|err| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Cannot start hardware traffic recording: {}",
+          err
+        ))
+      })?;
+    Ok(Self {
+      start: Instant::now(),
+      sink: Arc::new(RecorderSink::File(sender)),
+    })
+  }
+
+  /// Create a recorder that keeps traffic in memory until [taken](Self::take_recording).
+  pub fn in_memory() -> Self {
+    Self {
+      start: Instant::now(),
+      sink: Arc::new(RecorderSink::Memory(Mutex::new(Some(vec![])))),
+    }
+  }
+
+  /// Take the traffic recorded in memory so far, and stop recording. Recorders writing to a file
+  /// return an empty recording.
+  pub fn take_recording(&self) -> HardwareTrafficRecording {
+    match &*self.sink {
+      RecorderSink::Memory(records) => HardwareTrafficRecording::new(
+        records
+          .lock()
+          .expect("Recorder lock should never be poisoned")
+          .take()
+          .unwrap_or_default(),
+      ),
+      RecorderSink::File(_) => HardwareTrafficRecording::default(),
+    }
+  }
+
+  pub fn record(&self, address: &str, event: HardwareTrafficEvent) {
+    let record =
+      HardwareTrafficRecord::new(self.start.elapsed().as_millis() as u64, address, event);
+    match &*self.sink {
+      RecorderSink::File(sender) => {
+        if sender.send(FileWriterMessage::Record(record)).is_err() {
+          error!("Hardware traffic recording file writer has stopped, dropping record.");
+        }
+      }
+      RecorderSink::Memory(records) => {
+        if let Some(records) = &mut *records
+          .lock()
+          .expect("Recorder lock should never be poisoned")
+        {
+          records.push(record);
+        }
+      }
+    }
+  }
+
+  /// Wait until everything recorded so far has been written to the recording file. Returns right
+  /// away for in memory recorders.
+  pub async fn flush(&self) {
+    if let RecorderSink::File(sender) = &*self.sink {
+      let (done_sender, done_receiver) = oneshot::channel();
+      if sender.send(FileWriterMessage::Flush(done_sender)).is_ok() {
+        let _ = done_receiver.await;
+      }
+    }
+  }
+
+  /// True if both recorders are clones of the same recorder.
+  pub(super) fn is_same_recorder(&self, other: &HardwareTrafficRecorder) -> bool {
+    Arc::ptr_eq(&self.sink, &other.sink)
+  }
+
+  /// Record notifications and disconnects coming from a device, until its event stream closes or
+  /// the returned guard is dropped.
+  pub(super) fn record_events(
+    &self,
+    mut event_stream: broadcast::Receiver<HardwareEvent>,
+  ) -> DropGuard {
+    let recorder = self.clone();
+    let token = CancellationToken::new();
+    let child_token = token.child_token();
+    async_manager::spawn(async move {
+      loop {
+        let event = select! {
+          _ = child_token.cancelled().fuse() => return,
+          event = event_stream.recv().fuse() => event,
+        };
+        match event {
+          Ok(HardwareEvent::Notification(address, endpoint, data)) => recorder.record(
+            &address,
+            HardwareTrafficEvent::Notification { endpoint, data },
+          ),
+          Ok(HardwareEvent::Disconnected(address, ..)) => {
+            recorder.record(&address, HardwareTrafficEvent::Disconnected);
+            return;
+          }
+          Err(broadcast::error::RecvError::Lagged(count)) => {
+            warn!("Hardware traffic recorder missed {} device events.", count);
+          }
+          Err(broadcast::error::RecvError::Closed) => return,
+        }
+      }
+    });
+    token.drop_guard()
+  }
+}
+
+/// Hardware traffic loaded from a recording.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Getters)]
+#[getset(get = "pub")]
+pub struct HardwareTrafficRecording {
+  records: Vec<HardwareTrafficRecord>,
+}
+
+impl HardwareTrafficRecording {
+  pub fn new(records: Vec<HardwareTrafficRecord>) -> Self {
+    Self { records }
+  }
+
+  /// Parse a recording from JSONL text. Blank lines are skipped.
+  pub fn from_jsonl(jsonl: &str) -> Result<Self, ButtplugDeviceError> {
+    let records = jsonl
+      .lines()
+      .enumerate()
+      .filter(|(_, line)| !line.trim().is_empty())
+      .map(|(line_number, line)| {
+        serde_json::from_str(line).map_err(|err| {
+          ButtplugDeviceError::DeviceConfigurationError(format!(
+            "Cannot parse hardware traffic record on line {}: {}",
+            line_number + 1,
+            err
+          ))
+        })
+      })
+      .collect::<Result<Vec<HardwareTrafficRecord>, ButtplugDeviceError>>()?;
+    Ok(Self { records })
+  }
+
+  /// Load a recording from a JSONL file.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ButtplugDeviceError> {
+    let jsonl = std::fs::read_to_string(path.as_ref()).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot read hardware traffic recording {:?}: {}",
+        path.as_ref(),
+        err
+      ))
+    })?;
+    Self::from_jsonl(&jsonl)
+  }
+
+  /// Only the traffic for a single device.
+  pub fn for_address(&self, address: &str) -> Self {
+    Self {
+      records: self
+        .records
+        .iter()
+        .filter(|record| record.address == address)
+        .cloned()
+        .collect(),
+    }
+  }
+
+  /// Everything the server sent to the device, in order. Replaying a recording against a protocol
+  /// should produce the same commands.
+  pub fn outbound_commands(&self) -> Vec<HardwareCommand> {
+    self
+      .records
+      .iter()
+      .filter_map(|record| match &record.event {
+        HardwareTrafficEvent::Write(cmd) => Some(HardwareCommand::Write(cmd.clone())),
+        HardwareTrafficEvent::Subscribe(cmd) => Some(HardwareCommand::Subscribe(*cmd)),
+        HardwareTrafficEvent::Unsubscribe(cmd) => Some(HardwareCommand::Unsubscribe(*cmd)),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Every endpoint that shows up in the recording.
+  pub fn endpoints(&self) -> Vec<Endpoint> {
+    let mut endpoints = vec![];
+    for record in &self.records {
+      let endpoint = match &record.event {
+        HardwareTrafficEvent::Write(cmd) => cmd.endpoint(),
+        HardwareTrafficEvent::Subscribe(cmd) => cmd.endpoint(),
+        HardwareTrafficEvent::Unsubscribe(cmd) => cmd.endpoint(),
+        HardwareTrafficEvent::Read { endpoint, .. }
+        | HardwareTrafficEvent::Notification { endpoint, .. } => *endpoint,
+        HardwareTrafficEvent::Disconnected => continue,
+      };
+      if !endpoints.contains(&endpoint) {
+        endpoints.push(endpoint);
+      }
+    }
+    endpoints
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{
+    HardwareTrafficEvent,
+    HardwareTrafficRecord,
+    HardwareTrafficRecorder,
+    HardwareTrafficRecording,
+  };
+  use crate::{
+    core::message::Endpoint,
+    server::device::hardware::{
+      scripted::HardwareScript,
+      HardwareCommand,
+      HardwareSubscribeCmd,
+      HardwareWriteCmd,
+    },
+  };
+
+  #[test]
+  pub fn test_recording_round_trip() {
+    let records = vec![
+      HardwareTrafficRecord::new(
+        0,
+        "device-1",
+        HardwareTrafficEvent::Subscribe(HardwareSubscribeCmd::new(Endpoint::Rx)),
+      ),
+      HardwareTrafficRecord::new(
+        5,
+        "device-1",
+        HardwareTrafficEvent::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![1, 2], false)),
+      ),
+      HardwareTrafficRecord::new(
+        9,
+        "device-2",
+        HardwareTrafficEvent::Notification {
+          endpoint: Endpoint::Rx,
+          data: vec![3],
+        },
+      ),
+    ];
+    let jsonl = records
+      .iter()
+      .map(|record| serde_json::to_string(record).expect("Test, assuming infallible"))
+      .collect::<Vec<String>>()
+      .join("\n");
+    let recording =
+      HardwareTrafficRecording::from_jsonl(&jsonl).expect("Test, assuming infallible");
+    assert_eq!(recording, HardwareTrafficRecording::new(records));
+    assert_eq!(recording.for_address("device-1").records().len(), 2);
+    assert_eq!(recording.endpoints(), vec![Endpoint::Rx, Endpoint::Tx]);
+    assert_eq!(
+      recording.outbound_commands(),
+      vec![
+        HardwareCommand::Subscribe(HardwareSubscribeCmd::new(Endpoint::Rx)),
+        HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![1, 2], false)),
+      ]
+    );
+    assert!(HardwareTrafficRecording::from_jsonl("{\"not\": \"a record\"}").is_err());
+  }
+
+  #[test]
+  pub fn test_in_memory_recorder() {
+    let recorder = HardwareTrafficRecorder::in_memory();
+    recorder.record("device-1", HardwareTrafficEvent::Disconnected);
+    let recording = recorder.take_recording();
+    assert_eq!(recording.records().len(), 1);
+    assert_eq!(
+      *recording.records()[0].event(),
+      HardwareTrafficEvent::Disconnected
+    );
+    // Nothing is kept once the recording has been taken.
+    recorder.record("device-1", HardwareTrafficEvent::Disconnected);
+    assert!(recorder.take_recording().records().is_empty());
+  }
+
+  #[tokio::test]
+  pub async fn test_file_recorder() {
+    let path = std::env::temp_dir().join(format!(
+      "buttplug-traffic-recorder-{}.jsonl",
+      std::process::id()
+    ));
+    let recorder = HardwareTrafficRecorder::new(&path).expect("Test, assuming infallible");
+    let write = HardwareWriteCmd::new(Endpoint::Tx, vec![1], false);
+    recorder.record("device-1", HardwareTrafficEvent::Write(write.clone()));
+    recorder.record("device-1", HardwareTrafficEvent::Disconnected);
+    recorder.flush().await;
+    let recording = HardwareTrafficRecording::load(&path).expect("Test, assuming infallible");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(
+      recording
+        .records()
+        .iter()
+        .map(|record| record.event().clone())
+        .collect::<Vec<_>>(),
+      vec![
+        HardwareTrafficEvent::Write(write),
+        HardwareTrafficEvent::Disconnected
+      ]
+    );
+    // File recordings aren't kept in memory.
+    assert!(recorder.take_recording().records().is_empty());
+  }
+
+  #[tokio::test]
+  pub async fn test_failed_writes_not_recorded() {
+    let mut script = HardwareScript::default();
+    script.expect_write(Endpoint::Tx, &[1]);
+    let hardware = script.build_hardware("Test Device", "device-1");
+    let recorder = HardwareTrafficRecorder::in_memory();
+    hardware.set_traffic_recorder(recorder.clone());
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![1], false))
+      .await
+      .expect("Test, assuming infallible");
+    // Past the end of the script, so the write fails.
+    assert!(hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![2], false))
+      .await
+      .is_err());
+    assert_eq!(
+      recorder.take_recording().outbound_commands(),
+      vec![HardwareCommand::Write(HardwareWriteCmd::new(
+        Endpoint::Tx,
+        vec![1],
+        false
+      ))]
+    );
+  }
+
+  #[tokio::test]
+  pub async fn test_detached_recorder_stops_recording() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_write(Endpoint::Tx, &[1])
+      .expect_write(Endpoint::Tx, &[2]);
+    let hardware = script.build_hardware("Test Device", "device-1");
+    let recorder = HardwareTrafficRecorder::in_memory();
+    hardware.set_traffic_recorder(recorder.clone());
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![1], false))
+      .await
+      .expect("Test, assuming infallible");
+    hardware.detach_traffic_recorder(&recorder);
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![2], false))
+      .await
+      .expect("Test, assuming infallible");
+    let recording = recorder.take_recording();
+    assert_eq!(recording.outbound_commands().len(), 1);
+    script.finish();
+  }
+}
@@ -0,0 +1,456 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Scripted hardware for protocol unit tests
+//!
+//! Tests list the traffic they expect a protocol to send, in order, along with what the device
+//! answers with, e.g. "expect `DeviceType;` written to tx, then notify `C:11:0082059AD3BD;` on rx".
+//! Anything the protocol does that isn't next in the script fails, and is reported again by
+//! [HardwareScript::finish], in case the protocol swallowed the error.
+//!
+//! [HardwareScript::build_hardware] wraps a script in a [Hardware], for running identifiers,
+//! initializers and handlers against. Hardware that sits in front of something other than a
+//! protocol, like the Lovense dongle, can check traffic against a script directly instead.
+
+use super::{
+  Hardware,
+  HardwareCommand,
+  HardwareEvent,
+  HardwareInternal,
+  HardwareReadCmd,
+  HardwareReading,
+  HardwareSubscribeCmd,
+  HardwareUnsubscribeCmd,
+  HardwareWriteCmd,
+};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
+  server::device::{
+    configuration::{
+      DeviceConfigurationManager,
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerDeviceMessageAttributes,
+    },
+    protocol::{ProtocolHandler, ProtocolIdentifierFactory},
+    server_device::resolve_variant,
+    ServerDeviceIdentifier,
+  },
+  util::device_configuration::load_protocol_configs,
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+
+#[derive(Debug)]
+enum ScriptStep {
+  Write {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+    replies: Vec<(Endpoint, Vec<u8>)>,
+  },
+  Read {
+    endpoint: Endpoint,
+    data: Vec<u8>,
+  },
+  Subscribe {
+    endpoint: Endpoint,
+    replies: Vec<(Endpoint, Vec<u8>)>,
+  },
+  Unsubscribe(Endpoint),
+}
+
+impl ScriptStep {
+  fn endpoint(&self) -> Endpoint {
+    match self {
+      ScriptStep::Write { endpoint, .. }
+      | ScriptStep::Read { endpoint, .. }
+      | ScriptStep::Subscribe { endpoint, .. } => *endpoint,
+      ScriptStep::Unsubscribe(endpoint) => *endpoint,
+    }
+  }
+}
+
+/// Expected hardware traffic, and the device's replies to it. Clones share the same script.
+#[derive(Default, Clone)]
+pub(crate) struct HardwareScript {
+  steps: Arc<Mutex<VecDeque<ScriptStep>>>,
+  failures: Arc<Mutex<Vec<String>>>,
+}
+
+impl HardwareScript {
+  pub fn expect_write(&mut self, endpoint: Endpoint, data: &[u8]) -> &mut Self {
+    self.push(ScriptStep::Write {
+      endpoint,
+      data: data.to_vec(),
+      replies: vec![],
+    })
+  }
+
+  /// Send a notification once the last expected write or subscribe happens. Can be called more
+  /// than once to send several notifications.
+  pub fn reply(&mut self, endpoint: Endpoint, data: &[u8]) -> &mut Self {
+    match self.lock_steps().back_mut() {
+      Some(ScriptStep::Write { replies, .. }) | Some(ScriptStep::Subscribe { replies, .. }) => {
+        replies.push((endpoint, data.to_vec()))
+      }
+      _ => panic!("Replies have to follow an expected write or subscribe"),
+    }
+    self
+  }
+
+  /// Expect a read, which will return the data.
+  pub fn expect_read(&mut self, endpoint: Endpoint, data: &[u8]) -> &mut Self {
+    self.push(ScriptStep::Read {
+      endpoint,
+      data: data.to_vec(),
+    })
+  }
+
+  pub fn expect_subscribe(&mut self, endpoint: Endpoint) -> &mut Self {
+    self.push(ScriptStep::Subscribe {
+      endpoint,
+      replies: vec![],
+    })
+  }
+
+  pub fn expect_unsubscribe(&mut self, endpoint: Endpoint) -> &mut Self {
+    self.push(ScriptStep::Unsubscribe(endpoint))
+  }
+
+  /// Create hardware that checks traffic against the script. Endpoints are the ones mentioned in
+  /// the script so far.
+  pub fn build_hardware(&self, name: &str, address: &str) -> Arc<Hardware> {
+    let mut endpoints = vec![];
+    for step in self.lock_steps().iter() {
+      let endpoint = step.endpoint();
+      if !endpoints.contains(&endpoint) {
+        endpoints.push(endpoint);
+      }
+      if let ScriptStep::Write { replies, .. } | ScriptStep::Subscribe { replies, .. } = step {
+        for (endpoint, _) in replies {
+          if !endpoints.contains(endpoint) {
+            endpoints.push(*endpoint);
+          }
+        }
+      }
+    }
+    Arc::new(Hardware::new(
+      name,
+      address,
+      &endpoints,
+      Box::new(ScriptedHardware {
+        address: address.to_owned(),
+        script: self.clone(),
+        event_sender: broadcast::channel(256).0,
+      }),
+    ))
+  }
+
+  /// Check a write against the script, returning the notifications to send for it.
+  pub fn write(
+    &self,
+    endpoint: Endpoint,
+    data: &[u8],
+  ) -> Result<Vec<(Endpoint, Vec<u8>)>, ButtplugDeviceError> {
+    match self.next_step(&format!("write {:?} to {}", data, endpoint))? {
+      ScriptStep::Write {
+        endpoint: expected_endpoint,
+        data: expected_data,
+        replies,
+      } if expected_endpoint == endpoint && expected_data == data => Ok(replies),
+      step => self.fail(format!(
+        "Got write {:?} to {}, expected {:?}",
+        data, endpoint, step
+      )),
+    }
+  }
+
+  /// Check a read against the script, returning the data read.
+  pub fn read(&self, endpoint: Endpoint) -> Result<Vec<u8>, ButtplugDeviceError> {
+    match self.next_step(&format!("read from {}", endpoint))? {
+      ScriptStep::Read {
+        endpoint: expected_endpoint,
+        data,
+      } if expected_endpoint == endpoint => Ok(data),
+      step => self.fail(format!("Got read from {}, expected {:?}", endpoint, step)),
+    }
+  }
+
+  /// Check a subscribe against the script, returning the notifications to send for it.
+  pub fn subscribe(
+    &self,
+    endpoint: Endpoint,
+  ) -> Result<Vec<(Endpoint, Vec<u8>)>, ButtplugDeviceError> {
+    match self.next_step(&format!("subscribe to {}", endpoint))? {
+      ScriptStep::Subscribe {
+        endpoint: expected_endpoint,
+        replies,
+      } if expected_endpoint == endpoint => Ok(replies),
+      step => self.fail(format!(
+        "Got subscribe to {}, expected {:?}",
+        endpoint, step
+      )),
+    }
+  }
+
+  pub fn unsubscribe(&self, endpoint: Endpoint) -> Result<(), ButtplugDeviceError> {
+    match self.next_step(&format!("unsubscribe from {}", endpoint))? {
+      ScriptStep::Unsubscribe(expected_endpoint) if expected_endpoint == endpoint => Ok(()),
+      step => self.fail(format!(
+        "Got unsubscribe from {}, expected {:?}",
+        endpoint, step
+      )),
+    }
+  }
+
+  /// Panic if anything unexpected happened, or if there are steps left over.
+  pub fn finish(&self) {
+    let failures = self
+      .failures
+      .lock()
+      .expect("Lock is never held across a panic");
+    assert!(failures.is_empty(), "Unexpected traffic: {:?}", failures);
+    let steps = self.lock_steps();
+    assert!(
+      steps.is_empty(),
+      "Expected traffic never happened: {:?}",
+      steps
+    );
+  }
+
+  fn push(&mut self, step: ScriptStep) -> &mut Self {
+    self.lock_steps().push_back(step);
+    self
+  }
+
+  fn lock_steps(&self) -> std::sync::MutexGuard<'_, VecDeque<ScriptStep>> {
+    self
+      .steps
+      .lock()
+      .expect("Lock is never held across a panic")
+  }
+
+  fn next_step(&self, operation: &str) -> Result<ScriptStep, ButtplugDeviceError> {
+    let step = self.lock_steps().pop_front();
+    match step {
+      Some(step) => Ok(step),
+      None => self.fail(format!("Got {} after the end of the script", operation)),
+    }
+  }
+
+  fn fail<T>(&self, failure: String) -> Result<T, ButtplugDeviceError> {
+    self
+      .failures
+      .lock()
+      .expect("Lock is never held across a panic")
+      .push(failure.clone());
+    Err(ButtplugDeviceError::DeviceCommunicationError(failure))
+  }
+}
+
+struct ScriptedHardware {
+  address: String,
+  script: HardwareScript,
+  event_sender: broadcast::Sender<HardwareEvent>,
+}
+
+impl ScriptedHardware {
+  fn notify(&self, replies: Vec<(Endpoint, Vec<u8>)>) {
+    // Protocols listen for replies before writing or subscribing, so they can be sent right away.
+    for (endpoint, data) in replies {
+      let _ = self.event_sender.send(HardwareEvent::Notification(
+        self.address.clone(),
+        endpoint,
+        data,
+      ));
+    }
+  }
+}
+
+impl HardwareInternal for ScriptedHardware {
+  fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+    self.event_sender.subscribe()
+  }
+
+  fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    // Ignore send errors, they only mean nobody is listening.
+    let _ = self.event_sender.send(HardwareEvent::Disconnected(
+      self.address.clone(),
+      DeviceRemovedReason::UserDisconnect,
+      false,
+    ));
+    future::ready(Ok(())).boxed()
+  }
+
+  fn read_value(
+    &self,
+    msg: &HardwareReadCmd,
+  ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+    let result = self
+      .script
+      .read(msg.endpoint())
+      .map(|data| HardwareReading::new(msg.endpoint(), &data));
+    future::ready(result).boxed()
+  }
+
+  fn write_value(
+    &self,
+    msg: &HardwareWriteCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let result = self
+      .script
+      .write(msg.endpoint(), msg.data())
+      .map(|replies| self.notify(replies));
+    future::ready(result).boxed()
+  }
+
+  fn subscribe(
+    &self,
+    msg: &HardwareSubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    let result = self
+      .script
+      .subscribe(msg.endpoint())
+      .map(|replies| self.notify(replies));
+    future::ready(result).boxed()
+  }
+
+  fn unsubscribe(
+    &self,
+    msg: &HardwareUnsubscribeCmd,
+  ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+    future::ready(self.script.unsubscribe(msg.endpoint())).boxed()
+  }
+}
+
+/// Identify and initialize a protocol on the hardware, using attributes from the built in device
+/// configuration.
+pub(crate) async fn setup_protocol(
+  factory: &dyn ProtocolIdentifierFactory,
+  hardware: Arc<Hardware>,
+) -> Result<(ServerDeviceIdentifier, Arc<dyn ProtocolHandler>), ButtplugDeviceError> {
+  let config_manager: DeviceConfigurationManager =
+    load_protocol_configs(None, None, false)?.finish()?;
+  let (identifier, mut initializer) = factory.create().identify(hardware.clone()).await?;
+  let identifier = resolve_variant(
+    &config_manager,
+    identifier,
+    initializer.as_mut(),
+    hardware.clone(),
+  )
+  .await?;
+  let attributes = config_manager
+    .protocol_device_attributes(&identifier, &[])
+    .unwrap_or_else(|| {
+      ProtocolDeviceAttributes::new(
+        ProtocolAttributesType::Default,
+        None,
+        None,
+        ServerDeviceMessageAttributes::default(),
+        None,
+      )
+    });
+  let handler = initializer.initialize(hardware, &attributes).await?;
+  Ok((identifier, handler))
+}
+
+/// Send handler output to the hardware, as the device would.
+pub(crate) async fn send_commands(
+  hardware: &Hardware,
+  commands: Result<Vec<HardwareCommand>, ButtplugDeviceError>,
+) -> Result<(), ButtplugDeviceError> {
+  for command in commands? {
+    hardware.parse_message(&command).await?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::HardwareScript;
+  use crate::{
+    core::message::Endpoint,
+    server::device::hardware::{
+      HardwareReadCmd,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+  };
+
+  #[tokio::test]
+  async fn test_scripted_reads() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_write(Endpoint::Tx, b"Battery;")
+      .expect_read(Endpoint::Rx, b"85;");
+    let hardware = script.build_hardware("Test", "scripted-test");
+    hardware
+      .write_value(&HardwareWriteCmd::new(
+        Endpoint::Tx,
+        b"Battery;".to_vec(),
+        false,
+      ))
+      .await
+      .expect("Test, assuming infallible.");
+    let reading = hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(reading.data(), b"85;");
+    script.finish();
+  }
+
+  #[tokio::test]
+  #[should_panic(expected = "Unexpected traffic")]
+  async fn test_unexpected_read() {
+    let mut script = HardwareScript::default();
+    script.expect_read(Endpoint::Rx, b"85;");
+    let hardware = script.build_hardware("Test", "scripted-test");
+    // Reads from the wrong endpoint fail, and the script remembers it.
+    assert!(hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Tx, 0, 0))
+      .await
+      .is_err());
+    script.finish();
+  }
+
+  #[tokio::test]
+  #[should_panic(expected = "Unexpected traffic")]
+  async fn test_unexpected_traffic() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_subscribe(Endpoint::Rx)
+      .expect_unsubscribe(Endpoint::Rx)
+      .expect_write(Endpoint::Tx, b"Vibrate:1;");
+    let hardware = script.build_hardware("Test", "scripted-test");
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+      .await
+      .expect("Test, assuming infallible.");
+    hardware
+      .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::Rx))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(hardware
+      .write_value(&HardwareWriteCmd::new(
+        Endpoint::Tx,
+        b"Vibrate:2;".to_vec(),
+        false
+      ))
+      .await
+      .is_err());
+    script.finish();
+  }
+}
@@ -0,0 +1,230 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Haptic waveform synthesis, for hardware that is driven by a signal instead of motor speeds.
+//!
+//! Some hardware, like audio transducers or force feedback devices with periodic effects, can
+//! change the frequency of its vibration as well as the amplitude. These are exposed as a pair of
+//! scalar actuators: a `Vibrate` actuator for the amplitude, and a `Frequency` actuator whose step
+//! range is the frequency range of the hardware in Hz. Protocols gather the pair into a
+//! [HapticWaveform], and backends that need to render samples themselves can use a
+//! [WaveformSynth] to turn it into a sine wave, or a [WaveformMixer] to play several of them on a
+//! multichannel output.
+
+use getset::CopyGetters;
+use std::f64::consts::TAU;
+
+/// Amplitude and frequency of a vibration.
+#[derive(Debug, Clone, Copy, PartialEq, Default, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct HapticWaveform {
+  /// Amplitude, 0.0-1.0.
+  amplitude: f64,
+  /// Frequency, in Hz.
+  frequency: f64,
+}
+
+impl HapticWaveform {
+  pub fn new(amplitude: f64, frequency: f64) -> Self {
+    Self {
+      amplitude: amplitude.clamp(0.0, 1.0),
+      frequency: frequency.max(0.0),
+    }
+  }
+
+  /// True if the waveform shouldn't move the hardware at all.
+  pub fn is_silent(&self) -> bool {
+    self.amplitude == 0.0 || self.frequency == 0.0
+  }
+
+  /// Length of a single period of the wave in milliseconds, or None for a silent waveform.
+  pub fn period_ms(&self) -> Option<u16> {
+    if self.frequency == 0.0 {
+      None
+    } else {
+      Some((1000.0 / self.frequency).round().clamp(1.0, u16::MAX as f64) as u16)
+    }
+  }
+}
+
+/// Renders a [HapticWaveform] as a sine wave of f32 samples.
+///
+/// Phase carries over between calls to [render](WaveformSynth::render), and amplitude changes are
+/// ramped over the next rendered block, so updating the waveform while it plays doesn't click.
+#[derive(Debug, Clone, CopyGetters)]
+pub struct WaveformSynth {
+  #[getset(get_copy = "pub")]
+  sample_rate: u32,
+  #[getset(get_copy = "pub")]
+  waveform: HapticWaveform,
+  /// Amplitude at the end of the last rendered block.
+  current_amplitude: f64,
+  /// Position in the current period, 0.0-1.0.
+  phase: f64,
+}
+
+impl WaveformSynth {
+  pub fn new(sample_rate: u32) -> Self {
+    Self {
+      sample_rate,
+      waveform: HapticWaveform::default(),
+      current_amplitude: 0.0,
+      phase: 0.0,
+    }
+  }
+
+  /// Set the waveform to render from the next block on.
+  pub fn set_waveform(&mut self, waveform: HapticWaveform) {
+    self.waveform = waveform;
+  }
+
+  /// Fill a mono buffer with the next block of samples.
+  pub fn render(&mut self, buffer: &mut [f32]) {
+    if buffer.is_empty() {
+      return;
+    }
+    let start_amplitude = self.current_amplitude;
+    let target_amplitude = if self.waveform.frequency() == 0.0 {
+      0.0
+    } else {
+      self.waveform.amplitude()
+    };
+    let amplitude_step = (target_amplitude - start_amplitude) / buffer.len() as f64;
+    let phase_step = self.waveform.frequency() / self.sample_rate as f64;
+    for (i, sample) in buffer.iter_mut().enumerate() {
+      let amplitude = start_amplitude + amplitude_step * (i + 1) as f64;
+      *sample = (amplitude * (self.phase * TAU).sin()) as f32;
+      self.phase = (self.phase + phase_step).fract();
+    }
+    self.current_amplitude = target_amplitude;
+  }
+}
+
+/// Mixes the waveforms of several actuators into an interleaved multichannel buffer.
+///
+/// Each actuator plays on one output channel, or on all of them if it isn't routed anywhere.
+/// Actuators sharing a channel are summed, and the result is clipped to -1.0-1.0.
+#[derive(Debug, Clone)]
+pub struct WaveformMixer {
+  sample_rate: u32,
+  channels: u16,
+  synths: Vec<WaveformSynth>,
+  routes: Vec<Option<u16>>,
+  scratch: Vec<f32>,
+}
+
+impl WaveformMixer {
+  pub fn new(sample_rate: u32, channels: u16) -> Self {
+    Self {
+      sample_rate,
+      channels: channels.max(1),
+      synths: vec![],
+      routes: vec![],
+      scratch: vec![],
+    }
+  }
+
+  fn ensure_actuator(&mut self, index: usize) {
+    if index >= self.synths.len() {
+      self
+        .synths
+        .resize(index + 1, WaveformSynth::new(self.sample_rate));
+      self.routes.resize(index + 1, None);
+    }
+  }
+
+  /// Play the actuator at index on a single output channel, or all channels if None. Channels the
+  /// output doesn't have are ignored.
+  pub fn set_route(&mut self, index: usize, channel: Option<u16>) {
+    self.ensure_actuator(index);
+    self.routes[index] = channel;
+  }
+
+  pub fn set_waveform(&mut self, index: usize, waveform: HapticWaveform) {
+    self.ensure_actuator(index);
+    self.synths[index].set_waveform(waveform);
+  }
+
+  /// Fill an interleaved buffer with the next block of samples.
+  pub fn render(&mut self, buffer: &mut [f32]) {
+    buffer.fill(0.0);
+    let channels = self.channels as usize;
+    let frames = buffer.len() / channels;
+    self.scratch.resize(frames, 0.0);
+    for (synth, route) in self.synths.iter_mut().zip(self.routes.iter()) {
+      synth.render(&mut self.scratch);
+      for (frame, sample) in buffer.chunks_exact_mut(channels).zip(self.scratch.iter()) {
+        match route {
+          Some(channel) => {
+            if let Some(out) = frame.get_mut(*channel as usize) {
+              *out += sample;
+            }
+          }
+          None => frame.iter_mut().for_each(|out| *out += sample),
+        }
+      }
+    }
+    buffer
+      .iter_mut()
+      .for_each(|sample| *sample = sample.clamp(-1.0, 1.0));
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{HapticWaveform, WaveformMixer, WaveformSynth};
+
+  #[test]
+  fn test_waveform_period() {
+    assert_eq!(HapticWaveform::new(1.0, 100.0).period_ms(), Some(10));
+    assert_eq!(HapticWaveform::new(1.0, 0.0).period_ms(), None);
+    assert!(HapticWaveform::new(0.0, 50.0).is_silent());
+    assert_eq!(HapticWaveform::new(2.0, 50.0).amplitude(), 1.0);
+  }
+
+  #[test]
+  fn test_waveform_synth_render() {
+    let mut synth = WaveformSynth::new(1000);
+    let mut buffer = [1.0f32; 100];
+    synth.render(&mut buffer);
+    assert!(buffer.iter().all(|sample| *sample == 0.0));
+
+    // 250Hz at 1000 samples/s is a quarter period per sample.
+    synth.set_waveform(HapticWaveform::new(1.0, 250.0));
+    synth.render(&mut buffer);
+    // Amplitude ramps up over the first block.
+    assert!(buffer[1].abs() < buffer[97].abs());
+    synth.render(&mut buffer);
+    assert!(buffer[0].abs() < 1e-5);
+    assert!((buffer[1] - 1.0).abs() < 1e-5);
+    assert!(buffer[2].abs() < 1e-5);
+    assert!((buffer[3] + 1.0).abs() < 1e-5);
+    assert!(buffer.iter().all(|sample| sample.abs() <= 1.0));
+  }
+
+  #[test]
+  fn test_waveform_mixer_routing() {
+    let mut mixer = WaveformMixer::new(1000, 2);
+    mixer.set_route(0, Some(1));
+    mixer.set_waveform(0, HapticWaveform::new(1.0, 250.0));
+    let mut buffer = [0.0f32; 200];
+    // First block ramps in, second plays at full amplitude.
+    mixer.render(&mut buffer);
+    mixer.render(&mut buffer);
+    // Frame 1 is a quarter period in, at the peak of the wave.
+    assert_eq!(buffer[2], 0.0);
+    assert!((buffer[3] - 1.0).abs() < 1e-5);
+    assert!(buffer.chunks_exact(2).all(|frame| frame[0] == 0.0));
+
+    // Unrouted actuators play everywhere, and sums are clipped.
+    mixer.set_waveform(1, HapticWaveform::new(1.0, 250.0));
+    mixer.render(&mut buffer);
+    mixer.render(&mut buffer);
+    assert!((buffer[2] - 1.0).abs() < 1e-5);
+    assert!((buffer[3] - 1.0).abs() < 1e-5);
+  }
+}
@@ -0,0 +1,100 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-device idle tracking for low power mode
+//!
+//! Devices whose protocols [support low power
+//! mode](super::protocol::ProtocolHandler::supports_low_power), and that users gave an idle timeout
+//! in their configuration, keep track of when they were last sent a command. Once that's been longer than the idle timeout, the
+//! device is put into low power mode, and stays there until the next command wakes it up. Both
+//! happen while holding the device's turn in the command queue, so a wake can't be skipped by a
+//! command that races the device going into low power.
+
+use instant::Instant;
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+  time::Duration,
+};
+
+pub(super) struct DeviceIdleMonitor {
+  idle_timeout: Duration,
+  last_activity: Mutex<Instant>,
+  low_power: AtomicBool,
+}
+
+impl DeviceIdleMonitor {
+  pub(super) fn new(idle_timeout: Duration) -> Self {
+    Self {
+      idle_timeout,
+      last_activity: Mutex::new(Instant::now()),
+      low_power: AtomicBool::new(false),
+    }
+  }
+
+  pub(super) fn idle_timeout(&self) -> Duration {
+    self.idle_timeout
+  }
+
+  /// Restart the idle timeout. Called whenever the device is sent a command.
+  pub(super) fn record_activity(&self) {
+    *self
+      .last_activity
+      .lock()
+      .expect("Lock is never held across a panic") = Instant::now();
+  }
+
+  /// Time left until the device counts as idle, or None if it already is.
+  pub(super) fn time_until_idle(&self) -> Option<Duration> {
+    let idle_for = self
+      .last_activity
+      .lock()
+      .expect("Lock is never held across a panic")
+      .elapsed();
+    self
+      .idle_timeout
+      .checked_sub(idle_for)
+      .filter(|remaining| !remaining.is_zero())
+  }
+
+  pub(super) fn is_low_power(&self) -> bool {
+    self.low_power.load(Ordering::SeqCst)
+  }
+
+  pub(super) fn set_low_power(&self) {
+    self.low_power.store(true, Ordering::SeqCst);
+  }
+
+  /// Leave low power mode. Returns true if the device was in it, meaning it needs to be woken up
+  /// before anything else is written.
+  pub(super) fn take_low_power(&self) -> bool {
+    self.low_power.swap(false, Ordering::SeqCst)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_idle_monitor() {
+    let monitor = DeviceIdleMonitor::new(Duration::from_millis(50));
+    assert!(monitor.time_until_idle().is_some());
+    std::thread::sleep(Duration::from_millis(60));
+    assert!(monitor.time_until_idle().is_none());
+    monitor.record_activity();
+    assert!(monitor.time_until_idle().is_some());
+
+    assert!(!monitor.take_low_power());
+    monitor.set_low_power();
+    assert!(monitor.is_low_power());
+    assert!(monitor.take_low_power());
+    assert!(!monitor.is_low_power());
+  }
+}
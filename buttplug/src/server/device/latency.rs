@@ -0,0 +1,178 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-device command latency tracking
+//!
+//! When a user says "my toy lags", the delay could be anywhere between their app and the motor. To
+//! narrow that down, the server times each output command a device receives in stages:
+//!
+//! - Protocol: Turning the message into hardware commands in the protocol handler.
+//! - Queue: Waiting between the hardware commands being built and the first write starting.
+//! - Hardware: Writing to the hardware, which covers the comm manager and the radio/bus.
+//! - Total: Receipt of the message by the device to the server replying.
+//!
+//! Anything a client sees on top of the total is transport (network/IPC) time.
+//!
+//! Only the most recent [LATENCY_SAMPLE_WINDOW] samples of each stage are kept, so stats follow the
+//! current state of the connection instead of averaging over the whole session.
+
+use crate::core::message::{DeviceLatency, LatencyStageStats};
+use getset::CopyGetters;
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+/// Number of samples kept per stage for rolling stats.
+pub const LATENCY_SAMPLE_WINDOW: usize = 100;
+
+/// Rolling statistics for a single latency stage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct LatencyStats {
+  /// Number of samples the stats were computed from.
+  sample_count: usize,
+  min: Duration,
+  max: Duration,
+  mean: Duration,
+  /// 95th percentile
+  p95: Duration,
+}
+
+impl LatencyStats {
+  fn from_samples(samples: &VecDeque<Duration>) -> Self {
+    if samples.is_empty() {
+      return Self::default();
+    }
+    let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+    sorted.sort();
+    let p95_index = ((sorted.len() * 95).div_ceil(100)).saturating_sub(1);
+    Self {
+      sample_count: sorted.len(),
+      min: sorted[0],
+      max: sorted[sorted.len() - 1],
+      mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+      p95: sorted[p95_index],
+    }
+  }
+}
+
+impl From<LatencyStats> for LatencyStageStats {
+  fn from(stats: LatencyStats) -> Self {
+    let micros = |duration: Duration| u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+    LatencyStageStats::new(
+      stats.sample_count as u32,
+      micros(stats.min),
+      micros(stats.max),
+      micros(stats.mean),
+      micros(stats.p95),
+    )
+  }
+}
+
+/// Latency stats for each stage of handling commands on a device. See the [module
+/// documentation](self) for what each stage covers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct DeviceLatencyStats {
+  protocol: LatencyStats,
+  queue: LatencyStats,
+  hardware: LatencyStats,
+  total: LatencyStats,
+}
+
+impl DeviceLatencyStats {
+  /// Build the [DeviceLatency] reply for a device from its stats.
+  pub fn to_message(&self, device_index: u32) -> DeviceLatency {
+    DeviceLatency::new(
+      device_index,
+      self.protocol.into(),
+      self.queue.into(),
+      self.hardware.into(),
+      self.total.into(),
+    )
+  }
+}
+
+#[derive(Default)]
+struct LatencySamples {
+  protocol: VecDeque<Duration>,
+  queue: VecDeque<Duration>,
+  hardware: VecDeque<Duration>,
+  total: VecDeque<Duration>,
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+  if samples.len() == LATENCY_SAMPLE_WINDOW {
+    samples.pop_front();
+  }
+  samples.push_back(sample);
+}
+
+/// Rolling window of latency samples for a device.
+#[derive(Default)]
+pub(super) struct DeviceLatencyTracker {
+  samples: Mutex<LatencySamples>,
+}
+
+impl DeviceLatencyTracker {
+  fn with_samples(&self, update: impl FnOnce(&mut LatencySamples)) {
+    update(
+      &mut self
+        .samples
+        .lock()
+        .expect("Latency lock should never be poisoned"),
+    );
+  }
+
+  pub(super) fn record_protocol(&self, sample: Duration) {
+    self.with_samples(|samples| push_sample(&mut samples.protocol, sample));
+  }
+
+  pub(super) fn record_hardware_write(&self, queue: Duration, hardware: Duration) {
+    self.with_samples(|samples| {
+      push_sample(&mut samples.queue, queue);
+      push_sample(&mut samples.hardware, hardware);
+    });
+  }
+
+  pub(super) fn record_total(&self, sample: Duration) {
+    self.with_samples(|samples| push_sample(&mut samples.total, sample));
+  }
+
+  pub(super) fn stats(&self) -> DeviceLatencyStats {
+    let samples = self
+      .samples
+      .lock()
+      .expect("Latency lock should never be poisoned");
+    DeviceLatencyStats {
+      protocol: LatencyStats::from_samples(&samples.protocol),
+      queue: LatencyStats::from_samples(&samples.queue),
+      hardware: LatencyStats::from_samples(&samples.hardware),
+      total: LatencyStats::from_samples(&samples.total),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_latency_stats_window() {
+    let tracker = DeviceLatencyTracker::default();
+    assert_eq!(tracker.stats(), DeviceLatencyStats::default());
+    // Fill the window past capacity, the oldest (largest) samples should fall out.
+    for ms in (1..=LATENCY_SAMPLE_WINDOW as u64 + 10).rev() {
+      tracker.record_total(Duration::from_millis(ms));
+    }
+    let total = tracker.stats().total();
+    assert_eq!(total.sample_count(), LATENCY_SAMPLE_WINDOW);
+    assert_eq!(total.min(), Duration::from_millis(1));
+    assert_eq!(total.max(), Duration::from_millis(100));
+    assert_eq!(total.p95(), Duration::from_millis(95));
+    assert_eq!(total.mean(), Duration::from_micros(50500));
+    assert_eq!(tracker.stats().hardware().sample_count(), 0);
+  }
+}
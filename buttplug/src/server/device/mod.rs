@@ -95,12 +95,40 @@
 //!
 //!
 
+pub mod battery;
+pub mod choreography;
+mod command_coalescer;
+mod command_queue;
 pub mod configuration;
+mod connection_scheduler;
+mod device_group;
+pub mod funscript;
 pub mod hardware;
+mod idle;
+pub mod latency;
+pub mod pattern;
 pub mod protocol;
+pub mod remembered;
+pub mod safety;
+pub mod scanning;
 pub mod server_device;
 mod server_device_manager;
 mod server_device_manager_event_loop;
 
+pub use battery::BatteryPollingPolicy;
+pub use choreography::ServerClock;
+pub use funscript::{Funscript, FunscriptAction, FunscriptOutput, FunscriptPlaybackState};
+pub use latency::{DeviceLatencyStats, LatencyStats};
+pub use pattern::Pattern;
+pub use remembered::{GattServiceCache, RememberedDevice, RememberedDeviceStore};
+pub use safety::{SafetyLimits, SafetyPolicy};
+pub use scanning::{CommunicationManagerScanStatus, ScanState};
 pub use server_device::{ServerDevice, ServerDeviceEvent, ServerDeviceIdentifier};
-pub use server_device_manager::{ServerDeviceManager, ServerDeviceManagerBuilder};
+pub use server_device_manager::{
+  ServerDeviceManager,
+  ServerDeviceManagerBuilder,
+  StopAllDevicesResult,
+  DEFAULT_CONNECTION_RETRIES,
+  DEFAULT_DEVICE_STOP_TIMEOUT,
+  DEFAULT_MAX_PARALLEL_CONNECTIONS,
+};
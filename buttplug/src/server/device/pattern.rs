@@ -0,0 +1,239 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server side pattern playback
+//!
+//! Instead of streaming ScalarCmds at 20hz from a client (which burns both bandwidth and, for
+//! remote connections, battery), a pattern can be handed to the server once. The server then
+//! schedules the hardware writes itself, interpolating between keyframes on a fixed tick.
+//!
+//! Clients start playback with a PlayPatternCmd, and end it with a StopPatternCmd. Only one pattern
+//! can play on a device at a time. Starting a new pattern on a device replaces whatever was
+//! playing, and a StopDeviceCmd/StopAllDevices will cancel playback.
+
+use super::ServerDevice;
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      check_pattern_keyframes,
+      ButtplugDeviceMessageType,
+      PatternKeyframe,
+      PlayPatternCmd,
+      ScalarCmd,
+      ScalarSubcommand,
+      StopDeviceCmd,
+    },
+  },
+  util::{async_manager, sleep},
+};
+use dashmap::DashMap;
+use futures::{select, FutureExt};
+use getset::{CopyGetters, Getters};
+use instant::Instant;
+use std::{
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Time between hardware updates while a pattern is playing.
+pub const PATTERN_TICK_MS: u64 = 50;
+
+/// Timed intensity curve, linearly interpolated between keyframes.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters)]
+pub struct Pattern {
+  #[getset(get = "pub")]
+  keyframes: Vec<PatternKeyframe>,
+  /// If true, the pattern will restart from the beginning once it finishes, until stopped.
+  #[getset(get_copy = "pub")]
+  looping: bool,
+}
+
+impl Pattern {
+  /// Create a new pattern. Keyframes must be non-empty, in time order, and have intensities in the
+  /// range 0.0-1.0.
+  pub fn new(keyframes: &[PatternKeyframe], looping: bool) -> Result<Self, ButtplugError> {
+    check_pattern_keyframes(keyframes)?;
+    Ok(Self {
+      keyframes: keyframes.to_vec(),
+      looping,
+    })
+  }
+
+  /// Pattern sent by a client.
+  pub fn from_message(msg: &PlayPatternCmd) -> Result<Self, ButtplugError> {
+    Self::new(msg.keyframes(), msg.looping())
+  }
+
+  /// Length of the pattern, which is the time of the last keyframe.
+  pub fn duration_ms(&self) -> u32 {
+    self.keyframes.last().map_or(0, |k| k.time_ms())
+  }
+
+  /// Intensity of the pattern at a certain time. Times before the first keyframe hold the first
+  /// keyframe's value, times after the last hold the last.
+  pub fn intensity_at(&self, time_ms: u32) -> f64 {
+    let next_index = self.keyframes.iter().position(|k| k.time_ms() > time_ms);
+    match next_index {
+      None => self.keyframes.last().map_or(0.0, |k| k.intensity()),
+      Some(0) => self.keyframes[0].intensity(),
+      Some(index) => {
+        let prev = self.keyframes[index - 1];
+        let next = self.keyframes[index];
+        let span = (next.time_ms() - prev.time_ms()) as f64;
+        let progress = (time_ms - prev.time_ms()) as f64 / span;
+        prev.intensity() + (next.intensity() - prev.intensity()) * progress
+      }
+    }
+  }
+}
+
+/// Tracks which devices are currently playing patterns, and runs their playback tasks.
+#[derive(Default)]
+pub(super) struct PatternPlayer {
+  playing: Arc<DashMap<u32, (u64, CancellationToken)>>,
+  next_playback_id: AtomicU64,
+}
+
+impl PatternPlayer {
+  pub fn play(
+    &self,
+    device_index: u32,
+    device: Arc<ServerDevice>,
+    pattern: Pattern,
+  ) -> Result<(), ButtplugError> {
+    let scalar_attrs = device.message_attributes().scalar_cmd().clone().ok_or(
+      ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::ScalarCmd),
+    )?;
+    let playback_id = self.next_playback_id.fetch_add(1, Ordering::SeqCst);
+    let token = CancellationToken::new();
+    if let Some((_, (_, old_token))) = self.playing.remove(&device_index) {
+      old_token.cancel();
+    }
+    self
+      .playing
+      .insert(device_index, (playback_id, token.clone()));
+    let playing = self.playing.clone();
+    async_manager::spawn(async move {
+      let mut start_time = Instant::now();
+      let duration_ms = pattern.duration_ms();
+      loop {
+        let mut elapsed_ms = Instant::now().duration_since(start_time).as_millis() as u32;
+        if elapsed_ms > duration_ms {
+          if !pattern.looping() {
+            break;
+          }
+          start_time = Instant::now();
+          elapsed_ms = 0;
+        }
+        let intensity = pattern.intensity_at(elapsed_ms);
+        let subcommands = scalar_attrs
+          .iter()
+          .enumerate()
+          .map(|(index, attr)| {
+            ScalarSubcommand::new(index as u32, intensity, *attr.actuator_type())
+          })
+          .collect();
+        if let Err(err) = device
+          .parse_message(ScalarCmd::new(device_index, subcommands).into())
+          .await
+        {
+          error!(
+            "Error playing pattern on device {}: {:?}",
+            device_index, err
+          );
+          break;
+        }
+        select! {
+          _ = token.cancelled().fuse() => return,
+          _ = sleep(Duration::from_millis(PATTERN_TICK_MS)).fuse() => {}
+        }
+      }
+      playing.remove_if(&device_index, |_, (id, _)| *id == playback_id);
+      if let Err(err) = device
+        .parse_message(StopDeviceCmd::new(device_index).into())
+        .await
+      {
+        error!(
+          "Cannot stop device {} after pattern finished: {:?}",
+          device_index, err
+        );
+      }
+    });
+    Ok(())
+  }
+
+  /// Cancel playback on a device. Returns true if a pattern was playing.
+  pub fn stop(&self, device_index: u32) -> bool {
+    if let Some((_, (_, token))) = self.playing.remove(&device_index) {
+      token.cancel();
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn stop_all(&self) {
+    for entry in self.playing.iter() {
+      entry.value().1.cancel();
+    }
+    self.playing.clear();
+  }
+
+  pub fn is_playing(&self, device_index: u32) -> bool {
+    self.playing.contains_key(&device_index)
+  }
+}
+
+impl Drop for PatternPlayer {
+  fn drop(&mut self) {
+    self.stop_all();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Pattern, PatternKeyframe};
+
+  #[test]
+  pub fn test_pattern_interpolation() {
+    let pattern = Pattern::new(
+      &[
+        PatternKeyframe::new(0, 0.0),
+        PatternKeyframe::new(100, 1.0),
+        PatternKeyframe::new(300, 0.0),
+      ],
+      false,
+    )
+    .expect("Valid pattern");
+    assert_eq!(pattern.duration_ms(), 300);
+    assert_eq!(pattern.intensity_at(0), 0.0);
+    assert_eq!(pattern.intensity_at(50), 0.5);
+    assert_eq!(pattern.intensity_at(100), 1.0);
+    assert_eq!(pattern.intensity_at(200), 0.5);
+    assert_eq!(pattern.intensity_at(1000), 0.0);
+  }
+
+  #[test]
+  pub fn test_pattern_validation() {
+    assert!(Pattern::new(&[], false).is_err());
+    assert!(Pattern::new(
+      &[
+        PatternKeyframe::new(100, 0.5),
+        PatternKeyframe::new(50, 0.5)
+      ],
+      false
+    )
+    .is_err());
+    assert!(Pattern::new(&[PatternKeyframe::new(0, 1.5)], false).is_err());
+    assert!(Pattern::new(&[PatternKeyframe::new(0, 0.5)], true).is_ok());
+  }
+}
@@ -6,10 +6,13 @@
 // for full license information.
 
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
 };
 
@@ -19,6 +22,10 @@ generic_protocol_setup!(AdrienLastic, "adrienlastic");
 pub struct AdrienLastic {}
 
 impl ProtocolHandler for AdrienLastic {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -8,12 +8,16 @@
 use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::server::device::hardware::HardwareReadCmd;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -77,6 +81,10 @@ impl ProtocolInitializer for AnkniInitializer {
 pub struct Ankni {}
 
 impl ProtocolHandler for Ankni {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
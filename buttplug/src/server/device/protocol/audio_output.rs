@@ -0,0 +1,114 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Virtual device that plays sine waves on an audio output, through the audio output communication
+//! manager. Used for bass shakers, like the Buttkicker, and haptic vests driven by an amplifier.
+//!
+//! Each Vibrate actuator is a sine wave, with its amplitude set by the actuator (step range 0-65535).
+//! If the device also has Frequency actuators, the first Frequency actuator sets the frequency of
+//! the first Vibrate actuator in Hz, the second the second, and so on. A frequency of 0, or no
+//! Frequency actuator, uses the frequency set on the output definition. Which output channel each
+//! wave plays on is also set on the output definition. Writes to Tx are:
+//!
+//! - `[AUDIO_OUTPUT_PACKET_WAVEFORM, actuator index, amplitude (u16 le), frequency (u16 le)]`
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    hardware::{HardwareCommand, HardwareWriteCmd},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
+  },
+};
+
+generic_protocol_setup!(AudioOutput, "audio-output");
+
+/// Packet type for the amplitude and frequency of a single wave.
+pub const AUDIO_OUTPUT_PACKET_WAVEFORM: u8 = 0x00;
+
+#[derive(Default)]
+pub struct AudioOutput {}
+
+impl ProtocolHandler for AudioOutput {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Frequency])
+  }
+
+  fn needs_full_command_set(&self) -> bool {
+    true
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let actuator_values = |actuator_type: ActuatorType| {
+      commands
+        .iter()
+        .flatten()
+        .filter(move |(actuator, _)| *actuator == actuator_type)
+        .map(|(_, value)| (*value).min(u16::MAX as u32) as u16)
+    };
+    let mut frequencies = actuator_values(ActuatorType::Frequency);
+    Ok(
+      actuator_values(ActuatorType::Vibrate)
+        .enumerate()
+        .map(|(index, amplitude)| {
+          let frequency = frequencies.next().unwrap_or(0);
+          let mut data = vec![AUDIO_OUTPUT_PACKET_WAVEFORM, index as u8];
+          data.extend_from_slice(&amplitude.to_le_bytes());
+          data.extend_from_slice(&frequency.to_le_bytes());
+          HardwareWriteCmd::new(Endpoint::Tx, data, false).into()
+        })
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{setup::AudioOutputIdentifierFactory, AUDIO_OUTPUT_PACKET_WAVEFORM};
+  use crate::{
+    core::message::{ActuatorType, Endpoint},
+    server::device::hardware::scripted::{send_commands, setup_protocol, HardwareScript},
+  };
+
+  #[tokio::test]
+  async fn test_audio_output_waveforms() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_write(
+        Endpoint::Tx,
+        &[AUDIO_OUTPUT_PACKET_WAVEFORM, 0, 0xff, 0x7f, 0x28, 0x00],
+      )
+      .expect_write(
+        Endpoint::Tx,
+        &[AUDIO_OUTPUT_PACKET_WAVEFORM, 1, 0x00, 0x10, 0x00, 0x00],
+      );
+    let hardware = script.build_hardware("Bass Shaker", "audio-output-test");
+    let (identifier, handler) =
+      setup_protocol(&AudioOutputIdentifierFactory::default(), hardware.clone())
+        .await
+        .expect("Test, assuming infallible.");
+    assert_eq!(identifier.protocol(), "audio-output");
+
+    // The second wave has no Frequency actuator, so it plays at the output's default frequency.
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 0x7fff)),
+        Some((ActuatorType::Frequency, 40)),
+        Some((ActuatorType::Vibrate, 0x1000)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+}
@@ -0,0 +1,74 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Autoblow AI Ultra, controlled through its REST API by the network communication manager.
+//!
+//! The Ultra strokes by itself when told to oscillate, with speed and stroke length both 0-100.
+//! LinearCmd moves it to a position (0 at the bottom, 100 at the top) over the given duration.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{self, ActuatorType, Endpoint},
+  },
+  server::device::{
+    hardware::{HardwareCommand, HardwareWriteCmd},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
+  },
+};
+use serde_json::json;
+
+generic_protocol_setup!(Autoblow, "autoblow");
+
+fn autoblow_request(method: &str, path: &str, body: Option<serde_json::Value>) -> HardwareCommand {
+  let mut request = format!("{} autoblow/{}", method, path);
+  if let Some(body) = body {
+    request = format!("{}\n{}", request, body);
+  }
+  HardwareWriteCmd::new(Endpoint::Tx, request.into_bytes(), false).into()
+}
+
+#[derive(Default)]
+pub struct Autoblow {}
+
+impl ProtocolHandler for Autoblow {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Oscillate])
+      .with_linear()
+  }
+
+  fn handle_scalar_oscillate_cmd(
+    &self,
+    _index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    if scalar == 0 {
+      return Ok(vec![autoblow_request("PUT", "oscillate/stop", None)]);
+    }
+    Ok(vec![autoblow_request(
+      "PUT",
+      "oscillate",
+      Some(json!({ "speed": scalar.min(100), "minY": 0, "maxY": 100 })),
+    )])
+  }
+
+  fn handle_linear_cmd(
+    &self,
+    message: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let Some(vector) = message.vectors().first() else {
+      return Ok(vec![]);
+    };
+    let position = (vector.position().clamp(0f64, 1f64) * 100f64).round() as u32;
+    Ok(vec![autoblow_request(
+      "PUT",
+      "goto",
+      Some(json!({ "position": position, "duration": vector.duration() })),
+    )])
+  }
+}
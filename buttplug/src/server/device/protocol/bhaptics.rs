@@ -0,0 +1,170 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! bHaptics TactSuit haptic vests.
+//!
+//! The vest has 40 motors, in a 4 column by 5 row grid on each of its front and back panels. Each
+//! motor is a scalar actuator, front panel first, each panel in rows from the top left, so motor N
+//! of the front panel is actuator N and motor N of the back panel is actuator 20 + N. Motors have 16
+//! intensity levels.
+//!
+//! Updating motors one at a time is far too slow for a suit with 40 of them, so a whole panel goes
+//! out in one write: the panel ID, followed by its 20 motors packed two to a byte (even motors in
+//! the high nibble). A panel is only written if one of its motors changed.
+//!
+//! Before the vest takes motor writes, it has to be put into control mode by writing to TxMode, and
+//! it acknowledges by sending its status on Rx.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareEvent,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+    protocol::{
+      generic_protocol_initializer_setup,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+  util::sleep,
+};
+use async_trait::async_trait;
+use futures::{select, FutureExt};
+use std::{
+  sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+/// Motors on each panel of the vest.
+const BHAPTICS_PANEL_MOTORS: usize = 20;
+/// Front and back.
+const BHAPTICS_PANELS: [u8; 2] = [0x00, 0x01];
+/// Puts the vest into control mode, with motor writes taking effect as soon as they arrive.
+const BHAPTICS_CONTROL_MODE: [u8; 2] = [0x01, 0x01];
+/// How long to wait for the vest to acknowledge control mode. Older firmware doesn't always
+/// answer, but takes motor writes anyways.
+const BHAPTICS_INIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+generic_protocol_initializer_setup!(BHaptics, "bhaptics");
+
+#[derive(Default)]
+pub struct BHapticsInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for BHapticsInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let mut event_receiver = hardware.event_stream();
+    hardware
+      .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+      .await?;
+    hardware
+      .write_value(&HardwareWriteCmd::new(
+        Endpoint::TxMode,
+        BHAPTICS_CONTROL_MODE.to_vec(),
+        true,
+      ))
+      .await?;
+    let acknowledged = async {
+      loop {
+        match event_receiver.recv().await {
+          Ok(HardwareEvent::Notification(_, Endpoint::Rx, _)) => return true,
+          Ok(_) => continue,
+          Err(_) => return false,
+        }
+      }
+    };
+    select! {
+      acknowledged = acknowledged.fuse() => if !acknowledged {
+        return Err(ButtplugDeviceError::ProtocolSpecificError(
+          "bHaptics".to_owned(),
+          "Device disconnected while entering control mode".to_owned(),
+        ));
+      },
+      _ = sleep(BHAPTICS_INIT_TIMEOUT).fuse() => {
+        warn!("bHaptics device didn't acknowledge control mode, continuing anyways.");
+      }
+    }
+    hardware
+      .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::Rx))
+      .await?;
+    Ok(Arc::new(BHaptics::default()))
+  }
+}
+
+pub struct BHaptics {
+  motors: [AtomicU8; BHAPTICS_PANEL_MOTORS * 2],
+}
+
+impl Default for BHaptics {
+  fn default() -> Self {
+    Self {
+      motors: std::array::from_fn(|_| AtomicU8::new(0)),
+    }
+  }
+}
+
+impl BHaptics {
+  fn panel_command(&self, panel: usize) -> HardwareCommand {
+    let motors = &self.motors[panel * BHAPTICS_PANEL_MOTORS..(panel + 1) * BHAPTICS_PANEL_MOTORS];
+    let mut data = vec![BHAPTICS_PANELS[panel]];
+    data.extend(motors.chunks(2).map(|pair| {
+      pair[0].load(Ordering::Relaxed) << 4 | pair[1].load(Ordering::Relaxed)
+    }));
+    HardwareWriteCmd::new(Endpoint::Tx, data, false).into()
+  }
+}
+
+impl ProtocolHandler for BHaptics {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
+  fn needs_full_command_set(&self) -> bool {
+    true
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut changed = [false; BHAPTICS_PANELS.len()];
+    for (index, command) in commands.iter().enumerate().take(self.motors.len()) {
+      if let Some((_, intensity)) = command {
+        let intensity = (*intensity).min(0x0f) as u8;
+        if self.motors[index].swap(intensity, Ordering::Relaxed) != intensity {
+          changed[index / BHAPTICS_PANEL_MOTORS] = true;
+        }
+      }
+    }
+    Ok(
+      (0..BHAPTICS_PANELS.len())
+        .filter(|panel| changed[*panel])
+        .map(|panel| self.panel_command(panel))
+        .collect(),
+    )
+  }
+}
@@ -0,0 +1,294 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Read-only sensors that broadcast their values in BLE advertisement manufacturer data.
+//!
+//! The hardware for these devices exposes the latest manufacturer data payload on the Rx endpoint.
+//! Sensor index N reports byte N of that payload.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{
+      self,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorReading,
+    },
+  },
+  server::device::{
+    hardware::{
+      Hardware,
+      HardwareEvent,
+      HardwareReadCmd,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+    },
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
+  },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+};
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
+};
+use std::{
+  collections::HashMap,
+  pin::Pin,
+  sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+generic_protocol_setup!(BleAdvertisementSensor, "ble-advertisement-sensor");
+
+pub struct BleAdvertisementSensor {
+  // Sensors we've subscribed to for updates, each with the task sending its readings. Removing a
+  // sensor stops its task.
+  subscribed_sensors: Arc<Mutex<HashMap<u32, DropGuard>>>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl Default for BleAdvertisementSensor {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      subscribed_sensors: Arc::new(Mutex::new(HashMap::new())),
+      event_stream: sender,
+    }
+  }
+}
+
+impl ProtocolHandler for BleAdvertisementSensor {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_sensor_read(&ProtocolCapabilities::ALL_SENSOR_TYPES)
+      .with_sensor_subscribe(&ProtocolCapabilities::ALL_SENSOR_TYPES)
+  }
+
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
+  fn handle_sensor_read_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorReadCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    async move {
+      let reading = device
+        .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+        .await?;
+      let value = reading
+        .data()
+        .get(*message.sensor_index() as usize)
+        .ok_or_else(|| {
+          ButtplugDeviceError::DeviceCommunicationError(format!(
+            "Advertisement payload has no value for sensor {}",
+            message.sensor_index()
+          ))
+        })?;
+      Ok(
+        SensorReading::new(
+          message.device_index(),
+          *message.sensor_index(),
+          *message.sensor_type(),
+          vec![*value as i32],
+        )
+        .into(),
+      )
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    let sensor_index = *message.sensor_index();
+    let token = CancellationToken::new();
+    let first_sensor = {
+      let mut sensors = self
+        .subscribed_sensors
+        .lock()
+        .expect("Lock is never held across a panic");
+      // Subscribing to a sensor we're already subscribed to is a no-op.
+      if sensors.contains_key(&sensor_index) {
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+      let first_sensor = sensors.is_empty();
+      sensors.insert(sensor_index, token.clone().drop_guard());
+      first_sensor
+    };
+    // Get the stream before subscribing, so we don't miss the first advertisement.
+    let mut hardware_stream = device.event_stream();
+    let sensors = self.subscribed_sensors.clone();
+    let sender = self.event_stream.clone();
+    async move {
+      // If this is the first sensor, we'll need to start listening for advertisement updates.
+      if first_sensor {
+        if let Err(err) = device
+          .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+          .await
+        {
+          sensors
+            .lock()
+            .expect("Lock is never held across a panic")
+            .remove(&sensor_index);
+          return Err(err);
+        }
+      }
+      let device_index = message.device_index();
+      let sensor_type = *message.sensor_type();
+      async_manager::spawn(async move {
+        loop {
+          let info = tokio::select! {
+            _ = token.cancelled() => return,
+            info = hardware_stream.recv() => info,
+          };
+          match info {
+            Ok(HardwareEvent::Notification(_, Endpoint::Rx, data)) => {
+              let Some(value) = data.get(sensor_index as usize) else {
+                continue;
+              };
+              if sender
+                .send(
+                  SensorReading::new(device_index, sensor_index, sensor_type, vec![*value as i32])
+                    .into(),
+                )
+                .is_err()
+              {
+                debug!("Hardware device listener for advertisement sensor shut down, returning from task.");
+                return;
+              }
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+          }
+        }
+      });
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    let last_sensor = {
+      let mut sensors = self
+        .subscribed_sensors
+        .lock()
+        .expect("Lock is never held across a panic");
+      if sensors.remove(message.sensor_index()).is_none() {
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+      sensors.is_empty()
+    };
+    async move {
+      if last_sensor {
+        device
+          .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::Rx))
+          .await?;
+      }
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::BleAdvertisementSensor;
+  use crate::{
+    core::message::{
+      ButtplugServerDeviceMessage,
+      Endpoint,
+      SensorReading,
+      SensorSubscribeCmd,
+      SensorType,
+      SensorUnsubscribeCmd,
+    },
+    server::device::{hardware::scripted::HardwareScript, protocol::ProtocolHandler},
+  };
+  use futures::StreamExt;
+
+  #[tokio::test]
+  async fn test_sensor_subscriptions() {
+    let mut script = HardwareScript::default();
+    // Only the first subscribe and the last unsubscribe should reach the hardware.
+    script
+      .expect_subscribe(Endpoint::Rx)
+      .reply(Endpoint::Rx, &[7, 9])
+      .expect_unsubscribe(Endpoint::Rx);
+    let hardware = script.build_hardware("Sensor", "advertisement-test");
+    let handler = BleAdvertisementSensor::default();
+    let mut events = handler.event_stream();
+
+    handler
+      .handle_sensor_subscribe_cmd(
+        hardware.clone(),
+        SensorSubscribeCmd::new(0, 1, SensorType::Pressure),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      events.next().await,
+      Some(ButtplugServerDeviceMessage::SensorReading(
+        SensorReading::new(0, 1, SensorType::Pressure, vec![9])
+      ))
+    );
+    // Subscribing again doesn't touch the hardware, or start a second task sending readings.
+    handler
+      .handle_sensor_subscribe_cmd(
+        hardware.clone(),
+        SensorSubscribeCmd::new(0, 1, SensorType::Pressure),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    handler
+      .handle_sensor_subscribe_cmd(
+        hardware.clone(),
+        SensorSubscribeCmd::new(0, 0, SensorType::Button),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      handler
+        .subscribed_sensors
+        .lock()
+        .expect("Test, assuming infallible.")
+        .len(),
+      2
+    );
+
+    for sensor_index in [1, 1, 0] {
+      handler
+        .handle_sensor_unsubscribe_cmd(
+          hardware.clone(),
+          SensorUnsubscribeCmd::new(0, sensor_index, SensorType::Pressure),
+        )
+        .await
+        .expect("Test, assuming infallible.");
+    }
+    assert!(handler
+      .subscribed_sensors
+      .lock()
+      .expect("Test, assuming infallible.")
+      .is_empty());
+    script.finish();
+  }
+}
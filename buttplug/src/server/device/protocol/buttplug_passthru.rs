@@ -12,7 +12,7 @@ use crate::{
   },
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
 };
 
@@ -22,6 +22,10 @@ generic_protocol_setup!(ButtplugPassthru, "buttplug-passthru");
 struct ButtplugPassthru {}
 
 impl ProtocolHandler for ButtplugPassthru {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::all_messages()
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
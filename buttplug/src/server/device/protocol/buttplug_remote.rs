@@ -0,0 +1,474 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Protocol for devices proxied from another Buttplug server.
+//!
+//! The hardware on the other end of this protocol is a device on a remote server, so instead of
+//! bytes, it talks in Buttplug messages. Commands are written to [Endpoint::Tx] as JSON device
+//! messages, sensor readings come back as JSON [SensorReading] notifications on [Endpoint::Rx], and
+//! reading [Endpoint::Rx] returns the message attributes the remote server reported for the device.
+//! As remote devices describe their own features, they don't need device configuration entries.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceCommandMessageUnion,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      ClientDeviceMessageAttributes,
+      ClientGenericDeviceMessageAttributes,
+      Endpoint,
+      RotationSubcommand,
+      ScalarSubcommand,
+      SensorReading,
+    },
+  },
+  server::device::{
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerDeviceMessageAttributes,
+      ServerDeviceMessageAttributesBuilder,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{Hardware, HardwareCommand, HardwareEvent, HardwareReadCmd, HardwareWriteCmd},
+    protocol::{ProtocolCapabilities, ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    ServerDeviceIdentifier,
+  },
+  util::{async_manager, sleep, stream::convert_broadcast_receiver_to_stream},
+};
+use async_trait::async_trait;
+use dashmap::DashSet;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
+};
+use std::{
+  ops::RangeInclusive,
+  pin::Pin,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::sync::broadcast;
+
+const SENSOR_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub mod setup {
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
+  #[derive(Default)]
+  pub struct ButtplugRemoteIdentifierFactory {}
+
+  impl ProtocolIdentifierFactory for ButtplugRemoteIdentifierFactory {
+    fn identifier(&self) -> &str {
+      "buttplug-remote"
+    }
+
+    fn create(&self) -> Box<dyn ProtocolIdentifier> {
+      Box::new(super::ButtplugRemoteIdentifier::default())
+    }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::ButtplugRemote as ProtocolHandler>::capabilities()
+    }
+  }
+}
+
+fn server_generic_attributes(
+  attrs: &[ClientGenericDeviceMessageAttributes],
+) -> Vec<ServerGenericDeviceMessageAttributes> {
+  attrs
+    .iter()
+    .map(|attr| {
+      ServerGenericDeviceMessageAttributes::new(
+        attr.feature_descriptor(),
+        &RangeInclusive::new(0, *attr.step_count()),
+        *attr.actuator_type(),
+      )
+    })
+    .collect()
+}
+
+/// Rebuild server side attributes from what the remote server told its client. Step ranges start
+/// at 0, since the remote server already took care of any offsets its own configuration has.
+fn server_message_attributes(
+  attrs: &ClientDeviceMessageAttributes,
+) -> ServerDeviceMessageAttributes {
+  let mut builder = ServerDeviceMessageAttributesBuilder::default();
+  if let Some(scalars) = attrs.scalar_cmd() {
+    builder.scalar_cmd(&server_generic_attributes(scalars));
+  }
+  if let Some(rotates) = attrs.rotate_cmd() {
+    builder.rotate_cmd(&server_generic_attributes(rotates));
+  }
+  if let Some(linears) = attrs.linear_cmd() {
+    builder.linear_cmd(&server_generic_attributes(linears));
+  }
+  if let Some(sensors) = attrs.sensor_read_cmd() {
+    builder.sensor_read_cmd(sensors);
+  }
+  if let Some(sensors) = attrs.sensor_subscribe_cmd() {
+    builder.sensor_subscribe_cmd(sensors);
+  }
+  builder.finish()
+}
+
+/// Map a step value from the generic command manager back to the 0.0-1.0 range the remote server
+/// expects.
+fn step_to_float(step_ranges: &[RangeInclusive<u32>], index: usize, step: u32) -> f64 {
+  let Some(range) = step_ranges.get(index) else {
+    return 0.0;
+  };
+  if step == 0 {
+    return 0.0;
+  }
+  let width = range.end() - range.start();
+  if width == 0 {
+    return 1.0;
+  }
+  (step.saturating_sub(*range.start()) as f64 / width as f64).clamp(0.0, 1.0)
+}
+
+fn write_message(message: ButtplugDeviceCommandMessageUnion) -> HardwareWriteCmd {
+  HardwareWriteCmd::new(
+    Endpoint::Tx,
+    serde_json::to_string(&message)
+      .expect("Type is always serializable")
+      .as_bytes()
+      .to_vec(),
+    false,
+  )
+}
+
+fn parse_reading(event: HardwareEvent) -> Option<SensorReading> {
+  if let HardwareEvent::Notification(_, Endpoint::Rx, data) = event {
+    match serde_json::from_slice(&data) {
+      Ok(reading) => Some(reading),
+      Err(err) => {
+        error!("Cannot parse remote sensor reading: {:?}", err);
+        None
+      }
+    }
+  } else {
+    None
+  }
+}
+
+#[derive(Default)]
+pub struct ButtplugRemoteIdentifier {}
+
+#[async_trait]
+impl ProtocolIdentifier for ButtplugRemoteIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    let reading = hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
+      .await?;
+    let remote_attributes: ClientDeviceMessageAttributes = serde_json::from_slice(reading.data())
+      .map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot parse remote device attributes: {}",
+        err
+      ))
+    })?;
+    let identifier = ProtocolAttributesType::Identifier(hardware.name().to_owned());
+    let attributes = ProtocolDeviceAttributes::new(
+      identifier.clone(),
+      Some(hardware.name().to_owned()),
+      None,
+      server_message_attributes(&remote_attributes),
+      None,
+    );
+    Ok((
+      ServerDeviceIdentifier::new(hardware.address(), "buttplug-remote", &identifier),
+      Box::new(ButtplugRemoteInitializer { attributes }),
+    ))
+  }
+}
+
+pub struct ButtplugRemoteInitializer {
+  attributes: ProtocolDeviceAttributes,
+}
+
+#[async_trait]
+impl ProtocolInitializer for ButtplugRemoteInitializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let step_ranges = |attrs: &Option<Vec<ServerGenericDeviceMessageAttributes>>| -> Vec<_> {
+      attrs
+        .iter()
+        .flatten()
+        .map(|attr| attr.step_range().clone())
+        .collect()
+    };
+    let message_attributes = attributes.message_attributes();
+    Ok(Arc::new(ButtplugRemote::new(
+      step_ranges(message_attributes.scalar_cmd()),
+      step_ranges(message_attributes.rotate_cmd()),
+    )))
+  }
+
+  fn device_attributes(&self) -> Option<ProtocolDeviceAttributes> {
+    Some(self.attributes.clone())
+  }
+}
+
+pub struct ButtplugRemote {
+  scalar_step_ranges: Vec<RangeInclusive<u32>>,
+  rotate_step_ranges: Vec<RangeInclusive<u32>>,
+  subscribed_sensors: Arc<DashSet<u32>>,
+  listening: Arc<AtomicBool>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl ButtplugRemote {
+  fn new(
+    scalar_step_ranges: Vec<RangeInclusive<u32>>,
+    rotate_step_ranges: Vec<RangeInclusive<u32>>,
+  ) -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      scalar_step_ranges,
+      rotate_step_ranges,
+      subscribed_sensors: Arc::new(DashSet::new()),
+      listening: Arc::new(AtomicBool::new(false)),
+      event_stream: sender,
+    }
+  }
+
+  /// Forward readings for subscribed sensors from the remote device into our event stream, under
+  /// the local device index. Only one listener runs per device, no matter how many sensors are
+  /// subscribed.
+  fn start_sensor_listener(&self, device: Arc<Hardware>, device_index: u32) {
+    if self.listening.swap(true, Ordering::SeqCst) {
+      return;
+    }
+    let sender = self.event_stream.clone();
+    let sensors = self.subscribed_sensors.clone();
+    let listening = self.listening.clone();
+    let mut hardware_stream = device.event_stream();
+    async_manager::spawn(async move {
+      while let Ok(event) = hardware_stream.recv().await {
+        if sensors.is_empty() {
+          break;
+        }
+        let Some(reading) = parse_reading(event) else {
+          continue;
+        };
+        if !sensors.contains(&reading.sensor_index()) {
+          continue;
+        }
+        let reading = SensorReading::new(
+          device_index,
+          reading.sensor_index(),
+          reading.sensor_type(),
+          reading.data().clone(),
+        );
+        if sender.send(reading.into()).is_err() {
+          debug!("Hardware device listener for remote device shut down, returning from task.");
+          break;
+        }
+      }
+      listening.store(false, Ordering::SeqCst);
+    });
+  }
+}
+
+impl ProtocolHandler for ButtplugRemote {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&ProtocolCapabilities::ALL_ACTUATOR_TYPES)
+      .with_rotate()
+      .with_linear()
+      .with_sensor_read(&ProtocolCapabilities::ALL_SENSOR_TYPES)
+      .with_sensor_subscribe(&ProtocolCapabilities::ALL_SENSOR_TYPES)
+  }
+
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let scalars: Vec<ScalarSubcommand> = commands
+      .iter()
+      .enumerate()
+      .filter_map(|(index, command)| {
+        command.map(|(actuator, step)| {
+          ScalarSubcommand::new(
+            index as u32,
+            step_to_float(&self.scalar_step_ranges, index, step),
+            actuator,
+          )
+        })
+      })
+      .collect();
+    if scalars.is_empty() {
+      return Ok(vec![]);
+    }
+    Ok(vec![write_message(
+      message::ScalarCmd::new(0, scalars).into(),
+    )
+    .into()])
+  }
+
+  fn handle_rotate_cmd(
+    &self,
+    commands: &[Option<(u32, bool)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let rotations: Vec<RotationSubcommand> = commands
+      .iter()
+      .enumerate()
+      .filter_map(|(index, command)| {
+        command.map(|(step, clockwise)| {
+          RotationSubcommand::new(
+            index as u32,
+            step_to_float(&self.rotate_step_ranges, index, step),
+            clockwise,
+          )
+        })
+      })
+      .collect();
+    if rotations.is_empty() {
+      return Ok(vec![]);
+    }
+    Ok(vec![write_message(
+      message::RotateCmd::new(0, rotations).into(),
+    )
+    .into()])
+  }
+
+  fn handle_linear_cmd(
+    &self,
+    message: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![write_message(message.into()).into()])
+  }
+
+  fn handle_sensor_read_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorReadCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    // Listen before writing, so the reading can't show up before we're waiting for it.
+    let mut hardware_stream = device.event_stream();
+    async move {
+      let sensor_index = *message.sensor_index();
+      let device_index = message.device_index();
+      device.write_value(&write_message(message.into())).await?;
+      let wait_for_reading = async {
+        while let Ok(event) = hardware_stream.recv().await {
+          if let Some(reading) = parse_reading(event) {
+            if reading.sensor_index() == sensor_index {
+              return Some(reading);
+            }
+          }
+        }
+        None
+      };
+      select! {
+        reading = wait_for_reading.fuse() => match reading {
+          Some(reading) => Ok(
+            SensorReading::new(
+              device_index,
+              sensor_index,
+              reading.sensor_type(),
+              reading.data().clone(),
+            )
+            .into(),
+          ),
+          None => Err(ButtplugDeviceError::DeviceNotConnected(
+            "Remote device disconnected before sending a reading.".to_owned(),
+          )),
+        },
+        _ = sleep(SENSOR_READ_TIMEOUT).fuse() => Err(ButtplugDeviceError::DeviceCommunicationError(
+          "Remote device did not send a sensor reading in time.".to_owned(),
+        )),
+      }
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    async move {
+      let id = message.id();
+      let sensor_index = *message.sensor_index();
+      let device_index = message.device_index();
+      device.write_value(&write_message(message.into())).await?;
+      sensors.insert(sensor_index);
+      self.start_sensor_listener(device, device_index);
+      Ok(message::Ok::new(id).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if !self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    async move {
+      let id = message.id();
+      let sensor_index = *message.sensor_index();
+      device.write_value(&write_message(message.into())).await?;
+      sensors.remove(&sensor_index);
+      Ok(message::Ok::new(id).into())
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_step_to_float() {
+    let ranges = vec![RangeInclusive::new(0, 20), RangeInclusive::new(10, 20)];
+    assert_eq!(step_to_float(&ranges, 0, 0), 0.0);
+    assert_eq!(step_to_float(&ranges, 0, 10), 0.5);
+    assert_eq!(step_to_float(&ranges, 0, 20), 1.0);
+    // Offset ranges come back relative to their start, but 0 still means stopped.
+    assert_eq!(step_to_float(&ranges, 1, 0), 0.0);
+    assert_eq!(step_to_float(&ranges, 1, 15), 0.5);
+    assert_eq!(step_to_float(&ranges, 2, 5), 0.0);
+  }
+}
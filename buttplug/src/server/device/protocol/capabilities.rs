@@ -0,0 +1,331 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Declarations of which commands a protocol implements
+//!
+//! Protocols override the [ProtocolHandler](super::ProtocolHandler) command handlers for whatever
+//! their devices can do, and leave the rest at the trait defaults, which fail with
+//! [ButtplugDeviceError::UnhandledCommand](crate::core::errors::ButtplugDeviceError::UnhandledCommand).
+//! Protocols also declare which handlers they override in
+//! [ProtocolHandler::capabilities](super::ProtocolHandler::capabilities), so they can be looked up
+//! without a device. The declaration needs to be updated along with the handlers. Protocols that
+//! don't declare anything, like ones written before declarations existed, report no capabilities.
+//!
+//! This describes the protocol implementation, not any specific device. What a device supports is
+//! still up to the device configuration. Protocols that send every scalar command the same way,
+//! whatever its actuator type, declare the actuator types their devices use.
+//!
+//! Raw messages, and battery readings from the standard BLE battery service, are handled by the
+//! server rather than by protocols, so they work with every protocol and aren't declared.
+
+use crate::core::message::{ActuatorType, SensorType};
+use getset::{CopyGetters, Getters};
+use serde::Serialize;
+
+/// Commands a protocol implementation handles.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Getters, CopyGetters)]
+pub struct ProtocolCapabilities {
+  /// True if the protocol passes every device message to the hardware itself, in which case none
+  /// of the other capabilities apply.
+  #[getset(get_copy = "pub")]
+  handles_all_messages: bool,
+  /// Actuator types handled in ScalarCmd.
+  #[getset(get = "pub")]
+  scalar: Vec<ActuatorType>,
+  /// True if RotateCmd is handled.
+  #[getset(get_copy = "pub")]
+  rotate: bool,
+  /// True if LinearCmd is handled.
+  #[getset(get_copy = "pub")]
+  linear: bool,
+  /// Sensor types handled in SensorReadCmd.
+  #[getset(get = "pub")]
+  sensor_read: Vec<SensorType>,
+  /// Sensor types handled in SensorSubscribeCmd.
+  #[getset(get = "pub")]
+  sensor_subscribe: Vec<SensorType>,
+}
+
+impl ProtocolCapabilities {
+  /// Every controllable actuator type, for protocols that pass scalar commands through as is.
+  pub const ALL_ACTUATOR_TYPES: [ActuatorType; 9] = [
+    ActuatorType::Vibrate,
+    ActuatorType::Rotate,
+    ActuatorType::Oscillate,
+    ActuatorType::Constrict,
+    ActuatorType::Inflate,
+    ActuatorType::Position,
+    ActuatorType::Heater,
+    ActuatorType::Frequency,
+    ActuatorType::Estim,
+  ];
+
+  /// Every known sensor type, for protocols that pass sensor commands through as is.
+  pub const ALL_SENSOR_TYPES: [SensorType; 5] = [
+    SensorType::Battery,
+    SensorType::RSSI,
+    SensorType::Button,
+    SensorType::Pressure,
+    SensorType::Position,
+  ];
+
+  /// Capabilities of a protocol that passes every device message to the hardware itself.
+  pub fn all_messages() -> Self {
+    Self {
+      handles_all_messages: true,
+      ..Default::default()
+    }
+  }
+
+  pub fn with_scalar(mut self, actuators: &[ActuatorType]) -> Self {
+    self.scalar = actuators.to_vec();
+    self
+  }
+
+  pub fn with_rotate(mut self) -> Self {
+    self.rotate = true;
+    self
+  }
+
+  pub fn with_linear(mut self) -> Self {
+    self.linear = true;
+    self
+  }
+
+  pub fn with_sensor_read(mut self, sensors: &[SensorType]) -> Self {
+    self.sensor_read = sensors.to_vec();
+    self
+  }
+
+  pub fn with_sensor_subscribe(mut self, sensors: &[SensorType]) -> Self {
+    self.sensor_subscribe = sensors.to_vec();
+    self
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::{
+    core::{
+      errors::ButtplugDeviceError,
+      message::{Endpoint, LinearCmd, SensorReadCmd, SensorSubscribeCmd, VectorSubcommand},
+    },
+    server::device::{
+      configuration::{
+        ProtocolAttributesType,
+        ProtocolDeviceAttributes,
+        ServerDeviceMessageAttributes,
+      },
+      hardware::{
+        Hardware,
+        HardwareEvent,
+        HardwareInternal,
+        HardwareReadCmd,
+        HardwareReading,
+        HardwareSubscribeCmd,
+        HardwareUnsubscribeCmd,
+        HardwareWriteCmd,
+      },
+      protocol::{get_default_protocol_map, ProtocolHandler, ProtocolIdentifierFactory},
+    },
+  };
+  use futures::{
+    future::{self, BoxFuture},
+    FutureExt,
+  };
+  use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::Duration,
+  };
+  use tokio::sync::broadcast;
+
+  /// Hardware that takes every write and subscription, and reads back zeros.
+  struct NullHardware {
+    event_sender: broadcast::Sender<HardwareEvent>,
+  }
+
+  impl HardwareInternal for NullHardware {
+    fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn event_stream(&self) -> broadcast::Receiver<HardwareEvent> {
+      self.event_sender.subscribe()
+    }
+
+    fn read_value(
+      &self,
+      msg: &HardwareReadCmd,
+    ) -> BoxFuture<'static, Result<HardwareReading, ButtplugDeviceError>> {
+      let reading = HardwareReading::new(msg.endpoint(), &vec![0; msg.length().max(1) as usize]);
+      future::ready(Ok(reading)).boxed()
+    }
+
+    fn write_value(
+      &self,
+      _msg: &HardwareWriteCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn subscribe(
+      &self,
+      _msg: &HardwareSubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+
+    fn unsubscribe(
+      &self,
+      _msg: &HardwareUnsubscribeCmd,
+    ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
+      future::ready(Ok(())).boxed()
+    }
+  }
+
+  fn null_hardware() -> Arc<Hardware> {
+    // Everything but the BLE battery endpoint, which the server handles for every protocol.
+    let endpoints = [
+      Endpoint::Command,
+      Endpoint::Feature,
+      Endpoint::Firmware,
+      Endpoint::Rx,
+      Endpoint::RxAccel,
+      Endpoint::RxPressure,
+      Endpoint::RxTouch,
+      Endpoint::Tx,
+      Endpoint::TxMode,
+      Endpoint::TxShock,
+      Endpoint::TxVibrate,
+      Endpoint::TxVendorControl,
+      Endpoint::Whitelist,
+    ];
+    Arc::new(Hardware::new(
+      "Test Device",
+      "test-address",
+      &endpoints,
+      Box::new(NullHardware {
+        event_sender: broadcast::channel(256).0,
+      }),
+    ))
+  }
+
+  /// Handler for the protocol, if it can be set up without a real device to talk to.
+  async fn build_handler(
+    factory: Arc<dyn ProtocolIdentifierFactory>,
+  ) -> Option<Arc<dyn ProtocolHandler>> {
+    let hardware = null_hardware();
+    let setup = async move {
+      let (_, mut initializer) = factory.create().identify(hardware.clone()).await.ok()?;
+      initializer
+        .initialize(
+          hardware,
+          &ProtocolDeviceAttributes::new(
+            ProtocolAttributesType::Default,
+            None,
+            None,
+            ServerDeviceMessageAttributes::default(),
+            None,
+          ),
+        )
+        .await
+        .ok()
+    };
+    tokio::time::timeout(Duration::from_secs(1), setup)
+      .await
+      .ok()
+      .flatten()
+  }
+
+  /// True if the handler implements the command itself, instead of falling back to the trait
+  /// default. Anything but the default's UnhandledCommand counts, including errors and panics from
+  /// the test commands not making sense for the protocol.
+  fn overridden<T>(handle: impl FnOnce() -> Result<T, ButtplugDeviceError>) -> bool {
+    !matches!(
+      panic::catch_unwind(AssertUnwindSafe(handle)),
+      Ok(Err(ButtplugDeviceError::UnhandledCommand(_)))
+    )
+  }
+
+  /// Same as [overridden], for sensor commands. The defaults fail right away, so anything still
+  /// waiting on the device has been overridden.
+  fn overridden_async<'a, T>(
+    handle: impl FnOnce() -> BoxFuture<'a, Result<T, ButtplugDeviceError>>,
+  ) -> bool {
+    overridden(|| {
+      handle()
+        .now_or_never()
+        .map_or(Ok(()), |result| result.map(|_| ()))
+    })
+  }
+
+  #[tokio::test]
+  async fn test_declared_capabilities_are_handled() {
+    let factories: Vec<_> = get_default_protocol_map().into_values().collect();
+    let handlers = future::join_all(factories.iter().cloned().map(build_handler)).await;
+    let mut checked = 0;
+    for (factory, handler) in factories.iter().zip(handlers) {
+      let Some(handler) = handler else {
+        continue;
+      };
+      checked += 1;
+      let protocol = factory.identifier();
+      let capabilities = factory.capabilities();
+      assert_eq!(
+        capabilities.handles_all_messages(),
+        handler.has_handle_message(),
+        "{}",
+        protocol
+      );
+      for actuator in capabilities.scalar() {
+        assert!(
+          overridden(|| handler.handle_scalar_cmd(&[Some((*actuator, 1)); 8])),
+          "{} declares {:?} scalar commands without handling them",
+          protocol,
+          actuator
+        );
+      }
+      if capabilities.rotate() {
+        assert!(
+          overridden(|| handler.handle_rotate_cmd(&[Some((1, true)); 8])),
+          "{} declares rotation without handling it",
+          protocol
+        );
+      }
+      if capabilities.linear() {
+        assert!(
+          overridden(|| handler
+            .handle_linear_cmd(LinearCmd::new(0, vec![VectorSubcommand::new(0, 500, 0.5)]))),
+          "{} declares linear commands without handling them",
+          protocol
+        );
+      }
+      for sensor in capabilities.sensor_read() {
+        assert!(
+          overridden_async(
+            || handler.handle_sensor_read_cmd(null_hardware(), SensorReadCmd::new(0, 0, *sensor))
+          ),
+          "{} declares {:?} sensor reads without handling them",
+          protocol,
+          sensor
+        );
+      }
+      for sensor in capabilities.sensor_subscribe() {
+        assert!(
+          overridden_async(|| handler
+            .handle_sensor_subscribe_cmd(null_hardware(), SensorSubscribeCmd::new(0, 0, *sensor))),
+          "{} declares {:?} sensor subscriptions without handling them",
+          protocol,
+          sensor
+        );
+      }
+    }
+    // Only a few protocols need a real device to finish setting up.
+    assert!(checked > factories.len() / 2);
+  }
+}
@@ -12,7 +12,7 @@ use crate::{
   core::{errors::ButtplugDeviceError, message::Endpoint},
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
 };
 
@@ -22,6 +22,10 @@ generic_protocol_setup!(Cowgirl, "cowgirl");
 pub struct Cowgirl {}
 
 impl ProtocolHandler for Cowgirl {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Rotate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
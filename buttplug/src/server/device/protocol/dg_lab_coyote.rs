@@ -0,0 +1,222 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! DG-Lab Coyote e-stim unit.
+//!
+//! The Coyote has two output channels, each exposed as an Estim scalar actuator setting channel
+//! power. Power for both channels goes out in a single packet, while each channel's waveform has
+//! its own characteristic. The waveform only plays for 100ms after it's written, so it's resent
+//! every 100ms for as long as a channel is on.
+//!
+//! Waveforms are made of bursts of X 1ms pulses followed by a Y ms pause, with each pulse Z * 5µs
+//! wide. Channels use a gentle default waveform, which can be changed with the "waveform-a" and
+//! "waveform-b" protocol options in the device's user config, i.e. `"waveform-a": [5, 95, 20]`.
+//!
+//! E-stim can hurt when power jumps suddenly, so the protocol requires power changes to be ramped,
+//! on top of whatever safety limits the server is configured with. Power is also capped at a fifth
+//! of the hardware maximum, until the server owner raises the max scalar for the device in their
+//! safety policy.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+      ProtocolKeepalive,
+    },
+    safety::SafetyLimits,
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::{
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+// Each waveform write plays for 100ms.
+const COYOTE_WAVEFORM_INTERVAL: Duration = Duration::from_millis(100);
+// Shortest time power changes can be spread over.
+const COYOTE_MIN_RAMP_TIME: Duration = Duration::from_secs(1);
+// Channel power is 11 bits.
+const COYOTE_MAX_POWER: u32 = 2047;
+// Highest power, out of 1.0, sent unless the device's safety limits say otherwise.
+const COYOTE_DEFAULT_MAX_SCALAR: f64 = 0.2;
+
+generic_protocol_initializer_setup!(DGLabCoyote, "dg-lab-coyote");
+
+#[derive(Default)]
+pub struct DGLabCoyoteInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for DGLabCoyoteInitializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let waveform = |option: &str| {
+      attributes
+        .protocol_option(option)
+        .map_or(CoyoteWaveform::default(), |values| {
+          CoyoteWaveform::from_option(&values)
+        })
+    };
+    Ok(Arc::new(DGLabCoyote {
+      channels: [
+        CoyoteChannel::new(Endpoint::Generic0, waveform("waveform-a")),
+        CoyoteChannel::new(Endpoint::Generic1, waveform("waveform-b")),
+      ],
+    }))
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CoyoteWaveform {
+  /// Pulses per burst, 0-31.
+  x: u32,
+  /// Pause after each burst in ms, 0-1023.
+  y: u32,
+  /// Pulse width in 5µs units, 0-31.
+  z: u32,
+}
+
+impl Default for CoyoteWaveform {
+  fn default() -> Self {
+    Self { x: 1, y: 9, z: 20 }
+  }
+}
+
+impl CoyoteWaveform {
+  /// Read waveform values from a protocol option, in X, Y, Z order, keeping the default for any
+  /// that are missing.
+  fn from_option(values: &[u32]) -> Self {
+    let default = Self::default();
+    let value =
+      |index: usize, default: u32, max: u32| values.get(index).map_or(default, |v| (*v).min(max));
+    Self {
+      x: value(0, default.x, 31),
+      y: value(1, default.y, 1023),
+      z: value(2, default.z, 31),
+    }
+  }
+
+  /// Packed as Z (bits 19-15), Y (bits 14-5), X (bits 4-0), little endian.
+  fn encode(&self) -> Vec<u8> {
+    let packed = self.z << 15 | self.y << 5 | self.x;
+    packed.to_le_bytes()[0..3].to_vec()
+  }
+}
+
+struct CoyoteChannel {
+  power: AtomicU32,
+  waveform_endpoint: Endpoint,
+  waveform: CoyoteWaveform,
+}
+
+impl CoyoteChannel {
+  fn new(waveform_endpoint: Endpoint, waveform: CoyoteWaveform) -> Self {
+    Self {
+      power: AtomicU32::new(0),
+      waveform_endpoint,
+      waveform,
+    }
+  }
+}
+
+pub struct DGLabCoyote {
+  channels: [CoyoteChannel; 2],
+}
+
+impl DGLabCoyote {
+  fn waveform_commands(&self) -> Vec<HardwareCommand> {
+    self
+      .channels
+      .iter()
+      .filter(|channel| channel.power.load(Ordering::SeqCst) > 0)
+      .map(|channel| {
+        HardwareWriteCmd::new(channel.waveform_endpoint, channel.waveform.encode(), false).into()
+      })
+      .collect()
+  }
+}
+
+impl ProtocolHandler for DGLabCoyote {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Estim])
+  }
+
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::while_active(COYOTE_WAVEFORM_INTERVAL))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(self.waveform_commands())
+  }
+
+  fn safety_limits(&self) -> SafetyLimits {
+    let mut limits = SafetyLimits::default();
+    limits.set_min_ramp_time(Some(COYOTE_MIN_RAMP_TIME));
+    limits
+  }
+
+  fn default_safety_limits(&self) -> SafetyLimits {
+    let mut limits = SafetyLimits::default();
+    limits.set_max_scalar(Some(COYOTE_DEFAULT_MAX_SCALAR));
+    limits
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    for (channel, command) in self.channels.iter().zip(commands) {
+      if let Some((_, power)) = command {
+        channel
+          .power
+          .store((*power).min(COYOTE_MAX_POWER), Ordering::SeqCst);
+      }
+    }
+    // Power is packed as channel A (bits 21-11), channel B (bits 10-0), little endian.
+    let packed = self.channels[0].power.load(Ordering::SeqCst) << 11
+      | self.channels[1].power.load(Ordering::SeqCst);
+    let mut hardware_commands: Vec<HardwareCommand> =
+      vec![HardwareWriteCmd::new(Endpoint::Tx, packed.to_le_bytes()[0..3].to_vec(), false).into()];
+    hardware_commands.extend(self.waveform_commands());
+    Ok(hardware_commands)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_coyote_waveform_encoding() {
+    assert_eq!(CoyoteWaveform::default().encode(), vec![0x21, 0x01, 0x0a]);
+    assert_eq!(
+      CoyoteWaveform::from_option(&[5, 95, 40]),
+      CoyoteWaveform { x: 5, y: 95, z: 31 }
+    );
+    assert_eq!(
+      CoyoteWaveform::from_option(&[5]),
+      CoyoteWaveform { x: 5, y: 9, z: 20 }
+    );
+  }
+}
@@ -0,0 +1,85 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Virtual device that sets channels in a DMX universe, through the DMX communication manager. Used
+//! for installations where haptic hardware is driven by DMX dimmers or controllers.
+//!
+//! Each scalar actuator is a DMX channel, in order from the start channel set on the DMX
+//! definition, with values clamped to 0-255. Every update writes all channels to Tx, one byte per
+//! channel.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    hardware::{HardwareCommand, HardwareWriteCmd},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
+  },
+};
+
+generic_protocol_setup!(Dmx, "dmx");
+
+#[derive(Default)]
+pub struct Dmx {}
+
+impl ProtocolHandler for Dmx {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
+  fn needs_full_command_set(&self) -> bool {
+    true
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let channels = commands
+      .iter()
+      .map(|command| command.map_or(0, |(_, value)| value.min(u8::MAX as u32) as u8))
+      .collect();
+    Ok(vec![
+      HardwareWriteCmd::new(Endpoint::Tx, channels, false).into()
+    ])
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::setup::DmxIdentifierFactory;
+  use crate::{
+    core::message::{ActuatorType, Endpoint},
+    server::device::hardware::scripted::{send_commands, setup_protocol, HardwareScript},
+  };
+
+  #[tokio::test]
+  async fn test_dmx_channels() {
+    let mut script = HardwareScript::default();
+    script.expect_write(Endpoint::Tx, &[255, 0, 128, 255]);
+    let hardware = script.build_hardware("DMX Fixture", "dmx-test");
+    let (identifier, handler) = setup_protocol(&DmxIdentifierFactory::default(), hardware.clone())
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(identifier.protocol(), "dmx");
+
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 255)),
+        Some((ActuatorType::Vibrate, 0)),
+        Some((ActuatorType::Vibrate, 128)),
+        Some((ActuatorType::Vibrate, 1000)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+}
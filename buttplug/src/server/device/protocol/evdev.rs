@@ -13,34 +13,44 @@ use crate::{
     message::{ActuatorType, Endpoint},
   },
   server::device::{
-    hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    hardware::{waveform::HapticWaveform, HardwareCommand, HardwareWriteCmd},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler, ProtocolKeepalive},
   },
 };
 use byteorder::WriteBytesExt;
+use std::{
+  sync::atomic::{AtomicI16, AtomicU16, Ordering},
+  time::Duration,
+};
 
 generic_protocol_setup!(Evdev, "evdev");
 
-#[derive(Default)]
-pub struct Evdev {}
+// Force feedback effects only play for as long as their length, so they're refreshed before they
+// run out for as long as the device should keep vibrating.
+const EVDEV_EFFECT_LENGTH_MS: u16 = 1000;
+const EVDEV_KEEPALIVE_INTERVAL: Duration = Duration::from_millis(500);
 
-impl ProtocolHandler for Evdev {
-  fn needs_full_command_set(&self) -> bool {
-    true
-  }
+/// Devices configured with a Frequency actuator play a periodic sine effect instead of a rumble.
+/// The actuator's step range is in Hz, and its value is sent to the hardware as the period of the
+/// wave, after the magnitude and effect length. A period of 0 asks for a plain rumble.
+#[derive(Default)]
+pub struct Evdev {
+  magnitude: AtomicI16,
+  period_ms: AtomicU16,
+}
 
-  fn handle_scalar_cmd(
+impl Evdev {
+  fn effect_command(
     &self,
-    cmds: &[Option<(ActuatorType, u32)>],
+    magnitude: i16,
+    period_ms: u16,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
     let mut cmd = vec![];
-    if cmd
-      .write_i16::<LittleEndian>(
-        cmds[0]
-          .expect(":3")
-          .1 as i16,
-      )
-      .is_err()
+    if cmd.write_i16::<LittleEndian>(magnitude).is_err()
+      || cmd
+        .write_u16::<LittleEndian>(EVDEV_EFFECT_LENGTH_MS)
+        .is_err()
+      || (period_ms != 0 && cmd.write_u16::<LittleEndian>(period_ms).is_err())
     {
       return Err(ButtplugDeviceError::ProtocolSpecificError(
         "Evdev".to_owned(),
@@ -50,3 +60,128 @@ impl ProtocolHandler for Evdev {
     Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, cmd, false).into()])
   }
 }
+
+impl ProtocolHandler for Evdev {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Frequency])
+  }
+
+  fn needs_full_command_set(&self) -> bool {
+    true
+  }
+
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::while_active(EVDEV_KEEPALIVE_INTERVAL))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.effect_command(
+      self.magnitude.load(Ordering::Relaxed),
+      self.period_ms.load(Ordering::Relaxed),
+    )
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    cmds: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let magnitude = cmds[0].expect(":3").1 as i16;
+    let period_ms = cmds
+      .iter()
+      .flatten()
+      .find(|(actuator, _)| *actuator == ActuatorType::Frequency)
+      .and_then(|(_, frequency)| HapticWaveform::new(1.0, *frequency as f64).period_ms())
+      .unwrap_or(0);
+    self.magnitude.store(magnitude, Ordering::Relaxed);
+    self.period_ms.store(period_ms, Ordering::Relaxed);
+    self.effect_command(magnitude, period_ms)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::setup::EvdevIdentifierFactory;
+  use crate::{
+    core::message::{ActuatorType, Endpoint},
+    server::device::hardware::scripted::{send_commands, setup_protocol, HardwareScript},
+  };
+
+  #[tokio::test]
+  async fn test_scalar_and_keepalive() {
+    let mut script = HardwareScript::default();
+    // Magnitude, then effect length, both little endian.
+    script
+      .expect_write(Endpoint::Tx, &[0x00, 0x40, 0xe8, 0x03])
+      .expect_write(Endpoint::Tx, &[0x00, 0x40, 0xe8, 0x03])
+      .expect_write(Endpoint::Tx, &[0x00, 0x00, 0xe8, 0x03]);
+    let hardware = script.build_hardware("Evdev Test", "evdev-test");
+    let (identifier, handler) =
+      setup_protocol(&EvdevIdentifierFactory::default(), hardware.clone())
+        .await
+        .expect("Test, assuming infallible.");
+    assert_eq!(identifier.protocol(), "evdev");
+    assert!(handler.keepalive().is_some());
+
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 0x4000)),
+        Some((ActuatorType::Vibrate, 0)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    // Keepalives refresh the effect with the last magnitude.
+    send_commands(&hardware, handler.handle_keepalive())
+      .await
+      .expect("Test, assuming infallible.");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 0)),
+        Some((ActuatorType::Vibrate, 0)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_frequency_waveform() {
+    let mut script = HardwareScript::default();
+    // Magnitude, effect length, then the 100Hz frequency as a 10ms period.
+    script
+      .expect_write(Endpoint::Tx, &[0x00, 0x40, 0xe8, 0x03, 0x0a, 0x00])
+      .expect_write(Endpoint::Tx, &[0x00, 0x40, 0xe8, 0x03, 0x0a, 0x00])
+      .expect_write(Endpoint::Tx, &[0x00, 0x40, 0xe8, 0x03]);
+    let hardware = script.build_hardware("Evdev Test", "evdev-test");
+    let (_, handler) = setup_protocol(&EvdevIdentifierFactory::default(), hardware.clone())
+      .await
+      .expect("Test, assuming infallible.");
+
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 0x4000)),
+        Some((ActuatorType::Frequency, 100)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    send_commands(&hardware, handler.handle_keepalive())
+      .await
+      .expect("Test, assuming infallible.");
+    // A frequency of 0 goes back to rumbling.
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 0x4000)),
+        Some((ActuatorType::Frequency, 0)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+}
@@ -6,12 +6,16 @@
 // for full license information.
 
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -54,6 +58,10 @@ pub struct Foreo {
 }
 
 impl ProtocolHandler for Foreo {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
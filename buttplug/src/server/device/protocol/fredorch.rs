@@ -17,6 +17,7 @@ use crate::{
     protocol::{
       fleshlight_launch_helper::calculate_speed,
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -127,10 +128,10 @@ impl ProtocolInitializer for FredorchInitializer {
           debug!("Fredorch: wake up - received {:?}", n);
         } else {
           return Err(
-            ButtplugDeviceError::ProtocolSpecificError(
-              "Fredorch".to_owned(),
-              "Fredorch Device disconnected while initialising.".to_owned(),
-            )
+            ButtplugDeviceError::DeviceDisconnected {
+              address: hardware.address().to_owned(),
+              protocol: "fredorch".to_owned(),
+            }
           );
         }
       }
@@ -154,19 +155,19 @@ impl ProtocolInitializer for FredorchInitializer {
             debug!("Fredorch: {} - received {:?}", data.0, n);
           } else {
             return Err(
-              ButtplugDeviceError::ProtocolSpecificError(
-                "Fredorch".to_owned(),
-                "Fredorch Device disconnected while initialising.".to_owned(),
-              )
+              ButtplugDeviceError::DeviceDisconnected {
+                address: hardware.address().to_owned(),
+                protocol: "fredorch".to_owned(),
+              }
             );
           }
         }
         _ = sleep(Duration::from_millis(FREDORCH_COMMAND_TIMEOUT_MS)).fuse() => {
           return Err(
-              ButtplugDeviceError::ProtocolSpecificError(
-                "Fredorch".to_owned(),
-                "Fredorch Device timed out while initialising.".to_owned(),
-              )
+              ButtplugDeviceError::HandshakeTimeout {
+                address: hardware.address().to_owned(),
+                protocol: "fredorch".to_owned(),
+              }
             );
         }
       }
@@ -182,6 +183,10 @@ pub struct Fredorch {
 }
 
 impl ProtocolHandler for Fredorch {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_linear()
+  }
+
   fn handle_linear_cmd(
     &self,
     message: message::LinearCmd,
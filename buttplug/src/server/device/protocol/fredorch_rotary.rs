@@ -7,12 +7,16 @@
 
 use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareEvent, HardwareSubscribeCmd, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -87,10 +91,10 @@ impl ProtocolInitializer for FredorchRotaryInitializer {
             debug!("FredorchRotary: {} - received {:?}", data.0, n);
           } else {
             return Err(
-              ButtplugDeviceError::ProtocolSpecificError(
-                "FredorchRotary".to_owned(),
-                "FredorchRotary Device disconnected while initialising.".to_owned(),
-              )
+              ButtplugDeviceError::DeviceDisconnected {
+                address: hardware.address().to_owned(),
+                protocol: "fredorch-rotary".to_owned(),
+              }
             );
           }
         }
@@ -192,6 +196,10 @@ impl FredorchRotary {
 }
 
 impl ProtocolHandler for FredorchRotary {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Oscillate])
+  }
+
   fn handle_scalar_oscillate_cmd(
     &self,
     _index: u32,
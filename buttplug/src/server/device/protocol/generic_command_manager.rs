@@ -24,14 +24,25 @@ use getset::Getters;
 use std::{
   ops::RangeInclusive,
   sync::atomic::{AtomicBool, AtomicU32, Ordering::SeqCst},
+  time::Duration,
 };
 
+/// Shortest time between intermediate updates while ramping a scalar actuator. Most hardware can't
+/// take updates much faster than this anyways.
+const SCALAR_RAMP_MIN_INTERVAL: Duration = Duration::from_millis(50);
+
 #[derive(Getters)]
 #[getset(get = "pub")]
 struct ScalarGenericCommand {
   actuator: ActuatorType,
   step_range: RangeInclusive<u32>,
+  /// Steps clients can pick from, which is the size of the step range unless the user configured
+  /// fewer.
+  step_count: u32,
   value: AtomicU32,
+  /// Last value actually sent to the hardware. Only tracked when ramping is on, where it trails
+  /// value until the ramp finishes.
+  output: AtomicU32,
 }
 
 impl ScalarGenericCommand {
@@ -39,7 +50,9 @@ impl ScalarGenericCommand {
     Self {
       actuator: *attributes.actuator_type(),
       step_range: attributes.step_range().clone(),
+      step_count: attributes.step_count(),
       value: AtomicU32::new(0),
+      output: AtomicU32::new(0),
     }
   }
 }
@@ -62,6 +75,7 @@ pub struct GenericCommandManager {
   _linears: Vec<(u32, u32)>,
   _linear_step_counts: Vec<u32>,
   stop_commands: Vec<ButtplugDeviceCommandMessageUnion>,
+  scalar_ramp_time: Option<Duration>,
 }
 
 impl GenericCommandManager {
@@ -110,7 +124,14 @@ impl GenericCommandManager {
       }
     }
 
+    // A ramp time of 0 is the same as no ramping.
+    let scalar_ramp_time = attributes
+      .scalar_ramp_time_ms()
+      .map(|ms| Duration::from_millis(ms as u64))
+      .filter(|ramp_time| !ramp_time.is_zero());
+
     Self {
+      scalar_ramp_time,
       sent_scalar: AtomicBool::new(false),
       sent_rotation: AtomicBool::new(false),
       _sent_linear: false,
@@ -140,7 +161,9 @@ impl GenericCommandManager {
     }
 
     // Now we convert from the generic 0.0-1.0 range to the StepCount
-    // attribute given by the device config.
+    // attribute given by the device config. If the user set a lower step
+    // count, values are quantized to that first, then spread over the
+    // device's step range.
 
     // If we've already sent commands before, we should check against our
     // old values. Otherwise, we should always send whatever command we're
@@ -164,19 +187,22 @@ impl GenericCommandManager {
 
       let range_start = self.scalars[index].step_range().start();
       let range = self.scalars[index].step_range().end() - range_start;
-      let scalar_modifier = scalar_command.scalar() * range as f64;
+      let step_count = *self.scalars[index].step_count();
+      let scalar_modifier = scalar_command.scalar() * step_count as f64;
       let scalar = if scalar_modifier < 0.0001 {
         0
       } else {
         // When calculating speeds, round up. This follows how we calculated
         // things in buttplug-js and buttplug-csharp, so it's more for history
         // than anything, but it's what users will expect.
-        (scalar_modifier + *range_start as f64).ceil() as u32
+        let step = scalar_modifier.ceil() as u32;
+        (step * range).div_ceil(step_count) + range_start
       };
       trace!(
-        "{:?} {} {} {}",
+        "{:?} {} {} {} {}",
         self.scalars[index].step_range(),
         range,
+        step_count,
         scalar_modifier,
         scalar
       );
@@ -213,6 +239,96 @@ impl GenericCommandManager {
     Ok(result)
   }
 
+  /// Time scalar changes should be spread over, if the device is configured to ramp.
+  pub fn scalar_ramp_time(&self) -> Option<Duration> {
+    self.scalar_ramp_time
+  }
+
+  /// Make sure scalar changes are spread over at least the given time. Longer configured ramp
+  /// times are kept.
+  pub fn set_min_scalar_ramp_time(&mut self, min_ramp_time: Duration) {
+    self.scalar_ramp_time = Some(
+      self
+        .scalar_ramp_time
+        .map_or(min_ramp_time, |ramp_time| ramp_time.max(min_ramp_time)),
+    )
+    .filter(|ramp_time| !ramp_time.is_zero());
+  }
+
+  /// Split a set of scalar updates (as returned from [GenericCommandManager::update_scalar]) into a
+  /// series of intermediate updates, moving linearly from the values last sent to the hardware to
+  /// the new values over the ramp time. Updates should be sent
+  /// [GenericCommandManager::scalar_ramp_time] / frame count apart, and recorded via
+  /// [GenericCommandManager::set_scalar_output] as they go out. The last update is always the
+  /// command set passed in.
+  pub fn scalar_ramp_frames(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+    match_all: bool,
+  ) -> Vec<Vec<Option<(ActuatorType, u32)>>> {
+    let Some(ramp_time) = self.scalar_ramp_time else {
+      return vec![commands.to_vec()];
+    };
+    let starts: Vec<u32> = self
+      .scalars
+      .iter()
+      .map(|scalar| scalar.output().load(SeqCst))
+      .collect();
+    let max_delta = commands
+      .iter()
+      .zip(starts.iter())
+      .filter_map(|(command, start)| command.map(|(_, target)| target.abs_diff(*start)))
+      .max()
+      .unwrap_or(0);
+    // Never emit more frames than there are steps to move through, otherwise we'd send duplicate
+    // values.
+    let frame_count = ((ramp_time.as_millis() / SCALAR_RAMP_MIN_INTERVAL.as_millis()) as u32)
+      .min(max_delta)
+      .max(1);
+
+    let mut frames = vec![];
+    let mut previous = starts.clone();
+    for frame in 1..=frame_count {
+      let progress = frame as f64 / frame_count as f64;
+      let mut values = vec![None; commands.len()];
+      for (index, command) in commands.iter().enumerate() {
+        let Some((actuator, target)) = command else {
+          continue;
+        };
+        let start = starts[index] as f64;
+        let mut value = (start + (*target as f64 - start) * progress).round() as u32;
+        // Intermediate values still need to land inside the actuator's step range.
+        let range_start = *self.scalars[index].step_range().start();
+        if value != 0 && value < range_start {
+          value = range_start;
+        }
+        if match_all || frame == frame_count || value != previous[index] {
+          values[index] = Some((*actuator, value));
+        }
+        previous[index] = value;
+      }
+      if values.iter().any(|x| x.is_some()) {
+        frames.push(values);
+      }
+    }
+    frames
+  }
+
+  /// Record scalar values that have been sent to the hardware, so ramps know where to start from.
+  pub fn set_scalar_output(&self, commands: &[Option<(ActuatorType, u32)>]) {
+    for (scalar, command) in self.scalars.iter().zip(commands.iter()) {
+      if let Some((_, value)) = command {
+        scalar.output().store(*value, SeqCst);
+      }
+    }
+  }
+
+  /// Returns true if any scalar or rotation actuator was last set to something other than off.
+  pub fn has_active_output(&self) -> bool {
+    self.scalars.iter().any(|x| x.value().load(SeqCst) != 0)
+      || self.rotations.iter().any(|x| x.0.load(SeqCst) != 0)
+  }
+
   // Test method
   #[cfg(test)]
   pub(super) fn scalars(&self) -> Vec<Option<(ActuatorType, u32)>> {
@@ -559,6 +675,113 @@ mod test {
     );
   }
 
+  #[test]
+  pub fn test_command_generator_vibration_user_step_count() {
+    let mut vibrate_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(10, 30),
+      ActuatorType::Vibrate,
+    );
+    vibrate_attrs.set_user_step_count(Some(4));
+    let vibrate_attributes = ServerDeviceMessageAttributesBuilder::default()
+      .scalar_cmd(&[vibrate_attrs])
+      .finish();
+    let device_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      None,
+      None,
+      vibrate_attributes,
+      None,
+    );
+    let mgr = GenericCommandManager::new(&device_attributes);
+    // Values are quantized to 4 steps, each 5 device steps apart.
+    for (scalar, expected) in [
+      (0.1, 15),
+      (0.3, 20),
+      (0.5, 20),
+      (0.6, 25),
+      (1.0, 30),
+      (0.0, 0),
+    ] {
+      let vibrate_msg = ScalarCmd::new(
+        0,
+        vec![ScalarSubcommand::new(0, scalar, ActuatorType::Vibrate)],
+      );
+      mgr
+        .update_scalar(&vibrate_msg, false)
+        .expect("Test, assuming infallible");
+      assert_eq!(mgr.scalars(), vec![Some((ActuatorType::Vibrate, expected))]);
+    }
+  }
+
+  #[test]
+  pub fn test_command_generator_scalar_ramp() {
+    let scalar_attrs = ServerGenericDeviceMessageAttributes::new(
+      "Test",
+      &RangeInclusive::new(0, 20),
+      ActuatorType::Vibrate,
+    );
+    let scalar_attributes = ServerDeviceMessageAttributesBuilder::default()
+      .scalar_cmd(&[scalar_attrs.clone(), scalar_attrs])
+      .finish();
+    let mut device_attributes = ProtocolDeviceAttributes::new(
+      ProtocolAttributesType::Default,
+      None,
+      None,
+      scalar_attributes,
+      None,
+    );
+    device_attributes.set_scalar_ramp_time_ms(Some(200));
+    let mgr = GenericCommandManager::new(&device_attributes);
+    let vibrate_msg = ScalarCmd::new(
+      0,
+      vec![ScalarSubcommand::new(0, 1.0, ActuatorType::Vibrate)],
+    );
+    let commands = mgr
+      .update_scalar(&vibrate_msg, false)
+      .expect("Test, assuming infallible");
+    let frames = mgr.scalar_ramp_frames(&commands, false);
+    assert_eq!(
+      frames,
+      vec![
+        vec![Some((ActuatorType::Vibrate, 5)), None],
+        vec![Some((ActuatorType::Vibrate, 10)), None],
+        vec![Some((ActuatorType::Vibrate, 15)), None],
+        vec![Some((ActuatorType::Vibrate, 20)), None],
+      ]
+    );
+    // Pretend the ramp was interrupted halfway through, the next ramp should start from there.
+    mgr.set_scalar_output(&frames[1]);
+    let vibrate_msg = ScalarCmd::new(
+      0,
+      vec![ScalarSubcommand::new(0, 0.0, ActuatorType::Vibrate)],
+    );
+    let commands = mgr
+      .update_scalar(&vibrate_msg, true)
+      .expect("Test, assuming infallible");
+    assert_eq!(
+      mgr.scalar_ramp_frames(&commands, true),
+      vec![
+        vec![
+          Some((ActuatorType::Vibrate, 8)),
+          Some((ActuatorType::Vibrate, 0))
+        ],
+        vec![
+          Some((ActuatorType::Vibrate, 5)),
+          Some((ActuatorType::Vibrate, 0))
+        ],
+        vec![
+          Some((ActuatorType::Vibrate, 3)),
+          Some((ActuatorType::Vibrate, 0))
+        ],
+        vec![
+          Some((ActuatorType::Vibrate, 0)),
+          Some((ActuatorType::Vibrate, 0))
+        ],
+      ]
+    );
+  }
+
   #[test]
   pub fn test_command_generator_rotation() {
     let rotate_attrs = ServerGenericDeviceMessageAttributes::new(
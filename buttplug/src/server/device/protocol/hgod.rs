@@ -14,13 +14,14 @@ use crate::{
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
+      ProtocolKeepalive,
     },
     ServerDeviceIdentifier,
   },
-  util::{async_manager, sleep},
 };
 use async_trait::async_trait;
 use std::{
@@ -31,8 +32,8 @@ use std::{
   time::Duration,
 };
 
-// Time between Hgod update commands, in milliseconds.
-const HGOD_COMMAND_DELAY_MS: u64 = 100;
+// HGod toys vibes only last ~100ms seconds.
+const HGOD_COMMAND_DELAY: Duration = Duration::from_millis(100);
 
 generic_protocol_initializer_setup!(Hgod, "hgod");
 
@@ -43,52 +44,41 @@ pub struct HgodInitializer {}
 impl ProtocolInitializer for HgodInitializer {
   async fn initialize(
     &mut self,
-    hardware: Arc<Hardware>,
+    _: Arc<Hardware>,
     _: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    Ok(Arc::new(Hgod::new(hardware)))
+    Ok(Arc::new(Hgod::default()))
   }
 }
 
+#[derive(Default)]
 pub struct Hgod {
-  last_command: Arc<AtomicU8>,
+  last_command: AtomicU8,
 }
 
 impl Hgod {
-  fn new(hardware: Arc<Hardware>) -> Self {
-    let last_command = Arc::new(AtomicU8::new(0));
+  fn speed_command(&self) -> Vec<HardwareCommand> {
+    let speed = self.last_command.load(Ordering::SeqCst);
+    if speed == 0 {
+      return vec![];
+    }
+    vec![HardwareWriteCmd::new(Endpoint::Tx, vec![0x55, 0x04, 0, 0, 0, speed], false).into()]
+  }
+}
 
-    let last_command_clone = last_command.clone();
-    async_manager::spawn(async move {
-      send_hgod_updates(hardware, last_command_clone).await;
-    });
+impl ProtocolHandler for Hgod {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
 
-    Self { last_command }
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::while_active(HGOD_COMMAND_DELAY))
   }
-}
 
-// HGod toys vibes only last ~100ms seconds.
-async fn send_hgod_updates(device: Arc<Hardware>, data: Arc<AtomicU8>) {
-  loop {
-    let speed = data.load(Ordering::SeqCst);
-    let command = vec![0x55, 0x04, 0, 0, 0, speed];
-    if speed > 0 {
-      if let Err(e) = device
-        .write_value(&HardwareWriteCmd::new(Endpoint::Tx, command, false))
-        .await
-      {
-        error!(
-          "Got an error from a hgod device, exiting control loop: {:?}",
-          e
-        );
-        break;
-      }
-    }
-    sleep(Duration::from_millis(HGOD_COMMAND_DELAY_MS)).await;
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(self.speed_command())
   }
-}
 
-impl ProtocolHandler for Hgod {
   fn handle_scalar_cmd(
     &self,
     commands: &[Option<(ActuatorType, u32)>],
@@ -96,6 +86,6 @@ impl ProtocolHandler for Hgod {
     if let Some(cmd) = commands[0] {
       self.last_command.store(cmd.1 as u8, Ordering::SeqCst);
     }
-    Ok(vec![])
+    Ok(self.speed_command())
   }
 }
@@ -7,19 +7,31 @@
 
 use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{ProtocolCapabilities, ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    SafetyLimits,
     ServerDeviceIdentifier,
   },
 };
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+// Shortest time machine speed changes can be spread over, so big jumps don't yank the stroker.
+const HISMITH_MIN_RAMP_TIME: Duration = Duration::from_millis(1500);
 
 pub mod setup {
-  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
   #[derive(Default)]
   pub struct HismithIdentifierFactory {}
 
@@ -31,6 +43,10 @@ pub mod setup {
     fn create(&self) -> Box<dyn ProtocolIdentifier> {
       Box::new(super::HismithIdentifier::default())
     }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::Hismith as ProtocolHandler>::capabilities()
+    }
   }
 }
 
@@ -73,20 +89,55 @@ impl ProtocolInitializer for HismithInitializer {
   async fn initialize(
     &mut self,
     _: Arc<Hardware>,
-    _: &ProtocolDeviceAttributes,
+    attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    Ok(Arc::new(Hismith::default()))
+    // Only machines with stroke adjustment list a Position feature in the device config.
+    let stroke_adjustable = attributes
+      .message_attributes()
+      .scalar_cmd()
+      .as_ref()
+      .is_some_and(|attrs| {
+        attrs
+          .iter()
+          .any(|attr| *attr.actuator_type() == ActuatorType::Position)
+      });
+    Ok(Arc::new(Hismith::new(stroke_adjustable)))
   }
 }
 
 #[derive(Default)]
-pub struct Hismith {}
+pub struct Hismith {
+  stroke_adjustable: bool,
+}
+
+impl Hismith {
+  pub fn new(stroke_adjustable: bool) -> Self {
+    Self { stroke_adjustable }
+  }
+}
 
 impl ProtocolHandler for Hismith {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[
+      ActuatorType::Vibrate,
+      ActuatorType::Oscillate,
+      ActuatorType::Position,
+    ])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
 
+  fn safety_limits(&self) -> SafetyLimits {
+    let mut limits = SafetyLimits::default();
+    // Stroke adjustable machines are the big ones, so ramp their speed instead of jumping.
+    if self.stroke_adjustable {
+      limits.set_min_ramp_time(Some(HISMITH_MIN_RAMP_TIME));
+    }
+    limits
+  }
+
   fn handle_scalar_oscillate_cmd(
     &self,
     _index: u32,
@@ -124,4 +175,37 @@ impl ProtocolHandler for Hismith {
     )
     .into()])
   }
+
+  fn handle_scalar_position_cmd(
+    &self,
+    _index: u32,
+    scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    // The machine sets how far it strokes, not where it is.
+    let idx: u8 = 0x05;
+    let stroke: u8 = scalar as u8;
+
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0xAA, idx, stroke, stroke + idx],
+      false,
+    )
+    .into()])
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::Hismith;
+  use crate::server::device::protocol::ProtocolHandler;
+  use std::time::Duration;
+
+  #[test]
+  fn test_stroke_machines_ramp_speed() {
+    assert_eq!(Hismith::default().safety_limits().min_ramp_time(), None);
+    assert_eq!(
+      Hismith::new(true).safety_limits().min_ramp_time(),
+      Some(Duration::from_millis(1500))
+    );
+  }
 }
@@ -8,11 +8,14 @@
 use crate::core::message::ActuatorType::Vibrate;
 use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{ProtocolCapabilities, ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
     ServerDeviceIdentifier,
   },
 };
@@ -20,7 +23,12 @@ use async_trait::async_trait;
 use std::sync::Arc;
 
 pub mod setup {
-  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
   #[derive(Default)]
   pub struct HismithMiniIdentifierFactory {}
 
@@ -32,6 +40,10 @@ pub mod setup {
     fn create(&self) -> Box<dyn ProtocolIdentifier> {
       Box::new(super::HismithMiniIdentifier::default())
     }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::HismithMini as ProtocolHandler>::capabilities()
+    }
   }
 }
 
@@ -96,6 +108,14 @@ pub struct HismithMini {
 }
 
 impl ProtocolHandler for HismithMini {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[
+      ActuatorType::Vibrate,
+      ActuatorType::Oscillate,
+      ActuatorType::Constrict,
+    ])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -17,6 +17,7 @@ use crate::{
       hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
       protocol::{
         ProtocolAttributesType,
+        ProtocolCapabilities,
         ProtocolHandler,
         ProtocolIdentifier,
         ProtocolInitializer,
@@ -104,6 +105,15 @@ impl JoyHub {
 }
 
 impl ProtocolHandler for JoyHub {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[
+      ActuatorType::Vibrate,
+      ActuatorType::Rotate,
+      ActuatorType::Oscillate,
+      ActuatorType::Constrict,
+    ])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
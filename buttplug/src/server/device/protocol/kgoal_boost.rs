@@ -21,7 +21,7 @@ use crate::{
   },
   server::device::{
     hardware::{Hardware, HardwareEvent, HardwareSubscribeCmd, HardwareUnsubscribeCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, parsing::u16_be_at, ProtocolCapabilities, ProtocolHandler},
   },
   util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
@@ -36,6 +36,14 @@ use tokio::sync::broadcast;
 
 generic_protocol_setup!(KGoalBoost, "kgoal-boost");
 
+/// Normalized and unnormalized pressure from a pressure notification.
+pub fn parse_pressure(data: &[u8]) -> Result<(i32, i32), ButtplugDeviceError> {
+  Ok((
+    u16_be_at("kgoal-boost", data, 3)? as i32,
+    u16_be_at("kgoal-boost", data, 5)? as i32,
+  ))
+}
+
 pub struct KGoalBoost {
   // Set of sensors we've subscribed to for updates.
   subscribed_sensors: Arc<DashSet<u32>>,
@@ -53,6 +61,10 @@ impl Default for KGoalBoost {
 }
 
 impl ProtocolHandler for KGoalBoost {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_sensor_subscribe(&[SensorType::Pressure])
+  }
+
   fn event_stream(
     &self,
   ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
@@ -94,14 +106,15 @@ impl ProtocolHandler for KGoalBoost {
             }
             if let HardwareEvent::Notification(_, endpoint, data) = info {
               if endpoint == Endpoint::RxPressure {
-                if data.len() < 7 {
-                  // Not even sure how this would happen, error and continue on.
-                  error!("KGoal Boost data not expected length!");
-                  continue;
-                }
                 // Extract our two pressure values.
-                let normalized = (data[3] as i32) << 8 | data[4] as i32;
-                let unnormalized = (data[5] as i32) << 8 | data[6] as i32;
+                let (normalized, unnormalized) = match parse_pressure(&data) {
+                  Ok(values) => values,
+                  Err(err) => {
+                    // Not even sure how this would happen, error and continue on.
+                    error!("KGoal Boost data not expected length: {}", err);
+                    continue;
+                  }
+                };
                 if stream_sensors.contains(&0)
                   && sender
                     .send(
@@ -17,6 +17,7 @@ use crate::{
     protocol::{
       fleshlight_launch_helper::calculate_speed,
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -54,6 +55,10 @@ pub struct KiirooV2 {
 }
 
 impl ProtocolHandler for KiirooV2 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_linear()
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -11,6 +11,7 @@ use crate::{
     errors::ButtplugDeviceError,
     message::{
       self,
+      ActuatorType,
       ButtplugDeviceMessage,
       ButtplugMessage,
       ButtplugServerDeviceMessage,
@@ -30,7 +31,7 @@ use crate::{
       HardwareUnsubscribeCmd,
       HardwareWriteCmd,
     },
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, parsing::byte_at, ProtocolCapabilities, ProtocolHandler},
   },
   util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
@@ -52,6 +53,30 @@ use tokio::sync::broadcast;
 
 generic_protocol_setup!(KiirooV21, "kiiroo-v21");
 
+/// Battery level from the whitelist endpoint, which is byte 5. All other bytes of the 20-byte
+/// result are unknown.
+pub fn parse_battery(data: &[u8]) -> Result<i32, ButtplugDeviceError> {
+  if data.len() != 20 {
+    // Maybe not the Kiiroo Pearl 2.1?
+    return Err(ButtplugDeviceError::DeviceCommunicationError(
+      "Kiiroo battery data not expected length!".to_owned(),
+    ));
+  }
+  byte_at("kiiroo-v21", data, 5).map(|level| level as i32)
+}
+
+/// Pressure and button values from a sensor notification, or None if it isn't one.
+pub fn parse_sensors(data: &[u8]) -> Option<(Vec<i32>, Vec<i32>)> {
+  let data: &[u8; 9] = data.try_into().ok()?;
+  // Invert analog values so that the value increases with pressure.
+  let analog = data[0..8]
+    .chunks_exact(2)
+    .map(|pair| (u16::MAX as i32) - (u16::from_be_bytes([pair[0], pair[1]]) as i32))
+    .collect();
+  let digital = (0..4).map(|i| ((data[8] as i32) >> i) & 1).collect();
+  Some((analog, digital))
+}
+
 pub struct KiirooV21 {
   previous_position: Arc<AtomicU8>,
   // Set of sensors we've subscribed to for updates.
@@ -71,6 +96,14 @@ impl Default for KiirooV21 {
 }
 
 impl ProtocolHandler for KiirooV21 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_linear()
+      .with_sensor_read(&[SensorType::Battery])
+      .with_sensor_subscribe(&[SensorType::Button, SensorType::Pressure])
+  }
+
   fn handle_scalar_vibrate_cmd(
     &self,
     _: u32,
@@ -128,14 +161,7 @@ impl ProtocolHandler for KiirooV21 {
     let fut = device.read_value(&msg);
     async move {
       let hw_msg = fut.await?;
-      let data = hw_msg.data();
-      if data.len() != 20 {
-        // Maybe not the Kiiroo Pearl 2.1?
-        return Err(ButtplugDeviceError::DeviceCommunicationError(
-          "Kiiroo battery data not expected length!".to_owned(),
-        ));
-      }
-      let battery_level = data[5] as i32;
+      let battery_level = parse_battery(hw_msg.data())?;
       let battery_reading = message::SensorReading::new(
         message.device_index(),
         *message.sensor_index(),
@@ -193,23 +219,11 @@ impl ProtocolHandler for KiirooV21 {
             }
             if let HardwareEvent::Notification(_, endpoint, data) = info {
               if endpoint == Endpoint::Rx {
-                if data.len() != 9 {
+                let Some((analog, digital)) = parse_sensors(&data) else {
                   // Maybe not the Kiiroo Pearl 2.1?
                   error!("Kiiroo sensor data not expected length!");
                   continue;
-                }
-                // Extract our pressure values.
-                // Invert analog values so that the value increases with pressure.
-                let analog: Vec<i32> = (0..4)
-                  .into_iter()
-                  .map(|i| {
-                    (u16::MAX as i32) - ((data[2 * i] as i32) << 8 | (data[2 * i + 1] as i32))
-                  })
-                  .collect();
-                let digital: Vec<i32> = (0..4)
-                  .into_iter()
-                  .map(|i| ((data[8] as i32) >> i) & 1)
-                  .collect();
+                };
                 for ((sensor_index, sensor_type), sensor_data) in (0u32..)
                   .zip([SensorType::Pressure, SensorType::Button])
                   .zip([analog, digital])
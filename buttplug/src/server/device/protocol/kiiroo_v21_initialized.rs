@@ -9,26 +9,55 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorReading,
+      SensorType,
+    },
   },
   server::device::{
     configuration::ProtocolAttributesType,
-    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareEvent,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
     protocol::{
       fleshlight_launch_helper::calculate_speed,
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
     },
     ServerDeviceIdentifier,
   },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
 use async_trait::async_trait;
-use std::sync::{
-  atomic::{AtomicU8, Ordering},
-  Arc,
+use dashmap::DashSet;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
 };
+use std::{
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+  },
+};
+use tokio::sync::broadcast;
 
 generic_protocol_initializer_setup!(KiirooV21Initialized, "kiiroo-v21-initialized");
 
@@ -61,12 +90,32 @@ impl ProtocolInitializer for KiirooV21InitializedInitializer {
   }
 }
 
-#[derive(Default)]
 pub struct KiirooV21Initialized {
   previous_position: Arc<AtomicU8>,
+  // Set of sensors we've subscribed to for updates.
+  subscribed_sensors: Arc<DashSet<u32>>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl Default for KiirooV21Initialized {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      previous_position: Default::default(),
+      subscribed_sensors: Arc::new(DashSet::new()),
+      event_stream: sender,
+    }
+  }
 }
 
 impl ProtocolHandler for KiirooV21Initialized {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_linear()
+      .with_sensor_subscribe(&[SensorType::Position])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -114,4 +163,101 @@ impl ProtocolHandler for KiirooV21Initialized {
     )
     .into()])
   }
+
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    // Stroke feedback from the Keon and friends comes back on the Rx endpoint in the same shape as
+    // the position commands we send:
+    // Byte 0: Always 0x03
+    // Byte 1: Always 0x00
+    // Byte 2: Current speed, 0-99
+    // Byte 3: Current position, 0-99
+    async move {
+      // If we have no sensors we're currently subscribed to, we'll need to bring up our BLE
+      // characteristic subscription.
+      if sensors.is_empty() {
+        device
+          .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+          .await?;
+        let sender = self.event_stream.clone();
+        let mut hardware_stream = device.event_stream();
+        let stream_sensors = sensors.clone();
+        let device_index = message.device_index();
+        // If we subscribe successfully, we need to set up our event handler.
+        async_manager::spawn(async move {
+          while let Ok(info) = hardware_stream.recv().await {
+            // If we have no receivers, quit.
+            if sender.receiver_count() == 0 || stream_sensors.is_empty() {
+              return;
+            }
+            if let HardwareEvent::Notification(_, endpoint, data) = info {
+              if endpoint == Endpoint::Rx {
+                if data.len() != 4 || data[0] != 0x03 {
+                  // Not a position report, nothing for us to do.
+                  continue;
+                }
+                if stream_sensors.contains(&0)
+                  && sender
+                    .send(
+                      SensorReading::new(
+                        device_index,
+                        0,
+                        SensorType::Position,
+                        vec![data[3] as i32],
+                      )
+                      .into(),
+                    )
+                    .is_err()
+                {
+                  debug!(
+                    "Hardware device listener for Kiiroo 2.1 device shut down, returning from task."
+                  );
+                  return;
+                }
+              }
+            }
+          }
+        });
+      }
+      sensors.insert(*message.sensor_index());
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if !self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    async move {
+      // If we have no sensors we're currently subscribed to, we'll need to end our BLE
+      // characteristic subscription.
+      sensors.remove(message.sensor_index());
+      if sensors.is_empty() {
+        device
+          .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::Rx))
+          .await?;
+      }
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
 }
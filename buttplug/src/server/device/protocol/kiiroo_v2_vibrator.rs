@@ -8,20 +8,75 @@
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{ActuatorType, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorReading,
+      SensorType,
+    },
   },
   server::device::{
-    hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareEvent,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+    protocol::{generic_protocol_setup, parsing::bytes_at, ProtocolCapabilities, ProtocolHandler},
   },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
+use dashmap::DashSet;
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
+  StreamExt,
+};
+use std::{pin::Pin, sync::Arc};
+use tokio::sync::broadcast;
 
 generic_protocol_setup!(KiirooV2Vibrator, "kiiroo-v2-vibrator");
 
-#[derive(Default)]
-pub struct KiirooV2Vibrator {}
+/// Values of the 4 touch sensors from a touch notification.
+pub fn parse_touch(data: &[u8]) -> Result<Vec<i32>, ButtplugDeviceError> {
+  Ok(
+    bytes_at("kiiroo-v2-vibrator", data, 0..4)?
+      .iter()
+      .map(|x| *x as i32)
+      .collect(),
+  )
+}
+
+pub struct KiirooV2Vibrator {
+  // Set of sensors we've subscribed to for updates.
+  subscribed_sensors: Arc<DashSet<u32>>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl Default for KiirooV2Vibrator {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      subscribed_sensors: Arc::new(DashSet::new()),
+      event_stream: sender,
+    }
+  }
+}
 
 impl ProtocolHandler for KiirooV2Vibrator {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_sensor_subscribe(&[SensorType::Pressure])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -53,4 +108,91 @@ impl ProtocolHandler for KiirooV2Vibrator {
     )
     .into()])
   }
+
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    // Format for the Kiiroo Pearl 2 touch characteristic:
+    // Byte 0-3: u8 touch reading for each of the 4 touch zones, higher values indicate a firmer
+    //           touch.
+    // Anything after that is unknown, and ignored.
+    async move {
+      // If we have no sensors we're currently subscribed to, we'll need to bring up our BLE
+      // characteristic subscription.
+      if sensors.is_empty() {
+        device
+          .subscribe(&HardwareSubscribeCmd::new(Endpoint::RxTouch))
+          .await?;
+        let sender = self.event_stream.clone();
+        let mut hardware_stream = device.event_stream();
+        let stream_sensors = sensors.clone();
+        let device_index = message.device_index();
+        // If we subscribe successfully, we need to set up our event handler.
+        async_manager::spawn(async move {
+          while let Ok(info) = hardware_stream.recv().await {
+            // If we have no receivers, quit.
+            if sender.receiver_count() == 0 || stream_sensors.is_empty() {
+              return;
+            }
+            if let HardwareEvent::Notification(_, endpoint, data) = info {
+              if endpoint == Endpoint::RxTouch {
+                let Ok(touch) = parse_touch(&data) else {
+                  error!("Kiiroo touch data not expected length!");
+                  continue;
+                };
+                if stream_sensors.contains(&0)
+                  && sender
+                    .send(SensorReading::new(device_index, 0, SensorType::Pressure, touch).into())
+                    .is_err()
+                {
+                  debug!(
+                    "Hardware device listener for Kiiroo v2 device shut down, returning from task."
+                  );
+                  return;
+                }
+              }
+            }
+          }
+        });
+      }
+      sensors.insert(*message.sensor_index());
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    if !self.subscribed_sensors.contains(message.sensor_index()) {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
+    let sensors = self.subscribed_sensors.clone();
+    async move {
+      // If we have no sensors we're currently subscribed to, we'll need to end our BLE
+      // characteristic subscription.
+      sensors.remove(message.sensor_index());
+      if sensors.is_empty() {
+        device
+          .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::RxTouch))
+          .await?;
+      }
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
 }
@@ -23,6 +23,7 @@ use crate::{
     },
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -99,6 +100,10 @@ impl ProtocolInitializer for LeloHarmonyInitializer {
 pub struct LeloHarmony {}
 
 impl ProtocolHandler for LeloHarmony {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Rotate])
+  }
+
   fn handle_scalar_cmd(
     &self,
     cmds: &[Option<(ActuatorType, u32)>],
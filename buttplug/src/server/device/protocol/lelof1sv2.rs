@@ -23,6 +23,7 @@ use crate::{
     },
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -101,6 +102,10 @@ impl ProtocolInitializer for LeloF1sV2Initializer {
 pub struct LeloF1sV2 {}
 
 impl ProtocolHandler for LeloF1sV2 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
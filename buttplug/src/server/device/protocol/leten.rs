@@ -6,7 +6,10 @@
 // for full license information.
 
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::{
     device::{
       configuration::ProtocolDeviceAttributes,
@@ -14,21 +17,24 @@ use crate::{
       protocol::{
         generic_protocol_initializer_setup,
         ProtocolAttributesType,
+        ProtocolCapabilities,
         ProtocolHandler,
         ProtocolIdentifier,
         ProtocolInitializer,
+        ProtocolKeepalive,
       },
     },
     ServerDeviceIdentifier,
   },
-  util::{async_manager, sleep},
 };
 use async_trait::async_trait;
-use std::sync::{
-  atomic::{AtomicU8, Ordering},
-  Arc,
+use std::{
+  sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+  },
+  time::Duration,
 };
-use std::time::Duration;
 
 generic_protocol_initializer_setup!(Leten, "leten");
 #[derive(Default)]
@@ -47,59 +53,46 @@ impl ProtocolInitializer for LetenInitializer {
       .write_value(&HardwareWriteCmd::new(Endpoint::Tx, vec![0x04, 0x01], true))
       .await?;
     // Sometimes sending this causes Rx to receive [0x0a]
-    Ok(Arc::new(Leten::new(hardware)))
+    Ok(Arc::new(Leten::default()))
   }
 }
 
-const LETEN_COMMAND_DELAY_MS: u64 = 1000;
-
-async fn command_update_handler(device: Arc<Hardware>, command_holder: Arc<AtomicU8>) {
-  trace!("Entering Leten keep-alive loop");
-  let mut current_command = command_holder.load(Ordering::Relaxed);
-  while device
-    .write_value(&HardwareWriteCmd::new(
-      Endpoint::Tx,
-      vec![0x02, current_command],
-      true,
-    ))
-    .await
-    .is_ok()
-  {
-    sleep(Duration::from_millis(LETEN_COMMAND_DELAY_MS)).await;
-    current_command = command_holder.load(Ordering::Relaxed);
-    trace!("Leten Command: {:?}", current_command);
-  }
-  trace!("Leten keep-alive loop exiting, most likely due to device disconnection.");
-}
+const LETEN_COMMAND_DELAY: Duration = Duration::from_millis(1000);
 
+#[derive(Default)]
 pub struct Leten {
-  current_command: Arc<AtomicU8>,
+  current_command: AtomicU8,
 }
 
-impl Leten {
-  fn new(device: Arc<Hardware>) -> Self {
-    let current_command = Arc::new(AtomicU8::new(0));
-    let current_command_clone = current_command.clone();
-    async_manager::spawn(
-      async move { command_update_handler(device, current_command_clone).await },
-    );
-    Self { current_command }
+impl ProtocolHandler for Leten {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
   }
-}
 
-impl ProtocolHandler for Leten {
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     // Leten keepalive is shorter
     super::ProtocolKeepaliveStrategy::NoStrategy
   }
 
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::always(LETEN_COMMAND_DELAY))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![0x02, self.current_command.load(Ordering::Relaxed)],
+      true,
+    )
+    .into()])
+  }
+
   fn handle_scalar_vibrate_cmd(
     &self,
     _index: u32,
     scalar: u32,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let current_command = self.current_command.clone();
-    current_command.store(scalar as u8, Ordering::Relaxed);
+    self.current_command.store(scalar as u8, Ordering::Relaxed);
 
     Ok(vec![HardwareWriteCmd::new(
       Endpoint::Tx,
@@ -0,0 +1,202 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Position interpolation for devices that can't take a position/duration pair natively.
+//!
+//! A lot of hardware can only be told "go here now" or "move at this speed", while LinearCmd asks
+//! for "be at this position after this many milliseconds". The [LinearInterpolator] bridges that
+//! gap by breaking a movement into small steps and writing each one to the hardware on a timer.
+//! Protocols just need to provide a closure that turns a position (0.0-1.0) into hardware
+//! commands.
+
+use crate::{
+  core::{errors::ButtplugDeviceError, message::VectorSubcommand},
+  server::device::hardware::{Hardware, HardwareCommand},
+  util::{async_manager, sleep},
+};
+use instant::Instant;
+use std::{
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+/// Default time between interpolated writes. 20hz is about as fast as most BLE hardware will take
+/// commands without dropping them.
+pub const DEFAULT_INTERPOLATION_TICK_MS: u32 = 50;
+
+/// Turns a position (0.0-1.0) into the commands needed to move the hardware there.
+pub type PositionCommandGenerator =
+  dyn Fn(f64) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> + Send + Sync;
+
+/// Calculate the intermediate positions for a movement, as (milliseconds from start, position)
+/// pairs. The last step will always land on the goal position at exactly the requested duration.
+///
+/// Steps are generated lazily, so long movements don't allocate a step list up front.
+pub fn interpolation_steps(
+  start: f64,
+  goal: f64,
+  duration_ms: u32,
+  tick_ms: u32,
+) -> impl Iterator<Item = (u32, f64)> {
+  let start = start.clamp(0.0, 1.0);
+  let goal = goal.clamp(0.0, 1.0);
+  // Zero durations or tick rates mean jumping straight to the goal, in a single step.
+  let (step_count, tick_ms) = if duration_ms == 0 || tick_ms == 0 {
+    (1, 0)
+  } else {
+    (duration_ms.div_ceil(tick_ms), tick_ms)
+  };
+  (1..=step_count).map(move |step| {
+    let offset = step
+      .checked_mul(tick_ms)
+      .map_or(duration_ms, |offset| offset.min(duration_ms));
+    if step == step_count {
+      (offset, goal)
+    } else {
+      let progress = offset as f64 / duration_ms as f64;
+      (offset, start + (goal - start) * progress)
+    }
+  })
+}
+
+/// Runs timed position movements against a piece of hardware.
+///
+/// Only one movement runs at a time. Starting a new movement cancels whatever was in flight, and
+/// the new movement starts from wherever the old one got to.
+pub struct LinearInterpolator {
+  hardware: Arc<Hardware>,
+  tick_ms: u32,
+  generator: Arc<PositionCommandGenerator>,
+  // f64 stored as bits, since there's no AtomicF64.
+  current_position: Arc<AtomicU64>,
+  movement_id: Arc<AtomicU64>,
+}
+
+impl LinearInterpolator {
+  pub fn new<F>(hardware: Arc<Hardware>, generator: F) -> Self
+  where
+    F: Fn(f64) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> + Send + Sync + 'static,
+  {
+    Self::new_with_tick_rate(hardware, DEFAULT_INTERPOLATION_TICK_MS, generator)
+  }
+
+  pub fn new_with_tick_rate<F>(hardware: Arc<Hardware>, tick_ms: u32, generator: F) -> Self
+  where
+    F: Fn(f64) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> + Send + Sync + 'static,
+  {
+    Self {
+      hardware,
+      tick_ms,
+      generator: Arc::new(generator),
+      current_position: Arc::new(AtomicU64::new(0f64.to_bits())),
+      movement_id: Arc::new(AtomicU64::new(0)),
+    }
+  }
+
+  /// Last position written to the hardware.
+  pub fn current_position(&self) -> f64 {
+    f64::from_bits(self.current_position.load(Ordering::SeqCst))
+  }
+
+  /// Start moving to a position over the given duration. Returns immediately, writes happen in a
+  /// spawned task.
+  pub fn move_to(&self, position: f64, duration_ms: u32) {
+    let id = self.movement_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let steps = interpolation_steps(self.current_position(), position, duration_ms, self.tick_ms);
+    let hardware = self.hardware.clone();
+    let generator = self.generator.clone();
+    let current_position = self.current_position.clone();
+    let movement_id = self.movement_id.clone();
+    async_manager::spawn(async move {
+      let start_time = Instant::now();
+      for (offset, step_position) in steps {
+        let elapsed = Instant::now().duration_since(start_time);
+        let target = Duration::from_millis(offset as u64);
+        if target > elapsed {
+          sleep(target - elapsed).await;
+        }
+        if movement_id.load(Ordering::SeqCst) != id {
+          return;
+        }
+        let commands = match generator(step_position) {
+          Ok(commands) => commands,
+          Err(err) => {
+            error!("Cannot generate interpolated position command: {:?}", err);
+            return;
+          }
+        };
+        for command in commands {
+          if let Err(err) = hardware.parse_message(&command).await {
+            error!("Cannot write interpolated position command: {:?}", err);
+            return;
+          }
+        }
+        current_position.store(step_position.to_bits(), Ordering::SeqCst);
+      }
+    });
+  }
+
+  /// Convenience for protocols that route a LinearCmd vector straight to the interpolator.
+  pub fn handle_vector(&self, vector: &VectorSubcommand) {
+    self.move_to(vector.position(), vector.duration());
+  }
+
+  /// Cancel any movement in progress. The hardware will stay wherever it was last written to.
+  pub fn stop(&self) {
+    self.movement_id.fetch_add(1, Ordering::SeqCst);
+  }
+}
+
+impl Drop for LinearInterpolator {
+  fn drop(&mut self) {
+    self.stop();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::interpolation_steps;
+
+  #[test]
+  pub fn test_interpolation_steps() {
+    let steps: Vec<_> = interpolation_steps(0.0, 1.0, 200, 50).collect();
+    assert_eq!(steps, vec![(50, 0.25), (100, 0.5), (150, 0.75), (200, 1.0)]);
+    let steps: Vec<_> = interpolation_steps(1.0, 0.0, 100, 50).collect();
+    assert_eq!(steps, vec![(50, 0.5), (100, 0.0)]);
+  }
+
+  #[test]
+  pub fn test_interpolation_steps_uneven_duration() {
+    // The last step should be shortened to land on the duration, not overshoot it.
+    let steps: Vec<_> = interpolation_steps(0.0, 1.0, 120, 50).collect();
+    assert_eq!(steps.len(), 3);
+    assert_eq!(steps.last(), Some(&(120, 1.0)));
+  }
+
+  #[test]
+  pub fn test_interpolation_steps_immediate() {
+    assert_eq!(
+      interpolation_steps(0.3, 0.8, 0, 50).collect::<Vec<_>>(),
+      vec![(0, 0.8)]
+    );
+    assert_eq!(
+      interpolation_steps(0.3, 1.5, 100, 0).collect::<Vec<_>>(),
+      vec![(0, 1.0)]
+    );
+  }
+
+  #[test]
+  pub fn test_interpolation_steps_long_duration() {
+    // Offsets near u32::MAX mustn't overflow, and the step list isn't built up front.
+    let mut steps = interpolation_steps(0.0, 1.0, u32::MAX, 1000);
+    assert_eq!(steps.next(), Some((1000, 1000.0 / u32::MAX as f64)));
+    assert_eq!(steps.last(), Some((u32::MAX, 1.0)));
+  }
+}
@@ -8,12 +8,17 @@
 use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::util::async_manager;
 use crate::{
-  core::{errors::ButtplugDeviceError, message, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -129,6 +134,10 @@ impl LongLostTouch {
 }
 
 impl ProtocolHandler for LongLostTouch {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Oscillate])
+  }
+
   fn handle_scalar_cmd(
     &self,
     commands: &[Option<(message::ActuatorType, u32)>],
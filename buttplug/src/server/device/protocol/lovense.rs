@@ -9,26 +9,37 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, ActuatorType, ButtplugDeviceMessage, ButtplugServerMessage, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorType,
+    },
   },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareEvent, HardwareSubscribeCmd, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{
+      parsing::data_str,
+      rotation_helper::RotationDirection,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
     ServerDeviceIdentifier,
   },
   util::sleep,
 };
 use async_trait::async_trait;
-use futures::{future::BoxFuture, FutureExt};
-use regex::Regex;
-use std::{
-  sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-  },
-  time::Duration,
+use futures::{
+  future::{self, BoxFuture},
+  FutureExt,
 };
+use regex::Regex;
+use std::{sync::Arc, time::Duration};
 
 // Constants for dealing with the Lovense subscript/write race condition. The
 // timeout needs to be VERY long, otherwise this trips up old lovense serial
@@ -39,7 +50,12 @@ const LOVENSE_COMMAND_TIMEOUT_MS: u64 = 500;
 const LOVENSE_COMMAND_RETRY: u64 = 5;
 
 pub mod setup {
-  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
   #[derive(Default)]
   pub struct LovenseIdentifierFactory {}
 
@@ -51,13 +67,18 @@ pub mod setup {
     fn create(&self) -> Box<dyn ProtocolIdentifier> {
       Box::new(super::LovenseIdentifier::default())
     }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::Lovense as ProtocolHandler>::capabilities()
+    }
   }
 }
 
 #[derive(Default)]
 pub struct LovenseIdentifier {}
 
-fn lovense_model_resolver(type_response: String) -> String {
+/// Model identifier from a DeviceType response, e.g. "P" for "P:23:0082059AD3BD;".
+pub fn lovense_model_resolver(type_response: &str) -> String {
   let parts = type_response.split(':').collect::<Vec<&str>>();
   if parts.len() < 2 {
     warn!(
@@ -67,15 +88,36 @@ fn lovense_model_resolver(type_response: String) -> String {
     return "lovense".to_string();
   }
 
-  let identifier = parts[0].to_owned();
-  let version = parts[1].to_owned().parse::<i32>().unwrap_or(0);
+  parts[0].to_owned()
+}
 
+/// Firmware version from a DeviceType response, e.g. 23 for "P:23:0082059AD3BD;".
+pub fn lovense_firmware_version(type_response: &str) -> Option<i32> {
+  type_response.split(':').nth(1)?.parse().ok()
+}
+
+/// Models that gain features with firmware updates, as the model identifier, the first firmware
+/// version with the features, and the identifier of the variant in the device configuration.
+const LOVENSE_FIRMWARE_VARIANTS: [(&str, i32, &str); 1] = [
   // Flexer: version must be 3+ to control actuators separately
-  if identifier == "EI" && version >= 3 {
-    return "EI-FW3".to_string();
-  }
+  ("EI", 3, "EI-FW3"),
+];
+
+/// Firmware variant identifier for a model, if the firmware version changes what the model can do.
+pub fn lovense_firmware_variant(model: &str, version: i32) -> Option<String> {
+  LOVENSE_FIRMWARE_VARIANTS
+    .iter()
+    .filter(|(variant_model, min_version, _)| *variant_model == model && version >= *min_version)
+    .max_by_key(|(_, min_version, _)| *min_version)
+    .map(|(_, _, variant)| variant.to_string())
+}
 
-  identifier
+/// Battery level from a Battery response. Depending on the state of the toy, there may be an
+/// initial character of some kind, i.e. if the toy is currently vibrating then battery level comes
+/// up as "s89;" versus just "89;".
+pub fn parse_battery(data: &[u8]) -> Option<u8> {
+  let level = std::str::from_utf8(data).ok()?.strip_suffix(';')?;
+  level.strip_prefix('s').unwrap_or(level).parse().ok()
 }
 
 #[async_trait]
@@ -97,16 +139,17 @@ impl ProtocolIdentifier for LovenseIdentifier {
       select! {
         event = event_receiver.recv().fuse() => {
           if let Ok(HardwareEvent::Notification(_, _, n)) = event {
-            let type_response = std::str::from_utf8(&n).map_err(|_| ButtplugDeviceError::ProtocolSpecificError("lovense".to_owned(), "Lovense device init got back non-UTF8 string.".to_owned()))?.to_owned();
+            let type_response = data_str("lovense", &n)?;
             info!("Lovense Device Type Response: {}", type_response);
             let ident = lovense_model_resolver(type_response);
-            return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Identifier(ident.clone())), Box::new(LovenseInitializer::new(ident))));
+            let version = lovense_firmware_version(type_response);
+            return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Identifier(ident.clone())), Box::new(LovenseInitializer::new(ident, version))));
           } else {
             return Err(
-              ButtplugDeviceError::ProtocolSpecificError(
-                "Lovense".to_owned(),
-                "Lovense Device disconnected while getting DeviceType info.".to_owned(),
-              ),
+              ButtplugDeviceError::DeviceDisconnected {
+                address: hardware.address().to_owned(),
+                protocol: "lovense".to_owned(),
+              },
             );
           }
         }
@@ -117,9 +160,9 @@ impl ProtocolIdentifier for LovenseIdentifier {
             let re = Regex::new(r"LVS-([A-Z]+)\d+").expect("Static regex shouldn't fail");
             if let Some(caps) = re.captures(hardware.name()) {
               info!("Lovense Device identified by BLE name");
-              return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Identifier(caps[1].to_string())), Box::new(LovenseInitializer::new(caps[1].to_string()))));
+              return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Identifier(caps[1].to_string())), Box::new(LovenseInitializer::new(caps[1].to_string(), None))));
             };
-            return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Default), Box::new(LovenseInitializer::new("".to_string()))));
+            return Ok((ServerDeviceIdentifier::new(hardware.address(), "lovense", &ProtocolAttributesType::Default), Box::new(LovenseInitializer::new("".to_string(), None))));
           }
         }
       }
@@ -128,11 +171,15 @@ impl ProtocolIdentifier for LovenseIdentifier {
 }
 pub struct LovenseInitializer {
   device_type: String,
+  firmware_version: Option<i32>,
 }
 
 impl LovenseInitializer {
-  pub fn new(device_type: String) -> Self {
-    Self { device_type }
+  pub fn new(device_type: String, firmware_version: Option<i32>) -> Self {
+    Self {
+      device_type,
+      firmware_version,
+    }
   }
 }
 
@@ -165,17 +212,37 @@ impl ProtocolInitializer for LovenseInitializer {
 
     Ok(Arc::new(protocol))
   }
+
+  async fn variant(&mut self, _: Arc<Hardware>) -> Result<Option<String>, ButtplugDeviceError> {
+    Ok(
+      self
+        .firmware_version
+        .and_then(|version| lovense_firmware_variant(&self.device_type, version)),
+    )
+  }
 }
 
 #[derive(Default)]
 pub struct Lovense {
-  rotation_direction: Arc<AtomicBool>,
+  rotation_direction: RotationDirection,
   vibrator_count: usize,
   use_mply: bool,
   device_type: String,
 }
 
 impl ProtocolHandler for Lovense {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[
+        ActuatorType::Vibrate,
+        ActuatorType::Rotate,
+        ActuatorType::Oscillate,
+        ActuatorType::Constrict,
+      ])
+      .with_rotate()
+      .with_sensor_read(&[SensorType::Battery])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     // For Lovense, we'll just repeat the device type packet and drop the result.
     super::ProtocolKeepaliveStrategy::RepeatPacketStrategy(HardwareWriteCmd::new(
@@ -185,6 +252,46 @@ impl ProtocolHandler for Lovense {
     ))
   }
 
+  // Each motor gets its own command, and none of them wait on a reply.
+  fn pipeline_writes(&self) -> bool {
+    true
+  }
+
+  fn scalar_command_rate_limit(&self) -> Option<Duration> {
+    // Since writes don't wait on the toy, nothing else keeps fast updates from piling up faster
+    // than the toy works through its text commands.
+    Some(Duration::from_millis(50))
+  }
+
+  // Lovense toys keep their LED on for as long as they're connected, which users can have turned
+  // off once a toy has sat around unused for a while.
+  fn supports_low_power(&self) -> bool {
+    true
+  }
+
+  fn handle_enter_low_power(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      b"Light:off;".to_vec(),
+      false,
+    )
+    .into()])
+  }
+
+  fn handle_wake(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      b"Light:on;".to_vec(),
+      false,
+    )
+    .into()])
+  }
+
+  // Toys announce being turned off with their power button before dropping the connection.
+  fn is_power_off_notification(&self, endpoint: Endpoint, data: &[u8]) -> bool {
+    endpoint == Endpoint::Rx && data == b"POWEROFF;"
+  }
+
   fn handle_scalar_cmd(
     &self,
     cmds: &[Option<(ActuatorType, u32)>],
@@ -297,15 +404,11 @@ impl ProtocolHandler for Lovense {
     &self,
     cmds: &[Option<(u32, bool)>],
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let direction = self.rotation_direction.clone();
     let mut hardware_cmds = vec![];
     if let Some(Some((speed, clockwise))) = cmds.get(0) {
       let lovense_cmd = format!("Rotate:{};", speed).as_bytes().to_vec();
       hardware_cmds.push(HardwareWriteCmd::new(Endpoint::Tx, lovense_cmd, false).into());
-      let dir = direction.load(Ordering::SeqCst);
-      // TODO Should we store speed and direction as an option for rotation caching? This is weird.
-      if dir != *clockwise {
-        direction.store(*clockwise, Ordering::SeqCst);
+      if self.rotation_direction.set(*clockwise) {
         hardware_cmds
           .push(HardwareWriteCmd::new(Endpoint::Tx, b"RotateChange;".to_vec(), false).into());
       }
@@ -313,11 +416,18 @@ impl ProtocolHandler for Lovense {
     Ok(hardware_cmds)
   }
 
-  fn handle_battery_level_cmd(
+  fn handle_sensor_read_cmd(
     &self,
     device: Arc<Hardware>,
     message: message::SensorReadCmd,
   ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    // Battery is the only sensor Lovense devices report.
+    if *message.sensor_type() != message::SensorType::Battery {
+      return future::ready(Err(ButtplugDeviceError::ProtocolSensorNotSupported(
+        *message.sensor_type(),
+      )))
+      .boxed();
+    }
     let mut device_notification_receiver = device.event_stream();
     async move {
       let write_fut = device.write_value(&HardwareWriteCmd::new(
@@ -329,44 +439,425 @@ impl ProtocolHandler for Lovense {
       while let Ok(event) = device_notification_receiver.recv().await {
         match event {
           HardwareEvent::Notification(_, _, data) => {
-            if let Ok(data_str) = std::str::from_utf8(&data) {
-              debug!("Lovense event received: {}", data_str);
-              let len = data_str.len();
-              // Depending on the state of the toy, we may get an initial
-              // character of some kind, i.e. if the toy is currently vibrating
-              // then battery level comes up as "s89;" versus just "89;". We'll
-              // need to chop the semicolon and make sure we only read the
-              // numbers in the string.
-              //
-              // Contains() is casting a wider net than we need here, but it'll
-              // do for now.
-              let start_pos = usize::from(data_str.contains('s'));
-              if let Ok(level) = data_str[start_pos..(len - 1)].parse::<u8>() {
-                return Ok(
-                  message::SensorReading::new(
-                    message.device_index(),
-                    0,
-                    message::SensorType::Battery,
-                    vec![level as i32],
-                  )
-                  .into(),
-                );
-              }
+            debug!("Lovense event received: {:?}", data);
+            if let Some(level) = parse_battery(&data) {
+              return Ok(
+                message::SensorReading::new(
+                  message.device_index(),
+                  *message.sensor_index(),
+                  message::SensorType::Battery,
+                  vec![level as i32],
+                )
+                .into(),
+              );
             }
           }
-          HardwareEvent::Disconnected(_) => {
-            return Err(ButtplugDeviceError::ProtocolSpecificError(
-              "Lovense".to_owned(),
-              "Lovense Device disconnected while getting Battery info.".to_owned(),
-            ))
+          HardwareEvent::Disconnected(..) => {
+            return Err(ButtplugDeviceError::DeviceDisconnected {
+              address: device.address().to_owned(),
+              protocol: "lovense".to_owned(),
+            })
           }
         }
       }
-      Err(ButtplugDeviceError::ProtocolSpecificError(
-        "Lovense".to_owned(),
-        "Lovense Device disconnected while getting Battery info.".to_owned(),
-      ))
+      Err(ButtplugDeviceError::DeviceDisconnected {
+        address: device.address().to_owned(),
+        protocol: "lovense".to_owned(),
+      })
     }
     .boxed()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::{
+    lovense_firmware_variant,
+    lovense_firmware_version,
+    lovense_model_resolver,
+    setup::LovenseIdentifierFactory,
+    LOVENSE_COMMAND_RETRY,
+  };
+  use crate::{
+    core::{
+      errors::ButtplugDeviceError,
+      message::{self, ActuatorType, ButtplugServerMessage, Endpoint, SensorType},
+    },
+    server::device::{
+      configuration::{DeviceConfigurationManager, ProtocolAttributesType},
+      hardware::{
+        scripted::{send_commands, setup_protocol, HardwareScript},
+        Hardware,
+      },
+      protocol::ProtocolHandler,
+    },
+    util::device_configuration::load_protocol_configs,
+  };
+  use std::sync::Arc;
+
+  async fn setup_lovense(
+    name: &str,
+    device_type: &str,
+  ) -> (HardwareScript, Arc<Hardware>, Arc<dyn ProtocolHandler>) {
+    let mut script = HardwareScript::default();
+    script
+      .expect_subscribe(Endpoint::Rx)
+      .expect_write(Endpoint::Tx, b"DeviceType;")
+      .reply(Endpoint::Rx, device_type.as_bytes());
+    let hardware = script.build_hardware(name, "lovense-test");
+    let (_, handler) = setup_protocol(&LovenseIdentifierFactory::default(), hardware.clone())
+      .await
+      .expect("Test, assuming infallible.");
+    (script, hardware, handler)
+  }
+
+  #[test]
+  fn test_model_resolver() {
+    assert_eq!(lovense_model_resolver("P:23:0082059AD3BD;"), "P");
+    assert_eq!(lovense_model_resolver("EI:2:0082059AD3BD;"), "EI");
+    assert_eq!(lovense_model_resolver("EI:3:0082059AD3BD;"), "EI");
+    assert_eq!(lovense_model_resolver("garbage"), "lovense");
+  }
+
+  #[test]
+  fn test_firmware_variant() {
+    assert_eq!(lovense_firmware_version("EI:03:0082059AD3BD;"), Some(3));
+    assert_eq!(lovense_firmware_version("garbage"), None);
+    assert_eq!(lovense_firmware_variant("EI", 2), None);
+    assert_eq!(lovense_firmware_variant("EI", 3), Some("EI-FW3".to_owned()));
+    assert_eq!(lovense_firmware_variant("EI", 4), Some("EI-FW3".to_owned()));
+    assert_eq!(lovense_firmware_variant("P", 23), None);
+  }
+
+  #[tokio::test]
+  async fn test_product_matrix() {
+    use ActuatorType::{Constrict, Oscillate, Rotate, Vibrate};
+    let config_manager: DeviceConfigurationManager = load_protocol_configs(None, None, false)
+      .expect("Test, assuming infallible.")
+      .finish()
+      .expect("Test, assuming infallible.");
+    // DeviceType response, then the name, scalar actuators, and rotation support the device should
+    // end up with.
+    let matrix = [
+      (
+        "B:11:0082059AD3BD;",
+        "Lovense Max",
+        vec![Vibrate, Constrict],
+        false,
+      ),
+      (
+        "P:23:0082059AD3BD;",
+        "Lovense Edge",
+        vec![Vibrate, Vibrate],
+        false,
+      ),
+      ("A:11:0082059AD3BD;", "Lovense Nora", vec![Vibrate], true),
+      ("C:11:0082059AD3BD;", "Lovense Nora", vec![Vibrate], true),
+      ("L:11:0082059AD3BD;", "Lovense Ambi", vec![Vibrate], false),
+      ("S:11:0082059AD3BD;", "Lovense Lush", vec![Vibrate], false),
+      ("Z:11:0082059AD3BD;", "Lovense Hush", vec![Vibrate], false),
+      ("W:11:0082059AD3BD;", "Lovense Domi", vec![Vibrate], false),
+      ("O:11:0082059AD3BD;", "Lovense Osci", vec![Vibrate], false),
+      (
+        "V:11:0082059AD3BD;",
+        "Lovense Mission",
+        vec![Vibrate],
+        false,
+      ),
+      ("X:11:0082059AD3BD;", "Lovense Ferri", vec![Vibrate], false),
+      ("R:11:0082059AD3BD;", "Lovense Diamo", vec![Vibrate], false),
+      ("ToyS:11:0082059AD3BD;", "Loveai Dolp", vec![Vibrate], false),
+      (
+        "F:11:0082059AD3BD;",
+        "Lovense Sex Machine",
+        vec![Oscillate],
+        false,
+      ),
+      (
+        "J:11:0082059AD3BD;",
+        "Lovense Dolce",
+        vec![Vibrate, Vibrate],
+        false,
+      ),
+      ("ED:11:0082059AD3BD;", "Lovense Gush", vec![Vibrate], false),
+      (
+        "EB:11:0082059AD3BD;",
+        "Lovense Hyphy",
+        vec![Vibrate, Vibrate],
+        false,
+      ),
+      ("T:11:0082059AD3BD;", "Lovense Calor", vec![Vibrate], false),
+      (
+        "EI:2:0082059AD3BD;",
+        "Lovense Flexer (Firmware update needed)",
+        vec![Vibrate],
+        false,
+      ),
+      (
+        "EI:3:0082059AD3BD;",
+        "Lovense Flexer",
+        vec![Vibrate, Vibrate, Rotate],
+        false,
+      ),
+      (
+        "EI:4:0082059AD3BD;",
+        "Lovense Flexer",
+        vec![Vibrate, Vibrate, Rotate],
+        false,
+      ),
+      (
+        "N:11:0082059AD3BD;",
+        "Lovense Gemini",
+        vec![Vibrate, Vibrate],
+        false,
+      ),
+      (
+        "EA:11:0082059AD3BD;",
+        "Lovense Gravity",
+        vec![Vibrate, Oscillate],
+        false,
+      ),
+      ("Q:11:0082059AD3BD;", "Lovense Tenera", vec![Vibrate], false),
+      ("EL:11:0082059AD3BD;", "Lovense Ridge", vec![Vibrate], true),
+      (
+        "U:11:0082059AD3BD;",
+        "Lovense Lapis",
+        vec![Vibrate, Vibrate, Vibrate],
+        false,
+      ),
+      ("SD:11:0082059AD3BD;", "Lovense Vulse", vec![Vibrate], false),
+      (
+        "H:1:0082059AD3BD;",
+        "Lovense Solace",
+        vec![Oscillate],
+        false,
+      ),
+      ("ZZ:1:0082059AD3BD;", "Lovense Device", vec![Vibrate], false),
+    ];
+    for (response, name, actuators, rotates) in matrix {
+      let mut script = HardwareScript::default();
+      script
+        .expect_subscribe(Endpoint::Rx)
+        .expect_write(Endpoint::Tx, b"DeviceType;")
+        .reply(Endpoint::Rx, response.as_bytes());
+      let hardware = script.build_hardware("LVS-Test", "lovense-test");
+      let (identifier, _) = setup_protocol(&LovenseIdentifierFactory::default(), hardware)
+        .await
+        .expect("Test, assuming infallible.");
+      let attributes = config_manager
+        .protocol_device_attributes(&identifier, &[])
+        .expect("Test, assuming infallible.");
+      assert_eq!(attributes.name(), name, "{}", response);
+      let messages = attributes.message_attributes();
+      let scalars = messages
+        .scalar_cmd()
+        .as_ref()
+        .map(|scalars| {
+          scalars
+            .iter()
+            .map(|x| *x.actuator_type())
+            .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+      assert_eq!(scalars, actuators, "{}", response);
+      assert_eq!(messages.rotate_cmd().is_some(), rotates, "{}", response);
+      script.finish();
+    }
+  }
+
+  #[tokio::test]
+  async fn test_identify_by_device_type() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_subscribe(Endpoint::Rx)
+      .expect_write(Endpoint::Tx, b"DeviceType;")
+      .reply(Endpoint::Rx, b"EI:3:0082059AD3BD;");
+    let hardware = script.build_hardware("LVS-Test", "lovense-test");
+    let (identifier, _) = setup_protocol(&LovenseIdentifierFactory::default(), hardware)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(identifier.protocol(), "lovense");
+    assert_eq!(
+      *identifier.attributes_identifier(),
+      ProtocolAttributesType::Identifier("EI-FW3".to_owned())
+    );
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_identify_by_name_after_timeout() {
+    let mut script = HardwareScript::default();
+    script.expect_subscribe(Endpoint::Rx);
+    for _ in 0..=LOVENSE_COMMAND_RETRY {
+      script.expect_write(Endpoint::Tx, b"DeviceType;");
+    }
+    let hardware = script.build_hardware("LVS-P36", "lovense-test");
+    let (identifier, _) = setup_protocol(&LovenseIdentifierFactory::default(), hardware)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      *identifier.attributes_identifier(),
+      ProtocolAttributesType::Identifier("P".to_owned())
+    );
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_identify_non_utf8_response() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_subscribe(Endpoint::Rx)
+      .expect_write(Endpoint::Tx, b"DeviceType;")
+      .reply(Endpoint::Rx, &[0xff, 0xfe]);
+    let hardware = script.build_hardware("LVS-Test", "lovense-test");
+    assert!(matches!(
+      setup_protocol(&LovenseIdentifierFactory::default(), hardware).await,
+      Err(ButtplugDeviceError::ProtocolSpecificError(..))
+    ));
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_vibrate_all_and_single_motors() {
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "P:23:0082059AD3BD;").await;
+    script
+      .expect_write(Endpoint::Tx, b"Vibrate:5;")
+      .expect_write(Endpoint::Tx, b"Vibrate1:5;")
+      .expect_write(Endpoint::Tx, b"Vibrate2:10;");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 5)),
+        Some((ActuatorType::Vibrate, 5)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 5)),
+        Some((ActuatorType::Vibrate, 10)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_vibrate_and_constrict() {
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "B:11:0082059AD3BD;").await;
+    script
+      .expect_write(Endpoint::Tx, b"Vibrate:10;")
+      .expect_write(Endpoint::Tx, b"Air:Level:2;");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 10)),
+        Some((ActuatorType::Constrict, 2)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_mply() {
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "EI:3:0082059AD3BD;").await;
+    script.expect_write(Endpoint::Tx, b"Mply:5:-1:10;");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[
+        Some((ActuatorType::Vibrate, 5)),
+        None,
+        Some((ActuatorType::Rotate, 10)),
+      ]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+
+    // The Solace gets its stroke range added.
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "H:1:0082059AD3BD;").await;
+    script.expect_write(Endpoint::Tx, b"Mply:10:20;");
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[Some((ActuatorType::Oscillate, 10))]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_rotate() {
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "C:11:0082059AD3BD;").await;
+    script
+      .expect_write(Endpoint::Tx, b"Rotate:10;")
+      .expect_write(Endpoint::Tx, b"Rotate:10;")
+      .expect_write(Endpoint::Tx, b"RotateChange;")
+      .expect_write(Endpoint::Tx, b"Rotate:5;");
+    send_commands(&hardware, handler.handle_rotate_cmd(&[Some((10, false))]))
+      .await
+      .expect("Test, assuming infallible.");
+    send_commands(&hardware, handler.handle_rotate_cmd(&[Some((10, true))]))
+      .await
+      .expect("Test, assuming infallible.");
+    send_commands(&hardware, handler.handle_rotate_cmd(&[Some((5, true))]))
+      .await
+      .expect("Test, assuming infallible.");
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_idle_light() {
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "Z:11:0082059AD3BD;").await;
+    script
+      .expect_write(Endpoint::Tx, b"Light:off;")
+      .expect_write(Endpoint::Tx, b"Light:on;");
+    assert!(handler.supports_low_power());
+    send_commands(&hardware, handler.handle_enter_low_power())
+      .await
+      .expect("Test, assuming infallible.");
+    send_commands(&hardware, handler.handle_wake())
+      .await
+      .expect("Test, assuming infallible.");
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_battery() {
+    let (mut script, hardware, handler) = setup_lovense("LVS-Test", "EI:3:0082059AD3BD;").await;
+    // A vibrating toy prefixes the battery level with "s".
+    script
+      .expect_write(Endpoint::Tx, b"Battery;")
+      .reply(Endpoint::Rx, b"s89;");
+    let reading = handler
+      .handle_sensor_read_cmd(
+        hardware.clone(),
+        message::SensorReadCmd::new(0, 3, SensorType::Battery),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    let ButtplugServerMessage::SensorReading(reading) = reading else {
+      panic!("Expected SensorReading, got {:?}", reading);
+    };
+    assert_eq!(reading.sensor_index(), 3);
+    assert_eq!(*reading.data(), vec![89]);
+
+    assert!(matches!(
+      handler
+        .handle_sensor_read_cmd(
+          hardware,
+          message::SensorReadCmd::new(0, 0, SensorType::Button)
+        )
+        .await,
+      Err(ButtplugDeviceError::ProtocolSensorNotSupported(
+        SensorType::Button
+      ))
+    ));
+    script.finish();
+  }
+}
@@ -9,13 +9,23 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, ActuatorType, ButtplugDeviceMessage, ButtplugServerMessage, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorType,
+    },
   },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      parsing::battery_level,
+      rotation_helper::RotationDirection,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -25,10 +35,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use futures::future::{BoxFuture, FutureExt};
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
-};
+use std::{sync::Arc, time::Duration};
 
 generic_protocol_initializer_setup!(LovenseConnectService, "lovense-connect-service");
 
@@ -86,7 +93,7 @@ impl ProtocolInitializer for LovenseConnectServiceInitializer {
 #[derive(Default)]
 pub struct LovenseConnectService {
   address: String,
-  rotation_direction: Arc<AtomicBool>,
+  rotation_direction: RotationDirection,
   vibrator_count: usize,
   thusting_count: usize,
 }
@@ -101,6 +108,23 @@ impl LovenseConnectService {
 }
 
 impl ProtocolHandler for LovenseConnectService {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[
+        ActuatorType::Vibrate,
+        ActuatorType::Rotate,
+        ActuatorType::Oscillate,
+        ActuatorType::Constrict,
+      ])
+      .with_rotate()
+      .with_sensor_read(&[SensorType::Battery])
+  }
+
+  fn scalar_command_rate_limit(&self) -> Option<Duration> {
+    // Every update is an HTTP request to the Connect app, which falls behind quickly if flooded.
+    Some(Duration::from_millis(100))
+  }
+
   fn handle_scalar_cmd(
     &self,
     cmds: &[Option<(ActuatorType, u32)>],
@@ -282,10 +306,7 @@ impl ProtocolHandler for LovenseConnectService {
         .as_bytes()
         .to_vec();
       hardware_cmds.push(HardwareWriteCmd::new(Endpoint::Tx, lovense_cmd, false).into());
-      let dir = self.rotation_direction.load(Ordering::SeqCst);
-      // TODO Should we store speed and direction as an option for rotation caching? This is weird.
-      if dir != *clockwise {
-        self.rotation_direction.store(*clockwise, Ordering::SeqCst);
+      if self.rotation_direction.set(*clockwise) {
         hardware_cmds
           .push(HardwareWriteCmd::new(Endpoint::Tx, b"RotateChange?".to_vec(), false).into());
       }
@@ -304,13 +325,14 @@ impl ProtocolHandler for LovenseConnectService {
       let reading = device
         .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
         .await?;
-      debug!("Battery level: {}", reading.data()[0]);
+      let battery_level = battery_level("lovense-connect-service", reading.data())?;
+      debug!("Battery level: {}", battery_level);
       Ok(
         message::SensorReading::new(
           msg.device_index(),
           *msg.sensor_index(),
           *msg.sensor_type(),
-          vec![reading.data()[0] as i32],
+          vec![battery_level],
         )
         .into(),
       )
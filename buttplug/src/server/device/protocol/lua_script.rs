@@ -0,0 +1,380 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Protocols implemented as Lua scripts, for devices the library doesn't support (yet).
+//!
+//! A script is registered under its own protocol identifier with
+//! [ButtplugServerBuilder::protocol_factory](crate::server::ButtplugServerBuilder::protocol_factory).
+//! The devices it handles still need communication specifiers and attributes for that identifier,
+//! either from a device configuration file or from the server builder.
+//!
+//! Scripts can define the following global functions, all of which are optional:
+//!
+//! - `init(device)` is called once when the device connects. `device` is a table with the `name`
+//!   and `address` of the device.
+//! - `handle_scalar(index, value, actuator)` is called for each changed ScalarCmd feature. `value`
+//!   is within the feature's StepRange, `actuator` is the actuator type name, i.e. "Vibrate".
+//! - `handle_linear(index, position, duration)` is called for each LinearCmd vector. `position`
+//!   is between 0.0 and 1.0, `duration` is in milliseconds.
+//!
+//! Each function returns nil, a command, or a list of commands to write to the device. A command
+//! is a table with `data` (a string, or a list of bytes), and optionally `endpoint` (defaults to
+//! "tx") and `write_with_response` (defaults to false):
+//!
+//! ```lua
+//! function handle_scalar(index, value, actuator)
+//!   return { data = { 0x0f, index, value } }
+//! end
+//! ```
+//!
+//! Scripts run in a sandbox with only the base, table, string, math and utf8 libraries, without
+//! any way to load code. Every device gets its own Lua state, so scripts can keep device state in
+//! globals. Memory use and the time each call can take are limited, so a broken script can't take
+//! down the server.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{self, ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolIdentifierFactory,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use mlua::{
+  Function,
+  HookTriggers,
+  IntoLuaMulti,
+  Lua,
+  LuaOptions,
+  MultiValue,
+  StdLib,
+  Table,
+  Value,
+};
+use std::{
+  path::Path,
+  str::FromStr,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+    Mutex,
+  },
+};
+
+const LUA_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+// Scripts are checked every LUA_HOOK_INTERVAL instructions, and stopped once a single call has
+// been checked LUA_MAX_HOOK_CALLS times. Protocol hooks should only ever need a handful.
+const LUA_HOOK_INTERVAL: u32 = 1000;
+const LUA_MAX_HOOK_CALLS: u32 = 1000;
+
+/// Creates protocol instances that run a Lua script. See the [module docs](self) for what scripts
+/// look like.
+pub struct LuaProtocolFactory {
+  identifier: String,
+  script: Arc<String>,
+  capabilities: ProtocolCapabilities,
+}
+
+impl LuaProtocolFactory {
+  /// Creates a factory for the protocol `identifier`. The script is run once here, so syntax errors
+  /// come up at server startup instead of when a device connects.
+  pub fn new(identifier: &str, script: &str) -> Result<Self, ButtplugDeviceError> {
+    let capabilities = LuaScript::new(identifier, script)?.script_capabilities()?;
+    Ok(Self {
+      identifier: identifier.to_owned(),
+      script: Arc::new(script.to_owned()),
+      capabilities,
+    })
+  }
+
+  pub fn from_file(identifier: &str, path: &Path) -> Result<Self, ButtplugDeviceError> {
+    let script = std::fs::read_to_string(path).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot read Lua protocol script {}: {}",
+        path.display(),
+        err
+      ))
+    })?;
+    Self::new(identifier, &script)
+  }
+}
+
+impl ProtocolIdentifierFactory for LuaProtocolFactory {
+  fn identifier(&self) -> &str {
+    &self.identifier
+  }
+
+  fn create(&self) -> Box<dyn ProtocolIdentifier> {
+    Box::new(LuaScriptIdentifier {
+      identifier: self.identifier.clone(),
+      script: self.script.clone(),
+    })
+  }
+
+  fn capabilities(&self) -> ProtocolCapabilities {
+    self.capabilities.clone()
+  }
+}
+
+struct LuaScriptIdentifier {
+  identifier: String,
+  script: Arc<String>,
+}
+
+#[async_trait]
+impl ProtocolIdentifier for LuaScriptIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    Ok((
+      ServerDeviceIdentifier::new(
+        hardware.address(),
+        &self.identifier,
+        &ProtocolAttributesType::Identifier(hardware.name().to_owned()),
+      ),
+      Box::new(LuaScriptInitializer {
+        identifier: self.identifier.clone(),
+        script: self.script.clone(),
+      }),
+    ))
+  }
+}
+
+struct LuaScriptInitializer {
+  identifier: String,
+  script: Arc<String>,
+}
+
+#[async_trait]
+impl ProtocolInitializer for LuaScriptInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let script = LuaScript::new(&self.identifier, &self.script)?;
+    let init_commands = script.call_hook("init", |lua| {
+      let device = lua.create_table()?;
+      device.set("name", hardware.name())?;
+      device.set("address", hardware.address())?;
+      device.into_lua_multi(lua)
+    })?;
+    for command in init_commands.unwrap_or_default() {
+      if let HardwareCommand::Write(write) = command {
+        hardware.write_value(&write).await?;
+      }
+    }
+    Ok(Arc::new(script))
+  }
+}
+
+pub struct LuaScript {
+  identifier: String,
+  lua: Mutex<Lua>,
+  hook_calls: Arc<AtomicU32>,
+}
+
+impl LuaScript {
+  fn new(identifier: &str, script: &str) -> Result<Self, ButtplugDeviceError> {
+    let script_error = |err: mlua::Error| {
+      ButtplugDeviceError::ProtocolSpecificError(identifier.to_owned(), err.to_string())
+    };
+    let lua = Lua::new_with(
+      StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+      LuaOptions::default(),
+    )
+    .map_err(script_error)?;
+    lua
+      .set_memory_limit(LUA_MEMORY_LIMIT)
+      .map_err(script_error)?;
+    // The base library is always loaded, so take out everything that can load more code.
+    let globals = lua.globals();
+    for name in ["dofile", "loadfile", "load", "require"] {
+      globals.set(name, Value::Nil).map_err(script_error)?;
+    }
+    let hook_calls = Arc::new(AtomicU32::new(0));
+    let hook_calls_clone = hook_calls.clone();
+    lua.set_hook(
+      HookTriggers::new().every_nth_instruction(LUA_HOOK_INTERVAL),
+      move |_, _| {
+        if hook_calls_clone.fetch_add(1, Ordering::SeqCst) >= LUA_MAX_HOOK_CALLS {
+          Err(mlua::Error::runtime("Script took too long to run"))
+        } else {
+          Ok(())
+        }
+      },
+    );
+    lua
+      .load(script)
+      .set_name(identifier)
+      .exec()
+      .map_err(script_error)?;
+    drop(globals);
+    Ok(Self {
+      identifier: identifier.to_owned(),
+      lua: Mutex::new(lua),
+      hook_calls,
+    })
+  }
+
+  fn script_error(&self, err: mlua::Error) -> ButtplugDeviceError {
+    ButtplugDeviceError::ProtocolSpecificError(self.identifier.clone(), err.to_string())
+  }
+
+  /// Calls a global function in the script, returning the commands it wants written. Returns None
+  /// if the script doesn't define the function.
+  fn call_hook<F>(
+    &self,
+    hook: &str,
+    args: F,
+  ) -> Result<Option<Vec<HardwareCommand>>, ButtplugDeviceError>
+  where
+    F: for<'lua> FnOnce(&'lua Lua) -> mlua::Result<MultiValue<'lua>>,
+  {
+    let lua = self
+      .lua
+      .lock()
+      .expect("Lua state lock should never be poisoned");
+    let function: Option<Function> = lua
+      .globals()
+      .get(hook)
+      .map_err(|err| self.script_error(err))?;
+    let Some(function) = function else {
+      return Ok(None);
+    };
+    self.hook_calls.store(0, Ordering::SeqCst);
+    let commands = args(&lua)
+      .and_then(|args| function.call::<_, Value>(args))
+      .and_then(hardware_commands)
+      .map_err(|err| self.script_error(err))?;
+    Ok(Some(commands))
+  }
+
+  fn defines_hook(&self, hook: &str) -> Result<bool, ButtplugDeviceError> {
+    let lua = self
+      .lua
+      .lock()
+      .expect("Lua state lock should never be poisoned");
+    let function: Option<Function> = lua
+      .globals()
+      .get(hook)
+      .map_err(|err| self.script_error(err))?;
+    Ok(function.is_some())
+  }
+
+  /// What this script handles, which is narrower than the [ProtocolHandler::capabilities] of Lua
+  /// scripts in general.
+  fn script_capabilities(&self) -> Result<ProtocolCapabilities, ButtplugDeviceError> {
+    let mut capabilities = ProtocolCapabilities::default();
+    if self.defines_hook("handle_scalar")? {
+      capabilities = capabilities.with_scalar(&ProtocolCapabilities::ALL_ACTUATOR_TYPES);
+    }
+    if self.defines_hook("handle_linear")? {
+      capabilities = capabilities.with_linear();
+    }
+    Ok(capabilities)
+  }
+
+  fn unhandled(&self, hook: &str) -> ButtplugDeviceError {
+    ButtplugDeviceError::UnhandledCommand(format!(
+      "{} protocol script does not define {}",
+      self.identifier, hook
+    ))
+  }
+}
+
+fn hardware_commands(value: Value) -> mlua::Result<Vec<HardwareCommand>> {
+  match value {
+    Value::Nil => Ok(vec![]),
+    // A single command has data, otherwise this is a list of commands.
+    Value::Table(table) if table.contains_key("data")? => Ok(vec![hardware_command(table)?]),
+    Value::Table(table) => table
+      .sequence_values::<Table>()
+      .map(|command| hardware_command(command?))
+      .collect(),
+    _ => Err(mlua::Error::runtime(
+      "Expected nil, a command table, or a list of command tables",
+    )),
+  }
+}
+
+fn hardware_command(table: Table) -> mlua::Result<HardwareCommand> {
+  let endpoint = match table.get::<_, Option<String>>("endpoint")? {
+    Some(name) => Endpoint::from_str(&name)
+      .map_err(|_| mlua::Error::runtime(format!("Unknown endpoint {}", name)))?,
+    None => Endpoint::Tx,
+  };
+  let data = match table.get::<_, Value>("data")? {
+    Value::String(data) => data.as_bytes().to_vec(),
+    Value::Table(data) => data.sequence_values::<u8>().collect::<mlua::Result<_>>()?,
+    _ => {
+      return Err(mlua::Error::runtime(
+        "Command data must be a string or a list of bytes",
+      ))
+    }
+  };
+  let write_with_response = table
+    .get::<_, Option<bool>>("write_with_response")?
+    .unwrap_or(false);
+  Ok(HardwareWriteCmd::new(endpoint, data, write_with_response).into())
+}
+
+impl ProtocolHandler for LuaScript {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&ProtocolCapabilities::ALL_ACTUATOR_TYPES)
+      .with_linear()
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut hardware_commands = vec![];
+    for (index, command) in commands.iter().enumerate() {
+      if let Some((actuator, value)) = command {
+        hardware_commands.extend(
+          self
+            .call_hook("handle_scalar", |lua| {
+              (index as u32, *value, actuator.to_string()).into_lua_multi(lua)
+            })?
+            .ok_or_else(|| self.unhandled("handle_scalar"))?,
+        );
+      }
+    }
+    Ok(hardware_commands)
+  }
+
+  fn handle_linear_cmd(
+    &self,
+    msg: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut hardware_commands = vec![];
+    for vector in msg.vectors() {
+      hardware_commands.extend(
+        self
+          .call_hook("handle_linear", |lua| {
+            (vector.index(), vector.position(), vector.duration()).into_lua_multi(lua)
+          })?
+          .ok_or_else(|| self.unhandled("handle_linear"))?,
+      );
+    }
+    Ok(hardware_commands)
+  }
+}
@@ -12,16 +12,33 @@ use crate::{
   },
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
 };
 
+// Function bytes starting each block of a command packet. Each block is the function, 0x08, the
+// value, 0x64, and the index of the motor/module it's for. Suction and heater blocks are only sent
+// for Constrict and Heater actuators, which no stock device config declares yet, so they're only
+// used by devices given those actuators in a user device config.
+const MAGIC_MOTION_VIBRATE: u8 = 0x04;
+const MAGIC_MOTION_SUCTION: u8 = 0x05;
+const MAGIC_MOTION_HEATER: u8 = 0x06;
+
 generic_protocol_setup!(MagicMotionV2, "magic-motion-2");
 
 #[derive(Default)]
 pub struct MagicMotionV2 {}
 
 impl ProtocolHandler for MagicMotionV2 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[
+      ActuatorType::Vibrate,
+      ActuatorType::Oscillate,
+      ActuatorType::Constrict,
+      ActuatorType::Heater,
+    ])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -34,47 +51,43 @@ impl ProtocolHandler for MagicMotionV2 {
     &self,
     cmds: &[Option<(ActuatorType, u32)>],
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let data = if cmds.len() == 1 {
-      vec![
-        0x10,
-        0xff,
-        0x04,
-        0x0a,
-        0x32,
-        0x0a,
-        0x00,
-        0x04,
-        0x08,
-        cmds[0].unwrap_or((ActuatorType::Vibrate, 0)).1 as u8,
-        0x64,
-        0x00,
-        0x04,
-        0x08,
-        0,
-        0x64,
-        0x01,
-      ]
-    } else {
-      vec![
-        0x10,
-        0xff,
-        0x04,
-        0x0a,
-        0x32,
-        0x0a,
-        0x00,
-        0x04,
-        0x08,
-        cmds[0].unwrap_or((ActuatorType::Vibrate, 0)).1 as u8,
-        0x64,
-        0x00,
-        0x04,
-        0x08,
-        cmds[1].unwrap_or((ActuatorType::Vibrate, 0)).1 as u8,
-        0x64,
-        0x01,
-      ]
-    };
+    // Vibration motors always get their two blocks, extra functions are appended after them.
+    let mut motors = vec![];
+    let mut extra_blocks = vec![];
+    for (actuator, scalar) in cmds.iter().flatten() {
+      match actuator {
+        ActuatorType::Constrict => {
+          extra_blocks.extend([MAGIC_MOTION_SUCTION, 0x08, *scalar as u8, 0x64, 0x00])
+        }
+        ActuatorType::Heater => {
+          extra_blocks.extend([MAGIC_MOTION_HEATER, 0x08, *scalar as u8, 0x64, 0x00])
+        }
+        _ => motors.push(*scalar as u8),
+      }
+    }
+    motors.resize(motors.len().max(1), 0);
+    let mut data = vec![
+      0x10,
+      0xff,
+      0x04,
+      0x0a,
+      0x32,
+      0x0a,
+      0x00,
+      MAGIC_MOTION_VIBRATE,
+      0x08,
+      motors[0],
+      0x64,
+      0x00,
+      MAGIC_MOTION_VIBRATE,
+      0x08,
+      if motors.len() == 1 { 0 } else { motors[1] },
+      0x64,
+      0x01,
+    ];
+    data.extend(extra_blocks);
+    // The first byte is the length of the rest of the packet.
+    data[0] = (data.len() - 1) as u8;
     Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, data, false).into()])
   }
 }
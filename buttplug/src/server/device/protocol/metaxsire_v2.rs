@@ -17,6 +17,7 @@ use crate::{
       protocol::{
         generic_protocol_initializer_setup,
         ProtocolAttributesType,
+        ProtocolCapabilities,
         ProtocolHandler,
         ProtocolIdentifier,
       },
@@ -50,6 +51,10 @@ impl ProtocolInitializer for MetaXSireV2Initializer {
 pub struct MetaXSireV2 {}
 
 impl ProtocolHandler for MetaXSireV2 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Oscillate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -0,0 +1,227 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Virtual device that drives a MIDI output port, through the MIDI communication manager.
+//!
+//! Scalar actuators become Control Change values (0-127), and linear actuators become pitch bend
+//! (0-16383, centered at 8192). Pitch bend jumps straight to whatever it's sent, so linear moves are
+//! stepped over their duration by a [LinearInterpolator]. Which channel and controller each actuator uses is set on the output
+//! definition, so the protocol only says which actuator changed and its new value. Writes to Tx are
+//! one of:
+//!
+//! - `[MIDI_PACKET_CONTROL_CHANGE, actuator index, value]`
+//! - `[MIDI_PACKET_PITCH_BEND, actuator index, value lsb, value msb]`, 7 bits in each byte
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{self, ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      linear_interpolation::LinearInterpolator,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+generic_protocol_initializer_setup!(Midi, "midi");
+
+/// Packet type for a scalar actuator value, sent as a Control Change.
+pub const MIDI_PACKET_CONTROL_CHANGE: u8 = 0x00;
+/// Packet type for a linear actuator position, sent as pitch bend.
+pub const MIDI_PACKET_PITCH_BEND: u8 = 0x01;
+
+const MIDI_PITCH_BEND_MAX: f64 = 16383f64;
+
+fn midi_packet(data: Vec<u8>) -> HardwareCommand {
+  HardwareWriteCmd::new(Endpoint::Tx, data, false).into()
+}
+
+fn pitch_bend_packet(index: u32, position: f64) -> HardwareCommand {
+  let bend = (position.clamp(0f64, 1f64) * MIDI_PITCH_BEND_MAX).round() as u16;
+  midi_packet(vec![
+    MIDI_PACKET_PITCH_BEND,
+    index as u8,
+    (bend & 0x7f) as u8,
+    (bend >> 7) as u8,
+  ])
+}
+
+#[derive(Default)]
+pub struct MidiInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for MidiInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let linear_count = attributes
+      .message_attributes()
+      .linear_cmd()
+      .as_ref()
+      .map_or(0, |linear| linear.len() as u32);
+    Ok(Arc::new(Midi::new(hardware, linear_count)))
+  }
+}
+
+pub struct Midi {
+  // One per linear actuator, indexed by actuator index.
+  pitch_bend_interpolators: Vec<LinearInterpolator>,
+}
+
+impl Midi {
+  fn new(hardware: Arc<Hardware>, linear_count: u32) -> Self {
+    Self {
+      pitch_bend_interpolators: (0..linear_count)
+        .map(|index| {
+          LinearInterpolator::new(hardware.clone(), move |position| {
+            Ok(vec![pitch_bend_packet(index, position)])
+          })
+        })
+        .collect(),
+    }
+  }
+}
+
+impl ProtocolHandler for Midi {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_linear()
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(
+      commands
+        .iter()
+        .enumerate()
+        .filter_map(|(index, command)| {
+          command.map(|(_, value)| {
+            midi_packet(vec![
+              MIDI_PACKET_CONTROL_CHANGE,
+              index as u8,
+              value.min(0x7f) as u8,
+            ])
+          })
+        })
+        .collect(),
+    )
+  }
+
+  fn handle_linear_cmd(
+    &self,
+    message: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    // Pitch bend has no notion of time, so moves are stepped by the interpolators, which write to
+    // the hardware themselves.
+    for vector in message.vectors() {
+      let interpolator = self
+        .pitch_bend_interpolators
+        .get(vector.index() as usize)
+        .ok_or_else(|| {
+          ButtplugDeviceError::DeviceFeatureIndexError(
+            self.pitch_bend_interpolators.len() as u32,
+            vector.index(),
+          )
+        })?;
+      interpolator.handle_vector(vector);
+    }
+    Ok(vec![])
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{setup::MidiIdentifierFactory, MIDI_PACKET_CONTROL_CHANGE, MIDI_PACKET_PITCH_BEND};
+  use crate::{
+    core::message::{self, ActuatorType, Endpoint},
+    server::device::{
+      hardware::{
+        scripted::{send_commands, setup_protocol, HardwareScript},
+        HardwareCommand,
+      },
+      protocol::ProtocolHandler,
+    },
+    util::sleep,
+  };
+  use std::time::Duration;
+
+  #[tokio::test]
+  async fn test_midi_commands() {
+    let mut script = HardwareScript::default();
+    // Center position is the pitch bend resting point, 8192, and 0.75 is 12287.
+    script
+      .expect_write(Endpoint::Tx, &[MIDI_PACKET_CONTROL_CHANGE, 1, 64])
+      .expect_write(Endpoint::Tx, &[MIDI_PACKET_PITCH_BEND, 0, 0x7f, 0x7f])
+      .expect_write(Endpoint::Tx, &[MIDI_PACKET_PITCH_BEND, 0, 0x7f, 0x5f])
+      .expect_write(Endpoint::Tx, &[MIDI_PACKET_PITCH_BEND, 0, 0x00, 0x40]);
+    let hardware = script.build_hardware("MIDI Output", "midi-test");
+    let (_, handler) = setup_protocol(&MidiIdentifierFactory::default(), hardware.clone())
+      .await
+      .expect("Test, assuming infallible.");
+
+    send_commands(
+      &hardware,
+      handler.handle_scalar_cmd(&[None, Some((ActuatorType::Vibrate, 64))]),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+    // Zero duration moves are written in a single step.
+    let commands = handler
+      .handle_linear_cmd(message::LinearCmd::new(
+        0,
+        vec![message::VectorSubcommand::new(0, 0, 1.0)],
+      ))
+      .expect("Test, assuming infallible.");
+    assert!(commands.is_empty());
+    sleep(Duration::from_millis(50)).await;
+    // 100ms at the default 50ms tick rate takes two steps.
+    handler
+      .handle_linear_cmd(message::LinearCmd::new(
+        0,
+        vec![message::VectorSubcommand::new(0, 100, 0.5)],
+      ))
+      .expect("Test, assuming infallible.");
+    sleep(Duration::from_millis(300)).await;
+    assert!(handler
+      .handle_linear_cmd(message::LinearCmd::new(
+        0,
+        vec![message::VectorSubcommand::new(1, 100, 0.5)],
+      ))
+      .is_err());
+    script.finish();
+  }
+
+  #[tokio::test]
+  async fn test_midi_scalar_skips_unchanged() {
+    let hardware = HardwareScript::default().build_hardware("MIDI Output", "midi-test");
+    let handler = super::Midi::new(hardware, 0);
+    let commands = handler
+      .handle_scalar_cmd(&[Some((ActuatorType::Vibrate, 200)), None])
+      .expect("Test, assuming infallible.");
+    assert_eq!(commands.len(), 1);
+    assert!(matches!(
+      &commands[0],
+      HardwareCommand::Write(cmd) if *cmd.data() == vec![MIDI_PACKET_CONTROL_CHANGE, 0, 0x7f]
+    ));
+  }
+}
@@ -7,19 +7,23 @@
 
 use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
+      ProtocolKeepalive,
     },
     ServerDeviceIdentifier,
   },
-  util::{async_manager, sleep},
 };
 use async_trait::async_trait;
 use std::sync::atomic::{AtomicU32, Ordering};
@@ -34,15 +38,15 @@ pub struct MizzZeeV3Initializer {}
 impl ProtocolInitializer for MizzZeeV3Initializer {
   async fn initialize(
     &mut self,
-    hardware: Arc<Hardware>,
+    _: Arc<Hardware>,
     _: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    Ok(Arc::new(MizzZeeV3::new(hardware)))
+    Ok(Arc::new(MizzZeeV3::default()))
   }
 }
 
-// Time between MizzZee v3 update commands, in milliseconds.
-const MIZZZEE3_COMMAND_DELAY_MS: u64 = 200;
+// Time between MizzZee v3 update commands.
+const MIZZZEE3_COMMAND_DELAY: Duration = Duration::from_millis(200);
 
 fn handle_scale(scale: f32) -> f32 {
   if scale == 0.0 {
@@ -78,53 +82,39 @@ fn scalar_to_vector(scalar: u32) -> Vec<u8> {
   data
 }
 
-async fn vibration_update_handler(device: Arc<Hardware>, current_scalar_holder: Arc<AtomicU32>) {
-  info!("Entering Mizz Zee v3 Control Loop");
-  let mut current_scalar = current_scalar_holder.load(Ordering::Relaxed);
-  while device
-    .write_value(&HardwareWriteCmd::new(
-      Endpoint::Tx,
-      scalar_to_vector(current_scalar),
-      true,
-    ))
-    .await
-    .is_ok()
-  {
-    sleep(Duration::from_millis(MIZZZEE3_COMMAND_DELAY_MS)).await;
-    current_scalar = current_scalar_holder.load(Ordering::Relaxed);
-    trace!("Mizz Zee v3 scalar: {}", current_scalar);
-  }
-  info!("Mizz Zee v3 control loop exiting, most likely due to device disconnection.");
-}
-
 #[derive(Default)]
 pub struct MizzZeeV3 {
-  current_scalar: Arc<AtomicU32>,
+  current_scalar: AtomicU32,
 }
 
-impl MizzZeeV3 {
-  fn new(device: Arc<Hardware>) -> Self {
-    let current_scalar = Arc::new(AtomicU32::new(0));
-    let current_scalar_clone = current_scalar.clone();
-    async_manager::spawn(
-      async move { vibration_update_handler(device, current_scalar_clone).await },
-    );
-    Self { current_scalar }
+impl ProtocolHandler for MizzZeeV3 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
   }
-}
 
-impl ProtocolHandler for MizzZeeV3 {
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::NoStrategy
   }
 
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::always(MIZZZEE3_COMMAND_DELAY))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      scalar_to_vector(self.current_scalar.load(Ordering::Relaxed)),
+      true,
+    )
+    .into()])
+  }
+
   fn handle_scalar_vibrate_cmd(
     &self,
     _index: u32,
     scalar: u32,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let current_scalar = self.current_scalar.clone();
-    current_scalar.store(scalar, Ordering::Relaxed);
+    self.current_scalar.store(scalar, Ordering::Relaxed);
     Ok(vec![HardwareWriteCmd::new(
       Endpoint::Tx,
       scalar_to_vector(scalar),
@@ -10,15 +10,28 @@
 pub mod generic_command_manager;
 
 // Utility mods
+pub mod capabilities;
 pub mod fleshlight_launch_helper;
+pub mod linear_interpolation;
+pub mod parsing;
+pub mod rotation_helper;
+
+pub use capabilities::ProtocolCapabilities;
 
 // Since users can pick and choose protocols, we need all of these to be public.
 pub mod adrienlastic;
 pub mod aneros;
 pub mod ankni;
+pub mod audio_output;
+pub mod autoblow;
+pub mod bhaptics;
+pub mod ble_advertisement_sensor;
 pub mod buttplug_passthru;
+pub mod buttplug_remote;
 pub mod cachito;
 pub mod cowgirl;
+pub mod dg_lab_coyote;
+pub mod dmx;
 pub mod evdev;
 pub mod foreo;
 pub mod fox;
@@ -51,6 +64,8 @@ pub mod lovehoney_desire;
 pub mod lovense;
 pub mod lovense_connect_service;
 pub mod lovenuts;
+#[cfg(feature = "lua-protocols")]
+pub mod lua_script;
 pub mod magic_motion_v1;
 pub mod magic_motion_v2;
 pub mod magic_motion_v3;
@@ -62,6 +77,7 @@ pub mod metaxsire;
 pub mod metaxsire_repeat;
 pub mod metaxsire_v2;
 pub mod metaxsire_v3;
+pub mod midi;
 pub mod mizzzee;
 pub mod mizzzee_v2;
 pub mod mizzzee_v3;
@@ -80,6 +96,8 @@ pub mod realov;
 pub mod sakuraneko;
 pub mod satisfyer;
 pub mod sensee;
+pub mod serial_template;
+pub mod sony_dualsense;
 pub mod svakom;
 pub mod svakom_alex;
 pub mod svakom_alex_v2;
@@ -128,7 +146,8 @@ use crate::{
   },
   server::device::{
     configuration::{ProtocolAttributesType, ProtocolCommunicationSpecifier},
-    hardware::{Hardware, HardwareCommand, HardwareReadCmd},
+    hardware::{Hardware, HardwareCommand, HardwareConnectionParameters, HardwareReadCmd},
+    safety::SafetyLimits,
     ServerDeviceIdentifier,
   },
 };
@@ -138,7 +157,7 @@ use futures::{
   StreamExt,
 };
 use std::pin::Pin;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 /// Strategy for situations where hardware needs to get updates every so often in order to keep
 /// things alive. Currently this only applies to iOS backgrounding with bluetooth devices, but since
@@ -149,9 +168,9 @@ use std::{collections::HashMap, sync::Arc};
 ///
 /// - All protocols use NoStrategy by default. For many devices, sending trash will break them in
 ///   very weird ways and we can't risk that, so we need to know the protocol context.
-/// - If the protocol already needs its own keepalive (Satisfyer, Mysteryvibe, etc...), use
-///   NoStrategy for now. RepeatLastPacketStrategy could be used, but we'd need per-protocol
-///   timeouts at that point.
+/// - If the protocol already needs its own keepalive (Satisfyer, Mysteryvibe, etc...), it should
+///   set up a [ProtocolKeepalive] and use NoStrategy here, as its keepalive will already keep the
+///   hardware busy.
 /// - If the protocol has a command that essentially does nothing to the actuators, set up
 ///   RepeatPacketStrategy to use that. This is useful for devices that have info commands (like
 ///   Lovense), ping commands (like The Handy), sensor commands that aren't yet subscribed to output
@@ -159,8 +178,8 @@ use std::{collections::HashMap, sync::Arc};
 /// - For many devices with only scalar actuators, RepeatLastPacketStrategy should work. You just
 ///   need to make sure the protocol doesn't have a packet counter or something else that will trip
 ///   if the same packet is replayed multiple times.
-/// - For all other devices, use Custom Strategy. This assumes the protocol will have implemented a
-///   method to generate a valid packet.
+/// - For all other devices, use Custom Strategy. This assumes the protocol will have implemented
+///   [ProtocolHandler::handle_keepalive] to generate a valid packet.
 #[derive(Debug)]
 pub enum ProtocolKeepaliveStrategy {
   /// Do nothing. This is for protocols that already require internal keepalives, like satisfyer,
@@ -171,13 +190,106 @@ pub enum ProtocolKeepaliveStrategy {
   /// Repeat whatever the last packet sent was, and send Stop commands until first packet sent. This
   /// will be useful for most devices that purely use scalar commands.
   RepeatLastPacketStrategy,
-  /// Call a specific method on the protocol implementation to generate keepalive packets.
+  /// Call [ProtocolHandler::handle_keepalive] to generate keepalive packets.
   CustomStrategy,
 }
 
+/// Keepalive for devices that shut themselves off, or drop their connection, if they aren't sent
+/// anything for a while. Unlike [ProtocolKeepaliveStrategy], this is about the device itself, not
+/// the platform, so it applies everywhere.
+///
+/// The device schedules keepalives itself, calling [ProtocolHandler::handle_keepalive] whenever
+/// nothing has been written to the hardware for the keepalive interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolKeepalive {
+  interval: Duration,
+  while_idle: bool,
+}
+
+impl ProtocolKeepalive {
+  /// Keepalive that's only sent while any actuator is running, for devices that stop moving when
+  /// they aren't refreshed.
+  pub fn while_active(interval: Duration) -> Self {
+    Self {
+      interval,
+      while_idle: false,
+    }
+  }
+
+  /// Keepalive that's sent for as long as the device is connected, for devices that disconnect
+  /// when they aren't refreshed.
+  pub fn always(interval: Duration) -> Self {
+    Self {
+      interval,
+      while_idle: true,
+    }
+  }
+
+  /// Longest time the hardware can go without a write.
+  pub fn interval(&self) -> Duration {
+    self.interval
+  }
+
+  /// True if keepalives are sent even when no actuators are running.
+  pub fn while_idle(&self) -> bool {
+    self.while_idle
+  }
+}
+
+/// Time a protocol has to identify and initialize a device, unless configured otherwise.
+pub const DEFAULT_INITIALIZATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Limits on how long protocol identification and initialization can take.
+///
+/// Handshakes that take longer than their protocol's timeout are cancelled, and the device fails to
+/// connect with a [HandshakeTimeout](crate::core::errors::ButtplugDeviceError::HandshakeTimeout)
+/// error, so a device that never answers doesn't hold up a connection slot forever. Timeouts can be
+/// set globally and overridden per protocol.
+#[derive(Debug, Clone)]
+pub struct ProtocolInitializationTimeouts {
+  timeout: Duration,
+  protocols: HashMap<String, Duration>,
+}
+
+impl Default for ProtocolInitializationTimeouts {
+  fn default() -> Self {
+    Self {
+      timeout: DEFAULT_INITIALIZATION_TIMEOUT,
+      protocols: HashMap::new(),
+    }
+  }
+}
+
+impl ProtocolInitializationTimeouts {
+  /// Timeout for every protocol without a timeout of its own.
+  pub fn global_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Timeout for the protocol with the given identifier.
+  pub fn protocol_timeout(&mut self, protocol: &str, timeout: Duration) -> &mut Self {
+    self.protocols.insert(protocol.to_owned(), timeout);
+    self
+  }
+
+  /// Timeout that applies to the protocol with the given identifier.
+  pub fn timeout_for(&self, protocol: &str) -> Duration {
+    self
+      .protocols
+      .get(protocol)
+      .copied()
+      .unwrap_or(self.timeout)
+  }
+}
+
 pub trait ProtocolIdentifierFactory: Send + Sync {
   fn identifier(&self) -> &str;
   fn create(&self) -> Box<dyn ProtocolIdentifier>;
+  /// Commands the protocol implements. See the [capabilities] module.
+  fn capabilities(&self) -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+  }
 }
 
 pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierFactory>> {
@@ -197,10 +309,18 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     adrienlastic::setup::AdrienLasticIdentifierFactory::default(),
   );
   add_to_protocol_map(&mut map, aneros::setup::AnerosIdentifierFactory::default());
+  add_to_protocol_map(
+    &mut map,
+    ble_advertisement_sensor::setup::BleAdvertisementSensorIdentifierFactory::default(),
+  );
   add_to_protocol_map(
     &mut map,
     buttplug_passthru::setup::ButtplugPassthruIdentifierFactory::default(),
   );
+  add_to_protocol_map(
+    &mut map,
+    buttplug_remote::setup::ButtplugRemoteIdentifierFactory::default(),
+  );
   add_to_protocol_map(
     &mut map,
     cachito::setup::CachitoIdentifierFactory::default(),
@@ -209,6 +329,11 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     cowgirl::setup::CowgirlIdentifierFactory::default(),
   );
+  add_to_protocol_map(
+    &mut map,
+    dg_lab_coyote::setup::DGLabCoyoteIdentifierFactory::default(),
+  );
+  add_to_protocol_map(&mut map, dmx::setup::DmxIdentifierFactory::default());
   add_to_protocol_map(
     &mut map,
     lovense::setup::LovenseIdentifierFactory::default(),
@@ -228,6 +353,18 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
   );
 
   add_to_protocol_map(&mut map, ankni::setup::AnkniIdentifierFactory::default());
+  add_to_protocol_map(
+    &mut map,
+    audio_output::setup::AudioOutputIdentifierFactory::default(),
+  );
+  add_to_protocol_map(
+    &mut map,
+    autoblow::setup::AutoblowIdentifierFactory::default(),
+  );
+  add_to_protocol_map(
+    &mut map,
+    bhaptics::setup::BHapticsIdentifierFactory::default(),
+  );
   add_to_protocol_map(&mut map, foreo::setup::ForeoIdentifierFactory::default());
   add_to_protocol_map(&mut map, fox::setup::FoxIdentifierFactory::default());
   add_to_protocol_map(
@@ -347,6 +484,7 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     &mut map,
     metaxsire_v3::setup::MetaXSireV3IdentifierFactory::default(),
   );
+  add_to_protocol_map(&mut map, midi::setup::MidiIdentifierFactory::default());
   add_to_protocol_map(
     &mut map,
     mizzzee::setup::MizzZeeIdentifierFactory::default(),
@@ -407,6 +545,14 @@ pub fn get_default_protocol_map() -> HashMap<String, Arc<dyn ProtocolIdentifierF
     satisfyer::setup::SatisfyerIdentifierFactory::default(),
   );
   add_to_protocol_map(&mut map, sensee::setup::SenseeIdentifierFactory::default());
+  add_to_protocol_map(
+    &mut map,
+    serial_template::setup::SerialTemplateIdentifierFactory::default(),
+  );
+  add_to_protocol_map(
+    &mut map,
+    sony_dualsense::setup::SonyDualSenseIdentifierFactory::default(),
+  );
   add_to_protocol_map(&mut map, svakom::setup::SvakomIdentifierFactory::default());
   add_to_protocol_map(
     &mut map,
@@ -516,21 +662,29 @@ fn print_type_of<T>(_: &T) -> &'static str {
 }
 
 pub struct ProtocolSpecializer {
+  protocol: String,
   specifiers: Vec<ProtocolCommunicationSpecifier>,
   identifier: Box<dyn ProtocolIdentifier>,
 }
 
 impl ProtocolSpecializer {
   pub fn new(
+    protocol: &str,
     specifiers: Vec<ProtocolCommunicationSpecifier>,
     identifier: Box<dyn ProtocolIdentifier>,
   ) -> Self {
     Self {
+      protocol: protocol.to_owned(),
       specifiers,
       identifier,
     }
   }
 
+  /// Name of the protocol, as used in the device configuration.
+  pub fn protocol(&self) -> &str {
+    &self.protocol
+  }
+
   pub fn specifiers(&self) -> &Vec<ProtocolCommunicationSpecifier> {
     &self.specifiers
   }
@@ -555,6 +709,23 @@ pub trait ProtocolInitializer: Sync + Send {
     hardware: Arc<Hardware>,
     attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError>;
+
+  /// Attributes the device reported about itself during identification. Only used if the device
+  /// configuration has no attributes for the device.
+  fn device_attributes(&self) -> Option<ProtocolDeviceAttributes> {
+    None
+  }
+
+  /// Variant of the identified model, for models whose features depend on more than the
+  /// identifier (firmware revisions and the like). Called before attributes are picked, and if the
+  /// device configuration has attributes for the variant, those are used, and the device is
+  /// identified as the variant from then on. Otherwise the model's attributes are used.
+  async fn variant(
+    &mut self,
+    _hardware: Arc<Hardware>,
+  ) -> Result<Option<String>, ButtplugDeviceError> {
+    Ok(None)
+  }
 }
 
 pub struct GenericProtocolIdentifier {
@@ -615,6 +786,15 @@ impl ProtocolInitializer for GenericProtocolInitializer {
 }
 
 pub trait ProtocolHandler: Sync + Send {
+  /// Commands this protocol implements, which has to match the command handlers it overrides. See
+  /// the [capabilities] module.
+  fn capabilities() -> ProtocolCapabilities
+  where
+    Self: Sized,
+  {
+    ProtocolCapabilities::default()
+  }
+
   fn needs_full_command_set(&self) -> bool {
     false
   }
@@ -627,6 +807,78 @@ pub trait ProtocolHandler: Sync + Send {
     ProtocolKeepaliveStrategy::NoStrategy
   }
 
+  /// Keepalive the device needs to keep running, if any. See [ProtocolKeepalive].
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    None
+  }
+
+  /// Commands to send to keep the device running, usually whatever was last sent to it. Called when
+  /// a [ProtocolKeepalive] is due, or for [ProtocolKeepaliveStrategy::CustomStrategy].
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("Keepalive")
+  }
+
+  /// True if the device can turn off its lights or go into standby while connected.
+  ///
+  /// Low power mode is opt-in, and only used for devices given an idle timeout in their user
+  /// configuration. Once such a device hasn't been sent a command and hasn't had anything running
+  /// for the idle timeout, [ProtocolHandler::handle_enter_low_power] is called. The next command
+  /// sent to the device is preceded by [ProtocolHandler::handle_wake].
+  fn supports_low_power(&self) -> bool {
+    false
+  }
+
+  /// Commands to put the device into low power mode (lights off, standby, etc...) once it's idle.
+  fn handle_enter_low_power(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("Enter Low Power")
+  }
+
+  /// Commands to bring the device out of low power mode, sent before the next command.
+  fn handle_wake(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("Wake")
+  }
+
+  /// Minimum time between scalar updates sent to the hardware. Updates that arrive faster than this
+  /// are coalesced, so only the latest value is written once the interval has passed. Can be
+  /// overridden per device via user configuration.
+  fn scalar_command_rate_limit(&self) -> Option<Duration> {
+    None
+  }
+
+  /// Link parameters (MTU, connection interval, etc...) to request from the hardware once the
+  /// protocol is initialized. Failing to apply these is not considered a connection failure, as
+  /// most platforms can't set them.
+  fn connection_parameters(&self) -> Option<HardwareConnectionParameters> {
+    None
+  }
+
+  /// If true, consecutive writes in the commands this protocol returns are sent to the hardware as
+  /// a single batch, which the hardware can pipeline instead of waiting for each write to finish.
+  /// Only for protocols where a write doesn't depend on the device having handled the one before.
+  fn pipeline_writes(&self) -> bool {
+    false
+  }
+
+  /// Safety limits the hardware always needs, no matter what the server is configured with. These
+  /// are combined with the server's [SafetyPolicy](crate::server::device::SafetyPolicy), keeping
+  /// the stricter of each limit.
+  fn safety_limits(&self) -> SafetyLimits {
+    SafetyLimits::default()
+  }
+
+  /// Conservative safety limits used until the server owner sets their own for this device. Unlike
+  /// [safety_limits](ProtocolHandler::safety_limits), limits set for the device in the server's
+  /// [SafetyPolicy](crate::server::device::SafetyPolicy) replace these.
+  fn default_safety_limits(&self) -> SafetyLimits {
+    SafetyLimits::default()
+  }
+
+  /// True if a notification from the device means it's about to turn off, so its disconnect can be
+  /// reported to clients as the device being powered off instead of lost.
+  fn is_power_off_notification(&self, _endpoint: Endpoint, _data: &[u8]) -> bool {
+    false
+  }
+
   fn handle_message(
     &self,
     message: &ButtplugDeviceCommandMessageUnion,
@@ -665,6 +917,9 @@ pub trait ProtocolHandler: Sync + Send {
           ActuatorType::Rotate => self.handle_scalar_rotate_cmd(index as u32, *scalar)?,
           ActuatorType::Vibrate => self.handle_scalar_vibrate_cmd(index as u32, *scalar)?,
           ActuatorType::Position => self.handle_scalar_position_cmd(index as u32, *scalar)?,
+          ActuatorType::Heater => self.handle_scalar_heater_cmd(index as u32, *scalar)?,
+          ActuatorType::Frequency => self.handle_scalar_frequency_cmd(index as u32, *scalar)?,
+          ActuatorType::Estim => self.handle_scalar_estim_cmd(index as u32, *scalar)?,
           ActuatorType::Unknown => Err(ButtplugDeviceError::UnhandledCommand(
             "Unknown actuator types are not controllable.".to_owned(),
           ))?,
@@ -722,6 +977,30 @@ pub trait ProtocolHandler: Sync + Send {
     self.command_unimplemented("ScalarCmd (Constrict Actuator)")
   }
 
+  fn handle_scalar_heater_cmd(
+    &self,
+    _index: u32,
+    _scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("ScalarCmd (Heater Actuator)")
+  }
+
+  fn handle_scalar_frequency_cmd(
+    &self,
+    _index: u32,
+    _scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("ScalarCmd (Frequency Actuator)")
+  }
+
+  fn handle_scalar_estim_cmd(
+    &self,
+    _index: u32,
+    _scalar: u32,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.command_unimplemented("ScalarCmd (Estim Actuator)")
+  }
+
   fn handle_vorze_a10_cyclone_cmd(
     &self,
     message: message::VorzeA10CycloneCmd,
@@ -763,7 +1042,7 @@ pub trait ProtocolHandler: Sync + Send {
     _message: message::SensorSubscribeCmd,
   ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
     future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Command not implemented for this protocol: BatteryCmd".to_string(),
+      "Command not implemented for this protocol: SensorSubscribeCmd".to_string(),
     )))
     .boxed()
   }
@@ -774,11 +1053,15 @@ pub trait ProtocolHandler: Sync + Send {
     _message: message::SensorUnsubscribeCmd,
   ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
     future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Command not implemented for this protocol: BatteryCmd".to_string(),
+      "Command not implemented for this protocol: SensorUnsubscribeCmd".to_string(),
     )))
     .boxed()
   }
 
+  /// Entry point for all sensor reads, including the ones older spec clients make with
+  /// BatteryLevelCmd and RSSILevelCmd. By default, battery and RSSI reads go to the handlers below,
+  /// and anything else is unsupported. Protocols with other sensors, or that need to handle reads
+  /// differently, should override this.
   fn handle_sensor_read_cmd(
     &self,
     device: Arc<Hardware>,
@@ -786,6 +1069,7 @@ pub trait ProtocolHandler: Sync + Send {
   ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
     match message.sensor_type() {
       SensorType::Battery => self.handle_battery_level_cmd(device, message),
+      SensorType::RSSI => self.handle_rssi_level_cmd(device, message),
       _ => future::ready(Err(ButtplugDeviceError::UnhandledCommand(
         "Command not implemented for this protocol: SensorReadCmd".to_string(),
       )))
@@ -806,7 +1090,7 @@ pub trait ProtocolHandler: Sync + Send {
       let fut = device.read_value(&msg);
       async move {
         let hw_msg = fut.await?;
-        let battery_level = hw_msg.data()[0] as i32;
+        let battery_level = parsing::battery_level("battery", hw_msg.data())?;
         let battery_reading = message::SensorReading::new(
           message.device_index(),
           *message.sensor_index(),
@@ -827,13 +1111,27 @@ pub trait ProtocolHandler: Sync + Send {
 
   fn handle_rssi_level_cmd(
     &self,
-    _device: Arc<Hardware>,
-    _message: message::RSSILevelCmd,
+    device: Arc<Hardware>,
+    message: message::SensorReadCmd,
   ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
-    future::ready(Err(ButtplugDeviceError::UnhandledCommand(
-      "Command not implemented for this protocol: SensorReadCmd".to_string(),
-    )))
-    .boxed()
+    // Transports that know the signal strength store it on the hardware when connecting.
+    if let Some(rssi) = device.rssi() {
+      future::ready(Ok(
+        message::SensorReading::new(
+          message.device_index(),
+          *message.sensor_index(),
+          *message.sensor_type(),
+          vec![rssi],
+        )
+        .into(),
+      ))
+      .boxed()
+    } else {
+      future::ready(Err(ButtplugDeviceError::UnhandledCommand(
+        "Command not implemented for this protocol: SensorReadCmd".to_string(),
+      )))
+      .boxed()
+    }
   }
 
   fn event_stream(
@@ -850,7 +1148,8 @@ macro_rules! generic_protocol_setup {
       pub mod setup {
         use std::sync::Arc;
         use $crate::server::device::protocol::{
-          GenericProtocolIdentifier, ProtocolIdentifier, ProtocolIdentifierFactory,
+          GenericProtocolIdentifier, ProtocolCapabilities, ProtocolHandler, ProtocolIdentifier,
+          ProtocolIdentifierFactory,
         };
         #[derive(Default)]
         pub struct [< $protocol_name IdentifierFactory >] {}
@@ -866,6 +1165,10 @@ macro_rules! generic_protocol_setup {
               self.identifier(),
             ))
           }
+
+          fn capabilities(&self) -> ProtocolCapabilities {
+            <super::$protocol_name as ProtocolHandler>::capabilities()
+          }
         }
       }
     }
@@ -877,7 +1180,9 @@ macro_rules! generic_protocol_initializer_setup {
   ( $protocol_name:ident, $protocol_identifier:tt) => {
     paste::paste! {
       pub mod setup {
-        use $crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+        use $crate::server::device::protocol::{
+          ProtocolCapabilities, ProtocolHandler, ProtocolIdentifier, ProtocolIdentifierFactory,
+        };
         #[derive(Default)]
         pub struct [< $protocol_name IdentifierFactory >] {}
 
@@ -889,6 +1194,10 @@ macro_rules! generic_protocol_initializer_setup {
           fn create(&self) -> Box<dyn ProtocolIdentifier> {
             Box::new(super::[< $protocol_name Identifier >]::default())
           }
+
+          fn capabilities(&self) -> ProtocolCapabilities {
+            <super::$protocol_name as ProtocolHandler>::capabilities()
+          }
         }
       }
 
@@ -13,15 +13,65 @@ use crate::{
   server::device::{
     configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{
+      parsing::{byte_at, bytes_at, data_str},
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
     ServerDeviceIdentifier,
   },
 };
 use async_trait::async_trait;
 use std::sync::Arc;
 
+const MONSTERPUB_AUTH_KEYS: [[u8; 15]; 4] = [
+  [
+    0x32, 0x49, 0x50, 0x4f, 0x32, 0x49, 0x50, 0x4f, 0x32, 0x49, 0x50, 0x4f, 0x32, 0x49, 0x50,
+  ],
+  [
+    0x4c, 0x53, 0x42, 0x42, 0x4c, 0x53, 0x42, 0x42, 0x4c, 0x53, 0x42, 0x42, 0x4c, 0x53, 0x42,
+  ],
+  [
+    0x53, 0x49, 0x53, 0x36, 0x53, 0x49, 0x53, 0x36, 0x53, 0x49, 0x53, 0x36, 0x53, 0x49, 0x53,
+  ],
+  [
+    0x54, 0x41, 0x4c, 0x4b, 0x54, 0x41, 0x4c, 0x4b, 0x54, 0x41, 0x4c, 0x4b, 0x54, 0x41, 0x4c,
+  ],
+];
+
+/// Model name from the model endpoint, which is padded with nulls.
+pub fn parse_model_name(data: &[u8]) -> Result<String, ButtplugDeviceError> {
+  Ok(data_str("monsterpub", data)?.replace('\0', ""))
+}
+
+/// Answer to the auth challenge read from rx. The first byte picks the key the other 15 are XORed
+/// with.
+pub fn auth_response(challenge: &[u8]) -> Result<Vec<u8>, ButtplugDeviceError> {
+  let key_index = byte_at("monsterpub", challenge, 0)? as usize;
+  let key = MONSTERPUB_AUTH_KEYS.get(key_index).ok_or_else(|| {
+    ButtplugDeviceError::ProtocolSpecificError(
+      "monsterpub".to_owned(),
+      format!("Unknown auth key {}", key_index),
+    )
+  })?;
+  Ok(
+    bytes_at("monsterpub", challenge, 1..16)?
+      .iter()
+      .zip(key.iter())
+      .map(|(&x1, &x2)| x1 ^ x2)
+      .collect(),
+  )
+}
+
 pub mod setup {
-  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
   #[derive(Default)]
   pub struct MonsterPubIdentifierFactory {}
 
@@ -33,6 +83,10 @@ pub mod setup {
     fn create(&self) -> Box<dyn ProtocolIdentifier> {
       Box::new(super::MonsterPubIdentifier::default())
     }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::MonsterPub as ProtocolHandler>::capabilities()
+    }
   }
 }
 
@@ -49,15 +103,7 @@ impl ProtocolIdentifier for MonsterPubIdentifier {
       .read_value(&HardwareReadCmd::new(Endpoint::RxBLEModel, 32, 500))
       .await;
     let ident = match read_resp {
-      Ok(data) => std::str::from_utf8(&data.data())
-        .map_err(|_| {
-          ButtplugDeviceError::ProtocolSpecificError(
-            "monsterpub".to_owned(),
-            "MonsterPub device name is non-UTF8 string.".to_owned(),
-          )
-        })?
-        .replace("\0", "")
-        .to_owned(),
+      Ok(data) => parse_model_name(data.data())?,
       Err(_) => "Unknown".to_string(),
     };
     return Ok((
@@ -85,34 +131,9 @@ impl ProtocolInitializer for MonsterPubInitializer {
       let value = hardware
         .read_value(&HardwareReadCmd::new(Endpoint::Rx, 16, 200))
         .await?;
-      let keys = [
-        [
-          0x32u8, 0x49, 0x50, 0x4f, 0x32, 0x49, 0x50, 0x4f, 0x32, 0x49, 0x50, 0x4f, 0x32, 0x49,
-          0x50,
-        ],
-        [
-          0x4c, 0x53, 0x42, 0x42, 0x4c, 0x53, 0x42, 0x42, 0x4c, 0x53, 0x42, 0x42, 0x4c, 0x53, 0x42,
-        ],
-        [
-          0x53, 0x49, 0x53, 0x36, 0x53, 0x49, 0x53, 0x36, 0x53, 0x49, 0x53, 0x36, 0x53, 0x49, 0x53,
-        ],
-        [
-          0x54, 0x41, 0x4c, 0x4b, 0x54, 0x41, 0x4c, 0x4b, 0x54, 0x41, 0x4c, 0x4b, 0x54, 0x41, 0x4c,
-        ],
-      ];
-
-      let auth = value.data()[1..16]
-        .iter()
-        .zip(keys[value.data()[0] as usize].iter())
-        .map(|(&x1, &x2)| x1 ^ x2)
-        .collect();
-
-      trace!(
-        "Got {:?} XOR with key {} to get {:?}",
-        value.data(),
-        value.data()[0],
-        auth
-      );
+      let auth = auth_response(value.data())?;
+
+      trace!("Got {:?} to get {:?}", value.data(), auth);
 
       hardware
         .write_value(&HardwareWriteCmd::new(Endpoint::Rx, auth, true))
@@ -139,6 +160,10 @@ impl MonsterPub {
 }
 
 impl ProtocolHandler for MonsterPub {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn needs_full_command_set(&self) -> bool {
     true
   }
@@ -16,17 +16,20 @@ use crate::{
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
+      ProtocolKeepalive,
     },
     ServerDeviceIdentifier,
   },
-  util::{async_manager, sleep},
 };
 use async_trait::async_trait;
-use std::{sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use std::{
+  sync::{Arc, RwLock},
+  time::Duration,
+};
 
 generic_protocol_initializer_setup!(MysteryVibe, "mysteryvibe");
 
@@ -42,7 +45,7 @@ impl ProtocolInitializer for MysteryVibeInitializer {
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
     let msg = HardwareWriteCmd::new(Endpoint::TxMode, vec![0x43u8, 0x02u8, 0x00u8], true);
     hardware.write_value(&msg).await?;
-    Ok(Arc::new(MysteryVibe::new(hardware)))
+    Ok(Arc::new(MysteryVibe::default()))
   }
 }
 
@@ -51,63 +54,66 @@ impl ProtocolInitializer for MysteryVibeInitializer {
 //
 // Thelemic vibrator. Neat.
 //
-const MYSTERYVIBE_COMMAND_DELAY_MS: u64 = 93;
-
-async fn vibration_update_handler(device: Arc<Hardware>, command_holder: Arc<RwLock<Vec<u8>>>) {
-  info!("Entering Mysteryvibe Control Loop");
-  let mut current_command = command_holder.read().await.clone();
-  while device
-    .write_value(&HardwareWriteCmd::new(
-      Endpoint::TxVibrate,
-      current_command,
-      false,
-    ))
-    .await
-    .is_ok()
-  {
-    sleep(Duration::from_millis(MYSTERYVIBE_COMMAND_DELAY_MS)).await;
-    current_command = command_holder.read().await.clone();
-    info!("MV Command: {:?}", current_command);
-  }
-  info!("Mysteryvibe control loop exiting, most likely due to device disconnection.");
-}
+const MYSTERYVIBE_COMMAND_DELAY: Duration = Duration::from_millis(93);
 
 pub struct MysteryVibe {
-  current_command: Arc<RwLock<Vec<u8>>>,
+  current_command: RwLock<Vec<u8>>,
 }
 
-impl MysteryVibe {
-  fn new(device: Arc<Hardware>) -> Self {
-    let current_command = Arc::new(RwLock::new(vec![0u8, 0, 0, 0, 0, 0]));
-    let current_command_clone = current_command.clone();
-    async_manager::spawn(
-      async move { vibration_update_handler(device, current_command_clone).await },
-    );
-    Self { current_command }
+impl Default for MysteryVibe {
+  fn default() -> Self {
+    Self {
+      current_command: RwLock::new(vec![0u8, 0, 0, 0, 0, 0]),
+    }
   }
 }
 
 impl ProtocolHandler for MysteryVibe {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn needs_full_command_set(&self) -> bool {
     true
   }
 
+  // The device needs a constant stream of updates, otherwise it stops.
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::always(MYSTERYVIBE_COMMAND_DELAY))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let command = self
+      .current_command
+      .read()
+      .expect("Lock is never poisoned")
+      .clone();
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::TxVibrate,
+      command,
+      false,
+    )
+    .into()])
+  }
+
   fn handle_scalar_cmd(
     &self,
     cmds: &[Option<(ActuatorType, u32)>],
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let current_command = self.current_command.clone();
-    let cmds = cmds.to_vec();
-    async_manager::spawn(async move {
-      let write_mutex = current_command.clone();
-      let mut command_writer = write_mutex.write().await;
-      let command: Vec<u8> = cmds
-        .into_iter()
-        .map(|x| x.expect("Validity ensured via GCM match_all").1 as u8)
-        .collect();
-      *command_writer = command;
-    });
-    Ok(vec![])
+    let command: Vec<u8> = cmds
+      .iter()
+      .map(|x| x.expect("Validity ensured via GCM match_all").1 as u8)
+      .collect();
+    *self
+      .current_command
+      .write()
+      .expect("Lock is never poisoned") = command.clone();
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::TxVibrate,
+      command,
+      false,
+    )
+    .into()])
   }
 }
 
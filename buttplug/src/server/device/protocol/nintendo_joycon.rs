@@ -1,13 +1,17 @@
 #[cfg(feature = "wasm")]
 use crate::util;
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   generic_protocol_initializer_setup,
   server::device::{
     configuration::ProtocolDeviceAttributes,
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       ProtocolAttributesType,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -19,7 +23,7 @@ use crate::{
 use async_trait::async_trait;
 use std::{
   sync::{
-    atomic::{AtomicBool, AtomicU16, Ordering},
+    atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering},
     Arc,
   },
   time::Duration,
@@ -38,10 +42,10 @@ async fn send_command_raw(
   rumble_l: Option<Rumble>,
 ) -> Result<(), ButtplugDeviceError> {
   let mut buf = [0x0; 0x40];
-  // set command
+  // set command. This is also the HID output report ID.
   buf[0] = command;
-  // set packet number
-  buf[1] = packet_number;
+  // set packet number. Controllers only look at the low nibble, and drop packets that repeat it.
+  buf[1] = packet_number & 0x0F;
 
   // rumble
   if let Some(rumble_l) = rumble_l {
@@ -174,50 +178,43 @@ impl Rumble {
   }
 }
 
-impl Into<[u8; 4]> for Rumble {
-  fn into(self) -> [u8; 4] {
-    let encoded_hex_freq = f32::round(f32::log2(self.frequency / 10.0) * 32.0) as u8;
+impl From<Rumble> for [u8; 4] {
+  /// Encodes to the Switch HD rumble format. The same frequency and amplitude is used for both the
+  /// high and low bands, so frequencies outside of ~82-626Hz will clip in one of them.
+  fn from(rumble: Rumble) -> [u8; 4] {
+    // Zero amplitude (or frequency) can't be log encoded, but the controllers have a well known
+    // "neutral" packet for that.
+    if rumble.amplitude <= 0.0 || rumble.frequency <= 0.0 {
+      return [0x00, 0x01, 0x40, 0x40];
+    }
+
+    let encoded_hex_freq = f32::round(f32::log2(rumble.frequency / 10.0) * 32.0) as u8;
 
-    let hf_freq: u16 = (encoded_hex_freq as u16).saturating_sub(0x60) * 4;
-    let lf_freq: u8 = encoded_hex_freq.saturating_sub(0x41) + 1;
+    // High band covers 0x60-0xDF, low band covers 0x40-0xBF. Clamp rather than wrap so out of range
+    // frequencies end up at the edge of the band.
+    let hf_freq: u16 = (encoded_hex_freq.clamp(0x60, 0xDF) as u16 - 0x60) * 4;
+    let lf_freq: u8 = encoded_hex_freq.clamp(0x40, 0xBF) - 0x40;
 
-    let encoded_hex_amp = if self.amplitude > 0.23 {
-      f32::round(f32::log2(self.amplitude * 8.7) * 32.0) as u8
-    } else if self.amplitude > 0.12 {
-      f32::round(f32::log2(self.amplitude * 17.0) * 16.0) as u8
+    let encoded_hex_amp = if rumble.amplitude > 0.23 {
+      f32::round(f32::log2(rumble.amplitude * 8.7) * 32.0) as u8
+    } else if rumble.amplitude > 0.12 {
+      f32::round(f32::log2(rumble.amplitude * 17.0) * 16.0) as u8
     } else {
-      f32::round(((f32::log2(self.amplitude) * 32.0) - 96.0) / (4.0 - 2.0 * self.amplitude)) as u8
+      f32::round(((f32::log2(rumble.amplitude) * 32.0) - 96.0) / (4.0 - 2.0 * rumble.amplitude))
+        as u8
     };
 
-    let hf_amp: u16 = {
-      let hf_amp: u16 = encoded_hex_amp as u16 * 2;
-      if hf_amp > 0x01FC {
-        0x01FC
-      } else {
-        hf_amp
-      }
-    }; // encoded_hex_amp<<1;
-    let lf_amp: u8 = {
-      let lf_amp = encoded_hex_amp / 2 + 64;
-      if lf_amp > 0x7F {
-        0x7F
-      } else {
-        lf_amp
-      }
-    }; // (encoded_hex_amp>>1)+0x40;
-
-    let mut buf = [0u8; 4];
-
-    // HF: Byte swapping
-    buf[0] = (hf_freq & 0xFF) as u8;
-    // buf[1] = (hf_amp + ((hf_freq >> 8) & 0xFF)) as u8; //Add amp + 1st byte of frequency to amplitude byte
-    buf[1] = (hf_amp + (hf_freq.wrapping_shr(8) & 0xFF)) as u8; //Add amp + 1st byte of frequency to amplitude byte
-
-    // LF: Byte swapping
-    buf[2] = lf_freq.saturating_add(lf_amp.wrapping_shr(8));
-    buf[3] = lf_amp;
-
-    buf
+    let hf_amp: u16 = (encoded_hex_amp as u16 * 2).min(0x01FC);
+    let lf_amp: u16 = (encoded_hex_amp as u16 / 2 + 0x40).min(0x7F);
+
+    [
+      // HF: frequency low byte, then amplitude plus the frequency high bit.
+      (hf_freq & 0xFF) as u8,
+      (hf_amp + (hf_freq >> 8)) as u8,
+      // LF: frequency plus the amplitude high bit, then amplitude.
+      lf_freq + (lf_amp >> 8) as u8,
+      (lf_amp & 0xFF) as u8,
+    ]
   }
 }
 
@@ -233,7 +230,8 @@ impl ProtocolInitializer for NintendoJoyconInitializer {
     hardware: Arc<Hardware>,
     _: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
-    send_sub_command(hardware.clone(), 0, 72, &[0x01])
+    // Enable vibration
+    send_sub_command(hardware.clone(), 0, 0x48, &[0x01])
       .await
       .map_err(|_| {
         ButtplugDeviceError::DeviceConnectionError("Cannot initialize joycon".to_owned())
@@ -258,6 +256,8 @@ impl NintendoJoycon {
     let notifier_clone = notifier.clone();
     let is_stopped = Arc::new(AtomicBool::new(false));
     let is_stopped_clone = is_stopped.clone();
+    // Initialization used packet 0.
+    let packet_number = AtomicU8::new(1);
     async_manager::spawn(async move {
       loop {
         if is_stopped_clone.load(Ordering::Relaxed) {
@@ -270,8 +270,18 @@ impl NintendoJoycon {
           Rumble::stop()
         };
 
-        if let Err(_) =
-          send_command_raw(hardware.clone(), 1, 16, 0, &[], Some(rumble), Some(rumble)).await
+        let packet_number = packet_number.fetch_add(1, Ordering::Relaxed);
+        if send_command_raw(
+          hardware.clone(),
+          packet_number,
+          0x10,
+          0,
+          &[],
+          Some(rumble),
+          Some(rumble),
+        )
+        .await
+        .is_err()
         {
           error!("Joycon command failed, exiting update loop");
           break;
@@ -296,6 +306,10 @@ impl NintendoJoycon {
 }
 
 impl ProtocolHandler for NintendoJoycon {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn handle_scalar_vibrate_cmd(
     &self,
     _: u32,
@@ -308,7 +322,21 @@ impl ProtocolHandler for NintendoJoycon {
 
 impl Drop for NintendoJoycon {
   fn drop(&mut self) {
-    self.is_stopped.store(false, Ordering::Relaxed);
+    self.is_stopped.store(true, Ordering::Relaxed);
     self.notifier.notify_one();
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::Rumble;
+
+  #[test]
+  fn test_rumble_encoding() {
+    let stop: [u8; 4] = Rumble::stop().into();
+    assert_eq!(stop, [0x00, 0x01, 0x40, 0x40]);
+    // 160Hz encodes to 0x80 (0x80 high band, 0x40 low band), amplitude 1.0 encodes to 0x64.
+    let rumble: [u8; 4] = Rumble::new(160.0, 1.0).into();
+    assert_eq!(rumble, [0x80, 0xC8, 0x40, 0x72]);
+  }
+}
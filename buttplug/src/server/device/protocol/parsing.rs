@@ -0,0 +1,88 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Checked access to data received from devices.
+//!
+//! Notifications and reads come straight from hardware, and can be short, garbled, or from a
+//! firmware version nobody has seen yet. Protocols should get at them through these helpers, which
+//! return errors instead of panicking the server. Protocol parsers built on them are fuzzed by the
+//! targets in the `fuzz` directory.
+
+use crate::core::errors::ButtplugDeviceError;
+use std::ops::Range;
+
+fn too_short(protocol: &str, data: &[u8], needed: usize) -> ButtplugDeviceError {
+  ButtplugDeviceError::ProtocolSpecificError(
+    protocol.to_owned(),
+    format!(
+      "Expected at least {} bytes from device, got {:?}",
+      needed, data
+    ),
+  )
+}
+
+/// Device data as a string.
+pub fn data_str<'a>(protocol: &str, data: &'a [u8]) -> Result<&'a str, ButtplugDeviceError> {
+  std::str::from_utf8(data).map_err(|_| {
+    ButtplugDeviceError::ProtocolSpecificError(
+      protocol.to_owned(),
+      format!("Device sent non-UTF8 data: {:?}", data),
+    )
+  })
+}
+
+pub fn byte_at(protocol: &str, data: &[u8], index: usize) -> Result<u8, ButtplugDeviceError> {
+  data
+    .get(index)
+    .copied()
+    .ok_or_else(|| too_short(protocol, data, index + 1))
+}
+
+pub fn bytes_at<'a>(
+  protocol: &str,
+  data: &'a [u8],
+  range: Range<usize>,
+) -> Result<&'a [u8], ButtplugDeviceError> {
+  let needed = range.end;
+  data
+    .get(range)
+    .ok_or_else(|| too_short(protocol, data, needed))
+}
+
+/// Big endian u16 starting at the index.
+pub fn u16_be_at(protocol: &str, data: &[u8], index: usize) -> Result<u16, ButtplugDeviceError> {
+  match bytes_at(protocol, data, index..index.saturating_add(2))? {
+    [high, low] => Ok(u16::from_be_bytes([*high, *low])),
+    _ => Err(too_short(protocol, data, usize::MAX)),
+  }
+}
+
+/// Battery level from the first byte of a reading, as sent by the standard BLE battery service.
+pub fn battery_level(protocol: &str, data: &[u8]) -> Result<i32, ButtplugDeviceError> {
+  byte_at(protocol, data, 0).map(|level| level as i32)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_checked_access() {
+    let data = [0x01, 0x02, 0x03];
+    assert_eq!(byte_at("test", &data, 2).unwrap(), 0x03);
+    assert!(byte_at("test", &data, 3).is_err());
+    assert_eq!(bytes_at("test", &data, 1..3).unwrap(), &[0x02, 0x03]);
+    assert!(bytes_at("test", &data, 2..4).is_err());
+    assert!(bytes_at("test", &data, usize::MAX..usize::MAX).is_err());
+    assert_eq!(u16_be_at("test", &data, 1).unwrap(), 0x0203);
+    assert!(u16_be_at("test", &data, 2).is_err());
+    assert!(u16_be_at("test", &data, usize::MAX).is_err());
+    assert!(battery_level("test", &[]).is_err());
+    assert_eq!(data_str("test", b"OK;").unwrap(), "OK;");
+    assert!(data_str("test", &[0xff]).is_err());
+  }
+}
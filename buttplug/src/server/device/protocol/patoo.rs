@@ -14,7 +14,7 @@ use crate::{
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{ProtocolCapabilities, ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
     ServerDeviceIdentifier,
   },
 };
@@ -22,7 +22,12 @@ use async_trait::async_trait;
 use std::sync::Arc;
 
 pub mod setup {
-  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
   #[derive(Default)]
   pub struct PatooIdentifierFactory {}
 
@@ -34,6 +39,10 @@ pub mod setup {
     fn create(&self) -> Box<dyn ProtocolIdentifier> {
       Box::new(super::PatooIdentifier::default())
     }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::Patoo as ProtocolHandler>::capabilities()
+    }
   }
 }
 
@@ -83,6 +92,10 @@ impl ProtocolInitializer for PatooInitializer {
 pub struct Patoo {}
 
 impl ProtocolHandler for Patoo {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
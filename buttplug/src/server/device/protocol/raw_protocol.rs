@@ -5,7 +5,11 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use crate::server::device::protocol::{generic_protocol_setup, ProtocolHandler};
+use crate::server::device::protocol::{
+  generic_protocol_setup,
+  ProtocolCapabilities,
+  ProtocolHandler,
+};
 
 generic_protocol_setup!(RawProtocol, "raw");
 
@@ -13,6 +17,9 @@ generic_protocol_setup!(RawProtocol, "raw");
 pub struct RawProtocol {}
 
 impl ProtocolHandler for RawProtocol {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+  }
 }
 
 // TODO Write tests
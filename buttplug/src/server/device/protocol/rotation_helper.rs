@@ -0,0 +1,97 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Rotation state for protocols that need to remember what they've sent to a device.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Direction a device is rotating in. Some devices (like Lovense) don't take a direction with their
+/// rotation commands, they just flip direction when told to, so the protocol needs to remember
+/// which way the device is going.
+#[derive(Debug, Default)]
+pub struct RotationDirection {
+  clockwise: AtomicBool,
+}
+
+impl RotationDirection {
+  pub fn clockwise(&self) -> bool {
+    self.clockwise.load(Ordering::SeqCst)
+  }
+
+  /// Record the direction the device should be rotating in. Returns true if that's a change.
+  pub fn set(&self, clockwise: bool) -> bool {
+    self.clockwise.swap(clockwise, Ordering::SeqCst) != clockwise
+  }
+}
+
+/// Speed and direction of every rotation motor on a device, for protocols that send the state of
+/// all motors whenever any of them change.
+#[derive(Debug)]
+pub struct RotationState {
+  motors: Vec<(AtomicU32, AtomicBool)>,
+}
+
+impl RotationState {
+  pub fn new(motor_count: usize) -> Self {
+    Self {
+      motors: (0..motor_count)
+        .map(|_| (AtomicU32::new(0), AtomicBool::new(false)))
+        .collect(),
+    }
+  }
+
+  /// Apply the commands that have values, leaving the other motors as they were. Returns true if
+  /// any commands were applied.
+  pub fn update(&self, commands: &[Option<(u32, bool)>]) -> bool {
+    let mut updated = false;
+    for ((speed, clockwise), command) in self.motors.iter().zip(commands) {
+      if let Some((new_speed, new_clockwise)) = command {
+        speed.store(*new_speed, Ordering::SeqCst);
+        clockwise.store(*new_clockwise, Ordering::SeqCst);
+        updated = true;
+      }
+    }
+    updated
+  }
+
+  /// Current speed and direction of every motor.
+  pub fn motors(&self) -> Vec<(u32, bool)> {
+    self
+      .motors
+      .iter()
+      .map(|(speed, clockwise)| {
+        (
+          speed.load(Ordering::SeqCst),
+          clockwise.load(Ordering::SeqCst),
+        )
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{RotationDirection, RotationState};
+
+  #[test]
+  fn test_rotation_direction_changes() {
+    let direction = RotationDirection::default();
+    assert!(!direction.set(false));
+    assert!(direction.set(true));
+    assert!(direction.clockwise());
+    assert!(!direction.set(true));
+  }
+
+  #[test]
+  fn test_rotation_state_keeps_unchanged_motors() {
+    let state = RotationState::new(2);
+    assert!(state.update(&[Some((10, true)), Some((20, false))]));
+    assert!(state.update(&[None, Some((30, true))]));
+    assert_eq!(state.motors(), vec![(10, true), (30, true)]);
+    assert!(!state.update(&[None, None]));
+  }
+}
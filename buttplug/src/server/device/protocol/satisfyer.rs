@@ -9,15 +9,20 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, Endpoint},
+    message::{self, ActuatorType, Endpoint},
   },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
-    protocol::{ProtocolHandler, ProtocolIdentifier, ProtocolInitializer},
+    protocol::{
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+      ProtocolKeepalive,
+    },
     ServerDeviceIdentifier,
   },
-  util::{async_manager, sleep},
 };
 use async_trait::async_trait;
 use std::{
@@ -29,7 +34,12 @@ use std::{
 };
 
 pub mod setup {
-  use crate::server::device::protocol::{ProtocolIdentifier, ProtocolIdentifierFactory};
+  use crate::server::device::protocol::{
+    ProtocolCapabilities,
+    ProtocolHandler,
+    ProtocolIdentifier,
+    ProtocolIdentifierFactory,
+  };
   #[derive(Default)]
   pub struct SatisfyerIdentifierFactory {}
 
@@ -41,6 +51,10 @@ pub mod setup {
     fn create(&self) -> Box<dyn ProtocolIdentifier> {
       Box::new(super::SatisfyerIdentifier::default())
     }
+
+    fn capabilities(&self) -> ProtocolCapabilities {
+      <super::Satisfyer as ProtocolHandler>::capabilities()
+    }
   }
 }
 
@@ -94,7 +108,7 @@ impl ProtocolInitializer for SatisfyerInitializer {
     if let Some(attrs) = attributes.message_attributes.scalar_cmd() {
       feature_count = attrs.len();
     }
-    Ok(Arc::new(Satisfyer::new(hardware, feature_count)))
+    Ok(Arc::new(Satisfyer::new(feature_count)))
   }
 }
 
@@ -103,6 +117,8 @@ pub struct Satisfyer {
   last_command: Arc<Vec<AtomicU8>>,
 }
 
+// Each motor gets 4 bytes in the Tx packet, in feature order, all set to the motor speed. The
+// packet always covers every motor, so motors that aren't being changed are sent their last speed.
 fn form_command(feature_count: usize, data: Arc<Vec<AtomicU8>>) -> Vec<u8> {
   data[0..feature_count]
     .iter()
@@ -113,39 +129,15 @@ fn form_command(feature_count: usize, data: Arc<Vec<AtomicU8>>) -> Vec<u8> {
 
 // Satisfyer toys will drop their connections if they don't get an update within ~10 seconds.
 // Therefore we try to send a command every ~1s unless something is sent/updated sooner.
-async fn send_satisfyer_updates(
-  device: Arc<Hardware>,
-  feature_count: usize,
-  data: Arc<Vec<AtomicU8>>,
-) {
-  loop {
-    let command = form_command(feature_count, data.clone());
-    if let Err(e) = device
-      .write_value(&HardwareWriteCmd::new(Endpoint::Tx, command, false))
-      .await
-    {
-      error!(
-        "Got an error from a satisfyer device, exiting control loop: {:?}",
-        e
-      );
-      break;
-    }
-    sleep(Duration::from_secs(1)).await;
-  }
-}
+const SATISFYER_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
 
 impl Satisfyer {
-  fn new(hardware: Arc<Hardware>, feature_count: usize) -> Self {
+  fn new(feature_count: usize) -> Self {
     let last_command = Arc::new(
       (0..feature_count)
         .map(|_| AtomicU8::new(0))
         .collect::<Vec<AtomicU8>>(),
     );
-    let last_command_clone = last_command.clone();
-    async_manager::spawn(async move {
-      send_satisfyer_updates(hardware, feature_count, last_command_clone).await;
-    });
-
     Self {
       feature_count,
       last_command,
@@ -154,8 +146,17 @@ impl Satisfyer {
 }
 
 impl ProtocolHandler for Satisfyer {
-  fn needs_full_command_set(&self) -> bool {
-    true
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    Some(ProtocolKeepalive::always(SATISFYER_KEEPALIVE_INTERVAL))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let data = form_command(self.feature_count, self.last_command.clone());
+    Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, data, false).into()])
   }
 
   fn handle_scalar_cmd(
@@ -168,9 +169,11 @@ impl ProtocolHandler for Satisfyer {
         commands.len() as u32,
       ));
     }
-    for (i, item) in commands.iter().enumerate() {
-      let command_val = item.as_ref().unwrap().1 as u8;
-      self.last_command[i].store(command_val, Ordering::SeqCst);
+    // Motors are addressed independently, so only update the ones with new speeds.
+    for (last_command, command) in self.last_command.iter().zip(commands) {
+      if let Some((_, speed)) = command {
+        last_command.store(*speed as u8, Ordering::SeqCst);
+      }
     }
     let data = form_command(self.feature_count, self.last_command.clone());
 
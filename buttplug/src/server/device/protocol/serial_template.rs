@@ -0,0 +1,187 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Text command protocol built from templates in the device config.
+//!
+//! Lots of DIY devices take simple text commands like "V50\n". Instead of needing a protocol for
+//! each of them, every feature can carry a CommandTemplate in the device config (usually a user
+//! config), which is filled in and written to the device's Tx endpoint:
+//!
+//! - `{value}` is the actuator value, within the feature's StepRange. For LinearCmd, this is the
+//!   position.
+//! - `{duration}` is the LinearCmd move duration in milliseconds.
+//! - `{clockwise}` is 1 or 0 for RotateCmd direction.
+//!
+//! Templates include their own line endings, i.e. `"V{value}\n"`, so devices that want `\r\n` or no
+//! line ending at all work too. Commands from the same message are written together.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{self, ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::{ops::RangeInclusive, sync::Arc};
+
+generic_protocol_initializer_setup!(SerialTemplate, "serial-template");
+
+#[derive(Default)]
+pub struct SerialTemplateInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for SerialTemplateInitializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let messages = &attributes.message_attributes;
+    Ok(Arc::new(SerialTemplate {
+      scalar_templates: command_templates("ScalarCmd", messages.scalar_cmd())?,
+      linear_templates: command_templates("LinearCmd", messages.linear_cmd())?,
+      rotate_templates: command_templates("RotateCmd", messages.rotate_cmd())?,
+    }))
+  }
+}
+
+struct CommandTemplate {
+  template: String,
+  range: RangeInclusive<u32>,
+}
+
+impl CommandTemplate {
+  fn render(&self, value: u32, extra: &[(&str, String)]) -> String {
+    extra.iter().fold(
+      self.template.replace("{value}", &value.to_string()),
+      |command, (name, value)| command.replace(&format!("{{{}}}", name), value),
+    )
+  }
+}
+
+fn command_templates(
+  message_type: &str,
+  attributes: &Option<Vec<ServerGenericDeviceMessageAttributes>>,
+) -> Result<Vec<CommandTemplate>, ButtplugDeviceError> {
+  attributes
+    .iter()
+    .flatten()
+    .enumerate()
+    .map(|(index, attrs)| {
+      let template = attrs.command_template().clone().ok_or_else(|| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "{} feature {} has no CommandTemplate.",
+          message_type, index
+        ))
+      })?;
+      Ok(CommandTemplate {
+        template,
+        range: attrs.step_range().clone(),
+      })
+    })
+    .collect()
+}
+
+fn command_template(
+  templates: &[CommandTemplate],
+  index: usize,
+) -> Result<&CommandTemplate, ButtplugDeviceError> {
+  templates
+    .get(index)
+    .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+      templates.len() as u32,
+      index as u32,
+    ))
+}
+
+fn template_write(commands: Vec<String>) -> Vec<HardwareCommand> {
+  if commands.is_empty() {
+    return vec![];
+  }
+  vec![HardwareWriteCmd::new(Endpoint::Tx, commands.concat().into_bytes(), false).into()]
+}
+
+pub struct SerialTemplate {
+  scalar_templates: Vec<CommandTemplate>,
+  linear_templates: Vec<CommandTemplate>,
+  rotate_templates: Vec<CommandTemplate>,
+}
+
+impl ProtocolHandler for SerialTemplate {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&ProtocolCapabilities::ALL_ACTUATOR_TYPES)
+      .with_rotate()
+      .with_linear()
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut template_commands = vec![];
+    for (index, command) in commands.iter().enumerate() {
+      if let Some((_, value)) = command {
+        template_commands
+          .push(command_template(&self.scalar_templates, index)?.render(*value, &[]));
+      }
+    }
+    Ok(template_write(template_commands))
+  }
+
+  fn handle_rotate_cmd(
+    &self,
+    commands: &[Option<(u32, bool)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut template_commands = vec![];
+    for (index, command) in commands.iter().enumerate() {
+      if let Some((speed, clockwise)) = command {
+        let clockwise = if *clockwise { "1" } else { "0" };
+        template_commands.push(
+          command_template(&self.rotate_templates, index)?
+            .render(*speed, &[("clockwise", clockwise.to_owned())]),
+        );
+      }
+    }
+    Ok(template_write(template_commands))
+  }
+
+  fn handle_linear_cmd(
+    &self,
+    msg: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let commands = msg
+      .vectors()
+      .iter()
+      .map(|v| {
+        let template = command_template(&self.linear_templates, v.index() as usize)?;
+        // Linear positions aren't scaled into the step range before they get here.
+        let start = *template.range.start() as f64;
+        let end = *template.range.end() as f64;
+        let position = (start + (end - start) * v.position().clamp(0f64, 1f64)).round() as u32;
+        Ok(template.render(position, &[("duration", v.duration().to_string())]))
+      })
+      .collect::<Result<Vec<_>, ButtplugDeviceError>>()?;
+    Ok(template_write(commands))
+  }
+}
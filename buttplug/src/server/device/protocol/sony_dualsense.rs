@@ -0,0 +1,242 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+};
+use async_trait::async_trait;
+use std::sync::{
+  atomic::{AtomicU8, Ordering},
+  Arc,
+};
+
+const USB_OUTPUT_REPORT_ID: u8 = 0x02;
+const USB_OUTPUT_REPORT_LEN: usize = 48;
+const USB_INPUT_REPORT_LEN: usize = 64;
+const BT_OUTPUT_REPORT_ID: u8 = 0x31;
+const BT_OUTPUT_REPORT_LEN: usize = 78;
+const BT_INPUT_REPORT_ID: u8 = 0x31;
+// Bluetooth output reports are CRC'd with the HID transaction header (DATA | OUTPUT) prepended.
+const BT_OUTPUT_CRC_SEED: u8 = 0xA2;
+
+// Flags telling the controller which parts of the report to apply.
+const VALID_FLAG0_COMPATIBLE_VIBRATION: u8 = 0x01;
+const VALID_FLAG0_HAPTICS_SELECT: u8 = 0x02;
+const VALID_FLAG0_RIGHT_TRIGGER: u8 = 0x04;
+const VALID_FLAG0_LEFT_TRIGGER: u8 = 0x08;
+// Firmware 2.24 and later wants this instead of VALID_FLAG0_COMPATIBLE_VIBRATION. Older firmware
+// ignores it, so we just send both.
+const VALID_FLAG2_COMPATIBLE_VIBRATION2: u8 = 0x04;
+
+// Offsets into the output report body, which is shared between USB and Bluetooth.
+const MOTOR_RIGHT_OFFSET: usize = 2;
+const MOTOR_LEFT_OFFSET: usize = 3;
+const RIGHT_TRIGGER_OFFSET: usize = 10;
+const LEFT_TRIGGER_OFFSET: usize = 21;
+const VALID_FLAG2_OFFSET: usize = 39;
+const OUTPUT_REPORT_BODY_LEN: usize = 47;
+
+const TRIGGER_EFFECT_OFF: u8 = 0x00;
+// Resistance across the whole pull of the trigger. Parameters are start position, then force.
+const TRIGGER_EFFECT_CONTINUOUS_RESISTANCE: u8 = 0x01;
+
+/// CRC32 (IEEE) as used by Bluetooth DualSense output reports.
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xFFFFFFFFu32;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 {
+        (crc >> 1) ^ 0xEDB88320
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  !crc
+}
+
+fn trigger_effect(force: u8) -> [u8; 11] {
+  let mut effect = [0u8; 11];
+  if force > 0 {
+    effect[0] = TRIGGER_EFFECT_CONTINUOUS_RESISTANCE;
+    effect[2] = force;
+  } else {
+    effect[0] = TRIGGER_EFFECT_OFF;
+  }
+  effect
+}
+
+generic_protocol_initializer_setup!(SonyDualSense, "sony-dualsense");
+
+#[derive(Default)]
+pub struct SonyDualSenseInitializer {}
+
+#[async_trait]
+impl ProtocolInitializer for SonyDualSenseInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    // The controller streams input reports as soon as it's opened, and their format tells us which
+    // transport we're on. USB reports are always full size, Bluetooth starts with short reports
+    // until the first output report switches it to the full (0x31) format.
+    let reading = hardware
+      .read_value(&HardwareReadCmd::new(
+        Endpoint::Rx,
+        BT_OUTPUT_REPORT_LEN as u32,
+        500,
+      ))
+      .await?;
+    let data = reading.data();
+    let is_bluetooth = match data.first() {
+      Some(&BT_INPUT_REPORT_ID) => true,
+      Some(_) => data.len() < USB_INPUT_REPORT_LEN,
+      None => {
+        warn!("No input report from DualSense before timeout, assuming USB connection.");
+        false
+      }
+    };
+    debug!(
+      "DualSense connected over {}",
+      if is_bluetooth { "Bluetooth" } else { "USB" }
+    );
+    Ok(Arc::new(SonyDualSense::new(is_bluetooth)))
+  }
+}
+
+/// Sony DualSense (PS5) controller.
+///
+/// Features are, in order, the left (strong) rumble motor, the right (weak) rumble motor, then
+/// left and right adaptive trigger resistance. Everything lives in a single output report, so all
+/// values are sent every time.
+pub struct SonyDualSense {
+  is_bluetooth: bool,
+  sequence_number: AtomicU8,
+}
+
+impl SonyDualSense {
+  fn new(is_bluetooth: bool) -> Self {
+    Self {
+      is_bluetooth,
+      sequence_number: AtomicU8::new(0),
+    }
+  }
+
+  fn output_report(
+    &self,
+    motor_left: u8,
+    motor_right: u8,
+    trigger_left: u8,
+    trigger_right: u8,
+  ) -> Vec<u8> {
+    let mut body = [0u8; OUTPUT_REPORT_BODY_LEN];
+    body[0] = VALID_FLAG0_COMPATIBLE_VIBRATION
+      | VALID_FLAG0_HAPTICS_SELECT
+      | VALID_FLAG0_RIGHT_TRIGGER
+      | VALID_FLAG0_LEFT_TRIGGER;
+    body[MOTOR_RIGHT_OFFSET] = motor_right;
+    body[MOTOR_LEFT_OFFSET] = motor_left;
+    body[RIGHT_TRIGGER_OFFSET..RIGHT_TRIGGER_OFFSET + 11]
+      .copy_from_slice(&trigger_effect(trigger_right));
+    body[LEFT_TRIGGER_OFFSET..LEFT_TRIGGER_OFFSET + 11]
+      .copy_from_slice(&trigger_effect(trigger_left));
+    body[VALID_FLAG2_OFFSET] = VALID_FLAG2_COMPATIBLE_VIBRATION2;
+
+    if !self.is_bluetooth {
+      let mut report = vec![0u8; USB_OUTPUT_REPORT_LEN];
+      report[0] = USB_OUTPUT_REPORT_ID;
+      report[1..1 + OUTPUT_REPORT_BODY_LEN].copy_from_slice(&body);
+      return report;
+    }
+
+    let mut report = vec![0u8; BT_OUTPUT_REPORT_LEN];
+    report[0] = BT_OUTPUT_REPORT_ID;
+    // Sequence number lives in the high nibble.
+    report[1] = (self.sequence_number.fetch_add(1, Ordering::Relaxed) & 0x0F) << 4;
+    // Tag the controller expects on all Bluetooth output reports.
+    report[2] = 0x10;
+    report[3..3 + OUTPUT_REPORT_BODY_LEN].copy_from_slice(&body);
+    let crc_start = BT_OUTPUT_REPORT_LEN - 4;
+    let mut crc_data = vec![BT_OUTPUT_CRC_SEED];
+    crc_data.extend_from_slice(&report[..crc_start]);
+    report[crc_start..].copy_from_slice(&crc32(&crc_data).to_le_bytes());
+    report
+  }
+}
+
+impl ProtocolHandler for SonyDualSense {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate, ActuatorType::Constrict])
+  }
+
+  fn needs_full_command_set(&self) -> bool {
+    true
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let value = |index: usize| {
+      commands
+        .get(index)
+        .and_then(|command| command.map(|(_, scalar)| scalar as u8))
+        .unwrap_or(0)
+    };
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      self.output_report(value(0), value(1), value(2), value(3)),
+      false,
+    )
+    .into()])
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{crc32, SonyDualSense, BT_OUTPUT_CRC_SEED, BT_OUTPUT_REPORT_LEN};
+
+  #[test]
+  fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+  }
+
+  #[test]
+  fn test_bluetooth_output_report() {
+    let dualsense = SonyDualSense::new(true);
+    let first = dualsense.output_report(0xFF, 0x80, 0x40, 0);
+    let second = dualsense.output_report(0xFF, 0x80, 0x40, 0);
+    assert_eq!(first.len(), BT_OUTPUT_REPORT_LEN);
+    assert_eq!(&first[0..3], &[0x31, 0x00, 0x10]);
+    assert_eq!(second[1], 0x10);
+    // Motors
+    assert_eq!(&first[5..7], &[0x80, 0xFF]);
+    // Right trigger off, left trigger on.
+    assert_eq!(first[13], 0x00);
+    assert_eq!(&first[24..27], &[0x01, 0x00, 0x40]);
+    let mut crc_data = vec![BT_OUTPUT_CRC_SEED];
+    crc_data.extend_from_slice(&first[..74]);
+    assert_eq!(first[74..], crc32(&crc_data).to_le_bytes());
+  }
+}
@@ -9,7 +9,7 @@ use crate::{
   core::{errors::ButtplugDeviceError, message::Endpoint},
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
 };
 
@@ -19,6 +19,10 @@ generic_protocol_setup!(Synchro, "synchro");
 pub struct Synchro {}
 
 impl ProtocolHandler for Synchro {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_rotate()
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -5,47 +5,201 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
+//! TCode v0.3, as spoken by the OSR2, SR6, and other DIY stroker robots.
+//!
+//! Every device feature drives a TCode axis. Linear features map to L0, L1, L2..., rotation
+//! features to R0, R1, R2..., and scalar features to V0, V1..., in the order they're listed in the
+//! device config. Features with a TCode axis name (like "R2") as their FeatureDescriptor drive that
+//! axis instead, so configs can skip or reorder axes.
+//!
+//! Step ranges are in TCode units, 0-9999, so an axis's travel can be limited by narrowing its step
+//! range in the device config. TCode rotation axes (twist, roll, pitch) are positions rather than
+//! motors, so RotateCmd speed moves the axis away from the middle of its range, in the direction
+//! given.
+
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, Endpoint},
+    message::{self, ActuatorType, Endpoint},
   },
   server::device::{
-    hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    configuration::{
+      ProtocolAttributesType,
+      ProtocolDeviceAttributes,
+      ServerGenericDeviceMessageAttributes,
+    },
+    hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+    protocol::{
+      generic_protocol_initializer_setup,
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
   },
 };
+use async_trait::async_trait;
+use std::{ops::RangeInclusive, sync::Arc};
+
+// Largest value a 4 digit TCode magnitude can hold.
+const TCODE_MAX_VALUE: u32 = 9999;
 
-generic_protocol_setup!(TCodeV03, "tcode-v03");
+generic_protocol_initializer_setup!(TCodeV03, "tcode-v03");
 
 #[derive(Default)]
-pub struct TCodeV03 {}
+pub struct TCodeV03Initializer {}
+
+#[async_trait]
+impl ProtocolInitializer for TCodeV03Initializer {
+  async fn initialize(
+    &mut self,
+    _: Arc<Hardware>,
+    attributes: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let messages = &attributes.message_attributes;
+    Ok(Arc::new(TCodeV03 {
+      linear_axes: tcode_axes('L', messages.linear_cmd()),
+      rotate_axes: tcode_axes('R', messages.rotate_cmd()),
+      scalar_axes: tcode_axes('V', messages.scalar_cmd()),
+    }))
+  }
+}
+
+struct TCodeAxis {
+  name: String,
+  range: RangeInclusive<u32>,
+}
+
+impl TCodeAxis {
+  /// Command moving the axis to a position between 0.0 and 1.0 of its range.
+  fn position_command(&self, position: f64) -> String {
+    let start = *self.range.start() as f64;
+    let end = *self.range.end() as f64;
+    let value = (start + (end - start) * position.clamp(0f64, 1f64)).round() as u32;
+    self.value_command(value)
+  }
+
+  /// Command setting the axis to a value that's already in TCode units.
+  fn value_command(&self, value: u32) -> String {
+    format!("{}{:04}", self.name, value.min(TCODE_MAX_VALUE))
+  }
+}
+
+fn is_tcode_axis_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  matches!(chars.next(), Some('L' | 'R' | 'V' | 'A'))
+    && chars.next().is_some_and(|c| c.is_ascii_digit())
+    && chars.next().is_none()
+}
+
+fn tcode_axes(
+  prefix: char,
+  attributes: &Option<Vec<ServerGenericDeviceMessageAttributes>>,
+) -> Vec<TCodeAxis> {
+  attributes
+    .iter()
+    .flatten()
+    .enumerate()
+    .map(|(index, attrs)| TCodeAxis {
+      name: if is_tcode_axis_name(attrs.feature_descriptor()) {
+        attrs.feature_descriptor().clone()
+      } else {
+        format!("{}{}", prefix, index)
+      },
+      range: attrs.step_range().clone(),
+    })
+    .collect()
+}
+
+fn tcode_axis(axes: &[TCodeAxis], index: usize) -> Result<&TCodeAxis, ButtplugDeviceError> {
+  axes
+    .get(index)
+    .ok_or(ButtplugDeviceError::DeviceFeatureIndexError(
+      axes.len() as u32,
+      index as u32,
+    ))
+}
+
+/// Commands on the same line are carried out together, so everything from one message goes out in
+/// a single write.
+fn tcode_write(commands: Vec<String>) -> Vec<HardwareCommand> {
+  if commands.is_empty() {
+    return vec![];
+  }
+  vec![HardwareWriteCmd::new(
+    Endpoint::Tx,
+    format!("{}\n", commands.join(" ")).into_bytes(),
+    false,
+  )
+  .into()]
+}
+
+pub struct TCodeV03 {
+  linear_axes: Vec<TCodeAxis>,
+  rotate_axes: Vec<TCodeAxis>,
+  scalar_axes: Vec<TCodeAxis>,
+}
 
 impl ProtocolHandler for TCodeV03 {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&ProtocolCapabilities::ALL_ACTUATOR_TYPES)
+      .with_rotate()
+      .with_linear()
+  }
+
   fn handle_linear_cmd(
     &self,
     msg: message::LinearCmd,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let mut msg_vec = vec![];
-    for v in msg.vectors() {
-      let position = (v.position() * 99f64) as u32;
+    let commands = msg
+      .vectors()
+      .iter()
+      .map(|v| {
+        let axis = tcode_axis(&self.linear_axes, v.index() as usize)?;
+        Ok(format!(
+          "{}I{}",
+          axis.position_command(v.position()),
+          v.duration()
+        ))
+      })
+      .collect::<Result<Vec<_>, ButtplugDeviceError>>()?;
+    Ok(tcode_write(commands))
+  }
 
-      let command = format!("L{}{:02}I{}\n", v.index(), position, v.duration());
-      msg_vec.push(HardwareWriteCmd::new(Endpoint::Tx, command.as_bytes().to_vec(), false).into());
+  fn handle_rotate_cmd(
+    &self,
+    commands: &[Option<(u32, bool)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let mut tcode_commands = vec![];
+    for (index, command) in commands.iter().enumerate() {
+      let Some((speed, clockwise)) = command else {
+        continue;
+      };
+      let axis = tcode_axis(&self.rotate_axes, index)?;
+      let range = (axis.range.end() - axis.range.start()).max(1) as f64;
+      let offset = speed.saturating_sub(*axis.range.start()) as f64 / range / 2f64;
+      let position = if *clockwise {
+        0.5 + offset
+      } else {
+        0.5 - offset
+      };
+      tcode_commands.push(axis.position_command(position));
     }
-    Ok(msg_vec)
+    Ok(tcode_write(tcode_commands))
   }
 
-  fn handle_scalar_vibrate_cmd(
+  fn handle_scalar_cmd(
     &self,
-    index: u32,
-    scalar: u32,
+    commands: &[Option<(ActuatorType, u32)>],
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    Ok(vec![HardwareWriteCmd::new(
-      Endpoint::Tx,
-      format!("V{}{:02}\n", index, scalar).as_bytes().to_vec(),
-      false,
-    )
-    .into()])
+    let mut tcode_commands = vec![];
+    for (index, command) in commands.iter().enumerate() {
+      if let Some((_, scalar)) = command {
+        tcode_commands.push(tcode_axis(&self.scalar_axes, index)?.value_command(*scalar));
+      }
+    }
+    Ok(tcode_write(tcode_commands))
   }
 }
@@ -19,18 +19,23 @@ use crate::{
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
+      ProtocolKeepalive,
     },
     ServerDeviceIdentifier,
   },
 };
 use async_trait::async_trait;
 use prost::Message;
-use std::sync::{
-  atomic::{AtomicU8, Ordering},
-  Arc,
+use std::{
+  sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+  },
+  time::Duration,
 };
 
 mod protocomm {
@@ -43,6 +48,87 @@ mod handyplug {
 
 generic_protocol_initializer_setup!(TheHandy, "thehandy");
 
+// Version of the handyplug message set we speak. Firmware older than 3.0 doesn't speak handyplug
+// at all (it only had the HTTP API), so there's nothing older to fall back to.
+const HANDYPLUG_MESSAGE_VERSION: u32 = 1;
+
+fn encode_payload(message: handyplug::message::Message) -> Vec<u8> {
+  let payload = handyplug::Payload {
+    messages: vec![handyplug::Message {
+      message: Some(message),
+    }],
+  };
+  let mut buf = vec![];
+  payload.encode(&mut buf).expect("Infallible encode.");
+  buf
+}
+
+fn handy_error(msg: &str) -> ButtplugDeviceError {
+  ButtplugDeviceError::ProtocolSpecificError("thehandy".to_owned(), msg.to_owned())
+}
+
+/// Check that the security session came up in plaintext mode.
+fn check_session_response(data: &[u8]) -> Result<(), ButtplugDeviceError> {
+  let session_resp = protocomm::SessionData::decode(data)
+    .map_err(|_| handy_error("Cannot decode security session response"))?;
+  match session_resp.proto {
+    Some(protocomm::session_data::Proto::Sec0(protocomm::Sec0Payload {
+      payload: Some(protocomm::sec0_payload::Payload::Sr(resp)),
+      ..
+    }))
+      if resp.status == protocomm::Status::Success as i32 =>
+    {
+      Ok(())
+    }
+    _ => Err(handy_error(&format!(
+      "Security session setup failed: {:?}",
+      session_resp
+    ))),
+  }
+}
+
+/// Run the handyplug handshake, which tells us whether the firmware speaks a message set we know,
+/// and how often it needs to be pinged.
+async fn request_server_info(
+  hardware: &Hardware,
+) -> Result<handyplug::ServerInfo, ButtplugDeviceError> {
+  let request = encode_payload(handyplug::message::Message::RequestServerInfo(
+    handyplug::RequestServerInfo {
+      id: 1,
+      client_name: "Buttplug".to_owned(),
+      message_version: HANDYPLUG_MESSAGE_VERSION,
+    },
+  ));
+  hardware
+    .write_value(&HardwareWriteCmd::new(Endpoint::Tx, request, true))
+    .await?;
+  let response = hardware
+    .read_value(&HardwareReadCmd::new(Endpoint::Tx, 100, 500))
+    .await?;
+  let payload = handyplug::Payload::decode(response.data().as_slice())
+    .map_err(|_| handy_error("Cannot decode handshake response, firmware may be too old"))?;
+  match payload.messages.into_iter().next().and_then(|m| m.message) {
+    Some(handyplug::message::Message::ServerInfo(info)) => {
+      if info.message_version < HANDYPLUG_MESSAGE_VERSION {
+        Err(handy_error(&format!(
+          "Firmware speaks handyplug message version {}, version {} or later is needed",
+          info.message_version, HANDYPLUG_MESSAGE_VERSION
+        )))
+      } else {
+        Ok(info)
+      }
+    }
+    Some(handyplug::message::Message::Error(err)) => Err(handy_error(&format!(
+      "Handshake rejected by device: {}",
+      err.error_message
+    ))),
+    msg => Err(handy_error(&format!(
+      "Unexpected handshake response: {:?}",
+      msg
+    ))),
+  }
+}
+
 #[derive(Default)]
 pub struct TheHandyInitializer {}
 
@@ -57,8 +143,7 @@ impl ProtocolInitializer for TheHandyInitializer {
     // firmware easier".
     //
     // This code is mostly my translation of the Handy Python POC. It leaves out a lot of stuff
-    // that doesn't seem needed (ping messages, the whole RequestServerInfo flow, etc...) If they
-    // ever change anything, I quit.
+    // that doesn't seem needed. If they ever change anything, I quit.
     //
     // If you are a sex toy manufacturer reading this code: Please, talk to me before implementing
     // your protocol. Buttplug is not made to be a hardware/firmware protocol, and you will regret
@@ -94,44 +179,74 @@ impl ProtocolInitializer for TheHandyInitializer {
     session_req
       .encode(&mut sec_buf)
       .expect("Infallible encode.");
-    hardware.write_value(&HardwareWriteCmd::new(Endpoint::Firmware, sec_buf, false));
-    let _ = hardware.read_value(&HardwareReadCmd::new(Endpoint::Firmware, 100, 500));
+    hardware
+      .write_value(&HardwareWriteCmd::new(Endpoint::Firmware, sec_buf, false))
+      .await?;
+    let session_resp = hardware
+      .read_value(&HardwareReadCmd::new(Endpoint::Firmware, 100, 500))
+      .await?;
+    check_session_response(session_resp.data())?;
+
+    // Now that we have a session, the handyplug handshake tells us whether the firmware speaks a
+    // message set we know. Firmware that doesn't speak handyplug won't answer at all. The
+    // handshake also hands us the max ping time, which is how long the device will wait to hear
+    // from us before it drops the connection.
+    let server_info = request_server_info(&hardware).await?;
+    info!(
+      "The Handy server {}, message version {}, max ping time {}ms",
+      server_info.server_name, server_info.message_version, server_info.max_ping_time
+    );
 
-    // At this point, the "handyplug" protocol does actually have both RequestServerInfo and Ping
-    // messages that it can use. However, having removed these and still tried to run the system,
-    // it seems fine. I've omitted those for the moment, and will readd the complexity once it
-    // does not seem needless.
-    //
     // We have no device name updates here, so just return a device.
-    Ok(Arc::new(TheHandy::default()))
+    Ok(Arc::new(TheHandy::new(server_info.max_ping_time)))
   }
 }
 
-#[derive(Default)]
+fn ping_command() -> HardwareWriteCmd {
+  HardwareWriteCmd::new(
+    Endpoint::Tx,
+    encode_payload(handyplug::message::Message::Ping(Ping { id: 999 })),
+    true,
+  )
+}
+
 pub struct TheHandy {
   // The generic command manager would normally handle this storage, but the only reason we're
   // retaining tracking information is to build our fucking timing calculation for the fleshlight
-  // command backport. I am so mad right now.
-  previous_position: Arc<AtomicU8>,
+  // command backport. I am so mad right now. Stored in FW12 position units (0-99).
+  previous_position: AtomicU8,
+  // Max time between messages before the device drops the connection, in milliseconds. 0 if the
+  // device doesn't care.
+  max_ping_time: u32,
+}
+
+impl TheHandy {
+  fn new(max_ping_time: u32) -> Self {
+    Self {
+      previous_position: AtomicU8::new(0),
+      max_ping_time,
+    }
+  }
 }
 
 impl ProtocolHandler for TheHandy {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_linear()
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
-    let ping_payload = handyplug::Payload {
-      messages: vec![handyplug::Message {
-        message: Some(handyplug::message::Message::Ping(Ping { id: 999 })),
-      }],
-    };
-    let mut ping_buf = vec![];
-    ping_payload
-      .encode(&mut ping_buf)
-      .expect("Infallible encode.");
+    super::ProtocolKeepaliveStrategy::RepeatPacketStrategy(ping_command())
+  }
 
-    super::ProtocolKeepaliveStrategy::RepeatPacketStrategy(HardwareWriteCmd::new(
-      Endpoint::Tx,
-      ping_buf,
-      true,
-    ))
+  fn keepalive(&self) -> Option<ProtocolKeepalive> {
+    // Ping at half the max ping time, so a ping stuck behind a slow write doesn't cost us the
+    // connection.
+    (self.max_ping_time > 0)
+      .then(|| ProtocolKeepalive::always(Duration::from_millis(self.max_ping_time as u64 / 2)))
+  }
+
+  fn handle_keepalive(&self) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    Ok(vec![ping_command().into()])
   }
 
   fn handle_fleshlight_launch_fw12_cmd(
@@ -142,11 +257,8 @@ impl ProtocolHandler for TheHandy {
     // work backward from fleshlight to my own Linear format that Handy uses.
     //
     // Building this library was a mistake.
-    let goal_position = message.position() as f64 / 100f64;
-    let previous_position = self.previous_position.load(Ordering::SeqCst) as f64 / 100f64;
-    self
-      .previous_position
-      .store(message.position(), Ordering::SeqCst);
+    let goal_position = message.position().min(99) as f64 / 99f64;
+    let previous_position = self.previous_position.load(Ordering::SeqCst) as f64 / 99f64;
     let distance = (goal_position - previous_position).abs();
     let duration =
       fleshlight_launch_helper::calculate_duration(distance, message.speed() as f64 / 99f64) as u32;
@@ -173,6 +285,13 @@ impl ProtocolHandler for TheHandy {
       ));
     }
 
+    // Positions should already be in range, but make sure the device is never sent anything outside
+    // of it.
+    let position = message.vectors()[0].position().clamp(0f64, 1f64);
+    self
+      .previous_position
+      .store((position * 99f64).round() as u8, Ordering::SeqCst);
+
     let linear = handyplug::LinearCmd {
       // You know when message IDs are important? When you have a protocol that handles multiple
       // asynchronous commands. You know what doesn't handle multiple asynchronous commands? The
@@ -199,18 +318,10 @@ impl ProtocolHandler for TheHandy {
       vectors: vec![handyplug::linear_cmd::Vector {
         index: 0,
         duration: message.vectors()[0].duration(),
-        position: message.vectors()[0].position(),
+        position,
       }],
     };
-    let linear_payload = handyplug::Payload {
-      messages: vec![handyplug::Message {
-        message: Some(handyplug::message::Message::LinearCmd(linear)),
-      }],
-    };
-    let mut linear_buf = vec![];
-    linear_payload
-      .encode(&mut linear_buf)
-      .expect("Infallible encode.");
+    let linear_buf = encode_payload(handyplug::message::Message::LinearCmd(linear));
     Ok(vec![
       HardwareWriteCmd::new(Endpoint::Tx, linear_buf, true).into()
     ])
@@ -6,11 +6,14 @@
 // for full license information.
 
 use crate::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, Endpoint},
+  },
   generic_protocol_setup,
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::ProtocolHandler,
+    protocol::{ProtocolCapabilities, ProtocolHandler},
   },
 };
 
@@ -20,6 +23,10 @@ generic_protocol_setup!(TryFun, "tryfun");
 pub struct TryFun {}
 
 impl ProtocolHandler for TryFun {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Rotate, ActuatorType::Oscillate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -15,6 +15,8 @@ use crate::{
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      parsing::data_str,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -51,12 +53,19 @@ fn encrypt(command: String) -> Vec<u8> {
   return res;
 }
 
-fn decrypt(data: Vec<u8>) -> String {
+/// Decrypt a notification from the device.
+pub fn decrypt(data: &[u8]) -> Result<String, ButtplugDeviceError> {
   let dec = Aes128EcbDec::new(&VIBCRAFTER_KEY.into());
-  let res = String::from_utf8(dec.decrypt_padded_vec_mut::<Pkcs7>(&data).unwrap()).unwrap();
+  let decrypted = dec.decrypt_padded_vec_mut::<Pkcs7>(data).map_err(|_| {
+    ButtplugDeviceError::ProtocolSpecificError(
+      "vibcrafter".to_owned(),
+      format!("Cannot decrypt device data: {:?}", data),
+    )
+  })?;
+  let res = data_str("vibcrafter", &decrypted)?.to_owned();
 
   info!("Decoded {} from {:?}", res, data);
-  return res;
+  Ok(res)
 }
 
 #[async_trait]
@@ -88,7 +97,7 @@ impl ProtocolInitializer for VibCrafterInitializer {
     loop {
       let event = event_receiver.recv().await;
       if let Ok(HardwareEvent::Notification(_, _, n)) = event {
-        let decoded = decrypt(n);
+        let decoded = decrypt(&n)?;
         if decoded.eq("OK;") {
           debug!("VibCrafter authenticated!");
           return Ok(Arc::new(VibCrafter::default()));
@@ -137,6 +146,10 @@ impl ProtocolInitializer for VibCrafterInitializer {
 pub struct VibCrafter {}
 
 impl ProtocolHandler for VibCrafter {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
@@ -9,13 +9,15 @@ use crate::server::device::configuration::ProtocolDeviceAttributes;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, Endpoint},
+    message::{self, ActuatorType, Endpoint},
   },
   server::device::{
     configuration::ProtocolAttributesType,
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      rotation_helper::RotationState,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -39,7 +41,7 @@ impl ProtocolInitializer for VorzeSAInitializer {
   async fn initialize(
     &mut self,
     hardware: Arc<Hardware>,
-    _: &ProtocolDeviceAttributes,
+    attributes: &ProtocolDeviceAttributes,
   ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
     let hwname = hardware.name().to_ascii_lowercase();
     let device_type = if hwname.contains("cycsa") {
@@ -60,20 +62,31 @@ impl ProtocolInitializer for VorzeSAInitializer {
         hardware.name()
       )));
     };
-    Ok(Arc::new(VorzeSA::new(device_type)))
+    // VorzeA10CycloneCmd always drives the first motor, even on devices without RotateCmd
+    // attributes.
+    let motor_count = attributes
+      .message_attributes
+      .rotate_cmd()
+      .as_ref()
+      .map_or(1, |attrs| attrs.len().max(1));
+    Ok(Arc::new(VorzeSA::new(device_type, motor_count)))
   }
 }
 
 pub struct VorzeSA {
-  previous_position: Arc<AtomicU8>,
+  previous_position: AtomicU8,
   device_type: VorzeDevice,
+  // Every rotation packet carries the state of all motors, and VorzeA10CycloneCmd skips the
+  // command manager, so we keep track of what each motor is doing here.
+  rotations: RotationState,
 }
 
 impl VorzeSA {
-  pub fn new(device_type: VorzeDevice) -> Self {
+  pub fn new(device_type: VorzeDevice, motor_count: usize) -> Self {
     Self {
-      previous_position: Arc::new(AtomicU8::new(0)),
+      previous_position: AtomicU8::new(0),
       device_type,
+      rotations: RotationState::new(motor_count),
     }
   }
 }
@@ -121,8 +134,11 @@ pub fn get_piston_speed(mut distance: f64, mut duration: f64) -> u8 {
 }
 
 impl ProtocolHandler for VorzeSA {
-  fn needs_full_command_set(&self) -> bool {
-    true
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_rotate()
+      .with_linear()
   }
 
   fn handle_scalar_vibrate_cmd(
@@ -148,62 +164,47 @@ impl ProtocolHandler for VorzeSA {
     &self,
     cmds: &[Option<(u32, bool)>],
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    if cmds.len() == 1 {
-      if let Some((speed, clockwise)) = cmds[0] {
-        let data: u8 = (clockwise as u8) << 7 | (speed as u8);
-        Ok(vec![HardwareWriteCmd::new(
-          Endpoint::Tx,
-          vec![self.device_type as u8, VorzeActions::Rotate as u8, data],
-          true,
-        )
-        .into()])
-      } else {
-        Ok(vec![])
-      }
-    } else {
-      let mut data_left = 0u8;
-      let mut data_right = 0u8;
-      let mut changed = false;
-      if let Some((speed, clockwise)) = cmds[0] {
-        data_left = (clockwise as u8) << 7 | (speed as u8);
-        changed = true;
-      }
-      if let Some((speed, clockwise)) = cmds[1] {
-        data_right = (clockwise as u8) << 7 | (speed as u8);
-        changed = true;
-      }
-      if changed {
-        Ok(vec![HardwareWriteCmd::new(
-          Endpoint::Tx,
-          vec![self.device_type as u8, data_left, data_right],
-          true,
-        )
-        .into()])
-      } else {
-        Ok(vec![])
-      }
+    if !self.rotations.update(cmds) {
+      return Ok(vec![]);
     }
+    let motors: Vec<u8> = self
+      .rotations
+      .motors()
+      .into_iter()
+      .map(|(speed, clockwise)| (clockwise as u8) << 7 | (speed as u8))
+      .collect();
+    // Single motor packets have an action byte, UFO TW packets have both motors instead.
+    let data = if let [motor] = motors[..] {
+      vec![self.device_type as u8, VorzeActions::Rotate as u8, motor]
+    } else {
+      [vec![self.device_type as u8], motors].concat()
+    };
+    Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, data, true).into()])
   }
 
   fn handle_linear_cmd(
     &self,
     msg: message::LinearCmd,
   ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
-    let v = msg.vectors()[0].clone();
+    // The Piston only has one axis.
+    if msg.vectors().len() != 1 {
+      return Err(ButtplugDeviceError::DeviceFeatureCountMismatch(
+        1,
+        msg.vectors().len() as u32,
+      ));
+    }
+    let v = &msg.vectors()[0];
 
-    let previous_position = self.previous_position.load(Ordering::SeqCst);
-    let position = v.position() * 200f64;
-    let distance = (previous_position as f64 - position).abs();
+    // Piston positions run from 0 to 200.
+    let position = (v.position().clamp(0f64, 1f64) * 200f64).round() as u8;
+    let previous_position = self.previous_position.swap(position, Ordering::SeqCst);
+    let distance = (previous_position as f64 - position as f64).abs();
 
     let speed = get_piston_speed(distance, v.duration() as f64);
 
-    self
-      .previous_position
-      .store(position as u8, Ordering::SeqCst);
-
     Ok(vec![HardwareWriteCmd::new(
       Endpoint::Tx,
-      vec![self.device_type as u8, position as u8, speed as u8],
+      vec![self.device_type as u8, position, speed],
       true,
     )
     .into()])
@@ -16,6 +16,7 @@ use crate::{
     hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
     protocol::{
       generic_protocol_initializer_setup,
+      ProtocolCapabilities,
       ProtocolHandler,
       ProtocolIdentifier,
       ProtocolInitializer,
@@ -24,7 +25,7 @@ use crate::{
   },
 };
 use async_trait::async_trait;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 generic_protocol_initializer_setup!(WeVibe, "wevibe");
 
@@ -61,10 +62,19 @@ impl ProtocolInitializer for WeVibeInitializer {
 pub struct WeVibe {}
 
 impl ProtocolHandler for WeVibe {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
 
+  fn scalar_command_rate_limit(&self) -> Option<Duration> {
+    // Every update is a write with response, which takes at least a connection interval.
+    Some(Duration::from_millis(100))
+  }
+
   fn needs_full_command_set(&self) -> bool {
     true
   }
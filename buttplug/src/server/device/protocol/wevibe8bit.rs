@@ -12,9 +12,10 @@ use crate::{
   },
   server::device::{
     hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
 };
+use std::time::Duration;
 
 generic_protocol_setup!(WeVibe8Bit, "wevibe-8bit");
 
@@ -22,10 +23,19 @@ generic_protocol_setup!(WeVibe8Bit, "wevibe-8bit");
 pub struct WeVibe8Bit {}
 
 impl ProtocolHandler for WeVibe8Bit {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default().with_scalar(&[ActuatorType::Vibrate])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
 
+  fn scalar_command_rate_limit(&self) -> Option<Duration> {
+    // Every update is a write with response, which takes at least a connection interval.
+    Some(Duration::from_millis(100))
+  }
+
   fn needs_full_command_set(&self) -> bool {
     true
   }
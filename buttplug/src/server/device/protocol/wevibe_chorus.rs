@@ -8,28 +8,100 @@
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{ActuatorType, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorReading,
+      SensorType,
+    },
   },
   server::device::{
-    hardware::{HardwareCommand, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    hardware::{
+      Hardware,
+      HardwareCommand,
+      HardwareEvent,
+      HardwareSubscribeCmd,
+      HardwareUnsubscribeCmd,
+      HardwareWriteCmd,
+    },
+    protocol::{generic_protocol_setup, ProtocolCapabilities, ProtocolHandler},
   },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
 };
+use futures::{
+  future::{self, BoxFuture},
+  select,
+  FutureExt,
+  StreamExt,
+};
+use std::{
+  pin::Pin,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
 
 generic_protocol_setup!(WeVibeChorus, "wevibe-chorus");
 
-#[derive(Default)]
-pub struct WeVibeChorus {}
+/// Squeeze pressure from a Chorus notification, or None if the notification is something else.
+///
+/// The Chorus remote reports squeezes on rx as `0x0c 0x01 <pressure>`, with pressure going from 0
+/// (not squeezed) to 255.
+pub fn parse_squeeze(data: &[u8]) -> Option<i32> {
+  match data {
+    [0x0c, 0x01, pressure, ..] => Some(*pressure as i32),
+    _ => None,
+  }
+}
+
+pub struct WeVibeChorus {
+  /// Stops the squeeze listener, set while a client is subscribed to squeezes.
+  squeeze_listener: Arc<Mutex<Option<CancellationToken>>>,
+  event_stream: broadcast::Sender<ButtplugServerDeviceMessage>,
+}
+
+impl Default for WeVibeChorus {
+  fn default() -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      squeeze_listener: Arc::new(Mutex::new(None)),
+      event_stream: sender,
+    }
+  }
+}
 
 impl ProtocolHandler for WeVibeChorus {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_sensor_subscribe(&[SensorType::Pressure])
+  }
+
   fn keepalive_strategy(&self) -> super::ProtocolKeepaliveStrategy {
     super::ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
   }
 
+  fn scalar_command_rate_limit(&self) -> Option<Duration> {
+    // Every update is a write with response, which takes at least a connection interval.
+    Some(Duration::from_millis(100))
+  }
+
   fn needs_full_command_set(&self) -> bool {
     true
   }
 
+  fn event_stream(
+    &self,
+  ) -> Pin<Box<dyn futures::Stream<Item = ButtplugServerDeviceMessage> + Send>> {
+    convert_broadcast_receiver_to_stream(self.event_stream.subscribe()).boxed()
+  }
+
   fn handle_scalar_cmd(
     &self,
     cmds: &[Option<(ActuatorType, u32)>],
@@ -59,4 +131,158 @@ impl ProtocolHandler for WeVibeChorus {
     };
     Ok(vec![HardwareWriteCmd::new(Endpoint::Tx, data, true).into()])
   }
+
+  fn handle_sensor_subscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorSubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    let token = {
+      let mut listener = self
+        .squeeze_listener
+        .lock()
+        .expect("Lock is never held across a panic");
+      if listener.is_some() {
+        return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+      }
+      listener.insert(CancellationToken::new()).clone()
+    };
+    let squeeze_listener = self.squeeze_listener.clone();
+    let sender = self.event_stream.clone();
+    async move {
+      // Listen before subscribing, so squeezes sent right away aren't missed.
+      let mut hardware_stream = device.event_stream();
+      if let Err(err) = device
+        .subscribe(&HardwareSubscribeCmd::new(Endpoint::Rx))
+        .await
+      {
+        *squeeze_listener
+          .lock()
+          .expect("Lock is never held across a panic") = None;
+        return Err(err);
+      }
+      let device_index = message.device_index();
+      let sensor_index = *message.sensor_index();
+      async_manager::spawn(async move {
+        loop {
+          select! {
+            _ = token.cancelled().fuse() => return,
+            event = hardware_stream.recv().fuse() => {
+              let Ok(event) = event else {
+                return;
+              };
+              let HardwareEvent::Notification(_, Endpoint::Rx, data) = event else {
+                continue;
+              };
+              let Some(pressure) = parse_squeeze(&data) else {
+                continue;
+              };
+              let reading = SensorReading::new(
+                device_index,
+                sensor_index,
+                SensorType::Pressure,
+                vec![pressure],
+              );
+              if sender.send(reading.into()).is_err() {
+                debug!("Hardware device listener for WeVibe Chorus shut down, returning.");
+                return;
+              }
+            }
+          }
+        }
+      });
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+
+  fn handle_sensor_unsubscribe_cmd(
+    &self,
+    device: Arc<Hardware>,
+    message: message::SensorUnsubscribeCmd,
+  ) -> BoxFuture<Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    let Some(token) = self
+      .squeeze_listener
+      .lock()
+      .expect("Lock is never held across a panic")
+      .take()
+    else {
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    };
+    token.cancel();
+    async move {
+      device
+        .unsubscribe(&HardwareUnsubscribeCmd::new(Endpoint::Rx))
+        .await?;
+      Ok(message::Ok::new(message.id()).into())
+    }
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{parse_squeeze, setup::WeVibeChorusIdentifierFactory};
+  use crate::{
+    core::message::{self, ButtplugServerDeviceMessage, Endpoint, SensorType},
+    server::device::hardware::scripted::{setup_protocol, HardwareScript},
+  };
+  use futures::StreamExt;
+
+  #[test]
+  fn test_parse_squeeze() {
+    assert_eq!(parse_squeeze(&[0x0c, 0x01, 0x80]), Some(128));
+    assert_eq!(parse_squeeze(&[0x0c, 0x02, 0x80]), None);
+    assert_eq!(parse_squeeze(&[0x0c, 0x01]), None);
+  }
+
+  #[tokio::test]
+  async fn test_squeeze_readings() {
+    let mut script = HardwareScript::default();
+    script
+      .expect_subscribe(Endpoint::Rx)
+      .reply(Endpoint::Rx, &[0x0c, 0x01, 0x40])
+      .reply(Endpoint::Rx, &[0x0b, 0x00])
+      .reply(Endpoint::Rx, &[0x0c, 0x01, 0xff])
+      .expect_unsubscribe(Endpoint::Rx);
+    let hardware = script.build_hardware("Chorus", "chorus-test");
+    let (_, handler) = setup_protocol(&WeVibeChorusIdentifierFactory::default(), hardware.clone())
+      .await
+      .expect("Test, assuming infallible.");
+    let mut events = handler.event_stream();
+    handler
+      .handle_sensor_subscribe_cmd(
+        hardware.clone(),
+        message::SensorSubscribeCmd::new(0, 0, SensorType::Pressure),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    // Subscribing again doesn't subscribe to the hardware twice.
+    handler
+      .handle_sensor_subscribe_cmd(
+        hardware.clone(),
+        message::SensorSubscribeCmd::new(0, 0, SensorType::Pressure),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    let mut pressures = vec![];
+    while pressures.len() < 2 {
+      match events.next().await {
+        Some(ButtplugServerDeviceMessage::SensorReading(reading)) => {
+          assert_eq!(reading.sensor_type(), SensorType::Pressure);
+          pressures.push(reading.data()[0]);
+        }
+        event => panic!("Unexpected event {:?}", event),
+      }
+    }
+    assert_eq!(pressures, vec![0x40, 0xff]);
+    handler
+      .handle_sensor_unsubscribe_cmd(
+        hardware,
+        message::SensorUnsubscribeCmd::new(0, 0, SensorType::Pressure),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    script.finish();
+  }
 }
@@ -10,11 +10,18 @@ use byteorder::LittleEndian;
 use crate::{
   core::{
     errors::ButtplugDeviceError,
-    message::{self, ActuatorType, ButtplugDeviceMessage, ButtplugServerMessage, Endpoint},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorType,
+    },
   },
   server::device::{
     hardware::{Hardware, HardwareCommand, HardwareReadCmd, HardwareWriteCmd},
-    protocol::{generic_protocol_setup, ProtocolHandler},
+    protocol::{generic_protocol_setup, parsing::byte_at, ProtocolCapabilities, ProtocolHandler},
   },
 };
 use byteorder::WriteBytesExt;
@@ -23,10 +30,30 @@ use std::sync::Arc;
 
 generic_protocol_setup!(XInput, "xinput");
 
+/// Battery level from an XInput battery reading, which only reports empty, low, medium or full.
+pub fn parse_battery(data: &[u8]) -> Result<i32, ButtplugDeviceError> {
+  match byte_at("xinput", data, 0)? {
+    0 => Ok(0),
+    1 => Ok(33),
+    2 => Ok(66),
+    3 => Ok(100),
+    level => Err(ButtplugDeviceError::DeviceCommunicationError(format!(
+      "Unknown XInput battery level {}",
+      level
+    ))),
+  }
+}
+
 #[derive(Default)]
 pub struct XInput {}
 
 impl ProtocolHandler for XInput {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_sensor_read(&[SensorType::Battery])
+  }
+
   fn needs_full_command_set(&self) -> bool {
     true
   }
@@ -72,17 +99,7 @@ impl ProtocolHandler for XInput {
       let reading = device
         .read_value(&HardwareReadCmd::new(Endpoint::Rx, 0, 0))
         .await?;
-      let battery = match reading.data()[0] {
-        0 => 0i32,
-        1 => 33,
-        2 => 66,
-        3 => 100,
-        _ => {
-          return Err(ButtplugDeviceError::DeviceCommunicationError(
-            "something went wrong".to_string(),
-          ))
-        }
-      };
+      let battery = parse_battery(reading.data())?;
       Ok(
         message::SensorReading::new(
           msg.device_index(),
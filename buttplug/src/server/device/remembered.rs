@@ -0,0 +1,294 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Devices the server has connected to before
+//!
+//! When a [RememberedDeviceStore] is handed to the device manager, every device that connects is
+//! written to a JSON file, keyed by address. When a remembered device shows up in a later scan, it
+//! is connected using the protocol it was identified with last time, instead of trying every
+//! protocol that matches its advertisement. That makes reconnecting faster and avoids picking a
+//! different protocol for devices that match more than one.
+//!
+//! Remembered devices can also carry configuration overrides (display name, rate limits, scalar
+//! adjustments, etc...), in the same format as device entries in user device configuration files.
+//! Overrides are applied on top of the device configuration, and take effect for devices connected
+//! after they're set.
+//!
+//! Bluetooth LE devices also have the GATT services and characteristics found on their last
+//! connection remembered, so reconnects can find their endpoints without service discovery.
+
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::{configuration::DeviceConfigurationManagerBuilder, ServerDeviceIdentifier},
+  util::device_configuration::{
+    user_device_config_attributes,
+    UserConfigDeviceIdentifier,
+    UserDeviceConfig,
+  },
+};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// GATT characteristics found on a Bluetooth LE device, keyed by the UUID of the service they
+/// belong to. Each characteristic UUID maps to its property flags, as defined by the Bluetooth
+/// spec.
+pub type GattServiceCache = BTreeMap<Uuid, BTreeMap<Uuid, u8>>;
+
+/// A device the server has connected to before.
+#[derive(Serialize, Deserialize, Debug, Clone, Getters)]
+#[getset(get = "pub")]
+pub struct RememberedDevice {
+  /// Address, protocol and configuration identifier the device was connected with.
+  identifier: UserConfigDeviceIdentifier,
+  /// Device name from the device configuration.
+  name: String,
+  /// Transport the device was connected over (btle, serial, hid, etc...)
+  transport: String,
+  /// User configuration overrides, if any were set.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  config: Option<UserDeviceConfig>,
+  /// GATT services found on the last connection, for Bluetooth LE devices.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  gatt_services: Option<GattServiceCache>,
+}
+
+impl RememberedDevice {
+  pub fn new(identifier: &ServerDeviceIdentifier, name: &str, transport: &str) -> Self {
+    Self {
+      identifier: identifier.clone().into(),
+      name: name.to_owned(),
+      transport: transport.to_owned(),
+      config: None,
+      gatt_services: None,
+    }
+  }
+
+  pub fn address(&self) -> &str {
+    &self.identifier.address
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RememberedDeviceFile {
+  devices: Vec<RememberedDevice>,
+}
+
+/// Remembered devices, backed by a JSON file. Clones share the same devices and file.
+#[derive(Clone)]
+pub struct RememberedDeviceStore {
+  path: PathBuf,
+  devices: Arc<Mutex<Vec<RememberedDevice>>>,
+  /// GATT services of devices that haven't been remembered yet. Hardware is connected before the
+  /// device is remembered, so these are held until it is.
+  pending_gatt_services: Arc<Mutex<HashMap<String, GattServiceCache>>>,
+}
+
+impl RememberedDeviceStore {
+  /// Load remembered devices from the file at the path. If there's no file yet, the store starts
+  /// out empty, and the file is created once the first device is remembered.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ButtplugDeviceError> {
+    let path = path.as_ref().to_path_buf();
+    let devices = match fs::read_to_string(&path) {
+      Ok(contents) => {
+        serde_json::from_str::<RememberedDeviceFile>(&contents)
+          .map_err(|err| {
+            ButtplugDeviceError::DeviceConfigurationError(format!(
+              "Cannot parse remembered device file {:?}: {}",
+              path, err
+            ))
+          })?
+          .devices
+      }
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => vec![],
+      Err(err) => {
+        return Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Cannot read remembered device file {:?}: {}",
+          path, err
+        )))
+      }
+    };
+    Ok(Self {
+      path,
+      devices: Arc::new(Mutex::new(devices)),
+      pending_gatt_services: Arc::new(Mutex::new(HashMap::new())),
+    })
+  }
+
+  /// All remembered devices, in the order they were first connected.
+  pub fn devices(&self) -> Vec<RememberedDevice> {
+    self
+      .devices
+      .lock()
+      .expect("Lock is never held across a panic")
+      .clone()
+  }
+
+  pub fn device(&self, address: &str) -> Option<RememberedDevice> {
+    self
+      .devices
+      .lock()
+      .expect("Lock is never held across a panic")
+      .iter()
+      .find(|device| device.address() == address)
+      .cloned()
+  }
+
+  /// Remember a device, replacing whatever was known about it except its configuration overrides
+  /// and GATT services.
+  pub fn remember(&self, mut device: RememberedDevice) -> Result<(), ButtplugDeviceError> {
+    if let Some(gatt_services) = self
+      .pending_gatt_services
+      .lock()
+      .expect("Lock is never held across a panic")
+      .remove(device.address())
+    {
+      device.gatt_services = Some(gatt_services);
+    }
+    self.update(|devices| {
+      if let Some(existing) = devices
+        .iter_mut()
+        .find(|existing| existing.address() == device.address())
+      {
+        device.config = existing.config.take();
+        if device.gatt_services.is_none() {
+          device.gatt_services = existing.gatt_services.take();
+        }
+        *existing = device;
+      } else {
+        devices.push(device);
+      }
+      true
+    })?;
+    Ok(())
+  }
+
+  /// Remove a device. Returns false if the device wasn't remembered.
+  pub fn forget(&self, address: &str) -> Result<bool, ButtplugDeviceError> {
+    self.update(|devices| {
+      let len = devices.len();
+      devices.retain(|device| device.address() != address);
+      devices.len() != len
+    })
+  }
+
+  /// Set or clear configuration overrides for a device. Returns false if the device wasn't
+  /// remembered.
+  pub fn set_config(
+    &self,
+    address: &str,
+    config: Option<UserDeviceConfig>,
+  ) -> Result<bool, ButtplugDeviceError> {
+    self.update(|devices| {
+      if let Some(device) = devices
+        .iter_mut()
+        .find(|device| device.address() == address)
+      {
+        device.config = config;
+        true
+      } else {
+        false
+      }
+    })
+  }
+
+  /// GATT services found the last time the device at the address connected, if any.
+  pub fn gatt_services(&self, address: &str) -> Option<GattServiceCache> {
+    self
+      .device(address)
+      .and_then(|device| device.gatt_services)
+      .or_else(|| {
+        self
+          .pending_gatt_services
+          .lock()
+          .expect("Lock is never held across a panic")
+          .get(address)
+          .cloned()
+      })
+  }
+
+  /// Set the GATT services found on a device, or clear them if they turned out to be stale. Devices
+  /// that aren't remembered yet get them once they are.
+  pub fn set_gatt_services(
+    &self,
+    address: &str,
+    gatt_services: Option<GattServiceCache>,
+  ) -> Result<(), ButtplugDeviceError> {
+    {
+      let mut pending = self
+        .pending_gatt_services
+        .lock()
+        .expect("Lock is never held across a panic");
+      pending.remove(address);
+      if self.device(address).is_none() {
+        if let Some(gatt_services) = gatt_services {
+          pending.insert(address.to_owned(), gatt_services);
+        }
+        return Ok(());
+      }
+    }
+    self.update(|devices| {
+      if let Some(device) = devices
+        .iter_mut()
+        .find(|device| device.address() == address)
+      {
+        let changed = device.gatt_services != gatt_services;
+        device.gatt_services = gatt_services;
+        changed
+      } else {
+        false
+      }
+    })?;
+    Ok(())
+  }
+
+  /// Add configuration overrides of all remembered devices to a device configuration.
+  pub(crate) fn apply_configs(&self, builder: &mut DeviceConfigurationManagerBuilder) {
+    for device in self.devices() {
+      if let Some(config) = device.config {
+        let identifier: ServerDeviceIdentifier = device.identifier.into();
+        let attributes = user_device_config_attributes(&identifier, &config);
+        builder.protocol_attributes((&identifier).into(), attributes);
+      }
+    }
+  }
+
+  /// Run a change on the devices, and write them out if it changed anything.
+  fn update(
+    &self,
+    change: impl FnOnce(&mut Vec<RememberedDevice>) -> bool,
+  ) -> Result<bool, ButtplugDeviceError> {
+    let mut devices = self
+      .devices
+      .lock()
+      .expect("Lock is never held across a panic");
+    if !change(&mut devices) {
+      return Ok(false);
+    }
+    let contents = serde_json::to_string_pretty(&RememberedDeviceFile {
+      devices: devices.clone(),
+    })
+    .expect("All remembered device types are serializable");
+    // Write to a temporary file first, so a crash mid-write doesn't lose every remembered device.
+    let temp_path = self.path.with_extension("tmp");
+    fs::write(&temp_path, contents)
+      .and_then(|_| fs::rename(&temp_path, &self.path))
+      .map_err(|err| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Cannot write remembered device file {:?}: {}",
+          self.path, err
+        ))
+      })?;
+    Ok(true)
+  }
+}
@@ -0,0 +1,392 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server enforced safety limits for device output
+//!
+//! Limits are set by whoever runs the server, and apply to every command sent to a device no matter
+//! where it came from (any client, patterns, device groups, bridges, etc). There are four limits:
+//!
+//! - Max scalar: Scalar and rotation values are clamped so they never go over this, in the 0.0-1.0
+//!   range the client sent them in. For linear commands it caps the position, the same as it does
+//!   for position actuators in scalar commands, so strokers can't go past that point of their
+//!   range. The Fleshlight Launch and Vorze A10 Cyclone messages have it applied to their 0-99
+//!   position and speed ranges.
+//! - Max run time: Once a device has been producing output continuously for this long, the server
+//!   stops the device. Scalar and rotation actuators count while they're set to anything but 0,
+//!   and linear devices count while they're moving.
+//! - Cooldown: After an auto-stop due to max run time, commands that would turn the device back on
+//!   are rejected until this much time has passed. Every linear command counts as turning the
+//!   device on. Stop commands are always allowed.
+//! - Min ramp time: Scalar changes are spread over at least this much time, instead of jumping
+//!   straight to the new value. Stop commands still take effect immediately.
+//!
+//! Limits can be set globally and overridden per device, by device address, using a
+//! [SafetyPolicy]. Per device limits replace only the global limits they set.
+//!
+//! Protocols for hardware that can hurt someone if misused (like e-stim units) can also require
+//! limits of their own. These are combined with the policy limits, with the stricter of the two
+//! winning, so a policy can tighten them but never loosen them.
+//!
+//! Protocols can also have conservative default limits, like a low max scalar. These are only
+//! loosened by limits set for that specific device, so turning up global limits never raises them.
+
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::ButtplugServerResultFuture,
+  util::{self, async_manager},
+};
+use futures::{future::BoxFuture, FutureExt};
+use getset::{CopyGetters, Setters};
+use instant::Instant;
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+    Mutex,
+    Weak,
+  },
+  time::Duration,
+};
+use tokio::sync::watch;
+
+/// Output limits for a device.
+#[derive(Debug, Default, Clone, Copy, PartialEq, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct SafetyLimits {
+  /// Highest scalar, rotation or linear position value, from 0.0 to 1.0, that will be sent to the
+  /// device.
+  max_scalar: Option<f64>,
+  /// Longest time the device can run continuously before it's stopped.
+  max_run_time: Option<Duration>,
+  /// Time after an auto-stop before the device can be turned on again.
+  cooldown: Option<Duration>,
+  /// Shortest time a scalar actuator can take to move between values.
+  min_ramp_time: Option<Duration>,
+}
+
+impl SafetyLimits {
+  /// Returns true if no limits are set.
+  pub fn is_empty(&self) -> bool {
+    self.max_scalar.is_none()
+      && self.max_run_time.is_none()
+      && self.cooldown.is_none()
+      && self.min_ramp_time.is_none()
+  }
+
+  /// Fill in anything not set here from other.
+  fn or(self, other: SafetyLimits) -> Self {
+    Self {
+      max_scalar: self.max_scalar.or(other.max_scalar),
+      max_run_time: self.max_run_time.or(other.max_run_time),
+      cooldown: self.cooldown.or(other.cooldown),
+      min_ramp_time: self.min_ramp_time.or(other.min_ramp_time),
+    }
+  }
+
+  /// Combine with other, keeping whichever limit is stricter wherever both are set.
+  pub(super) fn strictest(self, other: SafetyLimits) -> Self {
+    fn pick<T>(a: Option<T>, b: Option<T>, stricter: fn(&T, &T) -> bool) -> Option<T> {
+      match (a, b) {
+        (Some(a), Some(b)) => Some(if stricter(&b, &a) { b } else { a }),
+        (a, b) => a.or(b),
+      }
+    }
+    Self {
+      max_scalar: pick(self.max_scalar, other.max_scalar, |a, b| a < b),
+      max_run_time: pick(self.max_run_time, other.max_run_time, |a, b| a < b),
+      cooldown: pick(self.cooldown, other.cooldown, |a, b| a > b),
+      min_ramp_time: pick(self.min_ramp_time, other.min_ramp_time, |a, b| a > b),
+    }
+  }
+}
+
+/// Safety limits for all devices connected to the server.
+#[derive(Debug, Default, Clone)]
+pub struct SafetyPolicy {
+  global: SafetyLimits,
+  devices: HashMap<String, SafetyLimits>,
+}
+
+impl SafetyPolicy {
+  /// Limits for every device without its own limits.
+  pub fn global(&mut self, limits: SafetyLimits) -> &mut Self {
+    self.global = limits;
+    self
+  }
+
+  /// Limits for the device with the given address. Limits not set here fall back to the global
+  /// limits.
+  pub fn device(&mut self, address: &str, limits: SafetyLimits) -> &mut Self {
+    self.devices.insert(address.to_owned(), limits);
+    self
+  }
+
+  /// Limits that apply to the device with the given address.
+  pub fn limits_for(&self, address: &str) -> SafetyLimits {
+    self
+      .devices
+      .get(address)
+      .map_or(self.global, |limits| limits.or(self.global))
+  }
+
+  /// Limits that apply to the device with the given address, for a protocol with the given default
+  /// limits. Defaults are replaced by any limit set for the device, and otherwise combined with the
+  /// global limits, keeping the stricter of each.
+  pub fn limits_with_protocol_defaults(
+    &self,
+    address: &str,
+    protocol_defaults: SafetyLimits,
+  ) -> SafetyLimits {
+    let fallback = self.global.strictest(protocol_defaults);
+    self
+      .devices
+      .get(address)
+      .map_or(fallback, |limits| limits.or(fallback))
+  }
+}
+
+/// Scripts send the next linear move as the last one finishes, so moves count as running for this
+/// long after they should have finished. Otherwise back to back moves wouldn't count as continuous.
+const MOTION_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Kinds of output that count toward the max run time. A device is on while any of them is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum OutputSource {
+  /// Scalar and rotation actuators, as tracked by the generic command manager.
+  Actuators,
+  /// Vorze A10 Cyclone rotation, which goes straight to the protocol.
+  Vorze,
+  /// Linear and Fleshlight Launch moves.
+  Motion,
+}
+
+struct OutputState {
+  sources: Mutex<HashSet<OutputSource>>,
+  /// Bumped on every move, so only the timer for the latest move ends the motion.
+  motion_generation: AtomicU64,
+  /// True while any source is on. Dropping this ends the run time watchdog.
+  active: watch::Sender<bool>,
+}
+
+impl OutputState {
+  fn set(&self, source: OutputSource, active: bool) {
+    let mut sources = self
+      .sources
+      .lock()
+      .expect("Safety lock should never be poisoned");
+    if active {
+      sources.insert(source);
+    } else {
+      sources.remove(&source);
+    }
+    let any_active = !sources.is_empty();
+    self.active.send_if_modified(|current| {
+      let changed = *current != any_active;
+      *current = any_active;
+      changed
+    });
+  }
+}
+
+/// Safety state of a connected device.
+pub(super) struct DeviceSafety {
+  limits: SafetyLimits,
+  cooldown_until: Arc<Mutex<Option<Instant>>>,
+  output: Arc<OutputState>,
+}
+
+impl DeviceSafety {
+  /// Returns None if there are no limits to enforce.
+  pub(super) fn new(limits: SafetyLimits) -> Option<Self> {
+    if limits.is_empty() {
+      return None;
+    }
+    Some(Self {
+      limits,
+      cooldown_until: Arc::new(Mutex::new(None)),
+      output: Arc::new(OutputState {
+        sources: Mutex::new(HashSet::new()),
+        motion_generation: AtomicU64::new(0),
+        active: watch::channel(false).0,
+      }),
+    })
+  }
+
+  pub(super) fn min_ramp_time(&self) -> Option<Duration> {
+    self.limits.min_ramp_time
+  }
+
+  pub(super) fn clamp(&self, value: f64) -> f64 {
+    self.limits.max_scalar.map_or(value, |max| value.min(max))
+  }
+
+  /// Errors if the device is cooling down after an auto-stop.
+  pub(super) fn check_cooldown(&self) -> Result<(), ButtplugDeviceError> {
+    let cooldown_until = self
+      .cooldown_until
+      .lock()
+      .expect("Safety lock should never be poisoned");
+    match *cooldown_until {
+      Some(until) if until > Instant::now() => Err(ButtplugDeviceError::DeviceSafetyCooldown(
+        (until - Instant::now()).as_millis() as u64,
+      )),
+      _ => Ok(()),
+    }
+  }
+
+  /// Clamp a value in the 0-99 range used by the Fleshlight Launch and Vorze A10 Cyclone messages.
+  pub(super) fn clamp_percent(&self, value: u32) -> u32 {
+    self
+      .limits
+      .max_scalar
+      .map_or(value, |max| value.min((max * 99f64).floor() as u32))
+  }
+
+  pub(super) fn set_output_active(&self, source: OutputSource, active: bool) {
+    self.output.set(source, active);
+  }
+
+  /// Mark the device as moving for the given time, plus a grace period, or until the next move.
+  pub(super) fn set_motion_active(&self, duration: Duration) {
+    let generation = self.output.motion_generation.fetch_add(1, Ordering::SeqCst) + 1;
+    self.output.set(OutputSource::Motion, true);
+    let output = Arc::downgrade(&self.output);
+    async_manager::spawn(async move {
+      util::sleep(duration + MOTION_GRACE_PERIOD).await;
+      if let Some(output) = Weak::upgrade(&output) {
+        if output.motion_generation.load(Ordering::SeqCst) == generation {
+          output.set(OutputSource::Motion, false);
+        }
+      }
+    });
+  }
+
+  /// Everything but the actuators, which the generic command manager keeps track of, is off once
+  /// the device has been stopped.
+  pub(super) fn stopped(&self) {
+    self.output.motion_generation.fetch_add(1, Ordering::SeqCst);
+    self.output.set(OutputSource::Vorze, false);
+    self.output.set(OutputSource::Motion, false);
+  }
+
+  /// Future that stops the device, using the given function, whenever its output has been on for
+  /// longer than the max run time. Returns None if there's no max run time. Ends once the device
+  /// is dropped.
+  pub(super) fn run_time_watchdog<F>(&self, stop: F) -> Option<BoxFuture<'static, ()>>
+  where
+    F: Fn() -> Option<ButtplugServerResultFuture> + Send + 'static,
+  {
+    let max_run_time = self.limits.max_run_time?;
+    let cooldown = self.limits.cooldown;
+    let cooldown_until = self.cooldown_until.clone();
+    let mut output_active = self.output.active.subscribe();
+    Some(
+      async move {
+        loop {
+          // Wait for the device to turn on.
+          if output_active.wait_for(|active| *active).await.is_err() {
+            return;
+          }
+          // Then for it to either turn off, or run too long.
+          select! {
+            result = output_active.wait_for(|active| !*active).fuse() => {
+              if result.is_err() {
+                return;
+              }
+              continue;
+            }
+            _ = util::sleep(max_run_time).fuse() => {}
+          }
+          warn!(
+            "Device output on for longer than {:?}, stopping device.",
+            max_run_time
+          );
+          if let Some(cooldown) = cooldown {
+            *cooldown_until
+              .lock()
+              .expect("Safety lock should never be poisoned") = Some(Instant::now() + cooldown);
+          }
+          let Some(stop_fut) = stop() else {
+            return;
+          };
+          if let Err(err) = stop_fut.await {
+            error!("Could not stop device after max run time: {:?}", err);
+          }
+        }
+      }
+      .boxed(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_safety_policy_limits_for() {
+    let mut global = SafetyLimits::default();
+    global
+      .set_max_scalar(Some(0.5))
+      .set_max_run_time(Some(Duration::from_secs(60)));
+    let mut device = SafetyLimits::default();
+    device.set_max_scalar(Some(0.25));
+    let mut policy = SafetyPolicy::default();
+    policy.global(global).device("device", device);
+    assert_eq!(policy.limits_for("other"), global);
+    let limits = policy.limits_for("device");
+    assert_eq!(limits.max_scalar(), Some(0.25));
+    assert_eq!(limits.max_run_time(), Some(Duration::from_secs(60)));
+    assert_eq!(limits.cooldown(), None);
+    assert!(DeviceSafety::new(SafetyLimits::default()).is_none());
+  }
+
+  #[test]
+  fn test_safety_policy_protocol_defaults() {
+    let mut protocol_defaults = SafetyLimits::default();
+    protocol_defaults.set_max_scalar(Some(0.2));
+    let mut global = SafetyLimits::default();
+    global
+      .set_max_scalar(Some(0.8))
+      .set_max_run_time(Some(Duration::from_secs(60)));
+    let mut device = SafetyLimits::default();
+    device.set_max_scalar(Some(0.6));
+    let mut policy = SafetyPolicy::default();
+    policy.global(global).device("device", device);
+    // Raising the global limit doesn't raise the protocol default, only a device limit does.
+    let limits = policy.limits_with_protocol_defaults("other", protocol_defaults);
+    assert_eq!(limits.max_scalar(), Some(0.2));
+    assert_eq!(limits.max_run_time(), Some(Duration::from_secs(60)));
+    let limits = policy.limits_with_protocol_defaults("device", protocol_defaults);
+    assert_eq!(limits.max_scalar(), Some(0.6));
+    assert_eq!(limits.max_run_time(), Some(Duration::from_secs(60)));
+    // Stricter global limits still win over the defaults.
+    global.set_max_scalar(Some(0.1));
+    policy.global(global);
+    let limits = policy.limits_with_protocol_defaults("other", protocol_defaults);
+    assert_eq!(limits.max_scalar(), Some(0.1));
+  }
+
+  #[test]
+  fn test_safety_limits_strictest() {
+    let mut policy = SafetyLimits::default();
+    policy
+      .set_max_scalar(Some(0.5))
+      .set_min_ramp_time(Some(Duration::from_millis(100)));
+    let mut protocol = SafetyLimits::default();
+    protocol
+      .set_max_scalar(Some(0.75))
+      .set_cooldown(Some(Duration::from_secs(10)))
+      .set_min_ramp_time(Some(Duration::from_secs(1)));
+    let limits = policy.strictest(protocol);
+    assert_eq!(limits.max_scalar(), Some(0.5));
+    assert_eq!(limits.max_run_time(), None);
+    assert_eq!(limits.cooldown(), Some(Duration::from_secs(10)));
+    assert_eq!(limits.min_ramp_time(), Some(Duration::from_secs(1)));
+  }
+}
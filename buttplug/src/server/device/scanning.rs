@@ -0,0 +1,134 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per communication manager scanning status
+//!
+//! StartScanning starts every hardware communication manager at once, and clients only get a
+//! single ScanningFinished once all of them are done. That makes it impossible to tell when one of
+//! them couldn't scan at all, like Bluetooth on a machine without an adapter. The device manager
+//! tracks the scanning state of each communication manager separately, so frontends can show
+//! something like "Bluetooth scanning failed, serial scanning active" instead.
+
+use crate::core::{
+  errors::ButtplugError,
+  message::{ManagerScanningState, ManagerScanningStatus},
+};
+use getset::{CopyGetters, Getters};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Scanning state of a single hardware communication manager.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanState {
+  /// Not scanning, either because no scan was requested, or the last scan finished or was stopped.
+  Idle,
+  Scanning,
+  /// The manager reported it can't scan when the scan was started, e.g. because there's no
+  /// Bluetooth adapter.
+  Unavailable,
+  /// The manager returned an error when starting the scan.
+  Failed(ButtplugError),
+}
+
+/// Scanning state of a hardware communication manager, identified by its name.
+#[derive(Debug, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct CommunicationManagerScanStatus {
+  #[getset(get_copy = "pub")]
+  manager_name: &'static str,
+  #[getset(get = "pub")]
+  state: ScanState,
+}
+
+impl CommunicationManagerScanStatus {
+  pub fn new(manager_name: &'static str, state: ScanState) -> Self {
+    Self {
+      manager_name,
+      state,
+    }
+  }
+}
+
+impl From<&CommunicationManagerScanStatus> for ManagerScanningStatus {
+  fn from(status: &CommunicationManagerScanStatus) -> Self {
+    let (state, error_message) = match status.state() {
+      ScanState::Idle => (ManagerScanningState::Idle, None),
+      ScanState::Scanning => (ManagerScanningState::Scanning, None),
+      ScanState::Unavailable => (ManagerScanningState::Unavailable, None),
+      ScanState::Failed(err) => (ManagerScanningState::Failed, Some(err.to_string())),
+    };
+    ManagerScanningStatus::new(status.manager_name(), state, error_message)
+  }
+}
+
+/// Shared between the device manager, which answers status queries, and its event loop, which
+/// updates the status as scans start and stop.
+#[derive(Clone)]
+pub(super) struct ScanStatusTracker {
+  statuses: Arc<Mutex<Vec<CommunicationManagerScanStatus>>>,
+  sender: broadcast::Sender<CommunicationManagerScanStatus>,
+}
+
+impl ScanStatusTracker {
+  pub fn new(manager_names: impl Iterator<Item = &'static str>) -> Self {
+    let (sender, _) = broadcast::channel(256);
+    Self {
+      statuses: Arc::new(Mutex::new(
+        manager_names
+          .map(|name| CommunicationManagerScanStatus::new(name, ScanState::Idle))
+          .collect(),
+      )),
+      sender,
+    }
+  }
+
+  pub fn statuses(&self) -> Vec<CommunicationManagerScanStatus> {
+    self
+      .statuses
+      .lock()
+      .expect("Lock is never held across a panic")
+      .clone()
+  }
+
+  pub fn state(&self, manager_name: &str) -> Option<ScanState> {
+    self
+      .statuses
+      .lock()
+      .expect("Lock is never held across a panic")
+      .iter()
+      .find(|status| status.manager_name == manager_name)
+      .map(|status| status.state.clone())
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<CommunicationManagerScanStatus> {
+    self.sender.subscribe()
+  }
+
+  /// Update the state of a manager, sending out an update if it changed.
+  pub fn set_state(&self, manager_name: &'static str, state: ScanState) {
+    let mut statuses = self
+      .statuses
+      .lock()
+      .expect("Lock is never held across a panic");
+    let Some(status) = statuses
+      .iter_mut()
+      .find(|status| status.manager_name == manager_name)
+    else {
+      return;
+    };
+    if status.state == state {
+      return;
+    }
+    match &state {
+      ScanState::Failed(err) => warn!("{} could not start scanning: {}", manager_name, err),
+      ScanState::Unavailable => warn!("{} is not able to scan.", manager_name),
+      _ => debug!("{} scanning state is now {:?}", manager_name, state),
+    }
+    status.state = state;
+    // Ignore send errors, they only mean nobody is listening right now.
+    let _ = self.sender.send(status.clone());
+  }
+}
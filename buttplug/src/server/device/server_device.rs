@@ -6,8 +6,11 @@
 // for full license information.
 
 use std::{
+  any::Any,
+  collections::HashMap,
   fmt::{self, Debug},
-  sync::Arc,
+  panic::{self, AssertUnwindSafe},
+  sync::{Arc, Mutex, Weak},
   time::Duration,
 };
 
@@ -24,23 +27,40 @@ use crate::{
       ButtplugMessage,
       ButtplugServerDeviceMessage,
       ButtplugServerMessage,
+      DeviceConnectionInfo,
+      DeviceRemovedReason,
       Endpoint,
+      FleshlightLaunchFW12Cmd,
+      LinearCmd,
       RSSILevelReading,
       RawReading,
       RawSubscribeCmd,
+      RotateCmd,
+      RotationSubcommand,
       ScalarCmd,
       ScalarSubcommand,
       SensorDeviceMessageAttributes,
       SensorReadCmd,
+      SensorReading,
       SensorType,
+      VectorSubcommand,
+      VibrateCmd,
+      VibrateSubcommand,
+      VorzeA10CycloneCmd,
     },
     ButtplugResultFuture,
   },
   server::{
     device::{
       configuration::{DeviceConfigurationManager, ProtocolAttributesType},
-      hardware::{Hardware, HardwareCommand, HardwareConnector, HardwareEvent},
-      protocol::ProtocolHandler,
+      hardware::{
+        recording::{HardwareTrafficRecorder, HardwareTrafficRecording},
+        Hardware,
+        HardwareCommand,
+        HardwareConnector,
+        HardwareEvent,
+      },
+      protocol::{fleshlight_launch_helper, ProtocolHandler},
     },
     ButtplugServerResultFuture,
   },
@@ -48,27 +68,52 @@ use crate::{
 };
 use core::hash::{Hash, Hasher};
 use dashmap::DashSet;
-use futures::future::{self, FutureExt};
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  select,
+};
 use getset::{Getters, MutGetters, Setters};
+use instant::Instant;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing_futures::Instrument;
 
 use super::{
-  configuration::{ProtocolDeviceAttributes, ServerDeviceMessageAttributes},
+  battery::{BatteryEvent, BatteryPollingPolicy, DeviceBatteryMonitor},
+  command_coalescer::{CoalescerAction, ScalarCommandCoalescer, ScalarCommandSet},
+  command_queue::DeviceCommandQueue,
+  configuration::{
+    ProtocolCommunicationSpecifier,
+    ProtocolDeviceAttributes,
+    ScalarActuatorAdjustment,
+    ServerDeviceMessageAttributes,
+    ServerGenericDeviceMessageAttributes,
+  },
   hardware::HardwareWriteCmd,
+  idle::DeviceIdleMonitor,
+  latency::{DeviceLatencyStats, DeviceLatencyTracker},
   protocol::{
     generic_command_manager::GenericCommandManager,
+    ProtocolIdentifier,
+    ProtocolInitializationTimeouts,
+    ProtocolInitializer,
+    ProtocolKeepalive,
     ProtocolKeepaliveStrategy,
     ProtocolSpecializer,
   },
+  safety::{DeviceSafety, OutputSource, SafetyPolicy},
 };
 
 #[derive(Debug)]
 pub enum ServerDeviceEvent {
   Connected(Arc<ServerDevice>),
   Notification(ServerDeviceIdentifier, ButtplugServerDeviceMessage),
-  Disconnected(ServerDeviceIdentifier),
+  /// Polled battery level, in percent, dropped below the low battery threshold.
+  BatteryLow(ServerDeviceIdentifier, u8),
+  /// Device went away, with why, and whether its comm manager will try to connect it again.
+  Disconnected(ServerDeviceIdentifier, DeviceRemovedReason, bool),
 }
 
 /// Identifying information for a connected devices
@@ -100,8 +145,12 @@ impl ServerDeviceIdentifier {
 
 pub(super) async fn build_server_device(
   device_config_manager: Arc<DeviceConfigurationManager>,
-  mut hardware_connector: Box<dyn HardwareConnector>,
+  hardware_connector: &mut dyn HardwareConnector,
   protocol_specializers: Vec<ProtocolSpecializer>,
+  traffic_recorder: Option<HardwareTrafficRecorder>,
+  safety_policy: Arc<SafetyPolicy>,
+  battery_polling: Option<Arc<BatteryPollingPolicy>>,
+  initialization_timeouts: Arc<ProtocolInitializationTimeouts>,
 ) -> Result<ServerDevice, ButtplugDeviceError> {
   // We've already checked to make sure we have specializers in the server device manager event
   // loop. That check used to be here for sake of continuity in building devices in this method, but
@@ -116,13 +165,23 @@ pub(super) async fn build_server_device(
   let mut protocol_identifier = None;
   let mut hardware_out = None;
   for protocol_specializer in protocol_specializers {
-    if let Ok(specialized_hardware) = hardware_specializer
+    match hardware_specializer
       .specialize(protocol_specializer.specifiers())
       .await
     {
-      protocol_identifier = Some(protocol_specializer.identify());
-      hardware_out = Some(specialized_hardware);
-      break;
+      Ok(specialized_hardware) => {
+        protocol_identifier = Some((
+          protocol_specializer.protocol().to_owned(),
+          protocol_specializer.specifiers().clone(),
+          protocol_specializer.identify(),
+        ));
+        hardware_out = Some(specialized_hardware);
+        break;
+      }
+      // The hardware itself failed (e.g. a bluetooth connection that wouldn't come up), so trying
+      // other protocols won't help, and we don't want to hide the failure behind "no protocols".
+      Err(err @ ButtplugDeviceError::DeviceSpecificError(_)) => return Err(err),
+      Err(_) => continue,
     }
   }
 
@@ -132,20 +191,153 @@ pub(super) async fn build_server_device(
     ));
   }
 
-  let mut protocol_identifier_stage = protocol_identifier.unwrap();
-  let hardware = Arc::new(hardware_out.unwrap());
+  let (protocol, specifiers, protocol_identifier_stage) = protocol_identifier.unwrap();
+  let mut hardware = hardware_out.unwrap();
+  let mut named_endpoints = HashMap::new();
+  for specifier in specifiers {
+    if let ProtocolCommunicationSpecifier::BluetoothLE(btle) = specifier {
+      named_endpoints.extend(btle.named_endpoints().clone());
+    }
+  }
+  hardware.set_named_endpoints(&named_endpoints);
+  if let Some(recorder) = traffic_recorder {
+    hardware.set_traffic_recorder(recorder);
+  }
+  // Keep the handshake traffic around, so there's something to look at if the handshake fails.
+  let handshake_transcript = HardwareTrafficRecorder::in_memory();
+  hardware.set_traffic_recorder(handshake_transcript.clone());
+  let hardware = Arc::new(hardware);
 
-  let (identifier, mut protocol_initializer) =
-    protocol_identifier_stage.identify(hardware.clone()).await?;
+  // Dropping the handshake future on timeout cancels whatever the protocol was waiting on.
+  let initialization_timeout = initialization_timeouts.timeout_for(&protocol);
+  let handshake_result = select! {
+    result = identify_and_initialize(
+      device_config_manager.as_ref(),
+      protocol_identifier_stage,
+      hardware.clone(),
+    ).fuse() => result,
+    _ = util::sleep(initialization_timeout).fuse() => {
+      Err(ButtplugDeviceError::HandshakeTimeout {
+        address: hardware.address().to_owned(),
+        protocol: protocol.clone(),
+      })
+    }
+  };
+  hardware.detach_traffic_recorder(&handshake_transcript);
+  let transcript = handshake_transcript.take_recording();
+  let (identifier, handler, mut attrs) = match handshake_result {
+    Ok(handshake) => handshake,
+    Err(err) => {
+      log_handshake_transcript(hardware.address(), &err, &transcript);
+      if matches!(err, ButtplugDeviceError::HandshakeTimeout { .. }) {
+        // The device never finished its handshake, so there's no telling what state it's in. Let
+        // go of it, instead of leaving it connected to nothing.
+        if let Err(disconnect_err) = hardware.disconnect().await {
+          debug!(
+            "Could not disconnect timed out device: {:?}",
+            disconnect_err
+          );
+        }
+      }
+      return Err(err);
+    }
+  };
+
+  if let Some(params) = handler
+    .connection_parameters()
+    .filter(|params| !params.is_empty())
+  {
+    if let Err(err) = hardware.request_connection_parameters(&params).await {
+      info!(
+        "Could not set connection parameters {:?} for {}: {:?}",
+        params,
+        hardware.name(),
+        err
+      );
+    }
+  }
+
+  let requires_keepalive = hardware.requires_keepalive();
+  let strategy = handler.keepalive_strategy();
+  let transport = hardware_connector.specifier().transport();
+
+  // We now have fully initialized hardware, return a server device.
+  let safety = DeviceSafety::new(
+    safety_policy
+      .limits_with_protocol_defaults(hardware.address(), handler.default_safety_limits())
+      .strictest(handler.safety_limits()),
+  );
+  let battery_monitor = battery_polling.and_then(|policy| {
+    attrs
+      .add_polled_battery_sensor()
+      .map(|index| DeviceBatteryMonitor::new(&policy, hardware.address(), index))
+  });
+  let device = ServerDevice::new(
+    identifier,
+    handler,
+    hardware,
+    transport,
+    &attrs,
+    safety,
+    battery_monitor,
+  );
+
+  // If we need a keepalive with a packet replay, set this up via stopping the device on connect.
+  if requires_keepalive
+    && matches!(
+      strategy,
+      ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
+    )
+  {
+    if let Err(e) = device.handle_stop_device_cmd().await {
+      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
+        "Error setting up keepalive: {}",
+        e
+      )));
+    }
+  }
+
+  Ok(device)
+}
+
+/// Protocol specific errors during identification or initialization mean the device didn't respond
+/// to the protocol handshake the way we expected.
+/// Identify the device, look up its attributes, and initialize its protocol.
+async fn identify_and_initialize(
+  device_config_manager: &DeviceConfigurationManager,
+  mut protocol_identifier: Box<dyn ProtocolIdentifier>,
+  hardware: Arc<Hardware>,
+) -> Result<
+  (
+    ServerDeviceIdentifier,
+    Arc<dyn ProtocolHandler>,
+    ProtocolDeviceAttributes,
+  ),
+  ButtplugDeviceError,
+> {
+  let (identifier, mut protocol_initializer) = protocol_identifier
+    .identify(hardware.clone())
+    .await
+    .map_err(|err| handshake_error_context(err, hardware.address()))?;
+  tracing::Span::current().record("protocol", tracing::field::display(identifier.protocol()));
+  let identifier = resolve_variant(
+    device_config_manager,
+    identifier,
+    protocol_initializer.as_mut(),
+    hardware.clone(),
+  )
+  .await
+  .map_err(|err| handshake_error_context(err, hardware.address()))?;
 
   // Now we have an identifier. After this point, if anything fails, consider it a complete
   // connection failure, as identify may have already run commands on the device, and therefore
   // put it in an unknown state if anything fails.
 
   // Check in the DeviceConfigurationManager to make sure we have attributes
-  // for this device.
-  let attrs = if let Some(attrs) =
-    device_config_manager.protocol_device_attributes(&identifier, &hardware.endpoints())
+  // for this device, falling back to whatever the device told us about itself.
+  let attrs = if let Some(attrs) = device_config_manager
+    .protocol_device_attributes(&identifier, &hardware.endpoints())
+    .or_else(|| protocol_initializer.device_attributes())
   {
     attrs
   } else {
@@ -157,46 +349,417 @@ pub(super) async fn build_server_device(
 
   // If we have attributes, go ahead and initialize, handing us back our hardware instance that
   // is now ready to use with the protocol handler.
-
-  // Build the server device and return.
-
   let handler = protocol_initializer
     .initialize(hardware.clone(), &attrs)
-    .await?;
+    .await
+    .map_err(|err| {
+      device_error_context(
+        handshake_error_context(err, hardware.address()),
+        hardware.address(),
+        identifier.protocol(),
+      )
+    })?;
+  Ok((identifier, handler, attrs))
+}
 
-  let requires_keepalive = hardware.requires_keepalive();
-  let strategy = handler.keepalive_strategy();
+/// Switch to the identifier of the variant the initializer finds, if the device configuration has
+/// attributes for it.
+pub(crate) async fn resolve_variant(
+  device_config_manager: &DeviceConfigurationManager,
+  identifier: ServerDeviceIdentifier,
+  protocol_initializer: &mut dyn ProtocolInitializer,
+  hardware: Arc<Hardware>,
+) -> Result<ServerDeviceIdentifier, ButtplugDeviceError> {
+  let Some(variant) = protocol_initializer.variant(hardware).await? else {
+    return Ok(identifier);
+  };
+  if let Some(variant_identifier) = device_config_manager.variant_identifier(&identifier, &variant)
+  {
+    info!("Device {:?} identified as variant {}", identifier, variant);
+    return Ok(variant_identifier);
+  }
+  debug!(
+    "No configuration for variant {} of {:?}, using model attributes.",
+    variant, identifier
+  );
+  Ok(identifier)
+}
 
-  // We now have fully initialized hardware, return a server device.
-  let device = ServerDevice::new(identifier, handler, hardware, &attrs);
+/// Log the traffic from a failed handshake, for debugging the protocol.
+fn log_handshake_transcript(
+  address: &str,
+  err: &ButtplugDeviceError,
+  transcript: &HardwareTrafficRecording,
+) {
+  if transcript.records().is_empty() {
+    warn!("Handshake with {} failed with no traffic: {}", address, err);
+    return;
+  }
+  warn!(
+    "Handshake with {} failed: {}. Handshake traffic:",
+    address, err
+  );
+  for record in transcript.records() {
+    warn!("  +{}ms {:?}", record.timestamp_ms(), record.event());
+  }
+}
 
-  // If we need a keepalive with a packet replay, set this up via stopping the device on connect.
-  if requires_keepalive
-    && matches!(
-      strategy,
-      ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
-    )
-  {
-    if let Err(e) = device.handle_stop_device_cmd().await {
-      return Err(ButtplugDeviceError::DeviceConnectionError(format!(
-        "Error setting up keepalive: {}",
-        e
-      )));
+fn handshake_error_context(err: ButtplugDeviceError, address: &str) -> ButtplugDeviceError {
+  match err {
+    ButtplugDeviceError::ProtocolSpecificError(protocol, reason) => {
+      ButtplugDeviceError::HandshakeFailed {
+        address: address.to_owned(),
+        protocol,
+        reason,
+      }
     }
+    err => err,
   }
+}
 
-  Ok(device)
+/// Kind of hardware access that produced an error.
+#[derive(Clone, Copy)]
+enum HardwareAccess {
+  Read,
+  Write,
+  Subscribe,
+}
+
+impl HardwareAccess {
+  fn from_command(command: &HardwareCommand) -> (Self, Endpoint) {
+    match command {
+      HardwareCommand::Write(cmd) => (Self::Write, cmd.endpoint()),
+      HardwareCommand::Subscribe(cmd) => (Self::Subscribe, cmd.endpoint()),
+      HardwareCommand::Unsubscribe(cmd) => (Self::Subscribe, cmd.endpoint()),
+    }
+  }
+}
+
+/// The first value of a sensor reading, which is all spec v2 and older messages can carry.
+fn first_sensor_value(reading: &SensorReading) -> Result<i32, ButtplugError> {
+  reading.data().first().copied().ok_or_else(|| {
+    ButtplugDeviceError::DeviceCommunicationError(format!(
+      "{} reading came back without any data",
+      reading.sensor_type()
+    ))
+    .into()
+  })
+}
+
+/// Adds the device address and protocol to errors that don't say what device they're about, turning
+/// disconnections and missing endpoints into structured errors. Anything else is returned as is.
+fn device_error_context(
+  err: ButtplugDeviceError,
+  address: &str,
+  protocol: &str,
+) -> ButtplugDeviceError {
+  match err {
+    ButtplugDeviceError::DeviceNotConnected(_) => ButtplugDeviceError::DeviceDisconnected {
+      address: address.to_owned(),
+      protocol: protocol.to_owned(),
+    },
+    ButtplugDeviceError::InvalidEndpoint(endpoint) => ButtplugDeviceError::EndpointMissing {
+      address: address.to_owned(),
+      protocol: protocol.to_owned(),
+      endpoint,
+    },
+    err => err,
+  }
+}
+
+/// Turns errors from accessing a hardware endpoint into structured errors saying which device,
+/// protocol and endpoint failed.
+fn hardware_error_context(
+  err: ButtplugDeviceError,
+  address: &str,
+  protocol: &str,
+  access: HardwareAccess,
+  endpoint: Endpoint,
+) -> ButtplugDeviceError {
+  let reason = match err {
+    ButtplugDeviceError::DeviceCommunicationError(reason)
+    | ButtplugDeviceError::DeviceConnectionError(reason) => reason,
+    ButtplugDeviceError::DeviceSpecificError(_) => err.to_string(),
+    err => return device_error_context(err, address, protocol),
+  };
+  let (address, protocol) = (address.to_owned(), protocol.to_owned());
+  match access {
+    HardwareAccess::Read => ButtplugDeviceError::ReadFailed {
+      address,
+      protocol,
+      endpoint,
+      reason,
+    },
+    HardwareAccess::Write => ButtplugDeviceError::WriteFailed {
+      address,
+      protocol,
+      endpoint,
+      reason,
+    },
+    HardwareAccess::Subscribe => ButtplugDeviceError::SubscribeFailed {
+      address,
+      protocol,
+      endpoint,
+      reason,
+    },
+  }
+}
+
+/// Split commands into the groups they're sent to the hardware in. When the protocol pipelines
+/// writes, runs of consecutive writes are sent together. Everything else is sent on its own.
+fn group_hardware_commands(
+  commands: Vec<HardwareCommand>,
+  pipeline_writes: bool,
+) -> Vec<Vec<HardwareCommand>> {
+  let mut groups: Vec<Vec<HardwareCommand>> = vec![];
+  for command in commands {
+    match groups.last_mut() {
+      Some(group)
+        if pipeline_writes
+          && matches!(command, HardwareCommand::Write(_))
+          && matches!(group.last(), Some(HardwareCommand::Write(_))) =>
+      {
+        group.push(command)
+      }
+      _ => groups.push(vec![command]),
+    }
+  }
+  groups
+}
+
+async fn write_hardware_commands(
+  hardware: Arc<Hardware>,
+  protocol: Arc<str>,
+  keepalive_type: ProtocolKeepaliveStrategy,
+  pipeline_writes: bool,
+  keepalive_packet: Arc<RwLock<Option<HardwareWriteCmd>>>,
+  commands: Vec<HardwareCommand>,
+) -> Result<ButtplugServerMessage, ButtplugError> {
+  // Run commands in order, otherwise we may end up sending out of order. This may take a while,
+  // but it's what 99% of protocols expect. If they want something else, they can implement it
+  // themselves, or ask for their writes to be pipelined.
+  //
+  // If anything errors out, just bail on the command series. This most likely means the device
+  // disconnected.
+  for mut group in group_hardware_commands(commands, pipeline_writes) {
+    let result = if let [command] = group.as_slice() {
+      hardware.parse_message(command).await
+    } else {
+      let writes: Vec<HardwareWriteCmd> = group
+        .iter()
+        .filter_map(|command| match command {
+          HardwareCommand::Write(write) => Some(write.clone()),
+          _ => None,
+        })
+        .collect();
+      hardware.write_values(&writes).await
+    };
+    result.map_err(|err| {
+      let (access, endpoint) = HardwareAccess::from_command(&group[0]);
+      hardware_error_context(err, hardware.address(), &protocol, access, endpoint)
+    })?;
+    if hardware.requires_keepalive()
+      && matches!(
+        keepalive_type,
+        ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
+      )
+    {
+      if let Some(HardwareCommand::Write(command)) = group.pop() {
+        *keepalive_packet.write().await = Some(command);
+      }
+    }
+  }
+  Ok(message::Ok::default().into())
+}
+
+async fn write_keepalive_commands(
+  hardware: &Hardware,
+  commands: Vec<HardwareCommand>,
+) -> Result<(), ButtplugDeviceError> {
+  for command in commands {
+    hardware.parse_message(&command).await?;
+  }
+  Ok(())
+}
+
+/// Send a protocol's keepalives whenever the hardware has gone the keepalive interval without a
+/// write. Keepalives that run while idle also go out once as soon as the device is set up, so the
+/// device starts out in a known state. Keepalives wait their turn in the command queue like any
+/// other write, so they can't land in the middle of a command or after a stop. Runs until the
+/// device goes away or the hardware stops taking writes.
+async fn run_protocol_keepalive(
+  keepalive: ProtocolKeepalive,
+  hardware: Arc<Hardware>,
+  handler: Arc<dyn ProtocolHandler>,
+  gcm: Weak<GenericCommandManager>,
+  command_queue: Weak<DeviceCommandQueue>,
+) {
+  loop {
+    {
+      let (Some(gcm), Some(command_queue)) = (gcm.upgrade(), command_queue.upgrade()) else {
+        break;
+      };
+      if keepalive.while_idle() || gcm.has_active_output() {
+        let ticket = command_queue.ticket();
+        if let Some(_turn) = command_queue.wait_for_turn(ticket).await {
+          let result = match handler.handle_keepalive() {
+            Ok(commands) => write_keepalive_commands(&hardware, commands).await,
+            Err(err) => Err(err),
+          };
+          if let Err(err) = result {
+            warn!(
+              "Error writing keepalive for {}, stopping keepalives: {:?}",
+              hardware.name(),
+              err
+            );
+            break;
+          }
+        }
+      }
+    }
+    util::sleep(keepalive.interval()).await;
+    // Commands sent since the last keepalive push the next one back.
+    loop {
+      let since_last_write = hardware.time_since_last_write().await;
+      if since_last_write >= keepalive.interval() {
+        break;
+      }
+      util::sleep(keepalive.interval() - since_last_write).await;
+    }
+  }
+  debug!("Leaving protocol keepalive task for {}", hardware.name());
+}
+
+/// Put the device into low power mode once it's been idle for the monitor's timeout. A device
+/// counts as idle when it hasn't been sent a command for the timeout, and nothing is left running.
+/// Like keepalives, going into low power waits its turn in the command queue. Runs until the device
+/// goes away or the hardware stops taking writes.
+async fn run_idle_monitor(
+  monitor: Weak<DeviceIdleMonitor>,
+  hardware: Arc<Hardware>,
+  handler: Arc<dyn ProtocolHandler>,
+  gcm: Weak<GenericCommandManager>,
+  command_queue: Weak<DeviceCommandQueue>,
+) {
+  loop {
+    let wait = {
+      let (Some(monitor), Some(gcm), Some(command_queue)) =
+        (monitor.upgrade(), gcm.upgrade(), command_queue.upgrade())
+      else {
+        break;
+      };
+      match monitor.time_until_idle() {
+        Some(remaining) => remaining,
+        // Already asleep, or still running something. Either way, check back later.
+        None if monitor.is_low_power() || gcm.has_active_output() => monitor.idle_timeout(),
+        None => {
+          let ticket = command_queue.ticket();
+          if let Some(_turn) = command_queue.wait_for_turn(ticket).await {
+            // Commands may have come in while we were waiting for our turn.
+            if monitor.time_until_idle().is_none() && !gcm.has_active_output() {
+              debug!("{} is idle, entering low power mode.", hardware.name());
+              let result = match handler.handle_enter_low_power() {
+                Ok(commands) => write_keepalive_commands(&hardware, commands).await,
+                Err(err) => Err(err),
+              };
+              if let Err(err) = result {
+                warn!(
+                  "Error entering low power mode for {}, stopping idle monitor: {:?}",
+                  hardware.name(),
+                  err
+                );
+                break;
+              }
+              monitor.set_low_power();
+            }
+          }
+          monitor.idle_timeout()
+        }
+      }
+    };
+    util::sleep(wait).await;
+  }
+  debug!("Leaving idle monitor task for {}", hardware.name());
+}
+
+/// Turns a panic caught from a protocol handler into an error, and marks the device as faulted so
+/// it gets removed from the server. Hardware is disconnected, since the state of the protocol can't
+/// be trusted anymore.
+fn protocol_panic(
+  hardware: &Arc<Hardware>,
+  protocol: &str,
+  fault_token: &CancellationToken,
+  payload: Box<dyn Any + Send>,
+) -> ButtplugDeviceError {
+  let reason = payload
+    .downcast_ref::<&str>()
+    .map(|reason| reason.to_string())
+    .or_else(|| payload.downcast_ref::<String>().cloned())
+    .unwrap_or_else(|| "Unknown panic".to_owned());
+  error!(
+    "Protocol {} panicked handling a command for {}, removing device: {}",
+    protocol,
+    hardware.address(),
+    reason
+  );
+  if !fault_token.is_cancelled() {
+    fault_token.cancel();
+    let disconnect = hardware.disconnect();
+    async_manager::spawn(async move {
+      if let Err(err) = disconnect.await {
+        warn!("Error disconnecting device after protocol panic: {:?}", err);
+      }
+    });
+  }
+  ButtplugDeviceError::ProtocolPanicked {
+    address: hardware.address().to_owned(),
+    protocol: protocol.to_owned(),
+    reason,
+  }
 }
 
 pub struct ServerDevice {
   hardware: Arc<Hardware>,
   handler: Arc<dyn ProtocolHandler>,
   attributes: ProtocolDeviceAttributes,
-  generic_command_manager: GenericCommandManager,
+  /// Message attributes merged with the ones inherited from the protocol defaults. Merging takes a
+  /// full copy, so it's done once here instead of on every command.
+  message_attributes: ServerDeviceMessageAttributes,
+  generic_command_manager: Arc<GenericCommandManager>,
   /// Unique identifier for the device
   identifier: ServerDeviceIdentifier,
+  /// Protocol name from the identifier, for error messages. Shared, so commands don't copy it.
+  protocol_name: Arc<str>,
   raw_subscribed_endpoints: Arc<DashSet<Endpoint>>,
   keepalive_packet: Arc<RwLock<Option<HardwareWriteCmd>>>,
+  /// Rate limiter for scalar commands, if the protocol or user config asks for one.
+  scalar_coalescer: Option<ScalarCommandCoalescer>,
+  /// User configured scaling/remapping for scalar actuators, keyed by client facing index.
+  scalar_adjustments: HashMap<u32, ScalarActuatorAdjustment>,
+  /// Cancels the scalar ramp currently being sent to the hardware, if there is one.
+  scalar_ramp_token: Mutex<Option<CancellationToken>>,
+  /// Name of the transport the hardware was connected over.
+  transport: &'static str,
+  /// Timing of output commands, for diagnosing lag.
+  latency: Arc<DeviceLatencyTracker>,
+  /// Orders hardware writes, so stops can't be overtaken by commands received before them.
+  command_queue: Arc<DeviceCommandQueue>,
+  /// Idle tracking for low power mode, if the protocol supports it and it's not turned off.
+  idle_monitor: Option<Arc<DeviceIdleMonitor>>,
+  /// Server configured output limits, if any apply to this device.
+  safety: Option<DeviceSafety>,
+  /// Server side battery polling, if it's on and the device has a battery to read.
+  battery_monitor: Option<DeviceBatteryMonitor>,
+  /// Display name, starting out as the one from the device configuration. Can be changed while the
+  /// device is connected.
+  display_name: std::sync::RwLock<Option<String>>,
+  /// Cancelled if the protocol handler panics, after which the device is removed.
+  fault_token: CancellationToken,
+  /// Reason to report for the next disconnect instead of the one the hardware gives, set when the
+  /// server disconnects the device or the device says it's powering off.
+  removal_reason: Arc<Mutex<Option<DeviceRemovedReason>>>,
+  /// Span for everything done on behalf of the device, keyed by address and protocol.
+  span: tracing::Span,
 }
 impl Debug for ServerDevice {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -228,10 +791,22 @@ impl ServerDevice {
     identifier: ServerDeviceIdentifier,
     handler: Arc<dyn ProtocolHandler>,
     hardware: Arc<Hardware>,
+    transport: &'static str,
     attributes: &ProtocolDeviceAttributes,
+    safety: Option<DeviceSafety>,
+    battery_monitor: Option<DeviceBatteryMonitor>,
   ) -> Self {
+    let span = info_span!(
+      "device",
+      address = tracing::field::display(hardware.address()),
+      protocol = tracing::field::display(identifier.protocol())
+    );
     let keepalive_packet = Arc::new(RwLock::new(None));
-    let gcm = GenericCommandManager::new(attributes);
+    let mut gcm = GenericCommandManager::new(attributes);
+    if let Some(min_ramp_time) = safety.as_ref().and_then(|safety| safety.min_ramp_time()) {
+      gcm.set_min_scalar_ramp_time(min_ramp_time);
+    }
+    let gcm = Arc::new(gcm);
     // If we've gotten here, we know our hardware is connected. This means we can start the keepalive if it's required.
     if hardware.requires_keepalive()
       && !matches!(
@@ -240,51 +815,129 @@ impl ServerDevice {
       )
     {
       let hardware = hardware.clone();
+      let handler = handler.clone();
       let strategy = handler.keepalive_strategy();
       let keepalive_packet = keepalive_packet.clone();
-      async_manager::spawn(async move {
-        // Arbitrary wait time for now.
-        let wait_duration = Duration::from_secs(5);
-        loop {
-          if hardware.time_since_last_write().await > wait_duration {
-            match &strategy {
-              ProtocolKeepaliveStrategy::RepeatPacketStrategy(packet) => {
-                if let Err(e) = hardware.write_value(&packet).await {
-                  warn!("Error writing keepalive packet: {:?}", e);
-                  break;
-                }
-              }
-              ProtocolKeepaliveStrategy::RepeatLastPacketStrategy => {
-                if let Some(packet) = &*keepalive_packet.read().await {
+      async_manager::spawn(
+        async move {
+          // Arbitrary wait time for now.
+          let wait_duration = Duration::from_secs(5);
+          loop {
+            if hardware.time_since_last_write().await > wait_duration {
+              match &strategy {
+                ProtocolKeepaliveStrategy::RepeatPacketStrategy(packet) => {
                   if let Err(e) = hardware.write_value(&packet).await {
                     warn!("Error writing keepalive packet: {:?}", e);
                     break;
                   }
                 }
-              }
-              _ => {
-                info!(
-                  "Protocol keepalive strategy {:?} not implemented, replacing with NoStrategy",
-                  strategy
-                );
+                ProtocolKeepaliveStrategy::RepeatLastPacketStrategy => {
+                  if let Some(packet) = &*keepalive_packet.read().await {
+                    if let Err(e) = hardware.write_value(packet).await {
+                      warn!("Error writing keepalive packet: {:?}", e);
+                      break;
+                    }
+                  }
+                }
+                ProtocolKeepaliveStrategy::CustomStrategy => {
+                  let result = match handler.handle_keepalive() {
+                    Ok(commands) => write_keepalive_commands(&hardware, commands).await,
+                    Err(e) => Err(e),
+                  };
+                  if let Err(e) = result {
+                    warn!("Error writing keepalive packet: {:?}", e);
+                    break;
+                  }
+                }
+                _ => {
+                  info!(
+                    "Protocol keepalive strategy {:?} not implemented, replacing with NoStrategy",
+                    strategy
+                  );
+                }
               }
             }
+            // Arbitrary wait time for now.
+            util::sleep(wait_duration).await;
           }
-          // Arbitrary wait time for now.
-          util::sleep(wait_duration).await;
+          info!("Leaving keepalive task for {}", hardware.name());
         }
-        info!("Leaving keepalive task for {}", hardware.name());
-      });
+        .instrument(span.clone()),
+      );
+    }
+
+    let command_queue = Arc::new(DeviceCommandQueue::default());
+    if let Some(keepalive) = handler.keepalive() {
+      async_manager::spawn(
+        run_protocol_keepalive(
+          keepalive,
+          hardware.clone(),
+          handler.clone(),
+          Arc::downgrade(&gcm),
+          Arc::downgrade(&command_queue),
+        )
+        .instrument(span.clone()),
+      );
+    }
+
+    // Low power mode is opt-in, and only runs for devices with a user configured idle timeout. A
+    // timeout of 0 is the same as none.
+    let idle_monitor = attributes
+      .idle_timeout_ms()
+      .filter(|ms| *ms > 0 && handler.supports_low_power())
+      .map(|ms| Arc::new(DeviceIdleMonitor::new(Duration::from_millis(ms as u64))));
+    if let Some(monitor) = &idle_monitor {
+      async_manager::spawn(
+        run_idle_monitor(
+          Arc::downgrade(monitor),
+          hardware.clone(),
+          handler.clone(),
+          Arc::downgrade(&gcm),
+          Arc::downgrade(&command_queue),
+        )
+        .instrument(span.clone()),
+      );
     }
 
+    // User configured rate limits take precedence over protocol defaults. A rate limit of 0 turns
+    // coalescing off.
+    let scalar_coalescer = attributes
+      .command_rate_limit_ms()
+      .map(|ms| Duration::from_millis(ms as u64))
+      .or_else(|| handler.scalar_command_rate_limit())
+      .filter(|interval| !interval.is_zero())
+      .map(ScalarCommandCoalescer::new);
+
+    let scalar_adjustments = attributes
+      .scalar_adjustments()
+      .unwrap_or_default()
+      .into_iter()
+      .map(|adjustment| (adjustment.index(), adjustment))
+      .collect();
+
     Self {
+      protocol_name: identifier.protocol().as_str().into(),
       identifier,
       generic_command_manager: gcm,
       handler,
       hardware,
       keepalive_packet,
+      scalar_coalescer,
+      scalar_adjustments,
+      scalar_ramp_token: Mutex::new(None),
+      transport,
+      latency: Arc::new(DeviceLatencyTracker::default()),
+      command_queue,
+      idle_monitor,
+      safety,
+      battery_monitor,
+      display_name: std::sync::RwLock::new(attributes.display_name()),
+      fault_token: CancellationToken::new(),
+      removal_reason: Arc::new(Mutex::new(None)),
+      message_attributes: attributes.message_attributes(),
       attributes: attributes.clone(),
       raw_subscribed_endpoints: Arc::new(DashSet::new()),
+      span,
     }
   }
 
@@ -293,9 +946,30 @@ impl ServerDevice {
     &self.identifier
   }
 
+  /// Connection details (transport, address, signal strength) to pass along to clients.
+  pub fn connection_info(&self) -> DeviceConnectionInfo {
+    DeviceConnectionInfo::new(
+      self.transport,
+      self.hardware.address(),
+      self.hardware.rssi(),
+    )
+  }
+
   /// Get the user created display name for a device, if one exists.
   pub fn display_name(&self) -> Option<String> {
-    self.attributes.display_name()
+    self
+      .display_name
+      .read()
+      .expect("Lock is never held across a panic")
+      .clone()
+  }
+
+  /// Change the display name sent to clients. Clients see it the next time they get a device list.
+  pub(crate) fn set_display_name(&self, display_name: Option<String>) {
+    *self
+      .display_name
+      .write()
+      .expect("Lock is never held across a panic") = display_name;
   }
 
   /// Get the name of the device as set in the Device Configuration File.
@@ -323,13 +997,23 @@ impl ServerDevice {
 
   /// Disconnect from the device, if it's connected.
   pub fn disconnect(&self) -> ButtplugResultFuture {
+    self.disconnect_with_reason(DeviceRemovedReason::UserDisconnect)
+  }
+
+  /// Disconnect from the device, reporting the given reason to clients when it's removed.
+  pub(crate) fn disconnect_with_reason(&self, reason: DeviceRemovedReason) -> ButtplugResultFuture {
+    self
+      .removal_reason
+      .lock()
+      .expect("Lock is never held across a panic")
+      .get_or_insert(reason);
     let fut = self.hardware.disconnect();
     async move { fut.await.map_err(|err| err.into()) }.boxed()
   }
 
   /// Retreive the message attributes for the device.
   pub fn message_attributes(&self) -> ServerDeviceMessageAttributes {
-    self.attributes.message_attributes()
+    self.message_attributes.clone()
   }
 
   /// Retreive the event stream for the device.
@@ -339,12 +1023,28 @@ impl ServerDevice {
   pub fn event_stream(&self) -> impl futures::Stream<Item = ServerDeviceEvent> + Send {
     let identifier = self.identifier.clone();
     let raw_endpoints = self.raw_subscribed_endpoints.clone();
+    let handler = self.handler.clone();
+    let removal_reason = self.removal_reason.clone();
     let hardware_stream = convert_broadcast_receiver_to_stream(self.hardware.event_stream())
       .filter_map(move |hardware_event| {
         let id = identifier.clone();
         match hardware_event {
-          HardwareEvent::Disconnected(_) => Some(ServerDeviceEvent::Disconnected(id)),
+          HardwareEvent::Disconnected(_, reason, will_reconnect) => {
+            let reason = removal_reason
+              .lock()
+              .expect("Lock is never held across a panic")
+              .take()
+              .unwrap_or(reason);
+            Some(ServerDeviceEvent::Disconnected(id, reason, will_reconnect))
+          }
           HardwareEvent::Notification(_address, endpoint, data) => {
+            if handler.is_power_off_notification(endpoint, &data) {
+              info!("Device {} is powering off.", id.address());
+              removal_reason
+                .lock()
+                .expect("Lock is never held across a panic")
+                .get_or_insert(DeviceRemovedReason::PoweredOff);
+            }
             // TODO Figure out how we're going to parse raw data into something sendable to the client.
             if raw_endpoints.contains(&endpoint) {
               Some(ServerDeviceEvent::Notification(
@@ -363,7 +1063,32 @@ impl ServerDevice {
       let id = identifier.clone();
       ServerDeviceEvent::Notification(id, incoming_message)
     });
-    hardware_stream.merge(handler_mapped_stream)
+    // A device whose protocol panicked goes away like it was disconnected, whatever the hardware
+    // ends up doing.
+    let identifier = self.identifier.clone();
+    let fault_token = self.fault_token.clone();
+    let fault_stream = futures::stream::once(async move {
+      fault_token.cancelled().await;
+      ServerDeviceEvent::Disconnected(identifier, DeviceRemovedReason::DeviceError, false)
+    });
+    let identifier = self.identifier.clone();
+    let battery_stream = futures::StreamExt::flatten(futures::stream::iter(
+      self
+        .battery_monitor
+        .as_ref()
+        .map(|monitor| convert_broadcast_receiver_to_stream(monitor.event_stream())),
+    ))
+    .map(move |event| match event {
+      BatteryEvent::Reading(reading) => ServerDeviceEvent::Notification(
+        identifier.clone(),
+        ButtplugServerDeviceMessage::SensorReading(reading),
+      ),
+      BatteryEvent::Low(percent) => ServerDeviceEvent::BatteryLow(identifier.clone(), percent),
+    });
+    hardware_stream
+      .merge(handler_mapped_stream)
+      .merge(battery_stream)
+      .merge(fault_stream)
   }
 
   pub fn supports_message(
@@ -442,9 +1167,94 @@ impl ServerDevice {
   // In order to not have to worry about id setting at the protocol level (this
   // should be taken care of in the server's device manager), we return server
   // messages but Buttplug errors.
+  //
+  // Protocol handlers run inside of here, so this is also where their panics are caught. A panic
+  // fails the command and removes the device, instead of taking the rest of the server with it.
   pub fn parse_message(
     &self,
     command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    let _enter = self.span.enter();
+    if self.fault_token.is_cancelled() {
+      return future::ready(Err(
+        ButtplugDeviceError::DeviceDisconnected {
+          address: self.hardware.address().to_owned(),
+          protocol: self.protocol_name.to_string(),
+        }
+        .into(),
+      ))
+      .boxed();
+    }
+    let fut = match panic::catch_unwind(AssertUnwindSafe(|| {
+      self.timed_dispatch_message(command_message)
+    })) {
+      Ok(fut) => fut,
+      Err(payload) => {
+        let err = protocol_panic(
+          &self.hardware,
+          &self.protocol_name,
+          &self.fault_token,
+          payload,
+        );
+        return future::ready(Err(err.into())).boxed();
+      }
+    };
+    let hardware = self.hardware.clone();
+    let protocol_name = self.protocol_name.clone();
+    let fault_token = self.fault_token.clone();
+    async move {
+      match AssertUnwindSafe(fut).catch_unwind().await {
+        Ok(result) => result,
+        Err(payload) => {
+          Err(protocol_panic(&hardware, &protocol_name, &fault_token, payload).into())
+        }
+      }
+    }
+    .instrument(self.span.clone())
+    .boxed()
+  }
+
+  fn timed_dispatch_message(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
+  ) -> ButtplugServerResultFuture {
+    // Only time messages that end in hardware writes, reads and subscriptions wait on the device
+    // and would make the stats meaningless.
+    if !matches!(
+      command_message,
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(_)
+        | ButtplugDeviceCommandMessageUnion::VibrateCmd(_)
+        | ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(_)
+        | ButtplugDeviceCommandMessageUnion::RotateCmd(_)
+        | ButtplugDeviceCommandMessageUnion::LinearCmd(_)
+        | ButtplugDeviceCommandMessageUnion::FleshlightLaunchFW12Cmd(_)
+        | ButtplugDeviceCommandMessageUnion::VorzeA10CycloneCmd(_)
+        | ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_)
+    ) {
+      return self.dispatch_message(command_message);
+    }
+    let received = Instant::now();
+    let fut = self.dispatch_message(command_message);
+    self.latency.record_protocol(received.elapsed());
+    let latency = self.latency.clone();
+    async move {
+      let result = fut.await;
+      if result.is_ok() {
+        latency.record_total(received.elapsed());
+      }
+      result
+    }
+    .boxed()
+  }
+
+  /// Rolling latency stats for output commands sent to this device.
+  pub fn latency_stats(&self) -> DeviceLatencyStats {
+    self.latency.stats()
+  }
+
+  fn dispatch_message(
+    &self,
+    command_message: ButtplugDeviceCommandMessageUnion,
   ) -> ButtplugServerResultFuture {
     if let Err(err) = self.supports_message(&command_message) {
       return future::ready(Err(err)).boxed();
@@ -453,6 +1263,10 @@ impl ServerDevice {
     // If a handler implements handle message, bypass all of our parsing and let it do its own
     // thing. This should be a very rare thing.
     if self.handler.has_handle_message() {
+      let command_message = match self.apply_passthrough_safety(command_message) {
+        Ok(msg) => msg,
+        Err(err) => return future::ready(Err(err.into())).boxed(),
+      };
       let fut = self.handle_generic_command_result(self.handler.handle_message(&command_message));
       return async move { fut.await }.boxed();
     }
@@ -474,72 +1288,30 @@ impl ServerDevice {
       // Message that return lists of hardware commands which we'll handle sending to the devices
       // here, in order to reduce boilerplate in the implementations. Generic messages that we can
       // use the generic command manager for, but still need protocol level translation.
-      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
-        // TODO Add ability to turn off actuator matching
-        let attributes = self.attributes.message_attributes();
-        let attrs = attributes
-          .scalar_cmd()
-          .as_ref()
-          .expect("Already checked existence");
-        for command in msg.scalars() {
-          if command.index() > attrs.len() as u32 {
-            return future::ready(Err(
-              ButtplugDeviceError::DeviceFeatureIndexError(attrs.len() as u32, command.index())
-                .into(),
-            ))
-            .boxed();
-          }
-          if *attrs[command.index() as usize].actuator_type() != command.actuator_type() {
-            return future::ready(Err(
-              ButtplugDeviceError::DeviceActuatorTypeMismatch(
-                self.name(),
-                command.actuator_type(),
-                *attrs[command.index() as usize].actuator_type(),
-              )
-              .into(),
-            ))
-            .boxed();
-          }
-        }
-
-        let commands = match self
-          .generic_command_manager
-          .update_scalar(&msg, self.handler.needs_full_command_set())
-        {
-          Ok(values) => values,
-          Err(err) => return future::ready(Err(err)).boxed(),
-        };
-
-        if commands.is_empty() {
-          trace!(
-            "No commands generated for incoming device packet, skipping and returning success."
-          );
-          return future::ready(Ok(message::Ok::default().into())).boxed();
-        }
-
-        self.handle_generic_command_result(self.handler.handle_scalar_cmd(&commands))
-      }
-      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
-        let commands = match self
-          .generic_command_manager
-          .update_rotation(&msg, self.handler.needs_full_command_set())
-        {
-          Ok(values) => values,
-          Err(err) => return future::ready(Err(err)).boxed(),
-        };
-        self.handle_generic_command_result(self.handler.handle_rotate_cmd(&commands))
-      }
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => self.handle_scalar_cmd(msg, false),
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => self.handle_rotate_cmd(msg, false),
       ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
-        self.parse_message(ScalarCmd::from(msg).into())
-      }
-      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_linear_cmd(msg))
+        self.dispatch_message(ScalarCmd::from(msg).into())
       }
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => match self.apply_linear_safety(msg) {
+        Ok(msg) => self.handle_generic_command_result(self.handler.handle_linear_cmd(msg)),
+        Err(err) => future::ready(Err(err.into())).boxed(),
+      },
       ButtplugDeviceCommandMessageUnion::FleshlightLaunchFW12Cmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_fleshlight_launch_fw12_cmd(msg))
+        match self.apply_fleshlight_safety(msg) {
+          Ok(msg) => {
+            self.handle_generic_command_result(self.handler.handle_fleshlight_launch_fw12_cmd(msg))
+          }
+          Err(err) => future::ready(Err(err.into())).boxed(),
+        }
       }
       ButtplugDeviceCommandMessageUnion::VorzeA10CycloneCmd(msg) => {
-        self.handle_generic_command_result(self.handler.handle_vorze_a10_cyclone_cmd(msg))
+        match self.apply_vorze_safety(msg) {
+          Ok(msg) => {
+            self.handle_generic_command_result(self.handler.handle_vorze_a10_cyclone_cmd(msg))
+          }
+          Err(err) => future::ready(Err(err.into())).boxed(),
+        }
       }
       ButtplugDeviceCommandMessageUnion::SensorReadCmd(msg) => self.handle_sensor_read_cmd(msg),
       ButtplugDeviceCommandMessageUnion::SensorSubscribeCmd(msg) => {
@@ -556,31 +1328,547 @@ impl ServerDevice {
     }
   }
 
-  fn handle_hardware_commands(&self, commands: Vec<HardwareCommand>) -> ButtplugServerResultFuture {
+  /// Stops skip user adjustments, safety limits, rate limiting and ramping. They're always all 0.0
+  /// in hardware actuator order, and nothing should be able to hold one back or turn it into
+  /// output.
+  fn handle_scalar_cmd(&self, msg: ScalarCmd, is_stop: bool) -> ButtplugServerResultFuture {
+    // TODO Add ability to turn off actuator matching
+    let attrs = self
+      .message_attributes
+      .scalar_cmd()
+      .as_ref()
+      .expect("Already checked existence");
+    for command in msg.scalars() {
+      if command.index() > attrs.len() as u32 {
+        return future::ready(Err(
+          ButtplugDeviceError::DeviceFeatureIndexError(attrs.len() as u32, command.index()).into(),
+        ))
+        .boxed();
+      }
+      if *attrs[command.index() as usize].actuator_type() != command.actuator_type() {
+        return future::ready(Err(
+          ButtplugDeviceError::DeviceActuatorTypeMismatch(
+            self.name(),
+            command.actuator_type(),
+            *attrs[command.index() as usize].actuator_type(),
+          )
+          .into(),
+        ))
+        .boxed();
+      }
+    }
+
+    let msg = if is_stop {
+      msg
+    } else {
+      match self.apply_scalar_safety(self.adjust_scalar_cmd(msg, attrs)) {
+        Ok(msg) => msg,
+        Err(err) => return future::ready(Err(err.into())).boxed(),
+      }
+    };
+
+    let commands = match self
+      .generic_command_manager
+      .update_scalar(&msg, self.handler.needs_full_command_set())
+    {
+      Ok(values) => values,
+      Err(err) => return future::ready(Err(err)).boxed(),
+    };
+    self.update_safety_output();
+
+    if commands.is_empty() {
+      trace!("No commands generated for incoming device packet, skipping and returning success.");
+      return future::ready(Ok(message::Ok::default().into())).boxed();
+    }
+
+    if self.generic_command_manager.scalar_ramp_time().is_some() {
+      // Whatever ramp is still in progress is heading for a value we no longer want.
+      self.cancel_scalar_ramp();
+      // Like rate limiting, ramping is skipped for stops.
+      let frames = if is_stop {
+        vec![commands]
+      } else {
+        self
+          .generic_command_manager
+          .scalar_ramp_frames(&commands, self.handler.needs_full_command_set())
+      };
+      return self.send_scalar_ramp(frames);
+    }
+
+    if !is_stop {
+      if let Some(coalescer) = &self.scalar_coalescer {
+        match coalescer.submit(commands) {
+          CoalescerAction::Send(commands) => {
+            return self.handle_generic_command_result(self.handler.handle_scalar_cmd(&commands))
+          }
+          CoalescerAction::Deferred(flush_delay, result) => {
+            if let Some(flush_delay) = flush_delay {
+              self.schedule_scalar_flush(coalescer.clone(), flush_delay);
+            }
+            // Reply once the update has been written, so write errors still make it back. If the
+            // flush never happens because the device went away, the update counts as handled.
+            return async move {
+              result
+                .await
+                .unwrap_or(Ok(()))
+                .map(|_| message::Ok::default().into())
+            }
+            .boxed();
+          }
+        }
+      }
+    }
+
+    self.handle_generic_command_result(self.handler.handle_scalar_cmd(&commands))
+  }
+
+  /// Apply any user configured scaling, inversion, and remapping to a scalar command. Expects the
+  /// command to already be validated against the client facing actuator attributes.
+  fn adjust_scalar_cmd(
+    &self,
+    msg: ScalarCmd,
+    attrs: &[ServerGenericDeviceMessageAttributes],
+  ) -> ScalarCmd {
+    if self.scalar_adjustments.is_empty() {
+      return msg;
+    }
+    let scalars = msg
+      .scalars()
+      .iter()
+      .map(
+        |command| match self.scalar_adjustments.get(&command.index()) {
+          Some(adjustment) => {
+            let target = adjustment.target_index();
+            ScalarSubcommand::new(
+              target,
+              adjustment.apply(command.scalar()),
+              attrs
+                .get(target as usize)
+                .map_or(command.actuator_type(), |attr| *attr.actuator_type()),
+            )
+          }
+          None => command.clone(),
+        },
+      )
+      .collect();
+    let mut adjusted = ScalarCmd::new(msg.device_index(), scalars);
+    adjusted.set_id(msg.id());
+    adjusted
+  }
+
+  /// Same as [Self::handle_scalar_cmd], for rotation.
+  fn handle_rotate_cmd(&self, msg: RotateCmd, is_stop: bool) -> ButtplugServerResultFuture {
+    let msg = if is_stop {
+      msg
+    } else {
+      match self.apply_rotate_safety(msg) {
+        Ok(msg) => msg,
+        Err(err) => return future::ready(Err(err.into())).boxed(),
+      }
+    };
+    let commands = match self
+      .generic_command_manager
+      .update_rotation(&msg, self.handler.needs_full_command_set())
+    {
+      Ok(values) => values,
+      Err(err) => return future::ready(Err(err)).boxed(),
+    };
+    self.update_safety_output();
+    self.handle_generic_command_result(self.handler.handle_rotate_cmd(&commands))
+  }
+
+  /// Clamp scalar values to the safety limits. Commands that would turn anything on are rejected
+  /// while the device is cooling down.
+  fn apply_scalar_safety(&self, msg: ScalarCmd) -> Result<ScalarCmd, ButtplugDeviceError> {
+    let Some(safety) = &self.safety else {
+      return Ok(msg);
+    };
+    if msg.scalars().iter().any(|command| command.scalar() > 0.0) {
+      safety.check_cooldown()?;
+    }
+    let scalars = msg
+      .scalars()
+      .iter()
+      .map(|command| {
+        ScalarSubcommand::new(
+          command.index(),
+          safety.clamp(command.scalar()),
+          command.actuator_type(),
+        )
+      })
+      .collect();
+    let mut clamped = ScalarCmd::new(msg.device_index(), scalars);
+    clamped.set_id(msg.id());
+    Ok(clamped)
+  }
+
+  /// Same as [Self::apply_scalar_safety], for rotation speeds.
+  fn apply_rotate_safety(&self, msg: RotateCmd) -> Result<RotateCmd, ButtplugDeviceError> {
+    let Some(safety) = &self.safety else {
+      return Ok(msg);
+    };
+    if msg.rotations().iter().any(|command| command.speed() > 0.0) {
+      safety.check_cooldown()?;
+    }
+    let rotations = msg
+      .rotations()
+      .iter()
+      .map(|command| {
+        RotationSubcommand::new(
+          command.index(),
+          safety.clamp(command.speed()),
+          command.clockwise(),
+        )
+      })
+      .collect();
+    let mut clamped = RotateCmd::new(msg.device_index(), rotations);
+    clamped.set_id(msg.id());
+    Ok(clamped)
+  }
+
+  /// Cap linear positions to the safety limits, like position actuators in scalar commands. Every
+  /// move is output, so they're all rejected while the device is cooling down, and the device counts
+  /// as on until the longest move should have finished.
+  fn apply_linear_safety(&self, msg: LinearCmd) -> Result<LinearCmd, ButtplugDeviceError> {
+    let Some(safety) = &self.safety else {
+      return Ok(msg);
+    };
+    safety.check_cooldown()?;
+    let vectors = msg
+      .vectors()
+      .iter()
+      .map(|vector| {
+        VectorSubcommand::new(
+          vector.index(),
+          vector.duration(),
+          safety.clamp(vector.position()),
+        )
+      })
+      .collect();
+    let mut clamped = LinearCmd::new(msg.device_index(), vectors);
+    clamped.set_id(msg.id());
+    clamped.set_scheduled_time(msg.scheduled_time());
+    let duration = msg
+      .vectors()
+      .iter()
+      .map(|vector| vector.duration())
+      .max()
+      .unwrap_or(0);
+    safety.set_motion_active(Duration::from_millis(duration as u64));
+    Ok(clamped)
+  }
+
+  /// Same as [Self::apply_linear_safety], with the limits applied to both the position and speed.
+  /// The move counts as a full stroke at that speed.
+  fn apply_fleshlight_safety(
+    &self,
+    msg: FleshlightLaunchFW12Cmd,
+  ) -> Result<FleshlightLaunchFW12Cmd, ButtplugDeviceError> {
+    let Some(safety) = &self.safety else {
+      return Ok(msg);
+    };
+    safety.check_cooldown()?;
+    let speed = safety.clamp_percent(msg.speed() as u32) as u8;
+    let mut clamped = FleshlightLaunchFW12Cmd::new(
+      msg.device_index(),
+      safety.clamp_percent(msg.position() as u32) as u8,
+      speed,
+    );
+    clamped.set_id(msg.id());
+    let duration = fleshlight_launch_helper::calculate_duration(1f64, speed as f64 / 99f64);
+    safety.set_motion_active(Duration::from_millis(duration as u64));
+    Ok(clamped)
+  }
+
+  /// Same as [Self::apply_rotate_safety], for Vorze A10 Cyclone speeds.
+  fn apply_vorze_safety(
+    &self,
+    msg: VorzeA10CycloneCmd,
+  ) -> Result<VorzeA10CycloneCmd, ButtplugDeviceError> {
+    let Some(safety) = &self.safety else {
+      return Ok(msg);
+    };
+    if msg.speed() > 0 {
+      safety.check_cooldown()?;
+    }
+    let speed = safety.clamp_percent(msg.speed());
+    let mut clamped = VorzeA10CycloneCmd::new(msg.device_index(), speed, msg.clockwise());
+    clamped.set_id(msg.id());
+    safety.set_output_active(OutputSource::Vorze, speed > 0);
+    Ok(clamped)
+  }
+
+  /// Protocols that handle messages themselves still get the same limits as everything else. The
+  /// generic command manager isn't used to build their commands, but it still keeps track of which
+  /// actuators are on for the run time watchdog.
+  fn apply_passthrough_safety(
+    &self,
+    msg: ButtplugDeviceCommandMessageUnion,
+  ) -> Result<ButtplugDeviceCommandMessageUnion, ButtplugDeviceError> {
+    let Some(safety) = &self.safety else {
+      return Ok(msg);
+    };
+    let msg = match msg {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) => {
+        let msg = self.apply_scalar_safety(msg)?;
+        let _ = self.generic_command_manager.update_scalar(&msg, false);
+        msg.into()
+      }
+      ButtplugDeviceCommandMessageUnion::VibrateCmd(msg) => {
+        let scalars = self.apply_scalar_safety(ScalarCmd::from(msg.clone()))?;
+        let _ = self.generic_command_manager.update_scalar(&scalars, false);
+        let speeds = scalars
+          .scalars()
+          .iter()
+          .map(|command| VibrateSubcommand::new(command.index(), command.scalar()))
+          .collect();
+        let mut clamped = VibrateCmd::new(msg.device_index(), speeds);
+        clamped.set_id(msg.id());
+        clamped.into()
+      }
+      ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(msg) => {
+        if msg.speed() > 0.0 {
+          safety.check_cooldown()?;
+        }
+        let mut clamped =
+          message::SingleMotorVibrateCmd::new(msg.device_index(), safety.clamp(msg.speed()));
+        clamped.set_id(msg.id());
+        if let Some(scalars) = self.single_motor_scalar_cmd(&clamped) {
+          let _ = self.generic_command_manager.update_scalar(&scalars, false);
+        }
+        clamped.into()
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) => {
+        let msg = self.apply_rotate_safety(msg)?;
+        let _ = self.generic_command_manager.update_rotation(&msg, false);
+        msg.into()
+      }
+      ButtplugDeviceCommandMessageUnion::LinearCmd(msg) => self.apply_linear_safety(msg)?.into(),
+      ButtplugDeviceCommandMessageUnion::FleshlightLaunchFW12Cmd(msg) => {
+        self.apply_fleshlight_safety(msg)?.into()
+      }
+      ButtplugDeviceCommandMessageUnion::VorzeA10CycloneCmd(msg) => {
+        self.apply_vorze_safety(msg)?.into()
+      }
+      msg => msg,
+    };
+    self.update_safety_output();
+    Ok(msg)
+  }
+
+  /// Let the run time watchdog know whether the device is currently on.
+  fn update_safety_output(&self) {
+    if let Some(safety) = &self.safety {
+      safety.set_output_active(
+        OutputSource::Actuators,
+        self.generic_command_manager.has_active_output(),
+      );
+    }
+  }
+
+  /// Stop the device if it runs longer than its safety limits allow. Does nothing if the device has
+  /// no max run time.
+  pub(super) fn start_safety_watchdog(self: &Arc<Self>) {
+    let Some(safety) = &self.safety else {
+      return;
+    };
+    let device = Arc::downgrade(self);
+    let watchdog = safety.run_time_watchdog(move || {
+      Weak::upgrade(&device).map(|device| device.handle_stop_device_cmd())
+    });
+    if let Some(watchdog) = watchdog {
+      async_manager::spawn(watchdog.instrument(self.span.clone()));
+    }
+  }
+
+  /// Poll the battery while a client is subscribed to it. Does nothing if battery polling is off,
+  /// or the device has no battery to read.
+  pub(super) fn start_battery_monitor(self: &Arc<Self>) {
+    let Some(monitor) = &self.battery_monitor else {
+      return;
+    };
+    let device = Arc::downgrade(self);
+    let polling_task = monitor.polling_task(move || {
+      Weak::upgrade(&device).map(|device| device.read_legacy_sensor(SensorType::Battery))
+    });
+    async_manager::spawn(polling_task.instrument(self.span.clone()));
+  }
+
+  /// The polled battery monitor, if the sensor is the battery the server polls.
+  fn polled_battery(
+    &self,
+    sensor_index: u32,
+    sensor_type: SensorType,
+  ) -> Option<&DeviceBatteryMonitor> {
+    self.battery_monitor.as_ref().filter(|monitor| {
+      sensor_type == SensorType::Battery && monitor.sensor_index() == sensor_index
+    })
+  }
+
+  fn cancel_scalar_ramp(&self) {
+    if let Some(token) = self
+      .scalar_ramp_token
+      .lock()
+      .expect("Ramp lock should never be poisoned")
+      .take()
+    {
+      token.cancel();
+    }
+  }
+
+  /// Send the first update of a scalar ramp now, and schedule the rest to go out evenly spaced over
+  /// the ramp time.
+  fn send_scalar_ramp(&self, mut frames: Vec<ScalarCommandSet>) -> ButtplugServerResultFuture {
+    let first_frame = frames.remove(0);
+    if let Some(ramp_time) = self
+      .generic_command_manager
+      .scalar_ramp_time()
+      .filter(|_| !frames.is_empty())
+    {
+      let interval = ramp_time / (frames.len() as u32 + 1);
+      let token = CancellationToken::new();
+      *self
+        .scalar_ramp_token
+        .lock()
+        .expect("Ramp lock should never be poisoned") = Some(token.clone());
+      let gcm = self.generic_command_manager.clone();
+      let handler = self.handler.clone();
+      let hardware = self.hardware.clone();
+      let protocol = self.protocol_name.clone();
+      let keepalive_packet = self.keepalive_packet.clone();
+      let command_queue = self.command_queue.clone();
+      let ticket = command_queue.ticket();
+      async_manager::spawn(
+        async move {
+          for frame in frames {
+            select! {
+              _ = util::sleep(interval).fuse() => {}
+              _ = token.cancelled().fuse() => return,
+            }
+            let Some(_turn) = command_queue.wait_for_turn(ticket).await else {
+              return;
+            };
+            gcm.set_scalar_output(&frame);
+            let result = match handler.handle_scalar_cmd(&frame) {
+              Ok(hardware_commands) => {
+                write_hardware_commands(
+                  hardware.clone(),
+                  protocol.clone(),
+                  handler.keepalive_strategy(),
+                  handler.pipeline_writes(),
+                  keepalive_packet.clone(),
+                  hardware_commands,
+                )
+                .await
+              }
+              Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+              error!("Error writing scalar ramp update, stopping ramp: {:?}", err);
+              return;
+            }
+          }
+        }
+        .instrument(self.span.clone()),
+      );
+    }
+    self.generic_command_manager.set_scalar_output(&first_frame);
+    self.handle_generic_command_result(self.handler.handle_scalar_cmd(&first_frame))
+  }
+
+  /// Write whatever scalar updates have been coalesced once the rate limit interval has passed.
+  fn schedule_scalar_flush(&self, coalescer: ScalarCommandCoalescer, flush_delay: Duration) {
+    // Time spent waiting out the rate limit counts as queue latency, otherwise rate limited devices
+    // would only ever record the (near zero) queue time of writes that were sent immediately.
+    let queued = Instant::now();
+    let latency = self.latency.clone();
+    let handler = self.handler.clone();
     let hardware = self.hardware.clone();
-    let keepalive_type = self.handler.keepalive_strategy();
+    let protocol = self.protocol_name.clone();
     let keepalive_packet = self.keepalive_packet.clone();
-    async move {
-      // Run commands in order, otherwise we may end up sending out of order. This may take a while,
-      // but it's what 99% of protocols expect. If they want something else, they can implement it
-      // themselves.
-      //
-      // If anything errors out, just bail on the command series. This most likely means the device
-      // disconnected.
-      for command in commands {
-        hardware.parse_message(&command).await?;
-        if hardware.requires_keepalive()
-          && matches!(
-            keepalive_type,
-            ProtocolKeepaliveStrategy::RepeatLastPacketStrategy
-          )
-        {
-          if let HardwareCommand::Write(command) = command {
-            *keepalive_packet.write().await = Some(command);
+    let command_queue = self.command_queue.clone();
+    let ticket = command_queue.ticket();
+    async_manager::spawn(
+      async move {
+        util::sleep(flush_delay).await;
+        let Some(pending) = coalescer.take_pending() else {
+          return;
+        };
+        let Some(_turn) = command_queue.wait_for_turn(ticket).await else {
+          // Dropped for a stop, same as commands waiting in the queue.
+          pending.finish(Ok(()));
+          return;
+        };
+        let write_start = Instant::now();
+        let result = match handler.handle_scalar_cmd(pending.commands()) {
+          Ok(hardware_commands) => {
+            write_hardware_commands(
+              hardware,
+              protocol,
+              handler.keepalive_strategy(),
+              handler.pipeline_writes(),
+              keepalive_packet,
+              hardware_commands,
+            )
+            .await
           }
+          Err(err) => Err(err.into()),
+        };
+        match &result {
+          Ok(_) => latency.record_hardware_write(write_start - queued, write_start.elapsed()),
+          Err(err) => error!("Error writing coalesced scalar command: {:?}", err),
         }
+        pending.finish(result.map(|_| ()));
       }
-      Ok(message::Ok::default().into())
+      .instrument(self.span.clone()),
+    );
+  }
+
+  fn handle_hardware_commands(&self, commands: Vec<HardwareCommand>) -> ButtplugServerResultFuture {
+    let queued = Instant::now();
+    let latency = self.latency.clone();
+    let command_queue = self.command_queue.clone();
+    let ticket = command_queue.ticket();
+    let idle_monitor = self.idle_monitor.clone();
+    if let Some(monitor) = &idle_monitor {
+      monitor.record_activity();
+    }
+    let hardware = self.hardware.clone();
+    let handler = self.handler.clone();
+    let fut = write_hardware_commands(
+      self.hardware.clone(),
+      self.protocol_name.clone(),
+      self.handler.keepalive_strategy(),
+      self.handler.pipeline_writes(),
+      self.keepalive_packet.clone(),
+      commands,
+    );
+    async move {
+      let Some(_turn) = command_queue.wait_for_turn(ticket).await else {
+        debug!("Dropping device command received before a stop.");
+        return Ok(message::Ok::default().into());
+      };
+      if let Some(monitor) = idle_monitor.filter(|monitor| monitor.take_low_power()) {
+        debug!("Waking {} from low power mode.", hardware.name());
+        let result = match handler.handle_wake() {
+          Ok(commands) => write_keepalive_commands(&hardware, commands).await,
+          Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+          warn!(
+            "Error waking {} from low power mode: {:?}",
+            hardware.name(),
+            err
+          );
+        }
+        monitor.record_activity();
+      }
+      let write_start = Instant::now();
+      let result = fut.await;
+      if result.is_ok() {
+        latency.record_hardware_write(write_start - queued, write_start.elapsed());
+      }
+      result
     }
     .boxed()
   }
@@ -598,11 +1886,28 @@ impl ServerDevice {
   }
 
   fn handle_stop_device_cmd(&self) -> ButtplugServerResultFuture {
+    // Anything received before the stop and not written yet is dropped, and the stop's own writes
+    // wait for any write in progress to finish.
+    let _stop_scope = self.command_queue.begin_stop();
     let commands = self.generic_command_manager.stop_commands();
+    // Stops should never sit behind the rate limiter, and anything still waiting to be sent would
+    // just restart the device after we've stopped it.
+    if let Some(coalescer) = &self.scalar_coalescer {
+      coalescer.clear_pending();
+    }
+    if let Some(safety) = &self.safety {
+      safety.stopped();
+    }
     let mut fut_vec = vec![];
-    commands
-      .iter()
-      .for_each(|msg| fut_vec.push(self.parse_message(msg.clone())));
+    commands.into_iter().for_each(|msg| match msg {
+      ButtplugDeviceCommandMessageUnion::ScalarCmd(msg) if !self.handler.has_handle_message() => {
+        fut_vec.push(self.handle_scalar_cmd(msg, true))
+      }
+      ButtplugDeviceCommandMessageUnion::RotateCmd(msg) if !self.handler.has_handle_message() => {
+        fut_vec.push(self.handle_rotate_cmd(msg, true))
+      }
+      _ => fut_vec.push(self.dispatch_message(msg)),
+    });
     async move {
       for fut in fut_vec {
         fut.await?;
@@ -639,7 +1944,7 @@ impl ServerDevice {
   fn handle_sensor_read_cmd(&self, message: message::SensorReadCmd) -> ButtplugServerResultFuture {
     let result = self.check_sensor_command(
       self
-        .message_attributes()
+        .message_attributes
         .sensor_read_cmd()
         .as_ref()
         .expect("Already checked validity"),
@@ -648,12 +1953,13 @@ impl ServerDevice {
     );
     let device = self.hardware.clone();
     let handler = self.handler.clone();
+    let (address, protocol) = self.error_context();
     async move {
       result?;
       handler
         .handle_sensor_read_cmd(device, message)
         .await
-        .map_err(|e| e.into())
+        .map_err(|e| device_error_context(e, &address, &protocol).into())
     }
     .boxed()
   }
@@ -662,9 +1968,13 @@ impl ServerDevice {
     &self,
     message: message::SensorSubscribeCmd,
   ) -> ButtplugServerResultFuture {
+    if let Some(monitor) = self.polled_battery(*message.sensor_index(), *message.sensor_type()) {
+      monitor.set_subscribed(true);
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
     let result = self.check_sensor_command(
       self
-        .message_attributes()
+        .message_attributes
         .sensor_subscribe_cmd()
         .as_ref()
         .expect("Already checked validity"),
@@ -673,12 +1983,13 @@ impl ServerDevice {
     );
     let device = self.hardware.clone();
     let handler = self.handler.clone();
+    let (address, protocol) = self.error_context();
     async move {
       result?;
       handler
         .handle_sensor_subscribe_cmd(device, message)
         .await
-        .map_err(|e| e.into())
+        .map_err(|e| device_error_context(e, &address, &protocol).into())
     }
     .boxed()
   }
@@ -687,9 +1998,13 @@ impl ServerDevice {
     &self,
     message: message::SensorUnsubscribeCmd,
   ) -> ButtplugServerResultFuture {
+    if let Some(monitor) = self.polled_battery(*message.sensor_index(), *message.sensor_type()) {
+      monitor.set_subscribed(false);
+      return future::ready(Ok(message::Ok::new(message.id()).into())).boxed();
+    }
     let result = self.check_sensor_command(
       self
-        .message_attributes()
+        .message_attributes
         .sensor_subscribe_cmd()
         .as_ref()
         .expect("Already checked validity"),
@@ -698,12 +2013,13 @@ impl ServerDevice {
     );
     let device = self.hardware.clone();
     let handler = self.handler.clone();
+    let (address, protocol) = self.error_context();
     async move {
       result?;
       handler
         .handle_sensor_unsubscribe_cmd(device, message)
         .await
-        .map_err(|e| e.into())
+        .map_err(|e| device_error_context(e, &address, &protocol).into())
     }
     .boxed()
   }
@@ -712,24 +2028,14 @@ impl ServerDevice {
     &self,
     message: message::SingleMotorVibrateCmd,
   ) -> ButtplugServerResultFuture {
-    if let Some(attr) = self.attributes.message_attributes().scalar_cmd() {
-      let speed = message.speed();
-      let cmds: Vec<ScalarSubcommand> = attr
-        .iter()
-        .enumerate()
-        .filter(|(_, x)| *x.actuator_type() == ActuatorType::Vibrate)
-        .map(|(index, _)| ScalarSubcommand::new(index as u32, speed, ActuatorType::Vibrate))
-        .collect();
-      if cmds.is_empty() {
-        ButtplugDeviceError::ProtocolRequirementError(format!(
+    if self.message_attributes.scalar_cmd().is_some() {
+      match self.single_motor_scalar_cmd(&message) {
+        Some(vibrate_cmd) => self.dispatch_message(vibrate_cmd.into()),
+        None => ButtplugDeviceError::ProtocolRequirementError(format!(
           "{} has no vibrating features.",
           self.name()
         ))
-        .into()
-      } else {
-        let mut vibrate_cmd = ScalarCmd::new(message.device_index(), cmds);
-        vibrate_cmd.set_id(message.id());
-        self.parse_message(vibrate_cmd.into())
+        .into(),
       }
     } else {
       ButtplugDeviceError::ProtocolRequirementError(format!(
@@ -740,21 +2046,55 @@ impl ServerDevice {
     }
   }
 
+  /// A scalar command setting every vibrator to the single motor speed. Returns None if there are no
+  /// vibrators.
+  fn single_motor_scalar_cmd(&self, message: &message::SingleMotorVibrateCmd) -> Option<ScalarCmd> {
+    let cmds: Vec<ScalarSubcommand> = self
+      .message_attributes
+      .scalar_cmd()
+      .as_ref()?
+      .iter()
+      .enumerate()
+      .filter(|(_, x)| *x.actuator_type() == ActuatorType::Vibrate)
+      .map(|(index, _)| ScalarSubcommand::new(index as u32, message.speed(), ActuatorType::Vibrate))
+      .collect();
+    if cmds.is_empty() {
+      return None;
+    }
+    let mut vibrate_cmd = ScalarCmd::new(message.device_index(), cmds);
+    vibrate_cmd.set_id(message.id());
+    Some(vibrate_cmd)
+  }
+
+  /// Address and protocol name, for adding context to errors in futures that outlive self.
+  fn error_context(&self) -> (String, String) {
+    (
+      self.identifier.address().clone(),
+      self.identifier.protocol().clone(),
+    )
+  }
+
   fn handle_raw_write_cmd(&self, message: message::RawWriteCmd) -> ButtplugServerResultFuture {
     let id = message.id();
+    let endpoint = message.endpoint();
     let fut = self.hardware.write_value(&message.into());
+    let (address, protocol) = self.error_context();
     async move {
       fut
         .await
         .map(|_| message::Ok::new(id).into())
-        .map_err(|err| err.into())
+        .map_err(|err| {
+          hardware_error_context(err, &address, &protocol, HardwareAccess::Write, endpoint).into()
+        })
     }
     .boxed()
   }
 
   fn handle_raw_read_cmd(&self, message: message::RawReadCmd) -> ButtplugServerResultFuture {
     let id = message.id();
+    let endpoint = message.endpoint();
     let fut = self.hardware.read_value(&message.into());
+    let (address, protocol) = self.error_context();
     async move {
       fut
         .await
@@ -763,7 +2103,9 @@ impl ServerDevice {
           raw_msg.set_id(id);
           raw_msg.into()
         })
-        .map_err(|err| err.into())
+        .map_err(|err| {
+          hardware_error_context(err, &address, &protocol, HardwareAccess::Read, endpoint).into()
+        })
     }
     .boxed()
   }
@@ -776,6 +2118,7 @@ impl ServerDevice {
     let endpoint = message.endpoint();
     let fut = self.hardware.unsubscribe(&message.into());
     let raw_endpoints = self.raw_subscribed_endpoints.clone();
+    let (address, protocol) = self.error_context();
     async move {
       if !raw_endpoints.contains(&endpoint) {
         return Ok(message::Ok::new(id).into());
@@ -783,7 +2126,16 @@ impl ServerDevice {
       let result = fut
         .await
         .map(|_| message::Ok::new(id).into())
-        .map_err(|err| err.into());
+        .map_err(|err| {
+          hardware_error_context(
+            err,
+            &address,
+            &protocol,
+            HardwareAccess::Subscribe,
+            endpoint,
+          )
+          .into()
+        });
       raw_endpoints.remove(&endpoint);
       result
     }
@@ -798,6 +2150,7 @@ impl ServerDevice {
     let endpoint = message.endpoint();
     let fut = self.hardware.subscribe(&message.into());
     let raw_endpoints = self.raw_subscribed_endpoints.clone();
+    let (address, protocol) = self.error_context();
     async move {
       if raw_endpoints.contains(&endpoint) {
         return Ok(message::Ok::new(id).into());
@@ -805,80 +2158,145 @@ impl ServerDevice {
       let result = fut
         .await
         .map(|_| message::Ok::new(id).into())
-        .map_err(|err| err.into());
+        .map_err(|err| {
+          hardware_error_context(
+            err,
+            &address,
+            &protocol,
+            HardwareAccess::Subscribe,
+            endpoint,
+          )
+          .into()
+        });
       raw_endpoints.insert(endpoint);
       result
     }
     .boxed()
   }
 
-  fn handle_battery_level_cmd(&self) -> ButtplugServerResultFuture {
-    // See if we have a battery sensor.
-    if let Some(sensor_attributes) = self.message_attributes().sensor_read_cmd() {
-      for (index, sensor) in sensor_attributes.iter().enumerate() {
-        if *sensor.sensor_type() == SensorType::Battery {
-          let sensor_read_msg = SensorReadCmd::new(0, index as u32, SensorType::Battery);
-          let sensor_read = self.handle_sensor_read_cmd(sensor_read_msg);
-          let sensor_range_end = *sensor.sensor_range()[0].end();
-          return async move {
-            let return_msg = sensor_read.await?;
-            if let ButtplugServerMessage::SensorReading(reading) = return_msg {
-              if reading.sensor_type() == SensorType::Battery {
-                Ok(
-                  BatteryLevelReading::new(0, reading.data()[0] as f64 / sensor_range_end as f64)
-                    .into(),
-                )
-              } else {
-                Err(ButtplugError::ButtplugDeviceError(
-                  ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::Battery),
-                ))
-              }
-            } else {
-              Err(ButtplugError::ButtplugDeviceError(
-                ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::Battery),
-              ))
-            }
-          }
-          .boxed();
+  /// Read the first sensor of the given type, for spec v2 and older messages that predate
+  /// SensorReadCmd, and battery polling. Also returns the top of the sensor's range.
+  fn read_legacy_sensor(
+    &self,
+    sensor_type: SensorType,
+  ) -> BoxFuture<'static, Result<(SensorReading, u32), ButtplugError>> {
+    let not_supported = move || ButtplugDeviceError::ProtocolSensorNotSupported(sensor_type).into();
+    let sensor = self
+      .message_attributes
+      .sensor_read_cmd()
+      .as_ref()
+      .and_then(|sensors| {
+        sensors
+          .iter()
+          .enumerate()
+          .find(|(_, sensor)| *sensor.sensor_type() == sensor_type)
+      });
+    let Some((index, sensor)) = sensor else {
+      return future::ready(Err(not_supported())).boxed();
+    };
+    let Some(sensor_range_end) = sensor.sensor_range().first().map(|range| *range.end()) else {
+      return future::ready(Err(
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "{} sensor has no range configured",
+          sensor_type
+        ))
+        .into(),
+      ))
+      .boxed();
+    };
+    let sensor_read = self.handle_sensor_read_cmd(SensorReadCmd::new(0, index as u32, sensor_type));
+    async move {
+      match sensor_read.await? {
+        ButtplugServerMessage::SensorReading(reading) if reading.sensor_type() == sensor_type => {
+          Ok((reading, sensor_range_end))
         }
+        _ => Err(not_supported()),
       }
     }
-    future::ready(Err(ButtplugError::ButtplugDeviceError(
-      ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::Battery),
-    )))
+    .boxed()
+  }
+
+  fn handle_battery_level_cmd(&self) -> ButtplugServerResultFuture {
+    let sensor_read = self.read_legacy_sensor(SensorType::Battery);
+    async move {
+      let (reading, sensor_range_end) = sensor_read.await?;
+      let level = first_sensor_value(&reading)?;
+      Ok(BatteryLevelReading::new(0, level as f64 / sensor_range_end as f64).into())
+    }
     .boxed()
   }
 
   fn handle_rssi_level_cmd(&self) -> ButtplugServerResultFuture {
-    // See if we have a battery sensor.
-    if let Some(sensor_attributes) = self.message_attributes().sensor_read_cmd() {
-      for (index, sensor) in sensor_attributes.iter().enumerate() {
-        if *sensor.sensor_type() == SensorType::RSSI {
-          let sensor_read_msg = SensorReadCmd::new(0, index as u32, SensorType::RSSI);
-          let sensor_read = self.handle_sensor_read_cmd(sensor_read_msg);
-          return async move {
-            let return_msg = sensor_read.await?;
-            if let ButtplugServerMessage::SensorReading(reading) = return_msg {
-              if reading.sensor_type() == SensorType::RSSI {
-                Ok(RSSILevelReading::new(0, reading.data()[0]).into())
-              } else {
-                Err(ButtplugError::ButtplugDeviceError(
-                  ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::RSSI),
-                ))
-              }
-            } else {
-              Err(ButtplugError::ButtplugDeviceError(
-                ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::RSSI),
-              ))
-            }
-          }
-          .boxed();
-        }
-      }
+    let sensor_read = self.read_legacy_sensor(SensorType::RSSI);
+    async move {
+      let (reading, _) = sensor_read.await?;
+      Ok(RSSILevelReading::new(0, first_sensor_value(&reading)?).into())
     }
-    future::ready(Err(ButtplugError::ButtplugDeviceError(
-      ButtplugDeviceError::ProtocolSensorNotSupported(SensorType::RSSI),
-    )))
     .boxed()
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::server::device::hardware::HardwareSubscribeCmd;
+
+  fn write(data: u8) -> HardwareCommand {
+    HardwareWriteCmd::new(Endpoint::Tx, vec![data], false).into()
+  }
+
+  fn subscribe() -> HardwareCommand {
+    HardwareSubscribeCmd::new(Endpoint::Rx).into()
+  }
+
+  #[test]
+  fn test_group_hardware_commands_without_pipelining() {
+    assert_eq!(
+      group_hardware_commands(vec![write(1), write(2), subscribe()], false),
+      vec![vec![write(1)], vec![write(2)], vec![subscribe()]]
+    );
+  }
+
+  #[test]
+  fn test_group_hardware_commands_with_pipelining() {
+    // Only runs of consecutive writes are batched, so writes never move past other commands.
+    assert_eq!(
+      group_hardware_commands(
+        vec![
+          write(1),
+          write(2),
+          subscribe(),
+          write(3),
+          subscribe(),
+          write(4),
+          write(5),
+        ],
+        true
+      ),
+      vec![
+        vec![write(1), write(2)],
+        vec![subscribe()],
+        vec![write(3)],
+        vec![subscribe()],
+        vec![write(4), write(5)],
+      ]
+    );
+    assert!(group_hardware_commands(vec![], true).is_empty());
+  }
+
+  #[test]
+  fn test_first_sensor_value() {
+    assert_eq!(
+      first_sensor_value(&SensorReading::new(0, 0, SensorType::Battery, vec![42, 7]))
+        .expect("Test, assuming infallible."),
+      42
+    );
+    // Empty readings fail instead of taking down the server.
+    assert!(matches!(
+      first_sensor_value(&SensorReading::new(0, 0, SensorType::RSSI, vec![])),
+      Err(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::DeviceCommunicationError(_)
+      ))
+    ));
+  }
+}
@@ -8,13 +8,25 @@
 //! Buttplug Device Manager, manages Device Subtype (Platform/Communication bus
 //! specific) Managers
 
-use super::server_device_manager_event_loop::ServerDeviceManagerEventLoop;
+use super::{
+  battery::BatteryPollingPolicy,
+  choreography::{CommandScheduler, ServerClock},
+  device_group::{self, DeviceGroups},
+  funscript::{Funscript, FunscriptOutput, FunscriptPlaybackState, FunscriptPlayer},
+  latency::DeviceLatencyStats,
+  pattern::{Pattern, PatternPlayer},
+  remembered::{RememberedDevice, RememberedDeviceStore},
+  safety::SafetyPolicy,
+  scanning::{CommunicationManagerScanStatus, ScanStatusTracker},
+  server_device_manager_event_loop::ServerDeviceManagerEventLoop,
+};
 use crate::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugMessageError, ButtplugUnknownError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError, ButtplugUnknownError},
     message::{
       self,
       ButtplugClientMessage,
+      ButtplugClientMessageDestination,
       ButtplugDeviceCommandMessageUnion,
       ButtplugDeviceManagerMessageUnion,
       ButtplugDeviceMessage,
@@ -22,49 +34,65 @@ use crate::{
       ButtplugServerMessage,
       DeviceList,
       DeviceMessageInfo,
+      DeviceRemovedReason,
+      GroupCommandResult,
+      GroupScalarCmd,
+      ScanFilter,
+      ScanningStatus,
     },
   },
   server::{
     device::{
       configuration::{
+        DeviceConfigurationDiff,
+        DeviceConfigurationManager,
         DeviceConfigurationManagerBuilder,
         ProtocolAttributesIdentifier,
         ProtocolCommunicationSpecifier,
         ProtocolDeviceAttributes,
       },
-      hardware::communication::{
-        HardwareCommunicationManager,
-        HardwareCommunicationManagerBuilder,
+      hardware::{
+        communication::{HardwareCommunicationManager, HardwareCommunicationManagerBuilder},
+        recording::HardwareTrafficRecorder,
       },
-      protocol::ProtocolIdentifierFactory,
+      protocol::{ProtocolCapabilities, ProtocolIdentifierFactory, ProtocolInitializationTimeouts},
       ServerDevice,
       ServerDeviceIdentifier,
     },
     ButtplugServerError,
     ButtplugServerResultFuture,
   },
-  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+  util::{
+    self,
+    async_manager,
+    device_configuration::UserDeviceConfig,
+    stream::convert_broadcast_receiver_to_stream,
+  },
 };
 use dashmap::DashMap;
 use futures::{
-  future::{self, FutureExt},
+  future::{self, BoxFuture, FutureExt},
   Stream,
 };
 use getset::Getters;
 use std::{
-  convert::TryFrom,
+  collections::HashMap,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
+    Mutex,
   },
+  time::Duration,
 };
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 
 #[derive(Debug)]
 pub(super) enum DeviceManagerCommand {
-  StartScanning,
+  StartScanning(Option<ScanFilter>),
   StopScanning,
+  EnterBackground,
+  EnterForeground,
 }
 
 #[derive(Debug, Getters)]
@@ -74,10 +102,39 @@ pub struct ServerDeviceInfo {
   display_name: Option<String>,
 }
 
+/// How long a device gets to acknowledge a stop during StopAllDevices before it's disconnected.
+pub const DEFAULT_DEVICE_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many devices can be connecting at the same time, unless the builder says otherwise.
+pub const DEFAULT_MAX_PARALLEL_CONNECTIONS: usize = 4;
+
+/// How many times a failed device connection is retried, unless the builder says otherwise.
+pub const DEFAULT_CONNECTION_RETRIES: u32 = 1;
+
+/// Outcome of stopping all devices.
+#[derive(Debug, Default, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct StopAllDevicesResult {
+  /// Devices that acknowledged the stop.
+  stopped: Vec<u32>,
+  /// Devices that failed or timed out while stopping, and were disconnected instead, along with
+  /// the error that caused it.
+  force_disconnected: Vec<(u32, ButtplugError)>,
+}
+
 #[derive(Default)]
 pub struct ServerDeviceManagerBuilder {
   configuration_manager_builder: DeviceConfigurationManagerBuilder,
   comm_managers: Vec<Box<dyn HardwareCommunicationManagerBuilder>>,
+  traffic_recorder: Option<HardwareTrafficRecorder>,
+  device_stop_timeout: Option<Duration>,
+  protocol_stop_timeouts: HashMap<String, Duration>,
+  safety_policy: SafetyPolicy,
+  battery_polling: Option<BatteryPollingPolicy>,
+  initialization_timeouts: ProtocolInitializationTimeouts,
+  remembered_devices: Option<RememberedDeviceStore>,
+  max_parallel_connections: Option<usize>,
+  connection_retries: Option<u32>,
 }
 
 impl ServerDeviceManagerBuilder {
@@ -107,6 +164,14 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
+  /// Expose devices using an experimental protocol, which are hidden by default.
+  pub fn allow_experimental_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self
+      .configuration_manager_builder
+      .allow_experimental_protocol(protocol_name);
+    self
+  }
+
   pub fn reserved_index(&mut self, identifier: &ServerDeviceIdentifier, index: u32) -> &mut Self {
     self
       .configuration_manager_builder
@@ -154,11 +219,83 @@ impl ServerDeviceManagerBuilder {
     self
   }
 
+  /// Record all traffic to and from devices connected after this point. Meant for debugging
+  /// protocols, recordings can get large quickly.
+  pub fn hardware_traffic_recorder(&mut self, recorder: HardwareTrafficRecorder) -> &mut Self {
+    self.traffic_recorder = Some(recorder);
+    self
+  }
+
+  /// Time each device has to acknowledge a stop when all devices are stopped, before it's
+  /// disconnected so it can't keep running. Defaults to [DEFAULT_DEVICE_STOP_TIMEOUT].
+  pub fn device_stop_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.device_stop_timeout = Some(timeout);
+    self
+  }
+
+  /// Stop timeout for devices using a specific protocol, overriding
+  /// [device_stop_timeout](Self::device_stop_timeout). Useful for protocols that talk to devices
+  /// through something slower than a direct connection, like an app on another machine.
+  pub fn protocol_device_stop_timeout(&mut self, protocol: &str, timeout: Duration) -> &mut Self {
+    self
+      .protocol_stop_timeouts
+      .insert(protocol.to_owned(), timeout);
+    self
+  }
+
+  /// Output limits enforced on devices, no matter which client is sending commands. Applies to
+  /// devices connected after this point.
+  pub fn safety_policy(&mut self, policy: SafetyPolicy) -> &mut Self {
+    self.safety_policy = policy;
+    self
+  }
+
+  /// Poll the battery of devices clients are subscribed to, and warn clients when it's low. Applies
+  /// to devices connected after this point. See the [battery](super::battery) module for details.
+  pub fn battery_polling(&mut self, policy: BatteryPollingPolicy) -> &mut Self {
+    self.battery_polling = Some(policy);
+    self
+  }
+
+  /// Time protocols have to identify and initialize devices before the connection is given up on.
+  /// Applies to devices connected after this point.
+  pub fn initialization_timeouts(&mut self, timeouts: ProtocolInitializationTimeouts) -> &mut Self {
+    self.initialization_timeouts = timeouts;
+    self
+  }
+
+  /// Remember devices that connect in the store, and reconnect them with the same protocol when
+  /// they're seen again. See the [remembered](super::remembered) module for details.
+  pub fn remembered_devices(&mut self, store: RememberedDeviceStore) -> &mut Self {
+    self.remembered_devices = Some(store);
+    self
+  }
+
+  /// Number of devices that can be connecting at once. Devices found while all connection slots
+  /// are in use wait their turn, taking turns between transports. Defaults to
+  /// [DEFAULT_MAX_PARALLEL_CONNECTIONS].
+  pub fn max_parallel_connections(&mut self, max: usize) -> &mut Self {
+    self.max_parallel_connections = Some(max);
+    self
+  }
+
+  /// Number of times a failed connection is retried before giving up on the device, until it's
+  /// found again. Defaults to [DEFAULT_CONNECTION_RETRIES].
+  pub fn connection_retries(&mut self, retries: u32) -> &mut Self {
+    self.connection_retries = Some(retries);
+    self
+  }
+
   pub fn finish(&mut self) -> Result<ServerDeviceManager, ButtplugServerError> {
-    let config_mgr = self
-      .configuration_manager_builder
-      .finish()
-      .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+    let mut configuration_manager_builder = self.configuration_manager_builder.clone();
+    if let Some(store) = &self.remembered_devices {
+      store.apply_configs(&mut configuration_manager_builder);
+    }
+    let config_mgr = Arc::new(
+      configuration_manager_builder
+        .finish()
+        .map_err(ButtplugServerError::DeviceConfigurationManagerError)?,
+    );
 
     let (device_command_sender, device_command_receiver) = mpsc::channel(256);
     let (device_event_sender, device_event_receiver) = mpsc::channel(256);
@@ -206,35 +343,80 @@ impl ServerDeviceManagerBuilder {
     let loop_cancellation_token = CancellationToken::new();
 
     let output_sender = broadcast::channel(255).0;
+    let scan_status = ScanStatusTracker::new(comm_managers.iter().map(|mgr| mgr.name()));
+    let display_names = Arc::new(DashMap::new());
 
     let mut event_loop = ServerDeviceManagerEventLoop::new(
       comm_managers,
-      config_mgr,
+      config_mgr.clone(),
       devices.clone(),
       loop_cancellation_token.child_token(),
       output_sender.clone(),
       device_event_receiver,
       device_command_receiver,
+      self.traffic_recorder.clone(),
+      Arc::new(self.safety_policy.clone()),
+      self.battery_polling.clone().map(Arc::new),
+      Arc::new(self.initialization_timeouts.clone()),
+      scan_status.clone(),
+      self.remembered_devices.clone(),
+      display_names.clone(),
+      self
+        .max_parallel_connections
+        .unwrap_or(DEFAULT_MAX_PARALLEL_CONNECTIONS),
+      self.connection_retries.unwrap_or(DEFAULT_CONNECTION_RETRIES),
     );
     async_manager::spawn(async move {
       event_loop.run().await;
     });
     Ok(ServerDeviceManager {
+      config_mgr,
+      config_builder: Mutex::new(self.configuration_manager_builder.clone()),
+      remembered_devices: self.remembered_devices.clone(),
+      display_names,
       devices,
       device_command_sender,
       loop_cancellation_token,
       running: Arc::new(AtomicBool::new(true)),
+      server_output_paused: Arc::new(AtomicBool::new(false)),
       output_sender,
+      scan_status,
+      pattern_player: PatternPlayer::default(),
+      funscript_player: FunscriptPlayer::default(),
+      command_scheduler: CommandScheduler::default(),
+      device_groups: DeviceGroups::default(),
+      device_stop_timeout: self
+        .device_stop_timeout
+        .unwrap_or(DEFAULT_DEVICE_STOP_TIMEOUT),
+      protocol_stop_timeouts: self.protocol_stop_timeouts.clone(),
     })
   }
 }
 
 pub struct ServerDeviceManager {
+  config_mgr: Arc<DeviceConfigurationManager>,
+  /// Device configuration without remembered device overrides, so the overrides can be reapplied
+  /// when they change.
+  config_builder: Mutex<DeviceConfigurationManagerBuilder>,
+  remembered_devices: Option<RememberedDeviceStore>,
+  /// Display names set through [ServerDeviceManager::set_device_display_name], keyed by device
+  /// address. A None value means the name was cleared.
+  display_names: Arc<DashMap<String, Option<String>>>,
   devices: Arc<DashMap<u32, Arc<ServerDevice>>>,
   device_command_sender: mpsc::Sender<DeviceManagerCommand>,
   loop_cancellation_token: CancellationToken,
   running: Arc<AtomicBool>,
+  /// Set when all devices are stopped, so output the server drives by itself (like audio reactive)
+  /// doesn't turn them straight back on. Cleared by the next output command sent to a device.
+  server_output_paused: Arc<AtomicBool>,
   output_sender: broadcast::Sender<ButtplugServerMessage>,
+  scan_status: ScanStatusTracker,
+  pattern_player: PatternPlayer,
+  funscript_player: FunscriptPlayer,
+  command_scheduler: CommandScheduler,
+  device_groups: DeviceGroups,
+  device_stop_timeout: Duration,
+  protocol_stop_timeouts: HashMap<String, Duration>,
 }
 
 impl ServerDeviceManager {
@@ -244,11 +426,180 @@ impl ServerDeviceManager {
     convert_broadcast_receiver_to_stream(self.output_sender.subscribe())
   }
 
-  fn start_scanning(&self) -> ButtplugServerResultFuture {
+  /// Scanning state of each hardware communication manager, in the order they were added.
+  pub fn scanning_status(&self) -> Vec<CommunicationManagerScanStatus> {
+    self.scan_status.statuses()
+  }
+
+  /// Stream of scanning state changes of single hardware communication managers. Unlike
+  /// ScanningFinished, which is only sent once every manager is done, this shows which managers
+  /// are scanning, and which couldn't.
+  pub fn scanning_status_stream(&self) -> impl Stream<Item = CommunicationManagerScanStatus> {
+    convert_broadcast_receiver_to_stream(self.scan_status.subscribe())
+  }
+
+  /// Swap in a new device configuration without restarting the server. Connected devices are left
+  /// as is, changes only apply to devices found by later scans. Returns which communication
+  /// specifiers were added or removed.
+  pub fn reload_device_configuration(
+    &self,
+    dcm_builder: &DeviceConfigurationManagerBuilder,
+  ) -> Result<DeviceConfigurationDiff, ButtplugServerError> {
+    *self
+      .config_builder
+      .lock()
+      .expect("Lock is never held across a panic") = dcm_builder.clone();
+    self
+      .reload_remembered_configs()
+      .map_err(ButtplugServerError::DeviceConfigurationManagerError)
+  }
+
+  /// Reload the device configuration with the current remembered device overrides applied.
+  fn reload_remembered_configs(&self) -> Result<DeviceConfigurationDiff, ButtplugDeviceError> {
+    let mut dcm_builder = self
+      .config_builder
+      .lock()
+      .expect("Lock is never held across a panic")
+      .clone();
+    if let Some(store) = &self.remembered_devices {
+      store.apply_configs(&mut dcm_builder);
+    }
+    self.config_mgr.reload(&dcm_builder)
+  }
+
+  /// Devices that have connected before, if the manager was built with a [RememberedDeviceStore].
+  pub fn remembered_devices(&self) -> Vec<RememberedDevice> {
+    self
+      .remembered_devices
+      .as_ref()
+      .map(|store| store.devices())
+      .unwrap_or_default()
+  }
+
+  /// Stop remembering a device, dropping its configuration overrides. The device stays connected
+  /// if it currently is. Returns false if the device wasn't remembered.
+  pub fn forget_device(&self, address: &str) -> Result<bool, ButtplugServerError> {
+    let Some(store) = &self.remembered_devices else {
+      return Ok(false);
+    };
+    let had_config = store
+      .device(address)
+      .is_some_and(|device| device.config().is_some());
+    let forgotten = store
+      .forget(address)
+      .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+    if had_config {
+      self
+        .reload_remembered_configs()
+        .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+    }
+    Ok(forgotten)
+  }
+
+  /// Set or clear configuration overrides for a remembered device. Like other device configuration
+  /// changes, they only apply the next time the device connects. Returns false if the device
+  /// wasn't remembered.
+  pub fn set_remembered_device_config(
+    &self,
+    address: &str,
+    config: Option<UserDeviceConfig>,
+  ) -> Result<bool, ButtplugServerError> {
+    let Some(store) = &self.remembered_devices else {
+      return Ok(false);
+    };
+    if !store
+      .set_config(address, config)
+      .map_err(ButtplugServerError::DeviceConfigurationManagerError)?
+    {
+      return Ok(false);
+    }
+    self
+      .reload_remembered_configs()
+      .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+    Ok(true)
+  }
+
+  /// Give a connected device a display name, or clear it to go back to the device name. The name
+  /// is sent to clients in DeviceList right away, and in DeviceAdded whenever the device
+  /// reconnects. If the device is remembered, the name is also stored in its configuration
+  /// overrides, so it survives server restarts.
+  pub fn set_device_display_name(
+    &self,
+    device_index: u32,
+    display_name: Option<&str>,
+  ) -> Result<(), ButtplugError> {
+    let device = self
+      .devices
+      .get(&device_index)
+      .map(|device| device.value().clone())
+      .ok_or(ButtplugDeviceError::DeviceNotAvailable(device_index))?;
+    let display_name = display_name.map(|name| name.to_owned());
+    let address = device.identifier().address();
+    if let Some(store) = &self.remembered_devices {
+      if let Some(remembered) = store.device(address) {
+        let mut config = remembered.config().clone().unwrap_or_default();
+        config.set_display_name(display_name.clone());
+        store.set_config(address, Some(config))?;
+        self.reload_remembered_configs()?;
+      }
+    }
+    self
+      .display_names
+      .insert(address.clone(), display_name.clone());
+    device.set_display_name(display_name);
+    Ok(())
+  }
+
+  /// Tell the device manager the embedding app moved to the background. Scanning is suspended, as
+  /// platforms like iOS only allow filtered scans in the background, but connected devices and
+  /// their keepalives are left running. A scan that was running, or that's started while in the
+  /// background, resumes on [ServerDeviceManager::enter_foreground].
+  pub async fn enter_background(&self) {
+    if self
+      .device_command_sender
+      .send(DeviceManagerCommand::EnterBackground)
+      .await
+      .is_err()
+    {
+      warn!("Device manager event loop not running, cannot enter background.");
+    }
+  }
+
+  /// Tell the device manager the embedding app returned to the foreground, resuming any scan that
+  /// was suspended by [ServerDeviceManager::enter_background].
+  pub async fn enter_foreground(&self) {
+    if self
+      .device_command_sender
+      .send(DeviceManagerCommand::EnterForeground)
+      .await
+      .is_err()
+    {
+      warn!("Device manager event loop not running, cannot enter foreground.");
+    }
+  }
+
+  /// Addresses of the connected devices. Apps that may be terminated while in the background can
+  /// store these and pass them to [ServerDeviceManager::restore_devices] on relaunch. This stands
+  /// in for CoreBluetooth state restoration identifiers, which btleplug doesn't expose.
+  pub fn restoration_identifiers(&self) -> Vec<String> {
+    self
+      .devices
+      .iter()
+      .map(|device| device.value().identifier().address().clone())
+      .collect()
+  }
+
+  /// Reconnect devices from [ServerDeviceManager::restoration_identifiers], by scanning for only
+  /// those addresses. The scan finishes like any other, with ScanningFinished.
+  pub fn restore_devices(&self, identifiers: &[&str]) -> ButtplugServerResultFuture {
+    self.start_scanning(Some(ScanFilter::new(&[], &[], identifiers)))
+  }
+
+  fn start_scanning(&self, filter: Option<ScanFilter>) -> ButtplugServerResultFuture {
     let command_sender = self.device_command_sender.clone();
     async move {
       if command_sender
-        .send(DeviceManagerCommand::StartScanning)
+        .send(DeviceManagerCommand::StartScanning(filter))
         .await
         .is_err()
       {
@@ -274,19 +625,80 @@ impl ServerDeviceManager {
     .boxed()
   }
 
+  /// Stop all devices, replying with an error listing any devices that had to be disconnected.
   pub(crate) fn stop_all_devices(&self) -> ButtplugServerResultFuture {
-    let device_map = self.devices.clone();
-    // TODO This could use some error reporting.
+    let stop = self.stop_all_devices_with_result();
     async move {
-      let fut_vec: Vec<_> = device_map
-        .iter()
-        .map(|dev| {
-          let device = dev.value();
-          device.parse_message(message::StopDeviceCmd::new(1).into())
-        })
-        .collect();
-      future::join_all(fut_vec).await;
-      Ok(message::Ok::default().into())
+      let result = stop.await;
+      if result.force_disconnected.is_empty() {
+        Ok(message::Ok::default().into())
+      } else {
+        let indexes = result
+          .force_disconnected
+          .iter()
+          .map(|(index, _)| *index)
+          .collect();
+        Err(ButtplugDeviceError::DevicesForceDisconnected(indexes).into())
+      }
+    }
+    .boxed()
+  }
+
+  /// Stop all devices at once. Devices that return an error or don't acknowledge the stop within
+  /// the [device stop timeout](ServerDeviceManagerBuilder::device_stop_timeout) for their protocol
+  /// are disconnected, so a hung device can neither hold up the others nor keep running.
+  pub fn stop_all_devices_with_result(&self) -> BoxFuture<'static, StopAllDevicesResult> {
+    self.server_output_paused.store(true, Ordering::SeqCst);
+    self.pattern_player.stop_all();
+    self.funscript_player.stop_all();
+    self.command_scheduler.cancel_all();
+    // Build the stop futures now, so the stops take effect on each device as soon as the message
+    // is received, instead of whenever the returned future is first polled.
+    let fut_vec: Vec<_> = self
+      .devices
+      .iter()
+      .map(|dev| {
+        let device_index = *dev.key();
+        let device = dev.value().clone();
+        let stop_timeout = self
+          .protocol_stop_timeouts
+          .get(device.identifier().protocol())
+          .copied()
+          .unwrap_or(self.device_stop_timeout);
+        let stop = device.parse_message(message::StopDeviceCmd::new(device_index).into());
+        async move {
+          let result = select! {
+            result = stop.fuse() => result.map(|_| ()),
+            _ = util::sleep(stop_timeout).fuse() => {
+              Err(ButtplugDeviceError::DeviceStopTimeout(device_index).into())
+            }
+          };
+          if let Err(err) = &result {
+            warn!(
+              "Device {} did not stop, disconnecting it: {}",
+              device_index, err
+            );
+            // Disconnecting may hang on the same device, so don't make the other stops wait on it.
+            let disconnect = device.disconnect_with_reason(DeviceRemovedReason::DeviceError);
+            async_manager::spawn(async move {
+              if let Err(err) = disconnect.await {
+                error!("Could not disconnect device {}: {:?}", device_index, err);
+              }
+            });
+          }
+          (device_index, result)
+        }
+      })
+      .collect();
+    async move {
+      let mut stop_result = StopAllDevicesResult::default();
+      for (device_index, result) in future::join_all(fut_vec).await {
+        match result {
+          Ok(_) => stop_result.stopped.push(device_index),
+          Err(err) => stop_result.force_disconnected.push((device_index, err)),
+        }
+      }
+      stop_result
     }
     .boxed()
   }
@@ -297,14 +709,50 @@ impl ServerDeviceManager {
   ) -> ButtplugServerResultFuture {
     match self.devices.get(&device_msg.device_index()) {
       Some(device) => {
-        let fut = device.parse_message(device_msg);
-        // Create a future to run the message through the device, then handle adding the id to the result.
-        async move { fut.await }.boxed()
+        // A client stopping a device should also stop anything the server is playing on it.
+        if let ButtplugDeviceCommandMessageUnion::StopDeviceCmd(_) = device_msg {
+          self.pattern_player.stop(device_msg.device_index());
+          self.funscript_player.stop(device_msg.device_index());
+          self.command_scheduler.cancel(device_msg.device_index());
+        } else if matches!(
+          device_msg,
+          ButtplugDeviceCommandMessageUnion::ScalarCmd(_)
+            | ButtplugDeviceCommandMessageUnion::VibrateCmd(_)
+            | ButtplugDeviceCommandMessageUnion::SingleMotorVibrateCmd(_)
+            | ButtplugDeviceCommandMessageUnion::RotateCmd(_)
+            | ButtplugDeviceCommandMessageUnion::LinearCmd(_)
+            | ButtplugDeviceCommandMessageUnion::FleshlightLaunchFW12Cmd(_)
+            | ButtplugDeviceCommandMessageUnion::VorzeA10CycloneCmd(_)
+        ) {
+          // Sending output again means the client wants the devices running.
+          self.resume_server_output();
+        }
+        match device_msg.scheduled_time() {
+          Some(scheduled_time) => {
+            self
+              .command_scheduler
+              .schedule(device.value().clone(), device_msg, scheduled_time)
+          }
+          None => device.parse_message(device_msg),
+        }
       }
       None => ButtplugDeviceError::DeviceNotAvailable(device_msg.device_index()).into(),
     }
   }
 
+  /// Returns true while output the server drives by itself is paused, after all devices were
+  /// stopped.
+  #[cfg(feature = "audio-reactive")]
+  pub(crate) fn is_server_output_paused(&self) -> bool {
+    self.server_output_paused.load(Ordering::SeqCst)
+  }
+
+  /// Let output the server drives by itself, like audio reactive, start again after all devices
+  /// were stopped. Also happens on the next output command sent to a device.
+  pub fn resume_server_output(&self) {
+    self.server_output_paused.store(false, Ordering::SeqCst);
+  }
+
   fn parse_device_manager_message(
     &self,
     manager_msg: ButtplugDeviceManagerMessageUnion,
@@ -321,6 +769,7 @@ impl ServerDeviceManager {
               &dev.name(),
               &dev.display_name(),
               &None,
+              &Some(dev.connection_info()),
               dev.message_attributes().into(),
             )
           })
@@ -329,9 +778,57 @@ impl ServerDeviceManager {
         device_list.set_id(msg.id());
         future::ready(Ok(device_list.into())).boxed()
       }
+      ButtplugDeviceManagerMessageUnion::RequestScanningStatus(msg) => {
+        let mut status = ScanningStatus::new(
+          self
+            .scan_status
+            .statuses()
+            .iter()
+            .map(|status| status.into())
+            .collect(),
+        );
+        status.set_id(msg.id());
+        future::ready(Ok(status.into())).boxed()
+      }
+      ButtplugDeviceManagerMessageUnion::RequestDeviceLatency(msg) => {
+        let result = self
+          .device_latency_stats(msg.device_index())
+          .map(|stats| {
+            let mut reply = stats.to_message(msg.device_index());
+            reply.set_id(msg.id());
+            reply.into()
+          })
+          .ok_or_else(|| ButtplugDeviceError::DeviceNotAvailable(msg.device_index()).into());
+        future::ready(result).boxed()
+      }
       ButtplugDeviceManagerMessageUnion::StopAllDevices(_) => self.stop_all_devices(),
-      ButtplugDeviceManagerMessageUnion::StartScanning(_) => self.start_scanning(),
+      ButtplugDeviceManagerMessageUnion::StartScanning(msg) => {
+        self.start_scanning(msg.filter().clone())
+      }
       ButtplugDeviceManagerMessageUnion::StopScanning(_) => self.stop_scanning(),
+      ButtplugDeviceManagerMessageUnion::PlayPatternCmd(msg) => match Pattern::from_message(&msg) {
+        Ok(pattern) => self.play_pattern(msg.device_index(), pattern),
+        Err(err) => future::ready(Err(err)).boxed(),
+      },
+      ButtplugDeviceManagerMessageUnion::StopPatternCmd(msg) => {
+        self.stop_pattern(msg.device_index())
+      }
+      ButtplugDeviceManagerMessageUnion::AddDeviceToGroup(msg) => future::ready(
+        self
+          .add_device_to_group(msg.group_name(), msg.device_index())
+          .map(|_| message::Ok::new(msg.id()).into()),
+      )
+      .boxed(),
+      ButtplugDeviceManagerMessageUnion::RemoveDeviceFromGroup(msg) => future::ready(
+        self
+          .remove_device_from_group(msg.group_name(), msg.device_index())
+          .map(|_| message::Ok::new(msg.id()).into()),
+      )
+      .boxed(),
+      ButtplugDeviceManagerMessageUnion::GroupScalarCmd(msg) => {
+        let fut = self.send_group_scalar_cmd(&msg);
+        async move { fut.await.map(|result| result.into()) }.boxed()
+      }
     }
   }
 
@@ -341,13 +838,211 @@ impl ServerDeviceManager {
     }
     // If this is a device command message, just route it directly to the
     // device.
-    match ButtplugDeviceCommandMessageUnion::try_from(msg.clone()) {
-      Ok(device_msg) => self.parse_device_message(device_msg),
-      Err(_) => match ButtplugDeviceManagerMessageUnion::try_from(msg.clone()) {
-        Ok(manager_msg) => self.parse_device_manager_message(manager_msg),
-        Err(_) => ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into(),
-      },
+    match ButtplugClientMessageDestination::from(msg) {
+      ButtplugClientMessageDestination::Device(device_msg) => self.parse_device_message(device_msg),
+      ButtplugClientMessageDestination::DeviceManager(manager_msg) => {
+        self.parse_device_manager_message(manager_msg)
+      }
+      ButtplugClientMessageDestination::Server(msg) => {
+        ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into()
+      }
+    }
+  }
+
+  /// Play a [Pattern] on a device. The server handles timing and hardware updates until the pattern
+  /// finishes or is stopped. Any pattern already playing on the device is replaced.
+  pub fn play_pattern(&self, device_index: u32, pattern: Pattern) -> ButtplugServerResultFuture {
+    if !self.running.load(Ordering::SeqCst) {
+      return future::ready(Err(ButtplugUnknownError::DeviceManagerNotRunning.into())).boxed();
+    }
+    let device = match self.devices.get(&device_index) {
+      Some(device) => device.value().clone(),
+      None => return ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+    };
+    self.funscript_player.stop(device_index);
+    let result = self
+      .pattern_player
+      .play(device_index, device, pattern)
+      .map(|_| message::Ok::default().into());
+    future::ready(result).boxed()
+  }
+
+  /// Stop pattern playback on a device, and stop the device itself.
+  pub fn stop_pattern(&self, device_index: u32) -> ButtplugServerResultFuture {
+    self.pattern_player.stop(device_index);
+    self.parse_device_message(message::StopDeviceCmd::new(device_index).into())
+  }
+
+  /// Returns true if the server is currently playing a pattern on the device.
+  pub fn is_pattern_playing(&self, device_index: u32) -> bool {
+    self.pattern_player.is_playing(device_index)
+  }
+
+  /// Play a [Funscript] on a device, starting from the beginning. The server sends commands to the
+  /// device as playback reaches each action, until the script is stopped. Any script or pattern
+  /// already playing on the device is replaced.
+  pub fn play_funscript(
+    &self,
+    device_index: u32,
+    script: Funscript,
+    output: FunscriptOutput,
+  ) -> ButtplugServerResultFuture {
+    if !self.running.load(Ordering::SeqCst) {
+      return future::ready(Err(ButtplugUnknownError::DeviceManagerNotRunning.into())).boxed();
+    }
+    let device = match self.devices.get(&device_index) {
+      Some(device) => device.value().clone(),
+      None => return ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+    };
+    self.pattern_player.stop(device_index);
+    let result = self
+      .funscript_player
+      .play(device_index, device, script, output)
+      .map(|_| message::Ok::default().into());
+    future::ready(result).boxed()
+  }
+
+  /// Pause funscript playback on a device, stopping the device until playback is resumed.
+  pub fn pause_funscript(&self, device_index: u32) -> Result<(), ButtplugError> {
+    self.funscript_player.pause(device_index)
+  }
+
+  /// Resume paused funscript playback on a device.
+  pub fn resume_funscript(&self, device_index: u32) -> Result<(), ButtplugError> {
+    self.funscript_player.resume(device_index)
+  }
+
+  /// Move funscript playback on a device to a time in the script. Times past the end of the script
+  /// are clamped to the end.
+  pub fn seek_funscript(&self, device_index: u32, position: Duration) -> Result<(), ButtplugError> {
+    self.funscript_player.seek(device_index, position)
+  }
+
+  /// Set the funscript playback speed on a device, with 1.0 being the script's own timing. Rate
+  /// must be greater than 0.
+  pub fn set_funscript_rate(&self, device_index: u32, rate: f64) -> Result<(), ButtplugError> {
+    self.funscript_player.set_rate(device_index, rate)
+  }
+
+  /// Unload the funscript playing on a device, and stop the device itself.
+  pub fn stop_funscript(&self, device_index: u32) -> ButtplugServerResultFuture {
+    self.funscript_player.stop(device_index);
+    self.parse_device_message(message::StopDeviceCmd::new(device_index).into())
+  }
+
+  /// Current funscript playback state on a device, or None if no script is loaded.
+  pub fn funscript_state(&self, device_index: u32) -> Option<FunscriptPlaybackState> {
+    self.funscript_player.state(device_index)
+  }
+
+  /// Add a connected device to a named group, creating the group if it doesn't exist yet.
+  pub fn add_device_to_group(&self, group: &str, device_index: u32) -> Result<(), ButtplugError> {
+    if !self.devices.contains_key(&device_index) {
+      return Err(ButtplugDeviceError::DeviceNotAvailable(device_index).into());
     }
+    self.device_groups.add(group, device_index);
+    Ok(())
+  }
+
+  /// Remove a device from a named group. Groups are removed once their last device is.
+  pub fn remove_device_from_group(
+    &self,
+    group: &str,
+    device_index: u32,
+  ) -> Result<(), ButtplugError> {
+    self.device_groups.remove(group, device_index)
+  }
+
+  /// Indexes of all devices in a group, whether or not they're currently connected.
+  pub fn device_group_members(&self, group: &str) -> Result<Vec<u32>, ButtplugError> {
+    self.device_groups.members(group)
+  }
+
+  pub fn device_group_names(&self) -> Vec<String> {
+    self.device_groups.names()
+  }
+
+  /// Send scalar subcommands to every device in a group. Each device only gets the subcommands
+  /// that match its scalar actuators by index and actuator type, and devices with no matching
+  /// actuators are skipped. Errors from single devices are collected in the result instead of
+  /// failing the whole command.
+  pub fn send_group_scalar_cmd(
+    &self,
+    msg: &GroupScalarCmd,
+  ) -> BoxFuture<'static, Result<GroupCommandResult, ButtplugError>> {
+    if !self.running.load(Ordering::SeqCst) {
+      return future::ready(Err(ButtplugUnknownError::DeviceManagerNotRunning.into())).boxed();
+    }
+    let members = match self.device_groups.members(msg.group_name()) {
+      Ok(members) => members,
+      Err(err) => return future::ready(Err(err)).boxed(),
+    };
+    let mut result = GroupCommandResult::default();
+    result.set_id(msg.id());
+    let mut futs = vec![];
+    for device_index in members {
+      let Some(device) = self.devices.get(&device_index) else {
+        result.add_failure(
+          device_index,
+          ButtplugDeviceError::DeviceNotAvailable(device_index).into(),
+        );
+        continue;
+      };
+      match device_group::filter_scalar_cmd_for_device(device_index, &device, msg.scalars()) {
+        Some(device_msg) => {
+          let fut = device.parse_message(device_msg.into());
+          futs.push(async move { (device_index, fut.await) });
+        }
+        None => result.add_skipped(device_index),
+      }
+    }
+    async move {
+      for (device_index, device_result) in future::join_all(futs).await {
+        match device_result {
+          Ok(_) => result.add_success(device_index),
+          Err(err) => result.add_failure(device_index, err),
+        }
+      }
+      Ok(result)
+    }
+    .boxed()
+  }
+
+  /// Rolling latency stats for output commands sent to a device, or None if there's no device at
+  /// that index.
+  pub fn device_latency_stats(&self, device_index: u32) -> Option<DeviceLatencyStats> {
+    self
+      .devices
+      .get(&device_index)
+      .map(|device| device.value().latency_stats())
+  }
+
+  /// Clock that scheduled command times are measured on. See the
+  /// [choreography](super::choreography) module for details.
+  pub fn clock(&self) -> ServerClock {
+    self.command_scheduler.clock()
+  }
+
+  /// Names of all protocols the server has implementations for.
+  pub fn protocol_names(&self) -> Vec<String> {
+    self.config_mgr.protocol_names()
+  }
+
+  /// Which commands the named protocol's implementation handles, as declared by the protocol. See
+  /// the [capabilities](super::protocol::capabilities) module.
+  pub fn protocol_capabilities(
+    &self,
+    protocol: &str,
+  ) -> Result<ProtocolCapabilities, ButtplugDeviceError> {
+    self
+      .config_mgr
+      .protocol_identifier_factory(protocol)
+      .map(|factory| factory.capabilities())
+      .ok_or_else(|| ButtplugDeviceError::ProtocolNotImplemented(protocol.to_owned()))
+  }
+
+  pub(crate) fn devices(&self) -> &DashMap<u32, Arc<ServerDevice>> {
+    &self.devices
   }
 
   pub fn device_info(&self, index: u32) -> Option<ServerDeviceInfo> {
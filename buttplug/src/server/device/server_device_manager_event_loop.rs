@@ -6,19 +6,42 @@
 // for full license information.
 
 use crate::{
-  core::message::{ButtplugServerMessage, DeviceAdded, DeviceRemoved, ScanningFinished},
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ButtplugDeviceMessage,
+      ButtplugServerDeviceMessage,
+      ButtplugServerMessage,
+      DeviceAdded,
+      DeviceRemoved,
+      ScanFilter,
+      ScanningFinished,
+    },
+  },
   server::device::{
+    battery::BatteryPollingPolicy,
     configuration::DeviceConfigurationManager,
-    hardware::communication::{HardwareCommunicationManager, HardwareCommunicationManagerEvent},
+    connection_scheduler::ConnectionScheduler,
+    hardware::{
+      communication::{HardwareCommunicationManager, HardwareCommunicationManagerEvent},
+      recording::HardwareTrafficRecorder,
+      HardwareConnector,
+    },
+    protocol::ProtocolInitializationTimeouts,
+    remembered::{RememberedDevice, RememberedDeviceStore},
+    safety::SafetyPolicy,
+    scanning::{ScanState, ScanStatusTracker},
     server_device::build_server_device,
     ServerDevice,
     ServerDeviceEvent,
+    ServerDeviceIdentifier,
   },
-  util::async_manager,
+  util::{self, async_manager},
 };
 use dashmap::{DashMap, DashSet};
 use futures::{future, FutureExt, StreamExt};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 use tracing;
@@ -26,6 +49,128 @@ use tracing_futures::Instrument;
 
 use super::server_device_manager::DeviceManagerCommand;
 
+/// Time to wait before retrying a failed connection.
+const CONNECTION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Managers that only find devices on transports the scan filter doesn't include have nothing to
+/// look for, so they aren't started.
+fn scan_wanted(filter: &Option<ScanFilter>, mgr: &dyn HardwareCommunicationManager) -> bool {
+  match (filter, mgr.transport()) {
+    (Some(filter), Some(transport)) => filter.matches_transport(transport),
+    _ => true,
+  }
+}
+
+/// A device waiting to be connected.
+struct PendingConnection {
+  address: String,
+  connector: Box<dyn HardwareConnector>,
+  /// Protocols the device may be connected with. Protocol specializers can only be used once, so
+  /// they're recreated from the device configuration for every attempt.
+  protocols: Vec<String>,
+  span: tracing::Span,
+}
+
+/// Everything needed to connect devices, shared by all connection tasks.
+#[derive(Clone)]
+struct DeviceConnector {
+  device_config_manager: Arc<DeviceConfigurationManager>,
+  device_event_sender: mpsc::Sender<ServerDeviceEvent>,
+  connecting_devices: Arc<DashSet<String>>,
+  /// Recorder handed to every new device, if hardware traffic recording is on.
+  traffic_recorder: Option<HardwareTrafficRecorder>,
+  /// Safety limits handed to every new device.
+  safety_policy: Arc<SafetyPolicy>,
+  /// Battery polling settings handed to every new device, if polling is on.
+  battery_polling: Option<Arc<BatteryPollingPolicy>>,
+  /// Time each protocol has to finish its handshake.
+  initialization_timeouts: Arc<ProtocolInitializationTimeouts>,
+  scheduler: ConnectionScheduler<PendingConnection>,
+  /// Times a failed connection is retried before giving up on the device.
+  retries: u32,
+}
+
+impl DeviceConnector {
+  /// Queue a device for connection, starting it right away if a connection slot is free.
+  fn queue(&self, transport: &str, pending: PendingConnection) {
+    let Some(pending) = self.scheduler.enqueue(transport, pending) else {
+      debug!(
+        "Connection limit reached, {} devices waiting to connect.",
+        self.scheduler.queued()
+      );
+      return;
+    };
+    let connector = self.clone();
+    // Keep using the slot for queued devices until there are none left.
+    async_manager::spawn(async move {
+      let mut next = Some(pending);
+      while let Some(pending) = next {
+        let span = pending.span.clone();
+        connector.connect(pending).instrument(span).await;
+        next = connector.scheduler.finish();
+      }
+    });
+  }
+
+  async fn connect(&self, mut pending: PendingConnection) {
+    let mut attempt = 0;
+    loop {
+      let mut protocol_specializers = self
+        .device_config_manager
+        .protocol_specializers(&pending.connector.specifier());
+      protocol_specializers.retain(|specializer| {
+        pending
+          .protocols
+          .iter()
+          .any(|protocol| protocol == specializer.protocol())
+      });
+      match build_server_device(
+        self.device_config_manager.clone(),
+        pending.connector.as_mut(),
+        protocol_specializers,
+        self.traffic_recorder.clone(),
+        self.safety_policy.clone(),
+        self.battery_polling.clone(),
+        self.initialization_timeouts.clone(),
+      )
+      .await
+      {
+        Ok(device) => {
+          if self
+            .device_event_sender
+            .send(ServerDeviceEvent::Connected(Arc::new(device)))
+            .await
+            .is_err()
+          {
+            error!(
+              "Device manager disappeared before connection established, device will be dropped."
+            );
+          }
+          break;
+        }
+        // Configuration won't be any different next time around, so don't bother retrying.
+        Err(e @ ButtplugDeviceError::DeviceConfigurationError(_)) => {
+          error!("Device errored while trying to connect: {}", e);
+          break;
+        }
+        Err(e) if attempt < self.retries => {
+          attempt += 1;
+          warn!(
+            "Device errored while trying to connect, retrying ({}/{}): {}",
+            attempt, self.retries, e
+          );
+          util::sleep(CONNECTION_RETRY_DELAY).await;
+        }
+        Err(e) => {
+          error!("Device errored while trying to connect: {}", e);
+          break;
+        }
+      }
+    }
+    self.connecting_devices.remove(&pending.address);
+  }
+}
+
 pub(super) struct ServerDeviceManagerEventLoop {
   comm_managers: Vec<Box<dyn HardwareCommunicationManager>>,
   device_config_manager: Arc<DeviceConfigurationManager>,
@@ -51,22 +196,59 @@ pub(super) struct ServerDeviceManagerEventLoop {
   connecting_devices: Arc<DashSet<String>>,
   /// Cancellation token for the event loop
   loop_cancellation_token: CancellationToken,
+  /// Scanning state of each comm manager.
+  scan_status: ScanStatusTracker,
+  /// Filter of the current scan, if it was limited to certain devices.
+  scan_filter: Option<ScanFilter>,
+  /// Devices that have connected before, if they're being remembered.
+  remembered_devices: Option<RememberedDeviceStore>,
+  /// Display names set while the server is running, keyed by device address.
+  display_names: Arc<DashMap<String, Option<String>>>,
+  /// Connects found devices, a limited number at a time.
+  device_connector: DeviceConnector,
+  /// True while the embedding app is in the background.
+  in_background: bool,
+  /// Scan that was running or requested while in the background, resumed on entering the
+  /// foreground. The inner value is the scan filter.
+  suspended_scan: Option<Option<ScanFilter>>,
 }
 
 impl ServerDeviceManagerEventLoop {
+  #[allow(clippy::too_many_arguments)]
   pub fn new(
     comm_managers: Vec<Box<dyn HardwareCommunicationManager>>,
-    device_config_manager: DeviceConfigurationManager,
+    device_config_manager: Arc<DeviceConfigurationManager>,
     device_map: Arc<DashMap<u32, Arc<ServerDevice>>>,
     loop_cancellation_token: CancellationToken,
     server_sender: broadcast::Sender<ButtplugServerMessage>,
     device_comm_receiver: mpsc::Receiver<HardwareCommunicationManagerEvent>,
     device_command_receiver: mpsc::Receiver<DeviceManagerCommand>,
+    traffic_recorder: Option<HardwareTrafficRecorder>,
+    safety_policy: Arc<SafetyPolicy>,
+    battery_polling: Option<Arc<BatteryPollingPolicy>>,
+    initialization_timeouts: Arc<ProtocolInitializationTimeouts>,
+    scan_status: ScanStatusTracker,
+    remembered_devices: Option<RememberedDeviceStore>,
+    display_names: Arc<DashMap<String, Option<String>>>,
+    max_parallel_connections: usize,
+    connection_retries: u32,
   ) -> Self {
     let (device_event_sender, device_event_receiver) = mpsc::channel(256);
+    let connecting_devices = Arc::new(DashSet::new());
+    let device_connector = DeviceConnector {
+      device_config_manager: device_config_manager.clone(),
+      device_event_sender: device_event_sender.clone(),
+      connecting_devices: connecting_devices.clone(),
+      traffic_recorder,
+      safety_policy,
+      battery_polling,
+      initialization_timeouts,
+      scheduler: ConnectionScheduler::new(max_parallel_connections),
+      retries: connection_retries,
+    };
     Self {
       comm_managers,
-      device_config_manager: Arc::new(device_config_manager),
+      device_config_manager,
       server_sender,
       device_map,
       device_comm_receiver,
@@ -75,8 +257,15 @@ impl ServerDeviceManagerEventLoop {
       device_command_receiver,
       scanning_bringup_in_progress: false,
       scanning_started: false,
-      connecting_devices: Arc::new(DashSet::new()),
+      connecting_devices,
       loop_cancellation_token,
+      scan_status,
+      scan_filter: None,
+      remembered_devices,
+      display_names,
+      device_connector,
+      in_background: false,
+      suspended_scan: None,
     }
   }
 
@@ -88,27 +277,77 @@ impl ServerDeviceManagerEventLoop {
     false
   }
 
-  async fn handle_start_scanning(&mut self) {
+  async fn handle_start_scanning(&mut self, filter: Option<ScanFilter>) {
+    if self.in_background {
+      debug!("In background, holding scan until foreground.");
+      self.suspended_scan = Some(filter);
+      return;
+    }
     if self.scanning_status() || self.scanning_bringup_in_progress {
       debug!("System already scanning, ignoring new scanning request");
       return;
     }
 
     info!("No scan currently in progress, starting new scan.");
+    if !self
+      .comm_managers
+      .iter()
+      .any(|mgr| scan_wanted(&filter, mgr.as_ref()))
+    {
+      warn!("No hardware comm managers match the scan filter, finishing scan immediately.");
+      if self
+        .server_sender
+        .send(ScanningFinished::default().into())
+        .is_err()
+      {
+        info!("Server disappeared, exiting loop.");
+      }
+      return;
+    }
+    self.scan_filter = filter;
     self.scanning_bringup_in_progress = true;
     self.scanning_started = true;
+    let scan_filter = &self.scan_filter;
     let fut_vec: Vec<_> = self
       .comm_managers
       .iter_mut()
-      .map(|guard| guard.start_scanning())
+      .filter(|mgr| scan_wanted(scan_filter, mgr.as_ref()))
+      .map(|guard| {
+        let name = guard.name();
+        let can_scan = guard.can_scan();
+        let fut = guard.start_scanning();
+        async move { (name, can_scan, fut.await) }
+      })
       .collect();
-    // TODO If start_scanning fails anywhere, this will ignore it. We should maybe at least log?
-    future::join_all(fut_vec).await;
+    // A manager failing to scan shouldn't stop the others, it's only reported in its status.
+    for (name, can_scan, result) in future::join_all(fut_vec).await {
+      let state = match result {
+        Err(err) => ScanState::Failed(err),
+        Ok(_) if !can_scan => ScanState::Unavailable,
+        Ok(_) => ScanState::Scanning,
+      };
+      self.scan_status.set_state(name, state);
+    }
+    // Some managers may have already finished while the others were still starting up.
+    self.update_finished_scans();
     debug!("Scanning started for all hardware comm managers.");
     self.scanning_bringup_in_progress = false;
   }
 
   async fn handle_stop_scanning(&mut self) {
+    if self.in_background {
+      // The scan was already stopped when entering the background, so only the client still thinks
+      // it's running.
+      if self.suspended_scan.take().is_some()
+        && self
+          .server_sender
+          .send(ScanningFinished::default().into())
+          .is_err()
+      {
+        info!("Server disappeared, exiting loop.");
+      }
+      return;
+    }
     let fut_vec: Vec<_> = self
       .comm_managers
       .iter_mut()
@@ -116,6 +355,80 @@ impl ServerDeviceManagerEventLoop {
       .collect();
     // TODO If stop_scanning fails anywhere, this will ignore it. We should maybe at least log?
     future::join_all(fut_vec).await;
+    self.update_finished_scans();
+    self.scan_filter = None;
+  }
+
+  async fn handle_enter_background(&mut self) {
+    if self.in_background {
+      return;
+    }
+    info!("Entering background, suspending scanning.");
+    self.in_background = true;
+    if self.scanning_started || self.scanning_bringup_in_progress {
+      self.suspended_scan = Some(self.scan_filter.clone());
+      // Clients still think the scan is running, so managers finishing mustn't send
+      // ScanningFinished.
+      self.scanning_started = false;
+      let fut_vec: Vec<_> = self
+        .comm_managers
+        .iter_mut()
+        .map(|guard| guard.stop_scanning())
+        .collect();
+      future::join_all(fut_vec).await;
+      self.update_finished_scans();
+      self.scan_filter = None;
+    }
+    let fut_vec: Vec<_> = self
+      .comm_managers
+      .iter_mut()
+      .map(|guard| {
+        let name = guard.name();
+        let fut = guard.enter_background();
+        async move { (name, fut.await) }
+      })
+      .collect();
+    for (name, result) in future::join_all(fut_vec).await {
+      if let Err(err) = result {
+        warn!("{} failed to enter background: {:?}", name, err);
+      }
+    }
+  }
+
+  async fn handle_enter_foreground(&mut self) {
+    if !self.in_background {
+      return;
+    }
+    info!("Entering foreground.");
+    self.in_background = false;
+    let fut_vec: Vec<_> = self
+      .comm_managers
+      .iter_mut()
+      .map(|guard| {
+        let name = guard.name();
+        let fut = guard.enter_foreground();
+        async move { (name, fut.await) }
+      })
+      .collect();
+    for (name, result) in future::join_all(fut_vec).await {
+      if let Err(err) = result {
+        warn!("{} failed to enter foreground: {:?}", name, err);
+      }
+    }
+    if let Some(filter) = self.suspended_scan.take() {
+      info!("Resuming scan suspended in background.");
+      self.handle_start_scanning(filter).await;
+    }
+  }
+
+  /// Mark managers that were scanning, but aren't anymore, as idle. Failures are kept until the
+  /// next scan, so they can still be shown after scanning is over.
+  fn update_finished_scans(&self) {
+    for mgr in self.comm_managers.iter() {
+      if !mgr.scanning_status() && self.scan_status.state(mgr.name()) == Some(ScanState::Scanning) {
+        self.scan_status.set_state(mgr.name(), ScanState::Idle);
+      }
+    }
   }
 
   async fn handle_device_communication(&mut self, event: HardwareCommunicationManagerEvent) {
@@ -124,6 +437,7 @@ impl ServerDeviceManagerEventLoop {
         debug!(
           "System signaled that scanning was finished, check to see if all managers are finished."
         );
+        self.update_finished_scans();
         if self.scanning_bringup_in_progress {
           debug!("Hardware Comm Manager finished before scanning was fully started, continuing event loop.");
           return;
@@ -131,6 +445,7 @@ impl ServerDeviceManagerEventLoop {
         if !self.scanning_status() && self.scanning_started {
           debug!("All managers finished, emitting ScanningFinished");
           self.scanning_started = false;
+          self.scan_filter = None;
           if self
             .server_sender
             .send(ScanningFinished::default().into())
@@ -146,6 +461,14 @@ impl ServerDeviceManagerEventLoop {
         creator,
       } => {
         info!("Device {} ({}) found.", name, address);
+        if let Some(filter) = &self.scan_filter {
+          if !filter.matches_address(&address)
+            || !filter.matches_transport(creator.specifier().transport())
+          {
+            debug!("Device {} doesn't match scan filter, ignoring.", address);
+            return;
+          }
+        }
         // Make sure the device isn't on the deny list, or is on the allow list if anything is on it.
         if !self.device_config_manager.address_allowed(&address) {
           return;
@@ -176,9 +499,32 @@ impl ServerDeviceManagerEventLoop {
         //
         // We used to do this in build_server_device, but we shouldn't mark devices as actually
         // connecting until after this happens, so we're moving it back here.
-        let protocol_specializers = self
+        let mut protocol_specializers = self
           .device_config_manager
           .protocol_specializers(&creator.specifier());
+        if let Some(filter) = &self.scan_filter {
+          protocol_specializers
+            .retain(|specializer| filter.matches_protocol(specializer.protocol()));
+        }
+        // Devices we've connected before get the protocol they were identified with last time, as
+        // long as it still matches.
+        if let Some(remembered) = self
+          .remembered_devices
+          .as_ref()
+          .and_then(|store| store.device(&address))
+        {
+          let protocol = &remembered.identifier().protocol;
+          if protocol_specializers
+            .iter()
+            .any(|specializer| specializer.protocol() == protocol)
+          {
+            info!(
+              "Reconnecting remembered device {} with protocol {}.",
+              address, protocol
+            );
+            protocol_specializers.retain(|specializer| specializer.protocol() == protocol);
+          }
+        }
 
         // If we have no identifiers, then there's nothing to do here. Throw an error.
         if protocol_specializers.is_empty() {
@@ -206,32 +552,25 @@ impl ServerDeviceManagerEventLoop {
 
         self.connecting_devices.insert(address.clone());
 
-        let device_event_sender_clone = self.device_event_sender.clone();
-
-        let device_config_manager = self.device_config_manager.clone();
-        let connecting_devices = self.connecting_devices.clone();
         let span = info_span!(
           "device creation",
           name = tracing::field::display(name),
-          address = tracing::field::display(address.clone())
+          address = tracing::field::display(address.clone()),
+          protocol = tracing::field::Empty
+        );
+        let transport = creator.specifier().transport();
+        self.device_connector.queue(
+          transport,
+          PendingConnection {
+            address,
+            connector: creator,
+            protocols: protocol_specializers
+              .iter()
+              .map(|specializer| specializer.protocol().to_owned())
+              .collect(),
+            span,
+          },
         );
-
-        async_manager::spawn(async move {
-          match build_server_device(device_config_manager, creator, protocol_specializers).await {
-            Ok(device) => {
-              if device_event_sender_clone
-                .send(ServerDeviceEvent::Connected(Arc::new(device)))
-                .await
-                .is_err() {
-                error!("Device manager disappeared before connection established, device will be dropped.");
-              }
-            },
-            Err(e) => {
-              error!("Device errored while trying to connect: {}", e);
-            }
-          }
-          connecting_devices.remove(&address);
-        }.instrument(span));
       }
     }
   }
@@ -283,14 +622,32 @@ impl ServerDeviceManagerEventLoop {
           }
         });
 
+        device.start_safety_watchdog();
+        device.start_battery_monitor();
+
+        if let Some(display_name) = self.display_names.get(device.identifier().address()) {
+          device.set_display_name(display_name.value().clone());
+        }
+
         info!("Assigning index {} to {}", device_index, device.name());
         let device_added_message = DeviceAdded::new(
           device_index,
           &device.name(),
           &device.display_name(),
           &None,
+          &Some(device.connection_info()),
           &device.message_attributes().into(),
         );
+        if let Some(store) = &self.remembered_devices {
+          let remembered = RememberedDevice::new(
+            device.identifier(),
+            &device.name(),
+            device.connection_info().transport(),
+          );
+          if let Err(err) = store.remember(remembered) {
+            error!("Cannot remember device {}: {}", device.name(), err);
+          }
+        }
         self.device_map.insert(device_index, device);
         // After that, we can send out to the server's event listeners to let
         // them know a device has been added.
@@ -302,36 +659,60 @@ impl ServerDeviceManagerEventLoop {
           debug!("Server not currently available, dropping Device Added event.");
         }
       }
-      ServerDeviceEvent::Disconnected(identifier) => {
-        let mut device_index = None;
-        for device_pair in self.device_map.iter() {
-          if *device_pair.value().identifier() == identifier {
-            device_index = Some(*device_pair.key());
-            break;
-          }
-        }
-        if let Some(device_index) = device_index {
+      ServerDeviceEvent::Disconnected(identifier, reason, will_reconnect) => {
+        if let Some(device_index) = self.device_index(&identifier) {
           self
             .device_map
             .remove(&device_index)
             .expect("Remove will always work.");
+          info!(
+            "Device {} removed ({:?}, will reconnect: {}).",
+            device_index, reason, will_reconnect
+          );
           if self
             .server_sender
-            .send(DeviceRemoved::new(device_index).into())
+            .send(DeviceRemoved::new_with_reason(device_index, reason, will_reconnect).into())
             .is_err()
           {
             debug!("Server not currently available, dropping Device Removed event.");
           }
         }
       }
-      ServerDeviceEvent::Notification(_, message) => {
+      ServerDeviceEvent::Notification(identifier, mut message) => {
+        // Devices don't know their index, so notifications come in addressed to device 0.
+        if let Some(device_index) = self.device_index(&identifier) {
+          match &mut message {
+            ButtplugServerDeviceMessage::RawReading(msg) => msg.set_device_index(device_index),
+            ButtplugServerDeviceMessage::SensorReading(msg) => msg.set_device_index(device_index),
+          }
+        }
         if self.server_sender.send(message.into()).is_err() {
           debug!("Server not currently available, dropping Device Added event.");
         }
       }
+      ServerDeviceEvent::BatteryLow(identifier, percent) => {
+        let Some(device_index) = self.device_index(&identifier) else {
+          return;
+        };
+        warn!("Device {} battery is low ({}%).", device_index, percent);
+        let warning = message::Error::from(ButtplugError::from(
+          ButtplugDeviceError::DeviceBatteryLow(device_index, percent),
+        ));
+        if self.server_sender.send(warning.into()).is_err() {
+          debug!("Server not currently available, dropping Battery Low event.");
+        }
+      }
     }
   }
 
+  fn device_index(&self, identifier: &ServerDeviceIdentifier) -> Option<u32> {
+    self
+      .device_map
+      .iter()
+      .find(|device_pair| device_pair.value().identifier() == identifier)
+      .map(|device_pair| *device_pair.key())
+  }
+
   pub async fn run(&mut self) {
     debug!("Starting Device Manager Loop");
     loop {
@@ -357,8 +738,12 @@ impl ServerDeviceManagerEventLoop {
           if let Some(msg) = device_command_msg {
             trace!("Got device command message {:?}", msg);
             match msg {
-              DeviceManagerCommand::StartScanning => self.handle_start_scanning().await,
+              DeviceManagerCommand::StartScanning(filter) => {
+                self.handle_start_scanning(filter).await
+              }
               DeviceManagerCommand::StopScanning => self.handle_stop_scanning().await,
+              DeviceManagerCommand::EnterBackground => self.handle_enter_background().await,
+              DeviceManagerCommand::EnterForeground => self.handle_enter_foreground().await,
             }
           } else {
             debug!("Channel to Device Manager frontend dropped, exiting event loop.");
@@ -0,0 +1,135 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Lovense toy command parsing.
+
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Highest Vibrate/Rotate level in the Lovense command set.
+pub const LOVENSE_MAX_LEVEL: u32 = 20;
+/// Highest Air:Level level in the Lovense command set.
+pub const LOVENSE_MAX_AIR_LEVEL: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LovenseCommandError {
+  #[error("Unknown command {0}")]
+  UnknownCommand(String),
+  #[error("Invalid level {0}")]
+  InvalidLevel(String),
+}
+
+/// Commands from the Lovense toy command set, as sent over BLE or the serial dongle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LovenseCommand {
+  /// `DeviceType;`
+  DeviceType,
+  /// `Battery;`
+  Battery,
+  /// `Status:1;`
+  Status,
+  /// `PowerOff;`
+  PowerOff,
+  /// `Vibrate:N;` sets all vibrators, `VibrateM:N;` only the Mth (starting at 1).
+  Vibrate { motor: Option<u32>, level: u32 },
+  /// `Rotate:N;`
+  Rotate { level: u32 },
+  /// `RotateChange;`
+  RotateChange,
+  /// `Air:Level:N;`
+  AirLevel { level: u32 },
+}
+
+fn parse_level(level: &str, max: u32) -> Result<u32, LovenseCommandError> {
+  level
+    .parse()
+    .ok()
+    .filter(|level| *level <= max)
+    .ok_or_else(|| LovenseCommandError::InvalidLevel(level.to_owned()))
+}
+
+impl FromStr for LovenseCommand {
+  type Err = LovenseCommandError;
+
+  /// Parse a single command, without the trailing `;`.
+  fn from_str(command: &str) -> Result<Self, Self::Err> {
+    let parts: Vec<&str> = command.trim().split(':').collect();
+    match parts.as_slice() {
+      ["DeviceType"] => Ok(Self::DeviceType),
+      ["Battery"] => Ok(Self::Battery),
+      ["Status", _] => Ok(Self::Status),
+      ["PowerOff"] => Ok(Self::PowerOff),
+      ["RotateChange"] => Ok(Self::RotateChange),
+      ["Rotate", level] => Ok(Self::Rotate {
+        level: parse_level(level, LOVENSE_MAX_LEVEL)?,
+      }),
+      ["Air", "Level", level] => Ok(Self::AirLevel {
+        level: parse_level(level, LOVENSE_MAX_AIR_LEVEL)?,
+      }),
+      [vibrate, level] if vibrate.starts_with("Vibrate") => {
+        let motor = match &vibrate["Vibrate".len()..] {
+          "" => None,
+          motor => Some(
+            motor
+              .parse::<u32>()
+              .ok()
+              .filter(|motor| *motor > 0)
+              .ok_or_else(|| LovenseCommandError::UnknownCommand(command.to_owned()))?
+              - 1,
+          ),
+        };
+        Ok(Self::Vibrate {
+          motor,
+          level: parse_level(level, LOVENSE_MAX_LEVEL)?,
+        })
+      }
+      _ => Err(LovenseCommandError::UnknownCommand(command.to_owned())),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_lovense_commands() {
+    assert_eq!("DeviceType".parse(), Ok(LovenseCommand::DeviceType));
+    assert_eq!("Status:1".parse(), Ok(LovenseCommand::Status));
+    assert_eq!(
+      "Vibrate:20".parse(),
+      Ok(LovenseCommand::Vibrate {
+        motor: None,
+        level: 20
+      })
+    );
+    assert_eq!(
+      "Vibrate2:5".parse(),
+      Ok(LovenseCommand::Vibrate {
+        motor: Some(1),
+        level: 5
+      })
+    );
+    assert_eq!(
+      "Air:Level:3".parse(),
+      Ok(LovenseCommand::AirLevel { level: 3 })
+    );
+    assert_eq!(
+      "Vibrate:21".parse::<LovenseCommand>(),
+      Err(LovenseCommandError::InvalidLevel("21".to_owned()))
+    );
+    assert_eq!(
+      "Vibrate0:1".parse::<LovenseCommand>(),
+      Err(LovenseCommandError::UnknownCommand("Vibrate0:1".to_owned()))
+    );
+    assert_eq!(
+      "Air:Level:6".parse::<LovenseCommand>(),
+      Err(LovenseCommandError::InvalidLevel("6".to_owned()))
+    );
+    assert!("Thrusting:5".parse::<LovenseCommand>().is_err());
+  }
+}
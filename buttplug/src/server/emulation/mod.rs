@@ -0,0 +1,358 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Device emulation
+//!
+//! Emulation turns the usual flow around: instead of the server talking to devices, applications
+//! talk to the server as if it was a device. This is mostly useful for testing apps written against
+//! vendor SDKs with whatever hardware is connected to the server.
+//!
+//! The Lovense emulator accepts the Lovense toy command set (`Vibrate:10;`, `Battery;`, etc...) on
+//! a local TCP port, translates commands into Buttplug messages for the selected devices, and
+//! answers the way a Lovense toy would. None of the platform Bluetooth libraries we use can act as
+//! a BLE peripheral, so to look like a serial device, bridge the port to a pseudo-terminal, i.e.
+//! `socat pty,link=/tmp/ttyLovense,raw tcp:127.0.0.1:9002`.
+//!
+//! The emulator is configured with a [LovenseEmulatorConfig]:
+//!
+//! ```json
+//! {
+//!   "listen-address": "127.0.0.1:9002",
+//!   "device-name": "Lovense Hush"
+//! }
+//! ```
+//!
+//! If neither `device-name` nor `device-index` are given, commands go to all devices. Vibrate,
+//! Rotate and Air:Level commands are sent to the Vibrate, rotation and Constrict actuators of the
+//! selected devices, PowerOff stops them, and Battery reads the first battery sensor found.
+
+pub mod lovense;
+
+pub use lovense::{LovenseCommand, LovenseCommandError};
+
+use super::device::{ServerDevice, ServerDeviceManager};
+use crate::{
+  core::message::{
+    ActuatorType,
+    ButtplugDeviceCommandMessageUnion,
+    ButtplugServerMessage,
+    RotateCmd,
+    RotationSubcommand,
+    ScalarCmd,
+    ScalarSubcommand,
+    SensorReadCmd,
+    SensorType,
+    StopDeviceCmd,
+  },
+  util::async_manager,
+};
+use futures::{future, select, FutureExt};
+use getset::Getters;
+use lovense::{LOVENSE_MAX_AIR_LEVEL, LOVENSE_MAX_LEVEL};
+use serde::{Deserialize, Serialize};
+use std::{
+  net::{SocketAddr, TcpListener},
+  sync::Arc,
+};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+};
+use tokio_util::sync::CancellationToken;
+
+fn default_device_type() -> String {
+  // Hush, firmware version 11, with a made up address.
+  "Z:11:0082059AD3BD".to_owned()
+}
+
+/// Lovense emulator configuration. See the [module documentation](self) for the JSON format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters)]
+#[serde(rename_all = "kebab-case")]
+#[getset(get = "pub")]
+pub struct LovenseEmulatorConfig {
+  /// Local address to listen for connections on, e.g. `127.0.0.1:9002`.
+  listen_address: String,
+  /// Only send to devices with this name.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  device_name: Option<String>,
+  /// Only send to the device at this index.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  device_index: Option<u32>,
+  /// Answer to `DeviceType;`, in Lovense's type:firmware:address format.
+  #[serde(default = "default_device_type")]
+  device_type: String,
+}
+
+impl LovenseEmulatorConfig {
+  pub fn new(listen_address: &str) -> Self {
+    Self {
+      listen_address: listen_address.to_owned(),
+      device_name: None,
+      device_index: None,
+      device_type: default_device_type(),
+    }
+  }
+
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    serde_json::from_str(json)
+  }
+
+  fn selects_device(&self, index: u32, name: &str) -> bool {
+    self
+      .device_index
+      .is_none_or(|device_index| device_index == index)
+      && self
+        .device_name
+        .as_ref()
+        .is_none_or(|device_name| device_name == name)
+  }
+}
+
+/// Rotation state for a connection, since Lovense rotation direction is toggled instead of set.
+struct RotationState {
+  level: u32,
+  clockwise: bool,
+}
+
+/// Build one message per selected device, and send them all.
+async fn send_to_devices<F>(
+  config: &LovenseEmulatorConfig,
+  device_manager: &ServerDeviceManager,
+  build_message: F,
+) -> bool
+where
+  F: Fn(u32, &ServerDevice) -> Option<ButtplugDeviceCommandMessageUnion>,
+{
+  let futures: Vec<_> = device_manager
+    .devices()
+    .iter()
+    .filter(|device| config.selects_device(*device.key(), &device.name()))
+    .filter_map(|device| {
+      build_message(*device.key(), device.value()).map(|message| device.parse_message(message))
+    })
+    .collect();
+  let mut success = true;
+  for result in future::join_all(futures).await {
+    if let Err(err) = result {
+      error!("Lovense emulator could not update device: {:?}", err);
+      success = false;
+    }
+  }
+  success
+}
+
+fn scalar_message(
+  index: u32,
+  device: &ServerDevice,
+  actuator_type: ActuatorType,
+  motor: Option<u32>,
+  scalar: f64,
+) -> Option<ButtplugDeviceCommandMessageUnion> {
+  let attrs = device.message_attributes().scalar_cmd().clone()?;
+  let subcommands: Vec<ScalarSubcommand> = attrs
+    .iter()
+    .enumerate()
+    .filter(|(_, attr)| *attr.actuator_type() == actuator_type)
+    .enumerate()
+    .filter(|(motor_index, _)| motor.is_none_or(|motor| motor == *motor_index as u32))
+    .map(|(_, (actuator_index, _))| {
+      ScalarSubcommand::new(actuator_index as u32, scalar, actuator_type)
+    })
+    .collect();
+  (!subcommands.is_empty()).then(|| ScalarCmd::new(index, subcommands).into())
+}
+
+async fn battery_level(
+  config: &LovenseEmulatorConfig,
+  device_manager: &ServerDeviceManager,
+) -> Option<i32> {
+  let (index, device, sensor_index) = device_manager.devices().iter().find_map(|device| {
+    if !config.selects_device(*device.key(), &device.name()) {
+      return None;
+    }
+    let sensors = device.message_attributes().sensor_read_cmd().clone()?;
+    let sensor_index = sensors
+      .iter()
+      .position(|sensor| *sensor.sensor_type() == SensorType::Battery)?;
+    Some((*device.key(), device.value().clone(), sensor_index as u32))
+  })?;
+  let reading = device
+    .parse_message(SensorReadCmd::new(index, sensor_index, SensorType::Battery).into())
+    .await;
+  match reading {
+    Ok(ButtplugServerMessage::SensorReading(reading)) => reading.data().first().copied(),
+    _ => None,
+  }
+}
+
+/// Handle a command, returning the reply without the trailing `;`.
+async fn handle_command(
+  command: LovenseCommand,
+  rotation: &mut RotationState,
+  config: &LovenseEmulatorConfig,
+  device_manager: &ServerDeviceManager,
+) -> String {
+  let success = match command {
+    LovenseCommand::DeviceType => return config.device_type.clone(),
+    // 2 means the toy is working normally.
+    LovenseCommand::Status => return "2".to_owned(),
+    // Apps expect a number here, so report a full battery for devices without a battery sensor.
+    LovenseCommand::Battery => {
+      return battery_level(config, device_manager)
+        .await
+        .unwrap_or(100)
+        .to_string()
+    }
+    LovenseCommand::PowerOff => {
+      send_to_devices(config, device_manager, |index, _| {
+        Some(StopDeviceCmd::new(index).into())
+      })
+      .await
+    }
+    LovenseCommand::Vibrate { motor, level } => {
+      let scalar = level as f64 / LOVENSE_MAX_LEVEL as f64;
+      send_to_devices(config, device_manager, |index, device| {
+        scalar_message(index, device, ActuatorType::Vibrate, motor, scalar)
+      })
+      .await
+    }
+    LovenseCommand::AirLevel { level } => {
+      let scalar = level as f64 / LOVENSE_MAX_AIR_LEVEL as f64;
+      send_to_devices(config, device_manager, |index, device| {
+        scalar_message(index, device, ActuatorType::Constrict, None, scalar)
+      })
+      .await
+    }
+    LovenseCommand::Rotate { .. } | LovenseCommand::RotateChange => {
+      match command {
+        LovenseCommand::Rotate { level } => rotation.level = level,
+        _ => rotation.clockwise = !rotation.clockwise,
+      }
+      let speed = rotation.level as f64 / LOVENSE_MAX_LEVEL as f64;
+      let clockwise = rotation.clockwise;
+      send_to_devices(config, device_manager, |index, device| {
+        let rotators = device.message_attributes().rotate_cmd().clone()?;
+        let subcommands = (0..rotators.len() as u32)
+          .map(|rotator| RotationSubcommand::new(rotator, speed, clockwise))
+          .collect();
+        Some(RotateCmd::new(index, subcommands).into())
+      })
+      .await
+    }
+  };
+  if success { "OK" } else { "ERR" }.to_owned()
+}
+
+async fn handle_connection(
+  mut stream: TcpStream,
+  config: Arc<LovenseEmulatorConfig>,
+  device_manager: Arc<ServerDeviceManager>,
+  token: CancellationToken,
+) {
+  let mut rotation = RotationState {
+    level: 0,
+    clockwise: true,
+  };
+  let mut pending = String::new();
+  let mut buf = [0u8; 1024];
+  loop {
+    let len = select! {
+      _ = token.cancelled().fuse() => break,
+      result = stream.read(&mut buf).fuse() => match result {
+        Ok(0) | Err(_) => break,
+        Ok(len) => len,
+      }
+    };
+    pending.push_str(&String::from_utf8_lossy(&buf[..len]));
+    // Commands can be split across reads, only handle the complete ones.
+    while let Some(end) = pending.find(';') {
+      let command: String = pending.drain(..=end).collect();
+      let command = command.trim_end_matches(';');
+      trace!("Lovense emulator received {}", command);
+      let reply = match command.parse() {
+        Ok(command) => handle_command(command, &mut rotation, &config, &device_manager).await,
+        Err(err) => {
+          warn!("Lovense emulator received invalid command: {}", err);
+          "ERR".to_owned()
+        }
+      };
+      if stream
+        .write_all(format!("{};", reply).as_bytes())
+        .await
+        .is_err()
+      {
+        return;
+      }
+    }
+  }
+  debug!("Lovense emulator connection closed.");
+}
+
+/// Running Lovense emulator. Stops listening, and closes connections, when dropped.
+pub(crate) struct LovenseEmulator {
+  local_address: SocketAddr,
+  cancellation_token: CancellationToken,
+}
+
+impl LovenseEmulator {
+  /// Bind the listen address and start accepting connections. Binding happens immediately, so
+  /// address errors are reported here instead of from the listener task.
+  pub(crate) fn start(
+    config: &LovenseEmulatorConfig,
+    device_manager: Arc<ServerDeviceManager>,
+  ) -> Result<Self, std::io::Error> {
+    let listener = TcpListener::bind(config.listen_address())?;
+    listener.set_nonblocking(true)?;
+    let local_address = listener.local_addr()?;
+    info!("Lovense emulator listening on {}", local_address);
+    let cancellation_token = CancellationToken::new();
+    let child_token = cancellation_token.child_token();
+    let config = Arc::new(config.clone());
+    async_manager::spawn(async move {
+      let listener = match tokio::net::TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(err) => {
+          error!("Cannot start Lovense emulator listener: {}", err);
+          return;
+        }
+      };
+      loop {
+        let stream = select! {
+          _ = child_token.cancelled().fuse() => break,
+          result = listener.accept().fuse() => match result {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+              error!("Lovense emulator socket error, stopping emulator: {}", err);
+              break;
+            }
+          }
+        };
+        async_manager::spawn(handle_connection(
+          stream,
+          config.clone(),
+          device_manager.clone(),
+          child_token.child_token(),
+        ));
+      }
+      debug!("Lovense emulator stopped.");
+    });
+    Ok(Self {
+      local_address,
+      cancellation_token,
+    })
+  }
+
+  pub(crate) fn local_address(&self) -> SocketAddr {
+    self.local_address
+  }
+}
+
+impl Drop for LovenseEmulator {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
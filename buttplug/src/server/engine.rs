@@ -0,0 +1,362 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Embeddable server engine for GUI frontends
+//!
+//! Frontends like Intiface Central usually run the server in a separate engine process, and talk to
+//! it over a socket to start and stop it, show devices, control scanning, and collect logs. The
+//! [ButtplugEngine] offers the same controls in-process, so a frontend can embed the library
+//! directly instead of reimplementing that glue.
+//!
+//! The engine owns the server lifecycle. Frontends send [EngineCommand]s through
+//! [ButtplugEngine::command_sender], and get [EngineEvent]s back from
+//! [ButtplugEngine::event_stream]. Errors, including ones for commands that can't run in the
+//! current state, are reported as [EngineEvent::Error].
+//!
+//! Each [EngineCommand::Start] builds a new server with the factory the engine was created with,
+//! so settings changes can be applied by stopping and starting the engine. Stopping shuts the
+//! server down, which stops and disconnects all devices.
+//!
+//! Logs are forwarded to the event stream by using [ButtplugEngine::log_writer] as the tracing
+//! writer, i.e. `tracing_subscriber::fmt().with_writer(engine.log_writer()).init()`.
+
+use super::{device::CommunicationManagerScanStatus, ButtplugServer, ButtplugServerError};
+use crate::{
+  core::{
+    errors::ButtplugError,
+    message::{
+      ButtplugClientMessage,
+      ButtplugServerMessage,
+      DeviceMessageInfo,
+      RequestDeviceList,
+      StartScanning,
+      StopAllDevices,
+      StopScanning,
+    },
+  },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+};
+use futures::{select, FutureExt, Stream, StreamExt};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Whether the engine currently has a server running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineStatus {
+  Stopped,
+  Running,
+}
+
+/// Requests from a frontend to the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineCommand {
+  /// Build and start a new server.
+  Start,
+  /// Shut the running server down, stopping and disconnecting all devices.
+  Stop,
+  StartScanning,
+  StopScanning,
+  StopAllDevices,
+  /// Answered with an [EngineEvent::DeviceList].
+  RequestDeviceList,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ButtplugEngineError {
+  #[error("Engine is already running")]
+  AlreadyRunning,
+  #[error("Engine is not running")]
+  NotRunning,
+  #[error("Server could not be created: {0}")]
+  ServerCreationFailed(String),
+  #[error("Server error: {0}")]
+  ServerError(ButtplugError),
+}
+
+/// Updates from the engine to frontends.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum EngineEvent {
+  StatusChanged(EngineStatus),
+  DeviceAdded(DeviceMessageInfo),
+  DeviceRemoved(u32),
+  DeviceList(Vec<DeviceMessageInfo>),
+  ScanningFinished,
+  /// Scanning state of a single communication manager changed, e.g. Bluetooth scanning failed.
+  ScanningStatusChanged(CommunicationManagerScanStatus),
+  /// A line of log output, if [ButtplugEngine::log_writer] is in use.
+  Log(String),
+  Error(ButtplugEngineError),
+}
+
+/// Tracing writer that sends log output to the engine event stream. See the [module
+/// documentation](self) for setup.
+#[derive(Clone)]
+pub struct EngineLogWriter {
+  event_sender: broadcast::Sender<EngineEvent>,
+}
+
+impl std::io::Write for EngineLogWriter {
+  fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+    let line = String::from_utf8_lossy(buf);
+    // Ignore send errors, they only mean no frontend is listening right now.
+    let _ = self
+      .event_sender
+      .send(EngineEvent::Log(line.trim_end().to_owned()));
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> Result<(), std::io::Error> {
+    Ok(())
+  }
+}
+
+impl MakeWriter<'_> for EngineLogWriter {
+  type Writer = EngineLogWriter;
+  fn make_writer(&self) -> Self::Writer {
+    self.clone()
+  }
+}
+
+type ServerSlot = Arc<Mutex<Option<Arc<ButtplugServer>>>>;
+
+fn running_server(server: &ServerSlot) -> Option<Arc<ButtplugServer>> {
+  server
+    .lock()
+    .expect("Lock is never held across a panic")
+    .clone()
+}
+
+/// Runs commands for the engine. Only one command runs at a time, so starting and stopping can't
+/// race each other.
+struct EngineLoop<F> {
+  server_factory: F,
+  server: ServerSlot,
+  event_sender: broadcast::Sender<EngineEvent>,
+  /// Stops forwarding events from the running server.
+  server_event_token: Option<CancellationToken>,
+}
+
+impl<F> EngineLoop<F>
+where
+  F: Fn() -> Result<ButtplugServer, ButtplugServerError> + Send + Sync + 'static,
+{
+  async fn run(
+    mut self,
+    mut command_receiver: mpsc::Receiver<EngineCommand>,
+    token: CancellationToken,
+  ) {
+    loop {
+      let command = select! {
+        _ = token.cancelled().fuse() => break,
+        command = command_receiver.recv().fuse() => match command {
+          Some(command) => command,
+          None => break,
+        }
+      };
+      debug!("Engine received command {:?}", command);
+      if let Err(err) = self.handle_command(command).await {
+        warn!("Engine command {:?} failed: {}", command, err);
+        let _ = self.event_sender.send(EngineEvent::Error(err));
+      }
+    }
+    if running_server(&self.server).is_some() {
+      if let Err(err) = self.stop().await {
+        error!("Could not shut down server while stopping engine: {}", err);
+      }
+    }
+    debug!("Engine stopped.");
+  }
+
+  async fn handle_command(&mut self, command: EngineCommand) -> Result<(), ButtplugEngineError> {
+    match command {
+      EngineCommand::Start => self.start(),
+      EngineCommand::Stop => self.stop().await,
+      EngineCommand::StartScanning => self
+        .send_to_server(StartScanning::default().into())
+        .await
+        .map(|_| ()),
+      EngineCommand::StopScanning => self
+        .send_to_server(StopScanning::default().into())
+        .await
+        .map(|_| ()),
+      EngineCommand::StopAllDevices => self
+        .send_to_server(StopAllDevices::default().into())
+        .await
+        .map(|_| ()),
+      EngineCommand::RequestDeviceList => {
+        if let ButtplugServerMessage::DeviceList(list) = self
+          .send_to_server(RequestDeviceList::default().into())
+          .await?
+        {
+          let _ = self
+            .event_sender
+            .send(EngineEvent::DeviceList(list.devices().clone()));
+        }
+        Ok(())
+      }
+    }
+  }
+
+  fn start(&mut self) -> Result<(), ButtplugEngineError> {
+    if running_server(&self.server).is_some() {
+      return Err(ButtplugEngineError::AlreadyRunning);
+    }
+    let server = Arc::new(
+      (self.server_factory)()
+        .map_err(|err| ButtplugEngineError::ServerCreationFailed(err.to_string()))?,
+    );
+    let token = CancellationToken::new();
+    let child_token = token.child_token();
+    let mut server_events = Box::pin(server.event_stream());
+    let mut scan_status_events = Box::pin(server.device_manager().scanning_status_stream());
+    let event_sender = self.event_sender.clone();
+    async_manager::spawn(async move {
+      loop {
+        let message = select! {
+          _ = child_token.cancelled().fuse() => break,
+          status = scan_status_events.next().fuse() => match status {
+            Some(status) => {
+              let _ = event_sender.send(EngineEvent::ScanningStatusChanged(status));
+              continue;
+            }
+            None => break,
+          },
+          message = server_events.next().fuse() => match message {
+            Some(message) => message,
+            None => break,
+          }
+        };
+        let event = match message {
+          ButtplugServerMessage::DeviceAdded(device) => EngineEvent::DeviceAdded(device.into()),
+          ButtplugServerMessage::DeviceRemoved(device) => {
+            EngineEvent::DeviceRemoved(device.device_index())
+          }
+          ButtplugServerMessage::ScanningFinished(_) => EngineEvent::ScanningFinished,
+          _ => continue,
+        };
+        let _ = event_sender.send(event);
+      }
+    });
+    self.server_event_token = Some(token);
+    *self
+      .server
+      .lock()
+      .expect("Lock is never held across a panic") = Some(server);
+    info!("Engine started server.");
+    let _ = self
+      .event_sender
+      .send(EngineEvent::StatusChanged(EngineStatus::Running));
+    Ok(())
+  }
+
+  async fn stop(&mut self) -> Result<(), ButtplugEngineError> {
+    let server = self
+      .server
+      .lock()
+      .expect("Lock is never held across a panic")
+      .take()
+      .ok_or(ButtplugEngineError::NotRunning)?;
+    if let Some(token) = self.server_event_token.take() {
+      token.cancel();
+    }
+    let result = server.shutdown().await;
+    info!("Engine stopped server.");
+    let _ = self
+      .event_sender
+      .send(EngineEvent::StatusChanged(EngineStatus::Stopped));
+    result.map(|_| ()).map_err(ButtplugEngineError::ServerError)
+  }
+
+  async fn send_to_server(
+    &self,
+    message: ButtplugClientMessage,
+  ) -> Result<ButtplugServerMessage, ButtplugEngineError> {
+    let server = running_server(&self.server).ok_or(ButtplugEngineError::NotRunning)?;
+    // Frontends aren't clients, so skip the handshake and go straight to the device manager.
+    server
+      .device_manager()
+      .parse_message(message)
+      .await
+      .map_err(ButtplugEngineError::ServerError)
+  }
+}
+
+/// Supervises a [ButtplugServer] for an embedding frontend. See the [module documentation](self)
+/// for details. The running server, if any, is shut down when the engine is dropped.
+pub struct ButtplugEngine {
+  command_sender: mpsc::Sender<EngineCommand>,
+  event_sender: broadcast::Sender<EngineEvent>,
+  server: ServerSlot,
+  cancellation_token: CancellationToken,
+}
+
+impl ButtplugEngine {
+  /// Create a stopped engine. The factory is called to build a new server every time the engine is
+  /// started.
+  pub fn new<F>(server_factory: F) -> Self
+  where
+    F: Fn() -> Result<ButtplugServer, ButtplugServerError> + Send + Sync + 'static,
+  {
+    let (command_sender, command_receiver) = mpsc::channel(256);
+    let (event_sender, _) = broadcast::channel(256);
+    let server = ServerSlot::default();
+    let cancellation_token = CancellationToken::new();
+    let engine_loop = EngineLoop {
+      server_factory,
+      server: server.clone(),
+      event_sender: event_sender.clone(),
+      server_event_token: None,
+    };
+    async_manager::spawn(engine_loop.run(command_receiver, cancellation_token.child_token()));
+    Self {
+      command_sender,
+      event_sender,
+      server,
+      cancellation_token,
+    }
+  }
+
+  /// Sender for commands to the engine. Commands are run in the order they're received.
+  pub fn command_sender(&self) -> mpsc::Sender<EngineCommand> {
+    self.command_sender.clone()
+  }
+
+  /// Stream of engine updates. Only events sent after this is called are received.
+  pub fn event_stream(&self) -> impl Stream<Item = EngineEvent> {
+    convert_broadcast_receiver_to_stream(self.event_sender.subscribe())
+  }
+
+  pub fn status(&self) -> EngineStatus {
+    if running_server(&self.server).is_some() {
+      EngineStatus::Running
+    } else {
+      EngineStatus::Stopped
+    }
+  }
+
+  /// The running server, if any, for frontends that also want to send it messages directly.
+  pub fn server(&self) -> Option<Arc<ButtplugServer>> {
+    running_server(&self.server)
+  }
+
+  /// Tracing writer that forwards log output as [EngineEvent::Log] events.
+  pub fn log_writer(&self) -> EngineLogWriter {
+    EngineLogWriter {
+      event_sender: self.event_sender.clone(),
+    }
+  }
+}
+
+impl Drop for ButtplugEngine {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
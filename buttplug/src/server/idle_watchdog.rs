@@ -0,0 +1,77 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Stops devices when the connected client stops sending messages
+//!
+//! The ping timer only works if the client opts into it by sending pings, and a client that crashes
+//! without closing its connection will otherwise leave devices running at whatever they were last
+//! set to. The idle watchdog doesn't need anything from the client. Any message counts as activity,
+//! and if nothing arrives for the configured timeout while a client is connected, all devices are
+//! stopped. The client stays connected, and can keep sending commands afterward.
+
+use crate::util::{async_manager, sleep};
+use futures::{future::BoxFuture, FutureExt};
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+use tokio::sync::Notify;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+pub(super) struct ClientIdleWatchdog {
+  activity: Arc<Notify>,
+  _cancel_guard: DropGuard,
+}
+
+impl ClientIdleWatchdog {
+  /// Start a watchdog that calls on_idle whenever the client has been connected, but silent, for
+  /// longer than timeout. Stops when dropped.
+  pub(super) fn new<F>(timeout: Duration, connected: Arc<AtomicBool>, on_idle: F) -> Self
+  where
+    F: Fn() -> BoxFuture<'static, ()> + Send + 'static,
+  {
+    let activity = Arc::new(Notify::new());
+    let token = CancellationToken::new();
+    let activity_clone = activity.clone();
+    let child_token = token.child_token();
+    async_manager::spawn(async move {
+      loop {
+        select! {
+          _ = activity_clone.notified().fuse() => continue,
+          _ = child_token.cancelled().fuse() => return,
+          _ = sleep(timeout).fuse() => {}
+        }
+        if connected.load(Ordering::SeqCst) {
+          warn!(
+            "No messages from client in {:?}, stopping all devices.",
+            timeout
+          );
+          on_idle().await;
+        }
+        // Nothing more to do until the client does something.
+        select! {
+          _ = activity_clone.notified().fuse() => {}
+          _ = child_token.cancelled().fuse() => return,
+        }
+      }
+    });
+    Self {
+      activity,
+      _cancel_guard: token.drop_guard(),
+    }
+  }
+
+  /// Restart the idle timeout.
+  pub(super) fn record_activity(&self) {
+    // notify_one stores a permit if the watchdog isn't waiting right now, so activity is never
+    // missed.
+    self.activity.notify_one();
+  }
+}
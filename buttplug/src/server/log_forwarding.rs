@@ -0,0 +1,267 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Forwards server logs to clients that ask for them with [RequestLog]
+//!
+//! Debugging a user's setup usually means asking them to find and send over the server's log file.
+//! With forwarding, a client can subscribe to the server's logs at a chosen level instead, and
+//! receive them as [Log] messages.
+//!
+//! Logs are picked up through a [tracing_subscriber::Layer], which the application has to add to
+//! its subscriber next to whatever else it logs to, and hand to the servers that should forward
+//! its logs:
+//!
+//! ```ignore
+//! let forwarding_layer = ButtplugLogForwardingLayer::default();
+//! tracing_subscriber::registry()
+//!   .with(tracing_subscriber::fmt::layer())
+//!   .with(forwarding_layer.clone())
+//!   .init();
+//! ButtplugServerBuilder::default().log_forwarding_layer(&forwarding_layer);
+//! ```
+//!
+//! Servers only forward logs from the layer they were built with, so applications running several
+//! servers can decide which of them hand logs out.
+//!
+//! Each forwarded message includes the spans it was logged in, so device logs carry the address and
+//! protocol of the device they're about. Logs from connectors, transports and clients are never
+//! forwarded, as sending the forwarded logs would log more messages to forward.
+//!
+//! [RequestLog] and [Log] were dropped in spec version 2, and brought back in version 4, so clients
+//! on spec versions 2 and 3 can't request logs.
+//!
+//! [RequestLog]: crate::core::message::RequestLog
+
+use crate::{
+  core::message::{ButtplugServerMessage, Log, LogLevel},
+  util::async_manager,
+};
+use futures::FutureExt;
+use std::fmt::{self, Write};
+use tokio::sync::broadcast;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::{
+  field::{Field, Visit},
+  span,
+  Event,
+  Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// Log targets that are never forwarded. Forwarded logs go out through connectors and transports,
+/// and in-process clients log the messages they receive, so their logs would feed back into
+/// themselves.
+const IGNORED_TARGETS: [&str; 3] = [
+  "buttplug::core::connector",
+  "buttplug::client",
+  module_path!(),
+];
+
+/// Collects event and span fields into a single line, `message` first, then `name=value` pairs.
+#[derive(Default)]
+struct FieldFormatter {
+  message: String,
+  fields: String,
+}
+
+impl Visit for FieldFormatter {
+  fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+    if field.name() == "message" {
+      let _ = write!(self.message, "{:?}", value);
+    } else {
+      if !self.fields.is_empty() {
+        self.fields.push(' ');
+      }
+      let _ = write!(self.fields, "{}={:?}", field.name(), value);
+    }
+  }
+
+  fn record_str(&mut self, field: &Field, value: &str) {
+    if field.name() == "message" {
+      self.message.push_str(value);
+    } else {
+      self.record_debug(field, &value);
+    }
+  }
+}
+
+/// Span fields, formatted once when the span is created and stored in the span's extensions.
+struct FormattedSpanFields(String);
+
+/// [Layer] that makes logs available for forwarding to clients.
+///
+/// Events are only formatted while a client is subscribed, so the layer costs next to nothing the
+/// rest of the time. Clones share the same logs, so a clone can be added to the subscriber and
+/// another handed to the server.
+#[derive(Debug, Clone)]
+pub struct ButtplugLogForwardingLayer {
+  log_sender: broadcast::Sender<Log>,
+}
+
+impl Default for ButtplugLogForwardingLayer {
+  fn default() -> Self {
+    Self {
+      log_sender: broadcast::channel(256).0,
+    }
+  }
+}
+
+impl<S> Layer<S> for ButtplugLogForwardingLayer
+where
+  S: Subscriber + for<'a> LookupSpan<'a>,
+{
+  fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+    let Some(span) = ctx.span(id) else {
+      return;
+    };
+    let mut fields = FieldFormatter::default();
+    attrs.record(&mut fields);
+    span
+      .extensions_mut()
+      .insert(FormattedSpanFields(fields.fields));
+  }
+
+  fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+    let Some(span) = ctx.span(id) else {
+      return;
+    };
+    let mut fields = FieldFormatter::default();
+    values.record(&mut fields);
+    let mut extensions = span.extensions_mut();
+    if let Some(FormattedSpanFields(existing)) = extensions.get_mut::<FormattedSpanFields>() {
+      if !existing.is_empty() && !fields.fields.is_empty() {
+        existing.push(' ');
+      }
+      existing.push_str(&fields.fields);
+    }
+  }
+
+  fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+    if self.log_sender.receiver_count() == 0 {
+      return;
+    }
+    let metadata = event.metadata();
+    if IGNORED_TARGETS
+      .iter()
+      .any(|target| metadata.target().starts_with(target))
+    {
+      return;
+    }
+    let mut line = String::new();
+    if let Some(scope) = ctx.event_scope(event) {
+      for span in scope.from_root() {
+        line.push_str(span.name());
+        if let Some(FormattedSpanFields(fields)) = span.extensions().get::<FormattedSpanFields>() {
+          if !fields.is_empty() {
+            let _ = write!(line, "{{{}}}", fields);
+          }
+        }
+        line.push_str(": ");
+      }
+    }
+    let mut fields = FieldFormatter::default();
+    event.record(&mut fields);
+    let _ = write!(line, "{}: {}", metadata.target(), fields.message);
+    if !fields.fields.is_empty() {
+      let _ = write!(line, " {}", fields.fields);
+    }
+    // No receivers left is fine, the client just unsubscribed.
+    let _ = self
+      .log_sender
+      .send(Log::new((*metadata.level()).into(), &line));
+  }
+}
+
+/// Forwards logs at `level` or more severe into the server's event stream until dropped.
+pub(super) struct LogForwarder {
+  _cancel_guard: DropGuard,
+}
+
+impl LogForwarder {
+  pub(super) fn new(
+    layer: &ButtplugLogForwardingLayer,
+    level: LogLevel,
+    output_sender: broadcast::Sender<ButtplugServerMessage>,
+  ) -> Self {
+    let token = CancellationToken::new();
+    let child_token = token.child_token();
+    let mut log_receiver = layer.log_sender.subscribe();
+    async_manager::spawn(async move {
+      loop {
+        select! {
+          _ = child_token.cancelled().fuse() => return,
+          log = log_receiver.recv().fuse() => match log {
+            Ok(log) => {
+              // Lower levels are more severe, Off being the lowest.
+              if log.log_level() <= level && output_sender.send(log.into()).is_err() {
+                return;
+              }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+          }
+        }
+      }
+    });
+    Self {
+      _cancel_guard: token.drop_guard(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use tracing_subscriber::prelude::*;
+
+  #[tokio::test]
+  async fn test_log_forwarding_levels_and_spans() {
+    let layer = ButtplugLogForwardingLayer::default();
+    let subscriber = tracing_subscriber::registry().with(layer.clone());
+    let _default = tracing::subscriber::set_default(subscriber);
+    let (output_sender, mut output_receiver) = broadcast::channel(256);
+    let forwarder = LogForwarder::new(&layer, LogLevel::Info, output_sender);
+
+    let span = tracing::info_span!("device", address = "AA:BB", protocol = "lovense");
+    // Logs from this module are ignored, so pretend to be the device manager.
+    span.in_scope(|| {
+      tracing::info!(target: "buttplug::server::device", "Device connected");
+      tracing::debug!(target: "buttplug::server::device", "Too verbose to forward");
+    });
+    let ButtplugServerMessage::Log(log) = output_receiver
+      .recv()
+      .await
+      .expect("Test, assuming infallible.")
+    else {
+      panic!("Expected a log message");
+    };
+    assert_eq!(log.log_level(), LogLevel::Info);
+    assert!(log.log_message().contains("address=\"AA:BB\""));
+    assert!(log.log_message().contains("protocol=\"lovense\""));
+    assert!(log.log_message().ends_with("Device connected"));
+
+    // Once dropped, nothing else is forwarded.
+    drop(forwarder);
+    crate::util::sleep(std::time::Duration::from_millis(10)).await;
+    tracing::warn!(target: "buttplug::server::device", "After unsubscribing");
+    assert!(output_receiver.try_recv().is_err());
+  }
+  #[tokio::test]
+  async fn test_log_forwarding_only_from_own_layer() {
+    let layer = ButtplugLogForwardingLayer::default();
+    let other_layer = ButtplugLogForwardingLayer::default();
+    let subscriber = tracing_subscriber::registry().with(other_layer.clone());
+    let _default = tracing::subscriber::set_default(subscriber);
+    let (output_sender, mut output_receiver) = broadcast::channel(256);
+    let _forwarder = LogForwarder::new(&layer, LogLevel::Trace, output_sender);
+    let _other_receiver = other_layer.log_sender.subscribe();
+
+    tracing::error!(target: "buttplug::server::device", "Logged through another layer");
+    crate::util::sleep(std::time::Duration::from_millis(10)).await;
+    assert!(output_receiver.try_recv().is_err());
+  }
+}
@@ -0,0 +1,210 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Server metrics, for monitoring long running servers
+//!
+//! When turned on with
+//! [ButtplugServerBuilder::enable_metrics](super::ButtplugServerBuilder::enable_metrics), the
+//! server counts messages as they pass through
+//! [ButtplugServer::parse_message](super::ButtplugServer::parse_message). Metrics are pulled with
+//! [ButtplugServer::metrics](super::ButtplugServer::metrics), which returns a snapshot that can be
+//! read directly or rendered in the Prometheus text exposition format for whatever endpoint the
+//! application serves.
+//!
+//! Counters only ever go up while the server exists, so rates (like messages per second) are left
+//! to whatever is scraping them.
+
+use crate::core::errors::{ButtplugDeviceError, ButtplugError};
+use dashmap::DashMap;
+use getset::{CopyGetters, Getters};
+use std::{
+  collections::BTreeMap,
+  fmt::Write,
+  sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+/// Point in time copy of the server's metrics.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Getters, CopyGetters)]
+pub struct ServerMetricsSnapshot {
+  /// Number of devices currently connected to the server.
+  #[getset(get_copy = "pub")]
+  connected_devices: usize,
+  /// True if the server is currently scanning for devices.
+  #[getset(get_copy = "pub")]
+  scanning: bool,
+  /// True if a client is currently connected.
+  #[getset(get_copy = "pub")]
+  client_connected: bool,
+  /// Messages received from clients, keyed by message type.
+  #[getset(get = "pub")]
+  messages_received: BTreeMap<String, u64>,
+  /// Messages that were replied to with an error, keyed by message type.
+  #[getset(get = "pub")]
+  message_errors: BTreeMap<String, u64>,
+  /// Device commands that failed due to the hardware, like write errors or disconnections.
+  #[getset(get_copy = "pub")]
+  hardware_errors: u64,
+}
+
+impl ServerMetricsSnapshot {
+  /// Render the snapshot in the Prometheus text exposition format.
+  pub fn to_prometheus_text(&self) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, value: u64| {
+      let _ = writeln!(out, "# HELP {name} {help}");
+      let _ = writeln!(out, "# TYPE {name} gauge");
+      let _ = writeln!(out, "{name} {value}");
+    };
+    gauge(
+      "buttplug_connected_devices",
+      "Number of devices connected to the server.",
+      self.connected_devices as u64,
+    );
+    gauge(
+      "buttplug_scanning",
+      "1 if the server is scanning for devices.",
+      self.scanning as u64,
+    );
+    gauge(
+      "buttplug_client_connected",
+      "1 if a client is connected to the server.",
+      self.client_connected as u64,
+    );
+    for (name, help, counts) in [
+      (
+        "buttplug_messages_received_total",
+        "Messages received from clients, by message type.",
+        &self.messages_received,
+      ),
+      (
+        "buttplug_message_errors_total",
+        "Messages replied to with an error, by message type.",
+        &self.message_errors,
+      ),
+    ] {
+      let _ = writeln!(out, "# HELP {name} {help}");
+      let _ = writeln!(out, "# TYPE {name} counter");
+      for (message_type, count) in counts {
+        let _ = writeln!(out, "{name}{{type=\"{message_type}\"}} {count}");
+      }
+    }
+    let _ = writeln!(
+      out,
+      "# HELP buttplug_hardware_errors_total Device commands that failed due to the hardware."
+    );
+    let _ = writeln!(out, "# TYPE buttplug_hardware_errors_total counter");
+    let _ = writeln!(
+      out,
+      "buttplug_hardware_errors_total {}",
+      self.hardware_errors
+    );
+    out
+  }
+}
+
+/// Live counters, updated by the server as messages are handled.
+#[derive(Default)]
+pub(super) struct ServerMetrics {
+  messages_received: DashMap<&'static str, u64>,
+  message_errors: DashMap<&'static str, u64>,
+  hardware_errors: AtomicU64,
+  scanning: AtomicBool,
+}
+
+impl ServerMetrics {
+  pub(super) fn record_received(&self, message_type: &'static str) {
+    *self.messages_received.entry(message_type).or_default() += 1;
+  }
+
+  pub(super) fn record_success(&self, message_type: &'static str) {
+    match message_type {
+      "StartScanning" => self.scanning.store(true, Ordering::SeqCst),
+      "StopScanning" => self.scanning.store(false, Ordering::SeqCst),
+      _ => {}
+    }
+  }
+
+  pub(super) fn record_error(&self, message_type: &'static str, err: Option<&ButtplugError>) {
+    *self.message_errors.entry(message_type).or_default() += 1;
+    if err.is_some_and(is_hardware_error) {
+      self.hardware_errors.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  pub(super) fn set_scanning(&self, scanning: bool) {
+    self.scanning.store(scanning, Ordering::SeqCst);
+  }
+
+  pub(super) fn snapshot(
+    &self,
+    connected_devices: usize,
+    client_connected: bool,
+  ) -> ServerMetricsSnapshot {
+    let copy_counts = |counts: &DashMap<&'static str, u64>| {
+      counts
+        .iter()
+        .map(|entry| (entry.key().to_string(), *entry.value()))
+        .collect()
+    };
+    ServerMetricsSnapshot {
+      connected_devices,
+      scanning: self.scanning.load(Ordering::SeqCst),
+      client_connected,
+      messages_received: copy_counts(&self.messages_received),
+      message_errors: copy_counts(&self.message_errors),
+      hardware_errors: self.hardware_errors.load(Ordering::SeqCst),
+    }
+  }
+}
+
+/// True if the error came from talking to the hardware, as opposed to a bad message.
+fn is_hardware_error(err: &ButtplugError) -> bool {
+  matches!(
+    err,
+    ButtplugError::ButtplugDeviceError(
+      ButtplugDeviceError::DeviceNotConnected(_)
+        | ButtplugDeviceError::DeviceConnectionError(_)
+        | ButtplugDeviceError::DeviceCommunicationError(_)
+        | ButtplugDeviceError::DeviceSpecificError(_)
+        | ButtplugDeviceError::HandshakeTimeout { .. }
+        | ButtplugDeviceError::HandshakeFailed { .. }
+        | ButtplugDeviceError::WriteFailed { .. }
+        | ButtplugDeviceError::ReadFailed { .. }
+        | ButtplugDeviceError::SubscribeFailed { .. }
+        | ButtplugDeviceError::DeviceDisconnected { .. }
+        | ButtplugDeviceError::DeviceStopTimeout(_)
+        | ButtplugDeviceError::DevicesForceDisconnected(_)
+    )
+  )
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_metrics_prometheus_text() {
+    let metrics = ServerMetrics::default();
+    metrics.record_received("ScalarCmd");
+    metrics.record_received("ScalarCmd");
+    metrics.record_received("StartScanning");
+    metrics.record_success("StartScanning");
+    metrics.record_error(
+      "ScalarCmd",
+      Some(&ButtplugDeviceError::DeviceCommunicationError("gone".to_owned()).into()),
+    );
+    let snapshot = metrics.snapshot(2, true);
+    assert!(snapshot.scanning());
+    assert_eq!(snapshot.hardware_errors(), 1);
+    let text = snapshot.to_prometheus_text();
+    assert!(text.contains("buttplug_connected_devices 2\n"));
+    assert!(text.contains("buttplug_scanning 1\n"));
+    assert!(text.contains("buttplug_messages_received_total{type=\"ScalarCmd\"} 2\n"));
+    assert!(text.contains("buttplug_message_errors_total{type=\"ScalarCmd\"} 1\n"));
+    assert!(text.contains("buttplug_hardware_errors_total 1\n"));
+  }
+}
@@ -45,17 +45,38 @@
 //!   - If the server object is dropped, all devices are stopped and disconnected as part
 //!     of the [DeviceManager] teardown.
 
+#[cfg(feature = "audio-reactive")]
+pub mod audio;
 pub mod device;
+#[cfg(feature = "lovense-emulator")]
+pub mod emulation;
+pub mod engine;
+mod idle_watchdog;
+pub mod log_forwarding;
+pub mod metrics;
+pub mod multiplexer;
+#[cfg(feature = "osc-bridge")]
+pub mod osc;
+pub mod permissions;
 mod ping_timer;
+#[cfg(feature = "protocol-plugins")]
+pub mod plugin;
 
 use self::device::{
+  battery::BatteryPollingPolicy,
   configuration::{
+    DeviceConfigurationDiff,
     ProtocolAttributesIdentifier,
     ProtocolCommunicationSpecifier,
     ProtocolDeviceAttributes,
   },
-  hardware::communication::HardwareCommunicationManagerBuilder,
-  protocol::ProtocolIdentifierFactory,
+  hardware::{
+    communication::HardwareCommunicationManagerBuilder,
+    recording::HardwareTrafficRecorder,
+  },
+  protocol::{ProtocolIdentifierFactory, ProtocolInitializationTimeouts},
+  RememberedDeviceStore,
+  SafetyPolicy,
   ServerDeviceIdentifier,
   ServerDeviceManager,
   ServerDeviceManagerBuilder,
@@ -66,10 +87,9 @@ use crate::{
     message::{
       self,
       ButtplugClientMessage,
-      ButtplugDeviceCommandMessageUnion,
-      ButtplugDeviceManagerMessageUnion,
       ButtplugMessage,
       ButtplugServerMessage,
+      LogLevel,
       StopAllDevices,
       StopScanning,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
@@ -81,17 +101,31 @@ use crate::{
     stream::convert_broadcast_receiver_to_stream,
   },
 };
+#[cfg(feature = "audio-reactive")]
+use audio::{AudioInput, AudioReactive, AudioReactiveConfig};
 use futures::{
   future::{self, BoxFuture, FutureExt},
   Stream,
 };
+#[cfg(feature = "lovense-emulator")]
+use emulation::{LovenseEmulator, LovenseEmulatorConfig};
+use idle_watchdog::ClientIdleWatchdog;
+use log_forwarding::{ButtplugLogForwardingLayer, LogForwarder};
+use metrics::{ServerMetrics, ServerMetricsSnapshot};
+#[cfg(feature = "osc-bridge")]
+use osc::{OscBridge, OscBridgeConfig};
+use permissions::{ClientPermissions, PermissionPolicy};
 use ping_timer::PingTimer;
+#[cfg(feature = "protocol-plugins")]
+use plugin::{PluginRegistry, ProtocolPluginFactory, ProtocolPluginHost, ProtocolPluginHostConfig};
 use std::{
   fmt,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
+    Mutex,
   },
+  time::Duration,
 };
 use thiserror::Error;
 use tokio::sync::broadcast;
@@ -122,6 +156,22 @@ pub enum ButtplugServerError {
   /// Requested protocol has not been registered with the system.
   #[error("Buttplug Protocol of type {0} does not exist in the system and cannot be removed.")]
   ProtocolDoesNotExist(String),
+  /// OSC bridge could not start listening.
+  #[cfg(feature = "osc-bridge")]
+  #[error("OSC bridge could not listen on {0}: {1}")]
+  OscBridgeError(String, String),
+  /// Protocol plugin host could not start listening.
+  #[cfg(feature = "protocol-plugins")]
+  #[error("Protocol plugin host could not listen on {0}: {1}")]
+  PluginHostError(String, String),
+  /// Lovense emulator could not start listening.
+  #[cfg(feature = "lovense-emulator")]
+  #[error("Lovense emulator could not listen on {0}: {1}")]
+  LovenseEmulatorError(String, String),
+  /// Audio reactive processing could not start.
+  #[cfg(feature = "audio-reactive")]
+  #[error("Audio reactive processing could not start: {0}")]
+  AudioReactiveError(String),
 }
 
 /// Configures and creates [ButtplugServer] instances.
@@ -132,12 +182,33 @@ pub struct ButtplugServerBuilder {
   /// Maximum time system will live without receiving a Ping message before disconnecting. If None,
   /// ping timer does not run.
   max_ping_time: Option<u32>,
+  /// Time without any message from the client before all devices are stopped. If None, the idle
+  /// watchdog does not run.
+  client_idle_timeout: Option<Duration>,
   /// JSON string, with the contents of the base Device Configuration file
   device_configuration_json: Option<String>,
   /// JSON string, with the contents of the User Device Configuration file
   user_device_configuration_json: Option<String>,
   /// Device manager builder for the server
   device_manager_builder: ServerDeviceManagerBuilder,
+  /// OSC bridge to start with the server, if any
+  #[cfg(feature = "osc-bridge")]
+  osc_bridge_config: Option<OscBridgeConfig>,
+  /// Protocol plugin host to start with the server, if any
+  #[cfg(feature = "protocol-plugins")]
+  plugin_host_config: Option<(ProtocolPluginHostConfig, Arc<PluginRegistry>)>,
+  /// Lovense emulator to start with the server, if any
+  #[cfg(feature = "lovense-emulator")]
+  lovense_emulator_config: Option<LovenseEmulatorConfig>,
+  /// Audio reactive processing to start with the server, if any
+  #[cfg(feature = "audio-reactive")]
+  audio_reactive_config: Option<AudioReactiveConfig>,
+  /// If true, the server keeps counters of the messages it handles.
+  metrics_enabled: bool,
+  /// Permissions for raw messages, scanning, device groups and patterns, by client name
+  permission_policy: PermissionPolicy,
+  /// Layer to forward logs from when clients send RequestLog, if any
+  log_forwarding_layer: Option<ButtplugLogForwardingLayer>,
 }
 
 impl Default for ButtplugServerBuilder {
@@ -145,9 +216,21 @@ impl Default for ButtplugServerBuilder {
     Self {
       name: "Buttplug Server".to_owned(),
       max_ping_time: None,
+      client_idle_timeout: None,
       device_configuration_json: Some(DEVICE_CONFIGURATION_JSON.to_owned()),
       user_device_configuration_json: None,
       device_manager_builder: ServerDeviceManagerBuilder::default(),
+      #[cfg(feature = "osc-bridge")]
+      osc_bridge_config: None,
+      #[cfg(feature = "protocol-plugins")]
+      plugin_host_config: None,
+      #[cfg(feature = "lovense-emulator")]
+      lovense_emulator_config: None,
+      #[cfg(feature = "audio-reactive")]
+      audio_reactive_config: None,
+      metrics_enabled: false,
+      permission_policy: PermissionPolicy::default(),
+      log_forwarding_layer: None,
     }
   }
 }
@@ -172,7 +255,18 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Stop all devices if a connected client sends no messages at all, pings included, for this
+  /// long. Unlike the ping timer this doesn't require anything from the client, and doesn't
+  /// disconnect it. Meant to catch clients that crash without closing their connection.
+  pub fn client_idle_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.client_idle_timeout = Some(timeout);
+    self
+  }
+
   /// Set the device configuration json file contents, to be loaded during build.
+  ///
+  /// To load the configuration from a file or URL instead of the one bundled with the library, use
+  /// a [DeviceConfigurationSource](crate::util::device_configuration_source::DeviceConfigurationSource).
   pub fn device_configuration_json(&mut self, config_json: Option<String>) -> &mut Self {
     self.device_configuration_json = config_json;
     self
@@ -202,6 +296,14 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Expose devices using an experimental protocol, which are hidden by default.
+  pub fn allow_experimental_protocol(&mut self, protocol_name: &str) -> &mut Self {
+    self
+      .device_manager_builder
+      .allow_experimental_protocol(protocol_name);
+    self
+  }
+
   pub fn reserved_index(&mut self, identifier: &ServerDeviceIdentifier, index: u32) -> &mut Self {
     self
       .device_manager_builder
@@ -249,6 +351,129 @@ impl ButtplugServerBuilder {
     self
   }
 
+  /// Record all traffic between the server and devices to the given recorder. See
+  /// [HardwareTrafficRecorder] for the recording format.
+  pub fn hardware_traffic_recorder(&mut self, recorder: HardwareTrafficRecorder) -> &mut Self {
+    self.device_manager_builder.hardware_traffic_recorder(recorder);
+    self
+  }
+
+  /// Remember connected devices in the store, and reconnect them with the same protocol when
+  /// they're seen again. See the [remembered](device::remembered) module for details.
+  pub fn remembered_devices(&mut self, store: RememberedDeviceStore) -> &mut Self {
+    self.device_manager_builder.remembered_devices(store);
+    self
+  }
+
+  /// Time each device has to acknowledge a stop when all devices are stopped, before it's
+  /// disconnected. See [ServerDeviceManagerBuilder::device_stop_timeout].
+  pub fn device_stop_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.device_manager_builder.device_stop_timeout(timeout);
+    self
+  }
+
+  /// Stop timeout for devices using a specific protocol. See
+  /// [ServerDeviceManagerBuilder::protocol_device_stop_timeout].
+  pub fn protocol_device_stop_timeout(&mut self, protocol: &str, timeout: Duration) -> &mut Self {
+    self
+      .device_manager_builder
+      .protocol_device_stop_timeout(protocol, timeout);
+    self
+  }
+
+  /// Number of devices that can be connecting at once. See
+  /// [ServerDeviceManagerBuilder::max_parallel_connections].
+  pub fn max_parallel_connections(&mut self, max: usize) -> &mut Self {
+    self.device_manager_builder.max_parallel_connections(max);
+    self
+  }
+
+  /// Number of times a failed device connection is retried. See
+  /// [ServerDeviceManagerBuilder::connection_retries].
+  pub fn connection_retries(&mut self, retries: u32) -> &mut Self {
+    self.device_manager_builder.connection_retries(retries);
+    self
+  }
+
+  /// Output limits enforced on every device, regardless of which client sends commands. See the
+  /// [safety](device::safety) module for details.
+  pub fn safety_policy(&mut self, policy: SafetyPolicy) -> &mut Self {
+    self.device_manager_builder.safety_policy(policy);
+    self
+  }
+
+  /// Battery polling for devices clients subscribe to the battery of. See the
+  /// [battery](device::battery) module for details.
+  pub fn battery_polling(&mut self, policy: BatteryPollingPolicy) -> &mut Self {
+    self.device_manager_builder.battery_polling(policy);
+    self
+  }
+
+  /// Time protocols have to identify and initialize devices before the connection is given up on.
+  pub fn initialization_timeouts(&mut self, timeouts: ProtocolInitializationTimeouts) -> &mut Self {
+    self
+      .device_manager_builder
+      .initialization_timeouts(timeouts);
+    self
+  }
+
+  /// Limits raw messages and scanning for clients, based on the name they connect with. See the
+  /// [permissions] module for details.
+  pub fn permission_policy(&mut self, policy: PermissionPolicy) -> &mut Self {
+    self.permission_policy = policy;
+    self
+  }
+
+  /// Forward logs picked up by this layer to clients that send RequestLog. Without a layer, the
+  /// server rejects RequestLog. See the [log_forwarding] module for details.
+  pub fn log_forwarding_layer(&mut self, layer: &ButtplugLogForwardingLayer) -> &mut Self {
+    self.log_forwarding_layer = Some(layer.clone());
+    self
+  }
+
+  /// Listen for OSC messages and forward them to devices, as described by the config. See the
+  /// [osc] module for details.
+  #[cfg(feature = "osc-bridge")]
+  pub fn osc_bridge(&mut self, config: OscBridgeConfig) -> &mut Self {
+    self.osc_bridge_config = Some(config);
+    self
+  }
+
+  /// Accept out-of-process protocol plugins for the protocols in the config. See the [plugin]
+  /// module for details.
+  #[cfg(feature = "protocol-plugins")]
+  pub fn protocol_plugin_host(&mut self, config: ProtocolPluginHostConfig) -> &mut Self {
+    let registry = Arc::new(PluginRegistry::new(config.protocols()));
+    for protocol in config.protocols() {
+      self.protocol_factory(ProtocolPluginFactory::new(protocol, registry.clone()));
+    }
+    self.plugin_host_config = Some((config, registry));
+    self
+  }
+
+  /// Accept Lovense toy commands and forward them to devices, as described by the config. See the
+  /// [emulation] module for details.
+  #[cfg(feature = "lovense-emulator")]
+  pub fn lovense_emulator(&mut self, config: LovenseEmulatorConfig) -> &mut Self {
+    self.lovense_emulator_config = Some(config);
+    self
+  }
+
+  /// Drive devices from the level of an audio stream, as described by the config. See the [audio]
+  /// module for details.
+  #[cfg(feature = "audio-reactive")]
+  pub fn audio_reactive(&mut self, config: AudioReactiveConfig) -> &mut Self {
+    self.audio_reactive_config = Some(config);
+    self
+  }
+
+  /// Keep counters of handled messages, device errors and scanning state, which can be pulled with
+  /// [ButtplugServer::metrics]. See the [metrics] module for details.
+  pub fn enable_metrics(&mut self) -> &mut Self {
+    self.metrics_enabled = true;
+    self
+  }
+
   /// Try to build a [ButtplugServer] using the parameters given.
   pub fn finish(&mut self) -> Result<ButtplugServer, ButtplugServerError> {
     // Create the server
@@ -273,6 +498,67 @@ impl ButtplugServerBuilder {
 
     let device_manager = Arc::new(self.device_manager_builder.finish()?);
 
+    #[cfg(feature = "osc-bridge")]
+    let osc_bridge = self
+      .osc_bridge_config
+      .as_ref()
+      .map(|config| {
+        OscBridge::start(config, device_manager.clone()).map_err(|err| {
+          ButtplugServerError::OscBridgeError(config.listen_address().clone(), err.to_string())
+        })
+      })
+      .transpose()?;
+
+    #[cfg(feature = "protocol-plugins")]
+    let plugin_host = self
+      .plugin_host_config
+      .as_ref()
+      .map(|(config, registry)| {
+        ProtocolPluginHost::start(config, registry.clone()).map_err(|err| {
+          ButtplugServerError::PluginHostError(config.socket_path().clone(), err.to_string())
+        })
+      })
+      .transpose()?;
+
+    #[cfg(feature = "lovense-emulator")]
+    let lovense_emulator = self
+      .lovense_emulator_config
+      .as_ref()
+      .map(|config| {
+        LovenseEmulator::start(config, device_manager.clone()).map_err(|err| {
+          ButtplugServerError::LovenseEmulatorError(
+            config.listen_address().clone(),
+            err.to_string(),
+          )
+        })
+      })
+      .transpose()?;
+
+    #[cfg(feature = "audio-reactive")]
+    let audio_reactive = self
+      .audio_reactive_config
+      .as_ref()
+      .map(|config| {
+        AudioReactive::start(config, device_manager.clone())
+          .map_err(|err| ButtplugServerError::AudioReactiveError(err.to_string()))
+      })
+      .transpose()?;
+
+    let metrics = self.metrics_enabled.then(|| {
+      let metrics = Arc::new(ServerMetrics::default());
+      // Scanning can end on its own, which only shows up as an event.
+      let metrics_clone = metrics.clone();
+      let mut device_events = Box::pin(device_manager.event_stream());
+      async_manager::spawn(async move {
+        while let Some(event) = device_events.next().await {
+          if let ButtplugServerMessage::ScanningFinished(_) = event {
+            metrics_clone.set_scanning(false);
+          }
+        }
+      });
+      metrics
+    });
+
     let connected = Arc::new(AtomicBool::new(false));
     let connected_clone = connected.clone();
 
@@ -307,14 +593,42 @@ impl ButtplugServerBuilder {
       );
     }
 
+    let idle_watchdog = self.client_idle_timeout.map(|timeout| {
+      let device_manager = device_manager.clone();
+      ClientIdleWatchdog::new(timeout, connected.clone(), move || {
+        let stop_fut = device_manager.stop_all_devices();
+        async move {
+          if let Err(e) = stop_fut.await {
+            error!("Could not stop devices on client idle timeout: {:?}", e);
+          }
+        }
+        .boxed()
+      })
+    });
+
     // Assuming everything passed, return the server.
     Ok(ButtplugServer {
       server_name: self.name.clone(),
       max_ping_time: ping_time,
+      device_configuration_json: self.device_configuration_json.clone(),
       device_manager,
       ping_timer,
+      idle_watchdog,
       connected,
       output_sender,
+      #[cfg(feature = "osc-bridge")]
+      osc_bridge,
+      #[cfg(feature = "protocol-plugins")]
+      plugin_host,
+      #[cfg(feature = "lovense-emulator")]
+      lovense_emulator,
+      #[cfg(feature = "audio-reactive")]
+      audio_reactive,
+      metrics,
+      permission_policy: self.permission_policy.clone(),
+      client_permissions: Arc::new(Mutex::new((String::new(), ClientPermissions::default()))),
+      log_forwarding_layer: self.log_forwarding_layer.clone(),
+      log_forwarder: Arc::new(Mutex::new(None)),
     })
   }
 }
@@ -333,8 +647,12 @@ pub struct ButtplugServer {
   /// Note that this has nothing to do with communication medium specific pings, like those built
   /// into the Websocket protocol. This ping is specific to the Buttplug protocol.
   max_ping_time: u32,
+  /// Base device configuration the server was built with, kept for reloading user configurations.
+  device_configuration_json: Option<String>,
   /// Timer for managing ping time tracking, if max_ping_time > 0.
   ping_timer: Arc<PingTimer>,
+  /// Stops devices when the client goes quiet, if a client idle timeout was set.
+  idle_watchdog: Option<ClientIdleWatchdog>,
   /// Manages device discovery and communication.
   device_manager: Arc<ServerDeviceManager>,
   /// If true, client is currently connected to server
@@ -342,6 +660,29 @@ pub struct ButtplugServer {
   /// Broadcaster for server events. Receivers for this are handed out through the
   /// [ButtplugServer::event_stream()] method.
   output_sender: broadcast::Sender<ButtplugServerMessage>,
+  /// OSC listener, if configured. Stops when the server is dropped.
+  #[cfg(feature = "osc-bridge")]
+  osc_bridge: Option<OscBridge>,
+  /// Running protocol plugin host, if one was configured. Only held so it stops with the server.
+  #[cfg(feature = "protocol-plugins")]
+  #[allow(dead_code)]
+  plugin_host: Option<ProtocolPluginHost>,
+  /// Running Lovense emulator, if one was configured.
+  #[cfg(feature = "lovense-emulator")]
+  lovense_emulator: Option<LovenseEmulator>,
+  /// Running audio reactive processing, if one was configured.
+  #[cfg(feature = "audio-reactive")]
+  audio_reactive: Option<AudioReactive>,
+  /// Message counters, if metrics were enabled in the builder.
+  metrics: Option<Arc<ServerMetrics>>,
+  /// Permissions for each client name.
+  permission_policy: PermissionPolicy,
+  /// Name and permissions of the connected client, set during the handshake.
+  client_permissions: Arc<Mutex<(String, ClientPermissions)>>,
+  /// Layer logs are forwarded from, if the server was built with one.
+  log_forwarding_layer: Option<ButtplugLogForwardingLayer>,
+  /// Forwards logs to the client, if it asked for them with a RequestLog message.
+  log_forwarder: Arc<Mutex<Option<LogForwarder>>>,
 }
 
 impl std::fmt::Debug for ButtplugServer {
@@ -381,6 +722,71 @@ impl ButtplugServer {
     self.device_manager.clone()
   }
 
+  /// Address the OSC bridge is listening on, if one is running. Useful when the bridge was
+  /// configured to listen on port 0.
+  #[cfg(feature = "osc-bridge")]
+  pub fn osc_bridge_address(&self) -> Option<std::net::SocketAddr> {
+    self.osc_bridge.as_ref().map(|bridge| bridge.local_address())
+  }
+
+  /// Address the Lovense emulator is listening on, if one was configured. Useful when the emulator
+  /// is configured to listen on port 0.
+  #[cfg(feature = "lovense-emulator")]
+  pub fn lovense_emulator_address(&self) -> Option<std::net::SocketAddr> {
+    self
+      .lovense_emulator
+      .as_ref()
+      .map(|emulator| emulator.local_address())
+  }
+
+  /// Input for pushing PCM audio to the audio reactive subsystem, if one was configured.
+  #[cfg(feature = "audio-reactive")]
+  pub fn audio_input(&self) -> Option<AudioInput> {
+    self.audio_reactive.as_ref().map(|audio| audio.input())
+  }
+
+  /// Reload the user device configuration without restarting the server, reusing the base device
+  /// configuration the server was built with.
+  ///
+  /// Connected devices are left untouched, the new configuration only applies to devices found by
+  /// later scans. Note that this replaces the whole device configuration, so any specifiers,
+  /// attributes, or allow/deny entries added through the [ButtplugServerBuilder] methods won't
+  /// survive a reload.
+  pub fn reload_user_device_configuration(
+    &self,
+    user_config_json: Option<String>,
+  ) -> Result<DeviceConfigurationDiff, ButtplugServerError> {
+    let dcm_builder = load_protocol_configs(
+      self.device_configuration_json.clone(),
+      user_config_json,
+      false,
+    )
+    .map_err(ButtplugServerError::DeviceConfigurationManagerError)?;
+    self
+      .device_manager
+      .reload_device_configuration(&dcm_builder)
+  }
+
+  /// Suspend scanning while the embedding app is in the background, keeping devices connected. Meant
+  /// to be called from platform lifecycle hooks, e.g. a Swift wrapper's scene phase changes on iOS.
+  /// See [ServerDeviceManager::enter_background].
+  pub async fn enter_background(&self) {
+    self.device_manager.enter_background().await
+  }
+
+  /// Resume anything suspended by [ButtplugServer::enter_background].
+  pub async fn enter_foreground(&self) {
+    self.device_manager.enter_foreground().await
+  }
+
+  /// Snapshot of the server's metrics, or None if they weren't enabled with
+  /// [ButtplugServerBuilder::enable_metrics].
+  pub fn metrics(&self) -> Option<ServerMetricsSnapshot> {
+    self.metrics.as_ref().map(|metrics| {
+      metrics.snapshot(self.device_manager.devices().len(), self.connected())
+    })
+  }
+
   /// If true, client is currently connected to the server.
   pub fn connected(&self) -> bool {
     self.connected.load(Ordering::SeqCst)
@@ -396,6 +802,11 @@ impl ButtplugServer {
       StopAllDevices::default(),
     ));
     let connected = self.connected.clone();
+    self
+      .log_forwarder
+      .lock()
+      .expect("Lock is never held across a panic")
+      .take();
     async move {
       connected.store(false, Ordering::SeqCst);
       ping_timer.stop_ping_timer().await;
@@ -421,6 +832,13 @@ impl ButtplugServer {
       msg
     );
     let id = msg.id();
+    let message_type: &'static str = (&msg).into();
+    if let Some(metrics) = &self.metrics {
+      metrics.record_received(message_type);
+    }
+    if let Some(idle_watchdog) = &self.idle_watchdog {
+      idle_watchdog.record_activity();
+    }
     if !self.connected() {
       // Check for ping timeout first! There's no way we should've pinged out if
       // we haven't received RequestServerInfo first, but we do want to know if
@@ -437,32 +855,55 @@ impl ButtplugServer {
         None
       };
       if let Some(mut return_error) = error {
+        if let Some(metrics) = &self.metrics {
+          metrics.record_error(message_type, None);
+        }
         return_error.set_id(msg.id());
         return future::ready(Err(return_error)).boxed();
       }
       // If we haven't pinged out and we got an RSI message, fall thru.
+    } else {
+      let check = {
+        let client = self
+          .client_permissions
+          .lock()
+          .expect("Lock is never held across a panic");
+        client.1.check(&client.0, &msg)
+      };
+      if let Err(err) = check {
+        if let Some(metrics) = &self.metrics {
+          metrics.record_error(message_type, Some(&err));
+        }
+        let mut return_error = message::Error::from(err);
+        return_error.set_id(id);
+        return future::ready(Err(return_error)).boxed();
+      }
     }
     // Produce whatever future is needed to reply to the message, this may be a
     // device command future, or something the server handles. All futures will
     // return Result<ButtplugServerMessage, ButtplugError>, and we'll handle
     // tagging the result with the message id in the future we put out as the
     // return value from this method.
-    let out_fut = if ButtplugDeviceManagerMessageUnion::try_from(msg.clone()).is_ok()
-      || ButtplugDeviceCommandMessageUnion::try_from(msg.clone()).is_ok()
-    {
-      self.device_manager.parse_message(msg.clone())
-    } else {
-      match msg {
-        ButtplugClientMessage::RequestServerInfo(rsi_msg) => self.perform_handshake(rsi_msg),
-        ButtplugClientMessage::Ping(p) => self.handle_ping(p),
-        _ => ButtplugMessageError::UnexpectedMessageType(format!("{:?}", msg)).into(),
-      }
+    let out_fut = match msg {
+      ButtplugClientMessage::RequestServerInfo(rsi_msg) => self.perform_handshake(rsi_msg),
+      ButtplugClientMessage::Ping(p) => self.handle_ping(p),
+      ButtplugClientMessage::RequestLog(msg) => self.handle_request_log(msg),
+      // Everything else is moved on to the device manager, which rejects anything it can't handle
+      // either.
+      _ => self.device_manager.parse_message(msg),
     };
     // Simple way to set the ID on the way out. Just rewrap
     // the returned future to make sure it happens.
+    let metrics = self.metrics.clone();
     async move {
-      out_fut
-        .await
+      let result = out_fut.await;
+      if let Some(metrics) = metrics {
+        match &result {
+          Ok(_) => metrics.record_success(message_type),
+          Err(err) => metrics.record_error(message_type, Some(err)),
+        }
+      }
+      result
         .map(|mut ok_msg| {
           ok_msg.set_id(id);
           ok_msg
@@ -500,11 +941,21 @@ impl ButtplugServer {
     }
     // Only start the ping timer after we've received the handshake.
     let ping_timer = self.ping_timer.clone();
-    let out_msg =
+    let mut out_msg =
       message::ServerInfo::new(&self.server_name, msg.message_version(), self.max_ping_time);
+    // Older clients don't know about scheduled commands, and may reject fields they don't expect.
+    if msg.message_version() == BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION {
+      out_msg.set_server_time(Some(self.device_manager.clock().now()));
+    }
     let connected = self.connected.clone();
+    let permissions = self.permission_policy.permissions_for(msg.client_name());
+    let client_permissions = self.client_permissions.clone();
+    let client_name = msg.client_name().to_owned();
     async move {
       ping_timer.start_ping_timer().await;
+      *client_permissions
+        .lock()
+        .expect("Lock is never held across a panic") = (client_name, permissions);
       connected.store(true, Ordering::SeqCst);
       debug!("Server handshake check successful.");
       Result::Ok(out_msg.into())
@@ -525,6 +976,25 @@ impl ButtplugServer {
     .boxed()
   }
 
+  /// Start forwarding logs at the requested level to the client, replacing any previous request.
+  /// A level of Off stops forwarding. Only logs picked up by the layer the server was built with
+  /// are forwarded, servers without one reject the request.
+  fn handle_request_log(&self, msg: message::RequestLog) -> ButtplugServerResultFuture {
+    let Some(layer) = &self.log_forwarding_layer else {
+      return ButtplugMessageError::UnhandledMessage(
+        "Server was not built with a log forwarding layer".to_owned(),
+      )
+      .into();
+    };
+    let mut log_forwarder = self
+      .log_forwarder
+      .lock()
+      .expect("Lock is never held across a panic");
+    *log_forwarder = (msg.log_level() != LogLevel::Off)
+      .then(|| LogForwarder::new(layer, msg.log_level(), self.output_sender.clone()));
+    future::ready(Result::Ok(message::Ok::new(msg.id()).into())).boxed()
+  }
+
   pub fn shutdown(&self) -> ButtplugServerResultFuture {
     let device_manager = self.device_manager.clone();
     //let disconnect_future = self.disconnect();
@@ -0,0 +1,618 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Multiple clients sharing one server
+//!
+//! A [ButtplugServer] only talks to a single client at a time. The [ButtplugServerMultiplexer]
+//! sits in front of a server and lets any number of clients share it, each through its own
+//! [MultiplexedClient] handle, which takes the same messages and emits the same events as the server
+//! itself.
+//!
+//! Each client does its own handshake. The multiplexer handshakes with the underlying server once,
+//! when the first client connects, so the server's ping timer (if any) is kept alive by pings from
+//! any client.
+//!
+//! # Device claims
+//!
+//! Sending an output command (vibrate, rotate, linear, raw writes, etc) to a device claims it for
+//! the sending client. What happens when another client then sends output commands to the same
+//! device depends on the [DeviceClaimPolicy]:
+//!
+//! - [Exclusive](DeviceClaimPolicy::Exclusive): Commands from other clients are rejected until the
+//!   claiming client releases the device, or disconnects.
+//! - [LastWriterWins](DeviceClaimPolicy::LastWriterWins): Commands from any client are accepted,
+//!   and the claim moves to whoever sent the latest command.
+//!
+//! StopDeviceCmd is always accepted, from any client, and doesn't claim the device. StopAllDevices
+//! only stops the devices claimed by the client sending it. When a client disconnects, or its
+//! handle is dropped, the devices it claimed are stopped and released.
+//!
+//! Clients in the same process can connect through a [ButtplugMultiplexedClientConnector], made
+//! with [ButtplugServerMultiplexer::client_connector], the same way they'd use an in-process
+//! connector with a server of their own.
+//!
+//! # Permissions
+//!
+//! The server's [PermissionPolicy](super::permissions::PermissionPolicy) is applied to each client
+//! separately, based on the name that client sent in its own handshake.
+//!
+//! # Event routing
+//!
+//! Device added/removed, scanning, and error events go to every client. Sensor and raw readings
+//! from subscriptions only go to the clients that subscribed to that sensor or endpoint on the
+//! device. The server is only asked to unsubscribe once the last client subscribed to a sensor or
+//! endpoint unsubscribes or disconnects.
+//!
+//! [ButtplugMultiplexedClientConnector]: crate::core::connector::ButtplugMultiplexedClientConnector
+
+use super::{permissions::ClientPermissions, ButtplugServer};
+use crate::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
+    message::{
+      self,
+      ButtplugClientMessage,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      RawUnsubscribeCmd,
+      RequestServerInfo,
+      SensorType,
+      SensorUnsubscribeCmd,
+      ServerInfo,
+      StopDeviceCmd,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  util::{async_manager, stream::convert_broadcast_receiver_to_stream},
+};
+use dashmap::{DashMap, DashSet};
+use futures::{
+  future::{self, BoxFuture, FutureExt},
+  Stream,
+  StreamExt,
+};
+use std::sync::{
+  atomic::{AtomicBool, AtomicU32, Ordering},
+  Arc,
+  Mutex,
+  Weak,
+};
+use tokio::sync::{broadcast, OnceCell};
+
+type MessageResult = Result<ButtplugServerMessage, message::Error>;
+
+/// What a subscription reads from on a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SubscriptionTarget {
+  Sensor(u32, SensorType),
+  Raw(Endpoint),
+}
+
+impl SubscriptionTarget {
+  fn unsubscribe_message(&self, device_index: u32) -> ButtplugClientMessage {
+    match self {
+      SubscriptionTarget::Sensor(sensor_index, sensor_type) => {
+        SensorUnsubscribeCmd::new(device_index, *sensor_index, *sensor_type).into()
+      }
+      SubscriptionTarget::Raw(endpoint) => RawUnsubscribeCmd::new(device_index, *endpoint).into(),
+    }
+  }
+}
+
+/// How conflicting output commands from different clients are handled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClaimPolicy {
+  /// Only the client that claimed a device can send it output commands.
+  #[default]
+  Exclusive,
+  /// Any client can send output commands, and the latest one to do so owns the claim.
+  LastWriterWins,
+}
+
+struct MultiplexerState {
+  server: ButtplugServer,
+  policy: DeviceClaimPolicy,
+  /// Reply to the multiplexer's own handshake with the server.
+  server_info: OnceCell<ServerInfo>,
+  next_client_id: AtomicU32,
+  /// Event senders for connected clients, keyed by client id.
+  clients: DashMap<u32, broadcast::Sender<ButtplugServerMessage>>,
+  /// Client id holding the claim on each device, keyed by device index.
+  claims: DashMap<u32, u32>,
+  /// (device index, subscription target, client id) for each client subscription.
+  subscriptions: DashSet<(u32, SubscriptionTarget, u32)>,
+}
+
+impl MultiplexerState {
+  fn route_event(&self, event: ButtplugServerMessage) {
+    let subscription = match &event {
+      ButtplugServerMessage::SensorReading(msg) => Some((
+        msg.device_index(),
+        SubscriptionTarget::Sensor(msg.sensor_index(), msg.sensor_type()),
+      )),
+      ButtplugServerMessage::RawReading(msg) => {
+        Some((msg.device_index(), SubscriptionTarget::Raw(msg.endpoint())))
+      }
+      ButtplugServerMessage::DeviceRemoved(msg) => {
+        self.claims.remove(&msg.device_index());
+        self
+          .subscriptions
+          .retain(|(device_index, _, _)| *device_index != msg.device_index());
+        None
+      }
+      _ => None,
+    };
+    for client in self.clients.iter() {
+      if subscription.is_none_or(|(device_index, target)| {
+        self
+          .subscriptions
+          .contains(&(device_index, target, *client.key()))
+      }) {
+        // Clients without a live event stream just miss the event.
+        let _ = client.value().send(event.clone());
+      }
+    }
+  }
+
+  /// Claim a device for a client, if the policy allows it.
+  fn claim(&self, client_id: u32, device_index: u32) -> Result<(), ButtplugError> {
+    let mut claim = self.claims.entry(device_index).or_insert(client_id);
+    if *claim != client_id {
+      if self.policy == DeviceClaimPolicy::Exclusive {
+        return Err(ButtplugDeviceError::DeviceClaimedByOtherClient(device_index).into());
+      }
+      *claim = client_id;
+    }
+    Ok(())
+  }
+
+  fn claimed_devices(&self, client_id: u32) -> Vec<u32> {
+    self
+      .claims
+      .iter()
+      .filter(|claim| *claim.value() == client_id)
+      .map(|claim| *claim.key())
+      .collect()
+  }
+
+  /// Stop a client's claimed devices. Claims are released right away, stops go out when the
+  /// returned future is polled.
+  fn stop_claimed_devices(&self, client_id: u32) -> BoxFuture<'static, Result<(), ButtplugError>> {
+    let stop_futs: Vec<_> = self
+      .claimed_devices(client_id)
+      .into_iter()
+      .map(|device_index| {
+        self.claims.remove(&device_index);
+        self
+          .server
+          .parse_message(StopDeviceCmd::new(device_index).into())
+      })
+      .collect();
+    async move {
+      for result in future::join_all(stop_futs).await {
+        result.map_err(|err| err.original_error())?;
+      }
+      Ok(())
+    }
+    .boxed()
+  }
+
+  fn has_subscribers(&self, device_index: u32, target: SubscriptionTarget) -> bool {
+    self
+      .subscriptions
+      .iter()
+      .any(|subscription| subscription.0 == device_index && subscription.1 == target)
+  }
+
+  /// Remove a client, stopping and releasing its devices, and unsubscribing from anything no other
+  /// client is subscribed to.
+  fn remove_client(&self, client_id: u32) -> BoxFuture<'static, Result<(), ButtplugError>> {
+    self.clients.remove(&client_id);
+    let client_subscriptions: Vec<_> = self
+      .subscriptions
+      .iter()
+      .filter(|subscription| subscription.2 == client_id)
+      .map(|subscription| *subscription)
+      .collect();
+    let unsubscribe_futs: Vec<_> = client_subscriptions
+      .into_iter()
+      .filter_map(|subscription| {
+        self.subscriptions.remove(&subscription);
+        let (device_index, target, _) = subscription;
+        (!self.has_subscribers(device_index, target)).then(|| {
+          self
+            .server
+            .parse_message(target.unsubscribe_message(device_index))
+        })
+      })
+      .collect();
+    let stop_fut = self.stop_claimed_devices(client_id);
+    async move {
+      for result in future::join_all(unsubscribe_futs).await {
+        // The device may be gone already, which unsubscribes everything anyway.
+        if let Err(err) = result {
+          debug!("Could not unsubscribe for removed client: {:?}", err);
+        }
+      }
+      stop_fut.await
+    }
+    .boxed()
+  }
+}
+
+/// Lets multiple clients share a single [ButtplugServer]. See the [module level
+/// documentation](self) for details.
+///
+/// Clones share the same server and clients.
+#[derive(Clone)]
+pub struct ButtplugServerMultiplexer {
+  state: Arc<MultiplexerState>,
+}
+
+impl ButtplugServerMultiplexer {
+  /// Take over a server. The server should not be used by anything else afterward.
+  pub fn new(server: ButtplugServer, policy: DeviceClaimPolicy) -> Self {
+    let server_events = server.event_stream();
+    let state = Arc::new(MultiplexerState {
+      server,
+      policy,
+      server_info: OnceCell::new(),
+      next_client_id: AtomicU32::new(1),
+      clients: DashMap::new(),
+      claims: DashMap::new(),
+      subscriptions: DashSet::new(),
+    });
+    let weak_state = Arc::downgrade(&state);
+    async_manager::spawn(async move {
+      pin_mut!(server_events);
+      while let Some(event) = server_events.next().await {
+        let Some(state) = weak_state.upgrade() else {
+          break;
+        };
+        state.route_event(event);
+      }
+      debug!("Exiting server multiplexer event loop.");
+    });
+    Self { state }
+  }
+
+  /// The server all clients are sharing.
+  pub fn server(&self) -> &ButtplugServer {
+    &self.state.server
+  }
+
+  /// How conflicting output commands are handled.
+  pub fn policy(&self) -> DeviceClaimPolicy {
+    self.state.policy
+  }
+
+  /// Id of the client currently claiming a device, if any.
+  pub fn device_claim(&self, device_index: u32) -> Option<u32> {
+    self.state.claims.get(&device_index).map(|claim| *claim)
+  }
+
+  /// Connector for a [ButtplugClient](crate::client::ButtplugClient) in the same process. Each
+  /// connection made through it is a new client of the multiplexer.
+  #[cfg(feature = "client")]
+  pub fn client_connector(&self) -> crate::core::connector::ButtplugMultiplexedClientConnector {
+    crate::core::connector::ButtplugMultiplexedClientConnector::new(self.clone())
+  }
+
+  /// Add a new client. The client still has to send RequestServerInfo before anything else.
+  pub fn connect_client(&self) -> MultiplexedClient {
+    let id = self.state.next_client_id.fetch_add(1, Ordering::SeqCst);
+    let (event_sender, _) = broadcast::channel(256);
+    self.state.clients.insert(id, event_sender.clone());
+    MultiplexedClient {
+      id,
+      state: Arc::downgrade(&self.state),
+      event_sender,
+      handshake_done: Arc::new(AtomicBool::new(false)),
+      connected: AtomicBool::new(true),
+      permissions: Arc::new(Mutex::new((String::new(), ClientPermissions::default()))),
+    }
+  }
+}
+
+/// A single client's connection to a [ButtplugServerMultiplexer]. Used like a [ButtplugServer].
+pub struct MultiplexedClient {
+  id: u32,
+  state: Weak<MultiplexerState>,
+  event_sender: broadcast::Sender<ButtplugServerMessage>,
+  handshake_done: Arc<AtomicBool>,
+  connected: AtomicBool,
+  /// Name and permissions of this client, set during its handshake.
+  permissions: Arc<Mutex<(String, ClientPermissions)>>,
+}
+
+impl Drop for MultiplexedClient {
+  fn drop(&mut self) {
+    // Clients that go away without disconnecting still shouldn't leave devices running.
+    if self.connected.load(Ordering::SeqCst) {
+      if let Some(state) = self.state.upgrade() {
+        let fut = state.remove_client(self.id);
+        async_manager::spawn(async move {
+          if let Err(err) = fut.await {
+            error!("Could not stop devices for dropped client: {:?}", err);
+          }
+        });
+      }
+    }
+  }
+}
+
+impl MultiplexedClient {
+  /// Id of this client, as used in [ButtplugServerMultiplexer::device_claim].
+  pub fn id(&self) -> u32 {
+    self.id
+  }
+
+  /// True after a successful handshake, until disconnect.
+  pub fn connected(&self) -> bool {
+    self.connected.load(Ordering::SeqCst) && self.handshake_done.load(Ordering::SeqCst)
+  }
+
+  /// Events for this client. See [ButtplugServer::event_stream].
+  pub fn event_stream(&self) -> impl Stream<Item = ButtplugServerMessage> {
+    convert_broadcast_receiver_to_stream(self.event_sender.subscribe())
+  }
+
+  /// Give up this client's claim on a device, without stopping it.
+  pub fn release_device(&self, device_index: u32) {
+    if let Some(state) = self.state.upgrade() {
+      state
+        .claims
+        .remove_if(&device_index, |_, client_id| *client_id == self.id);
+    }
+  }
+
+  /// Stop and release all devices claimed by this client, and stop routing events to it.
+  pub fn disconnect(&self) -> BoxFuture<'static, Result<(), ButtplugError>> {
+    if !self.connected.swap(false, Ordering::SeqCst) {
+      return future::ready(Ok(())).boxed();
+    }
+    self.handshake_done.store(false, Ordering::SeqCst);
+    match self.state.upgrade() {
+      Some(state) => state.remove_client(self.id),
+      None => future::ready(Ok(())).boxed(),
+    }
+  }
+
+  /// Handle a message from this client. See [ButtplugServer::parse_message].
+  pub fn parse_message(&self, msg: ButtplugClientMessage) -> BoxFuture<'static, MessageResult> {
+    let id = msg.id();
+    let result = self.dispatch_message(msg);
+    async move {
+      result.await.map_err(|err| {
+        let mut error = message::Error::from(err);
+        error.set_id(id);
+        error
+      })
+    }
+    .boxed()
+  }
+
+  fn dispatch_message(
+    &self,
+    msg: ButtplugClientMessage,
+  ) -> BoxFuture<'static, Result<ButtplugServerMessage, ButtplugError>> {
+    let Some(state) = self
+      .state
+      .upgrade()
+      .filter(|_| self.connected.load(Ordering::SeqCst))
+    else {
+      return future::ready(Err(
+        ButtplugHandshakeError::RequestServerInfoExpected.into(),
+      ))
+      .boxed();
+    };
+    let handshake_done = self.handshake_done.load(Ordering::SeqCst);
+    if let ButtplugClientMessage::RequestServerInfo(rsi) = msg {
+      if handshake_done {
+        return future::ready(Err(ButtplugHandshakeError::HandshakeAlreadyHappened.into())).boxed();
+      }
+      return self.perform_handshake(state, rsi);
+    }
+    if !handshake_done {
+      return future::ready(Err(
+        ButtplugHandshakeError::RequestServerInfoExpected.into(),
+      ))
+      .boxed();
+    }
+    let check = {
+      let client = self
+        .permissions
+        .lock()
+        .expect("Lock is never held across a panic");
+      client.1.check(&client.0, &msg)
+    };
+    if let Err(err) = check {
+      return future::ready(Err(err)).boxed();
+    }
+
+    let server_fut = |msg: ButtplugClientMessage| {
+      let fut = state.server.parse_message(msg);
+      async move { fut.await.map_err(|err| err.original_error()) }.boxed()
+    };
+    match &msg {
+      ButtplugClientMessage::StopAllDevices(stop) => {
+        let stop_id = stop.id();
+        let fut = state.stop_claimed_devices(self.id);
+        return async move {
+          fut.await?;
+          Ok(message::Ok::new(stop_id).into())
+        }
+        .boxed();
+      }
+      ButtplugClientMessage::SensorSubscribeCmd(_) | ButtplugClientMessage::RawSubscribeCmd(_) => {
+        let (device_index, target) = subscription_target(&msg);
+        // The server is already sending readings for this, so just start routing them here too.
+        if state.has_subscribers(device_index, target) {
+          state.subscriptions.insert((device_index, target, self.id));
+          return future::ready(Ok(message::Ok::new(msg.id()).into())).boxed();
+        }
+        let client_id = self.id;
+        let fut = server_fut(msg);
+        return async move {
+          let result = fut.await;
+          if result.is_ok() {
+            state
+              .subscriptions
+              .insert((device_index, target, client_id));
+          }
+          result
+        }
+        .boxed();
+      }
+      ButtplugClientMessage::SensorUnsubscribeCmd(_)
+      | ButtplugClientMessage::RawUnsubscribeCmd(_) => {
+        let (device_index, target) = subscription_target(&msg);
+        state.subscriptions.remove(&(device_index, target, self.id));
+        // Other clients may still want readings from the sensor.
+        if state.has_subscribers(device_index, target) {
+          return future::ready(Ok(message::Ok::new(msg.id()).into())).boxed();
+        }
+      }
+      ButtplugClientMessage::GroupScalarCmd(group_cmd) => {
+        // Group commands drive every member of the group, so need a claim on each of them. Unknown
+        // groups are left for the device manager to reject.
+        let members = state
+          .server
+          .device_manager()
+          .device_group_members(group_cmd.group_name())
+          .unwrap_or_default();
+        if let Err(err) = members
+          .into_iter()
+          .try_for_each(|device_index| state.claim(self.id, device_index))
+        {
+          return future::ready(Err(err)).boxed();
+        }
+      }
+      _ => {
+        if let Some(device_index) = output_command_device_index(&msg) {
+          if let Err(err) = state.claim(self.id, device_index) {
+            return future::ready(Err(err)).boxed();
+          }
+        }
+      }
+    }
+    server_fut(msg)
+  }
+
+  fn perform_handshake(
+    &self,
+    state: Arc<MultiplexerState>,
+    msg: RequestServerInfo,
+  ) -> BoxFuture<'static, Result<ButtplugServerMessage, ButtplugError>> {
+    if BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION < msg.message_version() {
+      return future::ready(Err(
+        ButtplugHandshakeError::MessageSpecVersionMismatch(
+          BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+          msg.message_version(),
+        )
+        .into(),
+      ))
+      .boxed();
+    }
+    info!(
+      "Multiplexed client {} connecting as {}.",
+      self.id,
+      msg.client_name()
+    );
+    let handshake_done = self.handshake_done.clone();
+    let permissions = self.permissions.clone();
+    async move {
+      let server_info = state
+        .server_info
+        .get_or_try_init(|| async {
+          let rsi = RequestServerInfo::new(
+            "Buttplug Server Multiplexer",
+            BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+          );
+          match state.server.parse_message(rsi.into()).await {
+            Ok(ButtplugServerMessage::ServerInfo(server_info)) => {
+              // Permissions are checked per client here, so the server itself shouldn't apply the
+              // ones for the multiplexer's name on top.
+              *state
+                .server
+                .client_permissions
+                .lock()
+                .expect("Lock is never held across a panic") =
+                (String::new(), ClientPermissions::default());
+              Ok(server_info)
+            }
+            Ok(other) => Err(ButtplugError::from(
+              ButtplugHandshakeError::UnexpectedHandshakeMessageReceived(format!("{:?}", other)),
+            )),
+            Err(err) => Err(err.original_error()),
+          }
+        })
+        .await?;
+      *permissions
+        .lock()
+        .expect("Lock is never held across a panic") = (
+        msg.client_name().to_owned(),
+        state
+          .server
+          .permission_policy
+          .permissions_for(msg.client_name()),
+      );
+      handshake_done.store(true, Ordering::SeqCst);
+      let mut reply = ServerInfo::new(
+        server_info.server_name(),
+        msg.message_version(),
+        server_info.max_ping_time(),
+      );
+      if msg.message_version() == BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION {
+        reply.set_server_time(Some(state.server.device_manager().clock().now()));
+      }
+      reply.set_id(msg.id());
+      Ok(reply.into())
+    }
+    .boxed()
+  }
+}
+
+/// Device index and target of a subscribe or unsubscribe message.
+fn subscription_target(msg: &ButtplugClientMessage) -> (u32, SubscriptionTarget) {
+  let target = match msg {
+    ButtplugClientMessage::SensorSubscribeCmd(m) => {
+      SubscriptionTarget::Sensor(*m.sensor_index(), *m.sensor_type())
+    }
+    ButtplugClientMessage::SensorUnsubscribeCmd(m) => {
+      SubscriptionTarget::Sensor(*m.sensor_index(), *m.sensor_type())
+    }
+    ButtplugClientMessage::RawSubscribeCmd(m) => SubscriptionTarget::Raw(m.endpoint()),
+    ButtplugClientMessage::RawUnsubscribeCmd(m) => SubscriptionTarget::Raw(m.endpoint()),
+    _ => unreachable!("Only called on subscription messages"),
+  };
+  (
+    msg
+      .device_index()
+      .expect("Subscription messages are device commands"),
+    target,
+  )
+}
+
+/// Device index for messages that change a device's output, and so need a claim on it.
+fn output_command_device_index(msg: &ButtplugClientMessage) -> Option<u32> {
+  match msg {
+    ButtplugClientMessage::ScalarCmd(_)
+    | ButtplugClientMessage::VibrateCmd(_)
+    | ButtplugClientMessage::SingleMotorVibrateCmd(_)
+    | ButtplugClientMessage::RotateCmd(_)
+    | ButtplugClientMessage::LinearCmd(_)
+    | ButtplugClientMessage::FleshlightLaunchFW12Cmd(_)
+    | ButtplugClientMessage::VorzeA10CycloneCmd(_)
+    | ButtplugClientMessage::KiirooCmd(_)
+    | ButtplugClientMessage::PlayPatternCmd(_)
+    | ButtplugClientMessage::RawWriteCmd(_) => msg.device_index(),
+    _ => None,
+  }
+}
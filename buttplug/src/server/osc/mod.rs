@@ -0,0 +1,418 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Open Sound Control (OSC) bridge
+//!
+//! Lots of VR applications (VRChat avatar parameters being the most common) can emit OSC messages
+//! for haptics. The bridge listens for OSC on a UDP port, and turns messages matching configured
+//! address patterns into ScalarCmds for connected devices, without needing a client.
+//!
+//! The bridge is configured with an [OscBridgeConfig], usually as part of a server configuration
+//! file:
+//!
+//! ```json
+//! {
+//!   "listen-address": "127.0.0.1:9001",
+//!   "routes": [
+//!     {
+//!       "address": "/avatar/parameters/Haptic*",
+//!       "device-name": "Lovense*",
+//!       "actuator-index": 0,
+//!       "scale": 0.8
+//!     }
+//!   ],
+//!   "min-update-interval-ms": 50
+//! }
+//! ```
+//!
+//! Each route takes the first numeric argument of a matching message (booleans count as 0 or 1),
+//! multiplies it by the route scale, clamps it to 0.0-1.0, and sends it to every selected device.
+//! Messages whose value is NaN or infinite are dropped.
+//! Devices can be selected by name (with the same wildcards as OSC addresses) and/or index. If
+//! neither is given, the route applies to all devices. If no actuator index is given, all scalar
+//! actuators on the device are set.
+//!
+//! Messages are forwarded to devices one at a time, in the order they were received. Senders can
+//! easily put out updates faster than a device can take them, so updates to an OSC address that
+//! come in less than `min-update-interval-ms` after the last one sent for it are held back, and
+//! only the latest held back value is sent once the interval has passed.
+
+mod packet;
+
+pub use packet::{address_pattern_matches, decode_packet, OscArgument, OscMessage, OscPacketError};
+
+use super::device::ServerDeviceManager;
+use crate::{
+  core::message::{ScalarCmd, ScalarSubcommand},
+  util::{self, async_manager},
+};
+use futures::{future, select, FutureExt};
+use getset::{CopyGetters, Getters, Setters};
+use instant::Instant;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  net::{SocketAddr, UdpSocket},
+  sync::Arc,
+  time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Largest OSC packet we'll accept. OSC over UDP is limited by the datagram size anyways.
+const OSC_MAX_PACKET_SIZE: usize = 65536;
+/// Number of messages that can wait to be forwarded to devices before new ones are dropped.
+const OSC_MESSAGE_QUEUE_SIZE: usize = 256;
+
+fn default_min_update_interval_ms() -> u32 {
+  50
+}
+
+fn default_scale() -> f64 {
+  1.0
+}
+
+/// Maps OSC messages with matching addresses to scalar commands on a set of devices.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters, Setters)]
+#[serde(rename_all = "kebab-case")]
+pub struct OscRoute {
+  /// OSC address pattern to match incoming messages against.
+  #[getset(get = "pub")]
+  address: String,
+  /// Only send to devices with this name. Supports OSC pattern wildcards.
+  #[getset(get = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  device_name: Option<String>,
+  /// Only send to the device at this index.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  device_index: Option<u32>,
+  /// Only set this scalar actuator. If not set, all scalar actuators on the device are set.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  actuator_index: Option<u32>,
+  /// Multiplier for incoming values, applied before clamping to 0.0-1.0.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default = "default_scale")]
+  scale: f64,
+}
+
+impl OscRoute {
+  /// Create a route for an address pattern that sends to all devices.
+  pub fn new(address: &str) -> Self {
+    Self {
+      address: address.to_owned(),
+      device_name: None,
+      device_index: None,
+      actuator_index: None,
+      scale: default_scale(),
+    }
+  }
+
+  fn selects_device(&self, index: u32, name: &str) -> bool {
+    self
+      .device_index
+      .is_none_or(|device_index| device_index == index)
+      && self
+        .device_name
+        .as_ref()
+        .is_none_or(|device_name| address_pattern_matches(device_name, name))
+  }
+}
+
+/// OSC bridge configuration. See the [module documentation](self) for the JSON format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters, Setters)]
+#[serde(rename_all = "kebab-case")]
+pub struct OscBridgeConfig {
+  /// Local address to listen for OSC packets on, e.g. `127.0.0.1:9001`.
+  #[getset(get = "pub")]
+  listen_address: String,
+  #[getset(get = "pub")]
+  routes: Vec<OscRoute>,
+  /// Shortest time between updates sent for the same OSC address. 0 sends every update.
+  #[getset(get_copy = "pub", set = "pub")]
+  #[serde(default = "default_min_update_interval_ms")]
+  min_update_interval_ms: u32,
+}
+
+impl OscBridgeConfig {
+  pub fn new(listen_address: &str, routes: &[OscRoute]) -> Self {
+    Self {
+      listen_address: listen_address.to_owned(),
+      routes: routes.to_vec(),
+      min_update_interval_ms: default_min_update_interval_ms(),
+    }
+  }
+
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    serde_json::from_str(json)
+  }
+}
+
+/// Holds back updates to OSC addresses that were sent to less than the minimum update interval ago.
+struct OscRateLimiter {
+  min_update_interval: Duration,
+  last_sent: HashMap<String, Instant>,
+  held: Vec<OscMessage>,
+}
+
+impl OscRateLimiter {
+  fn new(min_update_interval: Duration) -> Self {
+    Self {
+      min_update_interval,
+      last_sent: HashMap::new(),
+      held: vec![],
+    }
+  }
+
+  /// Returns the message if it can be sent now, otherwise holds it back in place of any older
+  /// update to the same address.
+  fn submit(&mut self, message: OscMessage, now: Instant) -> Option<OscMessage> {
+    self.held.retain(|held| held.address() != message.address());
+    let interval = self.min_update_interval;
+    if self
+      .last_sent
+      .get(message.address())
+      .is_some_and(|sent| now.duration_since(*sent) < interval)
+    {
+      self.held.push(message);
+      None
+    } else {
+      self.last_sent.insert(message.address().clone(), now);
+      Some(message)
+    }
+  }
+
+  /// Time the earliest held back message can be sent at.
+  fn next_due(&self) -> Option<Instant> {
+    self
+      .held
+      .iter()
+      .filter_map(|held| self.last_sent.get(held.address()))
+      .min()
+      .map(|sent| *sent + self.min_update_interval)
+  }
+
+  /// Take all held back messages that can be sent now, in the order they were held.
+  fn take_due(&mut self, now: Instant) -> Vec<OscMessage> {
+    let interval = self.min_update_interval;
+    let last_sent = &mut self.last_sent;
+    // Addresses that haven't been sent to in a while don't need tracking anymore, anything held
+    // back was sent to within the interval so won't be pruned.
+    last_sent.retain(|_, sent| now.duration_since(*sent) < interval);
+    let (due, held) = self
+      .held
+      .drain(..)
+      .partition(|held| !last_sent.contains_key(held.address()));
+    self.held = held;
+    for message in &due {
+      last_sent.insert(message.address().clone(), now);
+    }
+    due
+  }
+}
+
+async fn handle_message(
+  message: &OscMessage,
+  routes: &[OscRoute],
+  device_manager: &ServerDeviceManager,
+) {
+  let Some(value) = message.value() else {
+    return;
+  };
+  // Build all commands before sending anything, so we don't hold on to the device map while
+  // waiting on devices.
+  let mut commands = vec![];
+  for route in routes
+    .iter()
+    .filter(|route| address_pattern_matches(route.address(), message.address()))
+  {
+    let scalar = (value * route.scale()).clamp(0.0, 1.0);
+    for device in device_manager.devices().iter() {
+      let index = *device.key();
+      if !route.selects_device(index, &device.name()) {
+        continue;
+      }
+      let Some(attrs) = device.message_attributes().scalar_cmd().clone() else {
+        continue;
+      };
+      let subcommands: Vec<ScalarSubcommand> = attrs
+        .iter()
+        .enumerate()
+        .filter(|(actuator_index, _)| {
+          route
+            .actuator_index()
+            .is_none_or(|route_index| route_index == *actuator_index as u32)
+        })
+        .map(|(actuator_index, attr)| {
+          ScalarSubcommand::new(actuator_index as u32, scalar, *attr.actuator_type())
+        })
+        .collect();
+      if subcommands.is_empty() {
+        continue;
+      }
+      commands.push((
+        index,
+        device.parse_message(ScalarCmd::new(index, subcommands).into()),
+      ));
+    }
+  }
+  for (index, fut) in commands {
+    if let Err(err) = fut.await {
+      error!("OSC bridge could not update device {}: {:?}", index, err);
+    }
+  }
+}
+
+/// Forwards messages from the listener to devices one at a time, so updates reach devices in the
+/// order they were received.
+async fn run_message_consumer(
+  mut receiver: mpsc::Receiver<OscMessage>,
+  routes: Vec<OscRoute>,
+  min_update_interval: Duration,
+  device_manager: Arc<ServerDeviceManager>,
+) {
+  let mut rate_limiter = OscRateLimiter::new(min_update_interval);
+  loop {
+    let flush = match rate_limiter.next_due() {
+      Some(due) => util::sleep(due.saturating_duration_since(Instant::now())).boxed(),
+      None => future::pending().boxed(),
+    };
+    let messages = select! {
+      message = receiver.recv().fuse() => match message {
+        Some(message) => rate_limiter.submit(message, Instant::now()).into_iter().collect(),
+        None => break,
+      },
+      _ = flush.fuse() => rate_limiter.take_due(Instant::now()),
+    };
+    for message in messages {
+      handle_message(&message, &routes, &device_manager).await;
+    }
+  }
+}
+
+/// Running OSC listener. Stops listening when dropped.
+pub(crate) struct OscBridge {
+  local_address: SocketAddr,
+  cancellation_token: CancellationToken,
+}
+
+impl OscBridge {
+  /// Bind the listen address and start forwarding messages to the device manager. Binding happens
+  /// immediately, so address errors are reported here instead of from the listener task.
+  pub(crate) fn start(
+    config: &OscBridgeConfig,
+    device_manager: Arc<ServerDeviceManager>,
+  ) -> Result<Self, std::io::Error> {
+    let socket = UdpSocket::bind(config.listen_address())?;
+    socket.set_nonblocking(true)?;
+    let local_address = socket.local_addr()?;
+    info!("OSC bridge listening on {}", local_address);
+    let cancellation_token = CancellationToken::new();
+    let child_token = cancellation_token.child_token();
+    let routes = config.routes().clone();
+    let (message_sender, message_receiver) = mpsc::channel(OSC_MESSAGE_QUEUE_SIZE);
+    // The consumer stops once the listener drops the sender.
+    async_manager::spawn(run_message_consumer(
+      message_receiver,
+      routes.clone(),
+      Duration::from_millis(config.min_update_interval_ms().into()),
+      device_manager,
+    ));
+    async_manager::spawn(async move {
+      let socket = match tokio::net::UdpSocket::from_std(socket) {
+        Ok(socket) => socket,
+        Err(err) => {
+          error!("Cannot start OSC bridge listener: {}", err);
+          return;
+        }
+      };
+      let mut buf = vec![0u8; OSC_MAX_PACKET_SIZE];
+      loop {
+        let len = select! {
+          _ = child_token.cancelled().fuse() => break,
+          result = socket.recv_from(&mut buf).fuse() => match result {
+            Ok((len, _)) => len,
+            Err(err) => {
+              error!("OSC bridge socket error, stopping bridge: {}", err);
+              break;
+            }
+          }
+        };
+        match decode_packet(&buf[..len]) {
+          Ok(messages) => {
+            for message in messages {
+              trace!("OSC bridge received {:?}", message);
+              // Drop anything we'd ignore before it can take the place of a valid held back value.
+              if message.value().is_none()
+                || !routes
+                  .iter()
+                  .any(|route| address_pattern_matches(route.address(), message.address()))
+              {
+                continue;
+              }
+              if message_sender.try_send(message).is_err() {
+                warn!("OSC bridge can't keep up with incoming messages, dropping message.");
+              }
+            }
+          }
+          Err(err) => warn!("OSC bridge received invalid packet: {}", err),
+        }
+      }
+      debug!("OSC bridge stopped.");
+    });
+    Ok(Self {
+      local_address,
+      cancellation_token,
+    })
+  }
+
+  pub(crate) fn local_address(&self) -> SocketAddr {
+    self.local_address
+  }
+}
+
+impl Drop for OscBridge {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_rate_limiter_holds_latest_update() {
+    let interval = Duration::from_millis(50);
+    let mut limiter = OscRateLimiter::new(interval);
+    let start = Instant::now();
+    let update = |value| OscMessage::new("/a", vec![OscArgument::Float(value)]);
+    assert_eq!(limiter.submit(update(0.1), start), Some(update(0.1)));
+    assert_eq!(limiter.next_due(), None);
+    // Updates inside the interval are held, with newer ones replacing older ones.
+    assert_eq!(limiter.submit(update(0.2), start), None);
+    assert_eq!(limiter.submit(update(0.3), start), None);
+    // Other addresses aren't limited by the first one.
+    let other = OscMessage::new("/b", vec![OscArgument::Float(1.0)]);
+    assert_eq!(limiter.submit(other.clone(), start), Some(other));
+    assert_eq!(limiter.next_due(), Some(start + interval));
+    assert!(limiter.take_due(start).is_empty());
+    assert_eq!(limiter.take_due(start + interval), vec![update(0.3)]);
+    assert_eq!(limiter.next_due(), None);
+    assert_eq!(limiter.submit(update(0.4), start + interval), None);
+  }
+
+  #[test]
+  fn test_rate_limiter_disabled() {
+    let mut limiter = OscRateLimiter::new(Duration::ZERO);
+    let now = Instant::now();
+    let update = OscMessage::new("/a", vec![OscArgument::Float(0.5)]);
+    assert_eq!(limiter.submit(update.clone(), now), Some(update.clone()));
+    assert_eq!(limiter.submit(update.clone(), now), Some(update));
+  }
+}
@@ -0,0 +1,360 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Decoding for OSC 1.0 packets, and address pattern matching.
+//!
+//! Only the receiving side is implemented, as that's all the bridge needs. Bundle time tags are
+//! ignored, everything is handled as soon as it arrives.
+
+use getset::Getters;
+use thiserror::Error;
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum OscPacketError {
+  #[error("OSC packet ended unexpectedly")]
+  UnexpectedEnd,
+  #[error("OSC string is not valid UTF-8")]
+  InvalidString,
+  #[error("OSC message address must start with '/', got {0}")]
+  InvalidAddress(String),
+  #[error("Unsupported OSC type tag '{0}'")]
+  UnsupportedType(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+  Int(i32),
+  Long(i64),
+  Float(f32),
+  Double(f64),
+  String(String),
+  Blob(Vec<u8>),
+  Bool(bool),
+  Nil,
+  Impulse,
+}
+
+impl OscArgument {
+  /// Numeric value of the argument, if it has one. Booleans are treated as 0 or 1.
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      OscArgument::Int(value) => Some(*value as f64),
+      OscArgument::Long(value) => Some(*value as f64),
+      OscArgument::Float(value) => Some(*value as f64),
+      OscArgument::Double(value) => Some(*value),
+      OscArgument::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct OscMessage {
+  address: String,
+  arguments: Vec<OscArgument>,
+}
+
+impl OscMessage {
+  pub fn new(address: &str, arguments: Vec<OscArgument>) -> Self {
+    Self {
+      address: address.to_owned(),
+      arguments,
+    }
+  }
+
+  /// Value of the first numeric argument, which is what parameter style senders put their value in.
+  /// Returns None if that value is NaN or infinite.
+  pub fn value(&self) -> Option<f64> {
+    self
+      .arguments
+      .iter()
+      .find_map(|arg| arg.as_f64())
+      .filter(|value| value.is_finite())
+  }
+}
+
+struct OscReader<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> OscReader<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Self { data, position: 0 }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.position >= self.data.len()
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], OscPacketError> {
+    let end = self
+      .position
+      .checked_add(len)
+      .filter(|end| *end <= self.data.len())
+      .ok_or(OscPacketError::UnexpectedEnd)?;
+    let bytes = &self.data[self.position..end];
+    self.position = end;
+    Ok(bytes)
+  }
+
+  fn take_array<const N: usize>(&mut self) -> Result<[u8; N], OscPacketError> {
+    let mut array = [0u8; N];
+    array.copy_from_slice(self.take(N)?);
+    Ok(array)
+  }
+
+  /// Everything in OSC is aligned to 4 bytes.
+  fn skip_padding(&mut self) -> Result<(), OscPacketError> {
+    let padding = (4 - self.position % 4) % 4;
+    self.take(padding).map(|_| ())
+  }
+
+  fn read_string(&mut self) -> Result<String, OscPacketError> {
+    let remaining = &self.data[self.position.min(self.data.len())..];
+    let len = remaining
+      .iter()
+      .position(|b| *b == 0)
+      .ok_or(OscPacketError::UnexpectedEnd)?;
+    let string = std::str::from_utf8(self.take(len)?)
+      .map_err(|_| OscPacketError::InvalidString)?
+      .to_owned();
+    // Null terminator
+    self.take(1)?;
+    self.skip_padding()?;
+    Ok(string)
+  }
+
+  fn read_i32(&mut self) -> Result<i32, OscPacketError> {
+    Ok(i32::from_be_bytes(self.take_array()?))
+  }
+
+  fn read_blob(&mut self) -> Result<Vec<u8>, OscPacketError> {
+    let len = usize::try_from(self.read_i32()?).map_err(|_| OscPacketError::UnexpectedEnd)?;
+    let blob = self.take(len)?.to_vec();
+    self.skip_padding()?;
+    Ok(blob)
+  }
+
+  fn read_argument(&mut self, tag: char) -> Result<OscArgument, OscPacketError> {
+    Ok(match tag {
+      'i' => OscArgument::Int(self.read_i32()?),
+      'h' => OscArgument::Long(i64::from_be_bytes(self.take_array()?)),
+      'f' => OscArgument::Float(f32::from_be_bytes(self.take_array()?)),
+      'd' => OscArgument::Double(f64::from_be_bytes(self.take_array()?)),
+      's' | 'S' => OscArgument::String(self.read_string()?),
+      'b' => OscArgument::Blob(self.read_blob()?),
+      'T' => OscArgument::Bool(true),
+      'F' => OscArgument::Bool(false),
+      'N' => OscArgument::Nil,
+      'I' => OscArgument::Impulse,
+      tag => return Err(OscPacketError::UnsupportedType(tag)),
+    })
+  }
+}
+
+fn decode_message(data: &[u8]) -> Result<OscMessage, OscPacketError> {
+  let mut reader = OscReader::new(data);
+  let address = reader.read_string()?;
+  if !address.starts_with('/') {
+    return Err(OscPacketError::InvalidAddress(address));
+  }
+  let mut arguments = vec![];
+  // Very old senders leave off the type tag string entirely, in which case there are no arguments
+  // we can decode.
+  if !reader.is_empty() {
+    let type_tags = reader.read_string()?;
+    for tag in type_tags.chars().skip_while(|c| *c == ',') {
+      arguments.push(reader.read_argument(tag)?);
+    }
+  }
+  Ok(OscMessage { address, arguments })
+}
+
+/// Decode an OSC packet, flattening any bundles into the messages they contain.
+pub fn decode_packet(data: &[u8]) -> Result<Vec<OscMessage>, OscPacketError> {
+  if !data.starts_with(BUNDLE_TAG) {
+    return Ok(vec![decode_message(data)?]);
+  }
+  let mut reader = OscReader::new(data);
+  // Bundle tag and time tag
+  reader.take(BUNDLE_TAG.len() + 8)?;
+  let mut messages = vec![];
+  while !reader.is_empty() {
+    let len = usize::try_from(reader.read_i32()?).map_err(|_| OscPacketError::UnexpectedEnd)?;
+    messages.append(&mut decode_packet(reader.take(len)?)?);
+  }
+  Ok(messages)
+}
+
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+  // Returns whether the character matched, and the length of the bracket expression.
+  let end = pattern.iter().position(|p| *p == ']')?;
+  let mut set = &pattern[1..end];
+  let negate = set.first() == Some(&'!');
+  if negate {
+    set = &set[1..];
+  }
+  let mut matched = false;
+  let mut i = 0;
+  while i < set.len() {
+    if i + 2 < set.len() && set[i + 1] == '-' {
+      matched |= set[i] <= c && c <= set[i + 2];
+      i += 3;
+    } else {
+      matched |= set[i] == c;
+      i += 1;
+    }
+  }
+  Some((matched != negate, end + 1))
+}
+
+fn pattern_matches_chars(pattern: &[char], address: &[char]) -> bool {
+  match pattern.first() {
+    None => address.is_empty(),
+    Some('*') => {
+      // Wildcards never cross an address part.
+      let rest = &pattern[1..];
+      (0..=address.len())
+        .take_while(|i| *i == 0 || address[i - 1] != '/')
+        .any(|i| pattern_matches_chars(rest, &address[i..]))
+    }
+    Some('?') => {
+      matches!(address.first(), Some(c) if *c != '/')
+        && pattern_matches_chars(&pattern[1..], &address[1..])
+    }
+    Some('[') => {
+      let Some(c) = address.first().filter(|c| **c != '/') else {
+        return false;
+      };
+      match match_bracket(pattern, *c) {
+        Some((matched, len)) => matched && pattern_matches_chars(&pattern[len..], &address[1..]),
+        // Unterminated bracket, treat it as a literal.
+        None => *c == '[' && pattern_matches_chars(&pattern[1..], &address[1..]),
+      }
+    }
+    Some('{') => {
+      let Some(end) = pattern.iter().position(|p| *p == '}') else {
+        return address.first() == Some(&'{')
+          && pattern_matches_chars(&pattern[1..], &address[1..]);
+      };
+      let rest = &pattern[end + 1..];
+      pattern[1..end].split(|p| *p == ',').any(|choice| {
+        address.starts_with(choice) && pattern_matches_chars(rest, &address[choice.len()..])
+      })
+    }
+    Some(p) => address.first() == Some(p) && pattern_matches_chars(&pattern[1..], &address[1..]),
+  }
+}
+
+/// Match an OSC address against an OSC 1.0 address pattern. Supports `?`, `*`, `[abc]`, `[a-z]`,
+/// `[!abc]` and `{foo,bar}`.
+pub fn address_pattern_matches(pattern: &str, address: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let address: Vec<char> = address.chars().collect();
+  pattern_matches_chars(&pattern, &address)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn padded(s: &str) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.push(0);
+    while !bytes.len().is_multiple_of(4) {
+      bytes.push(0);
+    }
+    bytes
+  }
+
+  fn float_message(address: &str, value: f32) -> Vec<u8> {
+    let mut bytes = padded(address);
+    bytes.extend(padded(",f"));
+    bytes.extend(value.to_be_bytes());
+    bytes
+  }
+
+  #[test]
+  fn test_decode_message() {
+    let mut data = padded("/avatar/parameters/Haptic");
+    data.extend(padded(",isT"));
+    data.extend(7i32.to_be_bytes());
+    data.extend(padded("hi"));
+    assert_eq!(
+      decode_packet(&data),
+      Ok(vec![OscMessage::new(
+        "/avatar/parameters/Haptic",
+        vec![
+          OscArgument::Int(7),
+          OscArgument::String("hi".to_owned()),
+          OscArgument::Bool(true)
+        ]
+      )])
+    );
+    assert_eq!(
+      decode_packet(&data[..data.len() - 2]),
+      Err(OscPacketError::UnexpectedEnd)
+    );
+  }
+
+  #[test]
+  fn test_decode_bundle() {
+    let mut data = padded("#bundle");
+    data.extend([0u8; 8]);
+    for (address, value) in [("/a", 0.25f32), ("/b", 1.0)] {
+      let message = float_message(address, value);
+      data.extend((message.len() as i32).to_be_bytes());
+      data.extend(message);
+    }
+    let messages = decode_packet(&data).expect("Test, assuming infallible.");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].address(), "/a");
+    assert_eq!(messages[0].value(), Some(0.25));
+    assert_eq!(messages[1].value(), Some(1.0));
+  }
+
+  #[test]
+  fn test_non_finite_values_rejected() {
+    for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+      let message = OscMessage::new("/a", vec![OscArgument::Float(value), OscArgument::Int(1)]);
+      assert_eq!(message.value(), None);
+    }
+    let message = OscMessage::new("/a", vec![OscArgument::Double(f64::NAN)]);
+    assert_eq!(message.value(), None);
+    let message = OscMessage::new("/a", vec![OscArgument::Double(0.5)]);
+    assert_eq!(message.value(), Some(0.5));
+  }
+
+  #[test]
+  fn test_address_pattern_matching() {
+    assert!(address_pattern_matches(
+      "/avatar/parameters/Haptic",
+      "/avatar/parameters/Haptic"
+    ));
+    assert!(address_pattern_matches(
+      "/avatar/parameters/Haptic*",
+      "/avatar/parameters/HapticLeft"
+    ));
+    assert!(!address_pattern_matches(
+      "/avatar/*",
+      "/avatar/parameters/Haptic"
+    ));
+    assert!(address_pattern_matches(
+      "/avatar/*/Haptic",
+      "/avatar/parameters/Haptic"
+    ));
+    assert!(address_pattern_matches("/hand/{left,right}", "/hand/right"));
+    assert!(!address_pattern_matches("/hand/{left,right}", "/hand/up"));
+    assert!(address_pattern_matches("/motor/[0-3]", "/motor/2"));
+    assert!(!address_pattern_matches("/motor/[!0-3]", "/motor/2"));
+    assert!(address_pattern_matches("/motor/?", "/motor/9"));
+  }
+}
@@ -0,0 +1,153 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Per-client permissions for raw access, scanning, device groups and patterns
+//!
+//! Raw messages give clients direct access to device hardware, scanning lets them pull new devices
+//! into the session, and device groups and patterns let them change how devices get driven outside
+//! of their own commands. A [PermissionPolicy] limits which clients can do each of these, by the
+//! client name sent in the handshake. Clients with no permissions of their own get the policy's default
+//! permissions, which allow everything unless changed.
+//!
+//! Gated actions:
+//!
+//! - Raw reads, writes, and subscriptions, each with their own permission. Raw messages still need
+//!   to be allowed on the server with
+//!   [allow_raw_messages](super::ButtplugServerBuilder::allow_raw_messages).
+//! - Raw messages to firmware and whitelist endpoints, which can change device modes or firmware
+//!   state, need the firmware permission on top of the raw permission.
+//! - Starting scanning.
+//! - Adding devices to and removing devices from groups.
+//! - Playing patterns.
+//!
+//! Stopping devices, scanning and patterns is always allowed, so no client can be locked out of shutting
+//! things off.
+//!
+//! Client names are whatever the client says they are, so this keeps well-behaved applications
+//! from doing more than they need, but isn't authentication. Servers exposed to untrusted clients
+//! should restrict the default permissions.
+
+use crate::core::{
+  errors::{ButtplugDeviceError, ButtplugError},
+  message::{ButtplugClientMessage, Endpoint},
+};
+use getset::{CopyGetters, Setters};
+use std::collections::HashMap;
+
+/// What a client is allowed to do beyond regular device commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters, Setters)]
+#[getset(get_copy = "pub", set = "pub")]
+pub struct ClientPermissions {
+  /// Send RawReadCmd messages.
+  raw_read: bool,
+  /// Send RawWriteCmd messages.
+  raw_write: bool,
+  /// Send RawSubscribeCmd and RawUnsubscribeCmd messages.
+  raw_subscribe: bool,
+  /// Send raw messages to firmware and whitelist endpoints.
+  firmware_endpoints: bool,
+  /// Start scanning for devices.
+  scanning: bool,
+  /// Send AddDeviceToGroup and RemoveDeviceFromGroup messages.
+  device_groups: bool,
+  /// Send PlayPatternCmd messages.
+  patterns: bool,
+}
+
+impl Default for ClientPermissions {
+  /// Allows everything, same as a server without permissions.
+  fn default() -> Self {
+    Self {
+      raw_read: true,
+      raw_write: true,
+      raw_subscribe: true,
+      firmware_endpoints: true,
+      scanning: true,
+      device_groups: true,
+      patterns: true,
+    }
+  }
+}
+
+impl ClientPermissions {
+  /// Permissions allowing none of the gated actions.
+  pub fn restricted() -> Self {
+    Self {
+      raw_read: false,
+      raw_write: false,
+      raw_subscribe: false,
+      firmware_endpoints: false,
+      scanning: false,
+      device_groups: false,
+      patterns: false,
+    }
+  }
+
+  /// Errors if these permissions don't allow the message.
+  pub(super) fn check(
+    &self,
+    client_name: &str,
+    msg: &ButtplugClientMessage,
+  ) -> Result<(), ButtplugError> {
+    let (allowed, endpoint) = match msg {
+      ButtplugClientMessage::RawWriteCmd(m) => (self.raw_write, Some(m.endpoint())),
+      ButtplugClientMessage::RawReadCmd(m) => (self.raw_read, Some(m.endpoint())),
+      ButtplugClientMessage::RawSubscribeCmd(m) => (self.raw_subscribe, Some(m.endpoint())),
+      ButtplugClientMessage::RawUnsubscribeCmd(m) => (self.raw_subscribe, Some(m.endpoint())),
+      ButtplugClientMessage::StartScanning(_) => (self.scanning, None),
+      ButtplugClientMessage::AddDeviceToGroup(_) => (self.device_groups, None),
+      ButtplugClientMessage::RemoveDeviceFromGroup(_) => (self.device_groups, None),
+      ButtplugClientMessage::PlayPatternCmd(_) => (self.patterns, None),
+      _ => return Ok(()),
+    };
+    let allowed = allowed
+      && (self.firmware_endpoints
+        || !matches!(endpoint, Some(Endpoint::Firmware | Endpoint::Whitelist)));
+    if allowed {
+      Ok(())
+    } else {
+      let message_type: &'static str = msg.into();
+      Err(
+        ButtplugDeviceError::DevicePermissionError(format!(
+          "Client {} is not allowed to send {}",
+          client_name, message_type
+        ))
+        .into(),
+      )
+    }
+  }
+}
+
+/// Permissions for all clients connecting to the server.
+#[derive(Debug, Default, Clone)]
+pub struct PermissionPolicy {
+  default: ClientPermissions,
+  clients: HashMap<String, ClientPermissions>,
+}
+
+impl PermissionPolicy {
+  /// Permissions for every client without its own permissions.
+  pub fn default_permissions(&mut self, permissions: ClientPermissions) -> &mut Self {
+    self.default = permissions;
+    self
+  }
+
+  /// Permissions for clients that connect with the given name.
+  pub fn client(&mut self, client_name: &str, permissions: ClientPermissions) -> &mut Self {
+    self.clients.insert(client_name.to_owned(), permissions);
+    self
+  }
+
+  /// Permissions that apply to a client with the given name.
+  pub fn permissions_for(&self, client_name: &str) -> ClientPermissions {
+    self
+      .clients
+      .get(client_name)
+      .copied()
+      .unwrap_or(self.default)
+  }
+}
@@ -0,0 +1,136 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Messages exchanged between the plugin host and protocol plugins. Each message is serialized as
+//! a single line of JSON.
+
+use crate::{
+  core::message::{ActuatorType, Endpoint},
+  server::device::hardware::{HardwareCommand, HardwareWriteCmd},
+};
+use getset::{CopyGetters, Getters};
+use serde::{Deserialize, Serialize};
+
+/// Version of the plugin message format. Plugins registering with a different version are
+/// rejected.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+/// Messages sent from a plugin to the host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PluginMessage {
+  /// First message a plugin sends, claiming the protocols it implements.
+  Register {
+    version: u32,
+    name: String,
+    protocols: Vec<String>,
+  },
+  /// Commands to write to the device, in response to the host message with the same id.
+  Commands {
+    id: u32,
+    commands: Vec<PluginWriteCommand>,
+  },
+  /// The host message with the same id could not be handled.
+  Error { id: u32, message: String },
+}
+
+/// Messages sent from the host to a plugin.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HostMessage {
+  Registered {
+    version: u32,
+  },
+  /// Registration was refused, the host closes the connection after sending this.
+  RegistrationFailed {
+    reason: String,
+  },
+  /// A device using one of the plugin's protocols connected.
+  Initialize {
+    id: u32,
+    protocol: String,
+    name: String,
+    address: String,
+  },
+  ScalarCmd {
+    id: u32,
+    protocol: String,
+    address: String,
+    commands: Vec<PluginScalarCommand>,
+  },
+  LinearCmd {
+    id: u32,
+    protocol: String,
+    address: String,
+    vectors: Vec<PluginLinearVector>,
+  },
+}
+
+/// A changed scalar actuator. `value` is within the actuator's StepRange.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct PluginScalarCommand {
+  index: u32,
+  actuator: ActuatorType,
+  value: u32,
+}
+
+impl PluginScalarCommand {
+  pub fn new(index: u32, actuator: ActuatorType, value: u32) -> Self {
+    Self {
+      index,
+      actuator,
+      value,
+    }
+  }
+}
+
+/// A linear movement. `position` is between 0.0 and 1.0, `duration` is in milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct PluginLinearVector {
+  index: u32,
+  position: f64,
+  duration: u32,
+}
+
+impl PluginLinearVector {
+  pub fn new(index: u32, position: f64, duration: u32) -> Self {
+    Self {
+      index,
+      position,
+      duration,
+    }
+  }
+}
+
+/// Data for the host to write to a device endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, CopyGetters)]
+#[serde(rename_all = "kebab-case")]
+pub struct PluginWriteCommand {
+  #[getset(get_copy = "pub")]
+  endpoint: Endpoint,
+  #[getset(get = "pub")]
+  data: Vec<u8>,
+  #[getset(get_copy = "pub")]
+  #[serde(default)]
+  write_with_response: bool,
+}
+
+impl PluginWriteCommand {
+  pub fn new(endpoint: Endpoint, data: &[u8], write_with_response: bool) -> Self {
+    Self {
+      endpoint,
+      data: data.to_vec(),
+      write_with_response,
+    }
+  }
+}
+
+impl From<PluginWriteCommand> for HardwareCommand {
+  fn from(command: PluginWriteCommand) -> Self {
+    HardwareWriteCmd::new(command.endpoint, command.data, command.write_with_response).into()
+  }
+}
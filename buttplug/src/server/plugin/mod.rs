@@ -0,0 +1,409 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Out-of-process protocol plugins
+//!
+//! Protocol plugins are separate processes that implement device protocols, so device support can
+//! be shipped (and updated) without rebuilding the server. The plugin host listens on a local
+//! socket (a unix domain socket, or a named pipe on Windows), and plugins connect to it to register
+//! the protocols they implement.
+//!
+//! The host is configured with a [ProtocolPluginHostConfig]:
+//!
+//! ```json
+//! {
+//!   "socket-path": "/run/buttplug/plugins.sock",
+//!   "protocols": ["example-protocol"]
+//! }
+//! ```
+//!
+//! Only the listed protocols can be registered by plugins, as the server needs to know about them
+//! when it starts. Like built-in protocols, devices are matched to them by the communication
+//! specifiers and attributes in the device configuration.
+//!
+//! Messages are JSON, one message per line (see [messages] for the full set). A plugin starts by
+//! registering, with the current [PLUGIN_API_VERSION]:
+//!
+//! ```json
+//! {"Register":{"version":1,"name":"Example Plugin","protocols":["example-protocol"]}}
+//! ```
+//!
+//! The host answers with `Registered`, or `RegistrationFailed` before closing the connection. After
+//! that, the host sends `Initialize` when a device connects, and `ScalarCmd`/`LinearCmd` when it
+//! needs to be updated. The plugin answers each of them, using the same id, with the commands to
+//! write to the device:
+//!
+//! ```json
+//! {"Commands":{"id":2,"commands":[{"endpoint":"tx","data":[15,0,10]}]}}
+//! ```
+//!
+//! or with an `Error` if it can't handle the message. If a plugin disconnects, its protocols can be
+//! registered again, so plugins can be restarted without restarting the server.
+
+pub mod messages;
+mod plugin_protocol;
+
+pub use messages::{HostMessage, PluginMessage, PLUGIN_API_VERSION};
+pub(crate) use plugin_protocol::ProtocolPluginFactory;
+
+use crate::{
+  core::errors::ButtplugDeviceError,
+  server::device::hardware::HardwareCommand,
+  util::async_manager,
+};
+use dashmap::DashMap;
+use futures::{select, FutureExt};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+    Mutex,
+  },
+  time::Duration,
+};
+use tokio::{
+  io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+  sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+
+/// How long to wait for a plugin to answer a host message.
+const PLUGIN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Plugin host configuration. See the [module documentation](self) for the JSON format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters)]
+#[serde(rename_all = "kebab-case")]
+#[getset(get = "pub")]
+pub struct ProtocolPluginHostConfig {
+  /// Path of the unix socket, or name of the named pipe (i.e. `\\.\pipe\buttplug-plugins`) on
+  /// Windows.
+  socket_path: String,
+  /// Protocol identifiers that plugins are allowed to register.
+  protocols: Vec<String>,
+}
+
+impl ProtocolPluginHostConfig {
+  pub fn new(socket_path: &str, protocols: &[String]) -> Self {
+    Self {
+      socket_path: socket_path.to_owned(),
+      protocols: protocols.to_vec(),
+    }
+  }
+
+  pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+    serde_json::from_str(json)
+  }
+}
+
+/// A connected plugin, and the host messages it hasn't answered yet.
+pub(crate) struct PluginConnection {
+  name: String,
+  sender: mpsc::UnboundedSender<HostMessage>,
+  pending: DashMap<u32, oneshot::Sender<Result<Vec<HardwareCommand>, String>>>,
+  next_id: AtomicU32,
+}
+
+impl PluginConnection {
+  /// Send a message built with a new id to the plugin, and wait for the commands it answers with.
+  pub(crate) async fn request(
+    &self,
+    protocol: &str,
+    message: impl FnOnce(u32) -> HostMessage,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let plugin_error = |reason: String| {
+      ButtplugDeviceError::ProtocolSpecificError(
+        protocol.to_owned(),
+        format!("Plugin {}: {}", self.name, reason),
+      )
+    };
+    let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+    let (sender, receiver) = oneshot::channel();
+    self.pending.insert(id, sender);
+    if self.sender.send(message(id)).is_err() {
+      self.pending.remove(&id);
+      return Err(plugin_error("disconnected".to_owned()));
+    }
+    let response = tokio::time::timeout(PLUGIN_REQUEST_TIMEOUT, receiver).await;
+    self.pending.remove(&id);
+    match response {
+      Ok(Ok(result)) => result.map_err(plugin_error),
+      Ok(Err(_)) => Err(plugin_error("disconnected".to_owned())),
+      Err(_) => Err(plugin_error("timed out".to_owned())),
+    }
+  }
+
+  fn respond(&self, id: u32, result: Result<Vec<HardwareCommand>, String>) {
+    match self.pending.remove(&id) {
+      Some((_, sender)) => {
+        let _ = sender.send(result);
+      }
+      None => warn!(
+        "Plugin {} answered unknown or expired message {}",
+        self.name, id
+      ),
+    }
+  }
+}
+
+/// Plugins currently connected to the host, by the protocols they've registered.
+pub(crate) struct PluginRegistry {
+  allowed_protocols: Vec<String>,
+  connections: Mutex<HashMap<String, Arc<PluginConnection>>>,
+}
+
+impl PluginRegistry {
+  pub(crate) fn new(allowed_protocols: &[String]) -> Self {
+    Self {
+      allowed_protocols: allowed_protocols.to_vec(),
+      connections: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub(crate) fn connection(&self, protocol: &str) -> Option<Arc<PluginConnection>> {
+    self
+      .connections
+      .lock()
+      .expect("Registry lock should never be poisoned")
+      .get(protocol)
+      .cloned()
+  }
+
+  fn register(
+    &self,
+    version: u32,
+    name: &str,
+    protocols: &[String],
+    sender: mpsc::UnboundedSender<HostMessage>,
+  ) -> Result<Arc<PluginConnection>, String> {
+    if version != PLUGIN_API_VERSION {
+      return Err(format!(
+        "Plugin API version {} is not supported, host uses version {}",
+        version, PLUGIN_API_VERSION
+      ));
+    }
+    let mut connections = self
+      .connections
+      .lock()
+      .expect("Registry lock should never be poisoned");
+    for protocol in protocols {
+      if !self.allowed_protocols.contains(protocol) {
+        return Err(format!(
+          "Protocol {} is not configured for plugins",
+          protocol
+        ));
+      }
+      if connections.contains_key(protocol) {
+        return Err(format!(
+          "Protocol {} is already registered by another plugin",
+          protocol
+        ));
+      }
+    }
+    let connection = Arc::new(PluginConnection {
+      name: name.to_owned(),
+      sender,
+      pending: DashMap::new(),
+      next_id: AtomicU32::new(1),
+    });
+    for protocol in protocols {
+      connections.insert(protocol.clone(), connection.clone());
+    }
+    Ok(connection)
+  }
+
+  fn unregister(&self, connection: &Arc<PluginConnection>) {
+    self
+      .connections
+      .lock()
+      .expect("Registry lock should never be poisoned")
+      .retain(|_, registered| !Arc::ptr_eq(registered, connection));
+    // Dropping the senders fails anything still waiting on this plugin.
+    connection.pending.clear();
+  }
+}
+
+async fn handle_connection<S>(stream: S, registry: Arc<PluginRegistry>, token: CancellationToken)
+where
+  S: AsyncRead + AsyncWrite + Send + 'static,
+{
+  let (reader, mut writer) = tokio::io::split(stream);
+  let mut lines = BufReader::new(reader).lines();
+  let (sender, mut receiver) = mpsc::unbounded_channel::<HostMessage>();
+  async_manager::spawn(async move {
+    while let Some(message) = receiver.recv().await {
+      let mut line =
+        serde_json::to_string(&message).expect("Host messages are always serializable");
+      line.push('\n');
+      if let Err(err) = writer.write_all(line.as_bytes()).await {
+        debug!("Cannot write to plugin, closing connection: {}", err);
+        break;
+      }
+    }
+  });
+
+  let registration = match lines.next_line().await {
+    Ok(Some(line)) => match serde_json::from_str(&line) {
+      Ok(PluginMessage::Register {
+        version,
+        name,
+        protocols,
+      }) => registry
+        .register(version, &name, &protocols, sender.clone())
+        .map(|connection| (connection, name, protocols)),
+      Ok(_) => Err("Plugins must register before sending anything else".to_owned()),
+      Err(err) => Err(format!("Invalid registration message: {}", err)),
+    },
+    _ => return,
+  };
+  let connection = match registration {
+    Ok((connection, name, protocols)) => {
+      info!("Protocol plugin {} registered {:?}", name, protocols);
+      let _ = sender.send(HostMessage::Registered {
+        version: PLUGIN_API_VERSION,
+      });
+      connection
+    }
+    Err(reason) => {
+      warn!("Rejected protocol plugin: {}", reason);
+      let _ = sender.send(HostMessage::RegistrationFailed { reason });
+      return;
+    }
+  };
+  drop(sender);
+
+  loop {
+    let line = select! {
+      _ = token.cancelled().fuse() => break,
+      line = lines.next_line().fuse() => match line {
+        Ok(Some(line)) => line,
+        _ => break,
+      }
+    };
+    match serde_json::from_str(&line) {
+      Ok(PluginMessage::Commands { id, commands }) => connection.respond(
+        id,
+        Ok(commands.into_iter().map(HardwareCommand::from).collect()),
+      ),
+      Ok(PluginMessage::Error { id, message }) => connection.respond(id, Err(message)),
+      Ok(PluginMessage::Register { .. }) => {
+        warn!("Protocol plugin {} is already registered", connection.name)
+      }
+      Err(err) => warn!(
+        "Invalid message from protocol plugin {}: {}",
+        connection.name, err
+      ),
+    }
+  }
+  info!("Protocol plugin {} disconnected", connection.name);
+  registry.unregister(&connection);
+}
+
+/// Running plugin host. Stops listening, and disconnects all plugins, when dropped.
+pub(crate) struct ProtocolPluginHost {
+  cancellation_token: CancellationToken,
+}
+
+impl ProtocolPluginHost {
+  /// Start listening for plugins. On unix, the socket is bound immediately so errors are reported
+  /// here. Any existing file at the socket path is replaced.
+  #[cfg(unix)]
+  pub(crate) fn start(
+    config: &ProtocolPluginHostConfig,
+    registry: Arc<PluginRegistry>,
+  ) -> Result<Self, std::io::Error> {
+    let path = config.socket_path();
+    if std::path::Path::new(path).exists() {
+      std::fs::remove_file(path)?;
+    }
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    info!("Protocol plugin host listening on {}", path);
+    let cancellation_token = CancellationToken::new();
+    let child_token = cancellation_token.child_token();
+    async_manager::spawn(async move {
+      let listener = match tokio::net::UnixListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(err) => {
+          error!("Cannot start protocol plugin host: {}", err);
+          return;
+        }
+      };
+      loop {
+        let stream = select! {
+          _ = child_token.cancelled().fuse() => break,
+          result = listener.accept().fuse() => match result {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+              error!("Protocol plugin host socket error, stopping host: {}", err);
+              break;
+            }
+          }
+        };
+        async_manager::spawn(handle_connection(
+          stream,
+          registry.clone(),
+          child_token.child_token(),
+        ));
+      }
+      debug!("Protocol plugin host stopped.");
+    });
+    Ok(Self { cancellation_token })
+  }
+
+  /// Start listening for plugins. Named pipe instances can only be created from the runtime, so
+  /// errors creating the pipe are logged instead of returned.
+  #[cfg(windows)]
+  pub(crate) fn start(
+    config: &ProtocolPluginHostConfig,
+    registry: Arc<PluginRegistry>,
+  ) -> Result<Self, std::io::Error> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+    let path = config.socket_path().clone();
+    info!("Protocol plugin host listening on {}", path);
+    let cancellation_token = CancellationToken::new();
+    let child_token = cancellation_token.child_token();
+    async_manager::spawn(async move {
+      let mut first_instance = true;
+      loop {
+        let server = match ServerOptions::new()
+          .first_pipe_instance(first_instance)
+          .create(&path)
+        {
+          Ok(server) => server,
+          Err(err) => {
+            error!("Cannot create protocol plugin pipe, stopping host: {}", err);
+            break;
+          }
+        };
+        first_instance = false;
+        select! {
+          _ = child_token.cancelled().fuse() => break,
+          result = server.connect().fuse() => if let Err(err) = result {
+            error!("Protocol plugin pipe error, stopping host: {}", err);
+            break;
+          }
+        };
+        async_manager::spawn(handle_connection(
+          server,
+          registry.clone(),
+          child_token.child_token(),
+        ));
+      }
+      debug!("Protocol plugin host stopped.");
+    });
+    Ok(Self { cancellation_token })
+  }
+}
+
+impl Drop for ProtocolPluginHost {
+  fn drop(&mut self) {
+    self.cancellation_token.cancel();
+  }
+}
@@ -0,0 +1,250 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use super::{
+  messages::{HostMessage, PluginLinearVector, PluginScalarCommand},
+  PluginRegistry,
+};
+use crate::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{self, ActuatorType},
+  },
+  server::device::{
+    configuration::{ProtocolAttributesType, ProtocolDeviceAttributes},
+    hardware::{Hardware, HardwareCommand},
+    protocol::{
+      ProtocolCapabilities,
+      ProtocolHandler,
+      ProtocolIdentifier,
+      ProtocolIdentifierFactory,
+      ProtocolInitializer,
+    },
+    ServerDeviceIdentifier,
+  },
+  util::async_manager,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Protocol instance factory for a protocol implemented by whichever plugin registers it.
+pub(crate) struct ProtocolPluginFactory {
+  identifier: String,
+  registry: Arc<PluginRegistry>,
+}
+
+impl ProtocolPluginFactory {
+  pub(crate) fn new(identifier: &str, registry: Arc<PluginRegistry>) -> Self {
+    Self {
+      identifier: identifier.to_owned(),
+      registry,
+    }
+  }
+}
+
+impl ProtocolIdentifierFactory for ProtocolPluginFactory {
+  fn identifier(&self) -> &str {
+    &self.identifier
+  }
+
+  fn create(&self) -> Box<dyn ProtocolIdentifier> {
+    Box::new(PluginProtocolIdentifier {
+      identifier: self.identifier.clone(),
+      registry: self.registry.clone(),
+    })
+  }
+
+  fn capabilities(&self) -> ProtocolCapabilities {
+    <PluginProtocol as ProtocolHandler>::capabilities()
+  }
+}
+
+struct PluginProtocolIdentifier {
+  identifier: String,
+  registry: Arc<PluginRegistry>,
+}
+
+#[async_trait]
+impl ProtocolIdentifier for PluginProtocolIdentifier {
+  async fn identify(
+    &mut self,
+    hardware: Arc<Hardware>,
+  ) -> Result<(ServerDeviceIdentifier, Box<dyn ProtocolInitializer>), ButtplugDeviceError> {
+    Ok((
+      ServerDeviceIdentifier::new(
+        hardware.address(),
+        &self.identifier,
+        &ProtocolAttributesType::Identifier(hardware.name().to_owned()),
+      ),
+      Box::new(PluginProtocolInitializer {
+        identifier: self.identifier.clone(),
+        registry: self.registry.clone(),
+      }),
+    ))
+  }
+}
+
+struct PluginProtocolInitializer {
+  identifier: String,
+  registry: Arc<PluginRegistry>,
+}
+
+#[async_trait]
+impl ProtocolInitializer for PluginProtocolInitializer {
+  async fn initialize(
+    &mut self,
+    hardware: Arc<Hardware>,
+    _: &ProtocolDeviceAttributes,
+  ) -> Result<Arc<dyn ProtocolHandler>, ButtplugDeviceError> {
+    let connection = self.registry.connection(&self.identifier).ok_or_else(|| {
+      ButtplugDeviceError::ProtocolSpecificError(
+        self.identifier.clone(),
+        "No plugin is registered for this protocol".to_owned(),
+      )
+    })?;
+    let init_commands = connection
+      .request(&self.identifier, |id| HostMessage::Initialize {
+        id,
+        protocol: self.identifier.clone(),
+        name: hardware.name().to_owned(),
+        address: hardware.address().to_owned(),
+      })
+      .await?;
+    write_commands(&hardware, init_commands).await?;
+    Ok(Arc::new(PluginProtocol::new(
+      &self.identifier,
+      self.registry.clone(),
+      hardware,
+    )))
+  }
+}
+
+async fn write_commands(
+  hardware: &Hardware,
+  commands: Vec<HardwareCommand>,
+) -> Result<(), ButtplugDeviceError> {
+  for command in commands {
+    if let HardwareCommand::Write(write) = command {
+      hardware.write_value(&write).await?;
+    }
+  }
+  Ok(())
+}
+
+enum PluginRequest {
+  Scalar(Vec<PluginScalarCommand>),
+  Linear(Vec<PluginLinearVector>),
+}
+
+/// Forwards commands to the plugin and writes its answers to the device. Plugins answer
+/// asynchronously, so this happens on a task, in the order commands came in.
+pub(crate) struct PluginProtocol {
+  request_sender: mpsc::UnboundedSender<PluginRequest>,
+}
+
+impl PluginProtocol {
+  fn new(identifier: &str, registry: Arc<PluginRegistry>, hardware: Arc<Hardware>) -> Self {
+    let (request_sender, mut request_receiver) = mpsc::unbounded_channel();
+    let identifier = identifier.to_owned();
+    async_manager::spawn(async move {
+      // Stops once the protocol handler, and with it the sender, is dropped.
+      while let Some(request) = request_receiver.recv().await {
+        let Some(connection) = registry.connection(&identifier) else {
+          warn!(
+            "No plugin registered for {}, dropping command for {}",
+            identifier,
+            hardware.address()
+          );
+          continue;
+        };
+        let address = hardware.address().to_owned();
+        let result = match request {
+          PluginRequest::Scalar(commands) => {
+            connection
+              .request(&identifier, |id| HostMessage::ScalarCmd {
+                id,
+                protocol: identifier.clone(),
+                address,
+                commands,
+              })
+              .await
+          }
+          PluginRequest::Linear(vectors) => {
+            connection
+              .request(&identifier, |id| HostMessage::LinearCmd {
+                id,
+                protocol: identifier.clone(),
+                address,
+                vectors,
+              })
+              .await
+          }
+        };
+        let result = match result {
+          Ok(commands) => write_commands(&hardware, commands).await,
+          Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+          error!(
+            "Plugin command for {} failed: {:?}",
+            hardware.address(),
+            err
+          );
+        }
+      }
+    });
+    Self { request_sender }
+  }
+
+  fn send_request(
+    &self,
+    request: PluginRequest,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    self.request_sender.send(request).map_err(|_| {
+      ButtplugDeviceError::DeviceCommunicationError("Plugin command task has stopped".to_owned())
+    })?;
+    Ok(vec![])
+  }
+}
+
+impl ProtocolHandler for PluginProtocol {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&ProtocolCapabilities::ALL_ACTUATOR_TYPES)
+      .with_linear()
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let commands: Vec<PluginScalarCommand> = commands
+      .iter()
+      .enumerate()
+      .filter_map(|(index, command)| {
+        command.map(|(actuator, value)| PluginScalarCommand::new(index as u32, actuator, value))
+      })
+      .collect();
+    if commands.is_empty() {
+      return Ok(vec![]);
+    }
+    self.send_request(PluginRequest::Scalar(commands))
+  }
+
+  fn handle_linear_cmd(
+    &self,
+    msg: message::LinearCmd,
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let vectors = msg
+      .vectors()
+      .iter()
+      .map(|vector| PluginLinearVector::new(vector.index(), vector.position(), vector.duration()))
+      .collect();
+    self.send_request(PluginRequest::Linear(vectors))
+  }
+}
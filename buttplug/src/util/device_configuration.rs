@@ -5,20 +5,27 @@
 // Licensed under the BSD 3-Clause license. See LICENSE file in the project root
 // for full license information.
 
-use super::json::JSONValidator;
+use super::{device_configuration_migration::migrate_user_config, json::JSONValidator};
 use crate::{
   core::errors::ButtplugDeviceError,
   server::device::{
     configuration::{
+      AudioOutputSpecifier,
       BluetoothLESpecifier,
       DeviceConfigurationManager,
       DeviceConfigurationManagerBuilder,
+      DmxSpecifier,
       HIDSpecifier,
       LovenseConnectServiceSpecifier,
+      MdnsSpecifier,
+      MidiSpecifier,
+      NetworkSpecifier,
       ProtocolAttributesIdentifier,
       ProtocolAttributesType,
       ProtocolCommunicationSpecifier,
       ProtocolDeviceAttributes,
+      RemoteServerSpecifier,
+      ScalarActuatorAdjustment,
       SerialSpecifier,
       ServerDeviceMessageAttributes,
       USBSpecifier,
@@ -83,6 +90,26 @@ pub struct UserDeviceConfig {
   display_name: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   #[serde(default)]
+  #[serde(rename = "command-rate-limit-ms")]
+  command_rate_limit_ms: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "scalar-ramp-time-ms")]
+  scalar_ramp_time_ms: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "idle-timeout-ms")]
+  idle_timeout_ms: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "scalar-adjustments")]
+  scalar_adjustments: Option<Vec<ScalarActuatorAdjustment>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
+  #[serde(rename = "protocol-options")]
+  protocol_options: Option<HashMap<String, Vec<u32>>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(default)]
   allow: Option<bool>,
   #[serde(skip_serializing_if = "Option::is_none")]
   #[serde(default)]
@@ -130,9 +157,25 @@ pub struct ProtocolDefinition {
   #[serde(rename = "lovense-connect-service")]
   lovense_connect_service: Option<LovenseConnectServiceSpecifier>,
   #[serde(skip_serializing_if = "Option::is_none")]
+  #[serde(rename = "remote-server")]
+  remote_server: Option<RemoteServerSpecifier>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  network: Option<NetworkSpecifier>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  mdns: Option<MdnsSpecifier>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  midi: Option<MidiSpecifier>,
+  #[serde(rename = "audio-output", skip_serializing_if = "Option::is_none")]
+  audio_output: Option<AudioOutputSpecifier>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  dmx: Option<DmxSpecifier>,
+  #[serde(skip_serializing_if = "Option::is_none")]
   defaults: Option<ProtocolAttributes>,
   #[serde(default)]
   configurations: Vec<ProtocolAttributes>,
+  /// Experimental protocols are only used if the user opts in to them.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  experimental: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default, Getters, Setters, MutGetters)]
@@ -155,6 +198,12 @@ pub struct UserConfigDefinition {
   specifiers: Option<HashMap<String, ProtocolDefinition>>,
   #[serde(rename = "devices", default, skip_serializing_if = "Option::is_none")]
   user_device_configs: Option<Vec<UserDeviceConfigPair>>,
+  #[serde(
+    rename = "allowed-experimental-protocols",
+    default,
+    skip_serializing_if = "Option::is_none"
+  )]
+  allowed_experimental_protocols: Option<Vec<String>>,
 }
 
 #[derive(
@@ -204,6 +253,8 @@ struct ExternalDeviceConfiguration {
   protocol_specifiers: HashMap<String, Vec<ProtocolCommunicationSpecifier>>,
   protocol_attributes: HashMap<ProtocolAttributesIdentifier, ProtocolDeviceAttributes>,
   user_configs: HashMap<ServerDeviceIdentifier, ProtocolDeviceAttributes>,
+  experimental_protocols: Vec<String>,
+  allowed_experimental_protocols: Vec<String>,
 }
 
 impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
@@ -242,6 +293,26 @@ impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
         lcs.clone(),
       ));
     }
+    if let Some(remote) = &protocol_def.remote_server {
+      specifiers.push(ProtocolCommunicationSpecifier::RemoteServer(remote.clone()));
+    }
+    if let Some(network) = &protocol_def.network {
+      specifiers.push(ProtocolCommunicationSpecifier::Network(network.clone()));
+    }
+    if let Some(mdns) = &protocol_def.mdns {
+      specifiers.push(ProtocolCommunicationSpecifier::Mdns(mdns.clone()));
+    }
+    if let Some(midi) = &protocol_def.midi {
+      specifiers.push(ProtocolCommunicationSpecifier::Midi(midi.clone()));
+    }
+    if let Some(audio_output) = &protocol_def.audio_output {
+      specifiers.push(ProtocolCommunicationSpecifier::AudioOutput(
+        audio_output.clone(),
+      ));
+    }
+    if let Some(dmx) = &protocol_def.dmx {
+      specifiers.push(ProtocolCommunicationSpecifier::Dmx(dmx.clone()));
+    }
 
     let mut configurations = HashMap::new();
 
@@ -276,6 +347,27 @@ impl From<ProtocolDefinition> for ProtocolDeviceConfiguration {
   }
 }
 
+/// Device attributes for a user device config entry. The device configuration fills in everything
+/// the user config doesn't set once the attributes are added to it.
+pub(crate) fn user_device_config_attributes(
+  identifier: &ServerDeviceIdentifier,
+  config: &UserDeviceConfig,
+) -> ProtocolDeviceAttributes {
+  let mut config_attrs = ProtocolDeviceAttributes::new(
+    identifier.attributes_identifier().clone(),
+    None,
+    config.display_name.clone(),
+    config.messages.clone().unwrap_or_default(),
+    None,
+  );
+  config_attrs.set_command_rate_limit_ms(config.command_rate_limit_ms);
+  config_attrs.set_scalar_ramp_time_ms(config.scalar_ramp_time_ms);
+  config_attrs.set_idle_timeout_ms(config.idle_timeout_ms);
+  config_attrs.set_scalar_adjustments(config.scalar_adjustments.clone());
+  config_attrs.set_protocol_options(config.protocol_options.clone());
+  config_attrs
+}
+
 fn add_user_configs_to_protocol(
   external_config: &mut ExternalDeviceConfiguration,
   user_config_def: UserConfigDefinition,
@@ -316,8 +408,30 @@ fn add_user_configs_to_protocol(
       if let Some(websocket) = &protocol_def.websocket {
         base_protocol_def.push(ProtocolCommunicationSpecifier::Websocket(websocket.clone()));
       }
+      if let Some(network) = &protocol_def.network {
+        base_protocol_def.push(ProtocolCommunicationSpecifier::Network(network.clone()));
+      }
+      if let Some(mdns) = &protocol_def.mdns {
+        base_protocol_def.push(ProtocolCommunicationSpecifier::Mdns(mdns.clone()));
+      }
+      if let Some(midi) = &protocol_def.midi {
+        base_protocol_def.push(ProtocolCommunicationSpecifier::Midi(midi.clone()));
+      }
+      if let Some(audio_output) = &protocol_def.audio_output {
+        base_protocol_def.push(ProtocolCommunicationSpecifier::AudioOutput(
+          audio_output.clone(),
+        ));
+      }
+      if let Some(dmx) = &protocol_def.dmx {
+        base_protocol_def.push(ProtocolCommunicationSpecifier::Dmx(dmx.clone()));
+      }
     }
   }
+  if let Some(protocols) = user_config_def.allowed_experimental_protocols() {
+    external_config
+      .allowed_experimental_protocols
+      .extend(protocols.iter().cloned());
+  }
   if let Some(user_device_configs) = user_config_def.user_device_configs() {
     for user_config in user_device_configs {
       if *user_config.config().allow().as_ref().unwrap_or(&false) {
@@ -336,14 +450,7 @@ fn add_user_configs_to_protocol(
           .insert(*index, user_config.identifier().clone().into());
       }
       let server_ident: ServerDeviceIdentifier = user_config.identifier.clone().into();
-
-      let config_attrs = ProtocolDeviceAttributes::new(
-        server_ident.attributes_identifier().clone(),
-        None,
-        user_config.config().display_name.clone(),
-        user_config.config().messages.clone().unwrap_or_default(),
-        None,
-      );
+      let config_attrs = user_device_config_attributes(&server_ident, user_config.config());
       info!("Adding user config for {:?}", server_ident);
       external_config
         .user_configs
@@ -408,7 +515,7 @@ impl ProtocolConfiguration {
   }
 }
 
-fn get_internal_config_version() -> ConfigVersion {
+pub(crate) fn get_internal_config_version() -> ConfigVersion {
   let config: ProtocolConfiguration = serde_json::from_str(DEVICE_CONFIGURATION_JSON)
     .expect("If this fails, the whole library goes with it.");
   config.version
@@ -469,10 +576,14 @@ fn load_protocol_configs_internal(
 
   let mut protocol_specifiers = HashMap::new();
   let mut protocol_attributes = HashMap::new();
+  let mut experimental_protocols = vec![];
 
   // Iterate through all of the protocols in the main config first and build up a map of protocol
   // name to ProtocolDeviceConfiguration structs.
   for (protocol_name, protocol_def) in main_config.protocols.unwrap_or_default() {
+    if *protocol_def.experimental() {
+      experimental_protocols.push(protocol_name.clone());
+    }
     let protocol_device_config: ProtocolDeviceConfiguration = protocol_def.into();
     protocol_specifiers.insert(
       protocol_name.clone(),
@@ -487,12 +598,15 @@ fn load_protocol_configs_internal(
   let mut external_config = ExternalDeviceConfiguration {
     protocol_specifiers,
     protocol_attributes,
+    experimental_protocols,
     ..Default::default()
   };
 
   // Then load the user config
   if let Some(user_config) = user_config_str {
     info!("Loading user configuration from string.");
+    let user_config =
+      migrate_user_config(&user_config, &get_internal_config_version(), skip_version_check)?;
     let config = load_protocol_config_from_json(&user_config, skip_version_check)?;
     if let Some(user_configs) = config.user_configs {
       add_user_configs_to_protocol(&mut external_config, user_configs);
@@ -526,6 +640,14 @@ pub fn load_protocol_configs(
     dcm_builder.reserved_index(address, *index);
   }
 
+  for name in external_config.experimental_protocols() {
+    dcm_builder.experimental_protocol(name);
+  }
+
+  for name in external_config.allowed_experimental_protocols() {
+    dcm_builder.allow_experimental_protocol(name);
+  }
+
   for (name, specifiers) in external_config.protocol_specifiers() {
     for spec in specifiers {
       dcm_builder.communication_specifier(name, spec.clone());
@@ -544,7 +666,9 @@ pub fn load_protocol_configs(
 }
 
 pub fn load_user_configs(user_config_str: &str) -> UserConfigDefinition {
-  load_protocol_config_from_json(user_config_str, true)
+  let user_config_str =
+    migrate_user_config(user_config_str, &get_internal_config_version(), true).unwrap();
+  load_protocol_config_from_json(&user_config_str, true)
     .unwrap()
     .user_configs
     .unwrap()
@@ -0,0 +1,272 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Upgrades user device configuration files written for older versions of the library.
+//!
+//! User configs carry the configuration version they were written against. Minor versions only
+//! ever add optional fields, so files from any minor version of the current major version load
+//! as is. When the major version changes, a migration rewrites the file from the old format into
+//! the new one. Migrations run one major version at a time, so a file several versions behind goes
+//! through every migration in between.
+//!
+//! Files that can't be migrated, either because they're from a version we have no migration for,
+//! or because they're from a newer version of the library, are rejected with an error instead of
+//! being loaded with settings silently missing.
+
+use super::device_configuration::ConfigVersion;
+use crate::core::errors::ButtplugDeviceError;
+use serde_json::{json, Map, Value};
+
+/// Rewrites a user config from one major version into the next.
+struct UserConfigMigration {
+  from_major: u32,
+  migrate: fn(&mut Value) -> Result<(), ButtplugDeviceError>,
+}
+
+/// Every supported migration, in order.
+const USER_CONFIG_MIGRATIONS: &[UserConfigMigration] = &[UserConfigMigration {
+  from_major: 1,
+  migrate: migrate_v1_to_v2,
+}];
+
+fn migration_error(message: String) -> ButtplugDeviceError {
+  ButtplugDeviceError::DeviceConfigurationError(format!(
+    "Cannot migrate user device configuration: {}",
+    message
+  ))
+}
+
+fn config_version(config: &Value) -> Result<ConfigVersion, ButtplugDeviceError> {
+  serde_json::from_value(config["version"].clone())
+    .map_err(|_| migration_error("file has no valid version".to_owned()))
+}
+
+/// Upgrade a user config JSON string to the current version of the format.
+///
+/// If `skip_version_check` is set, files from a newer major version are passed through untouched
+/// instead of being rejected.
+pub fn migrate_user_config(
+  config_json: &str,
+  current_version: &ConfigVersion,
+  skip_version_check: bool,
+) -> Result<String, ButtplugDeviceError> {
+  let mut config: Value = serde_json::from_str(config_json)
+    .map_err(|err| ButtplugDeviceError::DeviceConfigurationError(err.to_string()))?;
+  let version = config_version(&config)?;
+  if version.major == current_version.major {
+    return Ok(config_json.to_owned());
+  }
+  if version.major > current_version.major {
+    if skip_version_check {
+      return Ok(config_json.to_owned());
+    }
+    return Err(migration_error(format!(
+      "file version {} is newer than the library's version {}",
+      version, current_version
+    )));
+  }
+  for major in version.major..current_version.major {
+    let migration = USER_CONFIG_MIGRATIONS
+      .iter()
+      .find(|migration| migration.from_major == major)
+      .ok_or_else(|| {
+        migration_error(format!(
+          "version {} files are no longer supported, the configuration will need to be recreated",
+          version
+        ))
+      })?;
+    (migration.migrate)(&mut config)?;
+  }
+  info!(
+    "Migrated user device configuration from version {} to {}.",
+    version, current_version
+  );
+  config["version"] = json!({
+    "major": current_version.major,
+    "minor": current_version.minor
+  });
+  Ok(config.to_string())
+}
+
+/// Version 1 described actuators per message type, as a feature count and a list of step counts,
+/// instead of a list of actuators with step ranges.
+///
+/// ```json
+/// "VibrateCmd": { "FeatureCount": 2, "StepCount": [20, 20] }
+/// ```
+///
+/// Vibrate commands become Vibrate scalar actuators, rotate commands become Rotate actuators, and
+/// linear commands Position actuators. Messages that never had user configurable attributes are
+/// dropped.
+fn migrate_v1_to_v2(config: &mut Value) -> Result<(), ButtplugDeviceError> {
+  let Some(devices) = config
+    .get_mut("user-configs")
+    .and_then(|user_configs| user_configs.get_mut("devices"))
+    .and_then(Value::as_array_mut)
+  else {
+    return Ok(());
+  };
+  for device in devices {
+    let Some(messages) = device
+      .get_mut("config")
+      .and_then(|config| config.get_mut("messages"))
+    else {
+      continue;
+    };
+    let Some(old_messages) = messages.as_object() else {
+      return Err(migration_error(
+        "device messages are not an object".to_owned(),
+      ));
+    };
+    let mut new_messages = Map::new();
+    for (message, attributes) in old_messages {
+      let (new_message, actuator_type) = match message.as_str() {
+        "VibrateCmd" => ("ScalarCmd", "Vibrate"),
+        "RotateCmd" => ("RotateCmd", "Rotate"),
+        "LinearCmd" => ("LinearCmd", "Position"),
+        _ => {
+          debug!("Dropping {} from migrated user config.", message);
+          continue;
+        }
+      };
+      let actuators = migrate_v1_message_attributes(message, attributes, actuator_type)?;
+      new_messages
+        .entry(new_message)
+        .or_insert_with(|| Value::Array(vec![]))
+        .as_array_mut()
+        .expect("Always inserted as an array")
+        .extend(actuators);
+    }
+    *messages = Value::Object(new_messages);
+  }
+  Ok(())
+}
+
+fn migrate_v1_message_attributes(
+  message: &str,
+  attributes: &Value,
+  actuator_type: &str,
+) -> Result<Vec<Value>, ButtplugDeviceError> {
+  let feature_count = attributes["FeatureCount"]
+    .as_u64()
+    .ok_or_else(|| migration_error(format!("{} has no FeatureCount", message)))?;
+  let step_counts: Vec<u64> = attributes["StepCount"]
+    .as_array()
+    .map(|counts| counts.iter().filter_map(Value::as_u64).collect())
+    .unwrap_or_default();
+  if step_counts.len() as u64 != feature_count {
+    return Err(migration_error(format!(
+      "{} has {} features, but {} step counts",
+      message,
+      feature_count,
+      step_counts.len()
+    )));
+  }
+  Ok(
+    step_counts
+      .iter()
+      .map(|step_count| {
+        json!({
+          "StepRange": [0, step_count],
+          "ActuatorType": actuator_type
+        })
+      })
+      .collect(),
+  )
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::util::device_configuration::{get_internal_config_version, load_protocol_configs};
+
+  fn current_version() -> ConfigVersion {
+    get_internal_config_version()
+  }
+
+  fn user_config(major: u32, messages: Value) -> String {
+    json!({
+      "version": { "major": major, "minor": 0 },
+      "user-configs": {
+        "devices": [{
+          "identifier": { "address": "UserConfigTest", "protocol": "lovense", "identifier": "P" },
+          "config": { "display-name": "Edge", "messages": messages }
+        }]
+      }
+    })
+    .to_string()
+  }
+
+  fn v2_messages() -> Value {
+    json!({
+      "ScalarCmd": [
+        { "StepRange": [0, 10], "ActuatorType": "Vibrate" },
+        { "StepRange": [0, 5], "ActuatorType": "Vibrate" }
+      ],
+      "RotateCmd": [{ "StepRange": [0, 20], "ActuatorType": "Rotate" }]
+    })
+  }
+
+  #[test]
+  fn test_migrate_current_version_round_trip() {
+    let config = user_config(current_version().major, v2_messages());
+    let migrated =
+      migrate_user_config(&config, &current_version(), false).expect("Test, assuming infallible.");
+    assert_eq!(migrated, config);
+    assert!(load_protocol_configs(None, Some(migrated), false).is_ok());
+  }
+
+  #[test]
+  fn test_migrate_v1_round_trip() {
+    let config = user_config(
+      1,
+      json!({
+        "VibrateCmd": { "FeatureCount": 2, "StepCount": [10, 5] },
+        "RotateCmd": { "FeatureCount": 1, "StepCount": [20] },
+        "StopDeviceCmd": {}
+      }),
+    );
+    let migrated =
+      migrate_user_config(&config, &current_version(), false).expect("Test, assuming infallible.");
+    let migrated_value: Value =
+      serde_json::from_str(&migrated).expect("Test, assuming infallible.");
+    let mut expected: Value =
+      serde_json::from_str(&user_config(2, v2_messages())).expect("Test, assuming infallible.");
+    expected["version"]["minor"] = json!(current_version().minor);
+    assert_eq!(migrated_value, expected);
+    // Migrating again is a no-op, and the result loads.
+    assert_eq!(
+      migrate_user_config(&migrated, &current_version(), false)
+        .expect("Test, assuming infallible."),
+      migrated
+    );
+    assert!(load_protocol_configs(None, Some(migrated), false).is_ok());
+  }
+
+  #[test]
+  fn test_migrate_unsupported_versions() {
+    let messages = v2_messages();
+    // Too old to have a migration.
+    assert!(
+      migrate_user_config(&user_config(0, messages.clone()), &current_version(), false).is_err()
+    );
+    // Newer than the library.
+    let newer = user_config(current_version().major + 1, messages);
+    assert!(migrate_user_config(&newer, &current_version(), false).is_err());
+    assert_eq!(
+      migrate_user_config(&newer, &current_version(), true).expect("Test, assuming infallible."),
+      newer
+    );
+    // Malformed version 1 attributes.
+    let broken = user_config(
+      1,
+      json!({ "VibrateCmd": { "FeatureCount": 2, "StepCount": [10] } }),
+    );
+    assert!(migrate_user_config(&broken, &current_version(), false).is_err());
+    assert!(migrate_user_config("{}", &current_version(), false).is_err());
+  }
+}
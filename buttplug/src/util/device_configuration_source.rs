@@ -0,0 +1,447 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+//! Sources for the base device configuration.
+//!
+//! By default, servers use the device configuration bundled with the library, so supporting a new
+//! device means waiting for a crate release. A [DeviceConfigurationSource] can fetch the
+//! configuration from somewhere else instead, like a file, or (with the `device-config-url`
+//! feature) the latest configuration published at a URL.
+//!
+//! Sources are loaded before the server is built, and the result handed to the server builder:
+//!
+//! ```ignore
+//! let source = UrlDeviceConfigurationSource::new(url, verification).cache_path(cache);
+//! let config = source.load().await.ok();
+//! server_builder.device_configuration_json(config);
+//! ```
+//!
+//! Configurations are validated against the schema and the library's configuration version before
+//! being returned, so a source can't hand the server something it won't be able to load.
+
+use super::device_configuration::{load_protocol_configs, DEVICE_CONFIGURATION_JSON};
+use crate::core::errors::ButtplugDeviceError;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+/// Somewhere a device configuration file can be loaded from.
+#[async_trait]
+pub trait DeviceConfigurationSource: Send + Sync {
+  /// Load the device configuration JSON.
+  async fn load(&self) -> Result<String, ButtplugDeviceError>;
+}
+
+/// Make sure a configuration can actually be used by this version of the library.
+fn validate_configuration(config_json: &str) -> Result<(), ButtplugDeviceError> {
+  load_protocol_configs(Some(config_json.to_owned()), None, false).map(|_| ())
+}
+
+/// The device configuration compiled into the library.
+#[derive(Debug, Default, Clone)]
+pub struct BundledDeviceConfigurationSource {}
+
+#[async_trait]
+impl DeviceConfigurationSource for BundledDeviceConfigurationSource {
+  async fn load(&self) -> Result<String, ButtplugDeviceError> {
+    Ok(DEVICE_CONFIGURATION_JSON.to_owned())
+  }
+}
+
+/// A device configuration file on disk.
+#[derive(Debug, Clone)]
+pub struct FileDeviceConfigurationSource {
+  path: PathBuf,
+}
+
+impl FileDeviceConfigurationSource {
+  pub fn new(path: &Path) -> Self {
+    Self {
+      path: path.to_owned(),
+    }
+  }
+}
+
+#[async_trait]
+impl DeviceConfigurationSource for FileDeviceConfigurationSource {
+  async fn load(&self) -> Result<String, ButtplugDeviceError> {
+    let config_json = std::fs::read_to_string(&self.path).map_err(|err| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot read device configuration {}: {}",
+        self.path.display(),
+        err
+      ))
+    })?;
+    validate_configuration(&config_json)?;
+    Ok(config_json)
+  }
+}
+
+#[cfg(feature = "device-config-url")]
+pub use url_source::{
+  DeviceConfigurationVerification,
+  UrlDeviceConfigurationSource,
+  DEFAULT_DEVICE_CONFIGURATION_FETCH_TIMEOUT,
+};
+
+#[cfg(feature = "device-config-url")]
+mod url_source {
+  use super::{validate_configuration, DeviceConfigurationSource};
+  use crate::{
+    core::errors::ButtplugDeviceError,
+    util::device_configuration::ProtocolConfiguration,
+  };
+  use async_trait::async_trait;
+  use ring::signature::{UnparsedPublicKey, ED25519};
+  use sha2::{Digest, Sha256};
+  use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+  };
+
+  /// How long to wait on each download before giving up and falling back to the cache.
+  pub const DEFAULT_DEVICE_CONFIGURATION_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+  /// How to check that a downloaded configuration is the one that was published.
+  #[derive(Debug, Clone)]
+  pub enum DeviceConfigurationVerification {
+    /// The SHA-256 hash of the file must match. Pins the source to one exact file.
+    Sha256([u8; 32]),
+    /// The file must have a valid Ed25519 signature from the given public key. The raw 64 byte
+    /// signature is fetched from `signature_url`, or the configuration URL with `.sig` appended if
+    /// None.
+    Ed25519 {
+      public_key: Vec<u8>,
+      signature_url: Option<String>,
+    },
+  }
+
+  fn verification_error(message: &str) -> ButtplugDeviceError {
+    ButtplugDeviceError::DeviceConfigurationError(format!(
+      "Device configuration verification failed: {}",
+      message
+    ))
+  }
+
+  impl DeviceConfigurationVerification {
+    pub(super) fn verify(
+      &self,
+      config: &[u8],
+      signature: Option<&[u8]>,
+    ) -> Result<(), ButtplugDeviceError> {
+      match self {
+        Self::Sha256(expected) => {
+          if Sha256::digest(config).as_slice() == expected {
+            Ok(())
+          } else {
+            Err(verification_error("checksum does not match"))
+          }
+        }
+        Self::Ed25519 { public_key, .. } => {
+          let signature = signature.ok_or_else(|| verification_error("no signature"))?;
+          UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(config, signature)
+            .map_err(|_| verification_error("invalid signature"))
+        }
+      }
+    }
+  }
+
+  async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, ButtplugDeviceError> {
+    let fetch_error = |err: reqwest::Error| {
+      ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Cannot fetch device configuration from {}: {}",
+        url, err
+      ))
+    };
+    let response = client
+      .get(url)
+      .send()
+      .await
+      .and_then(|response| response.error_for_status())
+      .map_err(fetch_error)?;
+    Ok(response.bytes().await.map_err(fetch_error)?.to_vec())
+  }
+
+  fn config_version(config_json: &str) -> Result<(u32, u32), ButtplugDeviceError> {
+    let config: ProtocolConfiguration = serde_json::from_str(config_json)
+      .map_err(|err| ButtplugDeviceError::DeviceConfigurationError(format!("{}", err)))?;
+    Ok((config.version.major, config.version.minor))
+  }
+
+  /// Make sure a download isn't older than what we already have, so an old (but validly signed)
+  /// configuration can't be used to roll back device support.
+  pub(super) fn check_not_older(
+    config_json: &str,
+    cached_json: &str,
+  ) -> Result<(), ButtplugDeviceError> {
+    let version = config_version(config_json)?;
+    let cached_version = config_version(cached_json)?;
+    if version < cached_version {
+      Err(ButtplugDeviceError::DeviceConfigurationError(format!(
+        "Downloaded device configuration version {}.{} is older than cached version {}.{}",
+        version.0, version.1, cached_version.0, cached_version.1
+      )))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Where the signature for a cached configuration is kept.
+  fn signature_cache_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".sig");
+    PathBuf::from(signature_path)
+  }
+
+  /// A verified configuration, along with the signature it was verified with (if any).
+  struct VerifiedConfiguration {
+    config_json: String,
+    signature: Option<Vec<u8>>,
+  }
+
+  /// The device configuration published at a URL.
+  ///
+  /// Downloads are verified before use. If a cache path is set, each verified download is saved
+  /// there (with its signature next to it, at the cache path with `.sig` appended), and used
+  /// instead if a later download fails, so a server that has fetched the configuration once can
+  /// still start offline. The cache is verified again when loaded, and downloads older than the
+  /// cached configuration are rejected.
+  #[derive(Debug, Clone)]
+  pub struct UrlDeviceConfigurationSource {
+    url: String,
+    verification: DeviceConfigurationVerification,
+    cache_path: Option<PathBuf>,
+    timeout: Duration,
+  }
+
+  impl UrlDeviceConfigurationSource {
+    pub fn new(url: &str, verification: DeviceConfigurationVerification) -> Self {
+      Self {
+        url: url.to_owned(),
+        verification,
+        cache_path: None,
+        timeout: DEFAULT_DEVICE_CONFIGURATION_FETCH_TIMEOUT,
+      }
+    }
+
+    /// Save verified downloads to this path, and fall back to it when downloading fails.
+    pub fn cache_path(mut self, path: &Path) -> Self {
+      self.cache_path = Some(path.to_owned());
+      self
+    }
+
+    /// How long to wait on each download. Defaults to
+    /// [DEFAULT_DEVICE_CONFIGURATION_FETCH_TIMEOUT].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+      self.timeout = timeout;
+      self
+    }
+
+    /// Verify a configuration and make sure it's usable.
+    fn verify(
+      &self,
+      config: Vec<u8>,
+      signature: Option<Vec<u8>>,
+    ) -> Result<VerifiedConfiguration, ButtplugDeviceError> {
+      self.verification.verify(&config, signature.as_deref())?;
+      let config_json = String::from_utf8(config).map_err(|err| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Device configuration is not valid UTF-8: {}",
+          err
+        ))
+      })?;
+      validate_configuration(&config_json)?;
+      Ok(VerifiedConfiguration {
+        config_json,
+        signature,
+      })
+    }
+
+    async fn download(&self) -> Result<VerifiedConfiguration, ButtplugDeviceError> {
+      let client = reqwest::Client::builder()
+        .timeout(self.timeout)
+        .build()
+        .map_err(|err| {
+          ButtplugDeviceError::DeviceConfigurationError(format!(
+            "Cannot create HTTP client for device configuration: {}",
+            err
+          ))
+        })?;
+      let config = fetch(&client, &self.url).await?;
+      let signature = match &self.verification {
+        DeviceConfigurationVerification::Ed25519 { signature_url, .. } => {
+          let signature_url = signature_url
+            .clone()
+            .unwrap_or_else(|| format!("{}.sig", self.url));
+          Some(fetch(&client, &signature_url).await?)
+        }
+        DeviceConfigurationVerification::Sha256(_) => None,
+      };
+      self.verify(config, signature)
+    }
+
+    fn load_cache(&self, path: &Path) -> Result<VerifiedConfiguration, ButtplugDeviceError> {
+      let read_error = |path: &Path, err: std::io::Error| {
+        ButtplugDeviceError::DeviceConfigurationError(format!(
+          "Cannot read cached device configuration {}: {}",
+          path.display(),
+          err
+        ))
+      };
+      let config = std::fs::read(path).map_err(|err| read_error(path, err))?;
+      let signature = match &self.verification {
+        DeviceConfigurationVerification::Ed25519 { .. } => {
+          let signature_path = signature_cache_path(path);
+          Some(std::fs::read(&signature_path).map_err(|err| read_error(&signature_path, err))?)
+        }
+        DeviceConfigurationVerification::Sha256(_) => None,
+      };
+      self.verify(config, signature)
+    }
+
+    fn save_cache(&self, path: &Path, config: &VerifiedConfiguration) -> std::io::Result<()> {
+      // Anything that fails partway through leaves a cache that fails verification, which is
+      // treated the same as having no cache.
+      if let Some(signature) = &config.signature {
+        std::fs::write(signature_cache_path(path), signature)?;
+      }
+      std::fs::write(path, &config.config_json)
+    }
+  }
+
+  #[async_trait]
+  impl DeviceConfigurationSource for UrlDeviceConfigurationSource {
+    async fn load(&self) -> Result<String, ButtplugDeviceError> {
+      let cached = self
+        .cache_path
+        .as_ref()
+        .and_then(|path| match self.load_cache(path) {
+          Ok(cached) => Some(cached),
+          Err(err) => {
+            debug!("Not using device configuration cache: {}", err);
+            None
+          }
+        });
+      let downloaded = self.download().await.and_then(|config| {
+        if let Some(cached) = &cached {
+          check_not_older(&config.config_json, &cached.config_json)?;
+        }
+        Ok(config)
+      });
+      match downloaded {
+        Ok(config) => {
+          info!("Loaded device configuration from {}", self.url);
+          if let Some(path) = &self.cache_path {
+            if let Err(err) = self.save_cache(path, &config) {
+              warn!(
+                "Cannot cache device configuration to {}: {}",
+                path.display(),
+                err
+              );
+            }
+          }
+          Ok(config.config_json)
+        }
+        Err(err) => {
+          let Some(cached) = cached else {
+            return Err(err);
+          };
+          warn!("{}, using cached configuration.", err);
+          Ok(cached.config_json)
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  #[cfg(feature = "device-config-url")]
+  use std::time::Duration;
+
+  #[tokio::test]
+  async fn test_file_source() {
+    let path = std::env::temp_dir().join("buttplug-test-device-config-source.json");
+    std::fs::write(&path, DEVICE_CONFIGURATION_JSON).expect("Test, assuming infallible.");
+    let source = FileDeviceConfigurationSource::new(&path);
+    assert_eq!(
+      source.load().await.expect("Test, assuming infallible."),
+      DEVICE_CONFIGURATION_JSON
+    );
+    std::fs::write(&path, "{\"version\": {\"major\": 0, \"minor\": 0}}")
+      .expect("Test, assuming infallible.");
+    assert!(source.load().await.is_err());
+    std::fs::remove_file(&path).expect("Test, assuming infallible.");
+    assert!(source.load().await.is_err());
+  }
+
+  #[cfg(feature = "device-config-url")]
+  #[tokio::test]
+  async fn test_url_source_verification() {
+    use ring::{
+      rand::SystemRandom,
+      signature::{Ed25519KeyPair, KeyPair},
+    };
+    use sha2::{Digest, Sha256};
+
+    let config = DEVICE_CONFIGURATION_JSON.as_bytes();
+    let checksum = DeviceConfigurationVerification::Sha256(Sha256::digest(config).into());
+    assert!(checksum.verify(config, None).is_ok());
+    assert!(checksum.verify(b"{}", None).is_err());
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+      .expect("Test, assuming infallible.");
+    let key_pair =
+      Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("Test, assuming infallible.");
+    let signed = DeviceConfigurationVerification::Ed25519 {
+      public_key: key_pair.public_key().as_ref().to_vec(),
+      signature_url: None,
+    };
+    let signature = key_pair.sign(config);
+    assert!(signed.verify(config, Some(signature.as_ref())).is_ok());
+    assert!(signed.verify(b"{}", Some(signature.as_ref())).is_err());
+    assert!(signed.verify(config, None).is_err());
+
+    // Unreachable URLs fall back to the cache, if there is one.
+    let path = std::env::temp_dir().join("buttplug-test-device-config-cache.json");
+    let signature_path = std::env::temp_dir().join("buttplug-test-device-config-cache.json.sig");
+    let source = UrlDeviceConfigurationSource::new("http://127.0.0.1:1/config.json", signed)
+      .timeout(Duration::from_secs(1));
+    assert!(source.load().await.is_err());
+    std::fs::write(&path, config).expect("Test, assuming infallible.");
+    let source = source.cache_path(&path);
+    // The cache is verified like a download, so it's unusable without its signature.
+    let _ = std::fs::remove_file(&signature_path);
+    assert!(source.load().await.is_err());
+    std::fs::write(&signature_path, b"not a signature").expect("Test, assuming infallible.");
+    assert!(source.load().await.is_err());
+    std::fs::write(&signature_path, signature.as_ref()).expect("Test, assuming infallible.");
+    assert_eq!(
+      source.load().await.expect("Test, assuming infallible."),
+      DEVICE_CONFIGURATION_JSON
+    );
+    std::fs::remove_file(&path).expect("Test, assuming infallible.");
+    std::fs::remove_file(&signature_path).expect("Test, assuming infallible.");
+  }
+
+  #[cfg(feature = "device-config-url")]
+  #[test]
+  fn test_url_source_rejects_older_configuration() {
+    let with_version = |major: u64, minor: u64| {
+      let mut config: serde_json::Value =
+        serde_json::from_str(DEVICE_CONFIGURATION_JSON).expect("Test, assuming infallible.");
+      config["version"] = serde_json::json!({"major": major, "minor": minor});
+      config.to_string()
+    };
+    assert!(url_source::check_not_older(&with_version(2, 5), &with_version(2, 5)).is_ok());
+    assert!(url_source::check_not_older(&with_version(2, 6), &with_version(2, 5)).is_ok());
+    assert!(url_source::check_not_older(&with_version(3, 0), &with_version(2, 5)).is_ok());
+    assert!(url_source::check_not_older(&with_version(2, 4), &with_version(2, 5)).is_err());
+    assert!(url_source::check_not_older(&with_version(1, 9), &with_version(2, 5)).is_err());
+  }
+}
@@ -11,6 +11,10 @@
 pub mod async_manager;
 #[cfg(feature = "server")]
 pub mod device_configuration;
+#[cfg(feature = "server")]
+pub mod device_configuration_migration;
+#[cfg(feature = "server")]
+pub mod device_configuration_source;
 pub mod future;
 pub mod json;
 pub mod logging;
@@ -122,6 +126,11 @@ pub async fn in_process_client(client_name: &str, allow_raw_messages: bool) -> B
     use crate::server::device::hardware::communication::xinput::XInputDeviceCommunicationManagerBuilder;
     server_builder.comm_manager(XInputDeviceCommunicationManagerBuilder::default());
   }
+  #[cfg(all(feature = "webbluetooth-manager", target_arch = "wasm32"))]
+  {
+    use crate::server::device::hardware::communication::webbluetooth::WebBluetoothCommunicationManagerBuilder;
+    server_builder.comm_manager(WebBluetoothCommunicationManagerBuilder::default());
+  }
   if allow_raw_messages {
     server_builder.allow_raw_messages();
   }
@@ -0,0 +1,131 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "audio-reactive")]
+mod audio_reactive_tests {
+  use crate::util::test_device_manager::{
+    TestDeviceCommunicationManagerBuilder,
+    TestDeviceIdentifier,
+  };
+  use buttplug::{
+    core::message::{
+      self,
+      ActuatorType,
+      ButtplugServerMessage,
+      ScalarCmd,
+      ScalarSubcommand,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+    server::{
+      audio::{AudioInput, AudioReactiveConfig},
+      device::hardware::HardwareCommand,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::{pin_mut, StreamExt};
+  use std::time::Duration;
+  use tokio::sync::mpsc;
+
+  const SAMPLE_RATE: u32 = 44100;
+
+  /// Push full scale audio until the device gets a write, returning the written level.
+  async fn push_until_write(
+    input: &AudioInput,
+    receiver: &mut mpsc::Receiver<HardwareCommand>,
+  ) -> Option<u8> {
+    let samples = vec![1.0f32; SAMPLE_RATE as usize / 100];
+    for _ in 0..50 {
+      input
+        .push(&samples, SAMPLE_RATE, 1)
+        .expect("Test, assuming infallible.");
+      if let Ok(Some(HardwareCommand::Write(write))) =
+        tokio::time::timeout(Duration::from_millis(10), receiver.recv()).await
+      {
+        return Some(write.data()[1]);
+      }
+    }
+    None
+  }
+
+  /// Every write the device gets until it goes quiet.
+  async fn drain_writes(receiver: &mut mpsc::Receiver<HardwareCommand>) -> Vec<Vec<u8>> {
+    let mut writes = vec![];
+    while let Ok(Some(command)) =
+      tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await
+    {
+      if let HardwareCommand::Write(write) = command {
+        writes.push(write.data().clone());
+      }
+    }
+    writes
+  }
+
+  #[tokio::test]
+  async fn test_stop_all_devices_pauses_audio_reactive() {
+    let mut builder = TestDeviceCommunicationManagerBuilder::default();
+    let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder
+      .comm_manager(builder)
+      .audio_reactive(AudioReactiveConfig::default());
+    let server = server_builder.finish().expect("Test, assuming infallible.");
+    let input = server.audio_input().expect("Test, assuming infallible.");
+    let recv = server.event_stream();
+    pin_mut!(recv);
+    server
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    server
+      .parse_message(message::StartScanning::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+    let mut device_index = 100;
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::DeviceAdded(da) = msg {
+        device_index = da.device_index();
+        break;
+      }
+    }
+
+    // Audio turns the device on.
+    let level = push_until_write(&input, &mut device.receiver).await;
+    assert!(level.is_some_and(|level| level > 0));
+    // Let the level settle.
+    drain_writes(&mut device.receiver).await;
+
+    server
+      .parse_message(message::StopAllDevices::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+    let stop_writes = drain_writes(&mut device.receiver).await;
+    assert!(!stop_writes.is_empty());
+    assert!(stop_writes.iter().all(|write| write[1] == 0));
+    // Audio doesn't turn it back on after the stop.
+    assert_eq!(push_until_write(&input, &mut device.receiver).await, None);
+
+    // Until a client sends output to the device again, which also lets the audio back in.
+    server
+      .parse_message(
+        ScalarCmd::new(
+          device_index,
+          vec![ScalarSubcommand::new(0, 0.1, ActuatorType::Vibrate)],
+        )
+        .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    let level = push_until_write(&input, &mut device.receiver).await;
+    let writes = drain_writes(&mut device.receiver).await;
+    assert!(level.is_some_and(|level| level > 13) || writes.iter().any(|write| write[1] > 13));
+  }
+}
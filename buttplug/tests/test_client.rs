@@ -167,6 +167,41 @@ async fn test_client_ping() {
   // TODO Watch for ping events
   assert!(client.ping().await.is_err());
 }
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_request_log() {
+  use buttplug::{core::message::LogLevel, server::log_forwarding::ButtplugLogForwardingLayer};
+  use tracing_subscriber::prelude::*;
+  let layer = ButtplugLogForwardingLayer::default();
+  let subscriber = tracing_subscriber::registry().with(layer.clone());
+  let _default = tracing::subscriber::set_default(subscriber);
+  let server = ButtplugServerBuilder::default()
+    .log_forwarding_layer(&layer)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server)
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  let mut event_stream = client.event_stream();
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  client
+    .request_log(LogLevel::Warn)
+    .await
+    .expect("Test, assuming infallible.");
+  tracing::warn!(target: "buttplug::server::device", "Forwarded to the client");
+  while let Some(event) = event_stream.next().await {
+    if let ButtplugClientEvent::Log(log) = event {
+      assert_eq!(log.log_level(), LogLevel::Warn);
+      assert!(log.log_message().ends_with("Forwarded to the client"));
+      break;
+    }
+  }
+}
+
 /*
 // Tests both the stop all devices functionality, as well as both ends of the
 // command range for is_in_command_range message validation.
@@ -8,21 +8,48 @@
 mod util;
 use buttplug::{
   client::{
+    ButtplugClient,
     ButtplugClientDeviceEvent,
     ButtplugClientError,
     ButtplugClientEvent,
+    DeviceCommandBuilder,
+    ScalarCommand,
     ScalarValueCommand,
+    SensorValue,
   },
   core::{
+    connector::ButtplugInProcessClientConnectorBuilder,
     errors::{ButtplugDeviceError, ButtplugError, ButtplugMessageError},
-    message::{self, ButtplugClientMessage, ClientDeviceMessageAttributes},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugClientMessage,
+      ButtplugDeviceMessageType,
+      ClientDeviceMessageAttributes,
+      DeviceRemovedReason,
+      Endpoint,
+      SensorType,
+    },
+  },
+  server::{
+    device::hardware::{HardwareCommand, HardwareSubscribeCmd, HardwareUnsubscribeCmd},
+    ButtplugServerBuilder,
   },
   util::async_manager,
 };
 use futures::StreamExt;
 use std::{sync::Arc, time::Duration};
 use tokio::time::sleep;
-use util::{test_client_with_device, test_device_manager::TestHardwareEvent};
+use util::{
+  test_client_with_device,
+  test_device_manager::{
+    TestDeviceCommunicationManagerBuilder,
+    TestDeviceIdentifier,
+    TestHardwareEvent,
+    TestHardwareNotification,
+  },
+  test_server_with_device,
+};
 
 #[cfg(feature = "server")]
 #[tokio::test]
@@ -52,6 +79,11 @@ async fn test_client_device_connected_status() {
   while let Some(msg) = device_event_stream.next().await {
     if let ButtplugClientDeviceEvent::DeviceRemoved = msg {
       assert!(!test_device.connected());
+      assert_eq!(
+        test_device.removed_reason(),
+        Some(DeviceRemovedReason::LinkLost)
+      );
+      assert!(!test_device.will_reconnect());
       break;
     }
   }
@@ -62,6 +94,70 @@ async fn test_client_device_connected_status() {
   assert!(!client.connected());
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_powered_off_reason() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("LVS-Test", None));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder);
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server_builder.finish().expect("Test, assuming infallible."))
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  // Answer the Lovense identification (subscribe, then DeviceType;) once it's been sent.
+  for _ in 0..2 {
+    device
+      .receiver
+      .recv()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::Rx, b"A:11:0082059AD3BD;".to_vec()),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(_) = msg {
+      break;
+    }
+  }
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::Rx, b"POWEROFF;".to_vec()),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  device
+    .sender
+    .send(TestHardwareEvent::Disconnect)
+    .await
+    .expect("Test, assuming infallible.");
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceRemoved(removed) = msg {
+      assert_eq!(
+        removed.removed_reason(),
+        Some(DeviceRemovedReason::PoweredOff)
+      );
+      return;
+    }
+  }
+  panic!("Event stream closed before device was removed.");
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_device_client_disconnected_status() {
@@ -171,6 +267,133 @@ async fn test_client_device_invalid_command() {
   ));
 }
 
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_command_builder() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  let step_count = *test_device.vibrate_attributes()[0].step_count();
+  let scalar_count = test_device.scalar_attributes().len() as u32;
+
+  // Invalid commands are caught before anything is sent to the server.
+  let check_error = |builder: &DeviceCommandBuilder, expected: fn(&ButtplugDeviceError) -> bool| {
+    assert!(expected(&builder.build().unwrap_err()));
+  };
+  check_error(
+    test_device
+      .command()
+      .scalar(scalar_count, 0.5, ActuatorType::Vibrate),
+    |err| matches!(err, ButtplugDeviceError::DeviceFeatureIndexError(..)),
+  );
+  check_error(
+    test_device.command().scalar(0, 2.0, ActuatorType::Vibrate),
+    |err| matches!(err, ButtplugDeviceError::DeviceFeatureValueOutOfRange(0, _)),
+  );
+  check_error(
+    test_device.command().scalar(0, 0.5, ActuatorType::Rotate),
+    |err| matches!(err, ButtplugDeviceError::DeviceActuatorTypeMismatch(..)),
+  );
+  check_error(
+    test_device
+      .command()
+      .scalar_step(0, step_count + 1, ActuatorType::Vibrate),
+    |err| matches!(err, ButtplugDeviceError::DeviceFeatureStepOutOfRange(0, _, _)),
+  );
+  check_error(test_device.command().linear(0, 100, 0.5), |err| {
+    matches!(
+      err,
+      ButtplugDeviceError::MessageNotSupported(ButtplugDeviceMessageType::LinearCmd)
+    )
+  });
+  check_error(&test_device.command(), |err| {
+    matches!(err, ButtplugDeviceError::ProtocolRequirementError(_))
+  });
+  // One bad command stops the whole set from going out.
+  assert!(test_device
+    .command()
+    .scalar(0, 0.5, ActuatorType::Vibrate)
+    .rotate(0, 0.5, true)
+    .send()
+    .await
+    .is_err());
+  assert!(device.receiver.try_recv().is_err());
+
+  test_device
+    .command()
+    .scalar_step(0, step_count, ActuatorType::Vibrate)
+    .send()
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Write(_))
+  ));
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_group() {
+  let (client, mut device) = test_client_with_device().await;
+
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+
+  let mut group = client.device_group("all");
+  group
+    .add_device(&test_device)
+    .await
+    .expect("Test, assuming infallible.");
+  let result = group
+    .scalar(&ScalarCommand::Scalar((0.5, ActuatorType::Vibrate)))
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(result.is_success());
+  assert_eq!(result.succeeded(), &vec![test_device.index()]);
+  assert!(matches!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Write(_))
+  ));
+
+  let result = group
+    .scalar(&ScalarCommand::Scalar((0.5, ActuatorType::Rotate)))
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(result.skipped(), &vec![test_device.index()]);
+
+  group
+    .remove_device(&test_device)
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(group
+    .scalar(&ScalarCommand::Scalar((0.5, ActuatorType::Vibrate)))
+    .await
+    .is_err());
+}
+
 #[cfg(feature = "server")]
 #[tokio::test]
 async fn test_client_repeated_deviceadded_message() {
@@ -191,6 +414,7 @@ async fn test_client_repeated_deviceadded_message() {
       "Test Device",
       &None,
       &None,
+      &None,
       &ClientDeviceMessageAttributes::default(),
     );
     helper_clone
@@ -239,6 +463,7 @@ async fn test_client_repeated_deviceremoved_message() {
       "Test Device",
       &None,
       &None,
+      &None,
       &ClientDeviceMessageAttributes::default(),
     );
     let device_removed = message::DeviceRemoved::new(1);
@@ -279,6 +504,148 @@ async fn test_client_repeated_deviceremoved_message() {
 }
 
 // TODO Test invalid messages to device
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_sensor_stream() {
+  let (server, mut device) = test_server_with_device("Boost", false).await;
+  let connector = ButtplugInProcessClientConnectorBuilder::default()
+    .server(server)
+    .finish();
+  let client = ButtplugClient::new("Test Client");
+  client
+    .connect(connector)
+    .await
+    .expect("Test, assuming infallible.");
+  let mut event_stream = client.event_stream();
+  client
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut client_device = None;
+  while let Some(msg) = event_stream.next().await {
+    if let ButtplugClientEvent::DeviceAdded(da) = msg {
+      client_device = Some(da);
+      break;
+    }
+  }
+  let test_device = client_device.expect("Test, assuming infallible.");
+  assert!(test_device
+    .sensor_stream(SensorType::Button)
+    .await
+    .is_err());
+
+  let mut sensor_stream = test_device
+    .sensor_stream(SensorType::Pressure)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Subscribe(HardwareSubscribeCmd::new(
+      Endpoint::RxPressure
+    )))
+  );
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(Endpoint::RxPressure, vec![0x00, 0x01, 0x04, 0x00, 0x05, 0x00, 0x06]),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  let update = sensor_stream
+    .next()
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(update.sensor_index(), 0);
+  assert_eq!(*update.value(), SensorValue::Pressure(5));
+
+  // Dropping the last stream should unsubscribe from the sensors.
+  drop(sensor_stream);
+  assert_eq!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Unsubscribe(HardwareUnsubscribeCmd::new(
+      Endpoint::RxPressure
+    )))
+  );
+}
+
+#[cfg(feature = "server")]
+#[tokio::test]
+async fn test_client_device_kiiroo_sensor_streams() {
+  for (name, sensor_type, endpoint, notification, expected) in [
+    (
+      "Pearl2",
+      SensorType::Pressure,
+      Endpoint::RxTouch,
+      vec![0x20, 0x00, 0x00, 0x00],
+      SensorValue::Pressure(0x20),
+    ),
+    (
+      "KEON",
+      SensorType::Position,
+      Endpoint::Rx,
+      vec![0x03, 0x00, 0x32, 0x4a],
+      SensorValue::Position(0x4a),
+    ),
+  ] {
+    let (server, mut device) = test_server_with_device(name, false).await;
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+    let client = ButtplugClient::new("Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    let mut client_device = None;
+    while let Some(msg) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(da) = msg {
+        client_device = Some(da);
+        break;
+      }
+    }
+    let test_device = client_device.expect("Test, assuming infallible.");
+    let mut sensor_stream = test_device
+      .sensor_stream(sensor_type)
+      .await
+      .expect("Test, assuming infallible.");
+    // Skip past anything the protocol sent while initializing.
+    let subscribe = HardwareCommand::Subscribe(HardwareSubscribeCmd::new(endpoint));
+    while device
+      .receiver
+      .recv()
+      .await
+      .expect("Test, assuming infallible.")
+      != subscribe
+    {}
+    device
+      .sender
+      .send(TestHardwareEvent::Notifications(vec![
+        TestHardwareNotification::new(endpoint, notification),
+      ]))
+      .await
+      .expect("Test, assuming infallible.");
+    let update = sensor_stream
+      .next()
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(update.sensor_index(), 0);
+    assert_eq!(*update.value(), expected);
+
+    drop(sensor_stream);
+    assert_eq!(
+      device.receiver.recv().await,
+      Some(HardwareCommand::Unsubscribe(HardwareUnsubscribeCmd::new(
+        endpoint
+      )))
+    );
+  }
+}
+
 // TODO Test invalid parameters in message
 // TODO Test device invalidation across client connections (i.e. a device shouldn't be allowed to reconnect even if index is the same)
 // TODO Test DeviceList being sent followed by repeat DeviceAdded
@@ -0,0 +1,328 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use buttplug::{
+  client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent},
+  core::{
+    connector::{
+      transport::{ButtplugConnectorTransport, ButtplugTransportIncomingMessage},
+      ButtplugConnectorError,
+      ButtplugReconnectPolicy,
+      ButtplugRemoteClientConnector,
+    },
+    message::{
+      self,
+      serializer::{
+        ButtplugMessageSerializer,
+        ButtplugSerializedMessage,
+        ButtplugServerJSONSerializer,
+      },
+      ButtplugClientMessage,
+      ButtplugDeviceMessage,
+      ButtplugMessage,
+      ButtplugServerMessage,
+      ClientDeviceMessageAttributes,
+      DeviceConnectionInfo,
+      DeviceMessageInfo,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+};
+use futures::{
+  future::{self, BoxFuture},
+  join,
+  FutureExt,
+  Stream,
+  StreamExt,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+/// Server side of a single transport connection. Dropping it drops the connection.
+struct TestConnection {
+  outgoing: Receiver<ButtplugSerializedMessage>,
+  incoming: Sender<ButtplugTransportIncomingMessage>,
+  serializer: ButtplugServerJSONSerializer,
+}
+
+impl TestConnection {
+  async fn next_message(&mut self) -> ButtplugClientMessage {
+    let msg = self
+      .outgoing
+      .recv()
+      .await
+      .expect("Test, assuming infallible.");
+    self
+      .serializer
+      .deserialize(&msg)
+      .expect("Test, assuming infallible.")[0]
+      .clone()
+  }
+
+  async fn reply(&self, id: u32, mut msg: ButtplugServerMessage) {
+    msg.set_id(id);
+    self
+      .incoming
+      .send(ButtplugTransportIncomingMessage::Message(
+        self.serializer.serialize(&[msg]),
+      ))
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
+  /// Answer the client handshake, reporting the given devices.
+  async fn run_handshake(&mut self, devices: Vec<DeviceMessageInfo>) {
+    let msg = self.next_message().await;
+    assert!(matches!(msg, ButtplugClientMessage::RequestServerInfo(..)));
+    self
+      .reply(
+        msg.id(),
+        message::ServerInfo::new("test server", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION, 0).into(),
+      )
+      .await;
+    let msg = self.next_message().await;
+    assert!(matches!(msg, ButtplugClientMessage::RequestDeviceList(..)));
+    self
+      .reply(msg.id(), message::DeviceList::new(devices).into())
+      .await;
+  }
+}
+
+/// Transport that hands every connection to the test, so the test can play the server and drop
+/// connections whenever it wants.
+struct ReconnectableTransport {
+  connection_sender: UnboundedSender<TestConnection>,
+}
+
+impl ButtplugConnectorTransport for ReconnectableTransport {
+  fn connect(
+    &self,
+    outgoing: Receiver<ButtplugSerializedMessage>,
+    incoming: Sender<ButtplugTransportIncomingMessage>,
+  ) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    let result = self
+      .connection_sender
+      .send(TestConnection {
+        outgoing,
+        incoming,
+        serializer: ButtplugServerJSONSerializer::default(),
+      })
+      .map_err(|_| ButtplugConnectorError::ConnectorGenericError("Server gone".to_owned()));
+    future::ready(result).boxed()
+  }
+
+  fn disconnect(self) -> BoxFuture<'static, Result<(), ButtplugConnectorError>> {
+    future::ready(Ok(())).boxed()
+  }
+}
+
+fn device_info(index: u32, address: &str) -> DeviceMessageInfo {
+  DeviceMessageInfo::new(
+    index,
+    "Test Device",
+    &None,
+    &None,
+    &Some(DeviceConnectionInfo::new("btle", address, None)),
+    ClientDeviceMessageAttributes::default(),
+  )
+}
+
+fn device_at(client: &ButtplugClient, address: &str) -> Arc<ButtplugClientDevice> {
+  client
+    .devices()
+    .into_iter()
+    .find(|device| {
+      device
+        .connection_info()
+        .as_ref()
+        .is_some_and(|info| info.address() == address)
+    })
+    .expect("Test, assuming infallible.")
+}
+
+async fn wait_for_event(
+  events: &mut (impl Stream<Item = ButtplugClientEvent> + Unpin),
+  matcher: impl Fn(&ButtplugClientEvent) -> bool,
+) -> Vec<ButtplugClientEvent> {
+  let mut seen = vec![];
+  while let Some(event) = events.next().await {
+    let done = matcher(&event);
+    seen.push(event);
+    if done {
+      return seen;
+    }
+  }
+  panic!("Event stream ended early.");
+}
+
+async fn connect_client(
+  policy: ButtplugReconnectPolicy,
+  devices: Vec<DeviceMessageInfo>,
+) -> (
+  ButtplugClient,
+  UnboundedReceiver<TestConnection>,
+  TestConnection,
+) {
+  let (connection_sender, mut connections) = mpsc::unbounded_channel();
+  let mut connector =
+    ButtplugRemoteClientConnector::<ReconnectableTransport>::new(ReconnectableTransport {
+      connection_sender,
+    });
+  connector.set_reconnect_policy(policy);
+  let client = ButtplugClient::new("Reconnect Test Client");
+  let (result, mut connection) = join!(client.connect(connector), async {
+    let mut connection = connections
+      .recv()
+      .await
+      .expect("Test, assuming infallible.");
+    connection.run_handshake(devices).await;
+    connection
+  });
+  result.expect("Test, assuming infallible.");
+  // Make sure the device list has been processed before handing the client out.
+  let (result, _) = join!(client.ping(), async {
+    let msg = connection.next_message().await;
+    connection
+      .reply(msg.id(), message::Ok::new(msg.id()).into())
+      .await;
+  });
+  result.expect("Test, assuming infallible.");
+  (client, connections, connection)
+}
+
+#[tokio::test]
+async fn test_client_reconnect_rebinds_devices() {
+  let (client, mut connections, connection) = connect_client(
+    ButtplugReconnectPolicy::new(3, Duration::from_millis(10)),
+    vec![device_info(0, "AA"), device_info(1, "BB")],
+  )
+  .await;
+  let mut events = client.event_stream();
+  let device_a = device_at(&client, "AA");
+  let device_b = device_at(&client, "BB");
+
+  drop(connection);
+  wait_for_event(&mut events, |event| {
+    matches!(event, ButtplugClientEvent::ServerReconnecting)
+  })
+  .await;
+  let mut connection = connections
+    .recv()
+    .await
+    .expect("Test, assuming infallible.");
+  // The server comes back with AA at a new index, BB gone, and a new device at BB's old index.
+  connection
+    .run_handshake(vec![device_info(5, "AA"), device_info(1, "CC")])
+    .await;
+  let seen = wait_for_event(&mut events, |event| {
+    matches!(event, ButtplugClientEvent::ServerConnect)
+  })
+  .await;
+  assert!(seen.iter().any(
+    |event| matches!(event, ButtplugClientEvent::DeviceRemoved(device) if device.index() == 1)
+  ));
+  assert!(seen
+    .iter()
+    .any(|event| matches!(event, ButtplugClientEvent::DeviceAdded(device) if device.index() == 1)));
+  assert!(seen.iter().any(|event| matches!(
+    event,
+    ButtplugClientEvent::DeviceIndexChanged(device, 0) if Arc::ptr_eq(device, &device_a)
+  )));
+  assert!(client.connected());
+  assert!(device_a.connected());
+  assert_eq!(device_a.index(), 5);
+  assert!(!device_b.connected());
+  assert!(Arc::ptr_eq(&device_a, &device_at(&client, "AA")));
+
+  // The existing handle should now talk to the new index.
+  let (result, _) = join!(device_a.stop(), async {
+    let msg = connection.next_message().await;
+    if let ButtplugClientMessage::StopDeviceCmd(cmd) = &msg {
+      assert_eq!(cmd.device_index(), 5);
+    } else {
+      panic!("Expected StopDeviceCmd, got {:?}", msg);
+    }
+    connection
+      .reply(msg.id(), message::Ok::new(msg.id()).into())
+      .await;
+  });
+  result.expect("Test, assuming infallible.");
+}
+
+#[tokio::test]
+async fn test_client_revives_readded_devices() {
+  let (client, _connections, connection) = connect_client(
+    ButtplugReconnectPolicy::new(3, Duration::from_millis(10)),
+    vec![device_info(0, "AA")],
+  )
+  .await;
+  let mut events = client.event_stream();
+  let device = device_at(&client, "AA");
+
+  // The server restarts, so the device is removed, then added back at a new index, after a
+  // different device has taken its old one.
+  connection
+    .reply(0, message::DeviceRemoved::new(0).into())
+    .await;
+  wait_for_event(&mut events, |event| {
+    matches!(event, ButtplugClientEvent::DeviceRemoved(..))
+  })
+  .await;
+  assert!(!device.connected());
+  for info in [device_info(0, "CC"), device_info(4, "AA")] {
+    connection
+      .reply(
+        0,
+        message::DeviceAdded::new(
+          info.device_index(),
+          info.device_name(),
+          info.device_display_name(),
+          &None,
+          info.device_connection_info(),
+          info.device_messages(),
+        )
+        .into(),
+      )
+      .await;
+  }
+  let seen = wait_for_event(&mut events, |event| {
+    matches!(event, ButtplugClientEvent::DeviceAdded(added) if added.index() == 4)
+  })
+  .await;
+
+  // The old handle comes back for the same address, and the new device gets a handle of its own.
+  assert!(seen.iter().any(|event| matches!(
+    event,
+    ButtplugClientEvent::DeviceIndexChanged(changed, 0) if Arc::ptr_eq(changed, &device)
+  )));
+  assert!(device.connected());
+  assert_eq!(device.index(), 4);
+  assert!(Arc::ptr_eq(&device, &device_at(&client, "AA")));
+  assert!(!Arc::ptr_eq(&device, &device_at(&client, "CC")));
+}
+
+#[tokio::test]
+async fn test_client_reconnect_gives_up() {
+  let (client, connections, connection) = connect_client(
+    ButtplugReconnectPolicy::new(2, Duration::from_millis(10)),
+    vec![device_info(0, "AA")],
+  )
+  .await;
+  let mut events = client.event_stream();
+  let device = device_at(&client, "AA");
+
+  // With nothing listening for connections, every reconnect attempt fails.
+  drop(connections);
+  drop(connection);
+  wait_for_event(&mut events, |event| {
+    matches!(event, ButtplugClientEvent::ServerDisconnect)
+  })
+  .await;
+  assert!(!client.connected());
+  assert!(!device.connected());
+  assert!(client.devices().is_empty());
+}
@@ -0,0 +1,196 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "simulated-manager")]
+mod test {
+  use buttplug::{
+    core::{
+      errors::{ButtplugDeviceError, ButtplugError},
+      message::{self, ButtplugServerMessage, DeviceAdded, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION},
+    },
+    server::{
+      device::{
+        hardware::communication::simulated::{
+          SimulatedDeviceCommunicationManagerBuilder,
+          SimulatedDeviceConfig,
+          SimulatedDeviceHandle,
+        },
+        RememberedDeviceStore,
+      },
+      ButtplugServer,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::{pin_mut, Stream, StreamExt};
+
+  const LEFT_ADDRESS: &str = "AA:BB:CC:DD:EE:10";
+  const RIGHT_ADDRESS: &str = "AA:BB:CC:DD:EE:11";
+
+  async fn setup_server(
+    store: Option<RememberedDeviceStore>,
+  ) -> (ButtplugServer, Vec<SimulatedDeviceHandle>) {
+    let mut comm_builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    let handles = [LEFT_ADDRESS, RIGHT_ADDRESS]
+      .iter()
+      .map(|address| {
+        comm_builder.add_device(&SimulatedDeviceConfig::new("Massage Demo", Some(address)))
+      })
+      .collect();
+    let mut builder = ButtplugServerBuilder::default();
+    builder.comm_manager(comm_builder);
+    if let Some(store) = store {
+      builder.remembered_devices(store);
+    }
+    let server = builder.finish().expect("Test, assuming infallible.");
+    server
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    (server, handles)
+  }
+
+  /// Scan until the given number of devices were added, returning them sorted by address.
+  async fn scan_for_devices(
+    server: &ButtplugServer,
+    events: &mut (impl Stream<Item = ButtplugServerMessage> + Unpin),
+    count: usize,
+  ) -> Vec<DeviceAdded> {
+    server
+      .parse_message(message::StartScanning::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+    let mut devices = vec![];
+    while let Some(event) = events.next().await {
+      if let ButtplugServerMessage::DeviceAdded(device) = event {
+        devices.push(device);
+        if devices.len() == count {
+          break;
+        }
+      }
+    }
+    devices.sort_by_key(|device| {
+      device
+        .device_connection_info()
+        .as_ref()
+        .expect("Test, assuming infallible.")
+        .address()
+        .clone()
+    });
+    devices
+  }
+
+  async fn listed_display_name(server: &ButtplugServer, device_index: u32) -> Option<String> {
+    let ButtplugServerMessage::DeviceList(list) = server
+      .parse_message(message::RequestDeviceList::default().into())
+      .await
+      .expect("Test, assuming infallible.")
+    else {
+      panic!("Expected DeviceList");
+    };
+    list
+      .devices()
+      .iter()
+      .find(|device| device.device_index() == device_index)
+      .expect("Test, assuming infallible.")
+      .device_display_name()
+      .clone()
+  }
+
+  async fn wait_for_removal(events: &mut (impl Stream<Item = ButtplugServerMessage> + Unpin)) {
+    while let Some(event) = events.next().await {
+      if let ButtplugServerMessage::DeviceRemoved(_) = event {
+        return;
+      }
+    }
+    panic!("Event stream closed before device was removed.");
+  }
+
+  #[tokio::test]
+  async fn test_device_display_names() {
+    let (server, handles) = setup_server(None).await;
+    let events = server.event_stream();
+    pin_mut!(events);
+    let devices = scan_for_devices(&server, &mut events, 2).await;
+    assert_eq!(devices[0].device_name(), devices[1].device_name());
+    let left = devices[0].device_index();
+    let right = devices[1].device_index();
+
+    let device_manager = server.device_manager();
+    device_manager
+      .set_device_display_name(left, Some("Left"))
+      .expect("Test, assuming infallible.");
+    device_manager
+      .set_device_display_name(right, Some("Right"))
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      listed_display_name(&server, left).await,
+      Some("Left".to_owned())
+    );
+    assert_eq!(
+      listed_display_name(&server, right).await,
+      Some("Right".to_owned())
+    );
+    assert!(matches!(
+      device_manager.set_device_display_name(100, Some("Nope")),
+      Err(ButtplugError::ButtplugDeviceError(
+        ButtplugDeviceError::DeviceNotAvailable(100)
+      ))
+    ));
+
+    // Names stick around for the rest of the session when devices reconnect.
+    handles[0].disconnect();
+    wait_for_removal(&mut events).await;
+    let device = scan_for_devices(&server, &mut events, 1).await.remove(0);
+    assert_eq!(*device.device_display_name(), Some("Left".to_owned()));
+
+    device_manager
+      .set_device_display_name(right, None)
+      .expect("Test, assuming infallible.");
+    assert_eq!(listed_display_name(&server, right).await, None);
+  }
+
+  #[tokio::test]
+  async fn test_device_display_name_remembered() {
+    let path = std::env::temp_dir().join(format!(
+      "buttplug-display-names-{}.json",
+      std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    {
+      let store = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+      let (server, _handles) = setup_server(Some(store)).await;
+      let events = server.event_stream();
+      pin_mut!(events);
+      let devices = scan_for_devices(&server, &mut events, 2).await;
+      server
+        .device_manager()
+        .set_device_display_name(devices[1].device_index(), Some("Right"))
+        .expect("Test, assuming infallible.");
+    }
+
+    // A new server gets the name from the remembered device overrides.
+    let store = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    assert_eq!(
+      *store
+        .device(RIGHT_ADDRESS)
+        .and_then(|device| device.config().clone())
+        .expect("Test, assuming infallible.")
+        .display_name(),
+      Some("Right".to_owned())
+    );
+    let (server, _handles) = setup_server(Some(store)).await;
+    let events = server.event_stream();
+    pin_mut!(events);
+    let devices = scan_for_devices(&server, &mut events, 2).await;
+    assert_eq!(*devices[0].device_display_name(), None);
+    assert_eq!(*devices[1].device_display_name(), Some("Right".to_owned()));
+    let _ = std::fs::remove_file(&path);
+  }
+}
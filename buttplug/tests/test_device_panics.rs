@@ -0,0 +1,259 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+use util::test_device_manager::{
+  check_test_recv_value,
+  TestDeviceChannelHost,
+  TestDeviceCommunicationManagerBuilder,
+  TestDeviceIdentifier,
+};
+
+use buttplug::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugServerMessage,
+      Endpoint,
+      SensorType,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  server::{
+    device::{
+      hardware::{Hardware, HardwareCommand, HardwareWriteCmd},
+      protocol::{
+        GenericProtocolIdentifier,
+        ProtocolCapabilities,
+        ProtocolHandler,
+        ProtocolIdentifier,
+        ProtocolIdentifierFactory,
+      },
+    },
+    ButtplugServer,
+    ButtplugServerBuilder,
+  },
+};
+use futures::{future::BoxFuture, pin_mut, FutureExt, Stream, StreamExt};
+use std::{sync::Arc, time::Duration};
+
+const PANIC_TEST_DEVICE_CONFIG: &str = r#"
+  {
+    "version": { "major": 2, "minor": 24 },
+    "protocols": {
+      "panic-test": {
+        "btle": {
+          "names": ["Panic Test Device"],
+          "services": {
+            "0000eee0-0000-1000-8000-00805f9b34fb": {
+              "tx": "0000eee1-0000-1000-8000-00805f9b34fb"
+            }
+          }
+        },
+        "defaults": {
+          "name": "Panic Test Device",
+          "messages": {
+            "ScalarCmd": [
+              { "StepRange": [0, 20], "ActuatorType": "Vibrate" }
+            ],
+            "SensorReadCmd": [
+              { "FeatureDescriptor": "Battery Level", "SensorType": "Battery", "SensorRange": [[0, 100]] }
+            ]
+          }
+        }
+      }
+    }
+  }
+"#;
+
+// Panics on full speed while building commands, and inside of the future for battery reads.
+#[derive(Default)]
+struct PanicTest {}
+
+impl ProtocolHandler for PanicTest {
+  fn capabilities() -> ProtocolCapabilities {
+    ProtocolCapabilities::default()
+      .with_scalar(&[ActuatorType::Vibrate])
+      .with_sensor_read(&[SensorType::Battery])
+  }
+
+  fn handle_scalar_cmd(
+    &self,
+    commands: &[Option<(ActuatorType, u32)>],
+  ) -> Result<Vec<HardwareCommand>, ButtplugDeviceError> {
+    let (_, speed) = commands[0].expect("Test, assuming infallible.");
+    if speed == 20 {
+      panic!("Test protocol panic");
+    }
+    Ok(vec![HardwareWriteCmd::new(
+      Endpoint::Tx,
+      vec![speed as u8],
+      false,
+    )
+    .into()])
+  }
+
+  fn handle_battery_level_cmd(
+    &self,
+    _: Arc<Hardware>,
+    _: message::SensorReadCmd,
+  ) -> BoxFuture<'_, Result<ButtplugServerMessage, ButtplugDeviceError>> {
+    async move { panic!("Test protocol panic in future") }.boxed()
+  }
+}
+
+#[derive(Default)]
+struct PanicTestIdentifierFactory {}
+
+impl ProtocolIdentifierFactory for PanicTestIdentifierFactory {
+  fn identifier(&self) -> &str {
+    "panic-test"
+  }
+
+  fn create(&self) -> Box<dyn ProtocolIdentifier> {
+    Box::new(GenericProtocolIdentifier::new(
+      Arc::new(PanicTest::default()),
+      self.identifier(),
+    ))
+  }
+
+  fn capabilities(&self) -> ProtocolCapabilities {
+    PanicTest::capabilities()
+  }
+}
+
+async fn setup_panic_test_server() -> (
+  ButtplugServer,
+  impl Stream<Item = ButtplugServerMessage>,
+  Vec<(u32, TestDeviceChannelHost)>,
+) {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut channels = vec![];
+  for address in ["panic-test-1", "panic-test-2"] {
+    let channel = builder.add_test_device(&TestDeviceIdentifier::new(
+      "Panic Test Device",
+      Some(address.to_owned()),
+    ));
+    channels.push((address.to_owned(), channel));
+  }
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .device_configuration_json(Some(PANIC_TEST_DEVICE_CONFIG.to_owned()))
+    .protocol_factory(PanicTestIdentifierFactory::default())
+    .comm_manager(builder);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut recv = Box::pin(recv);
+  let mut devices = vec![];
+  while !channels.is_empty() {
+    if let Some(ButtplugServerMessage::DeviceAdded(added)) = recv.next().await {
+      let connection_info = added
+        .device_connection_info()
+        .clone()
+        .expect("Test, assuming infallible.");
+      let position = channels
+        .iter()
+        .position(|(address, _)| address == connection_info.address())
+        .expect("Test, assuming infallible.");
+      devices.push((added.device_index(), channels.remove(position).1));
+    }
+  }
+  (server, recv, devices)
+}
+
+async fn wait_for_removal(recv: impl Stream<Item = ButtplugServerMessage>, device_index: u32) {
+  pin_mut!(recv);
+  tokio::time::timeout(Duration::from_secs(5), async {
+    while let Some(msg) = recv.next().await {
+      if let ButtplugServerMessage::DeviceRemoved(removed) = msg {
+        assert_eq!(removed.device_index(), device_index);
+        return;
+      }
+    }
+    panic!("Event stream closed before device was removed");
+  })
+  .await
+  .expect("Device should be removed after a protocol panic");
+}
+
+fn vibrate(device_index: u32, speed: f64) -> message::ButtplugClientMessage {
+  message::ScalarCmd::new(
+    device_index,
+    vec![message::ScalarSubcommand::new(
+      0,
+      speed,
+      ActuatorType::Vibrate,
+    )],
+  )
+  .into()
+}
+
+fn assert_protocol_panicked(result: Result<ButtplugServerMessage, message::Error>) {
+  assert!(matches!(
+    result
+      .expect_err("Protocol panic should be an error")
+      .original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::ProtocolPanicked { .. })
+  ));
+}
+
+#[tokio::test]
+async fn test_protocol_panic_removes_only_that_device() {
+  let (server, mut recv, mut devices) = setup_panic_test_server().await;
+  let (healthy_index, mut healthy_channel) = devices.pop().expect("Test, assuming infallible.");
+  let (panicking_index, _) = devices.pop().expect("Test, assuming infallible.");
+
+  assert_protocol_panicked(server.parse_message(vibrate(panicking_index, 1.0)).await);
+  wait_for_removal(&mut recv, panicking_index).await;
+  assert!(matches!(
+    server
+      .parse_message(vibrate(panicking_index, 0.5))
+      .await
+      .expect_err("Removed device should not be available")
+      .original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(_))
+  ));
+
+  // The server, and the other device using the same protocol, keep working.
+  assert!(server.connected());
+  server
+    .parse_message(vibrate(healthy_index, 0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut healthy_channel,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![10], false)),
+  );
+}
+
+#[tokio::test]
+async fn test_protocol_panic_in_future_removes_device() {
+  let (server, mut recv, devices) = setup_panic_test_server().await;
+  let (device_index, _) = devices[0];
+
+  assert_protocol_panicked(
+    server
+      .parse_message(message::SensorReadCmd::new(device_index, 0, SensorType::Battery).into())
+      .await,
+  );
+  wait_for_removal(&mut recv, device_index).await;
+  assert!(server.connected());
+  assert!(server.device_manager().device_info(devices[1].0).is_some());
+}
@@ -23,9 +23,13 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_ankni_protocol_no_handshake.yaml" ; "Ankni Protocol - No Handshake")]
 #[test_case("test_cachito_protocol.yaml" ; "Cachito Protocol")]
 #[test_case("test_fredorch_protocol.yaml" ; "Fredorch Protocol")]
+#[test_case("test_thehandy_protocol.yaml" ; "The Handy Protocol")]
+#[test_case("test_tcode_sr6_user_config.yaml" ; "TCode Protocol - SR6 User Config")]
+#[test_case("test_serial_template_user_config.yaml" ; "Serial Template Protocol - User Config")]
 #[test_case("test_hismith_auxfun_box.yaml" ; "Hismith Mini Protocol - Auxfun Box")]
 #[test_case("test_hismith_sinloli.yaml" ; "Hismith Mini Protocol - Sinloli")]
 #[test_case("test_hismith_thrusting_cup.yaml" ; "Hismith Protocol - Thrusting Cup")]
+#[test_case("test_hismith_sex_machine.yaml" ; "Hismith Protocol - Sex Machine")]
 #[test_case("test_hismith_wildolo.yaml" ; "Hismith Protocol - Wildolo")]
 #[test_case("test_lovense_single_vibrator.yaml" ; "Lovense Protocol - Single Vibrator Device")]
 #[test_case("test_lovense_max.yaml" ; "Lovense Protocol - Lovense Max (Vibrate/Constrict)")]
@@ -38,6 +42,14 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_lovense_flexer_fw3.yaml" ; "Lovense Protocol - Flexer FW3")]
 #[test_case("test_lovense_edge.yaml" ; "Lovense Protocol - Edge")]
 #[test_case("test_user_config_display_name.yaml" ; "User Config Display Name")]
+#[test_case("test_aneros_rate_limit_user_config.yaml" ; "User Config Command Rate Limit")]
+#[test_case("test_aneros_scalar_adjustment_user_config.yaml" ; "User Config Scalar Adjustment")]
+#[test_case("test_aneros_scalar_invert_user_config.yaml" ; "User Config Scalar Inversion")]
+#[test_case("test_aneros_scalar_ramp_user_config.yaml" ; "User Config Scalar Ramp")]
+#[test_case("test_lovense_idle_user_config.yaml" ; "User Config Idle Timeout")]
+#[test_case("test_aneros_step_count_user_config.yaml" ; "User Config Step Count")]
+#[test_case("test_magic_motion_4_suction_user_config.yaml" ; "MagicMotion Protocol 4 - Suction (User Config)")]
+#[test_case("test_magic_motion_4_heater_user_config.yaml" ; "MagicMotion Protocol 4 - Heater (User Config)")]
 #[test_case("test_satisfyer_single_vibrator.yaml" ; "Satisfyer Protocol - Single Vibrator")]
 #[test_case("test_satisfyer_dual_vibrator.yaml" ; "Satisfyer Protocol - Dual Vibrator")]
 #[test_case("test_mysteryvibe.yaml" ; "Mysteryvibe Protocol")]
@@ -48,6 +60,7 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_vorze_ufo.yaml" ; "Vorze Protocol - UFO")]
 #[test_case("test_vorze_ufo_tw.yaml" ; "Vorze Protocol - UFO TW")]
 #[test_case("test_vorze_cyclone.yaml" ; "Vorze Protocol - Cyclone")]
+#[test_case("test_vorze_piston.yaml" ; "Vorze Protocol - Piston")]
 #[test_case("test_wevibe_4plus.yaml" ; "WeVibe Protocol (Legacy) - 4 Plus")]
 #[test_case("test_wevibe_pivot.yaml" ; "WeVibe Protocol (Legacy) - Pivot")]
 #[test_case("test_wevibe_vector.yaml" ; "WeVibe Protocol (8bit) - Vector")]
@@ -61,6 +74,7 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_sakuraneko_protocol.yaml" ; "Sakuraneko Protocol")]
 #[test_case("test_synchro_protocol.yaml" ; "Synchro Protocol")]
 #[test_case("test_lelo_tianiharmony.yaml" ; "Lelo Harmony Protocol - Tiani Harmony")]
+#[test_case("test_bhaptics_tactsuit.yaml" ; "bHaptics Protocol - TactSuit X40")]
 #[test_case("test_lelo_f1sv1.yaml" ; "Lelo F1s V1 Protocol")]
 #[test_case("test_lelo_f1sv2.yaml" ; "Lelo F1s V2 Protocol")]
 #[test_case("test_lelo_idawave.yaml" ; "Lelo Harmony Protocol - Ida Wave")]
@@ -81,6 +95,8 @@ async fn load_test_case(test_file: &str) -> DeviceTestCase {
 #[test_case("test_xibao_protocol.yaml" ; "Xibao Protocol")]
 #[test_case("test_sensee_protocol.yaml" ; "Sensee Diandou Protocol - Rabbit")]
 #[test_case("test_svakom_pulse.yaml" ; "Svakom Pulse Protocol - Pulse Lite Neo")]
+#[test_case("test_svakom_sam_neo.yaml" ; "Svakom Sam Protocol - Sam Neo")]
+#[test_case("test_svakom_sam_neo2.yaml" ; "Svakom Sam Protocol - Sam Neo 2")]
 #[test_case("test_svakom_ella.yaml" ; "Svakom V1 Protocol - Ella")]
 #[test_case("test_svakom_vivianna.yaml" ; "Svakom V2 Protocol - Vivianna")]
 #[test_case("test_svakom_theodore.yaml" ; "Svakom V3 Protocol - Theodore")]
@@ -109,9 +125,13 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_ankni_protocol_no_handshake.yaml" ; "Ankni Protocol - No Handshake")]
 #[test_case("test_cachito_protocol.yaml" ; "Cachito Protocol")]
 #[test_case("test_fredorch_protocol.yaml" ; "Fredorch Protocol")]
+#[test_case("test_thehandy_protocol.yaml" ; "The Handy Protocol")]
+#[test_case("test_tcode_sr6_user_config.yaml" ; "TCode Protocol - SR6 User Config")]
+#[test_case("test_serial_template_user_config.yaml" ; "Serial Template Protocol - User Config")]
 #[test_case("test_hismith_auxfun_box.yaml" ; "Hismith Mini Protocol - Auxfun Box")]
 #[test_case("test_hismith_sinloli.yaml" ; "Hismith Mini Protocol - Sinloli")]
 #[test_case("test_hismith_thrusting_cup.yaml" ; "Hismith Protocol - Thrusting Cup")]
+#[test_case("test_hismith_sex_machine.yaml" ; "Hismith Protocol - Sex Machine")]
 #[test_case("test_hismith_wildolo.yaml" ; "Hismith Protocol - Wildolo")]
 #[test_case("test_lovense_single_vibrator.yaml" ; "Lovense Protocol - Single Vibrator Device")]
 #[test_case("test_lovense_max.yaml" ; "Lovense Protocol - Lovense Max (Vibrate/Constrict)")]
@@ -124,6 +144,14 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_lovense_flexer_fw3.yaml" ; "Lovense Protocol - Flexer FW3")]
 #[test_case("test_lovense_edge.yaml" ; "Lovense Protocol - Edge")]
 #[test_case("test_user_config_display_name.yaml" ; "User Config Display Name")]
+#[test_case("test_aneros_rate_limit_user_config.yaml" ; "User Config Command Rate Limit")]
+#[test_case("test_aneros_scalar_adjustment_user_config.yaml" ; "User Config Scalar Adjustment")]
+#[test_case("test_aneros_scalar_invert_user_config.yaml" ; "User Config Scalar Inversion")]
+#[test_case("test_aneros_scalar_ramp_user_config.yaml" ; "User Config Scalar Ramp")]
+#[test_case("test_lovense_idle_user_config.yaml" ; "User Config Idle Timeout")]
+#[test_case("test_aneros_step_count_user_config.yaml" ; "User Config Step Count")]
+#[test_case("test_magic_motion_4_suction_user_config.yaml" ; "MagicMotion Protocol 4 - Suction (User Config)")]
+#[test_case("test_magic_motion_4_heater_user_config.yaml" ; "MagicMotion Protocol 4 - Heater (User Config)")]
 #[test_case("test_satisfyer_single_vibrator.yaml" ; "Satisfyer Protocol - Single Vibrator")]
 #[test_case("test_satisfyer_dual_vibrator.yaml" ; "Satisfyer Protocol - Dual Vibrator")]
 #[test_case("test_satisfyer_triple_vibrator.yaml" ; "Satisfyer Protocol - Triple Vibrator")]
@@ -135,6 +163,7 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_vorze_ufo.yaml" ; "Vorze Protocol - UFO")]
 #[test_case("test_vorze_ufo_tw.yaml" ; "Vorze Protocol - UFO TW")]
 #[test_case("test_vorze_cyclone.yaml" ; "Vorze Protocol - Cyclone")]
+#[test_case("test_vorze_piston.yaml" ; "Vorze Protocol - Piston")]
 #[test_case("test_wevibe_4plus.yaml" ; "WeVibe Protocol (Legacy) - 4 Plus")]
 #[test_case("test_wevibe_pivot.yaml" ; "WeVibe Protocol (Legacy) - Pivot")]
 #[test_case("test_wevibe_vector.yaml" ; "WeVibe Protocol (8bit) - Vector")]
@@ -148,6 +177,7 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_sakuraneko_protocol.yaml" ; "Sakuraneko Protocol")]
 #[test_case("test_synchro_protocol.yaml" ; "Synchro Protocol")]
 #[test_case("test_lelo_tianiharmony.yaml" ; "Lelo Harmony Protocol - Tiani Harmony")]
+#[test_case("test_bhaptics_tactsuit.yaml" ; "bHaptics Protocol - TactSuit X40")]
 #[test_case("test_lelo_f1sv1.yaml" ; "Lelo F1s V1 Protocol")]
 #[test_case("test_lelo_f1sv2.yaml" ; "Lelo F1s V2 Protocol")]
 #[test_case("test_lelo_idawave.yaml" ; "Lelo Harmony Protocol - Ida Wave")]
@@ -168,6 +198,8 @@ async fn test_device_protocols_embedded_v3(test_file: &str) {
 #[test_case("test_xibao_protocol.yaml" ; "Xibao Protocol")]
 #[test_case("test_sensee_protocol.yaml" ; "Sensee Diandou Protocol - Rabbit")]
 #[test_case("test_svakom_pulse.yaml" ; "Svakom Pulse Protocol - Pulse Lite Neo")]
+#[test_case("test_svakom_sam_neo.yaml" ; "Svakom Sam Protocol - Sam Neo")]
+#[test_case("test_svakom_sam_neo2.yaml" ; "Svakom Sam Protocol - Sam Neo 2")]
 #[test_case("test_svakom_ella.yaml" ; "Svakom V1 Protocol - Ella")]
 #[test_case("test_svakom_vivianna.yaml" ; "Svakom V2 Protocol - Vivianna")]
 #[test_case("test_svakom_theodore.yaml" ; "Svakom V3 Protocol - Theodore")]
@@ -194,6 +226,9 @@ async fn test_device_protocols_json_v3(test_file: &str) {
 #[test_case("test_ankni_protocol_no_handshake.yaml" ; "Ankni Protocol - No Handshake")]
 #[test_case("test_cachito_protocol.yaml" ; "Cachito Protocol")]
 #[test_case("test_fredorch_protocol.yaml" ; "Fredorch Protocol")]
+#[test_case("test_thehandy_protocol.yaml" ; "The Handy Protocol")]
+#[test_case("test_tcode_sr6_user_config.yaml" ; "TCode Protocol - SR6 User Config")]
+#[test_case("test_serial_template_user_config.yaml" ; "Serial Template Protocol - User Config")]
 #[test_case("test_hismith_wildolo.yaml" ; "Hismith Protocol - Wildolo")]
 #[test_case("test_lovense_single_vibrator.yaml" ; "Lovense Protocol - Single Vibrator Device")]
 #[test_case("test_lovense_nora.yaml" ; "Lovense Protocol - Lovense Nora (Vibrate/Rotate)")]
@@ -212,6 +247,7 @@ async fn test_device_protocols_json_v3(test_file: &str) {
 #[test_case("test_vorze_ufo.yaml" ; "Vorze Protocol - UFO")]
 #[test_case("test_vorze_ufo_tw.yaml" ; "Vorze Protocol - UFO TW")]
 #[test_case("test_vorze_cyclone.yaml" ; "Vorze Protocol - Cyclone")]
+#[test_case("test_vorze_piston.yaml" ; "Vorze Protocol - Piston")]
 #[test_case("test_wevibe_4plus.yaml" ; "WeVibe Protocol (Legacy) - 4 Plus")]
 #[test_case("test_wevibe_pivot.yaml" ; "WeVibe Protocol (Legacy) - Pivot")]
 #[test_case("test_wevibe_vector.yaml" ; "WeVibe Protocol (8bit) - Vector")]
@@ -225,6 +261,7 @@ async fn test_device_protocols_json_v3(test_file: &str) {
 #[test_case("test_sakuraneko_protocol.yaml" ; "Sakuraneko Protocol")]
 #[test_case("test_synchro_protocol.yaml" ; "Synchro Protocol")]
 #[test_case("test_lelo_tianiharmony.yaml" ; "Lelo Harmony Protocol - Tiani Harmony")]
+#[test_case("test_bhaptics_tactsuit.yaml" ; "bHaptics Protocol - TactSuit X40")]
 #[test_case("test_lelo_f1sv1.yaml" ; "Lelo F1s V1 Protocol")]
 #[test_case("test_lelo_f1sv2.yaml" ; "Lelo F1s V2 Protocol")]
 #[test_case("test_magic_motion_1_magic_cell.yaml" ; "MagicMotion Protocol 1 - Magic Cell")]
@@ -259,6 +296,9 @@ async fn test_device_protocols_embedded_v2(test_file: &str) {
 #[test_case("test_ankni_protocol_no_handshake.yaml" ; "Ankni Protocol - No Handshake")]
 #[test_case("test_cachito_protocol.yaml" ; "Cachito Protocol")]
 #[test_case("test_fredorch_protocol.yaml" ; "Fredorch Protocol")]
+#[test_case("test_thehandy_protocol.yaml" ; "The Handy Protocol")]
+#[test_case("test_tcode_sr6_user_config.yaml" ; "TCode Protocol - SR6 User Config")]
+#[test_case("test_serial_template_user_config.yaml" ; "Serial Template Protocol - User Config")]
 #[test_case("test_hismith_wildolo.yaml" ; "Hismith Protocol - Wildolo")]
 #[test_case("test_lovense_single_vibrator.yaml" ; "Lovense Protocol - Single Vibrator Device")]
 #[test_case("test_lovense_nora.yaml" ; "Lovense Protocol - Lovense Nora (Vibrate/Rotate)")]
@@ -276,6 +316,7 @@ async fn test_device_protocols_embedded_v2(test_file: &str) {
 #[test_case("test_vorze_ufo.yaml" ; "Vorze Protocol - UFO")]
 #[test_case("test_vorze_ufo_tw.yaml" ; "Vorze Protocol - UFO TW")]
 #[test_case("test_vorze_cyclone.yaml" ; "Vorze Protocol - Cyclone")]
+#[test_case("test_vorze_piston.yaml" ; "Vorze Protocol - Piston")]
 #[test_case("test_wevibe_4plus.yaml" ; "WeVibe Protocol (Legacy) - 4 Plus")]
 #[test_case("test_wevibe_pivot.yaml" ; "WeVibe Protocol (Legacy) - Pivot")]
 #[test_case("test_wevibe_vector.yaml" ; "WeVibe Protocol (8bit) - Vector")]
@@ -289,6 +330,7 @@ async fn test_device_protocols_embedded_v2(test_file: &str) {
 #[test_case("test_sakuraneko_protocol.yaml" ; "Sakuraneko Protocol")]
 #[test_case("test_synchro_protocol.yaml" ; "Synchro Protocol")]
 #[test_case("test_lelo_tianiharmony.yaml" ; "Lelo Harmony Protocol - Tiani Harmony")]
+#[test_case("test_bhaptics_tactsuit.yaml" ; "bHaptics Protocol - TactSuit X40")]
 #[test_case("test_lelo_f1sv1.yaml" ; "Lelo F1s V1 Protocol")]
 #[test_case("test_lelo_f1sv2.yaml" ; "Lelo F1s V2 Protocol")]
 #[test_case("test_magic_motion_1_magic_cell.yaml" ; "MagicMotion Protocol 1 - Magic Cell")]
@@ -0,0 +1,151 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+use buttplug::server::{
+  engine::{ButtplugEngine, ButtplugEngineError, EngineCommand, EngineEvent, EngineStatus},
+  ButtplugServerBuilder,
+};
+use futures::{pin_mut, Stream, StreamExt};
+use std::{
+  io::Write,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tracing_subscriber::fmt::MakeWriter;
+use util::{
+  test_device_manager::TestDeviceIdentifier,
+  TestDeviceChannelHost,
+  TestDeviceCommunicationManagerBuilder,
+};
+
+fn test_engine() -> ButtplugEngine {
+  // Device channels have to outlive the servers, or the devices lose their connection.
+  let device_channels: Arc<Mutex<Vec<TestDeviceChannelHost>>> = Arc::default();
+  ButtplugEngine::new(move || {
+    let mut builder = TestDeviceCommunicationManagerBuilder::default();
+    device_channels
+      .lock()
+      .expect("Test, assuming infallible.")
+      .push(builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None)));
+    ButtplugServerBuilder::default()
+      .comm_manager(builder)
+      .finish()
+  })
+}
+
+async fn next_event(events: &mut (impl Stream<Item = EngineEvent> + Unpin)) -> EngineEvent {
+  tokio::time::timeout(Duration::from_secs(5), events.next())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.")
+}
+
+#[tokio::test]
+async fn test_engine_lifecycle() {
+  let engine = test_engine();
+  let commands = engine.command_sender();
+  let events = engine.event_stream();
+  pin_mut!(events);
+  assert_eq!(engine.status(), EngineStatus::Stopped);
+  assert!(engine.server().is_none());
+
+  commands
+    .send(EngineCommand::StartScanning)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    next_event(&mut events).await,
+    EngineEvent::Error(ButtplugEngineError::NotRunning)
+  );
+
+  commands
+    .send(EngineCommand::Start)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    next_event(&mut events).await,
+    EngineEvent::StatusChanged(EngineStatus::Running)
+  );
+  assert_eq!(engine.status(), EngineStatus::Running);
+  assert!(engine.server().is_some());
+  commands
+    .send(EngineCommand::Start)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    next_event(&mut events).await,
+    EngineEvent::Error(ButtplugEngineError::AlreadyRunning)
+  );
+
+  commands
+    .send(EngineCommand::StartScanning)
+    .await
+    .expect("Test, assuming infallible.");
+  let device_index = loop {
+    if let EngineEvent::DeviceAdded(device) = next_event(&mut events).await {
+      assert_eq!(device.device_name(), "Aneros Vivi");
+      break device.device_index();
+    }
+  };
+  commands
+    .send(EngineCommand::RequestDeviceList)
+    .await
+    .expect("Test, assuming infallible.");
+  let devices = loop {
+    if let EngineEvent::DeviceList(devices) = next_event(&mut events).await {
+      break devices;
+    }
+  };
+  assert_eq!(devices.len(), 1);
+  assert_eq!(devices[0].device_index(), device_index);
+
+  commands
+    .send(EngineCommand::Stop)
+    .await
+    .expect("Test, assuming infallible.");
+  loop {
+    if next_event(&mut events).await == EngineEvent::StatusChanged(EngineStatus::Stopped) {
+      break;
+    }
+  }
+  assert_eq!(engine.status(), EngineStatus::Stopped);
+
+  // Restarting builds a fresh server.
+  commands
+    .send(EngineCommand::Start)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    next_event(&mut events).await,
+    EngineEvent::StatusChanged(EngineStatus::Running)
+  );
+  commands
+    .send(EngineCommand::RequestDeviceList)
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    next_event(&mut events).await,
+    EngineEvent::DeviceList(vec![])
+  );
+}
+
+#[tokio::test]
+async fn test_engine_log_forwarding() {
+  let engine = test_engine();
+  let events = engine.event_stream();
+  pin_mut!(events);
+  engine
+    .log_writer()
+    .make_writer()
+    .write_all(b"Test log line\n")
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    next_event(&mut events).await,
+    EngineEvent::Log("Test log line".to_owned())
+  );
+}
@@ -0,0 +1,119 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(all(feature = "simulated-manager", feature = "lovense-emulator"))]
+mod test {
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent},
+    core::{connector::ButtplugInProcessClientConnectorBuilder, message::Endpoint},
+    server::{
+      device::hardware::{
+        communication::simulated::{
+          SimulatedDeviceCommunicationManagerBuilder,
+          SimulatedDeviceConfig,
+        },
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      emulation::LovenseEmulatorConfig,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+  };
+
+  async fn send_command(stream: &mut TcpStream, command: &str) -> String {
+    stream
+      .write_all(command.as_bytes())
+      .await
+      .expect("Test, assuming infallible.");
+    let mut reply = String::new();
+    let mut buf = [0u8; 64];
+    while reply.matches(';').count() < command.matches(';').count() {
+      let len = stream
+        .read(&mut buf)
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(len > 0, "Emulator closed the connection.");
+      reply.push_str(&String::from_utf8_lossy(&buf[..len]));
+    }
+    reply
+  }
+
+  #[tokio::test]
+  async fn test_lovense_emulator_forwards_commands() {
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    let handle = builder.add_device(&SimulatedDeviceConfig::new("Massage Demo", None));
+    let mut commands = handle.command_stream();
+    let config = LovenseEmulatorConfig::from_json(
+      r#"{
+        "listen-address": "127.0.0.1:0",
+        "device-name": "Aneros Vivi"
+      }"#,
+    )
+    .expect("Test, assuming infallible.");
+    let server = ButtplugServerBuilder::default()
+      .comm_manager(builder)
+      .lovense_emulator(config)
+      .finish()
+      .expect("Test, assuming infallible.");
+    let emulator_address = server
+      .lovense_emulator_address()
+      .expect("Test, assuming infallible.");
+
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+    let client = ButtplugClient::new("Lovense Emulator Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(_) = event {
+        break;
+      }
+    }
+
+    let mut stream = TcpStream::connect(emulator_address)
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      send_command(&mut stream, "DeviceType;").await,
+      "Z:11:0082059AD3BD;"
+    );
+    // No battery sensor on this device.
+    assert_eq!(send_command(&mut stream, "Battery;").await, "100;");
+    // Lovense motors are numbered from 1, and only count vibrators.
+    assert_eq!(send_command(&mut stream, "Vibrate1:10;").await, "OK;");
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x40], false))
+    );
+    // Commands can be split across writes.
+    assert_eq!(send_command(&mut stream, "Vibra").await, "");
+    assert_eq!(send_command(&mut stream, "te2:20;").await, "OK;");
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x7F], false))
+    );
+    assert_eq!(send_command(&mut stream, "Thrust:10;").await, "ERR;");
+    assert_eq!(send_command(&mut stream, "PowerOff;").await, "OK;");
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x00], false))
+    );
+  }
+}
@@ -0,0 +1,192 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "lua-protocols")]
+mod util;
+
+#[cfg(feature = "lua-protocols")]
+mod test {
+  use super::util::{
+    expect_write,
+    test_device_manager::TestDeviceIdentifier,
+    TestDeviceChannelHost,
+    TestDeviceCommunicationManagerBuilder,
+  };
+  use buttplug::{
+    client::{
+      ButtplugClient,
+      ButtplugClientDevice,
+      ButtplugClientEvent,
+      LinearCommand,
+      ScalarValueCommand,
+    },
+    core::{
+      connector::ButtplugInProcessClientConnectorBuilder,
+      errors::ButtplugDeviceError,
+      message::ActuatorType,
+    },
+    server::{
+      device::protocol::{lua_script::LuaProtocolFactory, ProtocolIdentifierFactory},
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use std::{sync::Arc, time::Duration};
+
+  const TEST_SCRIPT: &str = r#"
+    function init(device)
+      mode = device.name == "Lua Test Device" and 2 or 1
+      return { data = { 0xaa, mode } }
+    end
+
+    function handle_scalar(index, value, actuator)
+      if actuator == "Oscillate" then
+        while true do end
+      end
+      return { { endpoint = "tx", data = { 0x0f, index, value } } }
+    end
+
+    function handle_linear(index, position, duration)
+      return { data = string.format("L%d:%dI%d\n", index, math.floor(position * 99), duration) }
+    end
+  "#;
+
+  const TEST_DEVICE_CONFIG: &str = r#"
+    {
+      "version": { "major": 2, "minor": 24 },
+      "protocols": {
+        "lua-test": {
+          "btle": {
+            "names": ["Lua Test Device"],
+            "services": {
+              "0000eee0-0000-1000-8000-00805f9b34fb": {
+                "tx": "0000eee1-0000-1000-8000-00805f9b34fb"
+              }
+            }
+          },
+          "defaults": {
+            "name": "Lua Test Device",
+            "messages": {
+              "ScalarCmd": [
+                { "StepRange": [0, 20], "ActuatorType": "Vibrate" },
+                { "StepRange": [0, 20], "ActuatorType": "Oscillate" }
+              ],
+              "LinearCmd": [
+                { "StepRange": [0, 99], "ActuatorType": "Position" }
+              ]
+            }
+          }
+        }
+      }
+    }
+  "#;
+
+  async fn lua_test_client() -> (
+    ButtplugClient,
+    Arc<ButtplugClientDevice>,
+    TestDeviceChannelHost,
+  ) {
+    let mut builder = TestDeviceCommunicationManagerBuilder::default();
+    let device_channel =
+      builder.add_test_device(&TestDeviceIdentifier::new("Lua Test Device", None));
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder
+      .device_configuration_json(Some(TEST_DEVICE_CONFIG.to_owned()))
+      .protocol_factory(
+        LuaProtocolFactory::new("lua-test", TEST_SCRIPT).expect("Test, assuming infallible."),
+      )
+      .comm_manager(builder);
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server_builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    let client = ButtplugClient::new("Lua Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        return (client, device, device_channel);
+      }
+    }
+    panic!("Event stream closed before device was found.");
+  }
+
+  #[tokio::test]
+  async fn test_lua_protocol_commands() {
+    let (_client, device, mut device_channel) = lua_test_client().await;
+    expect_write(&mut device_channel, &[0xaa, 2]).await;
+
+    device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_write(&mut device_channel, &[0x0f, 0, 10]).await;
+
+    device
+      .linear(&LinearCommand::Linear(500, 0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_write(&mut device_channel, b"L0:49I500\n").await;
+  }
+
+  #[tokio::test]
+  async fn test_lua_protocol_runaway_script() {
+    let (_client, device, _device_channel) = lua_test_client().await;
+    let result = tokio::time::timeout(
+      Duration::from_secs(5),
+      device.oscillate(&ScalarValueCommand::ScalarValue(0.5)),
+    )
+    .await
+    .expect("Script should be stopped before the timeout");
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn test_lua_protocol_factory_errors() {
+    assert!(matches!(
+      LuaProtocolFactory::new("lua-test", "function init(device"),
+      Err(ButtplugDeviceError::ProtocolSpecificError(..))
+    ));
+    // Nothing outside of the sandbox is available.
+    for script in [
+      "os.exit()",
+      "io.open('test')",
+      "load('return 1')()",
+      "require('os')",
+    ] {
+      assert!(matches!(
+        LuaProtocolFactory::new("lua-test", script),
+        Err(ButtplugDeviceError::ProtocolSpecificError(..))
+      ));
+    }
+  }
+
+  #[test]
+  fn test_lua_protocol_capabilities() {
+    let factory =
+      LuaProtocolFactory::new("lua-test", TEST_SCRIPT).expect("Test, assuming infallible.");
+    let capabilities = factory.capabilities();
+    assert!(capabilities.scalar().contains(&ActuatorType::Vibrate));
+    assert!(capabilities.linear());
+    assert!(!capabilities.rotate());
+
+    let factory = LuaProtocolFactory::new(
+      "lua-test",
+      "function handle_linear(index, position, duration) end",
+    )
+    .expect("Test, assuming infallible.");
+    let capabilities = factory.capabilities();
+    assert!(capabilities.scalar().is_empty());
+    assert!(capabilities.linear());
+  }
+}
@@ -0,0 +1,217 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "mqtt")]
+mod mqtt_connector_tests {
+  use crate::util::ButtplugTestServer;
+  use buttplug::{
+    client::ButtplugClient,
+    core::{
+      connector::{
+        ButtplugMqttTransport,
+        ButtplugMqttTransportBuilder,
+        ButtplugRemoteClientConnector,
+        ButtplugRemoteServerConnector,
+      },
+      message::serializer::{ButtplugClientJSONSerializer, ButtplugServerJSONSerializer},
+    },
+    util::async_manager,
+  };
+  use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+  };
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    sync::{mpsc, Notify},
+  };
+
+  type Subscribers = Arc<Mutex<HashMap<String, Vec<mpsc::UnboundedSender<Vec<u8>>>>>>;
+
+  async fn read_packet(stream: &mut OwnedReadHalf) -> Option<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await.ok()?;
+    let mut len = 0usize;
+    let mut shift = 0;
+    loop {
+      let byte = stream.read_u8().await.ok()?;
+      len |= ((byte & 0x7F) as usize) << shift;
+      shift += 7;
+      if byte & 0x80 == 0 {
+        break;
+      }
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).await.ok()?;
+    Some((header, body))
+  }
+
+  fn topic(body: &[u8]) -> String {
+    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    String::from_utf8(body[2..2 + len].to_vec()).expect("Test, assuming infallible.")
+  }
+
+  fn encode(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+      let byte = (len % 128) as u8;
+      len /= 128;
+      packet.push(if len > 0 { byte | 0x80 } else { byte });
+      if len == 0 {
+        break;
+      }
+    }
+    packet.extend(body);
+    packet
+  }
+
+  /// Bare bones QoS 0 broker, only supporting exact topic subscriptions.
+  async fn run_broker_connection(
+    stream: TcpStream,
+    subscribers: Subscribers,
+    subscribed: Arc<Notify>,
+  ) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Vec<u8>>();
+    async_manager::spawn(async move {
+      while let Some(packet) = receiver.recv().await {
+        if writer.write_all(&packet).await.is_err() {
+          return;
+        }
+      }
+    });
+    while let Some((header, body)) = read_packet(&mut reader).await {
+      match header >> 4 {
+        // CONNECT
+        1 => sender
+          .send(vec![0x20, 0x02, 0x00, 0x00])
+          .expect("Test, assuming infallible."),
+        // PUBLISH
+        3 => {
+          let topic = topic(&body);
+          for subscriber in subscribers
+            .lock()
+            .unwrap()
+            .get(&topic)
+            .into_iter()
+            .flatten()
+          {
+            let _ = subscriber.send(encode(header, &body));
+          }
+        }
+        // SUBSCRIBE
+        8 => {
+          subscribers
+            .lock()
+            .unwrap()
+            .entry(topic(&body[2..]))
+            .or_default()
+            .push(sender.clone());
+          sender
+            .send(vec![0x90, 0x03, body[0], body[1], 0x00])
+            .expect("Test, assuming infallible.");
+          subscribed.notify_one();
+        }
+        // PINGREQ
+        12 => sender
+          .send(vec![0xD0, 0x00])
+          .expect("Test, assuming infallible."),
+        // DISCONNECT
+        14 => return,
+        _ => {}
+      }
+    }
+  }
+
+  async fn start_broker() -> (SocketAddr, Arc<Notify>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let address = listener.local_addr().expect("Test, assuming infallible.");
+    let subscribed = Arc::new(Notify::new());
+    let subscribed_clone = subscribed.clone();
+    async_manager::spawn(async move {
+      let subscribers = Subscribers::default();
+      while let Ok((stream, _)) = listener.accept().await {
+        async_manager::spawn(run_broker_connection(
+          stream,
+          subscribers.clone(),
+          subscribed_clone.clone(),
+        ));
+      }
+    });
+    (address, subscribed)
+  }
+
+  #[tokio::test]
+  async fn test_client_mqtt_server_mqtt() {
+    let (broker_address, subscribed) = start_broker().await;
+    let broker_address = broker_address.to_string();
+    let server = Arc::new(ButtplugTestServer::default());
+    let server_clone = server.clone();
+    let server_broker_address = broker_address.clone();
+    async_manager::spawn(async move {
+      let connector =
+        ButtplugRemoteServerConnector::<ButtplugMqttTransport, ButtplugServerJSONSerializer>::new(
+          ButtplugMqttTransportBuilder::new(&server_broker_address)
+            .topic_prefix("test/buttplug")
+            .finish_server(),
+        );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+    // Messages aren't retained, so make sure the server is listening before the client connects.
+    subscribed.notified().await;
+
+    let connector =
+      ButtplugRemoteClientConnector::<ButtplugMqttTransport, ButtplugClientJSONSerializer>::new(
+        ButtplugMqttTransportBuilder::new(&broker_address)
+          .topic_prefix("test/buttplug")
+          .finish_client(),
+      );
+    let client = ButtplugClient::new("Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(client.connected());
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    client
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
+  #[tokio::test]
+  async fn test_mqtt_topic_too_long() {
+    let (broker_address, _) = start_broker().await;
+    // MQTT topics have a u16 length, so this can't be sent.
+    let connector =
+      ButtplugRemoteClientConnector::<ButtplugMqttTransport, ButtplugClientJSONSerializer>::new(
+        ButtplugMqttTransportBuilder::new(&broker_address.to_string())
+          .topic_prefix(&"a".repeat(u16::MAX as usize))
+          .keep_alive_secs(0)
+          .finish_client(),
+      );
+    let client = ButtplugClient::new("Test Client");
+    assert!(client.connect(connector).await.is_err());
+    assert!(!client.connected());
+  }
+}
@@ -0,0 +1,254 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "serialize-msgpack")]
+mod test {
+  use buttplug::core::message::{
+    self,
+    serializer::{
+      vec_to_protocol_json,
+      vec_to_protocol_msgpack,
+      ButtplugClientJSONSerializer,
+      ButtplugClientMessagePackSerializer,
+      ButtplugMessageSerializer,
+      ButtplugSerializationFormat,
+      ButtplugSerializedMessage,
+      ButtplugServerJSONSerializer,
+      ButtplugServerMessagePackSerializer,
+      ButtplugServerNegotiatedSerializer,
+      JsonValidationMode,
+    },
+    ButtplugCurrentSpecClientMessage,
+    ButtplugCurrentSpecServerMessage,
+    ButtplugMessageSpecVersion,
+    ButtplugServerMessage,
+    BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+  };
+  use serde_json::Value;
+
+  // One of every client message in the current spec.
+  const CLIENT_MESSAGES: &[&str] = &[
+    r#"{"RequestServerInfo": {"Id": 1, "ClientName": "Test Client", "MessageVersion": 3}}"#,
+    r#"{"Ping": {"Id": 1}}"#,
+    r#"{"StartScanning": {"Id": 1}}"#,
+    r#"{"StopScanning": {"Id": 1}}"#,
+    r#"{"RequestDeviceList": {"Id": 1}}"#,
+    r#"{"StopAllDevices": {"Id": 1}}"#,
+    r#"{"VibrateCmd": {"Id": 1, "DeviceIndex": 0, "Speeds": [{"Index": 0, "Speed": 0.5}]}}"#,
+    r#"{"LinearCmd": {"Id": 1, "DeviceIndex": 0, "Vectors": [
+      {"Index": 0, "Duration": 500, "Position": 0.3}
+    ]}}"#,
+    r#"{"RotateCmd": {"Id": 1, "DeviceIndex": 0, "Rotations": [
+      {"Index": 0, "Speed": 0.5, "Clockwise": true}
+    ]}}"#,
+    r#"{"RawWriteCmd": {"Id": 1, "DeviceIndex": 0, "Endpoint": "tx", "Data": [1, 2, 255],
+      "WriteWithResponse": false}}"#,
+    r#"{"RawReadCmd": {"Id": 1, "DeviceIndex": 0, "Endpoint": "rx", "ExpectedLength": 4,
+      "Timeout": 100}}"#,
+    r#"{"StopDeviceCmd": {"Id": 1, "DeviceIndex": 0}}"#,
+    r#"{"RawSubscribeCmd": {"Id": 1, "DeviceIndex": 0, "Endpoint": "rx"}}"#,
+    r#"{"RawUnsubscribeCmd": {"Id": 1, "DeviceIndex": 0, "Endpoint": "rx"}}"#,
+    r#"{"ScalarCmd": {"Id": 1, "DeviceIndex": 0, "Scalars": [
+      {"Index": 0, "Scalar": 0.5, "ActuatorType": "Vibrate"},
+      {"Index": 1, "Scalar": 1.0, "ActuatorType": "Constrict"}
+    ]}}"#,
+    r#"{"SensorReadCmd": {"Id": 1, "DeviceIndex": 0, "SensorIndex": 0, "SensorType": "Battery"}}"#,
+    r#"{"SensorSubscribeCmd": {"Id": 1, "DeviceIndex": 0, "SensorIndex": 0,
+      "SensorType": "Pressure"}}"#,
+    r#"{"SensorUnsubscribeCmd": {"Id": 1, "DeviceIndex": 0, "SensorIndex": 0,
+      "SensorType": "Pressure"}}"#,
+  ];
+
+  // One of every server message in the current spec.
+  const SERVER_MESSAGES: &[&str] = &[
+    r#"{"Ok": {"Id": 1}}"#,
+    r#"{"Error": {"Id": 0, "ErrorCode": 4, "ErrorMessage": "Test Error"}}"#,
+    r#"{"ServerInfo": {"Id": 1, "ServerName": "Test Server", "MessageVersion": 3,
+      "MaxPingTime": 0}}"#,
+    r#"{"DeviceList": {"Id": 1, "Devices": [{"DeviceIndex": 0, "DeviceName": "Test Device",
+      "DeviceMessages": {
+        "ScalarCmd": [{"FeatureDescriptor": "", "ActuatorType": "Vibrate", "StepCount": 20}],
+        "StopDeviceCmd": {}
+      }}]}}"#,
+    r#"{"DeviceAdded": {"Id": 0, "DeviceIndex": 1, "DeviceName": "Test Device",
+      "DeviceDisplayName": "Display Name", "DeviceMessageTimingGap": 50,
+      "DeviceMessages": {
+        "LinearCmd": [{"FeatureDescriptor": "", "ActuatorType": "Position", "StepCount": 100}],
+        "SensorReadCmd": [{"FeatureDescriptor": "Battery", "SensorType": "Battery",
+          "SensorRange": [[0, 100]]}],
+        "StopDeviceCmd": {}
+      }}}"#,
+    r#"{"DeviceRemoved": {"Id": 0, "DeviceIndex": 1}}"#,
+    r#"{"ScanningFinished": {"Id": 0}}"#,
+    r#"{"RawReading": {"Id": 1, "DeviceIndex": 0, "Endpoint": "rx", "Data": [0, 128, 255]}}"#,
+    r#"{"SensorReading": {"Id": 1, "DeviceIndex": 0, "SensorIndex": 0, "SensorType": "Battery",
+      "Data": [50]}}"#,
+  ];
+
+  fn as_array(msg: &str) -> String {
+    format!("[{}]", msg)
+  }
+
+  /// The MessagePack encoding should have exactly the same structure as the JSON one.
+  fn assert_same_structure(json: &ButtplugSerializedMessage, msgpack: &ButtplugSerializedMessage) {
+    let (ButtplugSerializedMessage::Text(json), ButtplugSerializedMessage::Binary(msgpack)) =
+      (json, msgpack)
+    else {
+      panic!("Expected text JSON and binary MessagePack, got {json:?} and {msgpack:?}");
+    };
+    let json_value: Value = serde_json::from_str(json).expect("Test, assuming infallible.");
+    let msgpack_value: Value = rmp_serde::from_slice(msgpack).expect("Test, assuming infallible.");
+    assert_eq!(json_value, msgpack_value);
+  }
+
+  #[test]
+  fn test_client_message_parity() {
+    for json in CLIENT_MESSAGES {
+      let msgs: Vec<ButtplugCurrentSpecClientMessage> =
+        serde_json::from_str(&as_array(json)).expect("Test, assuming infallible.");
+      let json_msg = ButtplugClientJSONSerializer::default().serialize(&msgs);
+      let msgpack_msg = ButtplugClientMessagePackSerializer::default().serialize(&msgs);
+      assert_same_structure(&json_msg, &msgpack_msg);
+
+      // Validation is off, since there's no schema to hold MessagePack messages to.
+      let json_server = ButtplugServerJSONSerializer::new(JsonValidationMode::Never);
+      json_server.force_message_version(&BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+      let msgpack_server = ButtplugServerMessagePackSerializer::default();
+      msgpack_server.force_message_version(&BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+      assert_eq!(
+        json_server.deserialize(&json_msg),
+        msgpack_server.deserialize(&msgpack_msg),
+        "Mismatch for {}",
+        json
+      );
+    }
+  }
+
+  #[test]
+  fn test_server_message_parity() {
+    for json in SERVER_MESSAGES {
+      let msgs: Vec<ButtplugCurrentSpecServerMessage> =
+        serde_json::from_str(&as_array(json)).expect("Test, assuming infallible.");
+      let json_msg = ButtplugSerializedMessage::Text(vec_to_protocol_json(&msgs));
+      let msgpack_msg = vec_to_protocol_msgpack(&msgs);
+      assert_same_structure(&json_msg, &msgpack_msg);
+      let json_client_msgs = ButtplugClientJSONSerializer::default()
+        .deserialize(&json_msg)
+        .expect("Test, assuming infallible.");
+      let msgpack_client_msgs = ButtplugClientMessagePackSerializer::default()
+        .deserialize(&msgpack_msg)
+        .expect("Test, assuming infallible.");
+      assert_eq!(
+        json_client_msgs, msgpack_client_msgs,
+        "Mismatch for {}",
+        json
+      );
+    }
+  }
+
+  #[test]
+  fn test_server_message_version_parity() {
+    let msgs: Vec<ButtplugServerMessage> = vec![
+      message::Ok::new(1).into(),
+      message::DeviceRemoved::new(2).into(),
+      message::ScanningFinished::default().into(),
+    ];
+    for version in [
+      ButtplugMessageSpecVersion::Version0,
+      ButtplugMessageSpecVersion::Version1,
+      ButtplugMessageSpecVersion::Version2,
+      ButtplugMessageSpecVersion::Version3,
+    ] {
+      let json_server = ButtplugServerJSONSerializer::default();
+      json_server.force_message_version(&version);
+      let msgpack_server = ButtplugServerMessagePackSerializer::default();
+      msgpack_server.force_message_version(&version);
+      assert_same_structure(
+        &json_server.serialize(&msgs),
+        &msgpack_server.serialize(&msgs),
+      );
+    }
+  }
+
+  #[test]
+  fn test_msgpack_requires_handshake() {
+    let server = ButtplugServerMessagePackSerializer::default();
+    let ping: Vec<ButtplugCurrentSpecClientMessage> =
+      serde_json::from_str(&as_array(CLIENT_MESSAGES[1])).expect("Test, assuming infallible.");
+    let ping = ButtplugClientMessagePackSerializer::default().serialize(&ping);
+    assert!(server.deserialize(&ping).is_err());
+    assert!(server
+      .deserialize(&ButtplugSerializedMessage::Text(as_array(
+        CLIENT_MESSAGES[1]
+      )))
+      .is_err());
+    assert!(server
+      .deserialize(&ButtplugSerializedMessage::Binary(vec![0x91, 0xc1]))
+      .is_err());
+  }
+
+  #[test]
+  fn test_msgpack_rejects_invalid_messages() {
+    let server = ButtplugServerMessagePackSerializer::default();
+    let client = ButtplugClientMessagePackSerializer::default();
+    let rsi: Vec<ButtplugCurrentSpecClientMessage> =
+      serde_json::from_str(&as_array(CLIENT_MESSAGES[0])).expect("Test, assuming infallible.");
+    server
+      .deserialize(&client.serialize(&rsi))
+      .expect("Test, assuming infallible.");
+    let scalar_cmd = |scalar: f64| {
+      let msgs: Vec<ButtplugCurrentSpecClientMessage> = serde_json::from_str(&format!(
+        r#"[{{"ScalarCmd": {{"Id": 1, "DeviceIndex": 0, "Scalars": [
+          {{"Index": 0, "Scalar": {}, "ActuatorType": "Vibrate"}}
+        ]}}}}]"#,
+        scalar
+      ))
+      .expect("Test, assuming infallible.");
+      client.serialize(&msgs)
+    };
+    assert!(server.deserialize(&scalar_cmd(0.5)).is_ok());
+    assert!(server.deserialize(&scalar_cmd(1e300)).is_err());
+    assert!(server.deserialize(&scalar_cmd(-1.0)).is_err());
+  }
+
+  #[test]
+  fn test_negotiated_serializer() {
+    let rsi: Vec<ButtplugCurrentSpecClientMessage> =
+      serde_json::from_str(&as_array(CLIENT_MESSAGES[0])).expect("Test, assuming infallible.");
+    let reply: Vec<ButtplugServerMessage> = vec![message::Ok::new(1).into()];
+
+    let json_server = ButtplugServerNegotiatedSerializer::default();
+    assert_eq!(json_server.format(), None);
+    json_server
+      .deserialize(&ButtplugClientJSONSerializer::default().serialize(&rsi))
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      json_server.format(),
+      Some(ButtplugSerializationFormat::Json)
+    );
+    assert!(matches!(
+      json_server.serialize(&reply),
+      ButtplugSerializedMessage::Text(_)
+    ));
+    assert!(json_server
+      .deserialize(&ButtplugClientMessagePackSerializer::default().serialize(&rsi))
+      .is_err());
+
+    let msgpack_server = ButtplugServerNegotiatedSerializer::default();
+    msgpack_server
+      .deserialize(&ButtplugClientMessagePackSerializer::default().serialize(&rsi))
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      msgpack_server.format(),
+      Some(ButtplugSerializationFormat::MessagePack)
+    );
+    assert!(matches!(
+      msgpack_server.serialize(&reply),
+      ButtplugSerializedMessage::Binary(_)
+    ));
+  }
+}
@@ -0,0 +1,193 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "network-manager")]
+mod test {
+  use buttplug::{
+    client::{
+      ButtplugClient,
+      ButtplugClientDevice,
+      ButtplugClientEvent,
+      LinearCommand,
+      ScalarValueCommand,
+    },
+    core::connector::ButtplugInProcessClientConnectorBuilder,
+    server::{
+      device::hardware::communication::network::{
+        NetworkCommunicationManagerBuilder,
+        NetworkDeviceDefinition,
+      },
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use std::{sync::Arc, time::Duration};
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+  };
+
+  const TEST_TOKEN: &str = "test-device-token";
+
+  /// Request as seen by the fake device: request line, device token header, and body.
+  type TestRequest = (String, Option<String>, String);
+
+  async fn read_request(stream: &mut TcpStream) -> TestRequest {
+    let mut data = vec![];
+    let mut buf = [0u8; 1024];
+    let header_end = loop {
+      let len = stream
+        .read(&mut buf)
+        .await
+        .expect("Test, assuming infallible.");
+      assert!(len > 0, "Connection closed before request was read.");
+      data.extend_from_slice(&buf[..len]);
+      if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+        break pos + 4;
+      }
+    };
+    let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let mut lines = head.lines();
+    let request_line = lines.next().expect("Test, assuming infallible.");
+    let header = |name: &str| {
+      head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key
+          .eq_ignore_ascii_case(name)
+          .then(|| value.trim().to_owned())
+      })
+    };
+    let content_length: usize =
+      header("content-length").map_or(0, |len| len.parse().expect("Test, assuming infallible."));
+    while data.len() < header_end + content_length {
+      let len = stream
+        .read(&mut buf)
+        .await
+        .expect("Test, assuming infallible.");
+      data.extend_from_slice(&buf[..len]);
+    }
+    // Drop the HTTP version from the request line.
+    let request_line = request_line
+      .rsplit_once(' ')
+      .expect("Test, assuming infallible.")
+      .0;
+    (
+      request_line.to_owned(),
+      header("x-device-token"),
+      String::from_utf8_lossy(&data[header_end..]).to_string(),
+    )
+  }
+
+  /// Start a fake Autoblow that answers every request with success, and reports the requests it gets.
+  async fn start_fake_device() -> (String, mpsc::UnboundedReceiver<TestRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+      .await
+      .expect("Test, assuming infallible.");
+    let url = format!(
+      "http://{}",
+      listener.local_addr().expect("Test, assuming infallible.")
+    );
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+      while let Ok((mut stream, _)) = listener.accept().await {
+        let sender = sender.clone();
+        tokio::spawn(async move {
+          let request = read_request(&mut stream).await;
+          let _ = sender.send(request);
+          let _ = stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}")
+            .await;
+        });
+      }
+    });
+    (url, receiver)
+  }
+
+  async fn expect_request(
+    requests: &mut mpsc::UnboundedReceiver<TestRequest>,
+    request_line: &str,
+    body: &str,
+  ) {
+    let (line, token, request_body) = tokio::time::timeout(Duration::from_secs(5), requests.recv())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    assert_eq!(line, request_line);
+    assert_eq!(token.as_deref(), Some(TEST_TOKEN));
+    assert_eq!(request_body, body);
+  }
+
+  async fn scan_for_device(client: &ButtplugClient) -> Arc<ButtplugClientDevice> {
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        return device;
+      }
+    }
+    panic!("Event stream closed before device was found.");
+  }
+
+  #[tokio::test]
+  async fn test_autoblow_network_device() {
+    let (url, mut requests) = start_fake_device().await;
+    let mut definition = NetworkDeviceDefinition::autoblow(TEST_TOKEN);
+    // The token is a secret, so shouldn't be usable as the address.
+    assert!(definition.address().starts_with("autoblow-"));
+    assert!(!definition.address().contains(TEST_TOKEN));
+    assert_eq!(
+      definition.address(),
+      NetworkDeviceDefinition::autoblow(TEST_TOKEN).address()
+    );
+    definition.url(&url);
+    let mut comm_manager_builder = NetworkCommunicationManagerBuilder::default();
+    comm_manager_builder.device(definition);
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder.comm_manager(comm_manager_builder);
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server_builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    let client = ButtplugClient::new("Network Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+
+    let device = scan_for_device(&client).await;
+    assert_eq!(device.name(), "Autoblow AI Ultra");
+    expect_request(&mut requests, "GET /autoblow/connected", "").await;
+
+    device
+      .oscillate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_request(
+      &mut requests,
+      "PUT /autoblow/oscillate",
+      r#"{"maxY":100,"minY":0,"speed":50}"#,
+    )
+    .await;
+
+    device
+      .linear(&LinearCommand::Linear(500, 0.25))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_request(
+      &mut requests,
+      "PUT /autoblow/goto",
+      r#"{"duration":500,"position":25}"#,
+    )
+    .await;
+
+    device.stop().await.expect("Test, assuming infallible.");
+    expect_request(&mut requests, "PUT /autoblow/oscillate/stop", "").await;
+  }
+}
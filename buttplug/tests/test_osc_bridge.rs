@@ -0,0 +1,127 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(all(feature = "simulated-manager", feature = "osc-bridge"))]
+mod test {
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientEvent},
+    core::{connector::ButtplugInProcessClientConnectorBuilder, message::Endpoint},
+    server::{
+      device::hardware::{
+        communication::simulated::{
+          SimulatedDeviceCommunicationManagerBuilder,
+          SimulatedDeviceConfig,
+        },
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      osc::OscBridgeConfig,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use std::net::UdpSocket;
+
+  fn osc_float_message(address: &str, value: f32) -> Vec<u8> {
+    let mut packet = vec![];
+    for string in [address, ",f"] {
+      packet.extend(string.as_bytes());
+      packet.push(0);
+      while !packet.len().is_multiple_of(4) {
+        packet.push(0);
+      }
+    }
+    packet.extend(value.to_be_bytes());
+    packet
+  }
+
+  #[tokio::test]
+  async fn test_osc_bridge_forwards_scalar() {
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    let handle = builder.add_device(&SimulatedDeviceConfig::new("Massage Demo", None));
+    let mut commands = handle.command_stream();
+    let config = OscBridgeConfig::from_json(
+      r#"{
+        "listen-address": "127.0.0.1:0",
+        "routes": [
+          {
+            "address": "/avatar/parameters/Haptic*",
+            "device-name": "Aneros*",
+            "actuator-index": 0,
+            "scale": 0.5
+          }
+        ],
+        "min-update-interval-ms": 100
+      }"#,
+    )
+    .expect("Test, assuming infallible.");
+    let server = ButtplugServerBuilder::default()
+      .comm_manager(builder)
+      .osc_bridge(config)
+      .finish()
+      .expect("Test, assuming infallible.");
+    let osc_address = server
+      .osc_bridge_address()
+      .expect("Test, assuming infallible.");
+
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+    let client = ButtplugClient::new("OSC Bridge Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(_) = event {
+        break;
+      }
+    }
+
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("Test, assuming infallible.");
+    // Not matching the route, should be ignored.
+    socket
+      .send_to(
+        &osc_float_message("/avatar/parameters/Other", 1.0),
+        osc_address,
+      )
+      .expect("Test, assuming infallible.");
+    socket
+      .send_to(
+        &osc_float_message("/avatar/parameters/HapticLeft", 1.0),
+        osc_address,
+      )
+      .expect("Test, assuming infallible.");
+    // Scaled to 0.5, only on the first vibrator.
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x40], false))
+    );
+    // Within the update interval, so only the latest value should make it to the device, and
+    // invalid values should never make it.
+    for value in [0.2, f32::NAN, 0.0] {
+      socket
+        .send_to(
+          &osc_float_message("/avatar/parameters/HapticLeft", value),
+          osc_address,
+        )
+        .expect("Test, assuming infallible.");
+    }
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x00], false))
+    );
+    assert!(commands.try_recv().is_err());
+  }
+}
@@ -0,0 +1,90 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+use buttplug::{
+  core::{
+    errors::ButtplugDeviceError,
+    message::{ActuatorType, SensorType},
+  },
+  server::ButtplugServer,
+};
+
+#[tokio::test]
+async fn test_protocol_capabilities_scalar_protocol() {
+  let server = ButtplugServer::default();
+  let capabilities = server
+    .device_manager()
+    .protocol_capabilities("magic-motion-1")
+    .expect("Test, assuming infallible.");
+  assert!(!capabilities.handles_all_messages());
+  assert_eq!(
+    capabilities.scalar(),
+    &vec![ActuatorType::Vibrate, ActuatorType::Oscillate]
+  );
+  assert!(!capabilities.rotate());
+  assert!(!capabilities.linear());
+  assert!(capabilities.sensor_read().is_empty());
+  assert!(capabilities.sensor_subscribe().is_empty());
+}
+
+#[tokio::test]
+async fn test_protocol_capabilities_linear_and_sensors() {
+  let server = ButtplugServer::default();
+  let capabilities = server
+    .device_manager()
+    .protocol_capabilities("kiiroo-v21")
+    .expect("Test, assuming infallible.");
+  assert_eq!(capabilities.scalar(), &vec![ActuatorType::Vibrate]);
+  assert!(capabilities.linear());
+  assert!(capabilities.sensor_read().contains(&SensorType::Battery));
+}
+
+#[tokio::test]
+async fn test_protocol_capabilities_rotate() {
+  let server = ButtplugServer::default();
+  let capabilities = server
+    .device_manager()
+    .protocol_capabilities("synchro")
+    .expect("Test, assuming infallible.");
+  assert!(capabilities.scalar().is_empty());
+  assert!(capabilities.rotate());
+}
+
+#[tokio::test]
+async fn test_protocol_capabilities_unknown_protocol() {
+  let server = ButtplugServer::default();
+  assert!(matches!(
+    server
+      .device_manager()
+      .protocol_capabilities("not-a-protocol"),
+    Err(ButtplugDeviceError::ProtocolNotImplemented(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_protocol_capabilities_passthrough_protocol() {
+  let server = ButtplugServer::default();
+  let capabilities = server
+    .device_manager()
+    .protocol_capabilities("buttplug-passthru")
+    .expect("Test, assuming infallible.");
+  assert!(capabilities.handles_all_messages());
+  assert!(capabilities.scalar().is_empty());
+}
+
+#[tokio::test]
+async fn test_protocol_capabilities_all_protocols_declared() {
+  let server = ButtplugServer::default();
+  let device_manager = server.device_manager();
+  for protocol in device_manager.protocol_names() {
+    assert!(
+      device_manager.protocol_capabilities(&protocol).is_ok(),
+      "{}",
+      protocol
+    );
+  }
+}
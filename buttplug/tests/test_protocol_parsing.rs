@@ -0,0 +1,76 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+// Runs the same protocol parsers as the fuzz targets over malformed input, so regressions show up
+// in a normal test run.
+
+use buttplug::server::device::protocol::{
+  kgoal_boost,
+  kiiroo_v21,
+  kiiroo_v2_vibrator,
+  lovense,
+  monsterpub,
+  parsing,
+  vibcrafter,
+  xinput,
+};
+
+fn parse_all(data: &[u8]) {
+  let _ = lovense::parse_battery(data);
+  if let Ok(type_response) = std::str::from_utf8(data) {
+    let _ = lovense::lovense_model_resolver(type_response);
+  }
+  let _ = kiiroo_v21::parse_battery(data);
+  let _ = kiiroo_v21::parse_sensors(data);
+  let _ = kiiroo_v2_vibrator::parse_touch(data);
+  let _ = kgoal_boost::parse_pressure(data);
+  let _ = monsterpub::parse_model_name(data);
+  let _ = monsterpub::auth_response(data);
+  let _ = vibcrafter::decrypt(data);
+  let _ = parsing::battery_level("test", data);
+  let _ = xinput::parse_battery(data);
+}
+
+#[test]
+fn test_parsers_reject_malformed_data() {
+  assert!(lovense::parse_battery(b"").is_none());
+  assert!(lovense::parse_battery(b"s").is_none());
+  assert!(lovense::parse_battery(b";").is_none());
+  assert!(lovense::parse_battery(&[0xff, b';']).is_none());
+  assert_eq!(lovense::parse_battery(b"s89;"), Some(89));
+  assert_eq!(lovense::parse_battery(b"89;"), Some(89));
+  assert!(kiiroo_v21::parse_battery(&[0; 5]).is_err());
+  assert!(kiiroo_v21::parse_sensors(&[0; 4]).is_none());
+  assert!(kiiroo_v2_vibrator::parse_touch(&[0; 3]).is_err());
+  assert!(kgoal_boost::parse_pressure(&[0; 6]).is_err());
+  assert!(monsterpub::parse_model_name(&[0xff, 0xfe]).is_err());
+  assert!(monsterpub::auth_response(&[]).is_err());
+  assert!(monsterpub::auth_response(&[0xff; 16]).is_err());
+  assert!(vibcrafter::decrypt(&[0xff; 7]).is_err());
+  assert!(xinput::parse_battery(&[]).is_err());
+}
+
+#[test]
+fn test_parsers_survive_arbitrary_data() {
+  for len in 0..64 {
+    parse_all(&vec![0; len]);
+    parse_all(&vec![0xff; len]);
+    parse_all(&vec![b';'; len]);
+  }
+  // Small deterministic xorshift so failures are reproducible without a fuzzing toolchain.
+  let mut state = 0x2545_f491_4f6c_dd1du64;
+  for _ in 0..2000 {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    let len = (state % 48) as usize;
+    let data: Vec<u8> = (0..len)
+      .map(|i| (state.rotate_left(i as u32 * 8) & 0xff) as u8)
+      .collect();
+    parse_all(&data);
+  }
+}
@@ -0,0 +1,265 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(all(feature = "protocol-plugins", unix))]
+mod util;
+
+#[cfg(all(feature = "protocol-plugins", unix))]
+mod test {
+  use super::util::{
+    expect_write,
+    test_device_manager::TestDeviceIdentifier,
+    TestDeviceChannelHost,
+    TestDeviceCommunicationManagerBuilder,
+  };
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, ScalarValueCommand},
+    core::{
+      connector::ButtplugInProcessClientConnectorBuilder,
+      message::{ActuatorType, Endpoint},
+    },
+    server::{
+      plugin::{
+        messages::{PluginScalarCommand, PluginWriteCommand},
+        HostMessage,
+        PluginMessage,
+        ProtocolPluginHostConfig,
+        PLUGIN_API_VERSION,
+      },
+      ButtplugServer,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use std::{sync::Arc, time::Duration};
+  use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+      unix::{OwnedReadHalf, OwnedWriteHalf},
+      UnixStream,
+    },
+  };
+
+  const TEST_DEVICE_CONFIG: &str = r#"
+    {
+      "version": { "major": 2, "minor": 24 },
+      "protocols": {
+        "plugin-test": {
+          "btle": {
+            "names": ["Plugin Test Device"],
+            "services": {
+              "0000eee0-0000-1000-8000-00805f9b34fb": {
+                "tx": "0000eee1-0000-1000-8000-00805f9b34fb"
+              }
+            }
+          },
+          "defaults": {
+            "name": "Plugin Test Device",
+            "messages": {
+              "ScalarCmd": [
+                { "StepRange": [0, 20], "ActuatorType": "Vibrate" }
+              ]
+            }
+          }
+        }
+      }
+    }
+  "#;
+
+  struct TestPlugin {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+  }
+
+  impl TestPlugin {
+    async fn connect(socket_path: &str) -> Self {
+      let (reader, writer) = UnixStream::connect(socket_path)
+        .await
+        .expect("Test, assuming infallible.")
+        .into_split();
+      Self {
+        lines: BufReader::new(reader).lines(),
+        writer,
+      }
+    }
+
+    async fn send(&mut self, message: PluginMessage) {
+      let mut line = serde_json::to_string(&message).expect("Test, assuming infallible.");
+      line.push('\n');
+      self
+        .writer
+        .write_all(line.as_bytes())
+        .await
+        .expect("Test, assuming infallible.");
+    }
+
+    async fn recv(&mut self) -> Option<HostMessage> {
+      let line = tokio::time::timeout(Duration::from_secs(5), self.lines.next_line())
+        .await
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible.")?;
+      Some(serde_json::from_str(&line).expect("Test, assuming infallible."))
+    }
+
+    async fn register(&mut self, version: u32, protocols: &[&str]) -> Option<HostMessage> {
+      self
+        .send(PluginMessage::Register {
+          version,
+          name: "Test Plugin".to_owned(),
+          protocols: protocols.iter().map(|p| p.to_string()).collect(),
+        })
+        .await;
+      self.recv().await
+    }
+  }
+
+  fn socket_path(name: &str) -> String {
+    std::env::temp_dir()
+      .join(format!("buttplug-{}-{}.sock", name, std::process::id()))
+      .to_string_lossy()
+      .to_string()
+  }
+
+  fn plugin_test_server(socket_path: &str) -> (ButtplugServer, TestDeviceChannelHost) {
+    let mut builder = TestDeviceCommunicationManagerBuilder::default();
+    let device_channel =
+      builder.add_test_device(&TestDeviceIdentifier::new("Plugin Test Device", None));
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder
+      .device_configuration_json(Some(TEST_DEVICE_CONFIG.to_owned()))
+      .protocol_plugin_host(ProtocolPluginHostConfig::new(
+        socket_path,
+        &["plugin-test".to_owned()],
+      ))
+      .comm_manager(builder);
+    (
+      server_builder.finish().expect("Test, assuming infallible."),
+      device_channel,
+    )
+  }
+
+  async fn connect_device(server: ButtplugServer) -> (ButtplugClient, Arc<ButtplugClientDevice>) {
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+    let client = ButtplugClient::new("Plugin Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        return (client, device);
+      }
+    }
+    panic!("Event stream closed before device was found.");
+  }
+
+  #[tokio::test]
+  async fn test_protocol_plugin_commands() {
+    let path = socket_path("plugin-commands");
+    let (server, mut device_channel) = plugin_test_server(&path);
+    let mut plugin = TestPlugin::connect(&path).await;
+    assert_eq!(
+      plugin.register(PLUGIN_API_VERSION, &["plugin-test"]).await,
+      Some(HostMessage::Registered {
+        version: PLUGIN_API_VERSION
+      })
+    );
+
+    let plugin_task = tokio::spawn(async move {
+      let Some(HostMessage::Initialize {
+        id, protocol, name, ..
+      }) = plugin.recv().await
+      else {
+        panic!("Expected device initialization");
+      };
+      assert_eq!(protocol, "plugin-test");
+      assert_eq!(name, "Plugin Test Device");
+      plugin
+        .send(PluginMessage::Commands {
+          id,
+          commands: vec![PluginWriteCommand::new(Endpoint::Tx, &[0xaa], false)],
+        })
+        .await;
+      let Some(HostMessage::ScalarCmd { id, commands, .. }) = plugin.recv().await else {
+        panic!("Expected scalar command");
+      };
+      assert_eq!(
+        commands,
+        vec![PluginScalarCommand::new(0, ActuatorType::Vibrate, 10)]
+      );
+      plugin
+        .send(PluginMessage::Commands {
+          id,
+          commands: vec![PluginWriteCommand::new(Endpoint::Tx, &[0x0f, 10], false)],
+        })
+        .await;
+      plugin
+    });
+
+    let (_client, device) = connect_device(server).await;
+    expect_write(&mut device_channel, &[0xaa]).await;
+    device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_write(&mut device_channel, &[0x0f, 10]).await;
+    plugin_task.await.expect("Test, assuming infallible.");
+  }
+
+  #[tokio::test]
+  async fn test_protocol_plugin_registration_failures() {
+    let path = socket_path("plugin-registration");
+    let (_server, _device_channel) = plugin_test_server(&path);
+
+    let mut plugin = TestPlugin::connect(&path).await;
+    assert!(matches!(
+      plugin
+        .register(PLUGIN_API_VERSION + 1, &["plugin-test"])
+        .await,
+      Some(HostMessage::RegistrationFailed { .. })
+    ));
+    let mut plugin = TestPlugin::connect(&path).await;
+    assert!(matches!(
+      plugin
+        .register(PLUGIN_API_VERSION, &["not-configured"])
+        .await,
+      Some(HostMessage::RegistrationFailed { .. })
+    ));
+
+    // Protocols can only be registered by one plugin at a time, but are freed when it disconnects.
+    let mut first_plugin = TestPlugin::connect(&path).await;
+    assert!(matches!(
+      first_plugin
+        .register(PLUGIN_API_VERSION, &["plugin-test"])
+        .await,
+      Some(HostMessage::Registered { .. })
+    ));
+    let mut second_plugin = TestPlugin::connect(&path).await;
+    assert!(matches!(
+      second_plugin
+        .register(PLUGIN_API_VERSION, &["plugin-test"])
+        .await,
+      Some(HostMessage::RegistrationFailed { .. })
+    ));
+    drop(first_plugin);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut second_plugin = TestPlugin::connect(&path).await;
+    assert!(matches!(
+      second_plugin
+        .register(PLUGIN_API_VERSION, &["plugin-test"])
+        .await,
+      Some(HostMessage::Registered { .. })
+    ));
+  }
+}
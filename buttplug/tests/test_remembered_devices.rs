@@ -0,0 +1,213 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+#[cfg(feature = "simulated-manager")]
+mod test {
+  use buttplug::{
+    core::message::{
+      self,
+      ButtplugServerMessage,
+      DeviceAdded,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+    server::{
+      device::{
+        hardware::communication::simulated::{
+          SimulatedDeviceCommunicationManagerBuilder,
+          SimulatedDeviceConfig,
+          SimulatedDeviceHandle,
+        },
+        GattServiceCache,
+        RememberedDeviceStore,
+      },
+      ButtplugServer,
+      ButtplugServerBuilder,
+    },
+    util::device_configuration::UserDeviceConfig,
+  };
+  use futures::{pin_mut, Stream, StreamExt};
+  use std::path::PathBuf;
+  use uuid::Uuid;
+
+  const ADDRESS: &str = "AA:BB:CC:DD:EE:01";
+
+  fn store_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+      "buttplug-remembered-{}-{}.json",
+      name,
+      std::process::id()
+    ))
+  }
+
+  async fn setup_server(store: RememberedDeviceStore) -> (ButtplugServer, SimulatedDeviceHandle) {
+    let mut comm_builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    let handle =
+      comm_builder.add_device(&SimulatedDeviceConfig::new("Massage Demo", Some(ADDRESS)));
+    let mut builder = ButtplugServerBuilder::default();
+    builder.comm_manager(comm_builder).remembered_devices(store);
+    let server = builder.finish().expect("Test, assuming infallible.");
+    server
+      .parse_message(
+        message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+          .into(),
+      )
+      .await
+      .expect("Test, assuming infallible.");
+    (server, handle)
+  }
+
+  async fn scan_for_device(
+    server: &ButtplugServer,
+    events: &mut (impl Stream<Item = ButtplugServerMessage> + Unpin),
+  ) -> DeviceAdded {
+    server
+      .parse_message(message::StartScanning::default().into())
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = events.next().await {
+      if let ButtplugServerMessage::DeviceAdded(device) = event {
+        return device;
+      }
+    }
+    panic!("Event stream closed before device was found.");
+  }
+
+  #[tokio::test]
+  async fn test_remembered_device_lifecycle() {
+    let path = store_path("lifecycle");
+    let _ = std::fs::remove_file(&path);
+    let store = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    assert!(store.devices().is_empty());
+    let (server, handle) = setup_server(store).await;
+    let events = server.event_stream();
+    pin_mut!(events);
+
+    let device = scan_for_device(&server, &mut events).await;
+    assert_eq!(*device.device_display_name(), None);
+    let device_manager = server.device_manager();
+    let remembered = device_manager.remembered_devices();
+    assert_eq!(remembered.len(), 1);
+    assert_eq!(remembered[0].address(), ADDRESS);
+    assert_eq!(remembered[0].identifier().protocol, "aneros");
+    assert_eq!(remembered[0].transport(), "btle");
+
+    // Overrides apply the next time the device connects.
+    let mut config = UserDeviceConfig::default();
+    config.set_display_name(Some("Bedside".to_owned()));
+    assert!(device_manager
+      .set_remembered_device_config(ADDRESS, Some(config))
+      .expect("Test, assuming infallible."));
+    assert!(!device_manager
+      .set_remembered_device_config("not-a-device", None)
+      .expect("Test, assuming infallible."));
+    handle.disconnect();
+    while let Some(event) = events.next().await {
+      if let ButtplugServerMessage::DeviceRemoved(_) = event {
+        break;
+      }
+    }
+    let device = scan_for_device(&server, &mut events).await;
+    assert_eq!(*device.device_display_name(), Some("Bedside".to_owned()));
+
+    // Everything is still there after loading the file again.
+    let reopened = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    let remembered = reopened
+      .device(ADDRESS)
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      *remembered
+        .config()
+        .as_ref()
+        .expect("Test, assuming infallible.")
+        .display_name(),
+      Some("Bedside".to_owned())
+    );
+
+    assert!(device_manager
+      .forget_device(ADDRESS)
+      .expect("Test, assuming infallible."));
+    assert!(!device_manager
+      .forget_device(ADDRESS)
+      .expect("Test, assuming infallible."));
+    assert!(device_manager.remembered_devices().is_empty());
+    assert!(RememberedDeviceStore::open(&path)
+      .expect("Test, assuming infallible.")
+      .devices()
+      .is_empty());
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[tokio::test]
+  async fn test_remembered_device_overrides_loaded_on_start() {
+    let path = store_path("startup");
+    let _ = std::fs::remove_file(&path);
+    {
+      let store = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+      let (server, _handle) = setup_server(store.clone()).await;
+      let events = server.event_stream();
+      pin_mut!(events);
+      scan_for_device(&server, &mut events).await;
+      let mut config = UserDeviceConfig::default();
+      config.set_display_name(Some("Travel".to_owned()));
+      assert!(store
+        .set_config(ADDRESS, Some(config))
+        .expect("Test, assuming infallible."));
+    }
+
+    // A new server picks the overrides up from the file.
+    let store = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    let (server, _handle) = setup_server(store).await;
+    let events = server.event_stream();
+    pin_mut!(events);
+    let device = scan_for_device(&server, &mut events).await;
+    assert_eq!(*device.device_display_name(), Some("Travel".to_owned()));
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[tokio::test]
+  async fn test_remembered_device_gatt_services() {
+    let path = store_path("gatt");
+    let _ = std::fs::remove_file(&path);
+    let store = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    let service = Uuid::from_u128(0x1);
+    // A write without response characteristic, and a notify characteristic.
+    let services = GattServiceCache::from([(
+      service,
+      [(Uuid::from_u128(0x2), 0x04), (Uuid::from_u128(0x3), 0x10)].into(),
+    )]);
+    // Hardware connects before the device is remembered, so the services are held until it is.
+    store
+      .set_gatt_services(ADDRESS, Some(services.clone()))
+      .expect("Test, assuming infallible.");
+    assert_eq!(store.gatt_services(ADDRESS), Some(services.clone()));
+    assert!(store.devices().is_empty());
+
+    let (server, _handle) = setup_server(store.clone()).await;
+    let events = server.event_stream();
+    pin_mut!(events);
+    scan_for_device(&server, &mut events).await;
+    let reopened = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    assert_eq!(reopened.gatt_services(ADDRESS), Some(services));
+
+    // Stale caches get cleared.
+    store
+      .set_gatt_services(ADDRESS, None)
+      .expect("Test, assuming infallible.");
+    let reopened = RememberedDeviceStore::open(&path).expect("Test, assuming infallible.");
+    assert_eq!(reopened.gatt_services(ADDRESS), None);
+    assert!(reopened.device(ADDRESS).is_some());
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[test]
+  fn test_remembered_device_invalid_file() {
+    let path = store_path("invalid");
+    std::fs::write(&path, "not json").expect("Test, assuming infallible.");
+    assert!(RememberedDeviceStore::open(&path).is_err());
+    let _ = std::fs::remove_file(&path);
+  }
+}
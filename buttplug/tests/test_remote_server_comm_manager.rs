@@ -0,0 +1,150 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "remote-server-manager")]
+mod test {
+  use super::util::{
+    expect_write,
+    test_device_manager::TestDeviceIdentifier,
+    TestDeviceChannelHost,
+    TestDeviceCommunicationManagerBuilder,
+    TestHardwareEvent,
+    TestHardwareNotification,
+  };
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, ScalarValueCommand},
+    core::{
+      connector::ButtplugInProcessClientConnectorBuilder,
+      message::{DeviceRemovedReason, Endpoint},
+    },
+    server::{
+      device::hardware::communication::remote_server::RemoteServerCommunicationManagerBuilder,
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use std::sync::Arc;
+
+  /// Build a remote server with a single test device, and a local client connected to a server
+  /// that federates the remote server's devices.
+  async fn setup_federated_client(device_name: &str) -> (ButtplugClient, TestDeviceChannelHost) {
+    let mut remote_builder = TestDeviceCommunicationManagerBuilder::default();
+    let device = remote_builder.add_test_device(&TestDeviceIdentifier::new(device_name, None));
+    let mut remote_server_builder = ButtplugServerBuilder::default();
+    remote_server_builder
+      .name("Remote Test Server")
+      .comm_manager(remote_builder);
+    let remote_connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(
+        remote_server_builder
+          .finish()
+          .expect("Test, assuming infallible."),
+      )
+      .finish();
+
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder.comm_manager(RemoteServerCommunicationManagerBuilder::new(
+      remote_connector,
+    ));
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server_builder.finish().expect("Test, assuming infallible."))
+      .finish();
+    let client = ButtplugClient::new("Federation Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    (client, device)
+  }
+
+  async fn scan_for_device(client: &ButtplugClient) -> Arc<ButtplugClientDevice> {
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        return device;
+      }
+    }
+    panic!("Event stream closed before device was found.");
+  }
+
+  #[tokio::test]
+  async fn test_remote_device_forwards_actuator_commands() {
+    let (client, mut remote_device) = setup_federated_client("Massage Demo").await;
+    let device = scan_for_device(&client).await;
+    // The proxied device keeps the name and features the remote server gave it.
+    assert_eq!(device.name(), "Aneros Vivi");
+    assert_eq!(device.vibrate_attributes().len(), 2);
+
+    device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_write(&mut remote_device, &[0xF1, 64]).await;
+    expect_write(&mut remote_device, &[0xF2, 64]).await;
+
+    device
+      .vibrate(&ScalarValueCommand::ScalarValueVec(vec![1.0, 0.5]))
+      .await
+      .expect("Test, assuming infallible.");
+    expect_write(&mut remote_device, &[0xF1, 127]).await;
+
+    device.stop().await.expect("Test, assuming infallible.");
+    expect_write(&mut remote_device, &[0xF1, 0]).await;
+    expect_write(&mut remote_device, &[0xF2, 0]).await;
+  }
+
+  #[tokio::test]
+  async fn test_remote_device_forwards_sensor_reads() {
+    let (client, remote_device) = setup_federated_client("Fugu").await;
+    let device = scan_for_device(&client).await;
+    assert!(device.has_battery_level());
+    remote_device
+      .sender
+      .send(TestHardwareEvent::Reads(vec![
+        TestHardwareNotification::new(Endpoint::RxBLEBattery, vec![42]),
+      ]))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      device
+        .battery_level()
+        .await
+        .expect("Test, assuming infallible."),
+      0.42
+    );
+  }
+
+  #[tokio::test]
+  async fn test_remote_device_removal() {
+    let (client, remote_device) = setup_federated_client("Massage Demo").await;
+    let mut event_stream = client.event_stream();
+    let device = scan_for_device(&client).await;
+    remote_device
+      .sender
+      .send(TestHardwareEvent::Disconnect)
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceRemoved(removed) = event {
+        assert_eq!(removed.index(), device.index());
+        // The remote server's reason is passed on.
+        assert_eq!(
+          removed.removed_reason(),
+          Some(DeviceRemovedReason::LinkLost)
+        );
+        return;
+      }
+    }
+    panic!("Event stream closed before device was removed.");
+  }
+}
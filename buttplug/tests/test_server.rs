@@ -11,13 +11,15 @@ pub use util::{
     check_test_recv_value,
     TestDeviceCommunicationManagerBuilder,
     TestDeviceIdentifier,
+    TestHardwareEvent,
   },
   test_server_with_device,
+  DelayDeviceCommunicationManagerBuilder,
 };
 
 use buttplug::{
   core::{
-    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError, ButtplugMessageError},
     message::{
       self,
       ButtplugMessageSpecVersion,
@@ -25,16 +27,34 @@ use buttplug::{
       Endpoint,
       BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
     },
+    ButtplugResultFuture,
   },
   server::{
-    device::hardware::{HardwareCommand, HardwareWriteCmd},
+    device::{
+      hardware::{
+        communication::{
+          HardwareCommunicationManager,
+          HardwareCommunicationManagerBuilder,
+          HardwareCommunicationManagerEvent,
+        },
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      CommunicationManagerScanStatus,
+      ScanState,
+      DEFAULT_DEVICE_STOP_TIMEOUT,
+    },
+    log_forwarding::ButtplugLogForwardingLayer,
     ButtplugServer,
     ButtplugServerBuilder,
   },
 };
-use futures::{pin_mut, Stream, StreamExt};
-use std::time::Duration;
-use tokio::time::sleep;
+use futures::{future, pin_mut, FutureExt, Stream, StreamExt};
+use std::{
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+use tokio::{sync::mpsc::Sender, time::sleep};
 
 async fn setup_test_server(
   msg_union: message::ButtplugClientMessage,
@@ -47,10 +67,14 @@ async fn setup_test_server(
     .await
     .expect("Test, assuming infallible.")
   {
-    ButtplugServerMessage::ServerInfo(s) => assert_eq!(
-      s,
-      message::ServerInfo::new("Buttplug Server", ButtplugMessageSpecVersion::Version3, 0)
-    ),
+    ButtplugServerMessage::ServerInfo(s) => {
+      // Current spec clients are also sent the server clock, which changes between runs.
+      assert!(s.server_time().is_some());
+      let mut expected =
+        message::ServerInfo::new("Buttplug Server", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION, 0);
+      expected.set_server_time(s.server_time());
+      assert_eq!(s, expected);
+    }
     _ => panic!("Should've received ok"),
   }
   (server, recv)
@@ -59,7 +83,7 @@ async fn setup_test_server(
 #[tokio::test]
 async fn test_server_handshake() {
   let msg =
-    message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3).into();
+    message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into();
   let (server, _recv) = setup_test_server(msg).await;
   assert!(server.connected());
 }
@@ -143,6 +167,62 @@ async fn test_ping_timeout() {
   }
 }
 
+#[tokio::test]
+async fn test_request_log_forwards_logs() {
+  use tracing_subscriber::prelude::*;
+  let layer = ButtplugLogForwardingLayer::default();
+  let subscriber = tracing_subscriber::registry().with(layer.clone());
+  let _default = tracing::subscriber::set_default(subscriber);
+  let server = ButtplugServerBuilder::default()
+    .log_forwarding_layer(&layer)
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let reply = server
+    .parse_message(message::RequestLog::new(message::LogLevel::Warn).into())
+    .await;
+  assert!(matches!(reply, Ok(ButtplugServerMessage::Ok(_))));
+  tracing::info!("Not severe enough to forward");
+  tracing::warn!("Forwarded to the client");
+  let msg = recv.next().await.expect("Test, assuming infallible.");
+  if let ButtplugServerMessage::Log(log) = msg {
+    assert_eq!(log.log_level(), message::LogLevel::Warn);
+    assert!(log.log_message().ends_with("Forwarded to the client"));
+  } else {
+    panic!("Didn't get a log message back");
+  }
+  // Turning logging off stops forwarding.
+  let reply = server
+    .parse_message(message::RequestLog::new(message::LogLevel::Off).into())
+    .await;
+  assert!(reply.is_ok());
+  tracing::error!("Not forwarded");
+  assert!(recv.next().now_or_never().is_none());
+}
+
+#[tokio::test]
+async fn test_request_log_without_forwarding_layer() {
+  let (server, _recv) = setup_test_server(
+    message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+  )
+  .await;
+  let err = server
+    .parse_message(message::RequestLog::new(message::LogLevel::Warn).into())
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugMessageError(ButtplugMessageError::UnhandledMessage(_))
+  ));
+}
+
 #[tokio::test]
 async fn test_device_stop_on_ping_timeout() {
   let mut builder = TestDeviceCommunicationManagerBuilder::default();
@@ -206,9 +286,69 @@ async fn test_device_stop_on_ping_timeout() {
    */
 }
 
+#[tokio::test]
+async fn test_device_stop_on_client_idle_timeout() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.client_idle_timeout(Duration::from_millis(300));
+  server_builder.comm_manager(builder);
+  let server = server_builder.finish().unwrap();
+
+  let recv = server.event_stream();
+  pin_mut!(recv);
+
+  let msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  assert!(server.parse_message(msg.into()).await.is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+  server
+    .parse_message(
+      message::VibrateCmd::new(device_index, vec![message::VibrateSubcommand::new(0, 0.5)]).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  check_test_recv_value(
+    &mut device,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false)),
+  );
+
+  // Keep the client active for longer than the timeout, the device shouldn't stop.
+  for _ in 0..4 {
+    sleep(Duration::from_millis(150)).await;
+    assert!(server
+      .parse_message(message::RequestDeviceList::default().into())
+      .await
+      .is_ok());
+  }
+  assert!(device.receiver.try_recv().is_err());
+
+  // Then go quiet.
+  let command = tokio::time::timeout(Duration::from_secs(2), device.receiver.recv())
+    .await
+    .expect("Device should stop once the client goes idle")
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    command,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false))
+  );
+  // Idle stops don't disconnect the client.
+  assert!(server.connected());
+}
+
 #[tokio::test]
 async fn test_repeated_handshake() {
-  let msg = message::RequestServerInfo::new("Test Client", ButtplugMessageSpecVersion::Version3);
+  let msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
 
   let (server, _recv) = setup_test_server((msg.clone()).into()).await;
   assert!(server.connected());
@@ -233,6 +373,52 @@ async fn test_invalid_device_index() {
   ));
 }
 
+#[test]
+fn test_client_message_destination() {
+  use message::{
+    ButtplugClientMessage,
+    ButtplugClientMessageDestination,
+    ButtplugDeviceCommandMessageUnion,
+    ButtplugDeviceManagerMessageUnion,
+  };
+  let cmd = message::StopDeviceCmd::new(3);
+  let msg: ButtplugClientMessage = cmd.clone().into();
+  assert_eq!(msg.device_index(), Some(3));
+  assert_eq!(
+    ButtplugClientMessageDestination::from(msg),
+    ButtplugClientMessageDestination::Device(ButtplugDeviceCommandMessageUnion::StopDeviceCmd(cmd))
+  );
+
+  let msg: ButtplugClientMessage = message::StartScanning::default().into();
+  assert_eq!(msg.device_index(), None);
+  assert_eq!(
+    ButtplugClientMessageDestination::from(msg),
+    ButtplugClientMessageDestination::DeviceManager(
+      ButtplugDeviceManagerMessageUnion::StartScanning(message::StartScanning::default())
+    )
+  );
+
+  let msg: ButtplugClientMessage = message::Ping::default().into();
+  assert_eq!(
+    ButtplugClientMessageDestination::from(msg.clone()),
+    ButtplugClientMessageDestination::Server(msg)
+  );
+}
+
+#[tokio::test]
+async fn test_unexpected_message_type() {
+  let msg = message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION);
+  let (server, _) = setup_test_server(msg.into()).await;
+  // Never implemented for any device, so neither the server nor the device manager handle it.
+  let reply = server
+    .parse_message(message::LovenseCmd::new(0, "Vibrate:20;").into())
+    .await;
+  assert!(matches!(
+    reply.unwrap_err().original_error(),
+    ButtplugError::ButtplugMessageError(ButtplugMessageError::UnexpectedMessageType(_))
+  ));
+}
+
 #[tokio::test]
 async fn test_device_index_generation() {
   let mut builder = TestDeviceCommunicationManagerBuilder::default();
@@ -466,6 +652,400 @@ async fn test_server_builder_user_device_config_old_config_version() {
     .is_err());
 }
 
+#[tokio::test]
+async fn test_server_metrics() {
+  assert!(ButtplugServer::default().metrics().is_none());
+
+  let mut device_builder = TestDeviceCommunicationManagerBuilder::default();
+  let _device = device_builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(device_builder)
+    .enable_metrics()
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  // Messages before the handshake are errors, but still counted.
+  assert!(server
+    .parse_message(message::Ping::default().into())
+    .await
+    .is_err());
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(server
+    .metrics()
+    .expect("Test, assuming infallible.")
+    .scanning());
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = msg {
+      break;
+    }
+  }
+  // The test comm manager finishes scanning as soon as it has found its devices.
+  while server
+    .metrics()
+    .expect("Test, assuming infallible.")
+    .scanning()
+  {
+    sleep(Duration::from_millis(10)).await;
+  }
+
+  let metrics = server.metrics().expect("Test, assuming infallible.");
+  assert!(metrics.client_connected());
+  assert_eq!(metrics.connected_devices(), 1);
+  assert_eq!(metrics.messages_received().get("Ping"), Some(&1));
+  assert_eq!(metrics.messages_received().get("RequestServerInfo"), Some(&1));
+  assert_eq!(metrics.message_errors().get("Ping"), Some(&1));
+  assert_eq!(metrics.message_errors().get("RequestServerInfo"), None);
+  assert_eq!(metrics.hardware_errors(), 0);
+  assert!(metrics
+    .to_prometheus_text()
+    .contains("buttplug_messages_received_total{type=\"StartScanning\"} 1\n"));
+}
+
+#[tokio::test]
+async fn test_server_stop_all_devices_force_disconnect() {
+  let mut device_builder = TestDeviceCommunicationManagerBuilder::default();
+  let hung_device = device_builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let mut working_device =
+    device_builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let server = ButtplugServerBuilder::default()
+    .comm_manager(device_builder)
+    .protocol_device_stop_timeout("aneros", Duration::from_millis(100))
+    .finish()
+    .expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let mut added = 0;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(_) = msg {
+      added += 1;
+      if added == 2 {
+        break;
+      }
+    }
+  }
+
+  hung_device
+    .sender
+    .send(TestHardwareEvent::StallWrites)
+    .await
+    .expect("Test, assuming infallible.");
+  // Give the test device time to process the event.
+  sleep(Duration::from_millis(50)).await;
+
+  // The protocol timeout should be used instead of the 1s default.
+  let start = Instant::now();
+  let err = server
+    .parse_message(message::StopAllDevices::default().into())
+    .await
+    .unwrap_err();
+  assert!(start.elapsed() < DEFAULT_DEVICE_STOP_TIMEOUT);
+  let ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DevicesForceDisconnected(indexes)) =
+    err.original_error()
+  else {
+    panic!("Expected force disconnect error, got {:?}", err);
+  };
+  assert_eq!(indexes.len(), 1);
+  let hung_index = indexes[0];
+  assert!(working_device.receiver.recv().await.is_some());
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceRemoved(dr) = msg {
+      assert_eq!(dr.device_index(), hung_index);
+      break;
+    }
+  }
+}
+
+/// Comm manager that always fails to start scanning.
+#[derive(Default)]
+struct FailingScanCommunicationManagerBuilder {}
+
+impl HardwareCommunicationManagerBuilder for FailingScanCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    _: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(FailingScanCommunicationManager {})
+  }
+}
+
+struct FailingScanCommunicationManager {}
+
+impl HardwareCommunicationManager for FailingScanCommunicationManager {
+  fn name(&self) -> &'static str {
+    "FailingScanCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    future::ready(Err(
+      ButtplugDeviceError::UnhandledCommand("No adapter".to_owned()).into(),
+    ))
+    .boxed()
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    future::ready(Ok(())).boxed()
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+}
+
+#[tokio::test]
+async fn test_server_scanning_status_partial_failure() {
+  let mut builder = ButtplugServerBuilder::default();
+  builder
+    .comm_manager(DelayDeviceCommunicationManagerBuilder::default())
+    .comm_manager(FailingScanCommunicationManagerBuilder::default());
+  let server = builder.finish().expect("Test, assuming infallible.");
+  let device_manager = server.device_manager();
+  assert!(device_manager
+    .scanning_status()
+    .iter()
+    .all(|status| *status.state() == ScanState::Idle));
+  let status_stream = device_manager.scanning_status_stream();
+  pin_mut!(status_stream);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+
+  // One manager failing doesn't fail StartScanning for the others.
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut updates = vec![
+    status_stream
+      .next()
+      .await
+      .expect("Test, assuming infallible."),
+    status_stream
+      .next()
+      .await
+      .expect("Test, assuming infallible."),
+  ];
+  updates.sort_by_key(|status| status.manager_name());
+  assert_eq!(
+    updates,
+    vec![
+      CommunicationManagerScanStatus::new("DelayDeviceCommunicationManager", ScanState::Scanning),
+      CommunicationManagerScanStatus::new(
+        "FailingScanCommunicationManager",
+        ScanState::Failed(ButtplugDeviceError::UnhandledCommand("No adapter".to_owned()).into())
+      ),
+    ]
+  );
+  assert_eq!(device_manager.scanning_status(), updates);
+
+  // Stopping only changes the manager that was actually scanning, the failure sticks around.
+  assert!(server
+    .parse_message(message::StopScanning::default().into())
+    .await
+    .is_ok());
+  assert_eq!(
+    status_stream.next().await,
+    Some(CommunicationManagerScanStatus::new(
+      "DelayDeviceCommunicationManager",
+      ScanState::Idle
+    ))
+  );
+  assert!(matches!(
+    device_manager.scanning_status()[1].state(),
+    ScanState::Failed(_)
+  ));
+
+  // Clients get the same status over the wire.
+  let reply = server
+    .parse_message(message::RequestScanningStatus::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  let status = match reply {
+    ButtplugServerMessage::ScanningStatus(status) => status,
+    msg => panic!("Expected ScanningStatus, got {:?}", msg),
+  };
+  assert_eq!(
+    status.managers(),
+    &vec![
+      message::ManagerScanningStatus::new(
+        "DelayDeviceCommunicationManager",
+        message::ManagerScanningState::Idle,
+        None
+      ),
+      message::ManagerScanningStatus::new(
+        "FailingScanCommunicationManager",
+        message::ManagerScanningState::Failed,
+        Some(
+          ButtplugError::from(ButtplugDeviceError::UnhandledCommand(
+            "No adapter".to_owned()
+          ))
+          .to_string()
+        )
+      ),
+    ]
+  );
+}
+
+#[derive(Default)]
+struct LifecycleCommunicationManagerBuilder {
+  calls: Arc<Mutex<Vec<&'static str>>>,
+}
+
+impl HardwareCommunicationManagerBuilder for LifecycleCommunicationManagerBuilder {
+  fn finish(
+    &mut self,
+    sender: Sender<HardwareCommunicationManagerEvent>,
+  ) -> Box<dyn HardwareCommunicationManager> {
+    Box::new(LifecycleCommunicationManager {
+      sender,
+      calls: self.calls.clone(),
+      scanning: false,
+    })
+  }
+}
+
+struct LifecycleCommunicationManager {
+  sender: Sender<HardwareCommunicationManagerEvent>,
+  calls: Arc<Mutex<Vec<&'static str>>>,
+  scanning: bool,
+}
+
+impl LifecycleCommunicationManager {
+  fn record(&self, call: &'static str) -> ButtplugResultFuture {
+    self
+      .calls
+      .lock()
+      .expect("Test, assuming infallible.")
+      .push(call);
+    future::ready(Ok(())).boxed()
+  }
+}
+
+impl HardwareCommunicationManager for LifecycleCommunicationManager {
+  fn name(&self) -> &'static str {
+    "LifecycleCommunicationManager"
+  }
+
+  fn start_scanning(&mut self) -> ButtplugResultFuture {
+    self.scanning = true;
+    self.record("start")
+  }
+
+  fn stop_scanning(&mut self) -> ButtplugResultFuture {
+    self.scanning = false;
+    let sender = self.sender.clone();
+    let fut = self.record("stop");
+    async move {
+      sender
+        .send(HardwareCommunicationManagerEvent::ScanningFinished)
+        .await
+        .expect("Test, assuming infallible.");
+      fut.await
+    }
+    .boxed()
+  }
+
+  fn scanning_status(&self) -> bool {
+    self.scanning
+  }
+
+  fn can_scan(&self) -> bool {
+    true
+  }
+
+  fn enter_background(&mut self) -> ButtplugResultFuture {
+    self.record("background")
+  }
+
+  fn enter_foreground(&mut self) -> ButtplugResultFuture {
+    self.record("foreground")
+  }
+}
+
+#[tokio::test]
+async fn test_server_background_suspends_scanning() {
+  let comm_builder = LifecycleCommunicationManagerBuilder::default();
+  let calls = comm_builder.calls.clone();
+  let mut builder = ButtplugServerBuilder::default();
+  builder.comm_manager(comm_builder);
+  let server = builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Commands are handled in order, so once the foreground resumes, everything before it is done.
+  server.enter_background().await;
+  server.enter_foreground().await;
+  sleep(Duration::from_millis(50)).await;
+  assert_eq!(
+    *calls.lock().expect("Test, assuming infallible."),
+    vec!["start", "stop", "background", "foreground", "start"]
+  );
+  // The suspended scan isn't reported as finished, as it's resumed in the foreground.
+  assert!(recv.next().now_or_never().is_none());
+
+  // Scans requested while in the background wait for the foreground, and stopping one that's still
+  // waiting finishes it right away.
+  server
+    .parse_message(message::StopScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    recv.next().await,
+    Some(ButtplugServerMessage::ScanningFinished(_))
+  ));
+  calls.lock().expect("Test, assuming infallible.").clear();
+  server.enter_background().await;
+  server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StopScanning::default().into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    recv.next().await,
+    Some(ButtplugServerMessage::ScanningFinished(_))
+  ));
+  server.enter_foreground().await;
+  sleep(Duration::from_millis(50)).await;
+  assert_eq!(
+    *calls.lock().expect("Test, assuming infallible."),
+    vec!["background", "foreground"]
+  );
+}
+
 // TODO Test sending system message (Id 0)
 // TODO Test sending system message (Ok but Id > 0)
 // TODO Test scan with no comm managers
@@ -6,13 +6,44 @@
 // for full license information.
 
 mod util;
-use buttplug::core::{
-  errors::{ButtplugDeviceError, ButtplugError},
-  message::{self, ButtplugServerMessage, Endpoint, BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION},
+use buttplug::{
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugDeviceMessage,
+      ButtplugServerMessage,
+      Endpoint,
+      LinearCmd,
+      PatternKeyframe,
+      ScalarCmd,
+      ScalarSubcommand,
+      VectorSubcommand,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  server::{
+    device::{
+      hardware::{HardwareCommand, HardwareWriteCmd},
+      protocol::ProtocolInitializationTimeouts,
+      Funscript,
+      FunscriptAction,
+      FunscriptOutput,
+      Pattern,
+      SafetyLimits,
+      SafetyPolicy,
+    },
+    permissions::{ClientPermissions, PermissionPolicy},
+    ButtplugServerBuilder,
+  },
+};
+use futures::{pin_mut, FutureExt, StreamExt};
+use std::{matches, time::Duration};
+pub use util::test_device_manager::{
+  TestDeviceCommunicationManagerBuilder,
+  TestDeviceIdentifier,
 };
-use futures::{pin_mut, StreamExt};
-use std::matches;
-pub use util::test_device_manager::TestDeviceCommunicationManagerBuilder;
 use util::test_server_with_device;
 
 // Test devices that have protocols that support movements not all devices do.
@@ -178,6 +209,1084 @@ async fn test_reject_on_no_raw_message() {
   }
 }
 
+#[tokio::test]
+async fn test_raw_message_invalid_endpoint() {
+  // Keep the device channel alive so writes to the test device don't fail.
+  let (server, _channel) = test_server_with_device("Massage Demo", true).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+      continue;
+    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      assert!(server
+        .parse_message(
+          message::RawWriteCmd::new(da.device_index(), Endpoint::Tx, &[0x0], false).into(),
+        )
+        .await
+        .is_ok());
+      assert!(server
+        .parse_message(message::RawSubscribeCmd::new(da.device_index(), Endpoint::Tx).into())
+        .await
+        .is_ok());
+      assert!(server
+        .parse_message(message::RawUnsubscribeCmd::new(da.device_index(), Endpoint::Tx).into())
+        .await
+        .is_ok());
+
+      // The Aneros only has a Tx endpoint, so everything aimed at Rx should come back as an error
+      // instead of taking down the server.
+      let mut should_be_err;
+      should_be_err = server
+        .parse_message(
+          message::RawWriteCmd::new(da.device_index(), Endpoint::Rx, &[0x0], false).into(),
+        )
+        .await;
+      assert!(matches!(
+        should_be_err.unwrap_err().original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::EndpointMissing {
+          endpoint: Endpoint::Rx,
+          protocol,
+          ..
+        }) if protocol == "aneros"
+      ));
+
+      should_be_err = server
+        .parse_message(message::RawSubscribeCmd::new(da.device_index(), Endpoint::Rx).into())
+        .await;
+      assert!(matches!(
+        should_be_err.unwrap_err().original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::EndpointMissing {
+          endpoint: Endpoint::Rx,
+          ..
+        })
+      ));
+
+      should_be_err = server
+        .parse_message(message::RawUnsubscribeCmd::new(da.device_index(), Endpoint::Rx).into())
+        .await;
+      assert!(matches!(
+        should_be_err.unwrap_err().original_error(),
+        ButtplugError::ButtplugDeviceError(ButtplugDeviceError::EndpointMissing {
+          endpoint: Endpoint::Rx,
+          ..
+        })
+      ));
+      return;
+    } else {
+      panic!(
+        "Returned message was not a DeviceAdded message or timed out: {:?}",
+        msg
+      );
+    }
+  }
+}
+
+#[tokio::test]
+async fn test_server_pattern_playback() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+      continue;
+    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    } else {
+      panic!(
+        "Returned message was not a DeviceAdded message or timed out: {:?}",
+        msg
+      );
+    }
+  }
+  server
+    .parse_message(
+      message::PlayPatternCmd::new(
+        device_index,
+        &[PatternKeyframe::new(0, 0.5), PatternKeyframe::new(100, 0.5)],
+        false,
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(server.device_manager().is_pattern_playing(device_index));
+
+  // Holding the same intensity should only cause one set of writes, followed by a stop once the
+  // pattern runs out.
+  for expected in [[0xF1, 64], [0xF2, 64], [0xF1, 0], [0xF2, 0]] {
+    let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      command,
+      HardwareCommand::Write(HardwareWriteCmd::new(
+        Endpoint::Tx,
+        expected.to_vec(),
+        false
+      ))
+    );
+  }
+  assert!(!server.device_manager().is_pattern_playing(device_index));
+
+  // Looping patterns play until they're stopped.
+  server
+    .parse_message(
+      message::PlayPatternCmd::new(device_index, &[PatternKeyframe::new(0, 0.25)], true).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  server
+    .parse_message(message::StopPatternCmd::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(!server.device_manager().is_pattern_playing(device_index));
+
+  // Invalid patterns are rejected before anything plays.
+  assert!(server
+    .parse_message(message::PlayPatternCmd::new(device_index, &[], false).into())
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_server_pattern_invalid_device() {
+  let (server, _) = test_server_with_device("Massage Demo", false).await;
+  let pattern =
+    Pattern::new(&[PatternKeyframe::new(0, 0.5)], true).expect("Test, assuming infallible.");
+  let err = server
+    .device_manager()
+    .play_pattern(10, pattern)
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(10))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_funscript_playback() {
+  let (server, mut device) = test_server_with_device("Onyx+", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+  let script = Funscript::from_json(
+    r#"{
+      "actions": [
+        { "at": 0, "pos": 0 },
+        { "at": 100, "pos": 100 },
+        { "at": 200, "pos": 0 }
+      ]
+    }"#,
+  )
+  .expect("Test, assuming infallible.");
+  server
+    .device_manager()
+    .play_funscript(device_index, script, FunscriptOutput::Linear(0))
+    .await
+    .expect("Test, assuming infallible.");
+
+  // Each action is sent as the device heads towards it, so we should see a stroke up then down,
+  // after the Onyx+ initialization writes.
+  for expected_position in [99, 0] {
+    loop {
+      let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+        .await
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible.");
+      if let HardwareCommand::Write(cmd) = command {
+        if !cmd.write_with_response() {
+          assert_eq!(cmd.data()[3], expected_position);
+          break;
+        }
+      }
+    }
+  }
+
+  // Playback holds at the end of the script, and can still be moved around.
+  tokio::time::sleep(Duration::from_millis(200)).await;
+  let device_manager = server.device_manager();
+  let state = device_manager
+    .funscript_state(device_index)
+    .expect("Test, assuming infallible.");
+  assert!(state.paused());
+  assert_eq!(state.position(), Duration::from_millis(200));
+  device_manager
+    .seek_funscript(device_index, Duration::from_millis(50))
+    .expect("Test, assuming infallible.");
+  device_manager
+    .set_funscript_rate(device_index, 2.0)
+    .expect("Test, assuming infallible.");
+  let state = device_manager
+    .funscript_state(device_index)
+    .expect("Test, assuming infallible.");
+  assert_eq!(state.position(), Duration::from_millis(50));
+  assert_eq!(state.rate(), 2.0);
+  assert!(device_manager
+    .set_funscript_rate(device_index, 0.0)
+    .is_err());
+
+  // Stopping the device unloads the script.
+  server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(device_manager.funscript_state(device_index).is_none());
+  assert!(matches!(
+    device_manager.pause_funscript(device_index),
+    Err(ButtplugError::ButtplugDeviceError(
+      ButtplugDeviceError::FunscriptNotLoaded(_)
+    ))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_funscript_invalid_output() {
+  let (server, _device) = test_server_with_device("Onyx+", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+  let script =
+    Funscript::new(&[FunscriptAction::new(0, 50)], false, 100).expect("Test, assuming infallible.");
+  // The Onyx+ only has a linear actuator.
+  let err = server
+    .device_manager()
+    .play_funscript(device_index, script.clone(), FunscriptOutput::Scalar(0))
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::MessageNotSupported(_))
+  ));
+  let err = server
+    .device_manager()
+    .play_funscript(device_index, script, FunscriptOutput::Linear(1))
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err,
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceFeatureIndexError(1, 1))
+  ));
+  assert!(server
+    .device_manager()
+    .funscript_state(device_index)
+    .is_none());
+}
+
+#[tokio::test]
+async fn test_server_device_group_scalar_cmd() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+      continue;
+    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    } else {
+      panic!(
+        "Returned message was not a DeviceAdded message or timed out: {:?}",
+        msg
+      );
+    }
+  }
+  assert!(server
+    .parse_message(message::AddDeviceToGroup::new("all", device_index).into())
+    .await
+    .is_ok());
+  let err = server
+    .parse_message(message::AddDeviceToGroup::new("all", 10).into())
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(10))
+  ));
+  assert_eq!(
+    server
+      .device_manager()
+      .device_group_members("all")
+      .expect("Test, assuming infallible."),
+    vec![device_index]
+  );
+
+  // Only the subcommand matching an actuator on the device should make it through.
+  let reply = server
+    .parse_message(
+      message::GroupScalarCmd::new(
+        "all",
+        vec![
+          ScalarSubcommand::new(1, 0.5, ActuatorType::Vibrate),
+          ScalarSubcommand::new(3, 1.0, ActuatorType::Vibrate),
+          ScalarSubcommand::new(0, 1.0, ActuatorType::Rotate),
+        ],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let ButtplugServerMessage::GroupCommandResult(result) = reply else {
+    panic!("Expected GroupCommandResult, got {:?}", reply);
+  };
+  assert!(result.is_success());
+  assert_eq!(result.succeeded(), &vec![device_index]);
+  let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    command,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 64], false))
+  );
+
+  let reply = server
+    .parse_message(
+      message::GroupScalarCmd::new(
+        "all",
+        vec![ScalarSubcommand::new(0, 1.0, ActuatorType::Rotate)],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let ButtplugServerMessage::GroupCommandResult(result) = reply else {
+    panic!("Expected GroupCommandResult, got {:?}", reply);
+  };
+  assert_eq!(result.skipped(), &vec![device_index]);
+  assert!(result.succeeded().is_empty());
+
+  assert!(server
+    .parse_message(message::RemoveDeviceFromGroup::new("all", device_index).into())
+    .await
+    .is_ok());
+  let err = server
+    .parse_message(message::GroupScalarCmd::new("all", vec![]).into())
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceGroupNotFound(_))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_device_latency_stats() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+      continue;
+    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    } else {
+      panic!(
+        "Returned message was not a DeviceAdded message or timed out: {:?}",
+        msg
+      );
+    }
+  }
+  let device_manager = server.device_manager();
+  assert_eq!(
+    device_manager
+      .device_latency_stats(device_index)
+      .expect("Test, assuming infallible.")
+      .total()
+      .sample_count(),
+    0
+  );
+  assert!(device_manager.device_latency_stats(10).is_none());
+
+  server
+    .parse_message(
+      ScalarCmd::new(
+        device_index,
+        vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(device.receiver.recv().await.is_some());
+  let stats = device_manager
+    .device_latency_stats(device_index)
+    .expect("Test, assuming infallible.");
+  for stage in [stats.protocol(), stats.queue(), stats.hardware(), stats.total()] {
+    assert_eq!(stage.sample_count(), 1);
+    assert!(stage.min() <= stage.max());
+  }
+  assert!(stats.hardware().max() <= stats.total().max());
+
+  let reply = server
+    .parse_message(message::RequestDeviceLatency::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  let ButtplugServerMessage::DeviceLatency(latency) = reply else {
+    panic!("Expected DeviceLatency, got {:?}", reply);
+  };
+  assert_eq!(latency.device_index(), device_index);
+  assert_eq!(latency.total().sample_count(), 1);
+  assert_eq!(
+    latency.total().max_us(),
+    stats.total().max().as_micros() as u64
+  );
+  let err = server
+    .parse_message(message::RequestDeviceLatency::new(10).into())
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceNotAvailable(10))
+  ));
+}
+
+#[tokio::test]
+async fn test_server_stop_cancels_pending_commands() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::ScanningFinished(_) = msg {
+      continue;
+    } else if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    } else {
+      panic!(
+        "Returned message was not a DeviceAdded message or timed out: {:?}",
+        msg
+      );
+    }
+  }
+
+  let scalar_fut = server.parse_message(
+    ScalarCmd::new(
+      device_index,
+      vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+    )
+    .into(),
+  );
+  let stop_fut = server.parse_message(message::StopDeviceCmd::new(device_index).into());
+  // Even if the stop runs first, the scalar command was received before it and shouldn't turn the
+  // device back on afterward.
+  stop_fut.await.expect("Test, assuming infallible.");
+  scalar_fut.await.expect("Test, assuming infallible.");
+  let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    command,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false))
+  );
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), device.receiver.recv())
+      .await
+      .is_err()
+  );
+}
+
+#[tokio::test]
+async fn test_server_scheduled_commands() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  let server_info = server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let ButtplugServerMessage::ServerInfo(server_info) = server_info else {
+    panic!("Expected ServerInfo, got {:?}", server_info);
+  };
+  assert!(server_info.server_time().is_some());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+  let clock = server.device_manager().clock();
+  let scalar_cmd = |scheduled_time| {
+    let mut msg = ScalarCmd::new(
+      device_index,
+      vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+    );
+    msg.set_scheduled_time(Some(scheduled_time));
+    msg
+  };
+
+  // The command is held until its scheduled time.
+  let scalar_fut = server.parse_message(scalar_cmd(clock.now() + 200).into());
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), device.receiver.recv())
+      .await
+      .is_err()
+  );
+  scalar_fut.await.expect("Test, assuming infallible.");
+  assert!(device.receiver.try_recv().is_ok());
+
+  // Stopping the device drops commands that haven't been sent yet.
+  let scalar_fut = server.parse_message(scalar_cmd(clock.now() + 200).into());
+  server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(scalar_fut.await.is_err());
+  // Only the stop itself should reach the device.
+  assert!(device.receiver.try_recv().is_ok());
+  assert!(
+    tokio::time::timeout(Duration::from_millis(300), device.receiver.recv())
+      .await
+      .is_err()
+  );
+
+  // Commands can't be scheduled too far ahead.
+  assert!(server
+    .parse_message(scalar_cmd(clock.now() + 120_000).into())
+    .await
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_server_protocol_keepalive() {
+  // Hgod devices stop vibrating unless their speed is resent every 100ms.
+  let (server, mut device) = test_server_with_device("AMN NEO", false).await;
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+
+  server
+    .parse_message(
+      ScalarCmd::new(
+        device_index,
+        vec![ScalarSubcommand::new(0, 0.5, ActuatorType::Vibrate)],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  // The command itself, then keepalives repeating it while the device is running.
+  for _ in 0..3 {
+    let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      command,
+      HardwareCommand::Write(HardwareWriteCmd::new(
+        Endpoint::Tx,
+        vec![0x55, 0x04, 0, 0, 0, 5],
+        false
+      ))
+    );
+  }
+  // Keepalives stop once the device does.
+  assert!(server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .is_ok());
+  // Drop a keepalive that might have gone out before the stop.
+  while device.receiver.try_recv().is_ok() {}
+  assert!(
+    tokio::time::timeout(Duration::from_millis(300), device.receiver.recv())
+      .await
+      .is_err()
+  );
+}
+
+#[tokio::test]
+async fn test_server_safety_limits() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let mut limits = SafetyLimits::default();
+  limits
+    .set_max_scalar(Some(0.5))
+    .set_max_run_time(Some(Duration::from_millis(100)))
+    .set_cooldown(Some(Duration::from_secs(60)));
+  let mut policy = SafetyPolicy::default();
+  policy.global(limits);
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).safety_policy(policy);
+  let server = server_builder.finish().unwrap();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+
+  let scalar_cmd = ScalarCmd::new(
+    device_index,
+    vec![ScalarSubcommand::new(0, 1.0, ActuatorType::Vibrate)],
+  );
+  server
+    .parse_message(scalar_cmd.clone().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // Clamped to the max scalar.
+  let command = device
+    .receiver
+    .recv()
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    command,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 64], false))
+  );
+  // Stopped by the server once the max run time is up.
+  let command = tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    command,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0], false))
+  );
+  // And can't be turned back on until the cooldown is over.
+  let err = server
+    .parse_message(scalar_cmd.into())
+    .await
+    .expect_err("Device should be cooling down");
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceSafetyCooldown(_))
+  ));
+  assert!(server
+    .parse_message(message::StopDeviceCmd::new(device_index).into())
+    .await
+    .is_ok());
+}
+
+#[tokio::test]
+async fn test_server_linear_safety_limits() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("VorzePiston", None));
+  let mut limits = SafetyLimits::default();
+  limits
+    .set_max_scalar(Some(0.5))
+    .set_max_run_time(Some(Duration::from_millis(100)))
+    .set_cooldown(Some(Duration::from_secs(60)));
+  let mut policy = SafetyPolicy::default();
+  policy.global(limits);
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder.comm_manager(builder).safety_policy(policy);
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+
+  let linear_cmd = LinearCmd::new(device_index, vec![VectorSubcommand::new(0, 1000, 1.0)]);
+  server
+    .parse_message(linear_cmd.clone().into())
+    .await
+    .expect("Test, assuming infallible.");
+  // The position is capped at the max scalar, which is 100 out of the Piston's 200.
+  let command = device
+    .receiver
+    .recv()
+    .await
+    .expect("Test, assuming infallible.");
+  let HardwareCommand::Write(write) = command else {
+    panic!("Expected a write, got {:?}", command);
+  };
+  assert_eq!(write.data()[1], 100);
+  // The move is longer than the max run time, so the device gets stopped, and further moves are
+  // rejected until the cooldown is over.
+  tokio::time::sleep(Duration::from_millis(300)).await;
+  let err = server
+    .parse_message(linear_cmd.into())
+    .await
+    .expect_err("Device should be cooling down");
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceSafetyCooldown(_))
+  ));
+}
+
+// Turns channel A of a DG-Lab Coyote all the way up, returning every power write sent to it until
+// it reaches the expected power.
+async fn coyote_power_writes(policy: SafetyPolicy, expected_power: &[u8]) -> Vec<Vec<u8>> {
+  // The Coyote is experimental, so has to be allowed before the device shows up.
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new(
+    "D-LAB ESTIM01",
+    Some("coyote".to_owned()),
+  ));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .safety_policy(policy)
+    .allow_experimental_protocol("dg-lab-coyote");
+  let server = server_builder.finish().expect("Test, assuming infallible.");
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+
+  server
+    .parse_message(
+      ScalarCmd::new(
+        device_index,
+        vec![ScalarSubcommand::new(0, 1.0, ActuatorType::Estim)],
+      )
+      .into(),
+    )
+    .await
+    .expect("Test, assuming infallible.");
+  let mut power_writes = vec![];
+  while power_writes.last().map(Vec::as_slice) != Some(expected_power) {
+    let command = tokio::time::timeout(Duration::from_secs(3), device.receiver.recv())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+    if let HardwareCommand::Write(write) = command {
+      if write.endpoint() == Endpoint::Tx {
+        power_writes.push(write.data().clone());
+      } else {
+        // Only channel A is on, so only its waveform should be sent.
+        assert_eq!(write.endpoint(), Endpoint::Generic0);
+      }
+    }
+  }
+  power_writes
+}
+
+#[tokio::test]
+async fn test_server_protocol_safety_limits() {
+  // The Coyote protocol requires power changes to be ramped, even with no safety policy set, and
+  // caps power at a fifth of the hardware max.
+  let power_writes = coyote_power_writes(SafetyPolicy::default(), &[0x00, 0xd0, 0x0c]).await;
+  // Power starts a twentieth of the way up, instead of jumping straight to the cap.
+  assert_eq!(power_writes[0], vec![0x00, 0xa8, 0x00]);
+  assert_eq!(power_writes.len(), 20);
+
+  // Raising the global max scalar doesn't lift the cap.
+  let mut limits = SafetyLimits::default();
+  limits.set_max_scalar(Some(1.0));
+  let mut policy = SafetyPolicy::default();
+  policy.global(limits);
+  let power_writes = coyote_power_writes(policy, &[0x00, 0xd0, 0x0c]).await;
+  assert_eq!(power_writes.len(), 20);
+
+  // Raising it for the device does, but ramping still applies.
+  let mut policy = SafetyPolicy::default();
+  policy.device("coyote", limits);
+  let power_writes = coyote_power_writes(policy, &[0x00, 0xf8, 0x3f]).await;
+  assert_eq!(power_writes[0], vec![0x00, 0x30, 0x03]);
+  assert_eq!(power_writes.len(), 20);
+}
+
+#[tokio::test]
+async fn test_server_initialization_timeout() {
+  // Lovense devices that don't answer DeviceType get asked again every 500ms, so a handshake that
+  // times out sooner should never get to the second ask.
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("LVS-Test", None));
+  let mut timeouts = ProtocolInitializationTimeouts::default();
+  timeouts.protocol_timeout("lovense", Duration::from_millis(100));
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .connection_retries(0)
+    .initialization_timeouts(timeouts);
+  let server = server_builder.finish().unwrap();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  for _ in 0..2 {
+    tokio::time::timeout(Duration::from_secs(1), device.receiver.recv())
+      .await
+      .expect("Test, assuming infallible.")
+      .expect("Test, assuming infallible.");
+  }
+  // Handshake was cancelled, so nothing else is written, and the device never shows up.
+  assert!(!matches!(
+    tokio::time::timeout(Duration::from_secs(1), device.receiver.recv()).await,
+    Ok(Some(_))
+  ));
+  while let Some(Some(msg)) = recv.next().now_or_never() {
+    assert!(!matches!(msg, ButtplugServerMessage::DeviceAdded(_)));
+  }
+}
+
+#[tokio::test]
+async fn test_server_client_permissions() {
+  let mut builder = TestDeviceCommunicationManagerBuilder::default();
+  let mut device = builder.add_test_device(&TestDeviceIdentifier::new("Massage Demo", None));
+  let mut trusted = ClientPermissions::restricted();
+  trusted.set_raw_write(true).set_scanning(true);
+  let mut policy = PermissionPolicy::default();
+  policy
+    .default_permissions(ClientPermissions::restricted())
+    .client("Trusted Client", trusted);
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(builder)
+    .allow_raw_messages()
+    .permission_policy(policy);
+  let server = server_builder.finish().unwrap();
+  let recv = server.event_stream();
+  pin_mut!(recv);
+  let is_permission_error = |err: message::Error| {
+    matches!(
+      err.original_error(),
+      ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DevicePermissionError(_))
+    )
+  };
+
+  // Clients without their own permissions get the restricted default.
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into()
+    )
+    .await
+    .is_ok());
+  assert!(is_permission_error(
+    server
+      .parse_message(message::StartScanning::default().into())
+      .await
+      .expect_err("Scanning should not be allowed")
+  ));
+  assert!(server.disconnect().await.is_ok());
+
+  assert!(server
+    .parse_message(
+      message::RequestServerInfo::new("Trusted Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into()
+    )
+    .await
+    .is_ok());
+  assert!(server
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+  assert!(server
+    .parse_message(message::RawWriteCmd::new(device_index, Endpoint::Tx, &[0x0], false).into())
+    .await
+    .is_ok());
+  assert_eq!(
+    device
+      .receiver
+      .recv()
+      .await
+      .expect("Test, assuming infallible."),
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0x0], false))
+  );
+  assert!(is_permission_error(
+    server
+      .parse_message(
+        message::RawWriteCmd::new(device_index, Endpoint::Firmware, &[0x0], false).into()
+      )
+      .await
+      .expect_err("Firmware endpoints should not be allowed")
+  ));
+  assert!(is_permission_error(
+    server
+      .parse_message(message::RawReadCmd::new(device_index, Endpoint::Tx, 1, 0).into())
+      .await
+      .expect_err("Raw reads should not be allowed")
+  ));
+  assert!(is_permission_error(
+    server
+      .parse_message(message::AddDeviceToGroup::new("Test Group", device_index).into())
+      .await
+      .expect_err("Device groups should not be allowed")
+  ));
+  assert!(is_permission_error(
+    server
+      .parse_message(message::RemoveDeviceFromGroup::new("Test Group", device_index).into())
+      .await
+      .expect_err("Device groups should not be allowed")
+  ));
+  assert!(is_permission_error(
+    server
+      .parse_message(
+        message::PlayPatternCmd::new(device_index, &[PatternKeyframe::new(100, 0.5)], false).into()
+      )
+      .await
+      .expect_err("Patterns should not be allowed")
+  ));
+  // Stopping patterns is always allowed.
+  assert!(server
+    .parse_message(message::StopPatternCmd::new(device_index).into())
+    .await
+    .is_ok());
+}
+
 /*
 #[cfg(target_os = "windows")]
 #[ignore = "Has weird timeout issues"]
@@ -0,0 +1,350 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+use buttplug::{
+  client::{ButtplugClient, ButtplugClientEvent, ScalarValueCommand},
+  core::{
+    errors::{ButtplugDeviceError, ButtplugError, ButtplugHandshakeError},
+    message::{
+      self,
+      ActuatorType,
+      ButtplugServerMessage,
+      Endpoint,
+      ScalarCmd,
+      ScalarSubcommand,
+      SensorType,
+      BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION,
+    },
+  },
+  server::{
+    device::hardware::{HardwareCommand, HardwareSubscribeCmd, HardwareUnsubscribeCmd},
+    multiplexer::{ButtplugServerMultiplexer, DeviceClaimPolicy, MultiplexedClient},
+    permissions::{ClientPermissions, PermissionPolicy},
+    ButtplugServerBuilder,
+  },
+};
+use futures::{pin_mut, StreamExt};
+use std::time::Duration;
+use util::{
+  expect_write,
+  test_server_with_device,
+  TestDeviceChannelHost,
+  TestDeviceCommunicationManagerBuilder,
+  TestHardwareEvent,
+  TestHardwareNotification,
+};
+
+async fn handshake(client: &MultiplexedClient) {
+  let reply = client
+    .parse_message(
+      message::RequestServerInfo::new("Test Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION).into(),
+    )
+    .await;
+  assert!(matches!(reply, Ok(ButtplugServerMessage::ServerInfo(_))));
+  assert!(client.connected());
+}
+
+async fn setup_multiplexer(
+  policy: DeviceClaimPolicy,
+) -> (
+  ButtplugServerMultiplexer,
+  MultiplexedClient,
+  MultiplexedClient,
+  TestDeviceChannelHost,
+  u32,
+) {
+  let (server, device) = test_server_with_device("Massage Demo", false).await;
+  let multiplexer = ButtplugServerMultiplexer::new(server, policy);
+  let client_a = multiplexer.connect_client();
+  let client_b = multiplexer.connect_client();
+  handshake(&client_a).await;
+  handshake(&client_b).await;
+  // Device events should reach every client, no matter which one started scanning.
+  let recv = client_b.event_stream();
+  pin_mut!(recv);
+  assert!(client_a
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+  (multiplexer, client_a, client_b, device, device_index)
+}
+
+fn vibrate(device_index: u32, speed: f64) -> message::ButtplugClientMessage {
+  ScalarCmd::new(
+    device_index,
+    vec![ScalarSubcommand::new(0, speed, ActuatorType::Vibrate)],
+  )
+  .into()
+}
+
+#[tokio::test]
+async fn test_multiplexer_requires_handshake() {
+  let (server, _device) = test_server_with_device("Massage Demo", false).await;
+  let multiplexer = ButtplugServerMultiplexer::new(server, DeviceClaimPolicy::default());
+  let client = multiplexer.connect_client();
+  let err = client
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugHandshakeError(ButtplugHandshakeError::RequestServerInfoExpected)
+  ));
+  handshake(&client).await;
+  // Other clients still get their own handshake.
+  handshake(&multiplexer.connect_client()).await;
+}
+
+#[tokio::test]
+async fn test_multiplexer_exclusive_claims() {
+  let (multiplexer, client_a, client_b, mut device, device_index) =
+    setup_multiplexer(DeviceClaimPolicy::Exclusive).await;
+
+  client_a
+    .parse_message(vibrate(device_index, 0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 64]).await;
+  assert_eq!(multiplexer.device_claim(device_index), Some(client_a.id()));
+
+  let err = client_b
+    .parse_message(vibrate(device_index, 1.0))
+    .await
+    .unwrap_err();
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DeviceClaimedByOtherClient(_))
+  ));
+  // StopAllDevices only covers the sending client's devices.
+  assert!(client_b
+    .parse_message(message::StopAllDevices::default().into())
+    .await
+    .is_ok());
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), device.receiver.recv())
+      .await
+      .is_err()
+  );
+
+  // Disconnecting stops and releases everything the client claimed.
+  client_a
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 0]).await;
+  assert_eq!(multiplexer.device_claim(device_index), None);
+  client_b
+    .parse_message(vibrate(device_index, 1.0))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 127]).await;
+}
+
+#[tokio::test]
+async fn test_multiplexer_last_writer_wins() {
+  let (multiplexer, client_a, client_b, mut device, device_index) =
+    setup_multiplexer(DeviceClaimPolicy::LastWriterWins).await;
+
+  client_a
+    .parse_message(vibrate(device_index, 0.5))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 64]).await;
+  client_b
+    .parse_message(vibrate(device_index, 1.0))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 127]).await;
+  assert_eq!(multiplexer.device_claim(device_index), Some(client_b.id()));
+
+  // Dropping a client without disconnecting still stops its devices.
+  drop(client_b);
+  expect_write(&mut device, &[0xF1, 0]).await;
+  assert_eq!(multiplexer.device_claim(device_index), None);
+}
+
+#[tokio::test]
+async fn test_multiplexer_client_permissions() {
+  let mut policy = PermissionPolicy::default();
+  policy
+    .default_permissions(ClientPermissions::restricted())
+    .client("Trusted Client", ClientPermissions::default());
+  let mut server_builder = ButtplugServerBuilder::default();
+  server_builder
+    .comm_manager(TestDeviceCommunicationManagerBuilder::default())
+    .permission_policy(policy);
+  let multiplexer = ButtplugServerMultiplexer::new(
+    server_builder.finish().expect("Test, assuming infallible."),
+    DeviceClaimPolicy::Exclusive,
+  );
+
+  // The first client to connect sets up the server, but its permissions shouldn't carry over to
+  // the clients after it.
+  let restricted_client = multiplexer.connect_client();
+  handshake(&restricted_client).await;
+  let trusted_client = multiplexer.connect_client();
+  assert!(trusted_client
+    .parse_message(
+      message::RequestServerInfo::new("Trusted Client", BUTTPLUG_CURRENT_MESSAGE_SPEC_VERSION)
+        .into()
+    )
+    .await
+    .is_ok());
+
+  let err = restricted_client
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .expect_err("Scanning should not be allowed");
+  assert!(matches!(
+    err.original_error(),
+    ButtplugError::ButtplugDeviceError(ButtplugDeviceError::DevicePermissionError(_))
+  ));
+  assert!(trusted_client
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+}
+
+#[tokio::test]
+async fn test_multiplexer_shared_sensor_subscriptions() {
+  let (server, mut device) = test_server_with_device("Boost", false).await;
+  let multiplexer = ButtplugServerMultiplexer::new(server, DeviceClaimPolicy::Exclusive);
+  let client_a = multiplexer.connect_client();
+  let client_b = multiplexer.connect_client();
+  handshake(&client_a).await;
+  handshake(&client_b).await;
+  let recv_a = client_a.event_stream();
+  pin_mut!(recv_a);
+  assert!(client_a
+    .parse_message(message::StartScanning::default().into())
+    .await
+    .is_ok());
+  let mut device_index = 100;
+  while let Some(msg) = recv_a.next().await {
+    if let ButtplugServerMessage::DeviceAdded(da) = msg {
+      device_index = da.device_index();
+      break;
+    }
+  }
+
+  let subscribe = || message::SensorSubscribeCmd::new(device_index, 0, SensorType::Pressure).into();
+  client_a
+    .parse_message(subscribe())
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Subscribe(HardwareSubscribeCmd::new(
+      Endpoint::RxPressure
+    )))
+  );
+  // The second subscriber rides along on the first subscription.
+  client_b
+    .parse_message(subscribe())
+    .await
+    .expect("Test, assuming infallible.");
+  let recv_b = client_b.event_stream();
+  pin_mut!(recv_b);
+  device
+    .sender
+    .send(TestHardwareEvent::Notifications(vec![
+      TestHardwareNotification::new(
+        Endpoint::RxPressure,
+        vec![0x00, 0x01, 0x04, 0x00, 0x05, 0x00, 0x06],
+      ),
+    ]))
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(matches!(
+    recv_b.next().await,
+    Some(ButtplugServerMessage::SensorReading(_))
+  ));
+
+  // The server subscription stays until the last subscriber goes away.
+  client_a
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  assert!(
+    tokio::time::timeout(Duration::from_millis(100), device.receiver.recv())
+      .await
+      .is_err()
+  );
+  client_b
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    device.receiver.recv().await,
+    Some(HardwareCommand::Unsubscribe(HardwareUnsubscribeCmd::new(
+      Endpoint::RxPressure
+    )))
+  );
+}
+
+#[tokio::test]
+async fn test_multiplexer_client_connector() {
+  let (server, mut device) = test_server_with_device("Massage Demo", false).await;
+  let multiplexer = ButtplugServerMultiplexer::new(server, DeviceClaimPolicy::Exclusive);
+  let client_a = ButtplugClient::new("Client A");
+  client_a
+    .connect(multiplexer.client_connector())
+    .await
+    .expect("Test, assuming infallible.");
+  let client_b = ButtplugClient::new("Client B");
+  client_b
+    .connect(multiplexer.client_connector())
+    .await
+    .expect("Test, assuming infallible.");
+
+  let mut event_stream_a = client_a.event_stream();
+  let mut event_stream_b = client_b.event_stream();
+  client_a
+    .start_scanning()
+    .await
+    .expect("Test, assuming infallible.");
+  let mut devices = vec![];
+  for event_stream in [&mut event_stream_a, &mut event_stream_b] {
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        devices.push(device);
+        break;
+      }
+    }
+  }
+  let (device_a, device_b) = (devices.remove(0), devices.remove(0));
+  device_a
+    .vibrate(&ScalarValueCommand::ScalarValueVec(vec![0.5]))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 64]).await;
+  assert!(device_b
+    .vibrate(&ScalarValueCommand::ScalarValueVec(vec![1.0]))
+    .await
+    .is_err());
+
+  // Disconnecting the client releases its claims.
+  client_a
+    .disconnect()
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 0]).await;
+  assert_eq!(multiplexer.device_claim(device_b.index()), None);
+  device_b
+    .vibrate(&ScalarValueCommand::ScalarValueVec(vec![1.0]))
+    .await
+    .expect("Test, assuming infallible.");
+  expect_write(&mut device, &[0xF1, 127]).await;
+}
@@ -0,0 +1,292 @@
+// Buttplug Rust Source Code File - See https://buttplug.io for more info.
+//
+// Copyright 2016-2022 Nonpolynomial Labs LLC. All rights reserved.
+//
+// Licensed under the BSD 3-Clause license. See LICENSE file in the project root
+// for full license information.
+
+mod util;
+
+#[cfg(feature = "simulated-manager")]
+mod test {
+
+  use buttplug::{
+    client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, ScalarValueCommand},
+    core::{
+      connector::ButtplugInProcessClientConnectorBuilder,
+      message::{Endpoint, ScanFilter},
+    },
+    server::{
+      device::hardware::{
+        communication::simulated::{
+          SimulatedDeviceCommunicationManagerBuilder,
+          SimulatedDeviceConfig,
+          SimulatedDeviceResponse,
+        },
+        recording::{HardwareTrafficRecorder, HardwareTrafficRecording},
+        HardwareCommand,
+        HardwareWriteCmd,
+      },
+      ButtplugServerBuilder,
+    },
+  };
+  use futures::StreamExt;
+  use std::{sync::Arc, time::Duration};
+
+  async fn setup_test_client(
+    builder: SimulatedDeviceCommunicationManagerBuilder,
+  ) -> ButtplugClient {
+    setup_test_client_with_recorder(builder, None).await
+  }
+
+  async fn setup_test_client_with_recorder(
+    builder: SimulatedDeviceCommunicationManagerBuilder,
+    recorder: Option<HardwareTrafficRecorder>,
+  ) -> ButtplugClient {
+    let mut server_builder = ButtplugServerBuilder::default();
+    server_builder
+      .name("Simulated DCM Test Server")
+      .comm_manager(builder);
+    if let Some(recorder) = recorder {
+      server_builder.hardware_traffic_recorder(recorder);
+    }
+    let server = server_builder.finish().expect("Test, assuming infallible.");
+    let connector = ButtplugInProcessClientConnectorBuilder::default()
+      .server(server)
+      .finish();
+
+    let client = ButtplugClient::new("Simulated DCM Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    client
+  }
+
+  async fn scan_for_device(client: &ButtplugClient) -> Arc<ButtplugClientDevice> {
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceAdded(device) = event {
+        return device;
+      }
+    }
+    panic!("Event stream closed before device was found.");
+  }
+
+  #[tokio::test]
+  async fn test_simulated_device_echoes_writes() {
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    let handle = builder.add_device(&SimulatedDeviceConfig::new("Massage Demo", None));
+    let mut commands = handle.command_stream();
+    let client = setup_test_client(builder).await;
+
+    let device = scan_for_device(&client).await;
+    assert_eq!(device.name(), "Aneros Vivi");
+    assert!(handle.connected());
+
+    device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF1, 0x40], false))
+    );
+    assert_eq!(
+      commands.recv().await.expect("Test, assuming infallible."),
+      HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, vec![0xF2, 0x40], false))
+    );
+
+    // Disconnected devices are removed, and found again on the next scan.
+    let mut event_stream = client.event_stream();
+    handle.disconnect();
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::DeviceRemoved(_) = event {
+        break;
+      }
+    }
+    assert!(!handle.connected());
+    let device = scan_for_device(&client).await;
+    assert_eq!(device.name(), "Aneros Vivi");
+  }
+
+  #[tokio::test]
+  async fn test_simulated_device_json_config_responses() {
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    // Lovense devices won't finish identifying until they answer a "DeviceType;" query.
+    let handles = builder
+      .add_devices_from_json(
+        r#"
+        {
+          "devices": [
+            {
+              "name": "LVS-Simulated",
+              "responses": [
+                {
+                  "endpoint": "tx",
+                  "data": [68, 101, 118, 105, 99, 101, 84, 121, 112, 101, 59],
+                  "reply-endpoint": "rx",
+                  "reply": [70, 58, 49, 49, 58, 48, 48, 56, 50, 48, 53, 57, 65, 68, 51, 66, 68, 59]
+                }
+              ]
+            }
+          ]
+        }
+        "#,
+      )
+      .expect("Test, assuming infallible.");
+    assert_eq!(handles.len(), 1);
+    assert_eq!(builder.devices().len(), 1);
+    let client = setup_test_client(builder).await;
+
+    let device = scan_for_device(&client).await;
+    assert_eq!(device.name(), "Lovense Sex Machine");
+    assert!(handles[0].connected());
+  }
+
+  #[test]
+  fn test_simulated_device_invalid_json_config() {
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    assert!(builder
+      .add_devices_from_json(r#"{"devices": [{"address": "no-name"}]}"#)
+      .is_err());
+    assert!(builder.devices().is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_hardware_traffic_record_and_replay() {
+    let recording_path = std::env::temp_dir().join(format!(
+      "buttplug-traffic-recording-{}.jsonl",
+      std::process::id()
+    ));
+
+    // Record a session with a device that has to answer during identification.
+    let mut config = SimulatedDeviceConfig::new("LVS-Recorded", Some("recorded-device"));
+    config.add_response(SimulatedDeviceResponse::new(
+      Endpoint::Tx,
+      b"DeviceType;",
+      Endpoint::Rx,
+      b"F:11:0082059AD3BD;",
+    ));
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    builder.add_device(&config);
+    let recorder =
+      HardwareTrafficRecorder::new(&recording_path).expect("Test, assuming infallible.");
+    let client = setup_test_client_with_recorder(builder, Some(recorder.clone())).await;
+    let device = scan_for_device(&client).await;
+    device
+      .oscillate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    client
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+    recorder.flush().await;
+
+    let recording = HardwareTrafficRecording::load(&recording_path)
+      .expect("Test, assuming infallible.")
+      .for_address("recorded-device");
+    let _ = std::fs::remove_file(&recording_path);
+    let expected = recording.outbound_commands();
+    assert!(expected.contains(&HardwareCommand::Write(HardwareWriteCmd::new(
+      Endpoint::Tx,
+      b"DeviceType;".to_vec(),
+      false
+    ))));
+
+    // Replaying the same session against a device built from the recording should produce the
+    // same traffic.
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    let handle = builder.add_device(&SimulatedDeviceConfig::from_recording(
+      "LVS-Recorded",
+      &recording,
+    ));
+    let mut commands = handle.command_stream();
+    let client = setup_test_client(builder).await;
+    let device = scan_for_device(&client).await;
+    assert_eq!(device.name(), "Lovense Sex Machine");
+    device
+      .oscillate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    let mut replayed = vec![];
+    while replayed.len() < expected.len() {
+      replayed.push(commands.recv().await.expect("Test, assuming infallible."));
+    }
+    assert_eq!(replayed, expected);
+  }
+
+  /// Run a filtered scan until it finishes, returning the devices that were added. Devices can
+  /// finish connecting after ScanningFinished, so they get a little more time after that.
+  async fn filtered_scan(
+    client: &ButtplugClient,
+    filter: ScanFilter,
+  ) -> Vec<Arc<ButtplugClientDevice>> {
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning_with_filter(filter)
+      .await
+      .expect("Test, assuming infallible.");
+    while let Some(event) = event_stream.next().await {
+      if let ButtplugClientEvent::ScanningFinished = event {
+        break;
+      }
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    client.devices()
+  }
+
+  fn two_device_builder() -> SimulatedDeviceCommunicationManagerBuilder {
+    let mut builder = SimulatedDeviceCommunicationManagerBuilder::default();
+    builder.add_device(&SimulatedDeviceConfig::new(
+      "Massage Demo",
+      Some("AA:BB:CC:DD:EE:01"),
+    ));
+    builder.add_device(&SimulatedDeviceConfig::new(
+      "Massage Demo",
+      Some("AA:BB:CC:DD:EE:02"),
+    ));
+    builder
+  }
+
+  #[tokio::test]
+  async fn test_scan_filter_address() {
+    let client = setup_test_client(two_device_builder()).await;
+    let devices = filtered_scan(&client, ScanFilter::new(&[], &[], &["aa:bb:cc:dd:ee:02"])).await;
+    assert_eq!(devices.len(), 1);
+    assert_eq!(
+      devices[0]
+        .connection_info()
+        .as_ref()
+        .expect("Test, assuming infallible.")
+        .address(),
+      "AA:BB:CC:DD:EE:02"
+    );
+
+    // Filters only last for one scan.
+    let devices = filtered_scan(&client, ScanFilter::default()).await;
+    assert_eq!(devices.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_scan_filter_protocol() {
+    let client = setup_test_client(two_device_builder()).await;
+    let devices = filtered_scan(&client, ScanFilter::new(&["lovense"], &[], &[])).await;
+    assert!(devices.is_empty());
+    let devices = filtered_scan(&client, ScanFilter::new(&["aneros"], &["btle"], &[])).await;
+    assert_eq!(devices.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_scan_filter_transport_skips_managers() {
+    let client = setup_test_client(two_device_builder()).await;
+    // The simulated manager only finds BLE devices, so it's never started.
+    let devices = filtered_scan(&client, ScanFilter::new(&[], &["serial"], &[])).await;
+    assert!(devices.is_empty());
+  }
+}
@@ -16,6 +16,7 @@ mod websocket_connector_tests {
     client::ButtplugClient,
     core::{
       connector::{
+        transport::TungsteniteError,
         ButtplugRemoteClientConnector,
         ButtplugRemoteServerConnector,
         ButtplugWebsocketClientTransport,
@@ -71,6 +72,111 @@ mod websocket_connector_tests {
       .expect("Test, assuming infallible.");
   }
 
+  #[tokio::test]
+  async fn test_client_ws_client_server_ws_server_secure() {
+    let cert_dir = std::path::Path::new(
+      &std::env::var("CARGO_MANIFEST_DIR").expect("Should have manifest path"),
+    )
+    .join("tests")
+    .join("util")
+    .join("certs");
+    let test_server = ButtplugTestServer::default();
+    let server = Arc::new(test_server);
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugWebsocketServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugWebsocketServerTransportBuilder::default()
+          .port(12350)
+          .tls_cert_path(cert_dir.join("localhost.crt").to_str().unwrap())
+          .tls_key_path(cert_dir.join("localhost.key").to_str().unwrap())
+          .finish(),
+      );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+    let mut connected = false;
+    for _ in 0..10u8 {
+      let connector = ButtplugRemoteClientConnector::<
+        ButtplugWebsocketClientTransport,
+        ButtplugClientJSONSerializer,
+      >::new(ButtplugWebsocketClientTransport::new_secure_connector(
+        "wss://127.0.0.1:12350",
+        true,
+      ));
+
+      let client = ButtplugClient::new("Test Client");
+      if client.connect(connector).await.is_ok() {
+        connected = true;
+        break;
+      }
+      sleep(Duration::from_secs(1)).await;
+    }
+    assert!(connected);
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
+  #[tokio::test]
+  async fn test_client_ws_client_server_ws_server_auth_token() {
+    let test_server = ButtplugTestServer::default();
+    let server = Arc::new(test_server);
+    let server_clone = server.clone();
+    async_manager::spawn(async move {
+      let connector = ButtplugRemoteServerConnector::<
+        ButtplugWebsocketServerTransport,
+        ButtplugServerJSONSerializer,
+      >::new(
+        ButtplugWebsocketServerTransportBuilder::default()
+          .port(12350)
+          .auth_token("test-token")
+          .finish(),
+      );
+      server_clone
+        .start(connector)
+        .await
+        .expect("Test, assuming infallible.");
+    });
+
+    // Connecting without the token gets turned away during the websocket handshake.
+    let mut rejected = false;
+    for _ in 0..10u8 {
+      if let Err(TungsteniteError::Http(response)) =
+        tokio_tungstenite::connect_async("ws://127.0.0.1:12350").await
+      {
+        assert_eq!(response.status(), 401);
+        rejected = true;
+        break;
+      }
+      sleep(Duration::from_secs(1)).await;
+    }
+    assert!(rejected);
+
+    // The server keeps listening, so a client with the token can still connect.
+    let connector = ButtplugRemoteClientConnector::<
+      ButtplugWebsocketClientTransport,
+      ButtplugClientJSONSerializer,
+    >::new(
+      ButtplugWebsocketClientTransport::new_insecure_connector("ws://127.0.0.1:12350")
+        .with_auth_token("test-token"),
+    );
+    let client = ButtplugClient::new("Test Client");
+    client
+      .connect(connector)
+      .await
+      .expect("Test, assuming infallible.");
+    server
+      .disconnect()
+      .await
+      .expect("Test, assuming infallible.");
+  }
+
   #[tokio::test]
   async fn test_client_ws_server_server_ws_client_insecure() {
     let test_server = ButtplugTestServer::default();
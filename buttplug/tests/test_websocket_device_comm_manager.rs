@@ -11,20 +11,45 @@ mod util;
 mod test {
 
   use buttplug::{
-    client::ButtplugClient,
+    client::{ButtplugClient, ButtplugClientEvent, ScalarValueCommand},
     core::connector::ButtplugInProcessClientConnectorBuilder,
     server::device::hardware::communication::websocket_server::websocket_server_comm_manager::WebsocketServerDeviceCommunicationManagerBuilder,
     server::ButtplugServerBuilder,
   };
+  use futures::{SinkExt, StreamExt};
+  use std::time::Duration;
+  use tokio::{net::TcpStream, time::timeout};
+  use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
-  async fn setup_test_client() -> ButtplugClient {
+  const WEBSOCKET_USER_CONFIG_JSON: &str = r#"
+  {
+    "version": {
+      "major": 2,
+      "minor": 999
+    },
+    "user-configs": {
+      "specifiers": {
+        "aneros": {
+          "websocket": {
+            "names": ["diy-aneros"]
+          }
+        }
+      }
+    }
+  }
+  "#;
+
+  async fn setup_test_client(port: u16) -> ButtplugClient {
     let mut builder = ButtplugServerBuilder::default();
 
-    builder.name("Websocket DCM Test Server").comm_manager(
-      WebsocketServerDeviceCommunicationManagerBuilder::default()
-        .server_port(51283)
-        .listen_on_all_interfaces(true),
-    );
+    builder
+      .name("Websocket DCM Test Server")
+      .comm_manager(
+        WebsocketServerDeviceCommunicationManagerBuilder::default()
+          .server_port(port)
+          .listen_on_all_interfaces(true),
+      )
+      .user_device_configuration_json(Some(WEBSOCKET_USER_CONFIG_JSON.to_owned()));
     let server = builder.finish().expect("Test, assuming infallible.");
     let connector = ButtplugInProcessClientConnectorBuilder::default()
       .server(server)
@@ -40,7 +65,78 @@ mod test {
 
   #[tokio::test]
   async fn test_websocket_server_dcm_bringup() {
-    let client = setup_test_client().await;
+    let client = setup_test_client(51283).await;
     assert!(client.connected());
   }
+
+  async fn connect_json_device(port: u16) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    let (mut device, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}", port))
+      .await
+      .expect("Test, assuming infallible.");
+    device
+      .send(Message::Text(
+        r#"{"identifier": "diy-aneros", "address": "esp32-01", "version": 2, "endpoints": ["tx"]}"#
+          .to_owned(),
+      ))
+      .await
+      .expect("Test, assuming infallible.");
+    device
+  }
+
+  async fn next_write(device: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> String {
+    loop {
+      let msg = timeout(Duration::from_secs(5), device.next())
+        .await
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible.")
+        .expect("Test, assuming infallible.");
+      if let Message::Text(text) = msg {
+        if text.starts_with(r#"{"Write""#) {
+          return text;
+        }
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn test_websocket_server_dcm_json_device_reconnect() {
+    let client = setup_test_client(51284).await;
+    let mut event_stream = client.event_stream();
+    client
+      .start_scanning()
+      .await
+      .expect("Test, assuming infallible.");
+    // Give the comm manager time to start listening.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut device = connect_json_device(51284).await;
+    let client_device = timeout(Duration::from_secs(5), async {
+      loop {
+        if let Some(ButtplugClientEvent::DeviceAdded(device)) = event_stream.next().await {
+          break device;
+        }
+      }
+    })
+    .await
+    .expect("Test, assuming infallible.");
+
+    client_device
+      .vibrate(&ScalarValueCommand::ScalarValue(1.0))
+      .await
+      .expect("Test, assuming infallible.");
+    assert!(next_write(&mut device).await.contains(r#""endpoint":"tx""#));
+
+    // Drop the connection without closing it, like a device losing Wi-Fi, then come back.
+    drop(device);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut device = connect_json_device(51284).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    client_device
+      .vibrate(&ScalarValueCommand::ScalarValue(0.5))
+      .await
+      .expect("Test, assuming infallible.");
+    next_write(&mut device).await;
+    assert!(client_device.connected());
+    assert_eq!(client.devices().len(), 1);
+  }
 }
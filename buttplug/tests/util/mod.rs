@@ -15,8 +15,12 @@ pub use delay_device_communication_manager::DelayDeviceCommunicationManagerBuild
 mod channel_transport;
 use buttplug::{
   client::ButtplugClient,
-  core::connector::ButtplugInProcessClientConnectorBuilder,
-  server::{ButtplugServer, ButtplugServerBuilder},
+  core::{connector::ButtplugInProcessClientConnectorBuilder, message::Endpoint},
+  server::{
+    device::hardware::{HardwareCommand, HardwareWriteCmd},
+    ButtplugServer,
+    ButtplugServerBuilder,
+  },
 };
 pub use channel_transport::*;
 pub use test_device_manager::{
@@ -27,6 +31,7 @@ pub use test_device_manager::{
 };
 
 use crate::util::test_device_manager::TestDeviceIdentifier;
+use std::time::Duration;
 
 #[allow(dead_code)]
 pub fn setup_logging() {
@@ -107,3 +112,17 @@ pub async fn test_server_with_device(
   let server = server_builder.finish().unwrap();
   (server, device)
 }
+
+/// Wait for the test device to get a write without response to its tx endpoint, and check what was
+/// written.
+#[allow(dead_code)]
+pub async fn expect_write(device: &mut TestDeviceChannelHost, data: &[u8]) {
+  let command = tokio::time::timeout(Duration::from_secs(5), device.receiver.recv())
+    .await
+    .expect("Test, assuming infallible.")
+    .expect("Test, assuming infallible.");
+  assert_eq!(
+    command,
+    HardwareCommand::Write(HardwareWriteCmd::new(Endpoint::Tx, data.to_vec(), false))
+  );
+}
@@ -6,7 +6,10 @@
 // for full license information.
 
 use buttplug::{
-  core::{errors::ButtplugDeviceError, message::Endpoint},
+  core::{
+    errors::ButtplugDeviceError,
+    message::{DeviceRemovedReason, Endpoint},
+  },
   server::device::{
     configuration::ProtocolCommunicationSpecifier,
     hardware::{
@@ -33,7 +36,10 @@ use serde::{Deserialize, Serialize};
 use std::{
   collections::{HashSet, VecDeque},
   fmt::{self, Debug},
-  sync::Arc,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
 };
 use tokio::sync::{broadcast, mpsc, Mutex};
 
@@ -43,6 +49,13 @@ pub struct TestHardwareNotification {
   data: Vec<u8>,
 }
 
+impl TestHardwareNotification {
+  #[allow(dead_code)]
+  pub fn new(endpoint: Endpoint, data: Vec<u8>) -> Self {
+    Self { endpoint, data }
+  }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum TestHardwareEvent {
   // Values to be emitted from subscriptions
@@ -50,6 +63,8 @@ pub enum TestHardwareEvent {
   // Values to be emitted when calls to ReadValue happen
   Reads(Vec<TestHardwareNotification>),
   Disconnect,
+  // Stop acknowledging writes, as a hung device would
+  StallWrites,
 }
 
 pub struct TestHardwareConnector {
@@ -162,6 +177,7 @@ pub struct TestDevice {
   event_sender: broadcast::Sender<HardwareEvent>,
   subscribed_endpoints: Arc<DashSet<Endpoint>>,
   read_data: Arc<Mutex<VecDeque<HardwareReading>>>,
+  writes_stalled: Arc<AtomicBool>,
 }
 
 impl TestDevice {
@@ -176,12 +192,18 @@ impl TestDevice {
     let subscribed_endpoints_clone = subscribed_endpoints.clone();
     let read_data = Arc::new(Mutex::new(VecDeque::new()));
     let read_data_clone = read_data.clone();
+    let writes_stalled = Arc::new(AtomicBool::new(false));
+    let writes_stalled_clone = writes_stalled.clone();
     async_manager::spawn(async move {
       while let Some(event) = receiver.recv().await {
         match event {
           TestHardwareEvent::Disconnect => {
             event_sender_clone
-              .send(HardwareEvent::Disconnected(address_clone.clone()))
+              .send(HardwareEvent::Disconnected(
+                address_clone.clone(),
+                DeviceRemovedReason::LinkLost,
+                false,
+              ))
               .expect("Test");
           }
           TestHardwareEvent::Notifications(notifications) => {
@@ -203,6 +225,7 @@ impl TestDevice {
               guard.push_front(HardwareReading::new(read.endpoint, &read.data));
             }
           }
+          TestHardwareEvent::StallWrites => writes_stalled_clone.store(true, Ordering::SeqCst),
         }
       }
     });
@@ -215,6 +238,7 @@ impl TestDevice {
       event_sender,
       subscribed_endpoints,
       read_data,
+      writes_stalled,
     }
   }
 
@@ -235,7 +259,11 @@ impl TestDevice {
     data_command: HardwareCommand,
   ) -> BoxFuture<'static, Result<(), ButtplugDeviceError>> {
     let sender = self.test_device_channel.clone();
+    let writes_stalled = self.writes_stalled.clone();
     async move {
+      if writes_stalled.load(Ordering::SeqCst) {
+        future::pending::<()>().await;
+      }
       sender.send(data_command).await.expect("Test");
       Ok(())
     }
@@ -253,7 +281,11 @@ impl HardwareInternal for TestDevice {
     let address = self.address.clone();
     async move {
       sender
-        .send(HardwareEvent::Disconnected(address))
+        .send(HardwareEvent::Disconnected(
+          address,
+          DeviceRemovedReason::UserDisconnect,
+          false,
+        ))
         .expect("Test");
       Ok(())
     }